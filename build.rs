@@ -3,5 +3,7 @@ extern crate cc;
 fn main() {
     cc::Build::new()
         .file("src/boot/head_32.S")
+        .file("src/cpu/idt_entry.S")
+        .file("src/cpu/gdt.S")
         .compile("head_asm");
 }
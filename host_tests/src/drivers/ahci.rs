@@ -0,0 +1,9 @@
+//! The AHCI controller and ports, without the kernel `mod.rs` that binds them to PCI
+
+#[path = "../../../src/drivers/ahci/hba.rs"]
+pub mod hba;
+#[path = "../../../src/drivers/ahci/port.rs"]
+pub mod port;
+
+pub use self::hba::{Error, Hba};
+pub use self::port::{Identity, Port};
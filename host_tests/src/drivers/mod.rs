@@ -0,0 +1,45 @@
+//! Kernel driver sources built for the host
+
+#[path = "../../../src/drivers/io.rs"]
+pub mod io;
+#[path = "../../../src/drivers/serial.rs"]
+mod serial;
+#[path = "../../../src/drivers/uart16550.rs"]
+pub mod uart16550;
+#[path = "../../../src/drivers/i8042.rs"]
+pub mod i8042;
+#[path = "../../../src/drivers/ps2kbd.rs"]
+pub mod ps2kbd;
+#[path = "../../../src/drivers/pic8259.rs"]
+pub mod pic8259;
+#[path = "../../../src/drivers/apic.rs"]
+pub mod apic;
+#[path = "../../../src/drivers/ioapic.rs"]
+pub mod ioapic;
+#[path = "../../../src/drivers/pit.rs"]
+pub mod pit;
+#[path = "../../../src/drivers/hpet.rs"]
+pub mod hpet;
+#[path = "../../../src/drivers/rtc.rs"]
+pub mod rtc;
+#[path = "../../../src/drivers/vga.rs"]
+pub mod vga;
+#[path = "../../../src/drivers/pci/mod.rs"]
+pub mod pci;
+#[path = "../../../src/drivers/dma.rs"]
+pub mod dma;
+#[path = "../../../src/drivers/fw_cfg/device.rs"]
+pub mod fw_cfg;
+#[path = "../../../src/drivers/qemu.rs"]
+pub mod qemu;
+#[path = "../../../src/drivers/e1000/device.rs"]
+pub mod e1000;
+#[path = "../../../src/drivers/framebuffer.rs"]
+pub mod framebuffer;
+#[path = "../../../src/drivers/bochs/dispi.rs"]
+pub mod bochs;
+pub mod ahci;
+pub mod nvme;
+pub mod virtio;
+
+pub use self::serial::Serial;
@@ -0,0 +1,11 @@
+//! The NVMe controller, queues and namespaces, without the kernel `mod.rs` that binds them to PCI
+
+#[path = "../../../src/drivers/nvme/controller.rs"]
+pub mod controller;
+#[path = "../../../src/drivers/nvme/namespace.rs"]
+pub mod namespace;
+#[path = "../../../src/drivers/nvme/queue.rs"]
+pub mod queue;
+
+pub use self::controller::{Controller, Error};
+pub use self::namespace::Namespace;
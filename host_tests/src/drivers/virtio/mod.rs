@@ -0,0 +1,25 @@
+//! The generic parts of virtio
+//!
+//! The kernel `mod.rs` maps devices through the PCI bus, so only the files it declares are built.
+
+#[path = "../../../../src/drivers/virtio/pci.rs"]
+mod pci;
+#[path = "../../../../src/drivers/virtio/queue.rs"]
+mod queue;
+#[path = "../../../../src/drivers/virtio/transport.rs"]
+mod transport;
+#[path = "../../../../src/drivers/virtio/pool.rs"]
+mod pool;
+#[path = "../../../../src/drivers/virtio/blk/device.rs"]
+pub mod blk;
+#[path = "../../../../src/drivers/virtio/console/device.rs"]
+pub mod console;
+#[path = "../../../../src/drivers/virtio/net/device.rs"]
+pub mod net;
+#[path = "../../../../src/drivers/virtio/rng/device.rs"]
+pub mod rng;
+
+pub use self::pci::{layout, Layout, Region};
+pub use self::queue::{Buffer, Virtqueue, MAX_SIZE};
+pub use self::transport::*;
+pub use self::pool::Pool;
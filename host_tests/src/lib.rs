@@ -0,0 +1,177 @@
+//! Host side testing of kernel drivers
+//!
+//! Drivers that are generic over `Io` do not need real hardware, only something that behaves
+//! like it. This crate builds the kernel driver sources for the host and provides register level
+//! models of devices that implement `Io`, so the drivers can be exercised with `cargo test`.
+//!
+//! Only driver sources that have no dependencies beyond `core` and the `Io` abstraction can be
+//! included here.
+
+#![feature(associated_type_defaults)]
+// The kernel keeps its global state in `static mut`s
+#![allow(static_mut_refs)]
+// Declarations are documented at the macro invocation
+#![allow(unused_doc_comments)]
+// The kernel is built by a compiler that predates `dyn`
+#![allow(bare_trait_objects)]
+// The kernel declares its linker symbols with a bare `extern`
+#![allow(missing_abi)]
+
+// Driver sources refer to `core` directly
+extern crate core;
+extern crate alloc;
+#[macro_use]
+extern crate bitflags;
+#[macro_use]
+extern crate bitfield;
+
+/// Stand in for the parts of the `x86` crate that the driver sources refer to
+///
+/// Actual port I/O is meaningless on the host, so any attempt to use it is a test bug
+mod x86 {
+    pub mod shared {
+        pub mod io {
+            pub unsafe fn inb(port: u16) -> u8 {
+                panic!("Port I/O read from {:#x} on the host", port)
+            }
+            pub unsafe fn outb(port: u16, _value: u8) {
+                panic!("Port I/O write to {:#x} on the host", port)
+            }
+            pub unsafe fn inw(port: u16) -> u16 {
+                panic!("Port I/O read from {:#x} on the host", port)
+            }
+            pub unsafe fn outw(port: u16, _value: u16) {
+                panic!("Port I/O write to {:#x} on the host", port)
+            }
+            pub unsafe fn inl(port: u16) -> u32 {
+                panic!("Port I/O read from {:#x} on the host", port)
+            }
+            pub unsafe fn outl(port: u16, _value: u32) {
+                panic!("Port I/O write to {:#x} on the host", port)
+            }
+        }
+        pub mod msr {
+            pub const IA32_TSC_DEADLINE: u32 = 0x6e0;
+            pub unsafe fn rdmsr(msr: u32) -> u64 {
+                panic!("MSR read from {:#x} on the host", msr)
+            }
+            pub unsafe fn wrmsr(msr: u32, _value: u64) {
+                panic!("MSR write to {:#x} on the host", msr)
+            }
+        }
+    }
+}
+
+/// Stand in for the CPU feature detection of the kernel, which the host cannot do for it
+mod cpu {
+    pub mod features {
+        pub fn hypervisor() -> bool {
+            panic!("CPUID on the host")
+        }
+    }
+}
+
+/// Stand in for the interrupt routing of the kernel, which drivers enable once they find a device
+///
+/// Probing is not done on the host, so this is never reached
+mod irq {
+    pub const ISA_KEYBOARD: u8 = 1;
+    pub fn enable_isa(irq: u8, _handler: fn()) -> bool {
+        panic!("ISA IRQ {} routed on the host", irq)
+    }
+    pub fn disable_isa(irq: u8) {
+        panic!("ISA IRQ {} masked on the host", irq)
+    }
+}
+
+/// Kernel logging goes to stderr, which the test harness captures
+macro_rules! print {
+    ($v:ident, $($arg:tt)*) => (eprintln!("{:?}: {}", stringify!($v), format_args!($($arg)*)));
+}
+
+/// There is no cmdline on the host, but keep the handler so it is still type checked
+macro_rules! make_cmdline_decl {
+    ($option:expr, $function:expr, $name:ident) => (
+        #[allow(dead_code)]
+        static $name: (&str, fn(&str)) = ($option, $function);
+    );
+}
+
+/// There are no linker sections on the host, but keep the driver so it is still type checked
+macro_rules! make_driver_decl {
+    ($driver:expr, $matches:expr, $probe:expr, $remove:expr, $name:ident) => (
+        #[allow(dead_code)]
+        static $name: ::decls::Driver = ::decls::Driver { name: $driver, matches: $matches, probe: $probe, remove: $remove };
+    );
+}
+
+#[path = "../../src/decls.rs"]
+pub mod decls;
+#[path = "../../src/bus/binding.rs"]
+mod binding;
+/// Only the matching of devices to drivers, without the buses they are found on
+pub mod bus {
+    pub use binding::{Binding, Bindings, Device};
+}
+#[path = "../../src/vspace/translation.rs"]
+mod translation;
+/// Only the translation interface of the kernel vspace, which DMA allocations are made through
+pub mod vspace {
+    pub use translation::{AsTranslation, Translation};
+}
+/// Uptime for the kernel sources that wait on it, which moves on a millisecond each time it is
+/// read so that their timeouts still run out
+pub mod time {
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    thread_local!(static UPTIME: Cell<Duration> = Cell::new(Duration::new(0, 0)));
+
+    pub fn uptime() -> Duration {
+        UPTIME.with(|uptime| {
+            uptime.set(uptime.get() + Duration::from_millis(1));
+            uptime.get()
+        })
+    }
+}
+#[path = "../../src/con/scrollback.rs"]
+mod scrollback;
+#[path = "../../src/con/cp437.rs"]
+mod cp437;
+#[path = "../../src/con/color.rs"]
+mod color;
+#[path = "../../src/con/net/buffer.rs"]
+mod netconsole;
+use con::V;
+/// Only the parts of the kernel consoles that do not touch hardware
+pub mod con {
+    pub use scrollback::{Cell, Scrollback, HISTORY_CELLS};
+    pub mod cp437 {
+        pub use cp437::{encode, from_char, REPLACEMENT};
+    }
+    /// Verbosity levels, as the kernel con has them, which the colours are chosen by
+    #[derive(Debug, Copy, Clone)]
+    pub enum V {
+        Panic,
+        Error,
+        Info,
+        Debug,
+        Trace,
+    }
+    pub mod color {
+        pub use color::{Color, ColorMode, Colors};
+    }
+    /// The buffering of the netconsole, without the socket it sends through
+    pub mod net {
+        pub use netconsole::{ConNet, Sink, FLUSH_INTERVAL_MS, MAX_BUFFER, PAYLOAD};
+    }
+}
+pub mod drivers;
+#[path = "../../src/block/mod.rs"]
+pub mod block;
+pub mod net;
+#[path = "../../src/input/mod.rs"]
+pub mod input;
+#[path = "../../src/random/mod.rs"]
+pub mod random;
+pub mod models;
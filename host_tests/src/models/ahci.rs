@@ -0,0 +1,400 @@
+//! Model of an AHCI controller with ATA disks
+//!
+//! Registers are those of the ABAR. Commands are run as soon as the driver issues them, by
+//! reading the command list and table from host memory, so they complete before the write to
+//! `PxCI` returns. A command that fails sets the task file error and stays issued, and the port
+//! has to be stopped to clear it, as on hardware.
+
+use std::ptr;
+
+use drivers::io::Io;
+
+const CAP: usize = 0x00;
+const GHC: usize = 0x04;
+const IS: usize = 0x08;
+const PI: usize = 0x0C;
+const VS: usize = 0x10;
+const CAP2: usize = 0x24;
+const BOHC: usize = 0x28;
+
+const PX_CLB: usize = 0x00;
+const PX_CLBU: usize = 0x04;
+const PX_FB: usize = 0x08;
+const PX_FBU: usize = 0x0C;
+const PX_IS: usize = 0x10;
+const PX_IE: usize = 0x14;
+const PX_CMD: usize = 0x18;
+const PX_TFD: usize = 0x20;
+const PX_SIG: usize = 0x24;
+const PX_SSTS: usize = 0x28;
+const PX_SERR: usize = 0x30;
+const PX_CI: usize = 0x38;
+
+const GHC_HR: u32 = 1 << 0;
+const GHC_AE: u32 = 1 << 31;
+const CMD_ST: u32 = 1 << 0;
+const CMD_FRE: u32 = 1 << 4;
+const CMD_FR: u32 = 1 << 14;
+const CMD_CR: u32 = 1 << 15;
+const IS_TFES: u32 = 1 << 30;
+const TFD_ERR: u32 = 1 << 0;
+const TFD_DRDY: u32 = 1 << 6;
+/// Error register value for an aborted command
+const ERROR_ABRT: u32 = 1 << 2;
+
+const CAP2_BOH: u32 = 1 << 0;
+const BOHC_BOS: u32 = 1 << 0;
+const BOHC_OOS: u32 = 1 << 1;
+
+/// What is plugged into a port
+pub enum Attached {
+    Nothing,
+    /// An ATA disk, with its contents and sector size
+    Disk { data: Vec<u8>, sector_size: usize, lba48: bool },
+    /// A device with some other signature, such as ATAPI
+    Other(u32),
+}
+
+pub struct Port {
+    pub attached: Attached,
+    clb: u64,
+    fb: u64,
+    is: u32,
+    ie: u32,
+    cmd: u32,
+    tfd: u32,
+    serr: u32,
+    ci: u32,
+    /// Sector at which transfers fail
+    pub bad_sector: Option<u64>,
+    /// Every command as (command, LBA, count, bytes transferred)
+    pub commands: Vec<(u8, u64, u16, usize)>,
+}
+
+impl Port {
+    fn new(attached: Attached) -> Self {
+        Port { attached: attached, clb: 0, fb: 0, is: 0, ie: 0, cmd: 0, tfd: 0, serr: 0, ci: 0, bad_sector: None, commands: Vec::new() }
+    }
+    fn signature(&self) -> u32 {
+        match self.attached {
+            Attached::Nothing => 0xFFFF_FFFF,
+            Attached::Disk { .. } => 0x0000_0101,
+            Attached::Other(signature) => signature,
+        }
+    }
+    pub fn data(&self) -> &[u8] {
+        match self.attached {
+            Attached::Disk { ref data, .. } => data,
+            _ => panic!("Port has no disk"),
+        }
+    }
+    fn read(&mut self, reg: usize) -> u32 {
+        match reg {
+            PX_CLB => self.clb as u32,
+            PX_CLBU => (self.clb >> 32) as u32,
+            PX_FB => self.fb as u32,
+            PX_FBU => (self.fb >> 32) as u32,
+            PX_IS => self.is,
+            PX_IE => self.ie,
+            PX_CMD => self.cmd,
+            PX_TFD => self.tfd,
+            PX_SIG => self.signature(),
+            PX_SSTS => match self.attached {
+                Attached::Nothing => 0,
+                // Device present at gen 3 speed and active
+                _ => 0x123,
+            },
+            PX_SERR => self.serr,
+            PX_CI => self.ci,
+            _ => panic!("Read of port register {:#x}", reg),
+        }
+    }
+    unsafe fn write(&mut self, reg: usize, value: u32) {
+        let stopped = |cmd: u32| cmd & (CMD_ST | CMD_CR) == 0;
+        match reg {
+            PX_CLB | PX_CLBU | PX_FB | PX_FBU => {
+                assert!(stopped(self.cmd) && self.cmd & CMD_FRE == 0, "Port memory moved while running");
+                match reg {
+                    PX_CLB => self.clb = self.clb & !0xFFFF_FFFF | value as u64,
+                    PX_CLBU => self.clb = self.clb & 0xFFFF_FFFF | (value as u64) << 32,
+                    PX_FB => self.fb = self.fb & !0xFFFF_FFFF | value as u64,
+                    _ => self.fb = self.fb & 0xFFFF_FFFF | (value as u64) << 32,
+                }
+            },
+            PX_IS => self.is &= !value,
+            PX_IE => self.ie = value,
+            PX_CMD => {
+                if value & CMD_FRE != 0 {
+                    assert!(self.fb != 0, "FIS receive enabled without a buffer");
+                }
+                if value & CMD_ST != 0 && self.cmd & CMD_ST == 0 {
+                    assert!(self.cmd & CMD_FRE != 0 || value & CMD_FRE != 0, "Port started without FIS receive");
+                    assert!(self.clb != 0, "Port started without a command list");
+                }
+                let mut cmd = value & !(CMD_FR | CMD_CR);
+                if value & CMD_FRE != 0 {
+                    cmd |= CMD_FR;
+                }
+                if value & CMD_ST != 0 {
+                    cmd |= CMD_CR;
+                } else {
+                    // Stopping the port clears whatever was issued, and a task file error
+                    self.ci = 0;
+                    self.tfd &= !TFD_ERR & 0xFF;
+                }
+                self.cmd = cmd;
+            },
+            PX_SERR => self.serr &= !value,
+            PX_CI => {
+                assert!(self.cmd & CMD_ST != 0, "Command issued on a stopped port");
+                self.ci |= value;
+                for slot in 0..32 {
+                    if value & 1 << slot != 0 && self.is & IS_TFES == 0 {
+                        self.run(slot);
+                    }
+                }
+            },
+            _ => panic!("Write of port register {:#x}", reg),
+        }
+    }
+    unsafe fn run(&mut self, slot: u32) {
+        let header = (self.clb + 32 * slot as u64) as usize as *const u32;
+        let flags = ptr::read(header);
+        let table = ptr::read(header.offset(2)) as u64 | (ptr::read(header.offset(3)) as u64) << 32;
+        assert_eq!(flags & 0x1F, 5, "Command FIS is not a register FIS");
+        assert_eq!(table % 128, 0, "Command table misaligned");
+        let write = flags & 1 << 6 != 0;
+        let prdt_len = (flags >> 16) as usize;
+        let fis = std::slice::from_raw_parts(table as usize as *const u8, 20);
+        assert_eq!((fis[0], fis[1] & 0x80), (0x27, 0x80), "Not a host to device command FIS");
+        let command = fis[2];
+        let lba48 = [fis[4], fis[5], fis[6], fis[8], fis[9], fis[10]].iter().enumerate()
+            .fold(0, |lba, (i, &byte)| lba | (byte as u64) << (i * 8));
+        let count = fis[12] as u16 | (fis[13] as u16) << 8;
+        let mut regions = Vec::new();
+        for entry in 0..prdt_len {
+            let prd = (table + 0x80 + 16 * entry as u64) as usize as *const u32;
+            let address = ptr::read(prd) as u64 | (ptr::read(prd.offset(1)) as u64) << 32;
+            let len = (ptr::read(prd.offset(3)) & 0x3F_FFFF) as usize + 1;
+            assert_eq!(address % 2, 0, "Data buffer not word aligned");
+            regions.push((address as usize, len));
+        }
+        let total: usize = regions.iter().map(|region| region.1).sum();
+        let mut transferred = 0;
+        let ok = match (command, &mut self.attached) {
+            (0xEC, &mut Attached::Disk { ref data, sector_size, lba48: supports_lba48 }) => {
+                assert!(!write, "IDENTIFY marked as a write");
+                let identify = identify(data.len() / sector_size, sector_size, supports_lba48);
+                copy_out(&regions, &identify);
+                transferred = 512;
+                true
+            },
+            (0x25, _) | (0x35, _) | (0xC8, _) | (0xCA, _) => {
+                let ext = command == 0x25 || command == 0x35;
+                let (lba, count) = if ext {
+                    (lba48, if count == 0 { 65536 } else { count as usize })
+                } else {
+                    assert_eq!(fis[7] & 0x40, 0x40, "LBA28 command without LBA mode");
+                    (lba48 & 0xFF_FFFF | ((fis[7] & 0xF) as u64) << 24, if count & 0xFF == 0 { 256 } else { (count & 0xFF) as usize })
+                };
+                let bad_sector = self.bad_sector;
+                match self.attached {
+                    Attached::Disk { ref mut data, sector_size, lba48: supports_lba48 } => {
+                        assert!(supports_lba48 || !ext, "LBA48 command to a disk without LBA48");
+                        let start = lba as usize * sector_size;
+                        let len = count * sector_size;
+                        assert_eq!(total, len, "PRDT does not cover the transfer");
+                        assert_eq!(write, command == 0x35 || command == 0xCA, "Header direction does not match the command");
+                        let bad = bad_sector.map_or(false, |bad| lba <= bad && bad < lba + count as u64);
+                        if start + len > data.len() || bad {
+                            false
+                        } else {
+                            if write {
+                                copy_in(&regions, &mut data[start..start + len]);
+                            } else {
+                                copy_out(&regions, &data[start..start + len]);
+                            }
+                            transferred = len;
+                            true
+                        }
+                    },
+                    _ => false,
+                }
+            },
+            (0xE7, &mut Attached::Disk { .. }) | (0xEA, &mut Attached::Disk { .. }) => true,
+            _ => false,
+        };
+        self.commands.push((command, lba48, count, transferred));
+        // The byte count is written back into the command header
+        ptr::write((header as *mut u32).offset(1), transferred as u32);
+        if ok {
+            self.tfd = TFD_DRDY;
+            self.ci &= !(1 << slot);
+        } else {
+            self.tfd = ERROR_ABRT << 8 | TFD_DRDY | TFD_ERR;
+            self.is |= IS_TFES;
+        }
+    }
+}
+
+unsafe fn copy_out(regions: &[(usize, usize)], data: &[u8]) {
+    let mut offset = 0;
+    for &(address, len) in regions {
+        let len = len.min(data.len() - offset);
+        ptr::copy_nonoverlapping(data[offset..].as_ptr(), address as *mut u8, len);
+        offset += len;
+    }
+}
+
+unsafe fn copy_in(regions: &[(usize, usize)], data: &mut [u8]) {
+    let mut offset = 0;
+    for &(address, len) in regions {
+        ptr::copy_nonoverlapping(address as *const u8, data[offset..].as_mut_ptr(), len);
+        offset += len;
+    }
+}
+
+fn put_string(words: &mut [u16], s: &str) {
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.resize(words.len() * 2, b' ');
+    for (word, pair) in words.iter_mut().zip(bytes.chunks(2)) {
+        *word = (pair[0] as u16) << 8 | pair[1] as u16;
+    }
+}
+
+/// IDENTIFY DEVICE data of a disk
+pub fn identify(sectors: usize, sector_size: usize, lba48: bool) -> Vec<u8> {
+    let mut words = [0u16; 256];
+    put_string(&mut words[10..20], "RLK0001");
+    put_string(&mut words[27..47], "RLK Model Disk");
+    let lba28 = sectors.min(0x0FFF_FFFF);
+    words[60] = lba28 as u16;
+    words[61] = (lba28 >> 16) as u16;
+    if lba48 {
+        words[83] = 1 << 10;
+        for i in 0..4 {
+            words[100 + i] = (sectors as u64 >> (i * 16)) as u16;
+        }
+    }
+    if sector_size != 512 {
+        words[106] = 0x4000 | (1 << 12);
+        words[117] = (sector_size / 2) as u16;
+        words[118] = ((sector_size / 2) >> 16) as u16;
+    }
+    words.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect()
+}
+
+pub struct Hba {
+    cap: u32,
+    ghc: u32,
+    is: u32,
+    cap2: u32,
+    bohc: u32,
+    pub ports: Vec<Port>,
+    /// Reads of `GHC` during which a reset is still in progress
+    reset_delay: usize,
+    resetting: usize,
+    pub resets: usize,
+}
+
+impl Hba {
+    /// A controller with a port for each of `attached`
+    pub fn new(attached: Vec<Attached>) -> Self {
+        let ports = attached.len() as u32;
+        assert!(ports > 0 && ports <= 32);
+        Hba {
+            // 64-bit, 32 command slots
+            cap: 1 << 31 | 31 << 8 | (ports - 1),
+            ghc: 0,
+            is: 0,
+            cap2: 0,
+            bohc: 0,
+            ports: attached.into_iter().map(Port::new).collect(),
+            reset_delay: 3,
+            resetting: 0,
+            resets: 0,
+        }
+    }
+    /// Have the BIOS own the controller, until the driver asks for it
+    pub fn bios_owned(mut self) -> Self {
+        self.cap2 |= CAP2_BOH;
+        self.bohc = BOHC_BOS;
+        self
+    }
+    /// Without 64-bit addressing
+    pub fn only_32bit(mut self) -> Self {
+        self.cap &= !(1 << 31);
+        self
+    }
+    pub fn bohc(&self) -> u32 {
+        self.bohc
+    }
+    fn reset(&mut self) {
+        self.resets += 1;
+        self.resetting = self.reset_delay;
+        self.is = 0;
+        for port in self.ports.iter_mut() {
+            let attached = std::mem::replace(&mut port.attached, Attached::Nothing);
+            *port = Port { attached: attached, commands: port.commands.split_off(0), bad_sector: port.bad_sector, ..Port::new(Attached::Nothing) };
+        }
+    }
+    fn port(&mut self, offset: usize) -> (&mut Port, usize) {
+        let index = (offset - 0x100) / 0x80;
+        assert!(index < self.ports.len(), "Access to port {} that is not implemented", index);
+        (&mut self.ports[index], (offset - 0x100) % 0x80)
+    }
+}
+
+impl Io for Hba {
+    type Item = u32;
+    unsafe fn read(&mut self, offset: usize) -> u32 {
+        assert_eq!(offset % 4, 0, "Misaligned register read {:#x}", offset);
+        match offset {
+            CAP => self.cap,
+            GHC => {
+                if self.resetting > 0 {
+                    self.resetting -= 1;
+                    if self.resetting == 0 {
+                        self.ghc &= !GHC_HR;
+                    }
+                }
+                self.ghc
+            },
+            IS => self.is,
+            PI => ((1u64 << self.ports.len()) - 1) as u32,
+            VS => 0x0001_0301,
+            CAP2 => self.cap2,
+            BOHC => self.bohc,
+            0x100..=0x10FF => {
+                let (port, reg) = self.port(offset);
+                port.read(reg)
+            },
+            _ => panic!("Read of controller register {:#x}", offset),
+        }
+    }
+    unsafe fn write(&mut self, offset: usize, value: u32) {
+        assert_eq!(offset % 4, 0, "Misaligned register write {:#x}", offset);
+        match offset {
+            GHC => {
+                assert!(value & GHC_AE != 0, "AHCI mode disabled");
+                self.ghc = value;
+                if value & GHC_HR != 0 {
+                    self.reset();
+                }
+            },
+            IS => self.is &= !value,
+            BOHC => {
+                if value & BOHC_OOS != 0 {
+                    // The BIOS gives the controller up straight away
+                    self.bohc = BOHC_OOS;
+                }
+            },
+            0x100..=0x10FF => {
+                assert!(self.ghc & GHC_AE != 0, "Port accessed without AHCI enabled");
+                let (port, reg) = self.port(offset);
+                port.write(reg, value);
+            },
+            _ => panic!("Write of controller register {:#x}", offset),
+        }
+    }
+}
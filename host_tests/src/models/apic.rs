@@ -0,0 +1,122 @@
+//! Model of a local APIC register file
+//!
+//! Registers are addressed by their xAPIC offset. The model can present itself in x2APIC mode,
+//! which only changes the format of the ID register, as the driver relies on the `Msr` accessor
+//! to hide the rest of the differences. IPIs are accepted instantly.
+
+use std::collections::HashMap;
+
+use drivers::io::Io;
+use super::Access;
+
+const ID: u16 = 0x20;
+const VERSION: u16 = 0x30;
+const EOI: u16 = 0xB0;
+const ESR: u16 = 0x280;
+const ICR_LOW: u16 = 0x300;
+const ICR_HIGH: u16 = 0x310;
+const LVT_TIMER: u16 = 0x320;
+const LVT_LINT0: u16 = 0x350;
+const LVT_LINT1: u16 = 0x360;
+const LVT_ERROR: u16 = 0x370;
+const TIMER_INITIAL: u16 = 0x380;
+const TIMER_CURRENT: u16 = 0x390;
+
+/// Value of the version register, with 7 LVT entries
+const VERSION_VALUE: u32 = 0x0006_0014;
+const LVT_MASKED: u32 = 1 << 16;
+
+pub struct LocalApic {
+    id: u32,
+    x2apic: bool,
+    registers: HashMap<u16, u32>,
+    /// Latched errors, moved to the ESR when it is written
+    pending_errors: u32,
+    eois: usize,
+    /// Every IPI sent, as the full ICR
+    ipis: Vec<u64>,
+    accesses: Vec<Access<u16, u32>>,
+}
+
+impl LocalApic {
+    pub fn new(id: u32) -> Self {
+        let mut registers = HashMap::new();
+        registers.insert(VERSION, VERSION_VALUE);
+        for lvt in [LVT_TIMER, LVT_LINT0, LVT_LINT1, LVT_ERROR].iter() {
+            registers.insert(*lvt, LVT_MASKED);
+        }
+        LocalApic {
+            id: id,
+            x2apic: false,
+            registers: registers,
+            pending_errors: 0,
+            eois: 0,
+            ipis: Vec::new(),
+            accesses: Vec::new(),
+        }
+    }
+    /// Present as being in x2APIC mode
+    pub fn x2apic(mut self) -> Self {
+        self.x2apic = true;
+        self
+    }
+    /// Latch an error to be reported in the ESR
+    pub fn error(&mut self, bits: u32) {
+        self.pending_errors |= bits;
+    }
+    /// Let the timer count down by `ticks`
+    pub fn tick(&mut self, ticks: u32) {
+        let current = self.register(TIMER_CURRENT);
+        self.registers.insert(TIMER_CURRENT, current.saturating_sub(ticks));
+    }
+    pub fn register(&self, offset: u16) -> u32 {
+        *self.registers.get(&offset).unwrap_or(&0)
+    }
+    pub fn eois(&self) -> usize {
+        self.eois
+    }
+    pub fn ipis(&self) -> &[u64] {
+        &self.ipis
+    }
+    pub fn accesses(&self) -> &[Access<u16, u32>] {
+        &self.accesses
+    }
+}
+
+impl Io for LocalApic {
+    type Item = u32;
+    type Range = u16;
+    unsafe fn read(&mut self, offset: u16) -> u32 {
+        assert!(offset % 16 == 0, "Unaligned local APIC read from {:#x}", offset);
+        let value = match offset {
+            ID if self.x2apic => self.id,
+            ID => self.id << 24,
+            _ => self.register(offset),
+        };
+        self.accesses.push(Access::Read(offset, value));
+        value
+    }
+    unsafe fn write(&mut self, offset: u16, value: u32) {
+        assert!(offset % 16 == 0, "Unaligned local APIC write to {:#x}", offset);
+        self.accesses.push(Access::Write(offset, value));
+        match offset {
+            ID | VERSION | TIMER_CURRENT => panic!("Write to read only local APIC register {:#x}", offset),
+            EOI => self.eois += 1,
+            ESR => {
+                self.registers.insert(ESR, self.pending_errors);
+                self.pending_errors = 0;
+            },
+            ICR_LOW => {
+                self.ipis.push((self.register(ICR_HIGH) as u64) << 32 | value as u64);
+                self.registers.insert(ICR_LOW, value);
+            },
+            TIMER_INITIAL => {
+                self.registers.insert(TIMER_INITIAL, value);
+                self.registers.insert(TIMER_CURRENT, value);
+            },
+            _ => {
+                self.registers.insert(offset, value);
+            },
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Block devices and disk images
+//!
+//! `Ram` is a block device backed by memory, and the image builders lay out partition tables
+//! the way partitioning tools do, so that the parsing can be checked against them.
+
+use block::{self, BlockDevice};
+use super::Shared;
+
+/// Memory backed block device
+pub struct Ram {
+    pub data: Vec<u8>,
+    sector_size: usize,
+    read_only: bool,
+    /// Every transfer as (write, sector, length)
+    pub transfers: Vec<(bool, u64, usize)>,
+    pub flushes: usize,
+}
+
+impl Ram {
+    pub fn new(data: Vec<u8>, sector_size: usize) -> Self {
+        assert_eq!(data.len() % sector_size, 0);
+        Ram { data: data, sector_size: sector_size, read_only: false, transfers: Vec::new(), flushes: 0 }
+    }
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+}
+
+impl BlockDevice for Ram {
+    fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+    fn sectors(&self) -> u64 {
+        (self.data.len() / self.sector_size) as u64
+    }
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+    unsafe fn read(&mut self, sector: u64, buffer: &mut [u8]) -> Result<(), block::Error> {
+        self.transfers.push((false, sector, buffer.len()));
+        let start = sector as usize * self.sector_size;
+        buffer.copy_from_slice(&self.data[start..start + buffer.len()]);
+        Ok(())
+    }
+    unsafe fn write(&mut self, sector: u64, buffer: &[u8]) -> Result<(), block::Error> {
+        assert!(!self.read_only, "Write reached a read only device");
+        self.transfers.push((true, sector, buffer.len()));
+        let start = sector as usize * self.sector_size;
+        self.data[start..start + buffer.len()].copy_from_slice(buffer);
+        Ok(())
+    }
+    unsafe fn flush(&mut self) -> Result<(), block::Error> {
+        self.flushes += 1;
+        Ok(())
+    }
+}
+
+impl<T: BlockDevice> BlockDevice for Shared<T> {
+    fn sector_size(&self) -> usize {
+        self.get().sector_size()
+    }
+    fn sectors(&self) -> u64 {
+        self.get().sectors()
+    }
+    fn read_only(&self) -> bool {
+        self.get().read_only()
+    }
+    unsafe fn read(&mut self, sector: u64, buffer: &mut [u8]) -> Result<(), block::Error> {
+        self.get().read(sector, buffer)
+    }
+    unsafe fn write(&mut self, sector: u64, buffer: &[u8]) -> Result<(), block::Error> {
+        self.get().write(sector, buffer)
+    }
+    unsafe fn flush(&mut self) -> Result<(), block::Error> {
+        self.get().flush()
+    }
+}
+
+fn put_u32(image: &mut [u8], offset: usize, value: u32) {
+    for i in 0..4 {
+        image[offset + i] = (value >> (i * 8)) as u8;
+    }
+}
+
+fn put_u64(image: &mut [u8], offset: usize, value: u64) {
+    put_u32(image, offset, value as u32);
+    put_u32(image, offset + 4, (value >> 32) as u32);
+}
+
+/// Write an MBR of (system ID, start, sectors) entries into the first sector
+pub fn mbr(image: &mut [u8], entries: &[(u8, u32, u32)]) {
+    for (index, &(id, start, sectors)) in entries.iter().enumerate() {
+        let entry = 446 + index * 16;
+        image[entry + 4] = id;
+        put_u32(image, entry + 8, start);
+        put_u32(image, entry + 12, sectors);
+    }
+    image[510] = 0x55;
+    image[511] = 0xAA;
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
+/// A GPT partition entry
+pub struct GptEntry<'a> {
+    pub type_guid: [u8; 16],
+    pub guid: [u8; 16],
+    pub first: u64,
+    pub last: u64,
+    pub name: &'a str,
+}
+
+const GPT_ENTRIES: usize = 128;
+const GPT_ENTRY_SIZE: usize = 128;
+
+/// Write a protective MBR, and primary and backup GPTs, with 512 byte sectors
+pub fn gpt(image: &mut [u8], entries: &[GptEntry]) {
+    let sectors = (image.len() / 512) as u64;
+    mbr(image, &[(0xEE, 1, (sectors - 1).min(0xFFFF_FFFF) as u32)]);
+    let mut array = vec![0; GPT_ENTRIES * GPT_ENTRY_SIZE];
+    for (index, entry) in entries.iter().enumerate() {
+        let base = index * GPT_ENTRY_SIZE;
+        array[base..base + 16].copy_from_slice(&entry.type_guid);
+        array[base + 16..base + 32].copy_from_slice(&entry.guid);
+        put_u64(&mut array, base + 32, entry.first);
+        put_u64(&mut array, base + 40, entry.last);
+        for (i, unit) in entry.name.encode_utf16().enumerate() {
+            array[base + 56 + i * 2] = unit as u8;
+            array[base + 57 + i * 2] = (unit >> 8) as u8;
+        }
+    }
+    let array_sectors = (array.len() / 512) as u64;
+    let entries_crc = crc32(&array);
+    for &(my_lba, alternate, entries_lba) in [(1, sectors - 1, 2), (sectors - 1, 1, sectors - 1 - array_sectors)].iter() {
+        let mut header = vec![0; 92];
+        header[..8].copy_from_slice(b"EFI PART");
+        put_u32(&mut header, 8, 0x0001_0000);
+        put_u32(&mut header, 12, 92);
+        put_u64(&mut header, 24, my_lba);
+        put_u64(&mut header, 32, alternate);
+        put_u64(&mut header, 40, 2 + array_sectors);
+        put_u64(&mut header, 48, sectors - 2 - array_sectors);
+        put_u64(&mut header, 72, entries_lba);
+        put_u32(&mut header, 80, GPT_ENTRIES as u32);
+        put_u32(&mut header, 84, GPT_ENTRY_SIZE as u32);
+        put_u32(&mut header, 88, entries_crc);
+        let crc = crc32(&header);
+        put_u32(&mut header, 16, crc);
+        let at = my_lba as usize * 512;
+        image[at..at + 92].copy_from_slice(&header);
+        let at = entries_lba as usize * 512;
+        image[at..at + array.len()].copy_from_slice(&array);
+    }
+}
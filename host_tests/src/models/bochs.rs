@@ -0,0 +1,131 @@
+//! Model of the Bochs VBE display interface
+//!
+//! Registers are reached either through the index and data ports, or as the MMIO array, as
+//! chosen when the model is made. Mode changes are checked as QEMU does, with anything it would
+//! refuse leaving the registers at their previous values.
+
+use drivers::bochs::Access;
+use drivers::io::MixedIo;
+
+const INDEX_ID: usize = 0x0;
+const INDEX_XRES: usize = 0x1;
+const INDEX_YRES: usize = 0x2;
+const INDEX_BPP: usize = 0x3;
+const INDEX_ENABLE: usize = 0x4;
+const INDEX_VIRT_WIDTH: usize = 0x6;
+const INDEX_VIDEO_MEMORY_64K: usize = 0xA;
+const REGISTERS: usize = 0xB;
+
+const ENABLED: u16 = 1 << 0;
+const GETCAPS: u16 = 1 << 1;
+
+pub struct Dispi {
+    access: Access,
+    regs: [u16; REGISTERS],
+    /// Register selected through the index port
+    index: u16,
+    pub max: (u16, u16, u16),
+    /// Every register write, by index
+    pub writes: Vec<(u16, u16)>,
+    /// Modes that were enabled
+    pub modes: Vec<(u16, u16, u16)>,
+    /// Refuse to enable any mode, as if it were beyond the device
+    pub refuse_modes: bool,
+}
+
+impl Dispi {
+    pub fn new(access: Access, id: u16, memory_64k: u16) -> Self {
+        let mut regs = [0; REGISTERS];
+        regs[INDEX_ID] = id;
+        regs[INDEX_XRES] = 640;
+        regs[INDEX_YRES] = 480;
+        regs[INDEX_BPP] = 8;
+        regs[INDEX_VIDEO_MEMORY_64K] = memory_64k;
+        Dispi { access: access, regs: regs, index: 0, max: (2560, 1600, 32), writes: Vec::new(), modes: Vec::new(),
+            refuse_modes: false }
+    }
+    pub fn enable(&self) -> u16 {
+        self.regs[INDEX_ENABLE]
+    }
+    fn read_reg(&self, index: u16) -> u16 {
+        let index = index as usize;
+        if self.regs[INDEX_ENABLE] & GETCAPS != 0 {
+            match index {
+                INDEX_XRES => return self.max.0,
+                INDEX_YRES => return self.max.1,
+                INDEX_BPP => return self.max.2,
+                _ => (),
+            }
+        }
+        self.regs.get(index).cloned().unwrap_or(0)
+    }
+    fn write_reg(&mut self, index: u16, value: u16) {
+        self.writes.push((index, value));
+        let enabled = self.regs[INDEX_ENABLE] & ENABLED != 0;
+        match index as usize {
+            INDEX_ID | INDEX_VIDEO_MEMORY_64K => (),
+            INDEX_XRES | INDEX_YRES | INDEX_BPP if enabled => panic!("Mode register {} written while enabled", index),
+            INDEX_XRES if value > self.max.0 || value % 8 != 0 => (),
+            INDEX_YRES if value > self.max.1 => (),
+            INDEX_BPP if [4, 8, 15, 16, 24, 32].iter().all(|&bpp| bpp != value) => (),
+            INDEX_ENABLE => {
+                if value & ENABLED != 0 && !enabled {
+                    let (width, height, bpp) = (self.regs[INDEX_XRES], self.regs[INDEX_YRES], self.regs[INDEX_BPP]);
+                    let size = width as usize * height as usize * ((bpp as usize + 7) / 8);
+                    if self.refuse_modes || size > self.regs[INDEX_VIDEO_MEMORY_64K] as usize * 64 * 1024 {
+                        return;
+                    }
+                    self.modes.push((width, height, bpp));
+                    self.regs[INDEX_VIRT_WIDTH] = width;
+                }
+                self.regs[INDEX_ENABLE] = value;
+            },
+            index if index < REGISTERS => self.regs[index] = value,
+            _ => panic!("Write to register {:#x}", index),
+        }
+    }
+}
+
+impl MixedIo for Dispi {
+    unsafe fn read8(&mut self, offset: usize) -> u8 {
+        panic!("Byte read at {:#x}", offset)
+    }
+    unsafe fn read16(&mut self, offset: usize) -> u16 {
+        match self.access {
+            Access::Ports => {
+                assert_eq!(offset, 1, "Word read from port {:#x}", offset);
+                let index = self.index;
+                self.read_reg(index)
+            },
+            Access::Mmio => {
+                assert_eq!(offset % 2, 0, "Misaligned read at {:#x}", offset);
+                self.read_reg((offset / 2) as u16)
+            },
+        }
+    }
+    unsafe fn read32(&mut self, offset: usize) -> u32 {
+        panic!("Dword read at {:#x}", offset)
+    }
+    unsafe fn write8(&mut self, offset: usize, _value: u8) {
+        panic!("Byte write at {:#x}", offset)
+    }
+    unsafe fn write16(&mut self, offset: usize, value: u16) {
+        match self.access {
+            Access::Ports => match offset {
+                0 => self.index = value,
+                1 => {
+                    let index = self.index;
+                    self.write_reg(index, value)
+                },
+                _ => panic!("Word write to port {:#x}", offset),
+            },
+            Access::Mmio => {
+                assert_eq!(offset % 2, 0, "Misaligned write at {:#x}", offset);
+                self.write_reg((offset / 2) as u16, value)
+            },
+        }
+    }
+    unsafe fn write32(&mut self, offset: usize, _value: u32) {
+        panic!("Dword write at {:#x}", offset)
+    }
+}
@@ -0,0 +1,277 @@
+//! Model of an e1000 or e1000e network device
+//!
+//! Only the registers the driver uses are modelled, with every other register reading as 0.
+//! Frames are transmitted as soon as the tail is written, by reading the descriptors and buffers
+//! from host memory. Received frames are delivered by the test into whichever descriptors the
+//! driver has given the device, with descriptors written back as the hardware would.
+
+use std::collections::BTreeMap;
+use std::ptr;
+
+use drivers::e1000::Model;
+use drivers::io::Io;
+
+const CTRL: usize = 0x0000;
+const STATUS: usize = 0x0008;
+const EERD: usize = 0x0014;
+const ICR: usize = 0x00C0;
+const IMS: usize = 0x00D0;
+const IMC: usize = 0x00D8;
+const RCTL: usize = 0x0100;
+const TCTL: usize = 0x0400;
+const RDBAL: usize = 0x2800;
+const RDBAH: usize = 0x2804;
+const RDLEN: usize = 0x2808;
+const RDH: usize = 0x2810;
+const RDT: usize = 0x2818;
+const TDBAL: usize = 0x3800;
+const TDBAH: usize = 0x3804;
+const TDLEN: usize = 0x3808;
+const TDH: usize = 0x3810;
+const TDT: usize = 0x3818;
+const RAL0: usize = 0x5400;
+const RAH0: usize = 0x5404;
+
+const CTRL_RST: u32 = 1 << 26;
+const STATUS_LU: u32 = 1 << 1;
+const ICR_TXDW: u32 = 1 << 0;
+const ICR_LSC: u32 = 1 << 2;
+const ICR_RXO: u32 = 1 << 6;
+const ICR_RXT0: u32 = 1 << 7;
+const RCTL_EN: u32 = 1 << 1;
+const RCTL_UPE: u32 = 1 << 3;
+const TCTL_EN: u32 = 1 << 1;
+const RAH_AV: u32 = 1 << 31;
+
+pub struct E1000 {
+    model: Model,
+    /// Every register written, which is what they read back as
+    regs: BTreeMap<usize, u32>,
+    /// Contents of the EEPROM, or none for a device without one
+    eeprom: Option<Vec<u16>>,
+    /// Address firmware left in the receive address registers
+    firmware_mac: Option<[u8; 6]>,
+    link: bool,
+    icr: u32,
+    ims: u32,
+    /// Reads of `CTRL` during which a reset is still in progress
+    resetting: usize,
+    pub resets: usize,
+    /// Every frame transmitted
+    pub sent: Vec<Vec<u8>>,
+    /// Leave transmit descriptors unprocessed, as if the wire were slow
+    pub hold_tx: bool,
+    /// Frames dropped for want of receive descriptors
+    pub overruns: usize,
+}
+
+impl E1000 {
+    pub fn new(model: Model, mac: [u8; 6]) -> Self {
+        let mut eeprom = vec![0; 64];
+        for word in 0..3 {
+            eeprom[word] = mac[word * 2] as u16 | (mac[word * 2 + 1] as u16) << 8;
+        }
+        let mut e1000 = E1000 {
+            model: model,
+            regs: BTreeMap::new(),
+            eeprom: Some(eeprom),
+            firmware_mac: None,
+            link: true,
+            icr: 0,
+            ims: 0,
+            resetting: 0,
+            resets: 0,
+            sent: Vec::new(),
+            hold_tx: false,
+            overruns: 0,
+        };
+        e1000.reset();
+        e1000
+    }
+    /// Without an EEPROM, with firmware having left `mac` in the receive address registers
+    pub fn without_eeprom(mut self, mac: Option<[u8; 6]>) -> Self {
+        self.eeprom = None;
+        self.firmware_mac = mac;
+        self.reset();
+        self
+    }
+    fn reset(&mut self) {
+        self.regs.clear();
+        if let Some(mac) = self.firmware_mac {
+            self.regs.insert(RAL0, mac[0] as u32 | (mac[1] as u32) << 8 | (mac[2] as u32) << 16 | (mac[3] as u32) << 24);
+            self.regs.insert(RAH0, mac[4] as u32 | (mac[5] as u32) << 8 | RAH_AV);
+        }
+        self.ims = 0;
+        self.icr = 0;
+    }
+    fn reg(&self, offset: usize) -> u32 {
+        self.regs.get(&offset).cloned().unwrap_or(0)
+    }
+    pub fn model(&self) -> Model {
+        self.model
+    }
+    pub fn ims(&self) -> u32 {
+        self.ims
+    }
+    pub fn rctl(&self) -> u32 {
+        self.reg(RCTL)
+    }
+    /// Receive address, as the driver programmed it
+    pub fn mac(&self) -> Option<[u8; 6]> {
+        let (low, high) = (self.reg(RAL0), self.reg(RAH0));
+        if high & RAH_AV == 0 {
+            return None;
+        }
+        Some([low as u8, (low >> 8) as u8, (low >> 16) as u8, (low >> 24) as u8, high as u8, (high >> 8) as u8])
+    }
+    pub fn set_link(&mut self, up: bool) {
+        if self.link != up {
+            self.link = up;
+            self.icr |= ICR_LSC;
+        }
+    }
+    /// Pending interrupt causes, without clearing them
+    pub fn icr(&self) -> u32 {
+        self.icr
+    }
+    /// Whether the INTx line is held, which it is while an unmasked cause is pending
+    pub fn asserted(&self) -> bool {
+        self.icr & self.ims != 0
+    }
+    fn ring(&self, low: usize, high: usize) -> u64 {
+        self.reg(low) as u64 | (self.reg(high) as u64) << 32
+    }
+    /// Receive a frame into the next descriptor, returning false if there was none to put it in
+    pub fn deliver(&mut self, frame: &[u8]) -> bool {
+        assert!(self.reg(RCTL) & RCTL_EN != 0, "Frame delivered with the receiver disabled");
+        let count = self.reg(RDLEN) as usize / 16;
+        let (head, tail) = (self.reg(RDH) as usize, self.reg(RDT) as usize);
+        if head == tail {
+            self.overruns += 1;
+            self.icr |= ICR_RXO;
+            return false;
+        }
+        let desc = (self.ring(RDBAL, RDBAH) + head as u64 * 16) as usize as *mut u8;
+        unsafe {
+            let buffer = ptr::read(desc as *const u64);
+            assert!(frame.len() <= 2048, "Frame larger than the receive buffer");
+            ptr::copy_nonoverlapping(frame.as_ptr(), buffer as usize as *mut u8, frame.len());
+            ptr::write(desc.offset(8) as *mut u16, frame.len() as u16);
+            // Descriptor done and end of packet, with no errors
+            ptr::write(desc.offset(12), 0b11);
+            ptr::write(desc.offset(13), 0);
+        }
+        self.regs.insert(RDH, ((head + 1) % count) as u32);
+        self.icr |= ICR_RXT0;
+        true
+    }
+    /// Send everything between the transmit head and tail
+    pub fn run_tx(&mut self) {
+        if self.reg(TCTL) & TCTL_EN == 0 {
+            return;
+        }
+        let count = self.reg(TDLEN) as usize / 16;
+        let ring = self.ring(TDBAL, TDBAH);
+        let mut frame = Vec::new();
+        while self.reg(TDH) != self.reg(TDT) {
+            let head = self.reg(TDH) as usize;
+            let desc = (ring + head as u64 * 16) as usize as *mut u8;
+            unsafe {
+                let buffer = ptr::read(desc as *const u64) as usize as *const u8;
+                let len = ptr::read(desc.offset(8) as *const u16) as usize;
+                let cmd = ptr::read(desc.offset(11));
+                assert_eq!(cmd & 0x20, 0, "Extended descriptors are not modelled");
+                frame.extend_from_slice(std::slice::from_raw_parts(buffer, len));
+                if cmd & 1 != 0 {
+                    self.sent.push(std::mem::take(&mut frame));
+                }
+                // Report status
+                if cmd & 1 << 3 != 0 {
+                    ptr::write(desc.offset(12), 1);
+                }
+            }
+            self.regs.insert(TDH, ((head + 1) % count) as u32);
+            self.icr |= ICR_TXDW;
+        }
+    }
+    /// Whether the driver has asked for every frame
+    pub fn promiscuous(&self) -> bool {
+        self.reg(RCTL) & RCTL_UPE != 0
+    }
+}
+
+impl Io for E1000 {
+    type Item = u32;
+    unsafe fn read(&mut self, offset: usize) -> u32 {
+        assert_eq!(offset % 4, 0, "Misaligned register read {:#x}", offset);
+        match offset {
+            CTRL => {
+                if self.resetting > 0 {
+                    self.resetting -= 1;
+                    if self.resetting == 0 {
+                        let ctrl = self.reg(CTRL) & !CTRL_RST;
+                        self.regs.insert(CTRL, ctrl);
+                    }
+                }
+                self.reg(CTRL)
+            },
+            STATUS => if self.link { STATUS_LU } else { 0 },
+            ICR => {
+                let icr = self.icr;
+                self.icr = 0;
+                icr
+            },
+            IMS => self.ims,
+            _ => self.reg(offset),
+        }
+    }
+    unsafe fn write(&mut self, offset: usize, value: u32) {
+        assert_eq!(offset % 4, 0, "Misaligned register write {:#x}", offset);
+        match offset {
+            CTRL if value & CTRL_RST != 0 => {
+                self.resets += 1;
+                self.reset();
+                self.regs.insert(CTRL, value);
+                self.resetting = 3;
+            },
+            EERD => {
+                let (address, done) = match self.model {
+                    Model::I8254x => ((value >> 8) & 0xFF, 1 << 4),
+                    Model::I82574 => ((value >> 2) & 0x3FFF, 1 << 1),
+                };
+                assert!(value & 1 != 0, "EEPROM read without start");
+                let result = match self.eeprom {
+                    Some(ref eeprom) => (eeprom[address as usize] as u32) << 16 | done | value,
+                    // Without an EEPROM the read never completes
+                    None => value,
+                };
+                self.regs.insert(EERD, result);
+            },
+            ICR => self.icr &= !value,
+            IMS => self.ims |= value,
+            IMC => self.ims &= !value,
+            RDH | TDH => {
+                assert_eq!(value, 0, "Head set to anything but 0");
+                self.regs.insert(offset, value);
+            },
+            RDT => {
+                assert!((value as usize) < self.reg(RDLEN) as usize / 16, "Receive tail beyond the ring");
+                self.regs.insert(offset, value);
+            },
+            TDT => {
+                assert!((value as usize) < self.reg(TDLEN) as usize / 16, "Transmit tail beyond the ring");
+                self.regs.insert(offset, value);
+                if !self.hold_tx {
+                    self.run_tx();
+                }
+            },
+            RDLEN | TDLEN => {
+                assert_eq!(value % 128, 0, "Ring length is not a multiple of 128 bytes");
+                self.regs.insert(offset, value);
+            },
+            _ => {
+                self.regs.insert(offset, value);
+            },
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Model of QEMU's fw_cfg, with both the port and DMA interfaces
+//!
+//! DMA requests are carried out on host memory, as physical addresses are the same as virtual
+//! ones in the tests.
+
+use std::ptr;
+
+use drivers::io::MixedIo;
+
+const KEY_FILE_DIR: u16 = 0x19;
+/// Key of the first file, as QEMU numbers them
+const FIRST_FILE_KEY: u16 = 0x20;
+
+pub struct FwCfg {
+    items: Vec<(u16, Vec<u8>)>,
+    files: Vec<(String, u16)>,
+    selected: u16,
+    offset: usize,
+    dma: bool,
+    dma_high: u32,
+    /// Fail every DMA request
+    pub dma_error: bool,
+    pub port_reads: usize,
+    /// Every DMA request as (control, length)
+    pub dma_requests: Vec<(u32, u32)>,
+}
+
+impl FwCfg {
+    pub fn new(dma: bool) -> Self {
+        let id = if dma { 3 } else { 1 };
+        let mut fw_cfg = FwCfg {
+            items: vec![(0, b"QEMU".to_vec()), (1, vec![id, 0, 0, 0])],
+            files: Vec::new(),
+            selected: 0,
+            offset: 0,
+            dma: dma,
+            dma_high: 0,
+            dma_error: false,
+            port_reads: 0,
+            dma_requests: Vec::new(),
+        };
+        fw_cfg.build_directory();
+        fw_cfg
+    }
+    pub fn file(mut self, name: &str, data: Vec<u8>) -> Self {
+        let key = FIRST_FILE_KEY + self.files.len() as u16;
+        self.items.push((key, data));
+        self.files.push((name.to_string(), key));
+        self.build_directory();
+        self
+    }
+    fn build_directory(&mut self) {
+        let mut dir = (self.files.len() as u32).to_be_bytes().to_vec();
+        for &(ref name, key) in self.files.iter() {
+            let size = self.items.iter().find(|item| item.0 == key).unwrap().1.len() as u32;
+            dir.extend_from_slice(&size.to_be_bytes());
+            dir.extend_from_slice(&key.to_be_bytes());
+            dir.extend_from_slice(&[0, 0]);
+            let mut name_bytes = name.as_bytes().to_vec();
+            name_bytes.resize(56, 0);
+            dir.extend_from_slice(&name_bytes);
+        }
+        self.items.retain(|item| item.0 != KEY_FILE_DIR);
+        self.items.push((KEY_FILE_DIR, dir));
+    }
+    fn next_byte(&mut self) -> u8 {
+        let selected = self.selected;
+        let byte = self.items.iter().find(|item| item.0 == selected)
+            .and_then(|item| item.1.get(self.offset).cloned())
+            .unwrap_or(0);
+        self.offset += 1;
+        byte
+    }
+    unsafe fn process_dma(&mut self, address: u64) {
+        let request = address as usize as *mut [u8; 16];
+        let fields = ptr::read_volatile(request);
+        let control = u32::from_be_bytes([fields[0], fields[1], fields[2], fields[3]]);
+        let len = u32::from_be_bytes([fields[4], fields[5], fields[6], fields[7]]);
+        let mut addr = [0; 8];
+        addr.copy_from_slice(&fields[8..16]);
+        let addr = u64::from_be_bytes(addr);
+        self.dma_requests.push((control, len));
+        let status: u32 = if self.dma_error {
+            1
+        } else {
+            if control & 8 != 0 {
+                self.selected = (control >> 16) as u16;
+                self.offset = 0;
+            }
+            assert_eq!(control & 0xFFFF & !(8 | 2), 0, "Unexpected DMA control {:#x}", control);
+            let data: Vec<u8> = (0..len).map(|_| self.next_byte()).collect();
+            ptr::copy_nonoverlapping(data.as_ptr(), addr as usize as *mut u8, data.len());
+            0
+        };
+        ptr::write_volatile(request as *mut u32, status.to_be());
+    }
+}
+
+impl MixedIo for FwCfg {
+    unsafe fn read8(&mut self, offset: usize) -> u8 {
+        assert_eq!(offset, 1, "Byte read at {:#x}", offset);
+        self.port_reads += 1;
+        self.next_byte()
+    }
+    unsafe fn read16(&mut self, offset: usize) -> u16 {
+        panic!("Word read at {:#x}", offset)
+    }
+    unsafe fn read32(&mut self, offset: usize) -> u32 {
+        panic!("Dword read at {:#x}", offset)
+    }
+    unsafe fn write8(&mut self, offset: usize, _value: u8) {
+        panic!("Byte write at {:#x}", offset)
+    }
+    unsafe fn write16(&mut self, offset: usize, value: u16) {
+        assert_eq!(offset, 0, "Word write at {:#x}", offset);
+        self.selected = value;
+        self.offset = 0;
+    }
+    unsafe fn write32(&mut self, offset: usize, value: u32) {
+        assert!(self.dma, "DMA register written without DMA");
+        match offset {
+            4 => self.dma_high = u32::from_be(value),
+            8 => {
+                let address = (self.dma_high as u64) << 32 | u32::from_be(value) as u64;
+                self.process_dma(address);
+            },
+            _ => panic!("Dword write at {:#x}", offset),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Model of an HPET block
+//!
+//! The main counter does not run in real time, instead it advances by a fixed step every time it
+//! is read while enabled. Comparator writes are recorded rather than acted on, along with whether
+//! the main counter was running at the time.
+
+use drivers::io::Io;
+use super::Access;
+
+const CAPABILITIES: usize = 0x000;
+const CONFIG: usize = 0x010;
+const INTERRUPT_STATUS: usize = 0x020;
+const MAIN_COUNTER: usize = 0x0F0;
+const TIMER_BASE: usize = 0x100;
+const TIMER_STRIDE: usize = 0x20;
+const TIMER_CONFIG: usize = 0x00;
+const TIMER_COMPARATOR: usize = 0x08;
+
+const CONFIG_ENABLE: u64 = 1 << 0;
+
+const CAP_COUNTER_64: u64 = 1 << 13;
+const CAP_LEGACY: u64 = 1 << 15;
+
+const TIMER_PERIODIC_CAPABLE: u64 = 1 << 4;
+const TIMER_VALUE_SET: u64 = 1 << 6;
+/// Bits of a timer configuration that software can change
+const TIMER_WRITABLE: u64 = 0b0111_1111_0100_1110;
+
+#[derive(Debug, Clone)]
+struct Timer {
+    config: u64,
+    /// Every comparator write, as (value, main counter running)
+    comparator: Vec<(u64, bool)>,
+}
+
+pub struct Hpet {
+    capabilities: u64,
+    config: u64,
+    status: u64,
+    counter: u64,
+    step: u64,
+    timers: Vec<Timer>,
+    accesses: Vec<Access<usize, u64>>,
+}
+
+impl Hpet {
+    /// A block with a 64-bit counter of the given period, that can do legacy replacement, where
+    /// only timer 0 is periodic capable and timers can be routed to inputs 2 and 20 to 23
+    pub fn new(period_fs: u32, timers: usize) -> Self {
+        let capabilities = (period_fs as u64) << 32 | CAP_LEGACY | CAP_COUNTER_64
+            | ((timers as u64 - 1) << 8) | 0x8086 << 16 | 1;
+        let timers = (0..timers).map(|index| Timer {
+            config: if index == 0 { TIMER_PERIODIC_CAPABLE } else { 0 } | 0x00F0_0004 << 32,
+            comparator: Vec::new(),
+        }).collect();
+        Hpet {
+            capabilities: capabilities,
+            config: 0,
+            status: 0,
+            counter: 0,
+            step: 1,
+            timers: timers,
+            accesses: Vec::new(),
+        }
+    }
+    /// Replace the capabilities register entirely, such as to look like a missing device
+    pub fn with_capabilities(mut self, capabilities: u64) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+    pub fn counter_32(mut self) -> Self {
+        self.capabilities &= !CAP_COUNTER_64;
+        self
+    }
+    pub fn no_legacy(mut self) -> Self {
+        self.capabilities &= !CAP_LEGACY;
+        self
+    }
+    /// Advance the main counter by `step` on every read
+    pub fn step(mut self, step: u64) -> Self {
+        self.step = step;
+        self
+    }
+    pub fn set_counter(&mut self, counter: u64) {
+        self.counter = counter;
+    }
+    pub fn config(&self) -> u64 {
+        self.config
+    }
+    pub fn timer_config(&self, timer: usize) -> u64 {
+        self.timers[timer].config
+    }
+    /// Whether the last configuration write to a timer asked for the periodic accumulator to
+    /// be set
+    pub fn value_set(&self, timer: usize) -> bool {
+        self.timers[timer].config & TIMER_VALUE_SET != 0
+    }
+    pub fn comparator_writes(&self, timer: usize) -> &[(u64, bool)] {
+        &self.timers[timer].comparator
+    }
+    /// Flag a level triggered interrupt from a timer as pending
+    pub fn raise(&mut self, timer: usize) {
+        self.status |= 1 << timer;
+    }
+    pub fn status(&self) -> u64 {
+        self.status
+    }
+    pub fn accesses(&self) -> &[Access<usize, u64>] {
+        &self.accesses
+    }
+    fn running(&self) -> bool {
+        self.config & CONFIG_ENABLE != 0
+    }
+    fn mask(&self) -> u64 {
+        if self.capabilities & CAP_COUNTER_64 != 0 { !0 } else { 0xFFFF_FFFF }
+    }
+    fn timer(&mut self, offset: usize) -> (&mut Timer, usize) {
+        let index = (offset - TIMER_BASE) / TIMER_STRIDE;
+        (&mut self.timers[index], (offset - TIMER_BASE) % TIMER_STRIDE)
+    }
+}
+
+impl Io for Hpet {
+    type Item = u64;
+    unsafe fn read(&mut self, offset: usize) -> u64 {
+        let value = match offset {
+            CAPABILITIES => self.capabilities,
+            CONFIG => self.config,
+            INTERRUPT_STATUS => self.status,
+            MAIN_COUNTER => {
+                let value = self.counter & self.mask();
+                if self.running() {
+                    self.counter = self.counter.wrapping_add(self.step);
+                }
+                value
+            },
+            _ if offset >= TIMER_BASE => match self.timer(offset) {
+                (timer, TIMER_CONFIG) => timer.config,
+                (timer, TIMER_COMPARATOR) => timer.comparator.last().map_or(!0, |c| c.0),
+                (_, reg) => panic!("Read from unknown HPET timer register {:#x}", reg),
+            },
+            _ => panic!("Read from unknown HPET register {:#x}", offset),
+        };
+        self.accesses.push(Access::Read(offset, value));
+        value
+    }
+    unsafe fn write(&mut self, offset: usize, value: u64) {
+        self.accesses.push(Access::Write(offset, value));
+        let running = self.running();
+        match offset {
+            CONFIG => self.config = value & 0b11,
+            // Write 1 to clear
+            INTERRUPT_STATUS => self.status &= !value,
+            MAIN_COUNTER => {
+                assert!(!running, "HPET main counter written while running");
+                self.counter = value;
+            },
+            _ if offset >= TIMER_BASE => match self.timer(offset) {
+                (timer, TIMER_CONFIG) => timer.config = (timer.config & !TIMER_WRITABLE) | (value & TIMER_WRITABLE),
+                (timer, TIMER_COMPARATOR) => {
+                    timer.comparator.push((value, running));
+                },
+                (_, reg) => panic!("Write to unknown HPET timer register {:#x}", reg),
+            },
+            _ => panic!("Write to read only HPET register {:#x}", offset),
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! Model of an 8042 PS/2 controller with a keyboard attached to the first port
+//!
+//! The controller is seen through a window based at 0x60, with data at offset 0 and status and
+//! command at offset 4. The controller responds to commands instantly, so the input buffer is
+//! never full. Translation to scancode set 1 is not modelled, bytes from the keyboard are passed
+//! through as is.
+
+use std::collections::VecDeque;
+
+use drivers::io::Io;
+use super::Access;
+
+const DATA: u16 = 0;
+const STATUS_COMMAND: u16 = 4;
+
+const STATUS_OUTPUT_FULL: u8 = 0x1;
+const STATUS_SYSTEM: u8 = 0x4;
+const STATUS_COMMAND_LAST: u8 = 0x8;
+
+const CONFIG_PORT1_INT: u8 = 0x1;
+const CONFIG_PORT2_INT: u8 = 0x2;
+const CONFIG_SYSTEM: u8 = 0x4;
+const CONFIG_PORT1_CLOCK_DISABLE: u8 = 0x10;
+const CONFIG_PORT2_CLOCK_DISABLE: u8 = 0x20;
+const CONFIG_TRANSLATE: u8 = 0x40;
+
+/// Configuration as left by a typical BIOS
+const CONFIG_DEFAULT: u8 = CONFIG_PORT1_INT | CONFIG_PORT2_INT | CONFIG_SYSTEM | CONFIG_TRANSLATE;
+
+const ACK: u8 = 0xFA;
+const RESEND: u8 = 0xFE;
+
+/// Model of a keyboard
+pub struct Keyboard {
+    set: u8,
+    /// Whether the keyboard agrees to change scancode sets
+    accepts_set_change: bool,
+    scanning: bool,
+    leds: u8,
+    /// Command waiting for its argument byte
+    pending: Option<u8>,
+}
+
+impl Keyboard {
+    fn reset(&mut self) {
+        self.scanning = true;
+        self.leds = 0;
+        self.pending = None;
+    }
+    /// Handle a byte sent from the host, returning the bytes sent in response
+    fn receive(&mut self, byte: u8) -> Vec<u8> {
+        if let Some(command) = self.pending.take() {
+            return match command {
+                0xED => {
+                    self.leds = byte & 0x7;
+                    vec![ACK]
+                },
+                0xF0 => match byte {
+                    0 => vec![ACK, self.set],
+                    1 | 2 | 3 if self.accepts_set_change => {
+                        self.set = byte;
+                        vec![ACK]
+                    },
+                    // Refuse, the host has to start the command over
+                    _ => vec![RESEND],
+                },
+                _ => unreachable!(),
+            };
+        }
+        match byte {
+            0xED | 0xF0 => {
+                self.pending = Some(byte);
+                vec![ACK]
+            },
+            0xF4 => {
+                self.scanning = true;
+                vec![ACK]
+            },
+            0xF5 => {
+                self.scanning = false;
+                vec![ACK]
+            },
+            0xFF => {
+                self.reset();
+                vec![ACK, 0xAA]
+            },
+            _ => vec![RESEND],
+        }
+    }
+    pub fn leds(&self) -> u8 {
+        self.leds
+    }
+    pub fn set(&self) -> u8 {
+        self.set
+    }
+}
+
+pub struct I8042 {
+    config: u8,
+    dual: bool,
+    self_test_result: u8,
+    port1_test_result: u8,
+    /// Whether a controller self test resets the configuration byte
+    self_test_resets: bool,
+    /// Command waiting for a data byte
+    pending: Option<u8>,
+    last_was_command: bool,
+    output: VecDeque<u8>,
+    reset_pulsed: bool,
+    keyboard: Keyboard,
+    accesses: Vec<Access<u16, u8>>,
+}
+
+impl Default for I8042 {
+    fn default() -> Self {
+        I8042 {
+            config: CONFIG_DEFAULT,
+            dual: true,
+            self_test_result: 0x55,
+            port1_test_result: 0,
+            self_test_resets: false,
+            pending: None,
+            last_was_command: false,
+            output: VecDeque::new(),
+            reset_pulsed: false,
+            keyboard: Keyboard {set: 2, accepts_set_change: true, scanning: true, leds: 0, pending: None},
+            accesses: Vec::new(),
+        }
+    }
+}
+
+impl I8042 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Controller with only a single port
+    pub fn single_port(mut self) -> Self {
+        self.dual = false;
+        self
+    }
+    /// Controller whose self test fails with the given code
+    pub fn failing_self_test(mut self, code: u8) -> Self {
+        self.self_test_result = code;
+        self
+    }
+    /// Controller whose self test resets the configuration byte
+    pub fn self_test_resets(mut self) -> Self {
+        self.self_test_resets = true;
+        self
+    }
+    /// Keyboard that is stuck in the given scancode set
+    pub fn fixed_set(mut self, set: u8) -> Self {
+        self.keyboard.set = set;
+        self.keyboard.accepts_set_change = false;
+        self
+    }
+    fn port1_enabled(&self) -> bool {
+        self.config & CONFIG_PORT1_CLOCK_DISABLE == 0
+    }
+    fn command(&mut self, command: u8) {
+        match command {
+            0x20 => self.output.push_back(self.config),
+            0x60 => self.pending = Some(command),
+            0xA7 => if self.dual { self.config |= CONFIG_PORT2_CLOCK_DISABLE },
+            0xA8 => if self.dual { self.config &= !CONFIG_PORT2_CLOCK_DISABLE },
+            0xA9 => self.output.push_back(0),
+            0xAA => {
+                if self.self_test_resets {
+                    self.config = CONFIG_DEFAULT;
+                }
+                self.output.push_back(self.self_test_result);
+            },
+            0xAB => self.output.push_back(self.port1_test_result),
+            0xAD => self.config |= CONFIG_PORT1_CLOCK_DISABLE,
+            0xAE => self.config &= !CONFIG_PORT1_CLOCK_DISABLE,
+            0xFE => self.reset_pulsed = true,
+            _ => panic!("Unknown 8042 command {:#x}", command),
+        }
+    }
+    fn data(&mut self, value: u8) {
+        match self.pending.take() {
+            Some(0x60) => {
+                self.config = value;
+                if !self.dual {
+                    // A single port controller has nothing to disable
+                    self.config &= !CONFIG_PORT2_CLOCK_DISABLE;
+                }
+            },
+            Some(_) => unreachable!(),
+            None => if self.port1_enabled() {
+                let response = self.keyboard.receive(value);
+                self.output.extend(response);
+            },
+        }
+    }
+    /// Keyboard sends the provided scancode bytes
+    ///
+    /// Bytes are dropped if the keyboard is not scanning or the port is disabled
+    pub fn keys(&mut self, bytes: &[u8]) {
+        if self.keyboard.scanning && self.port1_enabled() {
+            self.output.extend(bytes);
+        }
+    }
+    pub fn config(&self) -> u8 {
+        self.config
+    }
+    pub fn keyboard(&self) -> &Keyboard {
+        &self.keyboard
+    }
+    /// Whether the CPU reset line has been pulsed
+    pub fn reset_pulsed(&self) -> bool {
+        self.reset_pulsed
+    }
+    /// Bytes waiting to be read by the driver
+    pub fn output_pending(&self) -> usize {
+        self.output.len()
+    }
+    pub fn accesses(&self) -> &[Access<u16, u8>] {
+        &self.accesses
+    }
+}
+
+impl Io for I8042 {
+    type Item = u8;
+    type Range = u16;
+    unsafe fn read(&mut self, offset: u16) -> u8 {
+        let value = match offset {
+            DATA => self.output.pop_front().unwrap_or(0),
+            STATUS_COMMAND => {
+                let mut status = STATUS_SYSTEM;
+                if !self.output.is_empty() {
+                    status |= STATUS_OUTPUT_FULL;
+                }
+                if self.last_was_command {
+                    status |= STATUS_COMMAND_LAST;
+                }
+                status
+            },
+            _ => panic!("Read from offset {} outside of 8042 ports", offset),
+        };
+        self.accesses.push(Access::Read(offset, value));
+        value
+    }
+    unsafe fn write(&mut self, offset: u16, value: u8) {
+        self.accesses.push(Access::Write(offset, value));
+        match offset {
+            DATA => {
+                self.last_was_command = false;
+                self.data(value);
+            },
+            STATUS_COMMAND => {
+                self.last_was_command = true;
+                self.command(value);
+            },
+            _ => panic!("Write to offset {} outside of 8042 ports", offset),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Model of an I/O APIC
+//!
+//! Seen through its register window with IOREGSEL at offset 0 and IOWIN at offset 0x10.
+
+use drivers::io::Io;
+use super::Access;
+
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+
+const ID: u32 = 0x00;
+const VERSION: u32 = 0x01;
+const REDIRECTION_BASE: u32 = 0x10;
+
+/// Mask bit of a redirection entry, set at reset
+const MASKED: u64 = 1 << 16;
+
+pub struct IoApic {
+    id: u8,
+    select: u32,
+    redirection: Vec<u64>,
+    /// Every write to an entry, as (entry, full value after the write)
+    history: Vec<(usize, u64)>,
+    accesses: Vec<Access<usize, u32>>,
+}
+
+impl IoApic {
+    pub fn new(id: u8, entries: usize) -> Self {
+        IoApic {
+            id: id,
+            select: 0,
+            redirection: vec![MASKED; entries],
+            history: Vec::new(),
+            accesses: Vec::new(),
+        }
+    }
+    pub fn redirection(&self, entry: usize) -> u64 {
+        self.redirection[entry]
+    }
+    pub fn history(&self) -> &[(usize, u64)] {
+        &self.history
+    }
+    pub fn accesses(&self) -> &[Access<usize, u32>] {
+        &self.accesses
+    }
+    fn read_register(&self) -> u32 {
+        match self.select {
+            ID => (self.id as u32) << 24,
+            VERSION => ((self.redirection.len() as u32 - 1) << 16) | 0x20,
+            reg if reg >= REDIRECTION_BASE => {
+                let entry = self.redirection[((reg - REDIRECTION_BASE) / 2) as usize];
+                if reg % 2 == 0 { entry as u32 } else { (entry >> 32) as u32 }
+            },
+            reg => panic!("Read from unknown I/O APIC register {:#x}", reg),
+        }
+    }
+    fn write_register(&mut self, value: u32) {
+        let reg = self.select;
+        assert!(reg >= REDIRECTION_BASE, "Write to read only I/O APIC register {:#x}", reg);
+        let index = ((reg - REDIRECTION_BASE) / 2) as usize;
+        let entry = &mut self.redirection[index];
+        if reg % 2 == 0 {
+            *entry = (*entry & !0xFFFF_FFFF) | value as u64;
+        } else {
+            *entry = (*entry & 0xFFFF_FFFF) | (value as u64) << 32;
+        }
+        let entry = *entry;
+        self.history.push((index, entry));
+    }
+}
+
+impl Io for IoApic {
+    type Item = u32;
+    unsafe fn read(&mut self, offset: usize) -> u32 {
+        let value = match offset {
+            IOREGSEL => self.select,
+            IOWIN => self.read_register(),
+            _ => panic!("Read from offset {:#x} outside of I/O APIC registers", offset),
+        };
+        self.accesses.push(Access::Read(offset, value));
+        value
+    }
+    unsafe fn write(&mut self, offset: usize, value: u32) {
+        self.accesses.push(Access::Write(offset, value));
+        match offset {
+            IOREGSEL => self.select = value,
+            IOWIN => self.write_register(value),
+            _ => panic!("Write to offset {:#x} outside of I/O APIC registers", offset),
+        }
+    }
+}
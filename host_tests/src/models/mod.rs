@@ -0,0 +1,68 @@
+//! Register level device models
+//!
+//! Each model implements `Io` as the real device would respond to it, and records every access
+//! made so that tests can check not just the end state, but how a driver got there.
+
+use std::cell::{RefCell, RefMut};
+use std::rc::Rc;
+
+use drivers::io::Io;
+
+pub mod uart16550;
+pub mod i8042;
+pub mod pic8259;
+pub mod apic;
+pub mod ioapic;
+pub mod pit;
+pub mod hpet;
+pub mod rtc;
+pub mod pci;
+pub mod virtio;
+pub mod block;
+pub mod net;
+pub mod fw_cfg;
+pub mod ahci;
+pub mod nvme;
+pub mod e1000;
+pub mod bochs;
+pub mod vga;
+pub mod qemu;
+
+/// A single recorded register access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access<R, T> {
+    Read(R, T),
+    Write(R, T),
+}
+
+/// Handle to a model that can be given to a driver while the test keeps its own
+///
+/// Needed when a test must interact with the device, such as delivering input, while a driver
+/// owns it.
+pub struct Shared<T>(Rc<RefCell<T>>);
+
+impl<T> Shared<T> {
+    pub fn new(model: T) -> Self {
+        Shared(Rc::new(RefCell::new(model)))
+    }
+    pub fn get(&self) -> RefMut<'_, T> {
+        self.0.borrow_mut()
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Shared(self.0.clone())
+    }
+}
+
+impl<T: Io> Io for Shared<T> {
+    type Item = T::Item;
+    type Range = T::Range;
+    unsafe fn read(&mut self, offset: T::Range) -> T::Item {
+        self.0.borrow_mut().read(offset)
+    }
+    unsafe fn write(&mut self, offset: T::Range, value: T::Item) {
+        self.0.borrow_mut().write(offset, value)
+    }
+}
@@ -0,0 +1,79 @@
+//! A network device that is only a record of frames
+//!
+//! `Wire` lets the stack be tested without any driver, with the test playing the part of every
+//! other host on the network.
+
+use std::collections::VecDeque;
+
+use net::{device, MacAddress, NetDevice};
+use super::Shared;
+
+pub struct Wire {
+    mac: MacAddress,
+    /// Every frame transmitted
+    pub sent: Vec<Vec<u8>>,
+    /// Frames waiting to be received
+    pub incoming: VecDeque<Vec<u8>>,
+    /// Refuse to transmit, as if the device's queue were full
+    pub busy: bool,
+    pub link: bool,
+}
+
+impl Wire {
+    pub fn new(mac: MacAddress) -> Self {
+        Wire { mac: mac, sent: Vec::new(), incoming: VecDeque::new(), busy: false, link: true }
+    }
+    /// Take every frame transmitted so far
+    pub fn take_sent(&mut self) -> Vec<Vec<u8>> {
+        self.sent.split_off(0)
+    }
+}
+
+impl NetDevice for Wire {
+    fn name(&self) -> &'static str {
+        "wire"
+    }
+    fn mac(&self) -> MacAddress {
+        self.mac
+    }
+    fn mtu(&self) -> usize {
+        1500
+    }
+    unsafe fn link_up(&mut self) -> bool {
+        self.link
+    }
+    unsafe fn transmit(&mut self, frame: &[u8]) -> Result<(), device::Error> {
+        if self.busy {
+            return Err(device::Error::Busy);
+        }
+        self.sent.push(frame.to_vec());
+        Ok(())
+    }
+    unsafe fn receive(&mut self) -> Option<Vec<u8>> {
+        self.incoming.pop_front()
+    }
+}
+
+impl<T: NetDevice> NetDevice for Shared<T> {
+    fn name(&self) -> &'static str {
+        self.get().name()
+    }
+    fn mac(&self) -> MacAddress {
+        self.get().mac()
+    }
+    fn mtu(&self) -> usize {
+        self.get().mtu()
+    }
+    unsafe fn link_up(&mut self) -> bool {
+        self.get().link_up()
+    }
+    unsafe fn transmit(&mut self, frame: &[u8]) -> Result<(), device::Error> {
+        self.get().transmit(frame)
+    }
+    unsafe fn receive(&mut self) -> Option<Vec<u8>> {
+        self.get().receive()
+    }
+    unsafe fn set_promiscuous(&mut self, enable: bool) -> bool {
+        self.get().set_promiscuous(enable)
+    }
+}
@@ -0,0 +1,485 @@
+//! Model of an NVMe controller with namespaces
+//!
+//! Registers are those of BAR0. Writing a submission queue doorbell runs every command up to
+//! the new tail straight away, reading commands, PRP lists and data from host memory, and posts
+//! the completions with the current phase. Completion queues are checked for overflow against
+//! the head doorbell, so a driver that forgets to ring it fails loudly.
+
+use std::collections::BTreeMap;
+use std::ptr;
+
+use drivers::io::Io;
+
+const CAP: usize = 0x00;
+const CAP_HIGH: usize = 0x04;
+const VS: usize = 0x08;
+const INTMS: usize = 0x0C;
+const INTMC: usize = 0x10;
+const CC: usize = 0x14;
+const CSTS: usize = 0x1C;
+const AQA: usize = 0x24;
+const ASQ: usize = 0x28;
+const ASQ_HIGH: usize = 0x2C;
+const ACQ: usize = 0x30;
+const ACQ_HIGH: usize = 0x34;
+const DOORBELLS: usize = 0x1000;
+
+const CC_EN: u32 = 1 << 0;
+const CC_SHN: u32 = 0b11 << 14;
+const CSTS_RDY: u32 = 1 << 0;
+const CSTS_SHST_COMPLETE: u32 = 0b10 << 2;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Generic command status values, with the status code type above the code
+pub const STATUS_INVALID_OPCODE: u16 = 0x01;
+pub const STATUS_INVALID_FIELD: u16 = 0x02;
+pub const STATUS_INVALID_NAMESPACE: u16 = 0x0B;
+pub const STATUS_LBA_OUT_OF_RANGE: u16 = 0x80;
+pub const STATUS_INVALID_QUEUE: u16 = 0x101;
+pub const STATUS_UNRECOVERED_READ: u16 = 0x281;
+
+pub struct Namespace {
+    pub data: Vec<u8>,
+    pub block_size: usize,
+    /// Metadata bytes per block of the format in use
+    pub metadata: u16,
+    /// Listed by Identify, but inactive namespaces are not
+    pub active: bool,
+}
+
+impl Namespace {
+    pub fn new(data: Vec<u8>, block_size: usize) -> Self {
+        Namespace { data: data, block_size: block_size, metadata: 0, active: true }
+    }
+}
+
+struct Submission {
+    base: u64,
+    depth: u16,
+    head: u16,
+    tail: u16,
+    cq: u16,
+}
+
+struct CompletionQueue {
+    base: u64,
+    depth: u16,
+    tail: u16,
+    head: u16,
+    phase: bool,
+    vector: Option<u16>,
+}
+
+pub struct Controller {
+    cap: u64,
+    cc: u32,
+    csts: u32,
+    aqa: u32,
+    asq: u64,
+    acq: u64,
+    pub intms: u32,
+    mdts: u8,
+    pub namespaces: Vec<Namespace>,
+    sqs: BTreeMap<u16, Submission>,
+    cqs: BTreeMap<u16, CompletionQueue>,
+    /// Sector at which transfers fail
+    pub bad_lba: Option<u64>,
+    /// Every admin command as (opcode, CDW10, CDW11)
+    pub admin: Vec<(u8, u32, u32)>,
+    /// Every I/O command as (opcode, namespace, LBA, blocks, bytes transferred)
+    pub io: Vec<(u8, u32, u64, u32, usize)>,
+    /// MSI-X vector of every interrupt raised
+    pub interrupts: Vec<u16>,
+    /// Commands are left in the submission queues until `resume`
+    pub stalled: bool,
+    pub shutdowns: usize,
+}
+
+impl Controller {
+    pub fn new(namespaces: Vec<Namespace>) -> Self {
+        Controller {
+            // NVM command set, 4KiB pages only, 256 entry queues, 4 byte doorbells
+            cap: 1 << 37 | 0xFF,
+            cc: 0,
+            csts: 0,
+            aqa: 0,
+            asq: 0,
+            acq: 0,
+            intms: 0,
+            mdts: 0,
+            namespaces: namespaces,
+            sqs: BTreeMap::new(),
+            cqs: BTreeMap::new(),
+            bad_lba: None,
+            admin: Vec::new(),
+            io: Vec::new(),
+            interrupts: Vec::new(),
+            stalled: false,
+            shutdowns: 0,
+        }
+    }
+    /// Limit transfers to 2^`mdts` pages
+    pub fn mdts(mut self, mdts: u8) -> Self {
+        self.mdts = mdts;
+        self
+    }
+    /// Limit queues to `entries` entries
+    pub fn max_entries(mut self, entries: u16) -> Self {
+        self.cap = self.cap & !0xFFFF | (entries - 1) as u64;
+        self
+    }
+    /// Space doorbells 4 << `dstrd` bytes apart
+    pub fn doorbell_stride(mut self, dstrd: u8) -> Self {
+        self.cap = self.cap & !(0xF << 32) | (dstrd as u64) << 32;
+        self
+    }
+    /// Require a larger memory page size than the driver uses
+    pub fn large_pages(mut self) -> Self {
+        self.cap |= 1 << 48;
+        self
+    }
+    pub fn cc(&self) -> u32 {
+        self.cc
+    }
+    /// Whether I/O queue `id` exists, as (submission, completion)
+    pub fn has_queue(&self, id: u16) -> (bool, bool) {
+        (self.sqs.contains_key(&id), self.cqs.contains_key(&id))
+    }
+    fn stride(&self) -> usize {
+        4 << ((self.cap >> 32) & 0xF)
+    }
+    fn enable(&mut self) {
+        assert!(self.asq != 0 && self.acq != 0, "Controller enabled without admin queues");
+        assert_eq!(self.asq % PAGE_SIZE, 0, "Admin submission queue misaligned");
+        assert_eq!(self.acq % PAGE_SIZE, 0, "Admin completion queue misaligned");
+        let sq_depth = (self.aqa & 0xFFF) as u16 + 1;
+        let cq_depth = (self.aqa >> 16 & 0xFFF) as u16 + 1;
+        self.sqs.insert(0, Submission { base: self.asq, depth: sq_depth, head: 0, tail: 0, cq: 0 });
+        // The admin completion queue always interrupts, on the first vector
+        self.cqs.insert(0, CompletionQueue { base: self.acq, depth: cq_depth, tail: 0, head: 0, phase: true, vector: Some(0) });
+        self.csts = CSTS_RDY;
+    }
+    fn disable(&mut self) {
+        self.sqs.clear();
+        self.cqs.clear();
+        self.csts &= !CSTS_RDY;
+    }
+    unsafe fn doorbell(&mut self, offset: usize, value: u32) {
+        assert_eq!(offset % self.stride(), 0, "Doorbell write to {:#x} is not on the stride", offset);
+        assert!(self.csts & CSTS_RDY != 0, "Doorbell rung while the controller is disabled");
+        let index = offset / self.stride();
+        let queue = (index / 2) as u16;
+        if index % 2 == 1 {
+            let cq = self.cqs.get_mut(&queue).unwrap_or_else(|| panic!("Head doorbell of missing queue {}", queue));
+            assert!((value as u16) < cq.depth, "Completion head {} beyond the queue", value);
+            cq.head = value as u16;
+            return;
+        }
+        {
+            let sq = self.sqs.get_mut(&queue).unwrap_or_else(|| panic!("Tail doorbell of missing queue {}", queue));
+            assert!((value as u16) < sq.depth, "Submission tail {} beyond the queue", value);
+            sq.tail = value as u16;
+        }
+        if !self.stalled {
+            self.run(queue);
+        }
+    }
+    /// Run the commands of every queue left while stalled
+    pub unsafe fn resume(&mut self) {
+        self.stalled = false;
+        let queues: Vec<u16> = self.sqs.keys().cloned().collect();
+        for queue in queues {
+            self.run(queue);
+        }
+    }
+    /// Run and complete the commands between the head and tail of a submission queue
+    unsafe fn run(&mut self, queue: u16) {
+        loop {
+            let (base, head, tail, depth) = {
+                let sq = &self.sqs[&queue];
+                (sq.base, sq.head, sq.tail, sq.depth)
+            };
+            if head == tail {
+                break;
+            }
+            let entry = (base + head as u64 * 64) as usize as *const u32;
+            let mut command = [0; 16];
+            for (index, dword) in command.iter_mut().enumerate() {
+                *dword = ptr::read(entry.offset(index as isize));
+            }
+            let new_head = (head + 1) % depth;
+            self.sqs.get_mut(&queue).unwrap().head = new_head;
+            let (result, status) = if queue == 0 { self.run_admin(&command) } else { self.run_io(&command) };
+            let cq = self.sqs[&queue].cq;
+            self.complete(cq, queue, new_head, command[0] >> 16, result, status);
+        }
+    }
+    unsafe fn complete(&mut self, cq: u16, sq: u16, sq_head: u16, id: u32, result: u32, status: u16) {
+        let (vector, base, tail, phase) = {
+            let cq = self.cqs.get_mut(&cq).expect("Completion queue deleted under its submission queue");
+            let slot = cq.tail;
+            cq.tail = (cq.tail + 1) % cq.depth;
+            assert!(cq.tail != cq.head, "Completion queue overflow, the head doorbell was not rung");
+            let phase = cq.phase;
+            if cq.tail == 0 {
+                cq.phase = !cq.phase;
+            }
+            (cq.vector, cq.base, slot, phase)
+        };
+        let entry = (base + tail as u64 * 16) as usize as *mut u32;
+        ptr::write(entry, result);
+        ptr::write(entry.offset(1), 0);
+        ptr::write(entry.offset(2), sq_head as u32 | (sq as u32) << 16);
+        ptr::write(entry.offset(3), id | (phase as u32) << 16 | (status as u32) << 17);
+        if let Some(vector) = vector {
+            self.interrupts.push(vector);
+        }
+    }
+    /// Host memory regions the PRP entries of a command describe, for `len` bytes
+    unsafe fn regions(command: &[u32; 16], len: usize) -> Vec<(usize, usize)> {
+        let prp1 = command[6] as u64 | (command[7] as u64) << 32;
+        let prp2 = command[8] as u64 | (command[9] as u64) << 32;
+        assert_eq!(prp1 % 4, 0, "PRP entry misaligned");
+        let first = len.min((PAGE_SIZE - prp1 % PAGE_SIZE) as usize);
+        let mut regions = vec![(prp1 as usize, first)];
+        let mut remaining = len - first;
+        if remaining == 0 {
+            return regions;
+        }
+        if remaining <= PAGE_SIZE as usize {
+            assert_eq!(prp2 % PAGE_SIZE, 0, "Second PRP entry is not a page");
+            regions.push((prp2 as usize, remaining));
+            return regions;
+        }
+        assert_eq!(prp2 % 8, 0, "PRP list misaligned");
+        let list = prp2 as usize as *const u64;
+        let mut index = 0;
+        while remaining > 0 {
+            assert!(index < PAGE_SIZE as usize / 8 - 1, "PRP list chaining is not modelled");
+            let page = ptr::read(list.offset(index as isize));
+            assert_eq!(page % PAGE_SIZE, 0, "PRP list entry is not a page");
+            let len = remaining.min(PAGE_SIZE as usize);
+            regions.push((page as usize, len));
+            remaining -= len;
+            index += 1;
+        }
+        regions
+    }
+    unsafe fn run_admin(&mut self, command: &[u32; 16]) -> (u32, u16) {
+        let opcode = command[0] as u8;
+        let (nsid, cdw10, cdw11) = (command[1], command[10], command[11]);
+        self.admin.push((opcode, cdw10, cdw11));
+        match opcode {
+            // Identify
+            0x06 => {
+                let data = match cdw10 & 0xFF {
+                    0 => match self.namespace(nsid) {
+                        Some(namespace) => identify_namespace(namespace),
+                        None => return (0, STATUS_INVALID_NAMESPACE),
+                    },
+                    1 => identify_controller(self.mdts, self.namespaces.len() as u32),
+                    2 => {
+                        let mut data = vec![0; 4096];
+                        let active = (1..=self.namespaces.len() as u32)
+                            .filter(|&id| id > nsid && self.namespaces[id as usize - 1].active);
+                        for (slot, id) in active.enumerate() {
+                            data[slot * 4..slot * 4 + 4].copy_from_slice(&id.to_le_bytes());
+                        }
+                        data
+                    },
+                    _ => return (0, STATUS_INVALID_FIELD),
+                };
+                copy_out(&Self::regions(command, 4096), &data);
+                (0, 0)
+            },
+            // Set Features, only the number of queues
+            0x09 if cdw10 & 0xFF == 0x07 => {
+                let granted = (cdw11 & 0xFFFF).min(3);
+                (granted << 16 | granted, 0)
+            },
+            // Create I/O completion queue
+            0x05 => {
+                let (id, depth) = (cdw10 as u16, (cdw10 >> 16) as u16 + 1);
+                assert_eq!(self.cc >> 20 & 0xF, 4, "I/O queues created without the completion entry size");
+                assert!(cdw11 & 1 != 0, "Only contiguous queues are modelled");
+                if id == 0 || self.cqs.contains_key(&id) || depth as u64 > (self.cap & 0xFFFF) + 1 {
+                    return (0, STATUS_INVALID_QUEUE);
+                }
+                let base = command[6] as u64 | (command[7] as u64) << 32;
+                let vector = if cdw11 & 2 != 0 { Some((cdw11 >> 16) as u16) } else { None };
+                self.cqs.insert(id, CompletionQueue { base: base, depth: depth, tail: 0, head: 0, phase: true, vector: vector });
+                (0, 0)
+            },
+            // Create I/O submission queue
+            0x01 => {
+                let (id, depth, cq) = (cdw10 as u16, (cdw10 >> 16) as u16 + 1, (cdw11 >> 16) as u16);
+                assert_eq!(self.cc >> 16 & 0xF, 6, "I/O queues created without the submission entry size");
+                if id == 0 || self.sqs.contains_key(&id) || !self.cqs.contains_key(&cq) || cq == 0 {
+                    return (0, STATUS_INVALID_QUEUE);
+                }
+                let base = command[6] as u64 | (command[7] as u64) << 32;
+                self.sqs.insert(id, Submission { base: base, depth: depth, head: 0, tail: 0, cq: cq });
+                (0, 0)
+            },
+            // Delete I/O submission and completion queues
+            0x00 => if self.sqs.remove(&(cdw10 as u16)).is_some() { (0, 0) } else { (0, STATUS_INVALID_QUEUE) },
+            0x04 => if self.cqs.remove(&(cdw10 as u16)).is_some() { (0, 0) } else { (0, STATUS_INVALID_QUEUE) },
+            _ => (0, STATUS_INVALID_OPCODE),
+        }
+    }
+    unsafe fn run_io(&mut self, command: &[u32; 16]) -> (u32, u16) {
+        let opcode = command[0] as u8;
+        let nsid = command[1];
+        let lba = command[10] as u64 | (command[11] as u64) << 32;
+        let blocks = (command[12] & 0xFFFF) + 1;
+        let (mdts, bad_lba) = (self.mdts, self.bad_lba);
+        let status = match opcode {
+            // Flush
+            0x00 => {
+                self.io.push((opcode, nsid, 0, 0, 0));
+                return (0, if self.namespace(nsid).is_some() { 0 } else { STATUS_INVALID_NAMESPACE });
+            },
+            // Write and read
+            0x01 | 0x02 => {
+                let mut transferred = 0;
+                let status = match self.namespace_mut(nsid) {
+                    None => STATUS_INVALID_NAMESPACE,
+                    Some(namespace) => {
+                        let start = lba as usize * namespace.block_size;
+                        let len = blocks as usize * namespace.block_size;
+                        if mdts != 0 {
+                            assert!(len as u64 <= PAGE_SIZE << mdts, "Transfer of {} bytes beyond MDTS", len);
+                        }
+                        if start + len > namespace.data.len() {
+                            STATUS_LBA_OUT_OF_RANGE
+                        } else if bad_lba.map_or(false, |bad| lba <= bad && bad < lba + blocks as u64) {
+                            STATUS_UNRECOVERED_READ
+                        } else {
+                            let regions = Self::regions(command, len);
+                            if opcode == 0x01 {
+                                copy_in(&regions, &mut namespace.data[start..start + len]);
+                            } else {
+                                copy_out(&regions, &namespace.data[start..start + len]);
+                            }
+                            transferred = len;
+                            0
+                        }
+                    },
+                };
+                self.io.push((opcode, nsid, lba, blocks, transferred));
+                status
+            },
+            _ => STATUS_INVALID_OPCODE,
+        };
+        (0, status)
+    }
+    fn namespace(&self, nsid: u32) -> Option<&Namespace> {
+        self.namespaces.get((nsid as usize).wrapping_sub(1)).filter(|namespace| namespace.active)
+    }
+    fn namespace_mut(&mut self, nsid: u32) -> Option<&mut Namespace> {
+        self.namespaces.get_mut((nsid as usize).wrapping_sub(1)).filter(|namespace| namespace.active)
+    }
+}
+
+unsafe fn copy_out(regions: &[(usize, usize)], data: &[u8]) {
+    let mut offset = 0;
+    for &(address, len) in regions {
+        ptr::copy_nonoverlapping(data[offset..].as_ptr(), address as *mut u8, len);
+        offset += len;
+    }
+}
+
+unsafe fn copy_in(regions: &[(usize, usize)], data: &mut [u8]) {
+    let mut offset = 0;
+    for &(address, len) in regions {
+        ptr::copy_nonoverlapping(address as *const u8, data[offset..].as_mut_ptr(), len);
+        offset += len;
+    }
+}
+
+fn put_string(data: &mut [u8], s: &str) {
+    for (byte, c) in data.iter_mut().zip(s.bytes().chain(std::iter::repeat(b' '))) {
+        *byte = c;
+    }
+}
+
+/// Identify Controller data
+pub fn identify_controller(mdts: u8, namespaces: u32) -> Vec<u8> {
+    let mut data = vec![0; 4096];
+    data[0..2].copy_from_slice(&0x1B36u16.to_le_bytes());
+    put_string(&mut data[4..24], "RLK0001");
+    put_string(&mut data[24..64], "RLK Model NVMe");
+    put_string(&mut data[64..72], "1.0");
+    data[77] = mdts;
+    data[516..520].copy_from_slice(&namespaces.to_le_bytes());
+    data
+}
+
+/// Identify Namespace data, with a single LBA format
+pub fn identify_namespace(namespace: &Namespace) -> Vec<u8> {
+    let mut data = vec![0; 4096];
+    let blocks = (namespace.data.len() / namespace.block_size) as u64;
+    for offset in [0, 8, 16].iter() {
+        data[*offset..*offset + 8].copy_from_slice(&blocks.to_le_bytes());
+    }
+    let format = namespace.metadata as u32 | (namespace.block_size.trailing_zeros()) << 16;
+    data[128..132].copy_from_slice(&format.to_le_bytes());
+    data
+}
+
+impl Io for Controller {
+    type Item = u32;
+    unsafe fn read(&mut self, offset: usize) -> u32 {
+        assert_eq!(offset % 4, 0, "Misaligned register read {:#x}", offset);
+        match offset {
+            CAP => self.cap as u32,
+            CAP_HIGH => (self.cap >> 32) as u32,
+            VS => 0x0001_0400,
+            INTMS | INTMC => self.intms,
+            CC => self.cc,
+            CSTS => self.csts,
+            AQA => self.aqa,
+            ASQ => self.asq as u32,
+            ASQ_HIGH => (self.asq >> 32) as u32,
+            ACQ => self.acq as u32,
+            ACQ_HIGH => (self.acq >> 32) as u32,
+            _ => panic!("Read of controller register {:#x}", offset),
+        }
+    }
+    unsafe fn write(&mut self, offset: usize, value: u32) {
+        assert_eq!(offset % 4, 0, "Misaligned register write {:#x}", offset);
+        let set_low = |reg: u64| reg & !0xFFFF_FFFF | value as u64;
+        let set_high = |reg: u64| reg & 0xFFFF_FFFF | (value as u64) << 32;
+        let disabled = self.cc & CC_EN == 0 && self.csts & CSTS_RDY == 0;
+        match offset {
+            INTMS => self.intms |= value,
+            INTMC => self.intms &= !value,
+            CC => {
+                let was = self.cc;
+                self.cc = value;
+                if value & CC_EN != 0 && was & CC_EN == 0 {
+                    self.enable();
+                } else if value & CC_EN == 0 && was & CC_EN != 0 {
+                    self.disable();
+                }
+                if value & CC_SHN != 0 && was & CC_SHN == 0 {
+                    self.shutdowns += 1;
+                    self.csts |= CSTS_SHST_COMPLETE;
+                }
+            },
+            AQA | ASQ | ASQ_HIGH | ACQ | ACQ_HIGH => {
+                assert!(disabled, "Admin queues moved while the controller is enabled");
+                match offset {
+                    AQA => self.aqa = value,
+                    ASQ => self.asq = set_low(self.asq),
+                    ASQ_HIGH => self.asq = set_high(self.asq),
+                    ACQ => self.acq = set_low(self.acq),
+                    _ => self.acq = set_high(self.acq),
+                }
+            },
+            _ if offset >= DOORBELLS => self.doorbell(offset - DOORBELLS, value),
+            _ => panic!("Write of controller register {:#x}", offset),
+        }
+    }
+}
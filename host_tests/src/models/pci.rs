@@ -0,0 +1,264 @@
+//! Model of PCI configuration space
+//!
+//! A `Space` holds the configuration space of each function present, and is reached through
+//! models of either the legacy address and data ports or an ECAM window. Only the registers that
+//! enumeration cares about have any behaviour: BARs only keep the bits their size allows, and
+//! the ID and class registers are read only.
+
+use std::collections::BTreeMap;
+
+use drivers::io::Io;
+use super::{Access, Shared};
+
+const BAR0: usize = 0x10 / 4;
+const BRIDGE_BARS: usize = 2;
+const GENERAL_BARS: usize = 6;
+
+/// Configuration space of a single function
+#[derive(Clone)]
+pub struct Function {
+    config: [u32; 1024],
+    /// Bits of each dword that can be written
+    writable: [u32; 1024],
+}
+
+impl Function {
+    pub fn new(vendor: u16, device: u16, class: u8, subclass: u8) -> Self {
+        let mut config = [0; 1024];
+        config[0] = (device as u32) << 16 | vendor as u32;
+        config[2] = (class as u32) << 24 | (subclass as u32) << 16 | 0x01;
+        let mut writable = [!0; 1024];
+        writable[0] = 0;
+        writable[2] = 0;
+        // Only the header type is read only in this dword
+        writable[3] = 0xFF00_FFFF;
+        // Command is writable, status is not modelled
+        writable[1] = 0x0000_07FF;
+        for bar in BAR0..BAR0 + GENERAL_BARS {
+            writable[bar] = 0;
+        }
+        Function { config: config, writable: writable }
+    }
+    fn set_byte(&mut self, offset: usize, value: u8) {
+        let shift = (offset % 4) * 8;
+        let dword = &mut self.config[offset / 4];
+        *dword = *dword & !(0xFF << shift) | (value as u32) << shift;
+    }
+    pub fn multi_function(mut self) -> Self {
+        self.config[3] |= 0x80 << 16;
+        self
+    }
+    /// Make this a PCI-to-PCI bridge to `secondary`
+    pub fn bridge(mut self, secondary: u8) -> Self {
+        self.config[3] = self.config[3] & !(0x7F << 16) | 0x01 << 16;
+        self.set_byte(0x19, secondary);
+        for bar in BAR0 + BRIDGE_BARS..BAR0 + GENERAL_BARS {
+            self.writable[bar] = !0;
+        }
+        self
+    }
+    pub fn subsystem(mut self, vendor: u16, subsystem: u16) -> Self {
+        self.config[0x2C / 4] = (subsystem as u32) << 16 | vendor as u32;
+        self
+    }
+    pub fn interrupt(mut self, pin: u8, line: u8) -> Self {
+        self.set_byte(0x3C, line);
+        self.set_byte(0x3D, pin);
+        self
+    }
+    pub fn command(mut self, command: u16) -> Self {
+        self.config[1] = command as u32;
+        self
+    }
+    /// A 32-bit memory BAR, `size` must be a power of 2
+    pub fn memory_bar(mut self, index: usize, address: u32, size: u32) -> Self {
+        self.config[BAR0 + index] = address;
+        self.writable[BAR0 + index] = !(size - 1);
+        self
+    }
+    /// A 64-bit memory BAR, taking slots `index` and `index + 1`
+    pub fn memory_bar_64(mut self, index: usize, address: u64, size: u64, prefetchable: bool) -> Self {
+        let flags = 0b100 | if prefetchable { 0b1000 } else { 0 };
+        self.config[BAR0 + index] = address as u32 | flags;
+        self.config[BAR0 + index + 1] = (address >> 32) as u32;
+        let mask = !(size - 1);
+        self.writable[BAR0 + index] = mask as u32 & !0xF;
+        self.writable[BAR0 + index + 1] = (mask >> 32) as u32;
+        self
+    }
+    /// An I/O BAR that only implements the low 16 bits
+    pub fn io_bar(mut self, index: usize, port: u16, size: u16) -> Self {
+        self.config[BAR0 + index] = port as u32 | 1;
+        self.writable[BAR0 + index] = !(size as u32 - 1) & 0xFFFC;
+        self
+    }
+    /// Add a capability at `offset`, linking to `next`
+    pub fn capability(mut self, offset: u8, id: u8, next: u8) -> Self {
+        self.config[1] |= 1 << 20;
+        if self.config[0x34 / 4] == 0 {
+            self.set_byte(0x34, offset);
+        }
+        self.set_byte(offset as usize, id);
+        self.set_byte(offset as usize + 1, next);
+        self
+    }
+    /// Set a byte of configuration space, such as the body of a capability
+    pub fn byte(mut self, offset: u16, value: u8) -> Self {
+        self.set_byte(offset as usize, value);
+        self
+    }
+    /// Set an aligned dword of configuration space
+    pub fn dword(mut self, offset: u16, value: u32) -> Self {
+        self.config[offset as usize / 4] = value;
+        self
+    }
+    pub fn read(&self, offset: u16) -> u32 {
+        self.config[offset as usize / 4]
+    }
+    fn write(&mut self, offset: u16, value: u32) {
+        let index = offset as usize / 4;
+        let writable = self.writable[index];
+        self.config[index] = self.config[index] & !writable | value & writable;
+    }
+}
+
+/// Configuration space of every function, keyed by (bus, device, function)
+pub struct Space {
+    functions: BTreeMap<(u8, u8, u8), Function>,
+    /// Every write to a present function, as (bus, device, function, offset, value)
+    writes: Vec<(u8, u8, u8, u16, u32)>,
+}
+
+impl Space {
+    pub fn new() -> Self {
+        Space { functions: BTreeMap::new(), writes: Vec::new() }
+    }
+    pub fn add(&mut self, bus: u8, device: u8, function: u8, model: Function) -> &mut Self {
+        self.functions.insert((bus, device, function), model);
+        self
+    }
+    pub fn function(&self, bus: u8, device: u8, function: u8) -> &Function {
+        &self.functions[&(bus, device, function)]
+    }
+    pub fn writes(&self) -> &[(u8, u8, u8, u16, u32)] {
+        &self.writes
+    }
+    pub fn read(&self, bus: u8, device: u8, function: u8, offset: u16) -> u32 {
+        self.functions.get(&(bus, device, function)).map_or(!0, |model| model.read(offset))
+    }
+    pub fn write(&mut self, bus: u8, device: u8, function: u8, offset: u16, value: u32) {
+        if let Some(model) = self.functions.get_mut(&(bus, device, function)) {
+            self.writes.push((bus, device, function, offset, value));
+            model.write(offset, value);
+        }
+    }
+}
+
+/// The legacy configuration address and data ports
+pub struct LegacyPorts {
+    space: Shared<Space>,
+    address: u32,
+    accesses: Vec<Access<u16, u32>>,
+}
+
+impl LegacyPorts {
+    pub fn new(space: Shared<Space>) -> Self {
+        LegacyPorts { space: space, address: 0, accesses: Vec::new() }
+    }
+    pub fn accesses(&self) -> &[Access<u16, u32>] {
+        &self.accesses
+    }
+    fn decode(&self) -> (u8, u8, u8, u16) {
+        assert!(self.address & 1 << 31 != 0, "PCI configuration data accessed while disabled");
+        assert_eq!(self.address & 0x3, 0, "Unaligned PCI configuration address");
+        let address = self.address;
+        ((address >> 16) as u8, (address >> 11) as u8 & 0x1F, (address >> 8) as u8 & 0x7, address as u16 & 0xFC)
+    }
+}
+
+impl Io for LegacyPorts {
+    type Item = u32;
+    type Range = u16;
+    unsafe fn read(&mut self, offset: u16) -> u32 {
+        let value = match offset {
+            0 => self.address,
+            4 => {
+                let (bus, device, function, offset) = self.decode();
+                self.space.get().read(bus, device, function, offset)
+            },
+            _ => panic!("Read from PCI configuration port offset {}", offset),
+        };
+        self.accesses.push(Access::Read(offset, value));
+        value
+    }
+    unsafe fn write(&mut self, offset: u16, value: u32) {
+        self.accesses.push(Access::Write(offset, value));
+        match offset {
+            0 => self.address = value,
+            4 => {
+                let (bus, device, function, offset) = self.decode();
+                self.space.get().write(bus, device, function, offset, value)
+            },
+            _ => panic!("Write to PCI configuration port offset {}", offset),
+        }
+    }
+}
+
+/// An ECAM window starting at the configuration space of `start_bus`
+pub struct EcamWindow {
+    space: Shared<Space>,
+    start_bus: u8,
+    accesses: Vec<Access<usize, u32>>,
+}
+
+impl EcamWindow {
+    pub fn new(space: Shared<Space>, start_bus: u8) -> Self {
+        EcamWindow { space: space, start_bus: start_bus, accesses: Vec::new() }
+    }
+    pub fn accesses(&self) -> &[Access<usize, u32>] {
+        &self.accesses
+    }
+    fn decode(&self, offset: usize) -> (u8, u8, u8, u16) {
+        assert_eq!(offset & 0x3, 0, "Unaligned ECAM access");
+        (self.start_bus + (offset >> 20) as u8, (offset >> 15) as u8 & 0x1F, (offset >> 12) as u8 & 0x7, offset as u16 & 0xFFF)
+    }
+}
+
+impl Io for EcamWindow {
+    type Item = u32;
+    unsafe fn read(&mut self, offset: usize) -> u32 {
+        let (bus, device, function, reg) = self.decode(offset);
+        let value = self.space.get().read(bus, device, function, reg);
+        self.accesses.push(Access::Read(offset, value));
+        value
+    }
+    unsafe fn write(&mut self, offset: usize, value: u32) {
+        self.accesses.push(Access::Write(offset, value));
+        let (bus, device, function, reg) = self.decode(offset);
+        self.space.get().write(bus, device, function, reg, value)
+    }
+}
+
+/// An MSI-X vector table, with every entry masked out of reset
+pub struct MsixTable {
+    /// Address, upper address, data and vector control of each entry
+    pub entries: Vec<[u32; 4]>,
+}
+
+impl MsixTable {
+    pub fn new(size: usize) -> Self {
+        MsixTable { entries: vec![[0, 0, 0, 1]; size] }
+    }
+}
+
+impl Io for MsixTable {
+    type Item = u32;
+    unsafe fn read(&mut self, offset: usize) -> u32 {
+        assert_eq!(offset % 4, 0, "Misaligned MSI-X table read {:#x}", offset);
+        self.entries[offset / 16][offset % 16 / 4]
+    }
+    unsafe fn write(&mut self, offset: usize, value: u32) {
+        assert_eq!(offset % 4, 0, "Misaligned MSI-X table write {:#x}", offset);
+        self.entries[offset / 16][offset % 16 / 4] = value;
+    }
+}
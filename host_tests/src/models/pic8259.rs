@@ -0,0 +1,176 @@
+//! Model of a single 8259 PIC
+//!
+//! Seen through a two port window with command at offset 0 and data at offset 1. Only the
+//! features used on a PC are modelled: edge triggered, fully nested mode with non specific EOI.
+
+use drivers::io::Io;
+use super::Access;
+
+const COMMAND: u16 = 0;
+const DATA: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InitState {
+    Ready,
+    Icw2,
+    Icw3,
+    Icw4,
+}
+
+pub struct Pic8259 {
+    init: InitState,
+    icw1: u8,
+    offset: u8,
+    icw3: u8,
+    icw4: u8,
+    imr: u8,
+    irr: u8,
+    isr: u8,
+    /// Whether command port reads return the ISR instead of the IRR
+    read_isr: bool,
+    eois: usize,
+    accesses: Vec<Access<u16, u8>>,
+}
+
+impl Default for Pic8259 {
+    fn default() -> Self {
+        Pic8259 {
+            init: InitState::Ready,
+            icw1: 0,
+            // Master's power on mapping
+            offset: 8,
+            icw3: 0,
+            icw4: 0,
+            imr: 0,
+            irr: 0,
+            isr: 0,
+            read_isr: false,
+            eois: 0,
+            accesses: Vec::new(),
+        }
+    }
+}
+
+impl Pic8259 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Assert an input line
+    pub fn raise(&mut self, line: u8) {
+        self.irr |= 1 << line;
+    }
+    /// Perform an interrupt acknowledge cycle as the CPU would, returning the vector delivered
+    ///
+    /// If the request went away, or was masked, a spurious interrupt is delivered on line 7
+    /// without marking it in service
+    pub fn acknowledge(&mut self) -> u8 {
+        let pending = self.irr & !self.imr;
+        if pending == 0 {
+            return self.offset + 7;
+        }
+        let line = pending.trailing_zeros() as u8;
+        self.irr &= !(1 << line);
+        self.isr |= 1 << line;
+        self.offset + line
+    }
+    /// Withdraw a request before it is acknowledged
+    pub fn lower(&mut self, line: u8) {
+        self.irr &= !(1 << line);
+    }
+    pub fn offset(&self) -> u8 {
+        self.offset
+    }
+    pub fn icw3(&self) -> u8 {
+        self.icw3
+    }
+    pub fn icw4(&self) -> u8 {
+        self.icw4
+    }
+    pub fn imr(&self) -> u8 {
+        self.imr
+    }
+    pub fn isr(&self) -> u8 {
+        self.isr
+    }
+    /// Whether an initialization sequence has been completed
+    pub fn initialized(&self) -> bool {
+        self.init == InitState::Ready && self.icw1 != 0
+    }
+    /// Number of end of interrupt commands received
+    pub fn eois(&self) -> usize {
+        self.eois
+    }
+    pub fn accesses(&self) -> &[Access<u16, u8>] {
+        &self.accesses
+    }
+    fn command(&mut self, value: u8) {
+        if value & 0x10 != 0 {
+            // ICW1 restarts initialization and clears the mask
+            self.icw1 = value;
+            self.imr = 0;
+            self.isr = 0;
+            self.read_isr = false;
+            self.init = InitState::Icw2;
+        } else if value & 0x08 != 0 {
+            // OCW3
+            if value & 0x2 != 0 {
+                self.read_isr = value & 0x1 != 0;
+            }
+        } else if value & 0xE0 == 0x20 {
+            // OCW2 non specific EOI clears the highest priority in service line
+            self.eois += 1;
+            if self.isr != 0 {
+                self.isr &= self.isr - 1;
+            }
+        } else {
+            panic!("Unsupported OCW2 {:#x}", value);
+        }
+    }
+    fn data(&mut self, value: u8) {
+        self.init = match self.init {
+            InitState::Ready => {
+                self.imr = value;
+                InitState::Ready
+            },
+            InitState::Icw2 => {
+                self.offset = value & 0xf8;
+                // Single mode has no ICW3
+                if self.icw1 & 0x2 != 0 {
+                    if self.icw1 & 0x1 != 0 { InitState::Icw4 } else { InitState::Ready }
+                } else {
+                    InitState::Icw3
+                }
+            },
+            InitState::Icw3 => {
+                self.icw3 = value;
+                if self.icw1 & 0x1 != 0 { InitState::Icw4 } else { InitState::Ready }
+            },
+            InitState::Icw4 => {
+                self.icw4 = value;
+                InitState::Ready
+            },
+        }
+    }
+}
+
+impl Io for Pic8259 {
+    type Item = u8;
+    type Range = u16;
+    unsafe fn read(&mut self, offset: u16) -> u8 {
+        let value = match offset {
+            COMMAND => if self.read_isr { self.isr } else { self.irr },
+            DATA => self.imr,
+            _ => panic!("Read from offset {} outside of 8259 ports", offset),
+        };
+        self.accesses.push(Access::Read(offset, value));
+        value
+    }
+    unsafe fn write(&mut self, offset: u16, value: u8) {
+        self.accesses.push(Access::Write(offset, value));
+        match offset {
+            COMMAND => self.command(value),
+            DATA => self.data(value),
+            _ => panic!("Write to offset {} outside of 8259 ports", offset),
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! Model of an 8254 programmable interval timer
+//!
+//! The counters and the channel 2 gate are separate port ranges on a PC, so the model is shared
+//! between a `Port` for each. Counters do not run in real time. Instead channel 2 completes after
+//! its output has been polled a set number of times, and latched counts are whatever the test
+//! last set.
+
+use drivers::io::Io;
+use super::{Access, Shared};
+
+const COMMAND: u8 = 3;
+
+const GATE_CH2: u8 = 0b1;
+const GATE_OUT2: u8 = 0b100000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Byte {
+    Low,
+    High(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Channel {
+    mode: u8,
+    access: u8,
+    running: bool,
+    write: Byte,
+    count: u16,
+    latch: Option<Byte>,
+}
+
+pub struct Pit {
+    channels: [Channel; 3],
+    /// Every complete reload written, as (channel, mode, value)
+    reloads: Vec<(u8, u8, u16)>,
+    gate: u8,
+    /// Polls of the gate port after which the channel 2 output goes high
+    polls_to_complete: usize,
+    polls: usize,
+    accesses: Vec<Access<u8, u8>>,
+}
+
+impl Pit {
+    pub fn new() -> Self {
+        let channel = Channel {
+            mode: 0,
+            access: 0,
+            running: false,
+            write: Byte::Low,
+            count: 0,
+            latch: None,
+        };
+        Pit {
+            channels: [channel; 3],
+            reloads: Vec::new(),
+            gate: 0,
+            polls_to_complete: 1,
+            polls: 0,
+            accesses: Vec::new(),
+        }
+    }
+    /// Make channel 2 complete only once its output has been polled `polls` times
+    pub fn completes_after(mut self, polls: usize) -> Self {
+        self.polls_to_complete = polls;
+        self
+    }
+    /// Split in to handles for the counter ports and the gate port
+    pub fn ports(self) -> (Shared<Pit>, Port, Port) {
+        let pit = Shared::new(self);
+        (pit.clone(), Port::Counters(pit.clone()), Port::Gate(pit))
+    }
+    pub fn set_count(&mut self, channel: u8, count: u16) {
+        self.channels[channel as usize].count = count;
+    }
+    pub fn running(&self, channel: u8) -> bool {
+        self.channels[channel as usize].running
+    }
+    pub fn mode(&self, channel: u8) -> u8 {
+        self.channels[channel as usize].mode
+    }
+    pub fn reloads(&self) -> &[(u8, u8, u16)] {
+        &self.reloads
+    }
+    pub fn gate(&self) -> u8 {
+        self.gate
+    }
+    pub fn accesses(&self) -> &[Access<u8, u8>] {
+        &self.accesses
+    }
+    fn command(&mut self, value: u8) {
+        let index = value >> 6;
+        assert!(index != 3, "Read back command is not modelled");
+        let channel = &mut self.channels[index as usize];
+        let access = (value >> 4) & 0b11;
+        if access == 0 {
+            channel.latch = Some(Byte::Low);
+            return;
+        }
+        assert_eq!(access, 3, "Only lo/hi access is modelled");
+        channel.access = access;
+        channel.mode = (value >> 1) & 0b111;
+        // Setting the mode stops the counter until a count is written
+        channel.running = false;
+        channel.write = Byte::Low;
+    }
+    fn write_count(&mut self, index: u8, value: u8) {
+        let channel = &mut self.channels[index as usize];
+        assert_eq!(channel.access, 3, "Count written to PIT channel {} without a mode", index);
+        match channel.write {
+            Byte::Low => channel.write = Byte::High(value),
+            Byte::High(low) => {
+                let reload = (value as u16) << 8 | low as u16;
+                channel.count = reload;
+                channel.running = true;
+                channel.write = Byte::Low;
+                self.reloads.push((index, channel.mode, reload));
+                if index == 2 {
+                    self.polls = 0;
+                }
+            },
+        }
+    }
+    fn read_count(&mut self, index: u8) -> u8 {
+        let channel = &mut self.channels[index as usize];
+        match channel.latch {
+            Some(Byte::Low) => {
+                channel.latch = Some(Byte::High(0));
+                channel.count as u8
+            },
+            Some(Byte::High(_)) => {
+                channel.latch = None;
+                (channel.count >> 8) as u8
+            },
+            None => panic!("Read of PIT channel {} without latching", index),
+        }
+    }
+    fn read_gate(&mut self) -> u8 {
+        let counting = self.gate & GATE_CH2 != 0 && self.channels[2].running;
+        if counting {
+            self.polls += 1;
+        }
+        if counting && self.polls >= self.polls_to_complete {
+            self.gate | GATE_OUT2
+        } else {
+            self.gate
+        }
+    }
+    fn write_gate(&mut self, value: u8) {
+        if value & GATE_CH2 != 0 && self.gate & GATE_CH2 == 0 {
+            self.polls = 0;
+        }
+        // The output bit is read only
+        self.gate = value & !GATE_OUT2;
+    }
+}
+
+/// One of the two port ranges of the PIT
+#[derive(Clone)]
+pub enum Port {
+    /// Counters and command, offsets 0 to 3
+    Counters(Shared<Pit>),
+    /// Gate control, offset 0
+    Gate(Shared<Pit>),
+}
+
+impl Io for Port {
+    type Item = u8;
+    type Range = u8;
+    unsafe fn read(&mut self, offset: u8) -> u8 {
+        match *self {
+            Port::Counters(ref pit) => {
+                let mut pit = pit.get();
+                assert!(offset < COMMAND, "Read from PIT command port");
+                let value = pit.read_count(offset);
+                pit.accesses.push(Access::Read(offset, value));
+                value
+            },
+            Port::Gate(ref pit) => {
+                assert_eq!(offset, 0, "Read beyond PIT gate port");
+                pit.get().read_gate()
+            },
+        }
+    }
+    unsafe fn write(&mut self, offset: u8, value: u8) {
+        match *self {
+            Port::Counters(ref pit) => {
+                let mut pit = pit.get();
+                pit.accesses.push(Access::Write(offset, value));
+                match offset {
+                    COMMAND => pit.command(value),
+                    channel if channel < COMMAND => pit.write_count(channel, value),
+                    _ => panic!("Write beyond PIT ports to offset {}", offset),
+                }
+            },
+            Port::Gate(ref pit) => {
+                assert_eq!(offset, 0, "Write beyond PIT gate port");
+                pit.get().write_gate(value)
+            },
+        }
+    }
+}
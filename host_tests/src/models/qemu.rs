@@ -0,0 +1,52 @@
+//! Models of the QEMU debug devices
+
+use drivers::io::Io;
+
+/// Debug console port, or whatever is on real hardware where there is none
+pub struct DebugCon {
+    present: bool,
+    pub output: Vec<u8>,
+}
+
+impl DebugCon {
+    pub fn new(present: bool) -> Self {
+        DebugCon { present: present, output: Vec::new() }
+    }
+}
+
+impl Io for DebugCon {
+    type Item = u8;
+    unsafe fn read(&mut self, offset: usize) -> u8 {
+        assert_eq!(offset, 0);
+        // Nothing drives the bus for an unused port
+        if self.present { 0xE9 } else { 0xFF }
+    }
+    unsafe fn write(&mut self, offset: usize, value: u8) {
+        assert_eq!(offset, 0);
+        assert!(self.present, "Write to an absent debug console");
+        self.output.push(value);
+    }
+}
+
+/// isa-debug-exit, which records the status QEMU would have exited with
+pub struct DebugExit {
+    pub status: Option<i32>,
+}
+
+impl DebugExit {
+    pub fn new() -> Self {
+        DebugExit { status: None }
+    }
+}
+
+impl Io for DebugExit {
+    type Item = u8;
+    unsafe fn read(&mut self, offset: usize) -> u8 {
+        panic!("Read of isa-debug-exit at {:#x}", offset)
+    }
+    unsafe fn write(&mut self, offset: usize, value: u8) {
+        assert_eq!(offset, 0);
+        assert!(self.status.is_none(), "QEMU has already exited");
+        self.status = Some((value as i32) << 1 | 1);
+    }
+}
@@ -0,0 +1,115 @@
+//! Model of the CMOS real time clock
+//!
+//! The CMOS is a plain array of bytes behind an index port. An update of the clock can be made
+//! to be in progress for a number of reads of status register A, and the clock fields can be
+//! changed part way through a reading to look like an update that was not waited out.
+
+use drivers::io::Io;
+use super::Access;
+
+const INDEX: u8 = 0;
+const DATA: u8 = 1;
+
+const STATUS_A: u8 = 0x0A;
+const STATUS_A_UIP: u8 = 1 << 7;
+
+pub struct Rtc {
+    index: u8,
+    nmi_masked: bool,
+    cmos: [u8; 128],
+    /// Reads of status A that still report an update in progress
+    updating: usize,
+    /// Data reads after which to apply the pending change, and the change as (index, value)
+    change: Option<(usize, Vec<(u8, u8)>)>,
+    accesses: Vec<Access<u8, u8>>,
+}
+
+impl Rtc {
+    /// A clock with the given status register B and all fields zero
+    pub fn new(status_b: u8) -> Self {
+        let mut cmos = [0; 128];
+        cmos[0x0B] = status_b;
+        Rtc {
+            index: 0,
+            // As firmware may have left it
+            nmi_masked: true,
+            cmos: cmos,
+            updating: 0,
+            change: None,
+            accesses: Vec::new(),
+        }
+    }
+    pub fn set(&mut self, index: u8, value: u8) {
+        self.cmos[index as usize] = value;
+    }
+    /// Set the clock fields, in whatever encoding status B says
+    pub fn set_time(&mut self, year: u8, month: u8, day: u8, hour: u8, minute: u8, second: u8) {
+        self.set(0x09, year);
+        self.set(0x08, month);
+        self.set(0x07, day);
+        self.set(0x04, hour);
+        self.set(0x02, minute);
+        self.set(0x00, second);
+    }
+    /// Report an update in progress for the next `reads` reads of status A
+    pub fn updating(&mut self, reads: usize) {
+        self.updating = reads;
+    }
+    /// Apply `changes` once `reads` more data reads have been made
+    pub fn change_after(&mut self, reads: usize, changes: Vec<(u8, u8)>) {
+        self.change = Some((reads, changes));
+    }
+    pub fn accesses(&self) -> &[Access<u8, u8>] {
+        &self.accesses
+    }
+    /// Whether NMIs are masked by the top bit of the last index written
+    pub fn nmi_masked(&self) -> bool {
+        self.nmi_masked
+    }
+    fn read_data(&mut self) -> u8 {
+        let apply = match self.change {
+            Some((0, _)) => true,
+            Some((ref mut reads, _)) => {
+                *reads -= 1;
+                false
+            },
+            None => false,
+        };
+        if apply {
+            for (index, value) in self.change.take().unwrap().1 {
+                self.set(index, value);
+            }
+        }
+        let mut value = self.cmos[self.index as usize];
+        if self.index == STATUS_A && self.updating > 0 {
+            self.updating -= 1;
+            value |= STATUS_A_UIP;
+        }
+        value
+    }
+}
+
+impl Io for Rtc {
+    type Item = u8;
+    type Range = u8;
+    unsafe fn read(&mut self, offset: u8) -> u8 {
+        let value = match offset {
+            DATA => self.read_data(),
+            _ => panic!("Read from RTC port {}", offset),
+        };
+        self.accesses.push(Access::Read(offset, value));
+        value
+    }
+    unsafe fn write(&mut self, offset: u8, value: u8) {
+        self.accesses.push(Access::Write(offset, value));
+        match offset {
+            // The top bit masks NMIs, which is not part of the index
+            INDEX => {
+                self.index = value & 0x7F;
+                self.nmi_masked = value & 0x80 != 0;
+            },
+            DATA => self.cmos[self.index as usize] = value,
+            _ => panic!("Write to RTC port {}", offset),
+        }
+    }
+}
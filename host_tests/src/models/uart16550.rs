@@ -0,0 +1,241 @@
+//! Model of a 16550 UART
+//!
+//! Models the register file as seen through the 8 byte I/O window, including the divisor latch
+//! overlay controlled by LCR.DLAB, the 16 byte receive FIFO, loopback and the line status bits.
+//! Transmission is instantaneous unless the model is told to stay busy for some number of LSR
+//! polls, which lets tests check that a driver waits for the transmitter.
+
+use std::collections::VecDeque;
+
+use drivers::io::Io;
+use super::Access;
+
+const RBR_THR_DLL: u16 = 0;
+const IER_DLM: u16 = 1;
+const IIR_FCR: u16 = 2;
+const LCR: u16 = 3;
+const MCR: u16 = 4;
+const LSR: u16 = 5;
+const MSR: u16 = 6;
+const SCR: u16 = 7;
+
+const LCR_DLAB: u8 = 0x80;
+const MCR_LOOPBACK: u8 = 0x10;
+const FCR_ENABLE: u8 = 0x1;
+const FCR_CLEAR_RX: u8 = 0x2;
+
+const LSR_DR: u8 = 0x1;
+const LSR_OE: u8 = 0x2;
+const LSR_ETHR: u8 = 0x20;
+const LSR_EDHR: u8 = 0x40;
+
+const IER_ERDAI: u8 = 0x1;
+const IER_ETHREI: u8 = 0x2;
+const IER_ERLSI: u8 = 0x4;
+
+const FIFO_DEPTH: usize = 16;
+
+pub struct Uart16550 {
+    dll: u8,
+    dlm: u8,
+    ier: u8,
+    fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    overrun: bool,
+    rx: VecDeque<u8>,
+    tx: Vec<u8>,
+    tx_busy_polls: usize,
+    lost_writes: usize,
+    accesses: Vec<Access<u16, u8>>,
+}
+
+impl Default for Uart16550 {
+    /// Construct in the documented power on reset state
+    fn default() -> Self {
+        Uart16550 {
+            dll: 0,
+            dlm: 0,
+            ier: 0,
+            fcr: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            overrun: false,
+            rx: VecDeque::new(),
+            tx: Vec::new(),
+            tx_busy_polls: 0,
+            lost_writes: 0,
+            accesses: Vec::new(),
+        }
+    }
+}
+
+impl Uart16550 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+    fn fifo_enabled(&self) -> bool {
+        self.fcr & FCR_ENABLE != 0
+    }
+    fn rx_capacity(&self) -> usize {
+        if self.fifo_enabled() { FIFO_DEPTH } else { 1 }
+    }
+    fn tx_empty(&self) -> bool {
+        self.tx_busy_polls == 0
+    }
+    fn push_rx(&mut self, byte: u8) {
+        if self.rx.len() < self.rx_capacity() {
+            self.rx.push_back(byte);
+        } else if self.fifo_enabled() {
+            // Incoming byte is lost in the shift register
+            self.overrun = true;
+        } else {
+            // Without the FIFO the new byte overwrites the unread one in RBR
+            *self.rx.back_mut().unwrap() = byte;
+            self.overrun = true;
+        }
+    }
+    fn lsr(&self) -> u8 {
+        let mut lsr = 0;
+        if !self.rx.is_empty() {
+            lsr |= LSR_DR;
+        }
+        if self.overrun {
+            lsr |= LSR_OE;
+        }
+        if self.tx_empty() {
+            lsr |= LSR_ETHR | LSR_EDHR;
+        }
+        lsr
+    }
+    fn iir(&self) -> u8 {
+        let fifo = if self.fifo_enabled() { 0xc0 } else { 0 };
+        let id = if self.ier & IER_ERLSI != 0 && self.overrun {
+            0x6
+        } else if self.ier & IER_ERDAI != 0 && !self.rx.is_empty() {
+            0x4
+        } else if self.ier & IER_ETHREI != 0 && self.tx_empty() {
+            0x2
+        } else {
+            // No interrupt pending
+            0x1
+        };
+        fifo | id
+    }
+    fn transmit(&mut self, byte: u8) {
+        if !self.tx_empty() {
+            self.lost_writes += 1;
+            return;
+        }
+        if self.mcr & MCR_LOOPBACK != 0 {
+            self.push_rx(byte);
+        } else {
+            self.tx.push(byte);
+        }
+    }
+    /// Deliver bytes from the line into the receiver
+    pub fn receive(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.push_rx(*b);
+        }
+    }
+    /// Bytes that have been sent out on the line
+    pub fn transmitted(&self) -> &[u8] {
+        &self.tx
+    }
+    /// Keep the transmitter busy for the next `polls` reads of LSR
+    pub fn set_tx_busy(&mut self, polls: usize) {
+        self.tx_busy_polls = polls;
+    }
+    /// Number of writes to THR that were made while the transmitter was busy
+    pub fn lost_writes(&self) -> usize {
+        self.lost_writes
+    }
+    /// Current baud rate divisor latch
+    pub fn divisor(&self) -> u16 {
+        ((self.dlm as u16) << 8) | self.dll as u16
+    }
+    pub fn lcr(&self) -> u8 {
+        self.lcr
+    }
+    pub fn mcr(&self) -> u8 {
+        self.mcr
+    }
+    pub fn ier(&self) -> u8 {
+        self.ier
+    }
+    pub fn fcr(&self) -> u8 {
+        self.fcr
+    }
+    /// Number of bytes waiting to be read by the driver
+    pub fn rx_pending(&self) -> usize {
+        self.rx.len()
+    }
+    /// Every access that has been performed, in order
+    pub fn accesses(&self) -> &[Access<u16, u8>] {
+        &self.accesses
+    }
+    /// Forget any recorded accesses
+    pub fn clear_accesses(&mut self) {
+        self.accesses.clear()
+    }
+}
+
+impl Io for Uart16550 {
+    type Item = u8;
+    type Range = u16;
+    unsafe fn read(&mut self, offset: u16) -> u8 {
+        let value = match offset {
+            RBR_THR_DLL if self.dlab() => self.dll,
+            RBR_THR_DLL => self.rx.pop_front().unwrap_or(0),
+            IER_DLM if self.dlab() => self.dlm,
+            IER_DLM => self.ier,
+            IIR_FCR => self.iir(),
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => {
+                let lsr = self.lsr();
+                // Error bits are cleared by reading them
+                self.overrun = false;
+                if self.tx_busy_polls > 0 {
+                    self.tx_busy_polls -= 1;
+                }
+                lsr
+            },
+            // No modem is attached to anything
+            MSR => 0,
+            SCR => self.scr,
+            _ => panic!("Read from offset {} outside of 16550 register window", offset),
+        };
+        self.accesses.push(Access::Read(offset, value));
+        value
+    }
+    unsafe fn write(&mut self, offset: u16, value: u8) {
+        self.accesses.push(Access::Write(offset, value));
+        match offset {
+            RBR_THR_DLL if self.dlab() => self.dll = value,
+            RBR_THR_DLL => self.transmit(value),
+            IER_DLM if self.dlab() => self.dlm = value,
+            // Only the bottom 4 bits exist on a 16550
+            IER_DLM => self.ier = value & 0xf,
+            IIR_FCR => {
+                if value & FCR_CLEAR_RX != 0 || (value ^ self.fcr) & FCR_ENABLE != 0 {
+                    self.rx.clear();
+                }
+                // The clear bits are self clearing
+                self.fcr = value & !0x6;
+            },
+            LCR => self.lcr = value,
+            MCR => self.mcr = value & 0x1f,
+            // Writes to the status registers are ignored
+            LSR | MSR => (),
+            SCR => self.scr = value,
+            _ => panic!("Write to offset {} outside of 16550 register window", offset),
+        }
+    }
+}
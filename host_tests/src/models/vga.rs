@@ -0,0 +1,125 @@
+//! Model of the VGA registers
+//!
+//! Seen from `PORT_BASE`, and starting in the mode the BIOS leaves. CRTC registers 0 to 7 ignore
+//! writes while protected, and the attribute controller alternates between index and data
+//! writes, as the hardware does. The memory the glyphs are written to is not modelled, instead
+//! each time plane 2 is made reachable is counted.
+
+use drivers::io::Io;
+use drivers::vga::{Registers, TEXT_80X25};
+
+const ATTR_INDEX: usize = 0x00;
+const ATTR_READ: usize = 0x01;
+const MISC_WRITE: usize = 0x02;
+const SEQ_INDEX: usize = 0x04;
+const SEQ_DATA: usize = 0x05;
+const MISC_READ: usize = 0x0C;
+const GC_INDEX: usize = 0x0E;
+const GC_DATA: usize = 0x0F;
+const CRTC_INDEX: usize = 0x14;
+const CRTC_DATA: usize = 0x15;
+const STATUS: usize = 0x1A;
+
+pub struct Vga {
+    pub regs: Registers,
+    seq_index: u8,
+    gc_index: u8,
+    crtc_index: u8,
+    attr_index: u8,
+    /// Whether the next attribute write is data rather than an index
+    attr_data: bool,
+    /// Palette source bit of the last attribute index, without which the display is blank
+    pub display_enabled: bool,
+    /// Times the font plane was made linearly reachable
+    pub font_windows: usize,
+    /// Misc output writes while the sequencer was not held in reset
+    pub unsafe_clock_changes: usize,
+}
+
+impl Vga {
+    pub fn new() -> Self {
+        let mut regs = TEXT_80X25;
+        // The BIOS leaves the timing registers protected
+        regs.crtc[0x11] |= 0x80;
+        Vga {
+            regs: regs,
+            seq_index: 0,
+            gc_index: 0,
+            crtc_index: 0,
+            attr_index: 0,
+            attr_data: false,
+            display_enabled: true,
+            font_windows: 0,
+            unsafe_clock_changes: 0,
+        }
+    }
+    fn font_plane_mapped(&self) -> bool {
+        self.regs.seq[2] == 0x04 && self.regs.seq[4] & 0x04 != 0 && self.regs.gc[4] == 2 && self.regs.gc[5] & 0x10 == 0
+            && self.regs.gc[6] & 0x0E == 0x04
+    }
+    fn update_font_window(&mut self, was_mapped: bool) {
+        if !was_mapped && self.font_plane_mapped() {
+            self.font_windows += 1;
+        }
+    }
+}
+
+impl Io for Vga {
+    type Item = u8;
+    unsafe fn read(&mut self, offset: usize) -> u8 {
+        match offset {
+            ATTR_READ => self.regs.attr[self.attr_index as usize],
+            SEQ_DATA => self.regs.seq[self.seq_index as usize],
+            MISC_READ => self.regs.misc,
+            GC_DATA => self.regs.gc[self.gc_index as usize],
+            CRTC_DATA => self.regs.crtc[self.crtc_index as usize],
+            STATUS => {
+                self.attr_data = false;
+                0
+            },
+            _ => panic!("VGA read from {:#x}", offset),
+        }
+    }
+    unsafe fn write(&mut self, offset: usize, value: u8) {
+        let was_mapped = self.font_plane_mapped();
+        match offset {
+            ATTR_INDEX if self.attr_data => {
+                self.regs.attr[self.attr_index as usize] = value;
+                self.attr_data = false;
+            },
+            ATTR_INDEX => {
+                assert!((value & 0x1F) < 21, "Attribute index {:#x}", value);
+                self.attr_index = value & 0x1F;
+                self.display_enabled = value & 0x20 != 0;
+                self.attr_data = true;
+            },
+            MISC_WRITE => {
+                if self.regs.seq[0] & 0x02 != 0 {
+                    self.unsafe_clock_changes += 1;
+                }
+                self.regs.misc = value;
+            },
+            SEQ_INDEX => {
+                assert!(value < 5, "Sequencer index {:#x}", value);
+                self.seq_index = value;
+            },
+            SEQ_DATA => self.regs.seq[self.seq_index as usize] = value,
+            GC_INDEX => {
+                assert!(value < 9, "Graphics controller index {:#x}", value);
+                self.gc_index = value;
+            },
+            GC_DATA => self.regs.gc[self.gc_index as usize] = value,
+            CRTC_INDEX => {
+                assert!(value < 25, "CRTC index {:#x}", value);
+                self.crtc_index = value;
+            },
+            CRTC_DATA => {
+                if self.crtc_index >= 8 || self.regs.crtc[0x11] & 0x80 == 0 {
+                    self.regs.crtc[self.crtc_index as usize] = value;
+                }
+            },
+            _ => panic!("VGA write to {:#x}", offset),
+        }
+        self.update_font_window(was_mapped);
+    }
+}
@@ -0,0 +1,999 @@
+//! Model of a modern virtio-pci device
+//!
+//! `Common` is the common configuration structure, holding the negotiation state and the
+//! registers of each queue. The device side of a queue is a `QueueDevice`, which walks the rings
+//! at the addresses the driver gave, as the device would over DMA. Host memory is used directly,
+//! with physical addresses being the same as virtual ones.
+
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::ptr;
+
+use drivers::io::MixedIo;
+use drivers::virtio::Transport;
+use vspace::Translation;
+use super::Shared;
+
+const DEVICE_FEATURE_SELECT: usize = 0x00;
+const DEVICE_FEATURE: usize = 0x04;
+const DRIVER_FEATURE_SELECT: usize = 0x08;
+const DRIVER_FEATURE: usize = 0x0C;
+const MSIX_CONFIG: usize = 0x10;
+const NUM_QUEUES: usize = 0x12;
+const DEVICE_STATUS: usize = 0x14;
+const CONFIG_GENERATION: usize = 0x15;
+const QUEUE_SELECT: usize = 0x16;
+const QUEUE_SIZE: usize = 0x18;
+const QUEUE_MSIX_VECTOR: usize = 0x1A;
+const QUEUE_ENABLE: usize = 0x1C;
+const QUEUE_NOTIFY_OFF: usize = 0x1E;
+const QUEUE_DESC: usize = 0x20;
+const QUEUE_DRIVER: usize = 0x28;
+const QUEUE_DEVICE: usize = 0x30;
+
+const STATUS_FEATURES_OK: u8 = 8;
+
+/// Host memory, where addresses are their own physical address
+pub struct Identity;
+
+unsafe impl Translation for Identity {
+    fn range_valid(&self, _range: Range<usize>) -> bool {
+        true
+    }
+    fn vaddr_to_paddr_range(&self, range: Range<usize>) -> Option<Range<usize>> {
+        Some(range)
+    }
+    fn paddr_to_vaddr_range(&self, range: Range<usize>) -> Option<Range<usize>> {
+        Some(range)
+    }
+}
+
+/// Registers of a single queue
+#[derive(Debug, Clone, Default)]
+pub struct Queue {
+    pub max_size: u16,
+    pub size: u16,
+    pub msix_vector: u16,
+    pub enabled: bool,
+    pub notify_off: u16,
+    pub desc: u64,
+    pub driver: u64,
+    pub device: u64,
+}
+
+pub struct Common {
+    device_features: u64,
+    /// Features that, if selected, make the device refuse `FEATURES_OK`
+    rejected: u64,
+    device_feature_select: u32,
+    driver_feature_select: u32,
+    driver_features: u64,
+    pub msix_config: u16,
+    status: u8,
+    /// Every status written, reset included
+    status_writes: Vec<u8>,
+    /// Polls of the status before a reset is seen to complete, or `None` to never complete
+    reset_delay: Option<usize>,
+    resetting: Option<usize>,
+    pub generation: u8,
+    queue_select: u16,
+    queues: Vec<Queue>,
+}
+
+impl Common {
+    pub fn new(device_features: u64) -> Self {
+        Common {
+            device_features: device_features,
+            rejected: 0,
+            device_feature_select: 0,
+            driver_feature_select: 0,
+            driver_features: 0,
+            msix_config: 0,
+            status: 0,
+            status_writes: Vec::new(),
+            reset_delay: Some(0),
+            resetting: None,
+            generation: 0,
+            queue_select: 0,
+            queues: Vec::new(),
+        }
+    }
+    /// Add a queue, with the notification offset of the queue being its index
+    pub fn queue(mut self, max_size: u16) -> Self {
+        let index = self.queues.len() as u16;
+        self.queues.push(Queue { max_size: max_size, size: max_size, notify_off: index, ..Queue::default() });
+        self
+    }
+    pub fn reject(mut self, features: u64) -> Self {
+        self.rejected = features;
+        self
+    }
+    pub fn reset_delay(mut self, polls: Option<usize>) -> Self {
+        self.reset_delay = polls;
+        self
+    }
+    pub fn status(&self) -> u8 {
+        self.status
+    }
+    pub fn status_writes(&self) -> &[u8] {
+        &self.status_writes
+    }
+    pub fn driver_features(&self) -> u64 {
+        self.driver_features
+    }
+    pub fn queue_state(&self, index: usize) -> &Queue {
+        &self.queues[index]
+    }
+    /// Device side of a queue the driver has set up
+    pub fn queue_device(&self, index: usize) -> QueueDevice {
+        let queue = &self.queues[index];
+        assert!(queue.enabled, "Queue {} used before being enabled", index);
+        QueueDevice::new(queue)
+    }
+    fn selected(&mut self) -> Option<&mut Queue> {
+        self.queues.get_mut(self.queue_select as usize)
+    }
+    fn read_queue(&mut self, offset: usize) -> u64 {
+        match self.selected() {
+            Some(queue) => match offset {
+                QUEUE_SIZE => queue.size as u64,
+                QUEUE_MSIX_VECTOR => queue.msix_vector as u64,
+                QUEUE_ENABLE => queue.enabled as u64,
+                QUEUE_NOTIFY_OFF => queue.notify_off as u64,
+                QUEUE_DESC => queue.desc,
+                QUEUE_DRIVER => queue.driver,
+                QUEUE_DEVICE => queue.device,
+                _ => panic!("Read of queue register {:#x}", offset),
+            },
+            // Queues that do not exist read as size 0
+            None => 0,
+        }
+    }
+    fn write_queue(&mut self, offset: usize, value: u64, high: bool) {
+        let queue = self.selected().expect("Write to a queue that does not exist");
+        assert!(!queue.enabled, "Queue register {:#x} written while enabled", offset);
+        let set = |field: &mut u64| if high {
+            *field = *field & 0xFFFF_FFFF | value << 32
+        } else {
+            *field = *field & !0xFFFF_FFFF | value
+        };
+        match offset {
+            QUEUE_SIZE => {
+                assert!(value as u16 <= queue.max_size, "Queue size above the maximum");
+                queue.size = value as u16;
+            },
+            QUEUE_MSIX_VECTOR => queue.msix_vector = value as u16,
+            QUEUE_ENABLE => queue.enabled = value == 1,
+            QUEUE_DESC => set(&mut queue.desc),
+            QUEUE_DRIVER => set(&mut queue.driver),
+            QUEUE_DEVICE => set(&mut queue.device),
+            _ => panic!("Write of queue register {:#x}", offset),
+        }
+    }
+    fn write_status(&mut self, value: u8) {
+        self.status_writes.push(value);
+        if value == 0 {
+            self.driver_features = 0;
+            for queue in self.queues.iter_mut() {
+                *queue = Queue { max_size: queue.max_size, size: queue.max_size, notify_off: queue.notify_off, ..Queue::default() };
+            }
+            self.resetting = self.reset_delay;
+            self.status = 0xFF;
+            if self.resetting == Some(0) {
+                self.resetting = None;
+                self.status = 0;
+            }
+            return;
+        }
+        let mut value = value;
+        if value & STATUS_FEATURES_OK != 0 && self.driver_features & self.rejected != 0 {
+            value &= !STATUS_FEATURES_OK;
+        }
+        self.status = value;
+    }
+    fn read_status(&mut self) -> u8 {
+        if let Some(polls) = self.resetting {
+            if polls == 0 {
+                self.resetting = None;
+                self.status = 0;
+            } else {
+                self.resetting = Some(polls - 1);
+            }
+        }
+        self.status
+    }
+}
+
+impl MixedIo for Common {
+    unsafe fn read8(&mut self, offset: usize) -> u8 {
+        match offset {
+            DEVICE_STATUS => self.read_status(),
+            CONFIG_GENERATION => self.generation,
+            _ => panic!("Byte read of common configuration {:#x}", offset),
+        }
+    }
+    unsafe fn read16(&mut self, offset: usize) -> u16 {
+        match offset {
+            MSIX_CONFIG => self.msix_config,
+            NUM_QUEUES => self.queues.len() as u16,
+            QUEUE_SELECT => self.queue_select,
+            QUEUE_SIZE | QUEUE_MSIX_VECTOR | QUEUE_ENABLE | QUEUE_NOTIFY_OFF => self.read_queue(offset) as u16,
+            _ => panic!("Word read of common configuration {:#x}", offset),
+        }
+    }
+    unsafe fn read32(&mut self, offset: usize) -> u32 {
+        match offset {
+            DEVICE_FEATURE => match self.device_feature_select {
+                0 => self.device_features as u32,
+                1 => (self.device_features >> 32) as u32,
+                _ => 0,
+            },
+            QUEUE_DESC | QUEUE_DRIVER | QUEUE_DEVICE => self.read_queue(offset) as u32,
+            _ => panic!("Dword read of common configuration {:#x}", offset),
+        }
+    }
+    unsafe fn write8(&mut self, offset: usize, value: u8) {
+        match offset {
+            DEVICE_STATUS => self.write_status(value),
+            _ => panic!("Byte write of common configuration {:#x}", offset),
+        }
+    }
+    unsafe fn write16(&mut self, offset: usize, value: u16) {
+        match offset {
+            MSIX_CONFIG => self.msix_config = value,
+            QUEUE_SELECT => self.queue_select = value,
+            QUEUE_SIZE | QUEUE_MSIX_VECTOR | QUEUE_ENABLE => self.write_queue(offset, value as u64, false),
+            _ => panic!("Word write of common configuration {:#x}", offset),
+        }
+    }
+    unsafe fn write32(&mut self, offset: usize, value: u32) {
+        match offset {
+            DEVICE_FEATURE_SELECT => self.device_feature_select = value,
+            DRIVER_FEATURE_SELECT => self.driver_feature_select = value,
+            DRIVER_FEATURE => match self.driver_feature_select {
+                0 => self.driver_features = self.driver_features & !0xFFFF_FFFF | value as u64,
+                1 => self.driver_features = self.driver_features & 0xFFFF_FFFF | (value as u64) << 32,
+                _ => (),
+            },
+            QUEUE_DESC | QUEUE_DRIVER | QUEUE_DEVICE => self.write_queue(offset, value as u64, false),
+            _ if offset >= QUEUE_DESC && offset % 8 == 4 => self.write_queue(offset - 4, value as u64, true),
+            _ => panic!("Dword write of common configuration {:#x}", offset),
+        }
+    }
+}
+
+impl<T: MixedIo> MixedIo for Shared<T> {
+    unsafe fn read8(&mut self, offset: usize) -> u8 {
+        self.get().read8(offset)
+    }
+    unsafe fn read16(&mut self, offset: usize) -> u16 {
+        self.get().read16(offset)
+    }
+    unsafe fn read32(&mut self, offset: usize) -> u32 {
+        self.get().read32(offset)
+    }
+    unsafe fn write8(&mut self, offset: usize, value: u8) {
+        self.get().write8(offset, value)
+    }
+    unsafe fn write16(&mut self, offset: usize, value: u16) {
+        self.get().write16(offset, value)
+    }
+    unsafe fn write32(&mut self, offset: usize, value: u32) {
+        self.get().write32(offset, value)
+    }
+}
+
+/// A plain region of registers, such as the notification, ISR or device configuration
+///
+/// Records every write as (offset, width in bytes, value). Reads of the ISR clear it, as they
+/// do on the device, when `read_clears` is set.
+pub struct Region {
+    bytes: Vec<u8>,
+    writes: Vec<(usize, usize, u32)>,
+    read_clears: bool,
+    /// Common configuration whose generation is bumped on the first reads, to simulate the
+    /// device changing its configuration while it is read
+    changes: Option<(Shared<Common>, usize)>,
+}
+
+impl Region {
+    pub fn new(size: usize) -> Self {
+        Region { bytes: vec![0; size], writes: Vec::new(), read_clears: false, changes: None }
+    }
+    pub fn isr(value: u8) -> Self {
+        Region { bytes: vec![value], writes: Vec::new(), read_clears: true, changes: None }
+    }
+    pub fn set(mut self, offset: usize, bytes: &[u8]) -> Self {
+        self.bytes[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+    /// Change the configuration generation on each of the first `reads`
+    pub fn changing(mut self, common: Shared<Common>, reads: usize) -> Self {
+        self.changes = Some((common, reads));
+        self
+    }
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+    pub fn writes(&self) -> &[(usize, usize, u32)] {
+        &self.writes
+    }
+    fn read(&mut self, offset: usize, width: usize) -> u32 {
+        let mut value = 0;
+        for i in (0..width).rev() {
+            value = value << 8 | self.bytes[offset + i] as u32;
+        }
+        if self.read_clears {
+            for byte in self.bytes[offset..offset + width].iter_mut() {
+                *byte = 0;
+            }
+        }
+        if let Some((ref common, ref mut reads)) = self.changes {
+            if *reads > 0 {
+                *reads -= 1;
+                let mut common = common.get();
+                common.generation = common.generation.wrapping_add(1);
+            }
+        }
+        value
+    }
+    fn write(&mut self, offset: usize, width: usize, value: u32) {
+        self.writes.push((offset, width, value));
+        for i in 0..width {
+            self.bytes[offset + i] = (value >> (i * 8)) as u8;
+        }
+    }
+}
+
+impl MixedIo for Region {
+    unsafe fn read8(&mut self, offset: usize) -> u8 {
+        self.read(offset, 1) as u8
+    }
+    unsafe fn read16(&mut self, offset: usize) -> u16 {
+        self.read(offset, 2) as u16
+    }
+    unsafe fn read32(&mut self, offset: usize) -> u32 {
+        self.read(offset, 4)
+    }
+    unsafe fn write8(&mut self, offset: usize, value: u8) {
+        self.write(offset, 1, value as u32)
+    }
+    unsafe fn write16(&mut self, offset: usize, value: u16) {
+        self.write(offset, 2, value as u32)
+    }
+    unsafe fn write32(&mut self, offset: usize, value: u32) {
+        self.write(offset, 4, value)
+    }
+}
+
+/// A descriptor as the device sees it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Descriptor {
+    pub addr: u64,
+    pub len: u32,
+    pub writable: bool,
+}
+
+/// The device end of a split virtqueue
+pub struct QueueDevice {
+    size: u16,
+    desc: u64,
+    driver: u64,
+    device: u64,
+    last_avail: u16,
+    used_idx: u16,
+}
+
+impl QueueDevice {
+    fn new(queue: &Queue) -> Self {
+        QueueDevice {
+            size: queue.size,
+            desc: queue.desc,
+            driver: queue.driver,
+            device: queue.device,
+            last_avail: 0,
+            used_idx: 0,
+        }
+    }
+    unsafe fn at<T>(address: u64) -> *mut T {
+        address as usize as *mut T
+    }
+    /// Whether the driver asked not to be interrupted
+    pub unsafe fn interrupts_suppressed(&self) -> bool {
+        ptr::read_volatile(Self::at::<u16>(self.driver)) & 1 != 0
+    }
+    /// Ask the driver not to notify of new buffers
+    pub unsafe fn suppress_notifications(&mut self, suppress: bool) {
+        ptr::write_volatile(Self::at::<u16>(self.device), suppress as u16);
+    }
+    /// Number of chains the driver has made available that have not been taken
+    pub unsafe fn available(&self) -> u16 {
+        ptr::read_volatile(Self::at::<u16>(self.driver + 2)).wrapping_sub(self.last_avail)
+    }
+    /// Take the next chain the driver made available, as its head and descriptors
+    pub unsafe fn pop(&mut self) -> Option<(u16, Vec<Descriptor>)> {
+        let avail_idx = ptr::read_volatile(Self::at::<u16>(self.driver + 2));
+        if avail_idx == self.last_avail {
+            return None;
+        }
+        let slot = (self.last_avail % self.size) as u64;
+        let head = ptr::read_volatile(Self::at::<u16>(self.driver + 4 + 2 * slot));
+        self.last_avail = self.last_avail.wrapping_add(1);
+        let mut chain = Vec::new();
+        let mut index = head;
+        loop {
+            assert!(index < self.size, "Descriptor {} out of range", index);
+            assert!(chain.len() < self.size as usize, "Descriptor chain loops");
+            let desc = Self::at::<u8>(self.desc + 16 * index as u64);
+            let addr = ptr::read_volatile(desc as *const u64);
+            let len = ptr::read_volatile(desc.offset(8) as *const u32);
+            let flags = ptr::read_volatile(desc.offset(12) as *const u16);
+            let next = ptr::read_volatile(desc.offset(14) as *const u16);
+            chain.push(Descriptor { addr: addr, len: len, writable: flags & 2 != 0 });
+            if flags & 1 == 0 {
+                break;
+            }
+            index = next;
+        }
+        Some((head, chain))
+    }
+    /// Return a chain to the driver, having written `len` bytes
+    pub unsafe fn push(&mut self, head: u16, len: u32) {
+        let slot = (self.used_idx % self.size) as u64;
+        let elem = self.device + 4 + 8 * slot;
+        ptr::write_volatile(Self::at::<u32>(elem), head as u32);
+        ptr::write_volatile(Self::at::<u32>(elem + 4), len);
+        self.used_idx = self.used_idx.wrapping_add(1);
+        ptr::write_volatile(Self::at::<u16>(self.device + 2), self.used_idx);
+    }
+}
+
+/// A virtio-blk device, processing requests when its queue is notified
+///
+/// This stands in for the notification region, so requests complete before the notification
+/// write returns, as if the device were infinitely fast.
+pub struct Blk {
+    common: Shared<Common>,
+    queue: Option<QueueDevice>,
+    pub disk: Vec<u8>,
+    /// Every request as (type, sector, data length)
+    pub requests: Vec<(u32, u64, usize)>,
+    /// Sector at which transfers fail with an I/O error
+    pub bad_sector: Option<u64>,
+    pub notifies: usize,
+}
+
+const BLK_SECTOR: usize = 512;
+
+impl Blk {
+    pub fn new(common: Shared<Common>, disk: Vec<u8>) -> Self {
+        Blk { common: common, queue: None, disk: disk, requests: Vec::new(), bad_sector: None, notifies: 0 }
+    }
+    /// Device configuration with a capacity in sectors and block size
+    pub fn config(capacity: u64, block_size: u32) -> Region {
+        let mut bytes = [0; 24];
+        for i in 0..8 {
+            bytes[i] = (capacity >> (i * 8)) as u8;
+        }
+        for i in 0..4 {
+            bytes[20 + i] = (block_size >> (i * 8)) as u8;
+        }
+        Region::new(0x40).set(0, &bytes)
+    }
+    unsafe fn process(&mut self) {
+        if self.queue.is_none() {
+            self.queue = Some(self.common.get().queue_device(0));
+        }
+        while let Some((head, chain)) = self.queue.as_mut().unwrap().pop() {
+            assert!(chain.len() >= 2, "Request without a header and status");
+            let header = chain[0];
+            assert!(!header.writable && header.len == 16, "Bad request header {:?}", header);
+            let status = chain[chain.len() - 1];
+            assert!(status.writable && status.len == 1, "Bad request status {:?}", status);
+            let kind = ptr::read(header.addr as usize as *const u32);
+            let sector = ptr::read((header.addr + 8) as usize as *const u64);
+            let data = &chain[1..chain.len() - 1];
+            let len: usize = data.iter().map(|desc| desc.len as usize).sum();
+            self.requests.push((kind, sector, len));
+            let start = sector as usize * BLK_SECTOR;
+            let bad = self.bad_sector.map_or(false, |bad| sector <= bad && bad < sector + (len / BLK_SECTOR) as u64);
+            let mut written = 0;
+            let result = match kind {
+                0 | 1 if start + len > self.disk.len() || bad => 1,
+                0 => {
+                    let mut offset = start;
+                    for desc in data {
+                        assert!(desc.writable, "Read into a device readable buffer");
+                        let len = desc.len as usize;
+                        ptr::copy_nonoverlapping(self.disk[offset..offset + len].as_ptr(), desc.addr as usize as *mut u8, len);
+                        offset += len;
+                    }
+                    written = len as u32;
+                    0
+                },
+                1 => {
+                    let mut offset = start;
+                    for desc in data {
+                        assert!(!desc.writable, "Write from a device writable buffer");
+                        let len = desc.len as usize;
+                        ptr::copy_nonoverlapping(desc.addr as usize as *const u8, self.disk[offset..offset + len].as_mut_ptr(), len);
+                        offset += len;
+                    }
+                    0
+                },
+                4 => 0,
+                _ => 2,
+            };
+            ptr::write(status.addr as usize as *mut u8, result);
+            self.queue.as_mut().unwrap().push(head, written + 1);
+        }
+    }
+}
+
+impl MixedIo for Blk {
+    unsafe fn read8(&mut self, offset: usize) -> u8 {
+        panic!("Read of notification region {:#x}", offset)
+    }
+    unsafe fn read16(&mut self, offset: usize) -> u16 {
+        panic!("Read of notification region {:#x}", offset)
+    }
+    unsafe fn read32(&mut self, offset: usize) -> u32 {
+        panic!("Read of notification region {:#x}", offset)
+    }
+    unsafe fn write8(&mut self, offset: usize, _value: u8) {
+        panic!("Byte notification at {:#x}", offset)
+    }
+    unsafe fn write16(&mut self, offset: usize, value: u16) {
+        assert_eq!((offset, value), (0, 0), "Notification of a queue that does not exist");
+        self.notifies += 1;
+        self.process();
+    }
+    unsafe fn write32(&mut self, offset: usize, _value: u32) {
+        panic!("Dword notification at {:#x}", offset)
+    }
+}
+
+/// A virtio-net device, with the receive queue 0 and transmit queue 1
+///
+/// Like `Blk`, this stands in for the notification region. Frames to receive are queued until
+/// the driver offers a buffer for them, and transmitted frames are taken when the transmit
+/// queue is notified.
+pub struct Net {
+    common: Shared<Common>,
+    queues: Option<(QueueDevice, QueueDevice)>,
+    /// Frames waiting for a receive buffer
+    pub incoming: VecDeque<Vec<u8>>,
+    /// Every frame transmitted, without its header
+    pub sent: Vec<Vec<u8>>,
+    /// Keep transmit buffers, rather than returning them once sent, until `complete_tx`
+    pub hold_tx: bool,
+    held: Vec<u16>,
+    /// Notifications of each queue
+    pub notifies: [usize; 2],
+}
+
+const NET_HEADER: usize = 12;
+
+impl Net {
+    pub fn new(common: Shared<Common>) -> Self {
+        Net { common: common, queues: None, incoming: VecDeque::new(), sent: Vec::new(), hold_tx: false, held: Vec::new(), notifies: [0; 2] }
+    }
+    /// Device configuration with a MAC address and link status
+    pub fn config(mac: [u8; 6], status: u16) -> Region {
+        Region::new(0x20).set(0, &mac).set(6, &[status as u8, (status >> 8) as u8])
+    }
+    fn queues(&mut self) -> &mut (QueueDevice, QueueDevice) {
+        if self.queues.is_none() {
+            let common = self.common.get();
+            self.queues = Some((common.queue_device(0), common.queue_device(1)));
+        }
+        self.queues.as_mut().unwrap()
+    }
+    /// Receive buffers the driver has offered
+    pub unsafe fn rx_available(&mut self) -> u16 {
+        self.queues().0.available()
+    }
+    /// Receive a frame, as soon as there is a buffer for it
+    pub unsafe fn deliver(&mut self, frame: &[u8]) {
+        self.incoming.push_back(frame.to_vec());
+        self.fill();
+    }
+    unsafe fn fill(&mut self) {
+        while !self.incoming.is_empty() && self.rx_available() > 0 {
+            let frame = self.incoming.pop_front().unwrap();
+            let (head, chain) = self.queues().0.pop().unwrap();
+            assert_eq!(chain.len(), 1, "Receive buffer split across descriptors");
+            let desc = chain[0];
+            assert!(desc.writable, "Receive into a device readable buffer");
+            assert!(desc.len as usize >= NET_HEADER + frame.len(), "Receive buffer of {} bytes too small", desc.len);
+            let buffer = desc.addr as usize as *mut u8;
+            ptr::write_bytes(buffer, 0, NET_HEADER);
+            ptr::copy_nonoverlapping(frame.as_ptr(), buffer.offset(NET_HEADER as isize), frame.len());
+            self.queues().0.push(head, (NET_HEADER + frame.len()) as u32);
+        }
+    }
+    /// Return the transmit buffers held back by `hold_tx`
+    pub unsafe fn complete_tx(&mut self) {
+        for head in self.held.split_off(0) {
+            self.queues().1.push(head, 0);
+        }
+    }
+    unsafe fn transmit(&mut self) {
+        while let Some((head, chain)) = self.queues().1.pop() {
+            let mut bytes = Vec::new();
+            for desc in chain {
+                assert!(!desc.writable, "Transmit from a device writable buffer");
+                bytes.extend_from_slice(std::slice::from_raw_parts(desc.addr as usize as *const u8, desc.len as usize));
+            }
+            assert!(bytes.len() >= NET_HEADER, "Transmit without a header");
+            assert!(bytes[..NET_HEADER].iter().all(|&byte| byte == 0), "Header asks for offloads that were not negotiated");
+            self.sent.push(bytes.split_off(NET_HEADER));
+            if self.hold_tx {
+                self.held.push(head);
+            } else {
+                self.queues().1.push(head, 0);
+            }
+        }
+    }
+}
+
+impl MixedIo for Net {
+    unsafe fn read8(&mut self, offset: usize) -> u8 {
+        panic!("Read of notification region {:#x}", offset)
+    }
+    unsafe fn read16(&mut self, offset: usize) -> u16 {
+        panic!("Read of notification region {:#x}", offset)
+    }
+    unsafe fn read32(&mut self, offset: usize) -> u32 {
+        panic!("Read of notification region {:#x}", offset)
+    }
+    unsafe fn write8(&mut self, offset: usize, _value: u8) {
+        panic!("Byte notification at {:#x}", offset)
+    }
+    unsafe fn write16(&mut self, offset: usize, value: u16) {
+        assert_eq!(offset, 0, "Notification at {:#x}", offset);
+        match value {
+            0 => self.fill(),
+            1 => self.transmit(),
+            _ => panic!("Notification of queue {} that does not exist", value),
+        }
+        self.notifies[value as usize] += 1;
+    }
+    unsafe fn write32(&mut self, offset: usize, _value: u32) {
+        panic!("Dword notification at {:#x}", offset)
+    }
+}
+
+/// A virtio-console device with multiport, where port 0 uses queues 0 and 1, the control queues
+/// are 2 and 3, and each other port `n` uses queues `2n + 2` and `2n + 3`
+///
+/// Like `Blk`, this stands in for the notification region. Ports are announced when the driver
+/// says it is ready, and described once the driver says a port is ready, as QEMU does.
+pub struct Console {
+    common: Shared<Common>,
+    queues: Vec<Option<QueueDevice>>,
+    /// Every port the device has, as its name and whether it is the console
+    pub ports: Vec<(Option<String>, bool)>,
+    /// Ports open at the host end
+    pub host_open: Vec<bool>,
+    /// Control messages from the driver, as (ID, event, value)
+    pub control: Vec<(u32, u16, u16)>,
+    /// Control messages waiting for a buffer, without the driver having read them
+    control_pending: VecDeque<Vec<u8>>,
+    /// Everything written to each port
+    pub written: Vec<Vec<u8>>,
+    /// Bytes waiting for a receive buffer, for each port
+    incoming: Vec<VecDeque<u8>>,
+    /// Keep transmit buffers, rather than returning them once written, until `complete_tx`
+    pub hold_tx: bool,
+    held: Vec<(usize, u16)>,
+}
+
+const CONSOLE_DEVICE_READY: u16 = 0;
+const CONSOLE_DEVICE_ADD: u16 = 1;
+const CONSOLE_DEVICE_REMOVE: u16 = 2;
+const CONSOLE_PORT_READY: u16 = 3;
+const CONSOLE_CONSOLE_PORT: u16 = 4;
+const CONSOLE_PORT_OPEN: u16 = 6;
+const CONSOLE_PORT_NAME: u16 = 7;
+
+impl Console {
+    /// A device with the given ports, as (name, whether it is the console)
+    pub fn new(common: Shared<Common>, ports: &[(Option<&str>, bool)]) -> Self {
+        Console {
+            common: common,
+            queues: Vec::new(),
+            ports: ports.iter().map(|&(name, console)| (name.map(String::from), console)).collect(),
+            host_open: vec![false; ports.len()],
+            control: Vec::new(),
+            control_pending: VecDeque::new(),
+            written: vec![Vec::new(); ports.len()],
+            incoming: vec![VecDeque::new(); ports.len()],
+            hold_tx: false,
+            held: Vec::new(),
+        }
+    }
+    /// Device configuration with the most ports the device supports
+    pub fn config(max_ports: u32) -> Region {
+        let mut bytes = [0; 4];
+        for i in 0..4 {
+            bytes[i] = (max_ports >> (i * 8)) as u8;
+        }
+        Region::new(0x10).set(4, &bytes)
+    }
+    fn queue(&mut self, index: usize) -> &mut QueueDevice {
+        if self.queues.len() <= index {
+            self.queues.resize_with(index + 1, || None);
+        }
+        if self.queues[index].is_none() {
+            self.queues[index] = Some(self.common.get().queue_device(index));
+        }
+        self.queues[index].as_mut().unwrap()
+    }
+    fn port_queues(port: usize) -> (usize, usize) {
+        match port {
+            0 => (0, 1),
+            port => (2 * port + 2, 2 * port + 3),
+        }
+    }
+    /// Send a control message to the driver, as soon as there is a buffer for it
+    pub unsafe fn send_control(&mut self, id: u32, event: u16, value: u16, data: &[u8]) {
+        let mut message = Vec::new();
+        message.extend_from_slice(&id.to_le_bytes());
+        message.extend_from_slice(&event.to_le_bytes());
+        message.extend_from_slice(&value.to_le_bytes());
+        message.extend_from_slice(data);
+        self.control_pending.push_back(message);
+        self.fill_control();
+    }
+    unsafe fn fill_control(&mut self) {
+        while !self.control_pending.is_empty() && self.queue(2).available() > 0 {
+            let message = self.control_pending.pop_front().unwrap();
+            let (head, desc) = self.writable(2);
+            assert!(desc.len as usize >= message.len(), "Control buffer of {} bytes too small", desc.len);
+            ptr::copy_nonoverlapping(message.as_ptr(), desc.addr as usize as *mut u8, message.len());
+            self.queue(2).push(head, message.len() as u32);
+        }
+    }
+    unsafe fn writable(&mut self, index: usize) -> (u16, Descriptor) {
+        let (head, chain) = self.queue(index).pop().unwrap();
+        assert_eq!(chain.len(), 1, "Receive buffer split across descriptors");
+        assert!(chain[0].writable, "Receive into a device readable buffer");
+        (head, chain[0])
+    }
+    /// Send bytes to a port, as soon as there are buffers for them
+    pub unsafe fn send(&mut self, port: usize, data: &[u8]) {
+        self.incoming[port].extend(data.iter());
+        self.fill(port);
+    }
+    unsafe fn fill(&mut self, port: usize) {
+        let rx = Self::port_queues(port).0;
+        while !self.incoming[port].is_empty() && self.queue(rx).available() > 0 {
+            let (head, desc) = self.writable(rx);
+            let len = self.incoming[port].len().min(desc.len as usize);
+            for (i, byte) in self.incoming[port].drain(..len).enumerate() {
+                ptr::write((desc.addr as usize + i) as *mut u8, byte);
+            }
+            self.queue(rx).push(head, len as u32);
+        }
+    }
+    /// Open or close a port at the host end
+    pub unsafe fn set_host_open(&mut self, port: usize, open: bool) {
+        self.host_open[port] = open;
+        self.send_control(port as u32, CONSOLE_PORT_OPEN, open as u16, &[]);
+    }
+    /// Unplug a port
+    pub unsafe fn remove(&mut self, port: usize) {
+        self.send_control(port as u32, CONSOLE_DEVICE_REMOVE, 0, &[]);
+    }
+    /// Return the transmit buffers held back by `hold_tx`
+    pub unsafe fn complete_tx(&mut self) {
+        for (index, head) in self.held.split_off(0) {
+            self.queue(index).push(head, 0);
+        }
+    }
+    unsafe fn take(&mut self, index: usize) -> Vec<(u16, Vec<u8>)> {
+        let mut taken = Vec::new();
+        while let Some((head, chain)) = self.queue(index).pop() {
+            let mut bytes = Vec::new();
+            for desc in chain {
+                assert!(!desc.writable, "Transmit from a device writable buffer");
+                bytes.extend_from_slice(std::slice::from_raw_parts(desc.addr as usize as *const u8, desc.len as usize));
+            }
+            taken.push((head, bytes));
+        }
+        taken
+    }
+    unsafe fn handle_control(&mut self) {
+        for (head, message) in self.take(3) {
+            assert_eq!(message.len(), 8, "Control message of {} bytes", message.len());
+            let id = u32::from_le_bytes([message[0], message[1], message[2], message[3]]);
+            let event = u16::from_le_bytes([message[4], message[5]]);
+            let value = u16::from_le_bytes([message[6], message[7]]);
+            self.control.push((id, event, value));
+            self.queue(3).push(head, 0);
+            match event {
+                CONSOLE_DEVICE_READY => for port in 0..self.ports.len() {
+                    self.send_control(port as u32, CONSOLE_DEVICE_ADD, 0, &[]);
+                },
+                CONSOLE_PORT_READY if value == 1 => {
+                    let (name, console) = self.ports[id as usize].clone();
+                    if console {
+                        self.send_control(id, CONSOLE_CONSOLE_PORT, 1, &[]);
+                    }
+                    if let Some(name) = name {
+                        self.send_control(id, CONSOLE_PORT_NAME, 1, name.as_bytes());
+                    }
+                    if self.host_open[id as usize] {
+                        self.send_control(id, CONSOLE_PORT_OPEN, 1, &[]);
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+    unsafe fn transmit(&mut self, port: usize) {
+        let tx = Self::port_queues(port).1;
+        for (head, bytes) in self.take(tx) {
+            self.written[port].extend_from_slice(&bytes);
+            if self.hold_tx {
+                self.held.push((tx, head));
+            } else {
+                self.queue(tx).push(head, 0);
+            }
+        }
+    }
+}
+
+impl MixedIo for Console {
+    unsafe fn read8(&mut self, offset: usize) -> u8 {
+        panic!("Read of notification region {:#x}", offset)
+    }
+    unsafe fn read16(&mut self, offset: usize) -> u16 {
+        panic!("Read of notification region {:#x}", offset)
+    }
+    unsafe fn read32(&mut self, offset: usize) -> u32 {
+        panic!("Read of notification region {:#x}", offset)
+    }
+    unsafe fn write8(&mut self, offset: usize, _value: u8) {
+        panic!("Byte notification at {:#x}", offset)
+    }
+    unsafe fn write16(&mut self, offset: usize, value: u16) {
+        assert_eq!(offset, 0, "Notification at {:#x}", offset);
+        match value {
+            2 => self.fill_control(),
+            3 => self.handle_control(),
+            0 => self.fill(0),
+            1 => self.transmit(0),
+            queue => {
+                let port = (queue as usize - 2) / 2;
+                assert!((queue as usize) < self.common.get().queues.len(), "Notification of queue {} that does not exist", queue);
+                if port >= self.ports.len() {
+                    // Queues of ports the device never added are ignored
+                } else if queue % 2 == 0 {
+                    self.fill(port);
+                } else {
+                    self.transmit(port);
+                }
+            },
+        }
+    }
+    unsafe fn write32(&mut self, offset: usize, _value: u32) {
+        panic!("Dword notification at {:#x}", offset)
+    }
+}
+
+/// A virtio-rng device, which has a limited number of bytes to give
+///
+/// Like `Blk`, this stands in for the notification region. Buffers are filled with a counting
+/// pattern, as far as the bytes `supply` has made available go.
+pub struct Rng {
+    common: Shared<Common>,
+    queue: Option<QueueDevice>,
+    /// Bytes the device has to give
+    pub available: usize,
+    next: u8,
+    pub notifies: usize,
+}
+
+impl Rng {
+    pub fn new(common: Shared<Common>) -> Self {
+        Rng { common: common, queue: None, available: 0, next: 0, notifies: 0 }
+    }
+    /// Make more bytes available, filling any buffers the driver has given
+    pub unsafe fn supply(&mut self, bytes: usize) {
+        self.available += bytes;
+        self.fill();
+    }
+    unsafe fn fill(&mut self) {
+        if self.queue.is_none() {
+            self.queue = Some(self.common.get().queue_device(0));
+        }
+        while self.available > 0 && self.queue.as_ref().unwrap().available() > 0 {
+            let (head, chain) = self.queue.as_mut().unwrap().pop().unwrap();
+            assert_eq!(chain.len(), 1, "Entropy buffer split across descriptors");
+            let desc = chain[0];
+            assert!(desc.writable, "Entropy into a device readable buffer");
+            let len = self.available.min(desc.len as usize);
+            for i in 0..len {
+                ptr::write((desc.addr as usize + i) as *mut u8, self.next);
+                self.next = self.next.wrapping_add(1);
+            }
+            self.available -= len;
+            self.queue.as_mut().unwrap().push(head, len as u32);
+        }
+    }
+}
+
+impl MixedIo for Rng {
+    unsafe fn read8(&mut self, offset: usize) -> u8 {
+        panic!("Read of notification region {:#x}", offset)
+    }
+    unsafe fn read16(&mut self, offset: usize) -> u16 {
+        panic!("Read of notification region {:#x}", offset)
+    }
+    unsafe fn read32(&mut self, offset: usize) -> u32 {
+        panic!("Read of notification region {:#x}", offset)
+    }
+    unsafe fn write8(&mut self, offset: usize, _value: u8) {
+        panic!("Byte notification at {:#x}", offset)
+    }
+    unsafe fn write16(&mut self, offset: usize, value: u16) {
+        assert_eq!((offset, value), (0, 0), "Notification of a queue that does not exist");
+        self.notifies += 1;
+        self.fill();
+    }
+    unsafe fn write32(&mut self, offset: usize, _value: u32) {
+        panic!("Dword notification at {:#x}", offset)
+    }
+}
+
+/// Any region model, so that the regions of one transport can be different models
+pub struct Window(pub Box<MixedIo>);
+
+impl Window {
+    pub fn new<T: MixedIo + 'static>(region: T) -> Self {
+        Window(Box::new(region))
+    }
+}
+
+impl MixedIo for Window {
+    unsafe fn read8(&mut self, offset: usize) -> u8 {
+        self.0.read8(offset)
+    }
+    unsafe fn read16(&mut self, offset: usize) -> u16 {
+        self.0.read16(offset)
+    }
+    unsafe fn read32(&mut self, offset: usize) -> u32 {
+        self.0.read32(offset)
+    }
+    unsafe fn write8(&mut self, offset: usize, value: u8) {
+        self.0.write8(offset, value)
+    }
+    unsafe fn write16(&mut self, offset: usize, value: u16) {
+        self.0.write16(offset, value)
+    }
+    unsafe fn write32(&mut self, offset: usize, value: u32) {
+        self.0.write32(offset, value)
+    }
+}
+
+/// A whole device, with the model of each region kept for tests to look at
+///
+/// The device model is also the notification region, so that it runs its queues when notified.
+pub struct Device<T> {
+    pub common: Shared<Common>,
+    pub device: Shared<T>,
+    pub config: Option<Shared<Region>>,
+}
+
+impl<T: MixedIo + 'static> Device<T> {
+    pub fn new(common: Shared<Common>, device: T, config: Option<Region>) -> Self {
+        Device { common: common, device: Shared::new(device), config: config.map(Shared::new) }
+    }
+    /// Transport over the regions, as `virtio::open` gives the driver
+    pub fn transport(&self) -> Transport<Window> {
+        Transport::new(Window::new(self.common.clone()), Window::new(self.device.clone()), 0,
+            Window::new(Region::isr(0)), self.config.as_ref().map(|config| Window::new(config.clone())))
+    }
+}
@@ -0,0 +1,135 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::block::{self, BlockDevice, Disk};
+use rlk_host_tests::drivers::ahci::{hba, port, Error, Hba, Port};
+use rlk_host_tests::models::ahci::{Attached, Hba as HbaModel};
+use rlk_host_tests::models::virtio::Identity;
+use rlk_host_tests::models::block::mbr;
+use rlk_host_tests::models::Shared;
+
+/// Image with each sector filled with its own number
+fn numbered(sectors: usize, sector_size: usize) -> Vec<u8> {
+    (0..sectors).flat_map(|sector| vec![sector as u8; sector_size]).collect()
+}
+
+fn disk(sectors: usize) -> Attached {
+    Attached::Disk { data: numbered(sectors, 512), sector_size: 512, lba48: true }
+}
+
+/// Reset the controller, as the kernel does before starting ports
+fn controller(model: HbaModel) -> Shared<HbaModel> {
+    let model = Shared::new(model);
+    let mut hba = unsafe {Hba::new(model.clone())};
+    unsafe {hba.take_ownership()}.unwrap();
+    unsafe {hba.reset()}.unwrap();
+    model
+}
+
+fn start(model: &Shared<HbaModel>, index: u8) -> Result<Port<Shared<HbaModel>>, Error> {
+    unsafe {Port::new(model.clone(), index, &Identity)}
+}
+
+#[test]
+fn reset_and_ownership() {
+    let model = Shared::new(HbaModel::new(vec![disk(8), Attached::Nothing, Attached::Nothing]).bios_owned());
+    let mut hba = unsafe {Hba::new(model.clone())};
+    assert!(hba.supports_64bit());
+    assert_eq!(hba.slots(), 32);
+    assert_eq!(unsafe {hba.version()}, (1, 0x301));
+    unsafe {hba.take_ownership()}.unwrap();
+    assert_eq!(model.get().bohc() & (hba::BOHC_OOS | hba::BOHC_BOS), hba::BOHC_OOS);
+    unsafe {hba.reset()}.unwrap();
+    assert_eq!(model.get().resets, 1);
+    assert_eq!(unsafe {hba.ports_implemented()}, 0b111);
+}
+
+#[test]
+fn port_detection() {
+    let model = controller(HbaModel::new(vec![disk(64), Attached::Nothing, Attached::Other(0xEB14_0101)]));
+    let port = start(&model, 0).unwrap();
+    assert_eq!(port.index(), 0);
+    let identity = port.identity();
+    assert_eq!((identity.model.as_str(), identity.serial.as_str()), ("RLK Model Disk", "RLK0001"));
+    assert_eq!((identity.sectors, identity.sector_size, identity.lba48), (64, 512, true));
+    assert_eq!((port.sectors(), port.sector_size(), port.read_only()), (64, 512, false));
+    assert_eq!(start(&model, 1).err(), Some(Error::NoDevice));
+    assert_eq!(start(&model, 2).err(), Some(Error::NotAta(0xEB14_0101)));
+    assert_eq!(model.get().ports[0].commands, vec![(port::ATA_IDENTIFY, 0, 0, 512)]);
+}
+
+#[test]
+fn read_write_flush() {
+    let model = controller(HbaModel::new(vec![Attached::Nothing, disk(64)]));
+    let mut port = start(&model, 1).unwrap();
+    let mut buffer = [0; 1024];
+    unsafe {
+        port.read(3, &mut buffer).unwrap();
+        assert!(buffer[..512].iter().all(|&byte| byte == 3));
+        assert!(buffer[512..].iter().all(|&byte| byte == 4));
+        port.write(10, &[0xA5; 512]).unwrap();
+        port.flush().unwrap();
+    }
+    assert!(model.get().ports[1].data()[10 * 512..11 * 512].iter().all(|&byte| byte == 0xA5));
+    assert_eq!(model.get().ports[1].commands[1..], [
+        (port::ATA_READ_DMA_EXT, 3, 2, 1024),
+        (port::ATA_WRITE_DMA_EXT, 10, 1, 512),
+        (port::ATA_FLUSH_CACHE_EXT, 0, 0, 0),
+    ]);
+}
+
+#[test]
+fn large_transfers_split() {
+    let model = controller(HbaModel::new(vec![disk(512)]));
+    let mut port = start(&model, 0).unwrap();
+    let mut buffer = vec![0; 300 * 512];
+    unsafe {port.read(5, &mut buffer)}.unwrap();
+    assert_eq!(buffer, &numbered(512, 512)[5 * 512..305 * 512]);
+    let commands: Vec<_> = model.get().ports[0].commands[1..].iter().map(|command| (command.1, command.2)).collect();
+    assert_eq!(commands, vec![(5, 128), (133, 128), (261, 44)]);
+}
+
+#[test]
+fn failed_command_recovers() {
+    let model = controller(HbaModel::new(vec![disk(64)]));
+    let mut port = start(&model, 0).unwrap();
+    model.get().ports[0].bad_sector = Some(20);
+    let mut buffer = [0; 512];
+    unsafe {
+        assert_eq!(port.read(20, &mut buffer), Err(block::Error::Io));
+        assert_eq!(port.read(21, &mut buffer), Ok(()));
+    }
+    assert!(buffer.iter().all(|&byte| byte == 21));
+}
+
+#[test]
+fn lba28_and_large_sectors() {
+    let model = controller(HbaModel::new(vec![
+        Attached::Disk { data: numbered(32, 512), sector_size: 512, lba48: false },
+        Attached::Disk { data: numbered(16, 4096), sector_size: 4096, lba48: true },
+    ]));
+    let mut small = start(&model, 0).unwrap();
+    assert!(!small.identity().lba48);
+    let mut buffer = [0; 512];
+    unsafe {small.read(7, &mut buffer)}.unwrap();
+    assert_eq!(buffer[0], 7);
+    unsafe {small.flush()}.unwrap();
+    assert_eq!(model.get().ports[0].commands[1..], [(port::ATA_READ_DMA, 7, 1, 512), (port::ATA_FLUSH_CACHE, 0, 0, 0)]);
+
+    let mut large = start(&model, 1).unwrap();
+    assert_eq!((large.sectors(), large.sector_size()), (16, 4096));
+    let mut buffer = vec![0; 8192];
+    unsafe {large.read(2, &mut buffer)}.unwrap();
+    assert_eq!((buffer[0], buffer[4096]), (2, 3));
+}
+
+#[test]
+fn partitions() {
+    let mut image = vec![0; 8192 * 512];
+    mbr(&mut image, &[(0x83, 2048, 4096)]);
+    let model = controller(HbaModel::new(vec![Attached::Disk { data: image, sector_size: 512, lba48: true }]));
+    let port = start(&model, 0).unwrap();
+    let mut disk = Disk::new("sda".to_string(), Box::new(port));
+    unsafe {disk.scan_partitions()}.unwrap();
+    assert_eq!(disk.partitions().len(), 1);
+    assert_eq!((disk.partitions()[0].start, disk.partitions()[0].sectors), (2048, 4096));
+}
@@ -0,0 +1,182 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::drivers::apic::{DeliveryMode, Destination, Divide, Error, LocalApic, TimerMode};
+use rlk_host_tests::drivers::ioapic::{IoApic, Redirection};
+use rlk_host_tests::models::apic;
+use rlk_host_tests::models::ioapic;
+
+#[test]
+fn local_enable() {
+    let mut model = apic::LocalApic::new(3);
+    let mut lapic = LocalApic::new(&mut model, false);
+    unsafe {
+        assert_eq!(lapic.id(), 3);
+        assert_eq!(lapic.max_lvt(), 7);
+        lapic.enable(0xFF, 0xFE);
+    }
+    assert_eq!(model.register(0xF0), 0x1FF);
+    assert_eq!(model.register(0x370), 0xFE);
+    assert_eq!(model.register(0x80), 0);
+    // Other local sources stay masked
+    assert_eq!(model.register(0x350) & 1 << 16, 1 << 16);
+}
+
+#[test]
+fn local_x2apic_id() {
+    let mut model = apic::LocalApic::new(0x1234).x2apic();
+    assert_eq!(unsafe {LocalApic::new(&mut model, true).id()}, 0x1234);
+}
+
+#[test]
+fn local_eoi_and_errors() {
+    let mut model = apic::LocalApic::new(0);
+    model.error(0x80);
+    {
+        let mut lapic = LocalApic::new(&mut model, false);
+        unsafe {
+            lapic.eoi();
+            assert_eq!(lapic.error_status(), 0x80);
+            // Reading clears
+            assert_eq!(lapic.error_status(), 0);
+        }
+    }
+    assert_eq!(model.eois(), 1);
+}
+
+#[test]
+fn local_timer_modes() {
+    let mut model = apic::LocalApic::new(0);
+    {
+        let mut lapic = LocalApic::new(&mut model, false);
+        unsafe {
+            lapic.timer_periodic(0xF0, Divide::By16, 1000);
+            let lvt = lapic.timer_lvt();
+            assert_eq!(lvt.vector(), 0xF0);
+            assert_eq!(lvt.mode(), TimerMode::Periodic as u32);
+            assert!(!lvt.masked());
+            assert_eq!(lapic.timer_current(), 1000);
+        }
+    }
+    assert_eq!(model.register(0x3E0), 0b0011);
+    model.tick(400);
+    {
+        let mut lapic = LocalApic::new(&mut model, false);
+        unsafe {
+            assert_eq!(lapic.timer_current(), 600);
+            lapic.timer_one_shot(None, Divide::By1, 50);
+            assert!(lapic.timer_lvt().masked());
+            lapic.timer_one_shot(Some(0xF1), Divide::By1, 50);
+            assert_eq!(lapic.timer_lvt().mode(), TimerMode::OneShot as u32);
+            assert_eq!(lapic.timer_lvt().vector(), 0xF1);
+            assert!(!lapic.timer_lvt().masked());
+            lapic.timer_tsc_deadline(0xF2);
+            assert_eq!(lapic.timer_lvt().mode(), TimerMode::TscDeadline as u32);
+            lapic.timer_stop();
+            assert!(lapic.timer_lvt().masked());
+            assert_eq!(lapic.timer_current(), 0);
+        }
+    }
+    assert_eq!(model.register(0x3E0), 0b1011);
+}
+
+#[test]
+fn local_ipi() {
+    let mut model = apic::LocalApic::new(0);
+    {
+        let mut lapic = LocalApic::new(&mut model, false);
+        unsafe {
+            lapic.send_ipi(Destination::Apic(2), DeliveryMode::Fixed, 0x40).unwrap();
+            lapic.send_ipi(Destination::Others, DeliveryMode::Init, 0).unwrap();
+            lapic.send_ipi(Destination::Others, DeliveryMode::StartUp, 0x8).unwrap();
+            // xAPIC destinations are only 8 bits, with 0xFF being broadcast
+            assert_eq!(lapic.send_ipi(Destination::Apic(0xFF), DeliveryMode::Fixed, 0x40), Err(Error::InvalidDestination(0xFF)));
+        }
+    }
+    assert_eq!(model.ipis(), &[
+        0x0200_0000_0000_4040,
+        0x0000_0000_000C_4500,
+        0x0000_0000_000C_4608,
+    ]);
+}
+
+#[test]
+fn local_x2apic_ipi() {
+    let mut model = apic::LocalApic::new(0).x2apic();
+    unsafe {LocalApic::new(&mut model, true).send_ipi(Destination::Apic(0x300), DeliveryMode::NMI, 0)}.unwrap();
+    // Destination takes the whole high half in x2APIC mode
+    assert_eq!(model.ipis(), &[0x0000_0300_0000_4400]);
+}
+
+#[test]
+fn ioapic_entries() {
+    let mut model = ioapic::IoApic::new(1, 24);
+    let ioapic = unsafe {IoApic::new(&mut model, 16)};
+    assert_eq!(ioapic.entries(), 24);
+    assert!(ioapic.handles(16));
+    assert!(ioapic.handles(39));
+    assert!(!ioapic.handles(15));
+    assert!(!ioapic.handles(40));
+}
+
+#[test]
+fn ioapic_redirection() {
+    let mut model = ioapic::IoApic::new(0, 24);
+    {
+        let mut ioapic = unsafe {IoApic::new(&mut model, 0)};
+        unsafe {
+            ioapic.set_redirection(4, Redirection::fixed(0x24, 5).trigger(true, true));
+            let entry = ioapic.redirection(4);
+            assert!(entry.masked());
+            assert_eq!(entry.vector(), 0x24);
+            assert_eq!(entry.destination(), 5);
+            assert!(entry.level_trigger());
+            assert!(entry.active_low());
+            ioapic.unmask(4);
+            assert!(!ioapic.redirection(4).masked());
+        }
+    }
+    assert_eq!(model.redirection(4), 0x0500_0000_0001_A024 & !(1 << 16));
+    // Other entries are untouched
+    assert_eq!(model.redirection(3), 1 << 16);
+}
+
+#[test]
+fn ioapic_reprogram_masks_first() {
+    let mut model = ioapic::IoApic::new(0, 24);
+    {
+        let mut ioapic = unsafe {IoApic::new(&mut model, 0)};
+        unsafe {
+            let mut entry = Redirection::fixed(0x21, 0);
+            entry.set_masked(false);
+            ioapic.set_redirection(1, entry);
+            entry.set_destination(1);
+            ioapic.set_redirection(1, entry);
+        }
+    }
+    // The entry must never be unmasked while pointing at a mix of old and new settings
+    for &(index, value) in model.history() {
+        assert_eq!(index, 1);
+        let unmasked = value & 1 << 16 == 0;
+        assert!(!unmasked || value == 0x21 || value == 0x0100_0000_0000_0021, "Unmasked partial entry {:#x}", value);
+    }
+    assert_eq!(model.redirection(1), 0x0100_0000_0000_0021);
+}
+
+#[test]
+fn ioapic_mask_all() {
+    let mut model = ioapic::IoApic::new(0, 4);
+    {
+        let mut ioapic = unsafe {IoApic::new(&mut model, 0)};
+        unsafe {
+            for gsi in 0..4 {
+                let mut entry = Redirection::fixed(0x20 + gsi as u8, 0);
+                entry.set_masked(false);
+                ioapic.set_redirection(gsi, entry);
+            }
+            ioapic.mask_all();
+        }
+    }
+    for entry in 0..4 {
+        assert_eq!(model.redirection(entry) & 1 << 16, 1 << 16);
+    }
+}
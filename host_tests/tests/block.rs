@@ -0,0 +1,244 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::block::{self, BlockDevice, Disk, Error, Kind, Op, Partition, Request};
+use rlk_host_tests::drivers::virtio;
+use rlk_host_tests::drivers::virtio::blk::{self, Blk};
+use rlk_host_tests::models::block::{gpt, mbr, GptEntry, Ram};
+use rlk_host_tests::models::virtio::{Blk as BlkModel, Common, Device, Identity};
+use rlk_host_tests::models::Shared;
+
+const MIB: usize = 1024 * 1024;
+
+/// Image with each sector filled with its own number
+fn numbered(sectors: usize) -> Vec<u8> {
+    (0..sectors).flat_map(|sector| vec![sector as u8; 512]).collect()
+}
+
+fn ram_disk(image: Vec<u8>) -> (Shared<Ram>, Disk) {
+    let ram = Shared::new(Ram::new(image, 512));
+    (ram.clone(), Disk::new("test".to_string(), Box::new(ram)))
+}
+
+#[test]
+fn request_queue() {
+    let (ram, mut disk) = ram_disk(numbered(16));
+    let read = disk.submit(Request::read(2, 1024));
+    let write = disk.submit(Request::write(15, vec![0xAA; 512]));
+    let flush = disk.submit(Request::flush());
+    let past_end = disk.submit(Request::write(15, vec![0xAA; 1024]));
+    let partial = disk.submit(Request::read(0, 100));
+    assert!(disk.complete(read).is_none());
+    unsafe {disk.run()};
+    assert_eq!(disk.complete(flush), Some((Request::flush(), Ok(()))));
+    let (request, result) = disk.complete(read).unwrap();
+    assert_eq!((request.op, result), (Op::Read, Ok(())));
+    assert_eq!(&request.buffer[..], &numbered(4)[1024..]);
+    assert_eq!(disk.complete(read), None);
+    assert_eq!(disk.complete(write).unwrap().1, Ok(()));
+    assert_eq!(disk.complete(past_end).unwrap().1, Err(Error::OutOfRange));
+    assert_eq!(disk.complete(partial).unwrap().1, Err(Error::Misaligned));
+    // Performed in order, with the bad requests never reaching the device
+    let ram = ram.get();
+    assert_eq!(ram.transfers, vec![(false, 2, 1024), (true, 15, 512)]);
+    assert_eq!(ram.flushes, 1);
+    assert_eq!(&ram.data[15 * 512..], &[0xAA; 512][..]);
+}
+
+#[test]
+fn sector_access() {
+    let (_ram, mut disk) = ram_disk(numbered(8));
+    unsafe {
+        assert_eq!(disk.read(7, 1), Ok(vec![7; 512]));
+        assert_eq!(disk.read(7, 2), Err(Error::OutOfRange));
+        assert_eq!(disk.read(!0, 1), Err(Error::OutOfRange));
+        disk.write(1, &[1; 1024]).unwrap();
+        assert_eq!(disk.read(2, 1), Ok(vec![1; 512]));
+        assert_eq!(disk.write(0, &[1; 10]), Err(Error::Misaligned));
+        assert_eq!(disk.flush(), Ok(()));
+    }
+    let ram = Shared::new(Ram::new(numbered(8), 512).read_only());
+    let mut disk = Disk::new("ro".to_string(), Box::new(ram));
+    assert_eq!(unsafe {disk.write(0, &[0; 512])}, Err(Error::ReadOnly));
+    assert_eq!(format!("{}", disk), "ro: 8 sectors of 512 bytes (4096 B) read only");
+}
+
+#[test]
+fn mbr_partitions() {
+    let mut image = vec![0; 4 * MIB];
+    mbr(&mut image, &[(0x83, 2048, 4096), (0x00, 0, 0), (0x0C, 6144, 2048), (0x83, 8000, 1000)]);
+    let (_ram, mut disk) = ram_disk(image);
+    unsafe {disk.scan_partitions().unwrap()};
+    // The last partition extends past the end of the disk
+    assert_eq!(disk.partitions(), &[
+        Partition { index: 1, start: 2048, sectors: 4096, kind: Kind::Mbr(0x83) },
+        Partition { index: 3, start: 6144, sectors: 2048, kind: Kind::Mbr(0x0C) },
+    ]);
+    assert_eq!(format!("{}", disk.partitions()[1]), "sectors 6144 to 8191 type 0x0c");
+}
+
+#[test]
+fn no_partition_table() {
+    let (_ram, mut disk) = ram_disk(vec![0; 64 * 512]);
+    unsafe {disk.scan_partitions().unwrap()};
+    assert!(disk.partitions().is_empty());
+}
+
+/// Linux filesystem data, stored mixed endian as 0FC63DAF-8483-4772-8E79-3D69D8477DE4
+const LINUX_DATA: [u8; 16] = [0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4];
+const EFI_SYSTEM: [u8; 16] = [0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B];
+
+fn gpt_image() -> Vec<u8> {
+    let mut image = vec![0; 4 * MIB];
+    gpt(&mut image, &[
+        GptEntry { type_guid: EFI_SYSTEM, guid: [1; 16], first: 2048, last: 4095, name: "EFI system partition" },
+        GptEntry { type_guid: LINUX_DATA, guid: [2; 16], first: 4096, last: 8158, name: "root ∂" },
+    ]);
+    image
+}
+
+#[test]
+fn gpt_partitions() {
+    let (_ram, mut disk) = ram_disk(gpt_image());
+    unsafe {disk.scan_partitions().unwrap()};
+    let partitions = disk.partitions();
+    assert_eq!(partitions.len(), 2);
+    assert_eq!((partitions[0].index, partitions[0].start, partitions[0].sectors), (1, 2048, 2048));
+    match partitions[1].kind {
+        Kind::Gpt { ref type_guid, ref guid, ref name } => {
+            assert_eq!(format!("{}", type_guid), "0FC63DAF-8483-4772-8E79-3D69D8477DE4");
+            assert_eq!(guid.0, [2; 16]);
+            assert_eq!(name, "root ∂");
+        },
+        ref kind => panic!("Not a GPT partition {:?}", kind),
+    }
+    assert_eq!(format!("{}", partitions[0]),
+        "sectors 2048 to 4095 type C12A7328-F81F-11D2-BA4B-00A0C93EC93B \"EFI system partition\"");
+}
+
+#[test]
+fn gpt_backup() {
+    let expected = {
+        let (_ram, mut disk) = ram_disk(gpt_image());
+        unsafe {disk.scan_partitions().unwrap()};
+        disk.partitions().to_vec()
+    };
+    // Damage the primary header
+    let mut image = gpt_image();
+    image[512 + 40] ^= 1;
+    let (_ram, mut disk) = ram_disk(image);
+    unsafe {disk.scan_partitions().unwrap()};
+    assert_eq!(disk.partitions(), &expected[..]);
+    // Damage the primary entries, and then the backup entries as well
+    let mut image = gpt_image();
+    image[2 * 512 + 40] ^= 1;
+    let last = image.len() - 2 * 512;
+    let (_ram, mut disk) = ram_disk(image.clone());
+    unsafe {disk.scan_partitions().unwrap()};
+    assert_eq!(disk.partitions(), &expected[..]);
+    image[last - 32 * 512 + 512 + 40] ^= 1;
+    let (_ram, mut disk) = ram_disk(image);
+    unsafe {disk.scan_partitions().unwrap()};
+    assert!(disk.partitions().is_empty());
+}
+
+#[test]
+fn registry() {
+    let first = block::register("ram", Box::new(Ram::new(numbered(4), 512)));
+    let second = block::register("ram", Box::new(Ram::new(gpt_image(), 512)));
+    assert_eq!((first.as_str(), second.as_str()), ("rama", "ramb"));
+    assert_eq!(block::find("ramb").unwrap().partitions().len(), 2);
+    assert!(block::disks().iter().any(|disk| disk.name() == "rama"));
+    assert_eq!(block::unregister("rama").map(|disk| disk.sectors()), Some(4));
+    assert!(block::find("rama").is_none());
+    // The first free name is reused, and never one a disk still has
+    assert_eq!(block::register("ram", Box::new(Ram::new(numbered(4), 512))), "rama");
+    assert_eq!(block::register("ram", Box::new(Ram::new(numbered(4), 512))), "ramc");
+    assert_eq!(block::register("ramb", Box::new(Ram::new(numbered(4), 512))), "ramba");
+    // Names drivers choose are only taken when free
+    assert!(block::register_as("ram0".to_string(), Box::new(Ram::new(numbered(4), 512))));
+    assert!(!block::register_as("ramc".to_string(), Box::new(Ram::new(numbered(2), 512))));
+    assert_eq!(block::find("ramc").unwrap().sectors(), 4);
+}
+
+fn virtio_device(features: u64, image: Vec<u8>) -> Device<BlkModel> {
+    let common = Shared::new(Common::new(virtio::F_VERSION_1 | features).queue(256));
+    let capacity = (image.len() / 512) as u64;
+    Device::new(common.clone(), BlkModel::new(common, image), Some(BlkModel::config(capacity, 4096)))
+}
+
+#[test]
+fn virtio_blk() {
+    let device = virtio_device(blk::F_BLK_SIZE | blk::F_FLUSH | 1 << 7, numbered(512));
+    let mut blk = unsafe {Blk::new(device.transport(), &Identity)}.unwrap();
+    assert_eq!(device.common.get().driver_features(), virtio::F_VERSION_1 | blk::F_BLK_SIZE | blk::F_FLUSH);
+    assert_eq!(device.common.get().status() & virtio::STATUS_DRIVER_OK, virtio::STATUS_DRIVER_OK);
+    assert_eq!((blk.sectors(), blk.sector_size(), blk.block_size(), blk.read_only()), (512, 512, 4096, false));
+    let mut buffer = vec![0; 1024];
+    unsafe {
+        blk.read(3, &mut buffer).unwrap();
+        assert_eq!(buffer, [vec![3; 512], vec![4; 512]].concat());
+        blk.write(100, &[0x5A; 512]).unwrap();
+        blk.flush().unwrap();
+    }
+    let model = device.device.get();
+    assert_eq!(&model.disk[100 * 512..101 * 512], &[0x5A; 512][..]);
+    assert_eq!(model.requests, vec![(0, 3, 1024), (1, 100, 512), (4, 0, 0)]);
+    assert_eq!(model.notifies, 3);
+}
+
+#[test]
+fn virtio_blk_large_transfers() {
+    let device = virtio_device(0, numbered(512));
+    let mut blk = unsafe {Blk::new(device.transport(), &Identity)}.unwrap();
+    let mut buffer = vec![0; 150 * 1024];
+    unsafe {
+        blk.read(1, &mut buffer).unwrap();
+        blk.write(0, &buffer).unwrap();
+        // No write cache, so nothing to flush
+        blk.flush().unwrap();
+    }
+    assert_eq!(&buffer[..], &numbered(301)[512..]);
+    assert_eq!(device.device.get().requests, vec![
+        (0, 1, 64 * 1024), (0, 129, 64 * 1024), (0, 257, 22 * 1024),
+        (1, 0, 64 * 1024), (1, 128, 64 * 1024), (1, 256, 22 * 1024),
+    ]);
+    assert_eq!(blk.block_size(), 512);
+}
+
+#[test]
+fn virtio_blk_errors() {
+    let device = virtio_device(blk::F_RO, numbered(64));
+    device.device.get().bad_sector = Some(10);
+    let mut blk = unsafe {Blk::new(device.transport(), &Identity)}.unwrap();
+    assert!(blk.read_only());
+    let mut buffer = vec![0; 4096];
+    unsafe {
+        assert_eq!(blk.read(8, &mut buffer), Err(Error::Io));
+        assert_eq!(blk.read(11, &mut buffer), Ok(()));
+    }
+    // Going through a disk stops writes before they reach the device
+    let mut disk = Disk::new("vda".to_string(), Box::new(blk));
+    assert_eq!(unsafe {disk.write(0, &[0; 512])}, Err(Error::ReadOnly));
+    assert_eq!(device.device.get().requests.len(), 2);
+    drop(disk);
+    // The device is reset once the driver is gone
+    assert_eq!(device.common.get().status_writes().last(), Some(&0));
+}
+
+#[test]
+fn virtio_blk_partitions() {
+    let device = virtio_device(0, gpt_image());
+    let blk = unsafe {Blk::new(device.transport(), &Identity)}.unwrap();
+    let mut disk = Disk::new("vdz".to_string(), Box::new(blk));
+    unsafe {disk.scan_partitions().unwrap()};
+    assert_eq!(disk.partitions().len(), 2);
+    assert_eq!(format!("{}", disk), "vdz: 8192 sectors of 512 bytes (4096 KiB)");
+}
+
+#[test]
+fn virtio_blk_no_config() {
+    let common = Shared::new(Common::new(virtio::F_VERSION_1).queue(256));
+    let device = Device::new(common.clone(), BlkModel::new(common, numbered(8)), None);
+    assert_eq!(unsafe {Blk::new(device.transport(), &Identity)}.err(), Some(virtio::Error::NoDeviceConfig));
+    assert_eq!(device.common.get().status() & virtio::STATUS_FAILED, virtio::STATUS_FAILED);
+}
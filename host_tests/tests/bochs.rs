@@ -0,0 +1,104 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::drivers::bochs::{self, Access, Dispi, Error, Mode};
+use rlk_host_tests::models::bochs::Dispi as DispiModel;
+
+const MB: usize = 1024 * 1024;
+
+#[test]
+fn detect() {
+    for &access in [Access::Ports, Access::Mmio].iter() {
+        let mut model = DispiModel::new(access, 0xB0C5, 256);
+        {
+            let dispi = unsafe {Dispi::new(&mut model, access, 16 * MB)}.unwrap();
+            assert_eq!(dispi.version(), 5);
+            assert_eq!(dispi.max(), Mode::new(2560, 1600, 32));
+            assert_eq!(dispi.memory(), 16 * MB);
+        }
+        // Capabilities were read without leaving them showing, or enabling the display
+        assert_eq!(model.enable(), 0);
+    }
+    let mut old = DispiModel::new(Access::Ports, 0xB0C0, 256);
+    assert!(unsafe {Dispi::new(&mut old, Access::Ports, 16 * MB)}.is_none());
+    let mut absent = DispiModel::new(Access::Mmio, 0xFFFF, 256);
+    assert!(unsafe {Dispi::new(&mut absent, Access::Mmio, 16 * MB)}.is_none());
+}
+
+#[test]
+fn memory() {
+    // The smaller of what the device reports and the BAR
+    let mut model = DispiModel::new(Access::Mmio, 0xB0C5, 128);
+    assert_eq!(unsafe {Dispi::new(&mut model, Access::Mmio, 16 * MB)}.unwrap().memory(), 8 * MB);
+    // With nothing reported, all of the BAR
+    let mut model = DispiModel::new(Access::Mmio, 0xB0C2, 0);
+    assert_eq!(unsafe {Dispi::new(&mut model, Access::Mmio, 4 * MB)}.unwrap().memory(), 4 * MB);
+}
+
+#[test]
+fn modes() {
+    let mut model = DispiModel::new(Access::Mmio, 0xB0C5, 64);
+    model.max = (1600, 1200, 32);
+    let dispi = unsafe {Dispi::new(&mut model, Access::Mmio, 16 * MB)}.unwrap();
+    let modes = dispi.modes();
+    assert_eq!(modes[0], Mode::new(640, 480, 8));
+    assert!(modes.contains(&Mode::new(1024, 768, 32)));
+    // 4MB is too little for 32 bits at 1280x1024, or anything beyond the maximum
+    assert!(modes.contains(&Mode::new(1280, 1024, 24)) && !modes.contains(&Mode::new(1280, 1024, 32)));
+    assert!(modes.iter().all(|mode| mode.size() <= 4 * MB && mode.width <= 1600 && mode.height <= 1200));
+    assert!(!dispi.supports(Mode::new(1366, 768, 16)));
+}
+
+#[test]
+fn set_mode() {
+    for &access in [Access::Ports, Access::Mmio].iter() {
+        let mut model = DispiModel::new(access, 0xB0C5, 256);
+        {
+            let mut dispi = unsafe {Dispi::new(&mut model, access, 16 * MB)}.unwrap();
+            assert_eq!(unsafe {dispi.current()}, None);
+            unsafe {dispi.set_mode(Mode::new(1024, 768, 32))}.unwrap();
+            assert_eq!(unsafe {dispi.current()}, Some(Mode::new(1024, 768, 32)));
+            unsafe {dispi.set_mode(Mode::new(800, 600, 16))}.unwrap();
+            assert_eq!(unsafe {dispi.current()}, Some(Mode::new(800, 600, 16)));
+        }
+        assert_eq!(model.modes, vec![(1024, 768, 32), (800, 600, 16)]);
+        assert_eq!(model.enable(), bochs::ENABLE_ENABLED | bochs::ENABLE_LFB);
+        let mut dispi = unsafe {Dispi::new(&mut model, access, 16 * MB)}.unwrap();
+        unsafe {dispi.disable()};
+        assert_eq!(unsafe {dispi.current()}, None);
+    }
+}
+
+#[test]
+fn bad_modes() {
+    let mut model = DispiModel::new(Access::Mmio, 0xB0C5, 256);
+    {
+        let mut dispi = unsafe {Dispi::new(&mut model, Access::Mmio, 16 * MB)}.unwrap();
+        assert_eq!(unsafe {dispi.set_mode(Mode::new(4096, 2160, 32))}, Err(Error::Unsupported));
+        assert_eq!(unsafe {dispi.set_mode(Mode::new(1024, 768, 12))}, Err(Error::Unsupported));
+        assert_eq!(unsafe {dispi.set_mode(Mode::new(2560, 1600, 32))}, Ok(()));
+    }
+    // Only the supported mode reached the device
+    assert_eq!(model.modes, vec![(2560, 1600, 32)]);
+    assert_eq!(model.writes.iter().filter(|write| write.0 == bochs::INDEX_XRES).count(), 1);
+
+    // A device that refuses a mode it claimed to support is left in VGA mode
+    let mut model = DispiModel::new(Access::Mmio, 0xB0C5, 256);
+    model.refuse_modes = true;
+    {
+        let mut dispi = unsafe {Dispi::new(&mut model, Access::Mmio, 16 * MB)}.unwrap();
+        assert_eq!(unsafe {dispi.set_mode(Mode::new(1024, 768, 32))}, Err(Error::Rejected));
+        assert_eq!(unsafe {dispi.current()}, None);
+    }
+    assert_eq!(model.enable(), 0);
+}
+
+#[test]
+fn parse_modes() {
+    assert_eq!("1024x768x16".parse(), Ok(Mode::new(1024, 768, 16)));
+    assert_eq!("1920x1080".parse(), Ok(Mode::new(1920, 1080, 32)));
+    for bad in ["1024", "1024x768x12", "1024x768x32x1", "0x768", "axb", ""].iter() {
+        assert!(bad.parse::<Mode>().is_err(), "{} parsed", bad);
+    }
+    assert_eq!(format!("{}", Mode::new(800, 600, 24)), "800x600x24");
+    assert_eq!((Mode::new(800, 600, 24).stride(), Mode::new(800, 600, 15).size()), (2400, 800 * 600 * 2));
+}
@@ -0,0 +1,154 @@
+extern crate rlk_host_tests;
+
+use std::cell::RefCell;
+
+use rlk_host_tests::bus::{Bindings, Device};
+use rlk_host_tests::decls::{Driver, Match};
+use rlk_host_tests::drivers::pci::{Ecam, Function, Registry};
+use rlk_host_tests::models::pci::{EcamWindow, Function as FunctionModel, Space};
+use rlk_host_tests::models::Shared;
+
+const HID_HPET: &str = "PNP0103";
+
+thread_local!(static CALLS: RefCell<Vec<String>> = RefCell::new(Vec::new()));
+
+fn record(call: &str, device: &Device) {
+    CALLS.with(|calls| calls.borrow_mut().push(format!("{} {}", call, device)));
+}
+
+fn calls() -> Vec<String> {
+    CALLS.with(|calls| calls.borrow_mut().drain(..).collect())
+}
+
+fn probe_virtio(device: &Device) -> bool {
+    record("virtio", device);
+    true
+}
+fn probe_nvme(device: &Device) -> bool {
+    record("nvme", device);
+    true
+}
+fn probe_e1000(device: &Device) -> bool {
+    record("e1000", device);
+    false
+}
+fn probe_any(device: &Device) -> bool {
+    record("any", device);
+    true
+}
+fn probe_hpet(device: &Device) -> bool {
+    record("hpet", device);
+    true
+}
+fn probe_keyboard(device: &Device) -> bool {
+    record("keyboard", device);
+    true
+}
+fn probe_absent(device: &Device) -> bool {
+    record("absent", device);
+    false
+}
+fn remove(device: &Device) {
+    record("remove", device);
+}
+
+static VIRTIO: Driver = Driver {
+    name: "virtio",
+    matches: &[Match::Pci { vendor: Some(0x1AF4), device: None, class: None }],
+    probe: probe_virtio,
+    remove: remove,
+};
+static NVME: Driver = Driver { name: "nvme", matches: &[Match::pci_class(0x01, 0x08)], probe: probe_nvme, remove: remove };
+static E1000: Driver = Driver { name: "e1000", matches: &[Match::pci(0x8086, 0x100E)], probe: probe_e1000, remove: remove };
+static ANY: Driver = Driver {
+    name: "any",
+    matches: &[Match::Pci { vendor: None, device: None, class: None }],
+    probe: probe_any,
+    remove: remove,
+};
+static HPET: Driver = Driver { name: "hpet", matches: &[Match::Acpi(HID_HPET)], probe: probe_hpet, remove: remove };
+static KEYBOARD: Driver = Driver { name: "keyboard", matches: &[Match::Isa], probe: probe_keyboard, remove: remove };
+static ABSENT: Driver = Driver { name: "absent", matches: &[Match::Isa], probe: probe_absent, remove: remove };
+
+/// Functions of a virtio-net, a virtio-blk, an e1000 and an NVMe controller, in that order
+fn functions() -> &'static [Function] {
+    let mut space = Space::new();
+    space.add(0, 1, 0, FunctionModel::new(0x1AF4, 0x1000, 0x02, 0x00));
+    space.add(0, 2, 0, FunctionModel::new(0x1AF4, 0x1001, 0x01, 0x00));
+    space.add(0, 3, 0, FunctionModel::new(0x8086, 0x100E, 0x02, 0x00));
+    space.add(0, 4, 0, FunctionModel::new(0x144D, 0xA808, 0x01, 0x08));
+    let mut access = Ecam::new(EcamWindow::new(Shared::new(space), 0), 0, 0, 255);
+    let mut registry = Registry::new();
+    unsafe {registry.scan(&mut access, 0)};
+    Box::leak(Box::new(registry)).functions()
+}
+
+fn drivers() -> Vec<&'static Driver> {
+    vec![&VIRTIO, &NVME, &E1000, &HPET, &KEYBOARD, &ABSENT]
+}
+
+fn bound(bindings: &Bindings) -> Vec<String> {
+    bindings.bindings().iter().map(|binding| format!("{} {}", binding.driver.name, binding.device)).collect()
+}
+
+#[test]
+fn matching() {
+    let functions = functions();
+    let (net, blk, e1000, nvme) = (Device::Pci(&functions[0]), Device::Pci(&functions[1]),
+        Device::Pci(&functions[2]), Device::Pci(&functions[3]));
+    // Fields left as `None` match anything
+    assert!(net.matches(&VIRTIO.matches[0]) && blk.matches(&VIRTIO.matches[0]));
+    assert!(!e1000.matches(&VIRTIO.matches[0]));
+    assert!(nvme.matches(&NVME.matches[0]) && !blk.matches(&NVME.matches[0]));
+    assert!(e1000.matches(&E1000.matches[0]) && !net.matches(&E1000.matches[0]));
+    assert!([net, blk, e1000, nvme].iter().all(|device| device.matches(&ANY.matches[0])));
+    // Buses never match each other, and ISA devices are never matched as there is nothing to match
+    assert!(Device::Acpi(HID_HPET).matches(&HPET.matches[0]));
+    assert!(!Device::Acpi("PNP0500").matches(&HPET.matches[0]));
+    assert!(!Device::Acpi(HID_HPET).matches(&ANY.matches[0]));
+    assert!(!Device::Isa.matches(&Match::Isa));
+    assert!(!net.matches(&Match::Isa));
+    assert!(Device::Pci(&functions[0]).is(&net) && !net.is(&blk));
+    assert!(!Device::Isa.is(&Device::Isa));
+}
+
+#[test]
+fn bind_once() {
+    let functions = functions();
+    let mut bindings = Bindings::new();
+    bindings.bind(drivers().into_iter(), functions, &[HID_HPET, "PNP0500"]);
+    assert_eq!(calls(), vec![
+        "virtio PCI 0000:00:01.0", "virtio PCI 0000:00:02.0", "nvme PCI 0000:00:04.0", "e1000 PCI 0000:00:03.0",
+        "hpet ACPI PNP0103", "keyboard ISA", "absent ISA",
+    ]);
+    assert_eq!(bound(&bindings), vec![
+        "virtio PCI 0000:00:01.0", "virtio PCI 0000:00:02.0", "nvme PCI 0000:00:04.0", "hpet ACPI PNP0103",
+        "keyboard ISA",
+    ]);
+    // Bound devices, and legacy drivers that found theirs, are skipped when binding again, while
+    // whatever was declined is offered again
+    bindings.bind(drivers().into_iter(), functions, &[HID_HPET]);
+    assert_eq!(calls(), vec!["e1000 PCI 0000:00:03.0", "absent ISA"]);
+    // A device only goes to the first driver to take it
+    bindings.bind(vec![&ANY].into_iter(), functions, &[]);
+    assert_eq!(calls(), vec!["any PCI 0000:00:03.0"]);
+    assert_eq!(bindings.bindings().len(), 6);
+}
+
+#[test]
+fn rebind() {
+    let functions = functions();
+    let mut bindings = Bindings::new();
+    bindings.bind(drivers().into_iter(), functions, &[HID_HPET]);
+    calls();
+    bindings.unbind(&Device::Pci(&functions[1]));
+    // Legacy devices are never unbound
+    bindings.unbind(&Device::Isa);
+    assert_eq!(calls(), vec!["remove PCI 0000:00:02.0"]);
+    assert!(bindings.bound(&KEYBOARD, &Device::Isa));
+    assert!(!bindings.bound(&VIRTIO, &Device::Pci(&functions[1])));
+    // So of the bound devices only the one that was removed is offered again
+    bindings.bind(drivers().into_iter(), functions, &[HID_HPET]);
+    assert_eq!(calls(), vec!["virtio PCI 0000:00:02.0", "e1000 PCI 0000:00:03.0", "absent ISA"]);
+    assert_eq!(bound(&bindings).last().unwrap(), "virtio PCI 0000:00:02.0");
+}
@@ -0,0 +1,57 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::con::V;
+use rlk_host_tests::con::color::{Color, ColorMode, Colors};
+
+const COLORS: Colors = Colors {
+    panic: Color::LightRed,
+    error: Color::Yellow,
+    info: Color::White,
+    debug: Color::Green,
+    trace: Color::LightBlue,
+};
+
+#[test]
+fn ansi() {
+    // VGA order has blue and red swapped from ANSI, and brightness becomes bold
+    assert_eq!(Color::Black.ansi(), (0, 30));
+    assert_eq!(Color::Blue.ansi(), (0, 34));
+    assert_eq!(Color::Green.ansi(), (0, 32));
+    assert_eq!(Color::Cyan.ansi(), (0, 36));
+    assert_eq!(Color::Red.ansi(), (0, 31));
+    assert_eq!(Color::Magenta.ansi(), (0, 35));
+    assert_eq!(Color::Brown.ansi(), (0, 33));
+    assert_eq!(Color::LightGray.ansi(), (0, 37));
+    assert_eq!(Color::DarkGray.ansi(), (1, 30));
+    assert_eq!(Color::LightBlue.ansi(), (1, 34));
+    assert_eq!(Color::LightRed.ansi(), (1, 31));
+    assert_eq!(Color::Yellow.ansi(), (1, 33));
+    assert_eq!(Color::White.ansi(), (1, 37));
+    assert_eq!(Color::White.vga(), 15);
+}
+
+#[test]
+fn names() {
+    assert_eq!("lightcyan".parse(), Ok(Color::LightCyan));
+    assert_eq!("pink".parse(), Ok(Color::Pink));
+    assert_eq!("Pink".parse::<Color>(), Err(()));
+    assert_eq!("".parse::<Color>(), Err(()));
+    assert_eq!("never".parse(), Ok(ColorMode::Never));
+    assert_eq!("sometimes".parse::<ColorMode>(), Err(()));
+}
+
+#[test]
+fn levels() {
+    let mut colors = COLORS;
+    assert_eq!(colors.set("debug", "darkgray"), Ok(()));
+    assert_eq!(colors.get(V::Debug), Color::DarkGray);
+    assert_eq!(colors.set("panic", "magenta"), Ok(()));
+    assert_eq!(colors.get(V::Panic), Color::Magenta);
+    // Neither an unknown level nor an unknown colour changes anything
+    assert_eq!(colors.set("verbose", "red"), Err(()));
+    assert_eq!(colors.set("info", "purple"), Err(()));
+    assert_eq!(colors.set("info", ""), Err(()));
+    assert_eq!(colors.get(V::Info), Color::White);
+    assert_eq!(colors.get(V::Error), Color::Yellow);
+    assert_eq!(colors.get(V::Trace), Color::LightBlue);
+}
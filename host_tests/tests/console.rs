@@ -0,0 +1,149 @@
+// The kernel is built by a compiler that predates `dyn`
+#![allow(bare_trait_objects)]
+
+extern crate rlk_host_tests;
+
+use rlk_host_tests::drivers::virtio;
+use rlk_host_tests::drivers::virtio::console::{self, Console, PortError};
+use rlk_host_tests::models::virtio::{Common, Console as ConsoleModel, Device, Identity, Window};
+use rlk_host_tests::models::Shared;
+
+/// Console port, a shell and a debugger, as `run.sh` sets up
+const PORTS: &[(Option<&str>, bool)] = &[(None, true), (Some("org.rlk.shell"), false), (Some("org.rlk.debug"), false)];
+
+fn virtio_device(features: u64, ports: &[(Option<&str>, bool)], max_ports: u32) -> Device<ConsoleModel> {
+    let mut common = Common::new(virtio::F_VERSION_1 | features);
+    for _ in 0..2 * max_ports + 2 {
+        common = common.queue(64);
+    }
+    let common = Shared::new(common);
+    Device::new(common.clone(), ConsoleModel::new(common, ports), Some(ConsoleModel::config(max_ports)))
+}
+
+fn multiport() -> Device<ConsoleModel> {
+    virtio_device(console::F_MULTIPORT, PORTS, 4)
+}
+
+fn start(device: &Device<ConsoleModel>) -> Console<Window> {
+    unsafe {Console::new(device.transport(), &Identity)}.unwrap()
+}
+
+#[test]
+fn ports_announced() {
+    let device = multiport();
+    let console = start(&device);
+    assert!(console.multiport());
+    assert_eq!(console.ports().map(|port| port.id()).collect::<Vec<_>>(), vec![0, 1, 2]);
+    assert_eq!(console.find("org.rlk.shell"), Some(1));
+    assert_eq!(console.find("org.rlk.debug"), Some(2));
+    assert_eq!(console.find("org.rlk.log"), None);
+    assert_eq!(console.console_port(), Some(0));
+    assert!(console.port(0).unwrap().guest_open());
+    assert!(!console.port(1).unwrap().guest_open());
+    // Queues were set up for the fourth port, but the device never added it
+    assert!(console.port(3).is_none());
+    assert_eq!(device.device.get().control, vec![
+        (0xFFFF_FFFF, console::EVENT_DEVICE_READY, 1),
+        (0, console::EVENT_PORT_READY, 1),
+        (1, console::EVENT_PORT_READY, 1),
+        (2, console::EVENT_PORT_READY, 1),
+        (0, console::EVENT_PORT_OPEN, 1),
+    ]);
+}
+
+#[test]
+fn ports_beyond_limit_refused() {
+    let ports = vec![(None, false); console::MAX_PORTS as usize + 1];
+    let device = virtio_device(console::F_MULTIPORT, &ports, console::MAX_PORTS + 1);
+    let console = start(&device);
+    assert_eq!(console.ports().count(), console::MAX_PORTS as usize);
+    assert!(device.device.get().control.contains(&(console::MAX_PORTS, console::EVENT_PORT_READY, 0)));
+}
+
+#[test]
+fn single_port() {
+    let device = virtio_device(0, &[(None, false)], 1);
+    let mut console = start(&device);
+    assert!(!console.multiport());
+    assert_eq!(console.console_port(), Some(0));
+    assert_eq!(unsafe {console.write(0, b"hello\n")}, Ok(6));
+    assert_eq!(device.device.get().written[0], b"hello\n");
+    assert!(device.device.get().control.is_empty());
+}
+
+#[test]
+fn write_and_read() {
+    let device = multiport();
+    let mut console = start(&device);
+    assert_eq!(unsafe {console.write(1, b"$ ")}, Ok(2));
+    assert_eq!(device.device.get().written[1], b"$ ");
+    assert!(device.device.get().written[0].is_empty());
+    // Larger than a buffer, so split across several
+    let long: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+    assert_eq!(unsafe {console.write(2, &long)}, Ok(long.len()));
+    assert_eq!(device.device.get().written[2], long);
+
+    unsafe {device.device.get().send(1, b"ls\n")};
+    let mut buffer = [0; 2];
+    assert_eq!(unsafe {console.read(1, &mut buffer)}, Ok(2));
+    assert_eq!(&buffer, b"ls");
+    assert_eq!(unsafe {console.read(1, &mut buffer)}, Ok(1));
+    assert_eq!(buffer[0], b'\n');
+    assert_eq!(unsafe {console.read(1, &mut buffer)}, Ok(0));
+    assert_eq!(unsafe {console.read(2, &mut buffer)}, Ok(0));
+    assert_eq!(unsafe {console.write(3, b"x")}, Err(PortError::NoPort));
+}
+
+#[test]
+fn receive_more_than_buffers() {
+    let device = multiport();
+    let mut console = start(&device);
+    let data: Vec<u8> = (0..20000).map(|i| (i * 7) as u8).collect();
+    unsafe {device.device.get().send(2, &data)};
+    let mut received = Vec::new();
+    let mut buffer = [0; 3000];
+    loop {
+        let len = unsafe {console.read(2, &mut buffer)}.unwrap();
+        if len == 0 {
+            break;
+        }
+        received.extend_from_slice(&buffer[..len]);
+    }
+    assert_eq!(received, data);
+}
+
+#[test]
+fn write_when_full() {
+    let device = multiport();
+    let mut console = start(&device);
+    device.device.get().hold_tx = true;
+    let data = vec![b'x'; 64 * 1024];
+    let written = unsafe {console.write(0, &data)}.unwrap();
+    assert!(written > 0 && written < data.len());
+    assert_eq!(unsafe {console.write(0, b"more")}, Ok(0));
+    unsafe {device.device.get().complete_tx()};
+    assert_eq!(unsafe {console.write(0, b"more")}, Ok(4));
+    assert_eq!(device.device.get().written[0].len(), written + 4);
+}
+
+#[test]
+fn host_open_and_remove() {
+    let device = multiport();
+    let mut console = start(&device);
+    assert!(!console.port(1).unwrap().host_open());
+    unsafe {device.device.get().set_host_open(1, true)};
+    unsafe {console.poll()};
+    assert!(console.port(1).unwrap().host_open());
+
+    unsafe {console.open(1)}.unwrap();
+    assert_eq!(device.device.get().control.last(), Some(&(1, console::EVENT_PORT_OPEN, 1)));
+    unsafe {console.close(1)}.unwrap();
+    assert_eq!(device.device.get().control.last(), Some(&(1, console::EVENT_PORT_OPEN, 0)));
+
+    unsafe {device.device.get().remove(1)};
+    unsafe {console.poll()};
+    assert!(console.port(1).is_none());
+    assert_eq!(console.find("org.rlk.shell"), None);
+    assert_eq!(unsafe {console.write(1, b"x")}, Err(PortError::NoPort));
+    assert_eq!(unsafe {console.open(1)}, Err(PortError::NoPort));
+}
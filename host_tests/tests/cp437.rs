@@ -0,0 +1,50 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::con::cp437::{encode, from_char, REPLACEMENT};
+
+#[test]
+fn ascii() {
+    for b in 0x20..0x7F {
+        assert_eq!(from_char(b as u8 as char), Some(b as u8));
+    }
+    // Control characters are left for the console to escape
+    assert_eq!(from_char('\n'), None);
+    assert_eq!(from_char('\x1B'), None);
+    assert_eq!(from_char('\x7F'), None);
+}
+
+#[test]
+fn glyphs() {
+    let text: Vec<u8> = "Grüße, Ça coûte 5£ ±½°".chars().map(encode).collect();
+    assert_eq!(text, b"Gr\x81\xE1e, \x80a co\x96te 5\x9C \xF1\xAB\xF8".to_vec());
+    let boxed: Vec<u8> = "╔═╗║┼╝▓█".chars().map(encode).collect();
+    assert_eq!(boxed, vec![0xC9, 0xCD, 0xBB, 0xBA, 0xC5, 0xBC, 0xB2, 0xDB]);
+    assert_eq!(encode('☺'), 0x01);
+    assert_eq!(encode('▼'), 0x1F);
+    assert_eq!(encode('⌂'), 0x7F);
+    assert_eq!(encode('\u{a0}'), 0xFF);
+    // Lookalikes share a glyph
+    assert_eq!(encode('β'), encode('ß'));
+    assert_eq!(encode('μ'), encode('µ'));
+}
+
+#[test]
+fn replacement() {
+    assert_eq!(from_char('🍳'), None);
+    assert_eq!(encode('🍳'), REPLACEMENT);
+    assert_eq!(encode('中'), REPLACEMENT);
+    assert_eq!(encode('€'), REPLACEMENT);
+}
+
+#[test]
+fn round_trip() {
+    // Every glyph from 0x01 up is reached by exactly one of the characters it is listed as
+    let reached: Vec<u8> = (0..0x110000).filter_map(std::char::from_u32).filter_map(from_char).collect();
+    for glyph in 1..=0xFF {
+        let count = reached.iter().filter(|&&g| g == glyph).count();
+        match glyph {
+            0xE1 | 0xE6 | 0xEA => assert_eq!(count, 2, "{:#x}", glyph),
+            _ => assert_eq!(count, 1, "{:#x}", glyph),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+// The kernel is built by a compiler that predates `dyn`
+#![allow(bare_trait_objects)]
+
+extern crate rlk_host_tests;
+
+use std::time::Duration;
+
+use rlk_host_tests::drivers::e1000::{self, E1000, Error, Model};
+use rlk_host_tests::models::e1000::E1000 as E1000Model;
+use rlk_host_tests::models::virtio::Identity;
+use rlk_host_tests::models::Shared;
+use rlk_host_tests::net::{arp, device, ethernet, Config, Interface, Ipv4Address, MacAddress, NetDevice};
+use rlk_host_tests::net::ethernet::{Frame, TYPE_ARP};
+
+const MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+const PEER_MAC: MacAddress = MacAddress([0x52, 0x55, 0x0A, 0x00, 0x02, 0x02]);
+
+fn start(model: E1000Model) -> (Shared<E1000Model>, E1000<Shared<E1000Model>>) {
+    let kind = model.model();
+    let model = Shared::new(model);
+    let device = unsafe {E1000::new(model.clone(), kind, &Identity)}.unwrap();
+    (model, device)
+}
+
+fn frame(len: usize, fill: u8) -> Vec<u8> {
+    let mut frame = ethernet::build(MacAddress(MAC), PEER_MAC, 0x88B5, &vec![fill; len - 14]);
+    frame.truncate(len);
+    frame
+}
+
+#[test]
+fn init() {
+    for &model in [Model::I8254x, Model::I82574].iter() {
+        let shared = Shared::new(E1000Model::new(model, MAC));
+        let device = unsafe {E1000::new(shared.clone(), model, &Identity)}.unwrap();
+        assert_eq!(device.mac(), MacAddress(MAC));
+        assert_eq!(device.name(), if model == Model::I8254x { "e1000" } else { "e1000e" });
+        let shared = shared.get();
+        assert_eq!(shared.mac(), Some(MAC));
+        assert_eq!(shared.resets, 1);
+        assert_eq!(shared.ims(), 0);
+        assert_eq!(shared.rctl(), e1000::RCTL_EN | e1000::RCTL_BAM | e1000::RCTL_SECRC);
+        assert!(!shared.promiscuous());
+    }
+}
+
+#[test]
+fn mac_without_eeprom() {
+    let (model, device) = start(E1000Model::new(Model::I8254x, [0; 6]).without_eeprom(Some(MAC)));
+    assert_eq!(device.mac(), MacAddress(MAC));
+    assert_eq!(model.get().mac(), Some(MAC));
+
+    let model = Shared::new(E1000Model::new(Model::I8254x, [0; 6]).without_eeprom(None));
+    assert_eq!(unsafe {E1000::new(model, Model::I8254x, &Identity)}.err(), Some(Error::NoMac));
+}
+
+#[test]
+fn transmit_and_receive() {
+    let (model, mut device) = start(E1000Model::new(Model::I8254x, MAC));
+    assert!(unsafe {device.receive()}.is_none());
+    // Enough frames each way for both rings to wrap several times
+    for i in 0..100 {
+        let sent = frame(60 + i, i as u8);
+        unsafe {device.transmit(&sent)}.unwrap();
+        assert_eq!(model.get().sent.last(), Some(&sent));
+        let received = frame(1514 - i, !i as u8);
+        assert!(model.get().deliver(&received));
+        assert_eq!(unsafe {device.receive()}, Some(received));
+    }
+    assert_eq!(model.get().sent.len(), 100);
+    assert_eq!(unsafe {device.transmit(&frame(1515, 0))}, Err(device::Error::TooLarge));
+}
+
+#[test]
+fn transmit_ring_full() {
+    let (model, mut device) = start(E1000Model::new(Model::I8254x, MAC));
+    model.get().hold_tx = true;
+    // One descriptor is always left empty, to tell a full ring from an empty one
+    for i in 0..e1000::TX_DESCRIPTORS - 1 {
+        unsafe {device.transmit(&frame(60, i as u8))}.unwrap();
+    }
+    assert_eq!(unsafe {device.transmit(&frame(60, 0xFF))}, Err(device::Error::Busy));
+    assert!(model.get().sent.is_empty());
+    model.get().run_tx();
+    assert_eq!(model.get().sent.len(), e1000::TX_DESCRIPTORS - 1);
+    unsafe {device.transmit(&frame(60, 0xFF))}.unwrap();
+}
+
+#[test]
+fn receive_ring_full() {
+    let (model, mut device) = start(E1000Model::new(Model::I8254x, MAC));
+    for i in 0..e1000::RX_DESCRIPTORS - 1 {
+        assert!(model.get().deliver(&frame(64, i as u8)));
+    }
+    assert!(!model.get().deliver(&frame(64, 0xFF)));
+    assert_eq!(model.get().overruns, 1);
+    assert_eq!(unsafe {device.interrupt()} & e1000::ICR_RXO, e1000::ICR_RXO);
+    for i in 0..e1000::RX_DESCRIPTORS - 1 {
+        assert_eq!(unsafe {device.receive()}, Some(frame(64, i as u8)));
+    }
+    assert!(unsafe {device.receive()}.is_none());
+    // Every descriptor was handed back as it was emptied
+    assert!(model.get().deliver(&frame(64, 0xFF)));
+}
+
+#[test]
+fn link_and_interrupts() {
+    let (model, mut device) = start(E1000Model::new(Model::I8254x, MAC));
+    assert!(unsafe {device.link_up()});
+    unsafe {device.enable_interrupts()};
+    let enabled = model.get().ims();
+    assert_eq!(enabled & (e1000::ICR_RXT0 | e1000::ICR_LSC | e1000::ICR_TXDW), e1000::ICR_RXT0 | e1000::ICR_LSC | e1000::ICR_TXDW);
+    assert!(!model.get().asserted());
+    model.get().set_link(false);
+    assert!(model.get().asserted());
+    assert!(!unsafe {device.link_up()});
+    assert_eq!(unsafe {device.interrupt()}, e1000::ICR_LSC);
+    // Reading the causes acknowledged them, which drops the line
+    assert!(!model.get().asserted());
+    assert_eq!(unsafe {device.interrupt()}, 0);
+    unsafe {device.disable_interrupts()};
+    assert_eq!(model.get().ims(), 0);
+}
+
+#[test]
+fn promiscuous() {
+    let (model, mut device) = start(E1000Model::new(Model::I82574, MAC));
+    assert!(unsafe {device.set_promiscuous(true)});
+    assert!(model.get().promiscuous());
+    assert!(model.get().rctl() & e1000::RCTL_EN != 0);
+    assert!(unsafe {device.set_promiscuous(false)});
+    assert!(!model.get().promiscuous());
+}
+
+#[test]
+fn reset_on_drop() {
+    let (model, device) = start(E1000Model::new(Model::I8254x, MAC));
+    drop(device);
+    assert_eq!(model.get().resets, 2);
+}
+
+#[test]
+fn answers_arp() {
+    let (model, device) = start(E1000Model::new(Model::I8254x, MAC));
+    let address = Ipv4Address([10, 0, 2, 15]);
+    let mut interface = Interface::new("eth0".to_string(), Box::new(device));
+    interface.set_config(Some(Config { address: address, netmask: Ipv4Address([255, 255, 255, 0]), gateway: None, dns: None }));
+    let request = arp::Packet::request(PEER_MAC, Ipv4Address([10, 0, 2, 2]), address);
+    model.get().deliver(&ethernet::build(MacAddress([0xFF; 6]), PEER_MAC, TYPE_ARP, &request.to_bytes()));
+    unsafe {interface.poll(Duration::from_millis(0))};
+    let sent = model.get().sent.clone();
+    assert_eq!(sent.len(), 1);
+    let frame = Frame::parse(&sent[0]).unwrap();
+    assert_eq!((frame.dst, frame.src, frame.ethertype), (PEER_MAC, MacAddress(MAC), TYPE_ARP));
+    assert_eq!(arp::Packet::parse(frame.payload), Some(request.reply(MacAddress(MAC))));
+}
@@ -0,0 +1,109 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::drivers::framebuffer::{self, Color, Format, Framebuffer};
+
+const RED: Color = Color::new(0xFF, 0x00, 0x00);
+const TEAL: Color = Color::new(0x00, 0x80, 0x80);
+
+/// Framebuffer over `memory`, with rows padded out to `stride`
+fn framebuffer(memory: &mut Vec<u8>, width: usize, height: usize, stride: usize, format: Format) -> Framebuffer {
+    memory.clear();
+    memory.resize(stride * height, 0);
+    unsafe {Framebuffer::new(memory.as_mut_ptr(), 0xFD00_0000, width, height, stride, format)}
+}
+
+#[test]
+fn formats() {
+    assert_eq!(Format::from_bpp(24), Some(Format::Rgb888));
+    assert_eq!(Format::from_bpp(12), None);
+    assert_eq!(Format::Xrgb8888.pack(TEAL), 0x008080);
+    assert_eq!(Format::Rgb888.pack(RED), 0xFF0000);
+    assert_eq!(Format::Rgb565.pack(Color::new(0xFF, 0xFF, 0xFF)), 0xFFFF);
+    assert_eq!(Format::Rgb565.pack(Color::new(0x00, 0xFF, 0x00)), 0x07E0);
+    assert_eq!(Format::Rgb555.pack(RED), 0x7C00);
+    // Indexed formats use the nearest colour of the VGA palette
+    assert_eq!(Format::Indexed8.pack(RED), 4);
+    assert_eq!(Format::Indexed8.pack(Color::new(0xFF, 0x60, 0x50)), 12);
+    assert_eq!(Format::Indexed8.pack(TEAL), 3);
+    assert_eq!(Format::Indexed8.pack(Color::new(0x10, 0x10, 0x10)), 0);
+}
+
+#[test]
+fn pixels() {
+    let mut memory = Vec::new();
+    for &format in [Format::Indexed8, Format::Rgb555, Format::Rgb565, Format::Rgb888, Format::Xrgb8888].iter() {
+        let mut fb = framebuffer(&mut memory, 10, 4, 64, format);
+        fb.put_pixel(9, 3, RED);
+        fb.put_pixel(0, 0, TEAL);
+        // Off the edge is clipped rather than written beyond the framebuffer
+        fb.put_pixel(10, 0, RED);
+        fb.put_pixel(0, 4, RED);
+        assert_eq!(fb.pixel(9, 3), Some(format.pack(RED)));
+        assert_eq!(fb.pixel(0, 0), Some(format.pack(TEAL)));
+        assert_eq!(fb.pixel(10, 0), None);
+        let bytes = format.bytes_per_pixel();
+        let written = memory.iter().enumerate().filter(|&(_, &byte)| byte != 0).map(|(i, _)| i).max();
+        assert!(written.unwrap() < 3 * 64 + 10 * bytes);
+    }
+}
+
+#[test]
+fn fill_and_clear() {
+    let mut memory = Vec::new();
+    let mut fb = framebuffer(&mut memory, 8, 8, 32, Format::Xrgb8888);
+    fb.fill_rect(6, 6, 100, 100, RED);
+    for y in 0..8 {
+        for x in 0..8 {
+            let expected = if x >= 6 && y >= 6 { 0xFF0000 } else { 0 };
+            assert_eq!(fb.pixel(x, y), Some(expected), "at {},{}", x, y);
+        }
+    }
+    fb.clear(TEAL);
+    assert!((0..8).all(|y| (0..8).all(|x| fb.pixel(x, y) == Some(0x008080))));
+    assert_eq!(format!("{}", fb), "8x8x32 at 0xfd000000");
+}
+
+#[test]
+fn scroll() {
+    let mut memory = Vec::new();
+    let mut fb = framebuffer(&mut memory, 4, 6, 16, Format::Xrgb8888);
+    for y in 0..6 {
+        fb.fill_rect(0, y, 4, 1, Color::new(y as u8, 0, 0));
+    }
+    // Up by 2, as a console scrolls
+    fb.copy_rows(0, 2, 4);
+    let rows: Vec<_> = (0..6).map(|y| fb.pixel(3, y).unwrap() >> 16).collect();
+    assert_eq!(rows, vec![2, 3, 4, 5, 4, 5]);
+    // Down by 1, overlapping, with the row that would leave the framebuffer dropped
+    fb.copy_rows(1, 0, 6);
+    let rows: Vec<_> = (0..6).map(|y| fb.pixel(0, y).unwrap() >> 16).collect();
+    assert_eq!(rows, vec![2, 2, 3, 4, 5, 4]);
+}
+
+#[test]
+fn glyphs() {
+    let mut memory = Vec::new();
+    let mut fb = framebuffer(&mut memory, 12, 3, 64, Format::Xrgb8888);
+    let white = Color::new(0xFF, 0xFF, 0xFF);
+    fb.draw_glyph(0, 0, &[0x81, 0x7E], white, RED);
+    let row = |fb: &Framebuffer, y| (0..8).map(|x| fb.pixel(x, y) == Some(0xFFFFFF)).collect::<Vec<_>>();
+    assert_eq!(row(&fb, 0), vec![true, false, false, false, false, false, false, true]);
+    assert_eq!(row(&fb, 1), vec![false, true, true, true, true, true, true, false]);
+    assert_eq!(fb.pixel(1, 0), Some(0xFF0000));
+    // Clipped on the right and at the bottom
+    fb.draw_glyph(8, 2, &[0xFF, 0xFF], white, RED);
+    assert!((8..12).all(|x| fb.pixel(x, 2) == Some(0xFFFFFF)));
+    assert_eq!(fb.pixel(8, 1), Some(0));
+}
+
+#[test]
+fn primary() {
+    let mut memory = Vec::new();
+    assert!(framebuffer::primary().is_none());
+    let fb = framebuffer(&mut memory, 4, 4, 16, Format::Xrgb8888);
+    assert!(framebuffer::set_primary(Some(fb)).is_none());
+    framebuffer::primary().unwrap().put_pixel(1, 1, RED);
+    let previous = framebuffer::set_primary(None).unwrap();
+    assert_eq!(previous.pixel(1, 1), Some(0xFF0000));
+    assert!(framebuffer::primary().is_none());
+}
@@ -0,0 +1,77 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::drivers::fw_cfg::{self, Error, File, FwCfg};
+use rlk_host_tests::models::fw_cfg::FwCfg as Model;
+use rlk_host_tests::models::virtio::{Identity, Region};
+use rlk_host_tests::models::Shared;
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+}
+
+fn model(dma: bool) -> Shared<Model> {
+    Shared::new(Model::new(dma)
+        .file("etc/boot-menu-wait", vec![0x10, 0x27])
+        .file("opt/rlk/config", b"verbose=1\n".to_vec())
+        .file("opt/rlk/payload", payload(10000)))
+}
+
+#[test]
+fn absent() {
+    assert!(unsafe {FwCfg::new(Region::new(16))}.is_none());
+}
+
+#[test]
+fn directory() {
+    let device = model(false);
+    let fw_cfg = unsafe {FwCfg::new(device.clone())}.unwrap();
+    assert_eq!(fw_cfg.id() & fw_cfg::ID_DMA, 0);
+    assert_eq!(fw_cfg.files(), &[
+        File { name: "etc/boot-menu-wait".to_string(), size: 2, key: 0x20 },
+        File { name: "opt/rlk/config".to_string(), size: 10, key: 0x21 },
+        File { name: "opt/rlk/payload".to_string(), size: 10000, key: 0x22 },
+    ][..]);
+    assert_eq!(fw_cfg.find("opt/rlk/config").map(|file| file.key), Some(0x21));
+    assert_eq!(fw_cfg.find("opt/rlk"), None);
+}
+
+#[test]
+fn port_reads() {
+    let device = model(true);
+    let mut fw_cfg = unsafe {FwCfg::new(device.clone())}.unwrap();
+    // Without DMA enabled the ports are used, even though the device has DMA
+    assert_eq!(unsafe {fw_cfg.read_file("opt/rlk/config")}, Ok(b"verbose=1\n".to_vec()));
+    assert_eq!(unsafe {fw_cfg.read_file("opt/rlk/payload")}, Ok(payload(10000)));
+    assert_eq!(unsafe {fw_cfg.read_file("opt/missing")}, Err(Error::NotFound));
+    assert!(device.get().dma_requests.is_empty());
+}
+
+#[test]
+fn dma_reads() {
+    let device = model(true);
+    let mut fw_cfg = unsafe {FwCfg::new(device.clone())}.unwrap();
+    assert!(fw_cfg.enable_dma(&Identity));
+    let port_reads = device.get().port_reads;
+    assert_eq!(unsafe {fw_cfg.read_file("opt/rlk/payload")}, Ok(payload(10000)));
+    assert_eq!(device.get().port_reads, port_reads, "Port used alongside DMA");
+    // Split into chunks, only the first of which selects the file
+    assert_eq!(device.get().dma_requests, vec![(0x22 << 16 | 0xA, 4096), (0x2, 4096), (0x2, 1808)]);
+    assert_eq!(unsafe {fw_cfg.read(0x20, 2)}, Ok(vec![0x10, 0x27]));
+}
+
+#[test]
+fn dma_unsupported() {
+    let device = model(false);
+    let mut fw_cfg = unsafe {FwCfg::new(device.clone())}.unwrap();
+    assert!(!fw_cfg.enable_dma(&Identity));
+    assert_eq!(unsafe {fw_cfg.read_file("opt/rlk/config")}, Ok(b"verbose=1\n".to_vec()));
+}
+
+#[test]
+fn dma_error() {
+    let device = model(true);
+    let mut fw_cfg = unsafe {FwCfg::new(device.clone())}.unwrap();
+    fw_cfg.enable_dma(&Identity);
+    device.get().dma_error = true;
+    assert_eq!(unsafe {fw_cfg.read_file("opt/rlk/config")}, Err(Error::Dma));
+}
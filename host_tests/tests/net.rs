@@ -0,0 +1,386 @@
+// The kernel is built by a compiler that predates `dyn`
+#![allow(bare_trait_objects)]
+
+extern crate rlk_host_tests;
+
+use std::time::Duration;
+
+use rlk_host_tests::drivers::virtio;
+use rlk_host_tests::drivers::virtio::net::{self as virtio_net, Net};
+use rlk_host_tests::models::net::Wire;
+use rlk_host_tests::models::virtio::{Common, Device, Identity, Net as NetModel};
+use rlk_host_tests::models::Shared;
+use rlk_host_tests::net::{arp, device, dhcp, ethernet, icmp, ipv4};
+use rlk_host_tests::net::{Config, Error, Interface, Ipv4Address, MacAddress, NetDevice, Received};
+use rlk_host_tests::net::ethernet::{Frame, TYPE_ARP, TYPE_IPV4};
+use rlk_host_tests::net::icmp::Echo;
+use rlk_host_tests::net::udp::Datagram;
+
+const MAC: MacAddress = MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+const PEER_MAC: MacAddress = MacAddress([0x52, 0x55, 0x0A, 0x00, 0x02, 0x02]);
+const ADDRESS: Ipv4Address = Ipv4Address([10, 0, 2, 15]);
+const GATEWAY: Ipv4Address = Ipv4Address([10, 0, 2, 2]);
+const REMOTE: Ipv4Address = Ipv4Address([192, 168, 1, 1]);
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+fn interface(config: Option<Config>) -> (Shared<Wire>, Interface) {
+    let wire = Shared::new(Wire::new(MAC));
+    let mut interface = Interface::new("eth0".to_string(), Box::new(wire.clone()));
+    interface.set_config(config);
+    (wire, interface)
+}
+
+fn qemu_config() -> Option<Config> {
+    Some(Config { address: ADDRESS, netmask: Ipv4Address([255, 255, 255, 0]), gateway: Some(GATEWAY), dns: None })
+}
+
+/// Frame from the peer to us
+fn from_peer(ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    ethernet::build(MAC, PEER_MAC, ethertype, payload)
+}
+
+fn ipv4_from_peer(src: Ipv4Address, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    from_peer(TYPE_IPV4, &ipv4::build(src, ADDRESS, protocol, 1, payload))
+}
+
+/// Tell the interface where the gateway is, as if it had asked
+fn resolve_gateway(wire: &Shared<Wire>, interface: &mut Interface, now: Duration) {
+    let reply = arp::Packet::request(MAC, ADDRESS, GATEWAY).reply(PEER_MAC);
+    wire.get().incoming.push_back(from_peer(TYPE_ARP, &reply.to_bytes()));
+    unsafe {interface.poll(now)};
+}
+
+/// The IPv4 packet in a sent frame, checking it went to `dst_mac`
+fn sent_ipv4(frame: &[u8], dst_mac: MacAddress) -> ipv4::Packet<'_> {
+    let frame = Frame::parse(frame).unwrap();
+    assert_eq!((frame.dst, frame.src, frame.ethertype), (dst_mac, MAC, TYPE_IPV4));
+    ipv4::Packet::parse(frame.payload).expect("Bad IPv4 packet")
+}
+
+#[test]
+fn addresses() {
+    assert_eq!("10.0.2.15".parse::<Ipv4Address>(), Ok(ADDRESS));
+    for bad in ["10.0.2", "10.0.2.15.1", "10.0.2.256", "10..2.15", ""].iter() {
+        assert!(bad.parse::<Ipv4Address>().is_err(), "{} parsed", bad);
+    }
+    assert_eq!(format!("{}", ADDRESS), "10.0.2.15");
+    assert_eq!(format!("{}", MAC), "52:54:00:12:34:56");
+    let config = qemu_config().unwrap();
+    assert_eq!(format!("{}", config), "10.0.2.15/24 gateway 10.0.2.2");
+    assert!(config.on_link(GATEWAY) && !config.on_link(REMOTE));
+    assert_eq!(config.broadcast(), Ipv4Address([10, 0, 2, 255]));
+}
+
+#[test]
+fn checksums() {
+    // Commonly used worked example, whose checksum is 0xB861
+    let header = [0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xC0, 0xA8, 0x00, 0x01, 0xC0, 0xA8, 0x00, 0xC7];
+    assert_eq!(ipv4::checksum(&header, 0), 0xB861);
+    let packet = ipv4::build(ADDRESS, GATEWAY, ipv4::PROTOCOL_UDP, 7, &[1, 2, 3]);
+    assert_eq!(ipv4::checksum(&packet[..ipv4::HEADER_SIZE], 0), 0);
+    let mut corrupt = packet.clone();
+    corrupt[8] ^= 1;
+    assert_eq!(ipv4::Packet::parse(&corrupt), None);
+    let datagram = Datagram { src_port: 1234, dst_port: 53, payload: b"odd" }.to_bytes(ADDRESS, GATEWAY);
+    assert_eq!(Datagram::parse(&datagram, ADDRESS, GATEWAY).unwrap().payload, b"odd");
+    // The pseudo header is part of the checksum
+    assert_eq!(Datagram::parse(&datagram, ADDRESS, REMOTE), None);
+}
+
+#[test]
+fn arp_reply() {
+    let (wire, mut interface) = interface(qemu_config());
+    let other = arp::Packet::request(PEER_MAC, GATEWAY, Ipv4Address([10, 0, 2, 3]));
+    let ours = arp::Packet::request(PEER_MAC, GATEWAY, ADDRESS);
+    for request in [other, ours].iter() {
+        wire.get().incoming.push_back(ethernet::build(MacAddress::BROADCAST, PEER_MAC, TYPE_ARP, &request.to_bytes()));
+    }
+    unsafe {interface.poll(ms(0))};
+    let sent = wire.get().take_sent();
+    assert_eq!(sent.len(), 1, "Reply to a request for another address");
+    let frame = Frame::parse(&sent[0]).unwrap();
+    assert_eq!((frame.dst, frame.ethertype, frame.payload.len()), (PEER_MAC, TYPE_ARP, 46));
+    assert_eq!(arp::Packet::parse(frame.payload), Some(ours.reply(MAC)));
+    assert_eq!(interface.stats().dropped, 1);
+}
+
+#[test]
+fn unconfigured_ignores_arp() {
+    let (wire, mut interface) = interface(None);
+    let request = arp::Packet::request(PEER_MAC, GATEWAY, ADDRESS);
+    wire.get().incoming.push_back(from_peer(TYPE_ARP, &request.to_bytes()));
+    unsafe {interface.poll(ms(0))};
+    assert!(wire.get().sent.is_empty());
+    assert_eq!(unsafe {interface.send_ping(ms(0), GATEWAY, 1, 1, &[])}, Err(Error::NotConfigured));
+}
+
+#[test]
+fn ping_reply() {
+    let (wire, mut interface) = interface(qemu_config());
+    let request = Echo { kind: icmp::TYPE_ECHO_REQUEST, id: 0x1234, seq: 7, data: b"abcdefgh" }.to_bytes();
+    wire.get().incoming.push_back(ipv4_from_peer(GATEWAY, ipv4::PROTOCOL_ICMP, &request));
+    unsafe {interface.poll(ms(0))};
+    // The sender has to be resolved before replying
+    let sent = wire.get().take_sent();
+    assert_eq!(sent.len(), 1);
+    let frame = Frame::parse(&sent[0]).unwrap();
+    assert_eq!((frame.dst, frame.ethertype), (MacAddress::BROADCAST, TYPE_ARP));
+    assert_eq!(arp::Packet::parse(frame.payload), Some(arp::Packet::request(MAC, ADDRESS, GATEWAY)));
+    resolve_gateway(&wire, &mut interface, ms(10));
+    let sent = wire.get().take_sent();
+    assert_eq!(sent.len(), 1);
+    let packet = sent_ipv4(&sent[0], PEER_MAC);
+    assert_eq!((packet.src, packet.dst, packet.protocol), (ADDRESS, GATEWAY, ipv4::PROTOCOL_ICMP));
+    assert_eq!(Echo::parse(packet.payload), Some(Echo { kind: icmp::TYPE_ECHO_REPLY, id: 0x1234, seq: 7, data: b"abcdefgh" }));
+    // Now resolved, so replies go straight out
+    wire.get().incoming.push_back(ipv4_from_peer(GATEWAY, ipv4::PROTOCOL_ICMP, &request));
+    unsafe {interface.poll(ms(20))};
+    assert_eq!(wire.get().take_sent().len(), 1);
+}
+
+#[test]
+fn ping_through_gateway() {
+    let (wire, mut interface) = interface(qemu_config());
+    resolve_gateway(&wire, &mut interface, ms(0));
+    unsafe {interface.send_ping(ms(0), REMOTE, 5, 1, b"ping")}.unwrap();
+    let sent = wire.get().take_sent();
+    let packet = sent_ipv4(&sent[0], PEER_MAC);
+    assert_eq!(packet.dst, REMOTE);
+    let echo = Echo::parse(packet.payload).unwrap();
+    assert_eq!((echo.kind, echo.id, echo.seq, echo.data), (icmp::TYPE_ECHO_REQUEST, 5, 1, &b"ping"[..]));
+    assert!(!interface.take_echo_reply(REMOTE, 5, 1));
+    let reply = echo.reply().to_bytes();
+    wire.get().incoming.push_back(ipv4_from_peer(REMOTE, ipv4::PROTOCOL_ICMP, &reply));
+    unsafe {interface.poll(ms(5))};
+    assert!(!interface.take_echo_reply(REMOTE, 5, 2));
+    assert!(interface.take_echo_reply(REMOTE, 5, 1));
+    assert!(!interface.take_echo_reply(REMOTE, 5, 1), "Reply taken twice");
+    let mut no_gateway = qemu_config().unwrap();
+    no_gateway.gateway = None;
+    interface.set_config(Some(no_gateway));
+    assert_eq!(unsafe {interface.send_ping(ms(10), REMOTE, 5, 2, &[])}, Err(Error::NoRoute));
+}
+
+#[test]
+fn unresolved_packets_time_out() {
+    let (wire, mut interface) = interface(qemu_config());
+    unsafe {interface.send_ping(ms(0), GATEWAY, 1, 1, &[])}.unwrap();
+    unsafe {interface.send_ping(ms(0), GATEWAY, 1, 2, &[])}.unwrap();
+    assert_eq!(wire.get().take_sent().len(), 1, "Second ARP request before the retry time");
+    unsafe {interface.poll(ms(500))};
+    assert!(wire.get().take_sent().is_empty());
+    unsafe {interface.poll(ms(1000))};
+    assert_eq!(wire.get().take_sent().len(), 1);
+    unsafe {interface.poll(ms(3000))};
+    resolve_gateway(&wire, &mut interface, ms(3100));
+    assert!(wire.get().take_sent().is_empty(), "Packets sent after timing out");
+}
+
+#[test]
+fn udp() {
+    let (wire, mut interface) = interface(qemu_config());
+    resolve_gateway(&wire, &mut interface, ms(0));
+    assert_eq!(interface.bind_udp(0), Ok(49152));
+    assert_eq!(interface.bind_udp(0), Ok(49153));
+    assert_eq!(interface.bind_udp(7), Ok(7));
+    assert_eq!(interface.bind_udp(7), Err(Error::AddressInUse));
+    assert_eq!(interface.recv_udp(8), Err(Error::NotBound));
+    let datagram = |port, data: &[u8]| Datagram { src_port: 5000, dst_port: port, payload: data }.to_bytes(GATEWAY, ADDRESS);
+    for &(port, data) in [(7, &b"echo"[..]), (9, &b"discard"[..]), (7, &b"again"[..])].iter() {
+        wire.get().incoming.push_back(ipv4_from_peer(GATEWAY, ipv4::PROTOCOL_UDP, &datagram(port, data)));
+    }
+    let mut corrupt = datagram(7, b"corrupt");
+    corrupt[8] ^= 0xFF;
+    wire.get().incoming.push_back(ipv4_from_peer(GATEWAY, ipv4::PROTOCOL_UDP, &corrupt));
+    unsafe {interface.poll(ms(1))};
+    assert_eq!(interface.stats().dropped, 2);
+    assert_eq!(interface.recv_udp(7), Ok(Some(Received { src: GATEWAY, src_port: 5000, data: b"echo".to_vec() })));
+    assert_eq!(interface.recv_udp(7).unwrap().unwrap().data, b"again");
+    assert_eq!(interface.recv_udp(7), Ok(None));
+    unsafe {interface.send_udp(ms(2), 7, GATEWAY, 5000, b"reply")}.unwrap();
+    let sent = wire.get().take_sent();
+    let packet = sent_ipv4(&sent[0], PEER_MAC);
+    assert_eq!(packet.protocol, ipv4::PROTOCOL_UDP);
+    let reply = Datagram::parse(packet.payload, ADDRESS, GATEWAY).unwrap();
+    assert_eq!(reply, Datagram { src_port: 7, dst_port: 5000, payload: b"reply" });
+    interface.unbind_udp(7);
+    assert_eq!(interface.recv_udp(7), Err(Error::NotBound));
+    assert_eq!(unsafe {interface.send_udp(ms(3), 7, GATEWAY, 5000, &[0; 1473])}, Err(Error::TooLarge));
+}
+
+#[test]
+fn broadcast_bypasses_arp() {
+    let (wire, mut interface) = interface(qemu_config());
+    unsafe {interface.send_udp(ms(0), 1000, Ipv4Address([10, 0, 2, 255]), 1000, b"all")}.unwrap();
+    let sent = wire.get().take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent_ipv4(&sent[0], MacAddress::BROADCAST).dst, Ipv4Address([10, 0, 2, 255]));
+}
+
+#[test]
+fn device_errors() {
+    let (wire, mut interface) = interface(qemu_config());
+    wire.get().busy = true;
+    let broadcast = Ipv4Address::BROADCAST;
+    assert_eq!(unsafe {interface.send_udp(ms(0), 1, broadcast, 1, &[])}, Err(Error::Device(device::Error::Busy)));
+    assert_eq!(interface.stats().sent, 0);
+}
+
+/// Reply from a DHCP server to the client's `request`
+fn dhcp_reply(request: &[u8], kind: u8, offered: Ipv4Address) -> Vec<u8> {
+    let mut reply = vec![0; 236];
+    reply[0] = 2;
+    reply[1] = 1;
+    reply[2] = 6;
+    reply[4..8].copy_from_slice(&request[4..8]);
+    reply[16..20].copy_from_slice(&offered.0);
+    reply[28..34].copy_from_slice(&request[28..34]);
+    reply.extend_from_slice(&[99, 130, 83, 99, 53, 1, kind, 54, 4, 10, 0, 2, 2, 1, 4, 255, 255, 255, 0,
+        3, 4, 10, 0, 2, 2, 6, 4, 10, 0, 2, 3, 51, 4, 0, 1, 0x51, 0x80, 255]);
+    reply
+}
+
+/// The DHCP message in a sent frame, with its type
+fn sent_dhcp(frame: &[u8]) -> (u8, Vec<u8>) {
+    let packet = sent_ipv4(frame, MacAddress::BROADCAST);
+    assert_eq!((packet.src, packet.dst), (Ipv4Address::UNSPECIFIED, Ipv4Address::BROADCAST));
+    let datagram = Datagram::parse(packet.payload, packet.src, packet.dst).unwrap();
+    assert_eq!((datagram.src_port, datagram.dst_port), (dhcp::CLIENT_PORT, dhcp::SERVER_PORT));
+    let message = datagram.payload;
+    assert_eq!(&message[28..34], &MAC.0);
+    assert_eq!(&message[236..242], &[99, 130, 83, 99, 53, 1]);
+    (message[242], message.to_vec())
+}
+
+fn deliver_dhcp(wire: &Shared<Wire>, message: &[u8]) {
+    let datagram = Datagram { src_port: dhcp::SERVER_PORT, dst_port: dhcp::CLIENT_PORT, payload: message };
+    let packet = ipv4::build(GATEWAY, Ipv4Address::BROADCAST, ipv4::PROTOCOL_UDP, 1,
+        &datagram.to_bytes(GATEWAY, Ipv4Address::BROADCAST));
+    wire.get().incoming.push_back(ethernet::build(MacAddress::BROADCAST, PEER_MAC, TYPE_IPV4, &packet));
+}
+
+#[test]
+fn dhcp() {
+    let (wire, mut interface) = interface(None);
+    unsafe {interface.start_dhcp(ms(0), 0xABCD)};
+    assert!(interface.dhcp_pending());
+    let (kind, discover) = sent_dhcp(&wire.get().take_sent()[0]);
+    assert_eq!((kind, &discover[4..8]), (1, &[0, 0, 0xAB, 0xCD][..]));
+    // Unanswered messages are retried
+    unsafe {interface.poll(ms(1000))};
+    assert!(wire.get().sent.is_empty());
+    unsafe {interface.poll(ms(2000))};
+    assert_eq!(sent_dhcp(&wire.get().take_sent()[0]).0, 1);
+    // Replies to other clients are ignored
+    let mut other = dhcp_reply(&discover, 2, ADDRESS);
+    other[7] ^= 1;
+    deliver_dhcp(&wire, &other);
+    unsafe {interface.poll(ms(2100))};
+    assert!(wire.get().sent.is_empty());
+    deliver_dhcp(&wire, &dhcp_reply(&discover, 2, ADDRESS));
+    unsafe {interface.poll(ms(2200))};
+    let (kind, request) = sent_dhcp(&wire.get().take_sent()[0]);
+    assert_eq!(kind, 3);
+    assert!(request.windows(6).any(|option| option == [50, 4, 10, 0, 2, 15]), "Request without the offered address");
+    assert!(request.windows(6).any(|option| option == [54, 4, 10, 0, 2, 2]), "Request without the server");
+    assert_eq!(interface.config(), None);
+    deliver_dhcp(&wire, &dhcp_reply(&request, 5, ADDRESS));
+    unsafe {interface.poll(ms(2300))};
+    assert!(!interface.dhcp_pending());
+    assert_eq!(interface.config(), Some(Config { dns: Some(Ipv4Address([10, 0, 2, 3])), ..qemu_config().unwrap() }));
+    unsafe {interface.poll(ms(10000))};
+    assert!(wire.get().sent.is_empty(), "DHCP continued once bound");
+}
+
+#[test]
+fn dhcp_nak() {
+    let (wire, mut interface) = interface(None);
+    unsafe {interface.start_dhcp(ms(0), 1)};
+    let (_, discover) = sent_dhcp(&wire.get().take_sent()[0]);
+    deliver_dhcp(&wire, &dhcp_reply(&discover, 2, ADDRESS));
+    unsafe {interface.poll(ms(1))};
+    let (_, request) = sent_dhcp(&wire.get().take_sent()[0]);
+    deliver_dhcp(&wire, &dhcp_reply(&request, 6, Ipv4Address::UNSPECIFIED));
+    unsafe {interface.poll(ms(2))};
+    assert_eq!(sent_dhcp(&wire.get().take_sent()[0]).0, 1, "Did not start again after a NAK");
+    assert!(interface.dhcp_pending());
+}
+
+fn virtio_device(features: u64, status: u16) -> Device<NetModel> {
+    let common = Shared::new(Common::new(virtio::F_VERSION_1 | features).queue(256).queue(8));
+    Device::new(common.clone(), NetModel::new(common), Some(NetModel::config(MAC.0, status)))
+}
+
+#[test]
+fn virtio_net() {
+    let device = virtio_device(virtio_net::F_MAC | virtio_net::F_STATUS, 1);
+    let mut net = unsafe {Net::new(device.transport(), &Identity)}.unwrap();
+    assert_eq!(device.common.get().driver_features(), virtio::F_VERSION_1 | virtio_net::F_MAC | virtio_net::F_STATUS);
+    assert_eq!(device.common.get().status() & virtio::STATUS_DRIVER_OK, virtio::STATUS_DRIVER_OK);
+    assert_eq!(net.mac(), MAC);
+    assert!(unsafe {net.link_up()});
+    // Every receive buffer is offered
+    assert_eq!(unsafe {device.device.get().rx_available()}, 32);
+    assert_eq!(unsafe {net.receive()}, None);
+    let frames: Vec<Vec<u8>> = (0..40u8).map(|i| vec![i; 60 + i as usize]).collect();
+    for frame in frames.iter() {
+        unsafe {device.device.get().deliver(frame)};
+    }
+    // More frames than buffers, so the rest arrive as buffers are given back
+    for frame in frames.iter() {
+        assert_eq!(unsafe {net.receive()}.as_ref(), Some(frame));
+    }
+    assert_eq!(unsafe {net.receive()}, None);
+    assert_eq!(unsafe {device.device.get().rx_available()}, 32);
+    unsafe {net.transmit(&[0xAA; 1514])}.unwrap();
+    assert_eq!(unsafe {net.transmit(&[0xAA; 1515])}, Err(device::Error::TooLarge));
+    assert_eq!(device.device.get().sent, vec![vec![0xAA; 1514]]);
+}
+
+#[test]
+fn virtio_net_tx_completion() {
+    let device = virtio_device(0, 0);
+    device.device.get().hold_tx = true;
+    let mut net = unsafe {Net::new(device.transport(), &Identity)}.unwrap();
+    // Without the features there is a made up address and the link is assumed up
+    assert_eq!(net.mac(), MacAddress([0x02, 0, 0, 0, 0, 1]));
+    assert!(unsafe {net.link_up()});
+    // The transmit queue is only 8 long on this device
+    for i in 0..8 {
+        unsafe {net.transmit(&[i; 60])}.unwrap();
+    }
+    assert_eq!(unsafe {net.transmit(&[8; 60])}, Err(device::Error::Busy));
+    unsafe {device.device.get().complete_tx()};
+    for i in 8..16 {
+        unsafe {net.transmit(&[i; 60])}.unwrap();
+    }
+    let sent = device.device.get().sent.clone();
+    assert_eq!(sent, (0..16).map(|i| vec![i; 60]).collect::<Vec<_>>());
+}
+
+#[test]
+fn virtio_net_link_down() {
+    let device = virtio_device(virtio_net::F_STATUS, 0);
+    let mut net = unsafe {Net::new(device.transport(), &Identity)}.unwrap();
+    assert!(!unsafe {net.link_up()});
+    drop(net);
+    assert_eq!(device.common.get().status(), 0, "Not reset when dropped");
+}
+
+#[test]
+fn stack_over_virtio_net() {
+    let device = virtio_device(virtio_net::F_MAC, 0);
+    let net = unsafe {Net::new(device.transport(), &Identity)}.unwrap();
+    let mut interface = Interface::new("eth0".to_string(), Box::new(net));
+    interface.set_config(qemu_config());
+    let request = arp::Packet::request(PEER_MAC, GATEWAY, ADDRESS);
+    unsafe {device.device.get().deliver(&from_peer(TYPE_ARP, &request.to_bytes()))};
+    unsafe {interface.poll(ms(0))};
+    let sent = device.device.get().sent.clone();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(arp::Packet::parse(Frame::parse(&sent[0]).unwrap().payload), Some(request.reply(MAC)));
+    assert_eq!(format!("{}", interface), "eth0: virtio-net 52:54:00:12:34:56 10.0.2.15/24 gateway 10.0.2.2");
+}
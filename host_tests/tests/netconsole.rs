@@ -0,0 +1,105 @@
+extern crate rlk_host_tests;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use rlk_host_tests::con::V;
+use rlk_host_tests::con::net::{ConNet, Sink, FLUSH_INTERVAL_MS, MAX_BUFFER, PAYLOAD};
+
+/// Datagrams sent, and how many more can be before the network is busy
+struct Wire {
+    sent: Vec<Vec<u8>>,
+    room: usize,
+}
+
+#[derive(Clone)]
+struct Socket(Rc<RefCell<Wire>>);
+
+impl Socket {
+    fn new(room: usize) -> Socket {
+        Socket(Rc::new(RefCell::new(Wire { sent: Vec::new(), room: room })))
+    }
+    fn set_room(&self, room: usize) {
+        self.0.borrow_mut().room = room;
+    }
+    fn take(&self) -> Vec<Vec<u8>> {
+        self.0.borrow_mut().sent.drain(..).collect()
+    }
+}
+
+impl Sink for Socket {
+    fn try_send(&mut self, data: &[u8]) -> bool {
+        let mut wire = self.0.borrow_mut();
+        if wire.room == 0 {
+            return false;
+        }
+        wire.room -= 1;
+        wire.sent.push(data.to_vec());
+        true
+    }
+}
+
+fn line(con: &mut ConNet<Socket>, v: V, s: &str) {
+    con.prepare(v);
+    con.print(s);
+    con.end();
+}
+
+#[test]
+fn batching() {
+    let socket = Socket::new(usize::MAX);
+    let mut con = ConNet::new(socket.clone());
+    line(&mut con, V::Info, "booting");
+    assert!(socket.take().is_empty());
+    // Errors go straight out, along with what was waiting
+    line(&mut con, V::Error, "failed");
+    assert_eq!(socket.take(), vec![b"Info: booting\nError: failed\n".to_vec()]);
+    // Otherwise lines wait until the interval has passed
+    let mut lines = 0;
+    while socket.take().is_empty() {
+        line(&mut con, V::Debug, "x");
+        lines += 1;
+    }
+    assert!(lines > 1 && lines <= FLUSH_INTERVAL_MS as usize);
+}
+
+#[test]
+fn long_lines() {
+    let socket = Socket::new(usize::MAX);
+    let mut con = ConNet::new(socket.clone());
+    let long = "y".repeat(PAYLOAD);
+    line(&mut con, V::Info, "short");
+    line(&mut con, V::Error, &long);
+    let sent = socket.take();
+    // Split at the last newline that fits, then wherever it has to be
+    assert_eq!(sent[0], b"Info: short\n".to_vec());
+    assert_eq!(sent[1].len(), PAYLOAD);
+    assert_eq!(sent.concat().len(), "Info: short\nError: \n".len() + PAYLOAD);
+}
+
+#[test]
+fn dropped() {
+    let socket = Socket::new(0);
+    let mut con = ConNet::new(socket.clone());
+    con.print(&"z".repeat(MAX_BUFFER + 100));
+    con.send();
+    con.print(&"z".repeat(50));
+    assert!(socket.take().is_empty());
+    // What was buffered before the loss goes out first
+    socket.set_room(1);
+    con.send();
+    assert_eq!(socket.take(), vec![vec![b'z'; PAYLOAD]]);
+    con.print("after\n");
+    socket.set_room((MAX_BUFFER - PAYLOAD + PAYLOAD - 1) / PAYLOAD);
+    con.send();
+    let sent = socket.take();
+    assert_eq!(sent.concat(), vec![b'z'; MAX_BUFFER - PAYLOAD]);
+    assert!(sent.iter().all(|datagram| datagram.len() <= PAYLOAD));
+    // The note is only forgotten once it has been sent, and comes before what was printed later
+    socket.set_room(usize::MAX);
+    con.send();
+    assert_eq!(socket.take(), vec![b"Error: netconsole dropped 150 bytes\n".to_vec(), b"after\n".to_vec()]);
+    // Nothing more was lost
+    line(&mut con, V::Error, "done");
+    assert_eq!(socket.take(), vec![b"Error: done\n".to_vec()]);
+}
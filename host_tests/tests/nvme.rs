@@ -0,0 +1,207 @@
+extern crate rlk_host_tests;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use rlk_host_tests::block::{self, BlockDevice, Disk};
+use rlk_host_tests::drivers::nvme::{controller, Controller, Error, Namespace};
+use rlk_host_tests::models::nvme::{self as model, Controller as ControllerModel, Namespace as NamespaceModel};
+use rlk_host_tests::models::virtio::Identity;
+use rlk_host_tests::models::block::mbr;
+use rlk_host_tests::models::Shared;
+
+/// The controller as its namespaces share it
+type Handle = Rc<RefCell<Controller<Shared<ControllerModel>>>>;
+
+/// Image with each block filled with its own number
+fn numbered(blocks: usize, block_size: usize) -> Vec<u8> {
+    (0..blocks).flat_map(|block| vec![block as u8; block_size]).collect()
+}
+
+fn start(model: ControllerModel, vector: Option<u16>) -> (Shared<ControllerModel>, Handle) {
+    let model = Shared::new(model);
+    let controller = unsafe {Controller::new(model.clone(), &Identity, vector)}.unwrap();
+    (model, Rc::new(RefCell::new(controller)))
+}
+
+fn namespace(controller: &Handle, id: u32) -> Result<Namespace<Shared<ControllerModel>>, Error> {
+    unsafe {Namespace::new(controller.clone(), id)}
+}
+
+#[test]
+fn enable_and_identify() {
+    let (model, controller) = start(ControllerModel::new(vec![NamespaceModel::new(numbered(8, 512), 512)]), None);
+    {
+        let mut controller = controller.borrow_mut();
+        assert_eq!(unsafe {controller.version()}, (1, 4));
+        let identity = controller.identity();
+        assert_eq!((identity.vendor, identity.model.as_str(), identity.serial.as_str()), (0x1B36, "RLK Model NVMe", "RLK0001"));
+        assert_eq!((identity.firmware.as_str(), identity.namespaces), ("1.0", 1));
+    }
+    let model = model.get();
+    assert_eq!(model.cc(), 4 << 20 | 6 << 16 | 1);
+    assert_eq!(model.intms, 0xFFFF_FFFF);
+    assert_eq!(model.has_queue(1), (true, true));
+    let opcodes: Vec<_> = model.admin.iter().map(|command| command.0).collect();
+    assert_eq!(opcodes, vec![controller::ADMIN_IDENTIFY, controller::ADMIN_SET_FEATURES,
+        controller::ADMIN_CREATE_CQ, controller::ADMIN_CREATE_SQ]);
+    // Polled, so the I/O completion queue was created without interrupts
+    assert_eq!(model.admin[2], (controller::ADMIN_CREATE_CQ, 63 << 16 | 1, 1));
+}
+
+#[test]
+fn unsupported_controller() {
+    let model = Shared::new(ControllerModel::new(vec![]).large_pages());
+    assert_eq!(unsafe {Controller::new(model, &Identity, None)}.err(), Some(Error::Unsupported));
+}
+
+#[test]
+fn namespaces() {
+    let mut inactive = NamespaceModel::new(numbered(4, 512), 512);
+    inactive.active = false;
+    let mut metadata = NamespaceModel::new(numbered(4, 512), 512);
+    metadata.metadata = 8;
+    let (_model, controller) = start(ControllerModel::new(vec![
+        NamespaceModel::new(numbered(16, 512), 512),
+        inactive,
+        NamespaceModel::new(numbered(4, 4096), 4096),
+        metadata,
+    ]), None);
+    assert_eq!(unsafe {controller.borrow_mut().active_namespaces()}, Ok(vec![1, 3, 4]));
+    let first = namespace(&controller, 1).unwrap();
+    assert_eq!((first.id(), first.sectors(), first.sector_size(), first.read_only()), (1, 16, 512, false));
+    let large = namespace(&controller, 3).unwrap();
+    assert_eq!((large.sectors(), large.sector_size()), (4, 4096));
+    assert_eq!(namespace(&controller, 2).err(), Some(Error::Status(model::STATUS_INVALID_NAMESPACE)));
+    assert_eq!(namespace(&controller, 4).err(), Some(Error::Unsupported));
+}
+
+#[test]
+fn read_write_flush() {
+    let (model, controller) = start(ControllerModel::new(vec![
+        NamespaceModel::new(numbered(64, 512), 512),
+        NamespaceModel::new(numbered(64, 512), 512),
+    ]), None);
+    let mut first = namespace(&controller, 1).unwrap();
+    let mut second = namespace(&controller, 2).unwrap();
+    let mut buffer = [0; 1024];
+    unsafe {
+        first.read(3, &mut buffer).unwrap();
+        assert!(buffer[..512].iter().all(|&byte| byte == 3));
+        assert!(buffer[512..].iter().all(|&byte| byte == 4));
+        second.write(10, &[0xA5; 512]).unwrap();
+        second.flush().unwrap();
+    }
+    let model = model.get();
+    assert!(model.namespaces[1].data[10 * 512..11 * 512].iter().all(|&byte| byte == 0xA5));
+    assert!(model.namespaces[0].data[10 * 512..11 * 512].iter().all(|&byte| byte == 10));
+    assert_eq!(model.io, vec![
+        (controller::IO_READ, 1, 3, 2, 1024),
+        (controller::IO_WRITE, 2, 10, 1, 512),
+        (controller::IO_FLUSH, 2, 0, 0, 0),
+    ]);
+}
+
+#[test]
+fn large_transfers() {
+    // Up to the bounce buffer in one command, described by the PRP list
+    let (model, controller) = start(ControllerModel::new(vec![NamespaceModel::new(numbered(512, 512), 512)]), None);
+    let mut ns = namespace(&controller, 1).unwrap();
+    let mut buffer = vec![0; 300 * 512];
+    unsafe {ns.read(5, &mut buffer)}.unwrap();
+    assert_eq!(buffer, &numbered(512, 512)[5 * 512..305 * 512]);
+    let commands: Vec<_> = model.get().io.iter().map(|command| (command.2, command.3)).collect();
+    assert_eq!(commands, vec![(5, 256), (261, 44)]);
+
+    // And split to fit the controller's limit
+    let (model, controller) = start(ControllerModel::new(vec![NamespaceModel::new(numbered(128, 512), 512)]).mdts(2), None);
+    assert_eq!(controller.borrow().max_transfer(), 16384);
+    let mut ns = namespace(&controller, 1).unwrap();
+    let data: Vec<u8> = (0..80 * 512).map(|i| (i * 7) as u8).collect();
+    unsafe {ns.write(1, &data)}.unwrap();
+    assert_eq!(&model.get().namespaces[0].data[512..81 * 512], &data[..]);
+    let commands: Vec<_> = model.get().io.iter().map(|command| (command.2, command.3)).collect();
+    assert_eq!(commands, vec![(1, 32), (33, 32), (65, 16)]);
+}
+
+#[test]
+fn queues_wrap() {
+    // Small queues and wide doorbells, so the phase flips many times
+    let model = ControllerModel::new(vec![NamespaceModel::new(numbered(64, 512), 512)]).max_entries(4).doorbell_stride(2);
+    let (model, controller) = start(model, None);
+    let mut ns = namespace(&controller, 1).unwrap();
+    let mut buffer = [0; 512];
+    for sector in 0..40 {
+        unsafe {ns.read(sector, &mut buffer)}.unwrap();
+        assert!(buffer.iter().all(|&byte| byte == sector as u8));
+    }
+    assert_eq!(model.get().io.len(), 40);
+}
+
+#[test]
+fn failed_command() {
+    let (model, controller) = start(ControllerModel::new(vec![NamespaceModel::new(numbered(64, 512), 512)]), None);
+    let mut ns = namespace(&controller, 1).unwrap();
+    model.get().bad_lba = Some(20);
+    let mut buffer = [0; 512];
+    unsafe {
+        assert_eq!(ns.read(20, &mut buffer), Err(block::Error::Io));
+        assert_eq!(controller.borrow_mut().read_blocks(1, 20, 512, &mut buffer),
+            Err(Error::Status(model::STATUS_UNRECOVERED_READ)));
+        assert_eq!(ns.read(21, &mut buffer), Ok(()));
+    }
+    assert!(buffer.iter().all(|&byte| byte == 21));
+}
+
+#[test]
+fn msix_completion() {
+    let (model, controller) = start(ControllerModel::new(vec![NamespaceModel::new(numbered(8, 512), 512)]), Some(1));
+    assert_eq!(model.get().intms, 0);
+    assert_eq!(model.get().admin[2], (controller::ADMIN_CREATE_CQ, 63 << 16 | 1, 1 << 16 | 0b11));
+    let mut ns = namespace(&controller, 1).unwrap();
+    model.get().interrupts.clear();
+    let mut buffer = [0; 512];
+    unsafe {ns.read(2, &mut buffer)}.unwrap();
+    assert_eq!(model.get().interrupts, vec![1]);
+    // The command already took its completion, so the handler finds nothing left
+    assert!(!unsafe {controller.borrow_mut().interrupt()});
+}
+
+#[test]
+fn late_completion() {
+    let (model, controller) = start(ControllerModel::new(vec![NamespaceModel::new(numbered(8, 512), 512)]), Some(1));
+    let mut ns = namespace(&controller, 1).unwrap();
+    model.get().interrupts.clear();
+    model.get().stalled = true;
+    let mut buffer = [0; 512];
+    assert_eq!(unsafe {controller.borrow_mut().read_blocks(1, 3, 512, &mut buffer)}, Err(Error::CommandTimeout));
+    // The completion turns up with nobody waiting for it, so only the handler takes it off
+    unsafe {model.get().resume()};
+    assert_eq!(model.get().interrupts, vec![1]);
+    assert!(unsafe {controller.borrow_mut().interrupt()});
+    assert!(!unsafe {controller.borrow_mut().interrupt()});
+    unsafe {ns.read(4, &mut buffer)}.unwrap();
+    assert!(buffer.iter().all(|&byte| byte == 4));
+}
+
+#[test]
+fn shutdown_with_last_namespace() {
+    let (model, controller) = start(ControllerModel::new(vec![NamespaceModel::new(numbered(8, 512), 512)]), None);
+    let ns = namespace(&controller, 1).unwrap();
+    drop(controller);
+    assert_eq!(model.get().shutdowns, 0);
+    drop(ns);
+    assert_eq!(model.get().shutdowns, 1);
+    assert_eq!(model.get().cc() & 1, 0);
+}
+
+#[test]
+fn partitions() {
+    let mut image = vec![0; 8192 * 512];
+    mbr(&mut image, &[(0x83, 2048, 4096)]);
+    let (_model, controller) = start(ControllerModel::new(vec![NamespaceModel::new(image, 512)]), None);
+    let mut disk = Disk::new("nvme0n1".to_string(), Box::new(namespace(&controller, 1).unwrap()));
+    unsafe {disk.scan_partitions()}.unwrap();
+    assert_eq!(disk.partitions().len(), 1);
+    assert_eq!((disk.partitions()[0].start, disk.partitions()[0].sectors), (2048, 4096));
+}
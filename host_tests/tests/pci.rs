@@ -0,0 +1,216 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::drivers::pci::{self, msix, Address, Bar, Capability, Command, ConfigAccess, Ecam, Legacy, MsiX, Registry};
+use rlk_host_tests::models::pci::{EcamWindow, Function, LegacyPorts, MsixTable, Space};
+use rlk_host_tests::models::{Access, Shared};
+
+fn address(bus: u8, device: u8, function: u8) -> Address {
+    Address::new(0, bus, device, function)
+}
+
+fn scan(space: Space) -> (Shared<Space>, Registry) {
+    let space = Shared::new(space);
+    let mut access = Ecam::new(EcamWindow::new(space.clone(), 0), 0, 0, 255);
+    let mut registry = Registry::new();
+    unsafe {registry.scan(&mut access, 0)};
+    (space, registry)
+}
+
+fn addresses(registry: &Registry) -> Vec<String> {
+    registry.functions().iter().map(|function| format!("{}", function.address)).collect()
+}
+
+#[test]
+fn legacy_access() {
+    let mut space = Space::new();
+    space.add(1, 2, 3, Function::new(0x1234, 0x5678, 0x02, 0x00));
+    let mut ports = LegacyPorts::new(Shared::new(space));
+    {
+        let mut legacy = Legacy::new(&mut ports);
+        unsafe {
+            assert_eq!(legacy.read(address(1, 2, 3), 0x00), 0x5678_1234);
+            assert_eq!(legacy.read(address(1, 2, 4), 0x00), !0);
+            // Beyond the legacy configuration space, or another segment, is unreachable
+            assert_eq!(legacy.read(address(1, 2, 3), 0x100), !0);
+            assert_eq!(legacy.read(Address::new(1, 1, 2, 3), 0x00), !0);
+        }
+    }
+    assert_eq!(&ports.accesses()[..2], &[
+        Access::Write(0, 0x8000_0000 | 1 << 16 | 2 << 11 | 3 << 8),
+        Access::Read(4, 0x5678_1234),
+    ]);
+    assert_eq!(ports.accesses().len(), 4);
+}
+
+#[test]
+fn ecam_access() {
+    let mut space = Space::new();
+    space.add(0x11, 2, 3, Function::new(0x1234, 0x5678, 0x02, 0x00));
+    let mut window = EcamWindow::new(Shared::new(space), 0x10);
+    {
+        let mut ecam = Ecam::new(&mut window, 0, 0x10, 0x1F);
+        unsafe {
+            assert_eq!(ecam.read(address(0x11, 2, 3), 0x08), 0x0200_0001);
+            // Outside of the buses covered
+            assert_eq!(ecam.read(address(0x20, 2, 3), 0x00), !0);
+            assert_eq!(ecam.read(address(0x0F, 2, 3), 0x00), !0);
+        }
+    }
+    assert_eq!(window.accesses(), &[Access::Read(1 << 20 | 2 << 15 | 3 << 12 | 0x08, 0x0200_0001)]);
+}
+
+#[test]
+fn config_partial_writes() {
+    let mut space = Space::new();
+    space.add(0, 1, 0, Function::new(0x1234, 0x5678, 0x02, 0x00).interrupt(1, 11));
+    let space = Shared::new(space);
+    let mut access = Ecam::new(EcamWindow::new(space.clone(), 0), 0, 0, 255);
+    let mut config = pci::Config::new(&mut access, address(0, 1, 0));
+    unsafe {
+        assert_eq!(config.read_u16(pci::DEVICE_ID), 0x5678);
+        assert_eq!(config.read_u8(pci::CLASS), 0x02);
+        config.write_u8(pci::INTERRUPT_LINE, 5);
+        assert_eq!(config.read_u8(pci::INTERRUPT_LINE), 5);
+        assert_eq!(config.read_u8(pci::INTERRUPT_PIN), 1);
+        config.enable(Command::MEMORY | Command::BUS_MASTER);
+        config.enable(Command::IO);
+        assert_eq!(config.command(), Command::IO | Command::MEMORY | Command::BUS_MASTER);
+    }
+}
+
+#[test]
+fn scan_topology() {
+    let mut space = Space::new();
+    space.add(0, 0, 0, Function::new(0x8086, 0x29C0, 0x06, 0x00))
+        .add(0, 1, 0, Function::new(0x8086, 0x2918, 0x06, 0x01).multi_function())
+        .add(0, 1, 2, Function::new(0x8086, 0x2922, 0x01, 0x06))
+        .add(0, 2, 0, Function::new(0x1B36, 0x000C, 0x06, 0x04).bridge(1))
+        .add(1, 0, 0, Function::new(0x1AF4, 0x1041, 0x02, 0x00).subsystem(0x1AF4, 0x1100))
+        .add(0, 3, 0, Function::new(0x1234, 0x1111, 0x03, 0x00))
+        // A single function device aliasing function 0 must not be found twice
+        .add(0, 3, 1, Function::new(0x1234, 0x1111, 0x03, 0x00));
+    let (_space, registry) = scan(space);
+    assert_eq!(addresses(&registry), vec![
+        "0000:00:00.0", "0000:00:01.0", "0000:00:01.2", "0000:00:02.0", "0000:00:03.0", "0000:01:00.0",
+    ]);
+    let bridge = registry.find(address(0, 2, 0)).unwrap();
+    assert_eq!(bridge.header_type, pci::HEADER_BRIDGE);
+    assert_eq!(bridge.secondary_bus, Some(1));
+    let net = registry.find(address(1, 0, 0)).unwrap();
+    assert_eq!((net.subsystem_vendor, net.subsystem), (0x1AF4, 0x1100));
+    assert_eq!(format!("{}", net), "0000:01:00.0 1af4:1041 [020000] Ethernet controller");
+    let storage: Vec<_> = registry.matching(|function| function.class == 0x01).map(|function| function.address).collect();
+    assert_eq!(storage, vec![address(0, 1, 2)]);
+}
+
+#[test]
+fn scan_multiple_host_bridges() {
+    let mut space = Space::new();
+    space.add(0, 0, 0, Function::new(0x8086, 0x1237, 0x06, 0x00).multi_function())
+        .add(0, 0, 1, Function::new(0x8086, 0x1237, 0x06, 0x00))
+        .add(1, 4, 0, Function::new(0x1234, 0x1111, 0x03, 0x00));
+    let (_space, registry) = scan(space);
+    assert_eq!(addresses(&registry), vec!["0000:00:00.0", "0000:00:00.1", "0000:01:04.0"]);
+}
+
+#[test]
+fn scan_bridge_loop() {
+    let mut space = Space::new();
+    space.add(0, 0, 0, Function::new(0x8086, 0x29C0, 0x06, 0x00))
+        .add(0, 1, 0, Function::new(0x1B36, 0x000C, 0x06, 0x04).bridge(1))
+        .add(1, 0, 0, Function::new(0x1B36, 0x000C, 0x06, 0x04).bridge(0));
+    let (_space, registry) = scan(space);
+    assert_eq!(addresses(&registry), vec!["0000:00:00.0", "0000:00:01.0", "0000:01:00.0"]);
+}
+
+#[test]
+fn bars() {
+    let mut space = Space::new();
+    space.add(0, 4, 0, Function::new(0x1AF4, 0x1000, 0x02, 0x00)
+        .command(0x0007)
+        .io_bar(0, 0xC040, 0x20)
+        .memory_bar(1, 0xFEBD_1000, 0x1000)
+        .memory_bar_64(4, 0x8_0000_0000, 0x4000, true));
+    let (space, registry) = scan(space);
+    let function = registry.find(address(0, 4, 0)).unwrap();
+    assert_eq!(function.bars, [
+        Some(Bar::Io { port: 0xC040, size: 0x20 }),
+        Some(Bar::Memory { address: 0xFEBD_1000, size: 0x1000, prefetchable: false, wide: false }),
+        None,
+        None,
+        Some(Bar::Memory { address: 0x8_0000_0000, size: 0x4000, prefetchable: true, wide: true }),
+        None,
+    ]);
+    assert_eq!(format!("{}", function.bars[4].unwrap()), "mem 0x800000000 [16K] 64-bit prefetchable");
+    assert_eq!(format!("{}", function.bars[0].unwrap()), "io 0xc040 [32]");
+    // Everything was put back as it was found
+    let space = space.get();
+    let model = space.function(0, 4, 0);
+    assert_eq!(model.read(0x04) & 0xFFFF, 0x0007);
+    assert_eq!(model.read(0x10), 0xC041);
+    assert_eq!(model.read(0x14), 0xFEBD_1000);
+    assert_eq!(model.read(0x20), 0x0000_000C);
+    assert_eq!(model.read(0x24), 0x8);
+    // Decoding was disabled before any BAR was written
+    let first_bar = space.writes().iter().position(|write| write.3 == 0x10).unwrap();
+    let disable = space.writes().iter().position(|write| write.3 == 0x04).unwrap();
+    assert!(disable < first_bar);
+    assert_eq!(space.writes()[disable].4 & 0x3, 0);
+}
+
+#[test]
+fn capabilities() {
+    let mut space = Space::new();
+    space.add(0, 4, 0, Function::new(0x1AF4, 0x1041, 0x02, 0x00)
+        .capability(0x98, pci::capability::MSIX, 0x84)
+        .capability(0x84, pci::capability::VENDOR_SPECIFIC, 0x70)
+        .capability(0x70, pci::capability::VENDOR_SPECIFIC, 0x00));
+    space.add(0, 5, 0, Function::new(0x1AF4, 0x1041, 0x02, 0x00)
+        .capability(0x40, pci::capability::MSI, 0x50)
+        .capability(0x50, pci::capability::PCI_EXPRESS, 0x40));
+    space.add(0, 6, 0, Function::new(0x1AF4, 0x1041, 0x02, 0x00));
+    let (_space, registry) = scan(space);
+    let function = registry.find(address(0, 4, 0)).unwrap();
+    assert_eq!(function.capabilities, vec![
+        Capability { id: pci::capability::MSIX, offset: 0x98 },
+        Capability { id: pci::capability::VENDOR_SPECIFIC, offset: 0x84 },
+        Capability { id: pci::capability::VENDOR_SPECIFIC, offset: 0x70 },
+    ]);
+    assert_eq!(function.capability(pci::capability::VENDOR_SPECIFIC).map(|c| c.offset), Some(0x84));
+    assert_eq!(function.capability(pci::capability::MSI), None);
+    // A list that loops is cut short rather than walked forever
+    assert_eq!(registry.find(address(0, 5, 0)).unwrap().capabilities.len(), 48);
+    assert!(registry.find(address(0, 6, 0)).unwrap().capabilities.is_empty());
+}
+
+#[test]
+fn msix() {
+    let mut space = Space::new();
+    space.add(0, 3, 0, Function::new(0x1B36, 0x0010, 0x01, 0x08)
+        .capability(0x40, pci::capability::MSIX, 0x00)
+        // 4 entries with the function masked, the table in BAR0 and the pending bits in BAR4
+        .dword(0x40, (0x4000 | 3) << 16 | 0x11)
+        .dword(0x44, 0x2000)
+        .dword(0x48, 0x3004));
+    let (space, registry) = scan(space);
+    let function = registry.find(address(0, 3, 0)).unwrap();
+    let mut access = Ecam::new(EcamWindow::new(space.clone(), 0), 0, 0, 255);
+    let mut config = function.config(&mut access);
+    let msix = unsafe {MsiX::read(&mut config, function.capability(pci::capability::MSIX).unwrap())};
+    assert_eq!((msix.size, msix.table_bar, msix.table_offset, msix.pba_bar, msix.pba_offset), (4, 0, 0x2000, 4, 0x3000));
+    unsafe {msix.enable(&mut config)};
+    assert_eq!(space.get().function(0, 3, 0).read(0x40) >> 16, 0x8003);
+    unsafe {msix.disable(&mut config)};
+    assert_eq!(space.get().function(0, 3, 0).read(0x40) >> 16, 0x0003);
+
+    let model = Shared::new(MsixTable::new(4));
+    let mut table = msix::Table::new(model.clone(), msix.size);
+    unsafe {
+        table.set(2, msix::message(3, 0x40));
+        assert_eq!(model.get().entries[2], [0xFEE0_3000, 0, 0x40, 1]);
+        table.unmask(2);
+        assert_eq!(model.get().entries[2][3], 0);
+        table.mask_all();
+    }
+    assert!(model.get().entries.iter().all(|entry| entry[3] == 1));
+}
@@ -0,0 +1,129 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::drivers::pic8259::{ChainedPics, VECTOR_BASE};
+use rlk_host_tests::models::Shared;
+use rlk_host_tests::models::pic8259::Pic8259;
+
+fn remapped() -> (Pic8259, Pic8259) {
+    let mut master = Pic8259::new();
+    let mut slave = Pic8259::new();
+    unsafe {ChainedPics::new(&mut master, &mut slave).remap(VECTOR_BASE)};
+    (master, slave)
+}
+
+#[test]
+fn remap() {
+    let (master, slave) = remapped();
+    assert!(master.initialized() && slave.initialized());
+    assert_eq!(master.offset(), VECTOR_BASE);
+    assert_eq!(slave.offset(), VECTOR_BASE + 8);
+    // Slave on line 2 of the master, 8086 mode
+    assert_eq!(master.icw3(), 0x04);
+    assert_eq!(slave.icw3(), 0x02);
+    assert_eq!(master.icw4(), 0x01);
+    assert_eq!(slave.icw4(), 0x01);
+    // Everything but the cascade is masked
+    assert_eq!(master.imr(), 0xfb);
+    assert_eq!(slave.imr(), 0xff);
+}
+
+#[test]
+#[should_panic]
+fn remap_over_exceptions() {
+    let mut master = Pic8259::new();
+    let mut slave = Pic8259::new();
+    unsafe {ChainedPics::new(&mut master, &mut slave).remap(8)};
+}
+
+#[test]
+#[should_panic]
+fn remap_past_last_vector() {
+    let mut master = Pic8259::new();
+    let mut slave = Pic8259::new();
+    unsafe {ChainedPics::new(&mut master, &mut slave).remap(248)};
+}
+
+#[test]
+fn remap_at_last_vectors() {
+    let mut master = Pic8259::new();
+    let mut slave = Pic8259::new();
+    let mut pics = ChainedPics::new(&mut master, &mut slave);
+    unsafe {pics.remap(240)};
+    assert_eq!(pics.irq_for(255), Some(15));
+    assert_eq!(pics.irq_for(239), None);
+}
+
+#[test]
+fn masking() {
+    let master = Shared::new(Pic8259::new());
+    let slave = Shared::new(Pic8259::new());
+    let mut pics = ChainedPics::new(master.clone(), slave.clone());
+    unsafe {
+        pics.remap(VECTOR_BASE);
+        pics.unmask_irq(1);
+        pics.unmask_irq(12);
+        assert_eq!(pics.masks(), 0xeff9);
+        pics.mask_irq(1);
+        assert_eq!(pics.masks(), 0xeffb);
+    }
+    slave.get().raise(4);
+    master.get().raise(2);
+    let vector = master.get().acknowledge();
+    assert_eq!(vector, VECTOR_BASE + 2);
+    let vector = slave.get().acknowledge();
+    assert_eq!(pics.irq_for(vector), Some(12));
+    assert_eq!(pics.irq_for(VECTOR_BASE + 16), None);
+    assert!(!pics.handles(14));
+    unsafe {
+        pics.disable();
+        assert_eq!(pics.masks(), 0xffff);
+    }
+}
+
+#[test]
+fn eoi_slave_acknowledges_both() {
+    let (mut master, mut slave) = remapped();
+    unsafe {ChainedPics::new(&mut master, &mut slave).unmask_irq(12)};
+    slave.raise(4);
+    master.raise(2);
+    slave.acknowledge();
+    master.acknowledge();
+    unsafe {
+        let mut pics = ChainedPics::new(&mut master, &mut slave);
+        assert_eq!(pics.in_service(), 0x1004);
+        assert!(!pics.is_spurious(12));
+        pics.eoi(12);
+        assert_eq!(pics.in_service(), 0);
+    }
+    assert_eq!(master.eois(), 1);
+    assert_eq!(slave.eois(), 1);
+}
+
+#[test]
+fn spurious_master() {
+    let (mut master, mut slave) = remapped();
+    unsafe {ChainedPics::new(&mut master, &mut slave).unmask_irq(7)};
+    // Request goes away before it is acknowledged
+    master.raise(7);
+    master.lower(7);
+    assert_eq!(master.acknowledge(), VECTOR_BASE + 7);
+    assert!(unsafe {ChainedPics::new(&mut master, &mut slave).is_spurious(7)});
+    assert_eq!(master.eois(), 0);
+    // A real IRQ7 is not spurious
+    master.raise(7);
+    master.acknowledge();
+    assert!(!unsafe {ChainedPics::new(&mut master, &mut slave).is_spurious(7)});
+}
+
+#[test]
+fn spurious_slave() {
+    let (mut master, mut slave) = remapped();
+    master.raise(2);
+    master.acknowledge();
+    assert_eq!(slave.acknowledge(), VECTOR_BASE + 15);
+    assert!(unsafe {ChainedPics::new(&mut master, &mut slave).is_spurious(15)});
+    // The master still saw a real interrupt on the cascade and must be acknowledged
+    assert_eq!(master.eois(), 1);
+    assert_eq!(master.isr(), 0);
+    assert_eq!(slave.eois(), 0);
+}
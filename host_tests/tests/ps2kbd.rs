@@ -0,0 +1,206 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::drivers::i8042::{Controller, Error};
+use rlk_host_tests::drivers::ps2kbd::{Decoder, Keyboard, ScancodeSet};
+use rlk_host_tests::input::{self, keymap, Event, KeyCode, KeyState, Level, Modifiers, Queue};
+use rlk_host_tests::models::Shared;
+use rlk_host_tests::models::i8042::I8042;
+
+fn keyboard(model: &Shared<I8042>) -> Keyboard<Shared<I8042>> {
+    let mut controller = Controller::new(model.clone());
+    unsafe {
+        controller.init().unwrap();
+        Keyboard::new(controller).unwrap()
+    }
+}
+
+/// Type the provided bytes and collect every character produced
+fn type_chars(model: &Shared<I8042>, kb: &mut Keyboard<Shared<I8042>>, bytes: &[u8]) -> String {
+    model.get().keys(bytes);
+    let mut s = String::new();
+    while let Some(event) = unsafe {kb.poll()} {
+        if let Some(c) = event.ch {
+            s.push(c);
+        }
+    }
+    s
+}
+
+#[test]
+fn controller_init() {
+    let mut model = I8042::new();
+    let ports = unsafe {Controller::new(&mut model).init()}.unwrap();
+    assert!(ports.dual);
+    // Interrupts and translation off, both ports left disabled
+    assert_eq!(model.config() & 0x43, 0);
+    assert_eq!(model.config() & 0x30, 0x30);
+    assert_eq!(model.output_pending(), 0);
+}
+
+#[test]
+fn controller_single_port() {
+    let mut model = I8042::new().single_port();
+    let ports = unsafe {Controller::new(&mut model).init()}.unwrap();
+    assert!(!ports.dual);
+}
+
+#[test]
+fn controller_self_test_resets_config() {
+    let mut model = I8042::new().self_test_resets();
+    unsafe {Controller::new(&mut model).init()}.unwrap();
+    assert_eq!(model.config() & 0x43, 0);
+}
+
+#[test]
+fn controller_self_test_failure() {
+    let mut model = I8042::new().failing_self_test(0xFC);
+    let result = unsafe {Controller::new(&mut model).init()};
+    assert_eq!(result.unwrap_err(), Error::SelfTestFailed(0xFC));
+}
+
+#[test]
+fn controller_pulse_reset() {
+    let mut model = I8042::new();
+    unsafe {Controller::new(&mut model).pulse_reset()};
+    assert!(model.reset_pulsed());
+}
+
+#[test]
+fn keyboard_selects_set2() {
+    let model = Shared::new(I8042::new().fixed_set(2));
+    let kb = keyboard(&model);
+    assert_eq!(kb.scancode_set(), ScancodeSet::Set2);
+    let model = Shared::new(I8042::new());
+    let kb = keyboard(&model);
+    assert_eq!(kb.scancode_set(), ScancodeSet::Set2);
+    assert_eq!(model.get().keyboard().set(), 2);
+}
+
+#[test]
+fn keyboard_falls_back_to_set1() {
+    let model = Shared::new(I8042::new().fixed_set(1));
+    let mut kb = keyboard(&model);
+    assert_eq!(kb.scancode_set(), ScancodeSet::Set1);
+    // Shift+h i, in set 1
+    assert_eq!(type_chars(&model, &mut kb, &[0x2A, 0x23, 0xA3, 0xAA, 0x17, 0x97]), "Hi");
+}
+
+#[test]
+fn keyboard_types_set2() {
+    let model = Shared::new(I8042::new());
+    let mut kb = keyboard(&model);
+    // Shift+h i, in set 2
+    assert_eq!(type_chars(&model, &mut kb, &[0x12, 0x33, 0xF0, 0x33, 0xF0, 0x12, 0x43, 0xF0, 0x43]), "Hi");
+    assert!(unsafe {kb.poll()}.is_none());
+}
+
+#[test]
+fn keyboard_caps_lock_sets_leds() {
+    let model = Shared::new(I8042::new());
+    let mut kb = keyboard(&model);
+    assert_eq!(model.get().keyboard().leds(), 0);
+    // Caps lock, a, caps lock
+    assert_eq!(type_chars(&model, &mut kb, &[0x58, 0xF0, 0x58, 0x1C, 0xF0, 0x1C]), "A");
+    assert_eq!(model.get().keyboard().leds(), 4);
+    // Shift inverts caps lock for letters, but not for numbers
+    assert_eq!(type_chars(&model, &mut kb, &[0x12, 0x1C, 0x16, 0xF0, 0x12]), "a!");
+    type_chars(&model, &mut kb, &[0x58, 0xF0, 0x58]);
+    assert_eq!(model.get().keyboard().leds(), 0);
+}
+
+#[test]
+fn keyboard_service_fills_queue() {
+    // The only test using the global input queue, so nothing else can take from it
+    let model = Shared::new(I8042::new());
+    let mut kb = keyboard(&model);
+    // a, then pause which is released straight away
+    model.get().keys(&[0x1C, 0xF0, 0x1C, 0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77]);
+    unsafe {kb.service()};
+    assert_eq!(model.get().output_pending(), 0);
+    let mut events = Vec::new();
+    while let Some(Event::Key(event)) = input::pop() {
+        events.push((event.code, event.pressed, event.ch));
+    }
+    assert_eq!(events, vec![
+        (KeyCode::A, true, Some('a')),
+        (KeyCode::A, false, None),
+        (KeyCode::Pause, true, None),
+        (KeyCode::Pause, false, None),
+    ]);
+}
+
+#[test]
+fn decode_set2_extended() {
+    let mut decoder = Decoder::new(ScancodeSet::Set2);
+    let mut feed = |bytes: &[u8]| bytes.iter().filter_map(|b| decoder.feed(*b)).collect::<Vec<_>>();
+    assert_eq!(feed(&[0xE0, 0x75, 0xE0, 0xF0, 0x75]), vec![(KeyCode::Up, true), (KeyCode::Up, false)]);
+    // Print screen is wrapped in fake shifts that must not appear
+    assert_eq!(feed(&[0xE0, 0x12, 0xE0, 0x7C]), vec![(KeyCode::PrintScreen, true)]);
+    assert_eq!(feed(&[0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77]), vec![(KeyCode::Pause, true), (KeyCode::Pause, false)]);
+    // Decoding carries on correctly after pause
+    assert_eq!(feed(&[0x1C]), vec![(KeyCode::A, true)]);
+}
+
+#[test]
+fn decode_set1_extended() {
+    let mut decoder = Decoder::new(ScancodeSet::Set1);
+    let mut feed = |bytes: &[u8]| bytes.iter().filter_map(|b| decoder.feed(*b)).collect::<Vec<_>>();
+    assert_eq!(feed(&[0xE0, 0x1D, 0xE0, 0x9D]), vec![(KeyCode::RightCtrl, true), (KeyCode::RightCtrl, false)]);
+    assert_eq!(feed(&[0xE0, 0x2A, 0xE0, 0x37]), vec![(KeyCode::PrintScreen, true)]);
+    assert_eq!(feed(&[0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5]), vec![(KeyCode::Pause, true), (KeyCode::Pause, false)]);
+    // Releasing left shift is 0xAA in set 1 and must not be mistaken for a self test response
+    assert_eq!(feed(&[0xAA]), vec![(KeyCode::LeftShift, false)]);
+}
+
+fn type_codes(state: &mut KeyState, codes: &[(KeyCode, bool)]) -> String {
+    codes.iter().filter_map(|&(code, pressed)| state.process(code, pressed).ch).collect()
+}
+
+#[test]
+fn layouts() {
+    let mut de = KeyState::new(keymap::find("de").unwrap());
+    assert_eq!(type_codes(&mut de, &[(KeyCode::Y, true), (KeyCode::Z, true), (KeyCode::Semicolon, true)]), "zyö");
+    assert_eq!(type_codes(&mut de, &[(KeyCode::RightAlt, true), (KeyCode::Q, true), (KeyCode::RightAlt, false), (KeyCode::Q, true)]), "@q");
+    let uk = keymap::find("uk").unwrap();
+    assert_eq!(uk.lookup(KeyCode::Key3, Level::Shift), Some('£'));
+    assert_eq!(uk.lookup(KeyCode::Key1, Level::Shift), Some('!'));
+    let mut dvorak = KeyState::new(keymap::find("dvorak").unwrap());
+    assert_eq!(type_codes(&mut dvorak, &[(KeyCode::J, true), (KeyCode::D, true), (KeyCode::P, true), (KeyCode::P, true), (KeyCode::S, true)]), "hello");
+    assert!(keymap::find("klingon").is_none());
+}
+
+#[test]
+fn modifiers() {
+    let mut state = KeyState::new(keymap::default());
+    // Ctrl+C produces the control character
+    assert_eq!(type_codes(&mut state, &[(KeyCode::LeftCtrl, true), (KeyCode::C, true), (KeyCode::LeftCtrl, false)]), "\x03");
+    // Releasing one shift while the other is held stays shifted
+    let event = state.process(KeyCode::LeftShift, true);
+    assert!(event.modifiers.contains(Modifiers::LEFT_SHIFT));
+    state.process(KeyCode::RightShift, true);
+    state.process(KeyCode::LeftShift, false);
+    assert_eq!(state.process(KeyCode::A, true).ch, Some('A'));
+    state.process(KeyCode::RightShift, false);
+    // Keypad only produces digits with num lock
+    assert_eq!(type_codes(&mut state, &[(KeyCode::Kp1, true), (KeyCode::NumLock, true), (KeyCode::Kp1, true)]), "1");
+    // Releases never produce characters
+    assert_eq!(state.process(KeyCode::A, false).ch, None);
+}
+
+#[test]
+fn queue_overflow() {
+    let mut queue = Queue::new();
+    let mut state = KeyState::new(keymap::default());
+    let event = Event::Key(state.process(KeyCode::A, true));
+    let mut pushed = 0;
+    while queue.push(event) {
+        pushed += 1;
+    }
+    assert_eq!(queue.len(), pushed);
+    assert_eq!(queue.dropped(), 1);
+    while queue.pop().is_some() {
+        pushed -= 1;
+    }
+    assert_eq!(pushed, 0);
+    assert!(queue.is_empty());
+}
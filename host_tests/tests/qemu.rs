@@ -0,0 +1,39 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::drivers::fw_cfg;
+use rlk_host_tests::drivers::qemu::{self, DebugCon, DebugExit};
+use rlk_host_tests::models::fw_cfg::FwCfg;
+use rlk_host_tests::models::qemu::{DebugCon as DebugConModel, DebugExit as DebugExitModel};
+use rlk_host_tests::models::virtio::Region;
+
+#[test]
+fn debugcon() {
+    let mut model = DebugConModel::new(true);
+    {
+        let mut con = unsafe {DebugCon::new(&mut model)}.unwrap();
+        unsafe {con.write("Info: 🍳\n".as_bytes())};
+    }
+    assert_eq!(model.output, "Info: 🍳\n".as_bytes());
+}
+
+#[test]
+fn debugcon_absent() {
+    let mut model = DebugConModel::new(false);
+    assert!(unsafe {DebugCon::new(&mut model)}.is_none());
+}
+
+#[test]
+fn exit() {
+    let mut model = DebugExitModel::new();
+    unsafe {DebugExit::new(&mut model).exit(qemu::EXIT_PANIC)};
+    assert_eq!(model.status, Some(3));
+    assert_eq!(model.status, Some(qemu::exit_status(qemu::EXIT_PANIC)));
+    assert_eq!(qemu::exit_status(0x7F), 0xFF);
+}
+
+#[test]
+fn detect_qemu() {
+    assert!(unsafe {fw_cfg::detect(&mut FwCfg::new(false))});
+    // Nothing there, as on real hardware
+    assert!(!unsafe {fw_cfg::detect(&mut Region::new(16))});
+}
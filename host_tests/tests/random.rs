@@ -0,0 +1,143 @@
+// The kernel is built by a compiler that predates `dyn`
+#![allow(bare_trait_objects)]
+
+extern crate rlk_host_tests;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use rlk_host_tests::drivers::virtio;
+use rlk_host_tests::drivers::virtio::rng::Rng;
+use rlk_host_tests::models::virtio::{Common, Device, Identity, Rng as RngModel};
+use rlk_host_tests::models::Shared;
+use rlk_host_tests::random::{self, EntropySource};
+use rlk_host_tests::random::pool::{self, Pool};
+
+#[test]
+fn chacha20_block() {
+    // RFC 7539 section 2.3.2
+    let key = [0x0302_0100, 0x0706_0504, 0x0B0A_0908, 0x0F0E_0D0C, 0x1312_1110, 0x1716_1514, 0x1B1A_1918, 0x1F1E_1D1C];
+    let nonce = [0x0900_0000, 0x4A00_0000, 0];
+    assert_eq!(pool::chacha20_block(&key, 1, &nonce), [
+        0xE4E7_F110, 0x1559_3BD1, 0x1FDD_0F50, 0xC471_20A3, 0xC7F4_D1C7, 0x0368_C033, 0x9AAA_2204, 0x4E6C_D4C3,
+        0x4664_82D2, 0x09AA_9F07, 0x05D7_C214, 0xA202_8BD9, 0xD19C_12B5, 0xB94E_16DE, 0xE883_D0CB, 0x4E3C_50A2,
+    ]);
+}
+
+fn output(pool: &mut Pool, len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    pool.fill(&mut bytes);
+    bytes
+}
+
+#[test]
+fn pool_seeding() {
+    let mut pool = Pool::new();
+    assert!(!pool.seeded());
+    pool.add(&[1; 16], 16 * 8);
+    assert_eq!(pool.bits(), 128);
+    assert!(!pool.seeded());
+    pool.add(&[2; 16], 0);
+    assert!(!pool.seeded());
+    pool.add(&[3; 64], 64 * 8);
+    assert!(pool.seeded());
+    assert_eq!(pool.bits(), pool::SEED_BITS);
+}
+
+#[test]
+fn pool_output() {
+    let mut a = Pool::new();
+    let mut b = Pool::new();
+    a.add(b"the same input", 0);
+    b.add(b"the same input", 0);
+    let first = output(&mut a, 100);
+    assert_eq!(first, output(&mut b, 100));
+    // Each request continues from a new key
+    let second = output(&mut a, 100);
+    assert_ne!(first, second);
+    assert_eq!(a.generated(), 200);
+
+    // Any difference in input changes everything after it
+    let mut c = Pool::new();
+    c.add(b"the same inpuT", 0);
+    assert_ne!(output(&mut c, 100), first);
+    a.add(&[0], 0);
+    b.add(&[1], 0);
+    assert_ne!(output(&mut a, 32), output(&mut b, 32));
+    // Absorbing input is not a reseed
+    assert_eq!(a.generated(), 232);
+    a.reseeded();
+    assert_eq!(a.generated(), 0);
+
+    // Input of more than one block is all used
+    let mut d = Pool::new();
+    let mut e = Pool::new();
+    d.add(&[0; 100], 0);
+    e.add(&[0; 99], 0);
+    e.add(&[1], 0);
+    assert_ne!(output(&mut d, 16), output(&mut e, 16));
+}
+
+fn virtio_device() -> Device<RngModel> {
+    let common = Shared::new(Common::new(virtio::F_VERSION_1).queue(16));
+    Device::new(common.clone(), RngModel::new(common), None)
+}
+
+#[test]
+fn virtio_rng() {
+    let device = virtio_device();
+    let mut rng = unsafe {Rng::new(device.transport(), &Identity)}.unwrap();
+    assert_eq!(rng.name(), "virtio-rng");
+    let mut buffer = [0; 100];
+    assert_eq!(unsafe {rng.read(&mut buffer)}, 0);
+
+    unsafe {device.device.get().supply(150)};
+    assert_eq!(unsafe {rng.read(&mut buffer[..10])}, 10);
+    assert_eq!(&buffer[..10], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    assert_eq!(unsafe {rng.read(&mut buffer)}, 100);
+    assert_eq!(buffer[0], 10);
+    assert_eq!(buffer[99], 109);
+    assert_eq!(unsafe {rng.read(&mut buffer)}, 40);
+    assert_eq!(buffer[39], 149);
+    assert_eq!(unsafe {rng.read(&mut buffer)}, 0);
+
+    // Buffers handed back are filled again as the device has more
+    unsafe {device.device.get().supply(1000)};
+    assert_eq!(unsafe {rng.read(&mut buffer)}, 100);
+    assert_eq!(buffer[0], 150);
+}
+
+/// Source that counts the bytes read from it
+struct Counting(Rc<Cell<usize>>);
+
+impl EntropySource for Counting {
+    fn name(&self) -> &'static str {
+        "counting"
+    }
+    unsafe fn read(&mut self, buffer: &mut [u8]) -> usize {
+        for byte in buffer.iter_mut() {
+            *byte = self.0.get() as u8;
+            self.0.set(self.0.get() + 1);
+        }
+        buffer.len()
+    }
+}
+
+#[test]
+fn sources_reseed() {
+    // The only test of the kernel wide pool, so nothing else registers sources
+    let read = Rc::new(Cell::new(0));
+    random::register(Box::new(Counting(read.clone())));
+    assert!(random::seeded());
+    assert_eq!(read.get(), 64);
+    // Every request mixes in the uptime, which must not hold off the reseed
+    let mut buffer = vec![0; 4096];
+    for _ in 0..random::RESEED_INTERVAL as usize / buffer.len() {
+        random::fill(&mut buffer);
+    }
+    assert_eq!(read.get(), 64);
+    random::fill(&mut buffer[..1]);
+    assert_eq!(read.get(), 64 + 32);
+    random::fill(&mut buffer[..1]);
+    assert_eq!(read.get(), 64 + 32);
+}
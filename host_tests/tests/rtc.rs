@@ -0,0 +1,105 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::drivers::rtc::{DateTime, Rtc};
+use rlk_host_tests::models::rtc;
+use rlk_host_tests::models::Access;
+
+const BINARY: u8 = 1 << 2;
+const HOURS_24: u8 = 1 << 1;
+const PM: u8 = 1 << 7;
+
+fn date(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+    DateTime { year: year, month: month, day: day, hour: hour, minute: minute, second: second }
+}
+
+#[test]
+fn bcd_24_hour() {
+    let mut model = rtc::Rtc::new(HOURS_24);
+    model.set_time(0x18, 0x07, 0x15, 0x23, 0x34, 0x56);
+    let now = unsafe {Rtc::new(&mut model, None).now()};
+    assert_eq!(now, Some(date(2018, 7, 15, 23, 34, 56)));
+}
+
+#[test]
+fn nmis_left_unmasked() {
+    let mut model = rtc::Rtc::new(HOURS_24);
+    assert!(model.nmi_masked());
+    unsafe {Rtc::new(&mut model, None).now()};
+    assert!(!model.nmi_masked());
+    assert!(model.accesses().iter().all(|a| match *a {
+        Access::Write(0, index) => index & 0x80 == 0,
+        _ => true,
+    }));
+}
+
+#[test]
+fn binary_12_hour() {
+    let mut model = rtc::Rtc::new(BINARY);
+    model.set_time(18, 7, 15, 11 | PM, 34, 56);
+    assert_eq!(unsafe {Rtc::new(&mut model, None).now()}, Some(date(2018, 7, 15, 23, 34, 56)));
+    // 12 AM is midnight and 12 PM is noon
+    model.set(0x04, 12);
+    assert_eq!(unsafe {Rtc::new(&mut model, None).now()}.unwrap().hour, 0);
+    model.set(0x04, 12 | PM);
+    assert_eq!(unsafe {Rtc::new(&mut model, None).now()}.unwrap().hour, 12);
+}
+
+#[test]
+fn bcd_12_hour() {
+    let mut model = rtc::Rtc::new(0);
+    model.set_time(0x99, 0x12, 0x31, 0x12 | PM, 0x00, 0x00);
+    assert_eq!(unsafe {Rtc::new(&mut model, None).now()}, Some(date(1999, 12, 31, 12, 0, 0)));
+}
+
+#[test]
+fn century_register() {
+    let mut model = rtc::Rtc::new(HOURS_24);
+    model.set_time(0x01, 0x01, 0x01, 0x00, 0x00, 0x00);
+    model.set(0x32, 0x21);
+    assert_eq!(unsafe {Rtc::new(&mut model, Some(0x32)).now()}, Some(date(2101, 1, 1, 0, 0, 0)));
+}
+
+#[test]
+fn waits_for_update() {
+    let mut model = rtc::Rtc::new(HOURS_24 | BINARY);
+    model.set_time(18, 7, 15, 12, 0, 0);
+    model.updating(5);
+    assert_eq!(unsafe {Rtc::new(&mut model, None).now()}, Some(date(2018, 7, 15, 12, 0, 0)));
+    // Only status A was read until the update finished
+    let accesses = model.accesses();
+    let first = accesses.iter().position(|access| *access == Access::Write(0, 0x00)).unwrap();
+    assert_eq!(accesses[..first].iter().filter(|access| **access == Access::Write(0, 0x0A)).count(), 6);
+}
+
+#[test]
+fn update_part_way_through() {
+    let mut model = rtc::Rtc::new(HOURS_24 | BINARY);
+    model.set_time(18, 12, 31, 23, 59, 59);
+    // Roll over part way through the first reading, just after the hours were read
+    model.change_after(4, vec![(0x00, 0), (0x02, 0), (0x04, 0), (0x07, 1), (0x08, 1), (0x09, 19)]);
+    assert_eq!(unsafe {Rtc::new(&mut model, None).now()}, Some(date(2019, 1, 1, 0, 0, 0)));
+}
+
+#[test]
+fn invalid_date() {
+    let mut model = rtc::Rtc::new(HOURS_24 | BINARY);
+    model.set_time(18, 2, 30, 0, 0, 0);
+    assert_eq!(unsafe {Rtc::new(&mut model, None).now()}, None);
+}
+
+#[test]
+fn unix_time() {
+    assert_eq!(date(1970, 1, 1, 0, 0, 0).to_unix(), 0);
+    assert_eq!(date(2000, 2, 29, 0, 0, 0).to_unix(), 951782400);
+    assert_eq!(date(2018, 7, 15, 12, 34, 56).to_unix(), 1531658096);
+    assert_eq!(date(2099, 12, 31, 23, 59, 59).to_unix(), 4102444799);
+    for &seconds in &[0, 951782400, 951868799, 1531658096, 4102444799] {
+        assert_eq!(DateTime::from_unix(seconds).to_unix(), seconds);
+    }
+    assert_eq!(DateTime::from_unix(951868799), date(2000, 2, 29, 23, 59, 59));
+}
+
+#[test]
+fn display() {
+    assert_eq!(format!("{}", date(2018, 7, 5, 1, 2, 3)), "2018-07-05 01:02:03");
+}
@@ -0,0 +1,90 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::con::{Cell, Scrollback, HISTORY_CELLS};
+
+const BLANK: Cell = Cell::new(b' ', 0x0b);
+
+fn scrollback(width: u16, rows: u16) -> Box<Scrollback> {
+    let mut history = Box::new(Scrollback::new());
+    history.reset(width, rows, BLANK);
+    history
+}
+
+/// Write `count` lines, each starting with its number
+fn write_lines(history: &mut Scrollback, first: usize, count: usize) {
+    for line in first..first + count {
+        history.new_line();
+        history.put(0, Cell::new(line as u8, 0x07));
+    }
+}
+
+/// First character of each line of the view
+fn view(history: &Scrollback, rows: u16) -> Vec<u8> {
+    (0..rows).map(|y| history.cell(0, y).ch).collect()
+}
+
+#[test]
+fn live_view() {
+    let mut history = scrollback(80, 4);
+    assert_eq!(view(&history, 4), vec![b' '; 4]);
+    history.put(79, Cell::new(b'x', 0x0c));
+    // Beyond the width is dropped
+    history.put(80, Cell::new(b'y', 0x0c));
+    assert_eq!(history.cell(79, 3), Cell::new(b'x', 0x0c));
+    assert_eq!(history.cell(80, 3), BLANK);
+    write_lines(&mut history, 1, 2);
+    assert_eq!(view(&history, 4), vec![b' ', b' ', 1, 2]);
+    assert_eq!(history.cell(79, 1), Cell::new(b'x', 0x0c));
+    // Back no further than the screen as it was reset
+    assert!(history.page_up(1));
+    assert_eq!(view(&history, 4), vec![b' ', b' ', b' ', 1]);
+    assert!(history.page_up(1));
+    assert!(!history.page_up(1));
+}
+
+#[test]
+fn paging() {
+    let mut history = scrollback(80, 4);
+    write_lines(&mut history, 1, 20);
+    assert!(history.is_live());
+    assert!(history.page_up(6));
+    assert_eq!(view(&history, 4), vec![11, 12, 13, 14]);
+    // New lines do not move the view off what it shows
+    write_lines(&mut history, 21, 2);
+    assert_eq!(history.offset(), 8);
+    assert_eq!(view(&history, 4), vec![11, 12, 13, 14]);
+    assert!(history.page_down(3));
+    assert_eq!(view(&history, 4), vec![14, 15, 16, 17]);
+    assert!(history.snap());
+    assert!(!history.snap());
+    assert_eq!(view(&history, 4), vec![19, 20, 21, 22]);
+    assert!(!history.page_down(1));
+}
+
+#[test]
+fn oldest_dropped() {
+    let mut history = scrollback(128, 8);
+    let capacity = history.capacity();
+    assert_eq!(capacity, HISTORY_CELLS / 128);
+    assert!(capacity >= 300);
+    write_lines(&mut history, 0, capacity + 50);
+    assert!(history.page_up(usize::MAX));
+    assert_eq!(history.offset(), capacity - 8);
+    // The reset screen and the first 50 lines written are gone
+    let oldest = 50;
+    assert_eq!(view(&history, 8)[0], oldest);
+    // A held view is pushed along once the lines it shows are reused
+    write_lines(&mut history, capacity + 50, 1);
+    assert_eq!(view(&history, 8)[0], oldest + 1);
+}
+
+#[test]
+fn reset_forgets() {
+    let mut history = scrollback(80, 25);
+    write_lines(&mut history, 1, 100);
+    history.page_up(30);
+    history.reset(90, 60, BLANK);
+    assert!(history.is_live());
+    assert!(!history.page_up(1));
+    assert!((0..60).all(|y| (0..90).all(|x| history.cell(x, y) == BLANK)));
+}
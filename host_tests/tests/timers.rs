@@ -0,0 +1,206 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::drivers::hpet::Hpet;
+use rlk_host_tests::drivers::pit::{self, Mode, Pit};
+use rlk_host_tests::models::hpet;
+use rlk_host_tests::models::pit as pit_model;
+
+/// Period of the QEMU HPET, 100MHz
+const PERIOD_FS: u32 = 10_000_000;
+
+#[test]
+fn pit_count_for_nanos() {
+    assert_eq!(pit::count_for_nanos(0), 1);
+    assert_eq!(pit::count_for_nanos(1_000_000), 1193);
+    assert_eq!(pit::count_for_nanos(10_000_000), 11932);
+    // Longer than the counter can go is clamped
+    assert_eq!(pit::count_for_nanos(100_000_000), pit::MAX_COUNT);
+    assert_eq!(pit::count_for_nanos(!0), pit::MAX_COUNT);
+}
+
+#[test]
+fn pit_periodic_and_one_shot() {
+    let (model, counters, gate) = pit_model::Pit::new().ports();
+    let mut pit = Pit::new(counters, gate);
+    unsafe {
+        pit.periodic(11932);
+        pit.one_shot(pit::MAX_COUNT);
+    }
+    assert_eq!(model.get().reloads(), &[
+        (0, Mode::RateGenerator as u8, 11932),
+        // The maximum count is written as 0
+        (0, Mode::InterruptOnTerminalCount as u8, 0),
+    ]);
+    assert!(model.get().running(0));
+    unsafe {pit.stop()};
+    assert!(!model.get().running(0));
+}
+
+#[test]
+#[should_panic]
+fn pit_zero_count() {
+    let (_model, counters, gate) = pit_model::Pit::new().ports();
+    unsafe {Pit::new(counters, gate).periodic(0)};
+}
+
+#[test]
+fn pit_read_count() {
+    let (model, counters, gate) = pit_model::Pit::new().ports();
+    model.get().set_count(0, 0x1234);
+    assert_eq!(unsafe {Pit::new(counters, gate).read_count(0)}, 0x1234);
+}
+
+#[test]
+fn pit_free_running() {
+    let (model, counters, gate) = pit_model::Pit::new().ports();
+    let mut pit = Pit::new(counters, gate);
+    unsafe {pit.start_free_running()};
+    assert_eq!(model.get().reloads(), &[(2, Mode::RateGenerator as u8, 0)]);
+    assert!(model.get().running(2));
+    // Gate open with the speaker off
+    assert_eq!(model.get().gate() & 0b11, 0b01);
+    // Counting down from the full count reads as counting up from zero
+    assert_eq!(unsafe {pit.free_running_count()}, 0);
+    model.get().set_count(2, 0xFFF0);
+    assert_eq!(unsafe {pit.free_running_count()}, 0x10);
+    model.get().set_count(2, 1);
+    assert_eq!(unsafe {pit.free_running_count()}, 0xFFFF);
+}
+
+#[test]
+fn pit_calibrate() {
+    let (model, counters, gate) = pit_model::Pit::new().completes_after(100).ports();
+    let mut pit = Pit::new(counters, gate);
+    // A counter that advances 2000 between the two reads
+    let mut reads = vec![1000, 3000].into_iter();
+    let frequency = unsafe {pit.calibrate(1193, || reads.next().unwrap())};
+    assert_eq!(frequency, Some(2000 * pit::FREQUENCY / 1193));
+    assert_eq!(model.get().reloads(), &[(2, Mode::InterruptOnTerminalCount as u8, 1193)]);
+    // Gate is closed again and the speaker was never connected
+    assert_eq!(model.get().gate(), 0);
+}
+
+#[test]
+fn pit_calibrate_never_completes() {
+    let (_model, counters, gate) = pit_model::Pit::new().completes_after(usize::MAX).ports();
+    let mut pit = Pit::new(counters, gate);
+    assert_eq!(unsafe {pit.calibrate(1193, || 0)}, None);
+}
+
+#[test]
+fn hpet_rejects_missing_device() {
+    let mut model = hpet::Hpet::new(PERIOD_FS, 3).with_capabilities(0);
+    assert!(unsafe {Hpet::new(&mut model)}.is_none());
+    let mut model = hpet::Hpet::new(PERIOD_FS, 3).with_capabilities(!0);
+    assert!(unsafe {Hpet::new(&mut model)}.is_none());
+}
+
+#[test]
+fn hpet_capabilities() {
+    let mut model = hpet::Hpet::new(PERIOD_FS, 3);
+    let hpet = unsafe {Hpet::new(&mut model)}.unwrap();
+    assert_eq!(hpet.timers(), 3);
+    assert_eq!(hpet.frequency(), 100_000_000);
+    assert_eq!(hpet.counter_mask(), !0);
+    assert!(hpet.capabilities().legacy_capable());
+}
+
+#[test]
+fn hpet_enable() {
+    let mut model = hpet::Hpet::new(PERIOD_FS, 3);
+    {
+        let mut hpet = unsafe {Hpet::new(&mut model)}.unwrap();
+        unsafe {hpet.enable(true)};
+    }
+    assert_eq!(model.config(), 0b11);
+    {
+        let mut hpet = unsafe {Hpet::new(&mut model)}.unwrap();
+        unsafe {hpet.enable(false)};
+    }
+    assert_eq!(model.config(), 0b01);
+}
+
+#[test]
+#[should_panic]
+fn hpet_legacy_unsupported() {
+    let mut model = hpet::Hpet::new(PERIOD_FS, 3).no_legacy();
+    unsafe {Hpet::new(&mut model).unwrap().enable(true)};
+}
+
+#[test]
+fn hpet_periodic() {
+    let mut model = hpet::Hpet::new(PERIOD_FS, 3);
+    model.set_counter(5000);
+    {
+        let mut hpet = unsafe {Hpet::new(&mut model)}.unwrap();
+        unsafe {
+            hpet.enable(true);
+            hpet.periodic(0, 1000);
+        }
+    }
+    // Enabled, periodic, with the accumulator set
+    assert_eq!(model.timer_config(0) & 0b1110, 0b1100);
+    assert!(model.value_set(0));
+    // First the next comparison, then the period, without the counter being halted
+    assert_eq!(model.comparator_writes(0), &[(6000, true), (1000, true)]);
+    assert_eq!(model.config(), 0b11);
+}
+
+#[test]
+fn hpet_periodic_slow_setup() {
+    // The counter moves on further than the period between reads, so the first comparison
+    // could have been passed before it was written
+    let mut model = hpet::Hpet::new(PERIOD_FS, 3).step(1500);
+    model.set_counter(5000);
+    {
+        let mut hpet = unsafe {Hpet::new(&mut model)}.unwrap();
+        unsafe {
+            hpet.enable(true);
+            hpet.periodic(0, 1000);
+        }
+    }
+    assert_eq!(model.comparator_writes(0), &[(6000, true), (1000, true), (10000, true), (1000, true)]);
+}
+
+#[test]
+#[should_panic]
+fn hpet_periodic_unsupported() {
+    let mut model = hpet::Hpet::new(PERIOD_FS, 3);
+    unsafe {Hpet::new(&mut model).unwrap().periodic(1, 1000)};
+}
+
+#[test]
+fn hpet_one_shot_wraps() {
+    let mut model = hpet::Hpet::new(PERIOD_FS, 3).counter_32();
+    model.set_counter(0xFFFF_FF00);
+    {
+        let mut hpet = unsafe {Hpet::new(&mut model)}.unwrap();
+        assert_eq!(hpet.counter_mask(), 0xFFFF_FFFF);
+        unsafe {hpet.one_shot(1, 0x200)};
+    }
+    assert_eq!(model.timer_config(1) & 0b1110, 0b0100);
+    assert_eq!(model.comparator_writes(1), &[(0x100, false)]);
+}
+
+#[test]
+fn hpet_stop_and_ack() {
+    let mut model = hpet::Hpet::new(PERIOD_FS, 3);
+    model.raise(2);
+    {
+        let mut hpet = unsafe {Hpet::new(&mut model)}.unwrap();
+        unsafe {
+            hpet.periodic(0, 1000);
+            hpet.stop(0);
+            hpet.ack(2);
+        }
+    }
+    assert_eq!(model.timer_config(0) & 0b1100, 0);
+    assert_eq!(model.status(), 0);
+}
+
+#[test]
+fn hpet_route() {
+    let mut model = hpet::Hpet::new(PERIOD_FS, 3);
+    unsafe {Hpet::new(&mut model).unwrap().route(1, 20)};
+    assert_eq!((model.timer_config(1) >> 9) & 0x1F, 20);
+}
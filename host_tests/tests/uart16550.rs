@@ -0,0 +1,113 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::drivers::Serial;
+use rlk_host_tests::drivers::uart16550::Uart;
+use rlk_host_tests::models::Access;
+use rlk_host_tests::models::uart16550::Uart16550;
+
+#[test]
+fn init_programs_line() {
+    let mut model = Uart16550::new();
+    unsafe {Uart::new(&mut model)};
+    // 8 data bits, no parity, one stop bit and DLAB left clear
+    assert_eq!(model.lcr(), 0x03);
+    assert_eq!(model.divisor(), 1);
+    assert_eq!(model.ier(), 0);
+    assert_eq!(model.fcr(), 0);
+    // DTR, RTS, OUT1 and OUT2 asserted without loopback
+    assert_eq!(model.mcr(), 0x0f);
+}
+
+#[test]
+fn init_latches_divisor_under_dlab() {
+    let mut model = Uart16550::new();
+    unsafe {Uart::new(&mut model)};
+    // Track DLAB through the access log to find what was written to the latch
+    let mut dlab = false;
+    let mut latched = Vec::new();
+    for access in model.accesses() {
+        match *access {
+            Access::Write(3, v) => dlab = v & 0x80 != 0,
+            Access::Write(off, v) if dlab && off < 2 => latched.push((off, v)),
+            _ => (),
+        }
+    }
+    assert!(!dlab);
+    assert!(latched.contains(&(1, 0)));
+    assert!(latched.contains(&(0, 1)));
+    assert!(model.transmitted().is_empty());
+}
+
+#[test]
+fn init_recovers_from_dlab_left_set() {
+    let mut model = Uart16550::new();
+    unsafe {
+        use rlk_host_tests::drivers::io::Io;
+        model.write(3, 0x80);
+        model.write(0, 0x0c);
+        model.write(1, 0x00);
+        Uart::new(&mut model);
+    }
+    assert_eq!(model.divisor(), 1);
+    assert_eq!(model.lcr() & 0x80, 0);
+    // Nothing should have been sent while clearing state
+    assert!(model.transmitted().is_empty());
+}
+
+#[test]
+fn tx_bytes() {
+    let mut model = Uart16550::new();
+    {
+        let mut uart = unsafe {Uart::new(&mut model)};
+        for b in b"rlk\r\n" {
+            unsafe {uart.write_byte(*b)};
+        }
+    }
+    assert_eq!(model.transmitted(), b"rlk\r\n");
+}
+
+#[test]
+fn tx_waits_for_holding_register() {
+    let mut model = Uart16550::new();
+    unsafe {Uart::new(&mut model).write_byte(b'a')};
+    model.set_tx_busy(5);
+    model.clear_accesses();
+    unsafe {Uart::new(&mut model).write_byte(b'b')};
+    assert_eq!(model.lost_writes(), 0);
+    assert_eq!(model.transmitted(), b"ab");
+    // Should have kept polling LSR until the transmitter freed up
+    let polls = model.accesses().iter().filter(|x| if let Access::Read(5, _) = **x { true } else { false }).count();
+    assert_eq!(polls, 6);
+}
+
+#[test]
+fn rx_bytes() {
+    let mut model = Uart16550::new();
+    model.receive(b"x");
+    let mut uart = unsafe {Uart::new(&mut model)};
+    assert_eq!(unsafe {uart.read_byte()}, Some(b'x'));
+    assert_eq!(unsafe {uart.read_byte()}, None);
+}
+
+#[test]
+fn rx_without_fifo_overruns() {
+    use rlk_host_tests::drivers::io::Io;
+    let mut model = Uart16550::new();
+    // The driver leaves the FIFO disabled, so each byte overwrites the last in RBR
+    model.receive(b"xyz");
+    assert_eq!(unsafe {model.read(5)} & 0x2, 0x2);
+    let mut uart = unsafe {Uart::new(&mut model)};
+    assert_eq!(unsafe {uart.read_byte()}, Some(b'z'));
+    assert_eq!(unsafe {uart.read_byte()}, None);
+}
+
+#[test]
+fn init_discards_fifo_contents() {
+    use rlk_host_tests::drivers::io::Io;
+    let mut model = Uart16550::new();
+    unsafe {model.write(2, 0x1)};
+    model.receive(b"stale");
+    assert_eq!(model.rx_pending(), 5);
+    let mut uart = unsafe {Uart::new(&mut model)};
+    assert_eq!(unsafe {uart.read_byte()}, None);
+}
@@ -0,0 +1,86 @@
+extern crate rlk_host_tests;
+
+use rlk_host_tests::drivers::vga::{self, halve_font, TextMode, Vga, GLYPHS};
+use rlk_host_tests::models::vga::Vga as VgaModel;
+
+/// Stand in for the 64KiB window glyphs are written through
+fn window() -> Vec<u8> {
+    vec![0; 64 * 1024]
+}
+
+/// Font where every line of a glyph is its own number
+fn font(height: usize) -> Vec<u8> {
+    (0..GLYPHS).flat_map(|glyph| vec![glyph as u8; height]).collect()
+}
+
+#[test]
+fn text_modes() {
+    for &mode in [TextMode::Text80x50, TextMode::Text90x60, TextMode::Text80x25].iter() {
+        let mut model = VgaModel::new();
+        let mut memory = window();
+        let before = model.regs;
+        {
+            let mut vga = unsafe {Vga::new(&mut model, memory.as_mut_ptr())};
+            unsafe {vga.set_text_mode(mode, &font(mode.font_height()))}.unwrap();
+            assert_eq!(unsafe {vga.font_height()}, mode.font_height());
+        }
+        assert_eq!(model.regs, *mode.registers(), "{:?}", mode);
+        // Timing that was protected was still written, and the display is showing again
+        if mode == TextMode::Text90x60 {
+            assert_ne!(model.regs.crtc[..8], before.crtc[..8]);
+        }
+        assert!(model.display_enabled);
+        assert_eq!(model.unsafe_clock_changes, 0);
+        assert_eq!(model.font_windows, 1);
+    }
+}
+
+#[test]
+fn geometry() {
+    let modes = [("80x25", 80, 25, 16), ("80x50", 80, 50, 8), ("90x60", 90, 60, 8)];
+    for &(name, columns, rows, height) in modes.iter() {
+        let mode: TextMode = name.parse().unwrap();
+        assert_eq!((mode.columns(), mode.rows(), mode.font_height()), (columns, rows, height));
+        // The CRTC agrees, with the offset being in words of two characters
+        let regs = mode.registers();
+        assert_eq!(regs.crtc[0x01] as u16 + 1, columns);
+        assert_eq!(regs.crtc[0x13] as u16 * 2, columns);
+        assert_eq!((regs.crtc[0x09] & 0x1F) as usize + 1, height);
+        let lines = regs.crtc[0x12] as usize + 1 + ((regs.crtc[0x07] as usize >> 1 & 1) << 8) + ((regs.crtc[0x07] as usize >> 6 & 1) << 9);
+        assert_eq!(lines, rows as usize * height);
+    }
+    assert!("80x60".parse::<TextMode>().is_err());
+}
+
+#[test]
+fn fonts() {
+    let mut model = VgaModel::new();
+    let mut memory = window();
+    {
+        let mut vga = unsafe {Vga::new(&mut model, memory.as_mut_ptr())};
+        let original = unsafe {vga.registers()};
+        unsafe {vga.load_font(&font(8), 8)}.unwrap();
+        let mut read = vec![0; GLYPHS * 8];
+        unsafe {vga.read_font(&mut read, 8)}.unwrap();
+        assert_eq!(read, font(8));
+        // Access to the text is as it was
+        assert_eq!(unsafe {vga.registers()}, original);
+        assert_eq!(unsafe {vga.load_font(&font(8), 16)}, Err(vga::Error::BadFont));
+        assert_eq!(unsafe {vga.load_font(&font(33), 33)}, Err(vga::Error::BadFont));
+        assert_eq!(unsafe {vga.set_text_mode(TextMode::Text80x50, &font(16))}, Err(vga::Error::BadFont));
+    }
+    // Glyphs are 32 bytes apart in the plane, with the lines beyond the height cleared
+    assert_eq!(&memory[65 * 32..65 * 32 + 9], &[65, 65, 65, 65, 65, 65, 65, 65, 0]);
+    assert_eq!(model.font_windows, 2);
+}
+
+#[test]
+fn halved_font() {
+    let mut tall = vec![0; GLYPHS * 16];
+    // A glyph of single line strokes, on odd and even lines
+    tall[16..32].copy_from_slice(&[0, 0xFF, 0x81, 0, 0x18, 0x18, 0, 0, 0xF0, 0, 0, 0x0F, 0, 0, 0, 0]);
+    let mut short = vec![0; GLYPHS * 8];
+    halve_font(&tall, &mut short);
+    assert_eq!(&short[8..16], &[0xFF, 0x81, 0x18, 0, 0xF0, 0x0F, 0, 0]);
+    assert!(short[..8].iter().chain(short[16..].iter()).all(|&line| line == 0));
+}
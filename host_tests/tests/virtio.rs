@@ -0,0 +1,269 @@
+// The kernel is built by a compiler that predates `dyn`
+#![allow(bare_trait_objects)]
+
+extern crate rlk_host_tests;
+
+use rlk_host_tests::drivers::io::MixedIo;
+use rlk_host_tests::drivers::pci::{self, Address, Ecam, Registry};
+use rlk_host_tests::drivers::virtio::{self, Buffer, Error, Layout, Region, Transport, Virtqueue};
+use rlk_host_tests::models::pci::{EcamWindow, Function, Space};
+use rlk_host_tests::models::virtio::{Common, Descriptor, Identity};
+use rlk_host_tests::models::virtio::Region as Registers;
+use rlk_host_tests::models::Shared;
+
+const F_EXAMPLE: u64 = 1 << 5;
+
+/// A vendor capability at `offset` describing a virtio structure
+fn virtio_cap(function: Function, offset: u8, next: u8, cfg_type: u8, bar: u8, region: (u32, u32)) -> Function {
+    let offset16 = offset as u16;
+    function.capability(offset, pci::capability::VENDOR_SPECIFIC, next)
+        .byte(offset16 + 2, 16)
+        .byte(offset16 + 3, cfg_type)
+        .byte(offset16 + 4, bar)
+        .dword(offset16 + 8, region.0)
+        .dword(offset16 + 12, region.1)
+}
+
+fn layout_of(function: Function) -> Option<Layout> {
+    let mut space = Space::new();
+    space.add(0, 3, 0, function);
+    let mut access = Ecam::new(EcamWindow::new(Shared::new(space), 0), 0, 0, 255);
+    let mut registry = Registry::new();
+    unsafe {registry.scan(&mut access, 0)};
+    let function = registry.find(Address::new(0, 0, 3, 0)).unwrap();
+    let mut config = function.config(&mut access);
+    unsafe {virtio::layout(function, &mut config)}
+}
+
+struct Device {
+    common: Shared<Common>,
+    notify: Shared<Registers>,
+    isr: Shared<Registers>,
+}
+
+impl Device {
+    fn new(common: Common) -> Self {
+        Device {
+            common: Shared::new(common),
+            notify: Shared::new(Registers::new(0x100)),
+            isr: Shared::new(Registers::isr(0)),
+        }
+    }
+}
+
+/// Every region of the transport as a `MixedIo`, so the models of each can differ
+fn open<'a>(common: &'a mut Shared<Common>, notify: &'a mut Shared<Registers>, isr: &'a mut Shared<Registers>,
+        config: Option<&'a mut Shared<Registers>>) -> Transport<&'a mut MixedIo> {
+    Transport::new(common, notify, 4, isr, config.map(|config| config as &mut MixedIo))
+}
+
+#[test]
+fn layout() {
+    let function = Function::new(0x1AF4, 0x1042, 0x01, 0x00).memory_bar_64(4, 0xFE00_0000, 0x4000, true);
+    let function = virtio_cap(function, 0x84, 0x70, 1, 4, (0x0000, 0x1000));
+    let function = virtio_cap(function, 0x70, 0x60, 3, 4, (0x1000, 0x1000));
+    let function = virtio_cap(function, 0x60, 0x50, 4, 4, (0x2000, 0x1000));
+    // A second common configuration is ignored in favour of the first
+    let function = virtio_cap(function, 0x50, 0xA8, 1, 2, (0x0000, 0x1000));
+    let function = virtio_cap(function, 0xA8, 0x00, 2, 4, (0x3000, 0x1000)).dword(0xA8 + 16, 4);
+    assert_eq!(layout_of(function), Some(Layout {
+        common: Region { bar: 4, offset: 0x0000, length: 0x1000 },
+        notify: Region { bar: 4, offset: 0x3000, length: 0x1000 },
+        notify_multiplier: 4,
+        isr: Region { bar: 4, offset: 0x1000, length: 0x1000 },
+        device: Some(Region { bar: 4, offset: 0x2000, length: 0x1000 }),
+    }));
+}
+
+#[test]
+fn layout_incomplete() {
+    // Without a notification structure, as with a legacy only device
+    let function = Function::new(0x1AF4, 0x1001, 0x01, 0x00);
+    let function = virtio_cap(function, 0x84, 0x70, 1, 4, (0x0000, 0x1000));
+    let function = virtio_cap(function, 0x70, 0x00, 3, 4, (0x1000, 0x1000));
+    assert_eq!(layout_of(function), None);
+    // Structures in reserved BARs do not count
+    let function = Function::new(0x1AF4, 0x1042, 0x01, 0x00);
+    let function = virtio_cap(function, 0x84, 0x70, 1, 4, (0x0000, 0x1000));
+    let function = virtio_cap(function, 0x70, 0x60, 3, 4, (0x1000, 0x1000));
+    let function = virtio_cap(function, 0x60, 0x00, 2, 6, (0x3000, 0x1000));
+    assert_eq!(layout_of(function), None);
+}
+
+#[test]
+fn negotiate() {
+    let Device { common, mut notify, mut isr, .. } =
+        Device::new(Common::new(virtio::F_VERSION_1 | F_EXAMPLE | virtio::F_EVENT_IDX).reset_delay(Some(3)));
+    common.get().msix_config = 3;
+    let mut handle = common.clone();
+    let mut transport = open(&mut handle, &mut notify, &mut isr, None);
+    let features = unsafe {transport.negotiate(F_EXAMPLE | virtio::F_INDIRECT_DESC)};
+    assert_eq!(features, Ok(virtio::F_VERSION_1 | F_EXAMPLE));
+    assert_eq!(transport.features(), virtio::F_VERSION_1 | F_EXAMPLE);
+    let common = common.get();
+    assert_eq!(common.driver_features(), virtio::F_VERSION_1 | F_EXAMPLE);
+    assert_eq!(common.status_writes(), &[0, 1, 3, 11]);
+    assert_eq!(common.msix_config, 0xFFFF);
+}
+
+#[test]
+fn negotiate_failures() {
+    let Device { common, mut notify, mut isr, .. } = Device::new(Common::new(F_EXAMPLE));
+    {
+        let mut handle = common.clone();
+        let mut transport = open(&mut handle, &mut notify, &mut isr, None);
+        assert_eq!(unsafe {transport.negotiate(F_EXAMPLE)}, Err(Error::Legacy));
+    }
+    assert_eq!(common.get().status(), virtio::STATUS_ACKNOWLEDGE | virtio::STATUS_DRIVER | virtio::STATUS_FAILED);
+
+    let Device { common, mut notify, mut isr, .. } =
+        Device::new(Common::new(virtio::F_VERSION_1 | F_EXAMPLE).reject(F_EXAMPLE));
+    {
+        let mut handle = common.clone();
+        let mut transport = open(&mut handle, &mut notify, &mut isr, None);
+        assert_eq!(unsafe {transport.negotiate(F_EXAMPLE)}, Err(Error::FeaturesRejected));
+    }
+    assert_eq!(common.get().status() & virtio::STATUS_FAILED, virtio::STATUS_FAILED);
+
+    let Device { mut common, mut notify, mut isr, .. } =
+        Device::new(Common::new(virtio::F_VERSION_1).reset_delay(None));
+    let mut transport = open(&mut common, &mut notify, &mut isr, None);
+    assert_eq!(unsafe {transport.negotiate(0)}, Err(Error::ResetTimeout));
+}
+
+#[test]
+fn enable_queue() {
+    let Device { common, mut notify, mut isr, .. } = Device::new(Common::new(virtio::F_VERSION_1).queue(256).queue(64));
+    let mut queue = Virtqueue::new(&Identity, 1, 32).unwrap();
+    let mut handle = common.clone();
+    let mut transport = open(&mut handle, &mut notify, &mut isr, None);
+    unsafe {
+        transport.negotiate(0).unwrap();
+        assert_eq!(transport.num_queues(), 2);
+        assert_eq!(transport.max_queue_size(2), 0);
+        transport.enable_queue(&mut queue).unwrap();
+        assert_eq!(transport.enable_queue(&mut Virtqueue::new(&Identity, 0, 512).unwrap()), Err(Error::NoQueue(0)));
+        assert_eq!(transport.enable_queue(&mut Virtqueue::new(&Identity, 2, 8).unwrap()), Err(Error::NoQueue(2)));
+        transport.driver_ok();
+    }
+    {
+        let common = common.get();
+        let state = common.queue_state(1);
+        assert!(state.enabled);
+        assert_eq!(state.size, 32);
+        assert_eq!(state.msix_vector, 0xFFFF);
+        assert_eq!((state.desc, state.driver, state.device), (queue.desc_paddr(), queue.avail_paddr(), queue.used_paddr()));
+        assert!(!common.queue_state(0).enabled);
+        assert_eq!(common.status() & virtio::STATUS_DRIVER_OK, virtio::STATUS_DRIVER_OK);
+    }
+    // Rings are laid out one after the other, each aligned as required
+    assert_eq!(queue.avail_paddr() - queue.desc_paddr(), 16 * 32);
+    assert_eq!(queue.used_paddr() % 4, 0);
+    // The notification offset is scaled by the multiplier
+    assert_eq!(queue.notify_offset(), 4);
+    unsafe {
+        queue.add(&[Buffer::readable(0x1000, 16)]).unwrap();
+        transport.notify(&queue);
+    }
+    assert_eq!(notify.get().writes(), &[(4, 2, 1)]);
+}
+
+#[test]
+fn virtqueue_round_trip() {
+    let Device { common, mut notify, mut isr, .. } = Device::new(Common::new(virtio::F_VERSION_1).queue(4));
+    let mut queue = Virtqueue::new(&Identity, 0, 4).unwrap();
+    let mut handle = common.clone();
+    let mut transport = open(&mut handle, &mut notify, &mut isr, None);
+    unsafe {transport.enable_queue(&mut queue).unwrap()};
+    let mut device = common.get().queue_device(0);
+    unsafe {
+        let first = queue.add(&[Buffer::readable(0x1000, 16), Buffer::writable(0x2000, 512), Buffer::writable(0x3000, 1)]).unwrap();
+        let second = queue.add(&[Buffer::readable(0x4000, 8)]).unwrap();
+        assert_eq!(queue.free(), 0);
+        assert_eq!(queue.add(&[Buffer::readable(0x5000, 8)]), None);
+        assert!(!queue.has_used());
+        assert_eq!(device.pop(), Some((first, vec![
+            Descriptor { addr: 0x1000, len: 16, writable: false },
+            Descriptor { addr: 0x2000, len: 512, writable: true },
+            Descriptor { addr: 0x3000, len: 1, writable: true },
+        ])));
+        assert_eq!(device.pop(), Some((second, vec![Descriptor { addr: 0x4000, len: 8, writable: false }])));
+        assert_eq!(device.pop(), None);
+        // Chains can complete out of order
+        device.push(second, 0);
+        device.push(first, 513);
+        assert_eq!(queue.pop_used(), Some((second, 0)));
+        assert_eq!(queue.free(), 1);
+        assert_eq!(queue.pop_used(), Some((first, 513)));
+        assert_eq!(queue.pop_used(), None);
+        assert_eq!(queue.free(), 4);
+        // Freed descriptors are reused, and the rings wrap around
+        for i in 0..10 {
+            let head = queue.add(&[Buffer::readable(i, 1), Buffer::writable(i, 1)]).unwrap();
+            let (popped, chain) = device.pop().unwrap();
+            assert_eq!(popped, head);
+            assert_eq!(chain.len(), 2);
+            device.push(head, i as u32);
+            assert_eq!(queue.wait_used(), (head, i as u32));
+        }
+        assert_eq!(queue.free(), 4);
+    }
+}
+
+#[test]
+fn virtqueue_suppression() {
+    let Device { common, mut notify, mut isr, .. } = Device::new(Common::new(virtio::F_VERSION_1).queue(8));
+    let mut queue = Virtqueue::new(&Identity, 0, 8).unwrap();
+    let mut handle = common.clone();
+    let mut transport = open(&mut handle, &mut notify, &mut isr, None);
+    unsafe {transport.enable_queue(&mut queue).unwrap()};
+    let mut device = common.get().queue_device(0);
+    unsafe {
+        queue.disable_interrupts();
+        assert!(device.interrupts_suppressed());
+        queue.enable_interrupts();
+        assert!(!device.interrupts_suppressed());
+        device.suppress_notifications(true);
+        queue.add(&[Buffer::readable(0x1000, 1)]).unwrap();
+        transport.notify(&queue);
+        device.suppress_notifications(false);
+        transport.notify(&queue);
+    }
+    assert_eq!(notify.get().writes(), &[(0, 2, 0)]);
+}
+
+#[test]
+#[should_panic]
+fn virtqueue_readable_after_writable() {
+    let mut queue = Virtqueue::new(&Identity, 0, 8).unwrap();
+    unsafe {queue.add(&[Buffer::writable(0x1000, 1), Buffer::readable(0x2000, 1)])};
+}
+
+#[test]
+fn virtqueue_sizes() {
+    assert!(Virtqueue::new(&Identity, 0, 0).is_none());
+    assert!(Virtqueue::new(&Identity, 0, 24).is_none());
+    assert!(Virtqueue::new(&Identity, 0, virtio::MAX_SIZE).is_some());
+}
+
+#[test]
+fn isr_and_config() {
+    let Device { common, mut notify, .. } = Device::new(Common::new(virtio::F_VERSION_1));
+    let mut isr = Shared::new(Registers::isr(virtio::ISR_QUEUE | virtio::ISR_CONFIG));
+    let mut config = Shared::new(Registers::new(0x10).set(0, &[0x00, 0x10, 0, 0, 0, 0, 0, 0])
+        .changing(common.clone(), 2));
+    let mut handle = common.clone();
+    let mut transport = open(&mut handle, &mut notify, &mut isr, Some(&mut config));
+    unsafe {
+        assert_eq!(transport.isr(), virtio::ISR_QUEUE | virtio::ISR_CONFIG);
+        // Reading acknowledges
+        assert_eq!(transport.isr(), 0);
+        // The first two reads see the configuration change, so are retried
+        assert_eq!(transport.read_config(|config| config.read32(0) as u64 | (config.read32(4) as u64) << 32), Ok(0x1000));
+        transport.device_config().unwrap().write8(8, 1);
+    }
+    assert_eq!(config.get().bytes()[8], 1);
+    let Device { mut common, mut notify, mut isr, .. } = Device::new(Common::new(virtio::F_VERSION_1));
+    let mut transport = open(&mut common, &mut notify, &mut isr, None);
+    assert_eq!(unsafe {transport.read_config(|config| config.read8(0))}, Err(Error::NoDeviceConfig));
+}
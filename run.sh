@@ -2,4 +2,4 @@
 
 objcopy --output-target elf32-i386 $1 $1.elf32
 
-qemu-system-x86_64 -M pc -m 64 -kernel $1.elf32 -cpu Haswell,+pdpe1gb -serial mon:stdio -nographic -append "--earlycon=serial,port=0x3f8 --heap_debug_free=on" -no-reboot -d int
+qemu-system-x86_64 -M pc -m 64 -kernel $1.elf32 -cpu Haswell,+pdpe1gb -serial mon:stdio -nographic -append "--earlycon=serial,port=0x3f8 --heap_debug_free=on${CMDLINE:+ $CMDLINE}" -no-reboot -device isa-debug-exit ${DEBUGCON:+-debugcon $DEBUGCON} ${DISK:+-drive file=$DISK,if=virtio,format=raw} ${SATA:+-drive file=$SATA,if=none,id=sata0,format=raw -device ahci,id=ahci0 -device ide-hd,drive=sata0,bus=ahci0.0} ${NVME:+-drive file=$NVME,if=none,id=nvme0,format=raw -device nvme,drive=nvme0,serial=rlk0001} ${NET:+-netdev $NET,id=net0 -device virtio-net-pci,netdev=net0} ${E1000:+-netdev $E1000,id=net1 -device e1000,netdev=net1} ${RNG:+-device virtio-rng-pci} ${VIRTCON:+-device virtio-serial-pci,max_ports=4 -chardev socket,id=log,path=$VIRTCON/log,server,nowait -device virtserialport,chardev=log,name=org.rlk.log -chardev socket,id=shell,path=$VIRTCON/shell,server,nowait -device virtserialport,chardev=shell,name=org.rlk.shell -chardev socket,id=debug,path=$VIRTCON/debug,server,nowait -device virtserialport,chardev=debug,name=org.rlk.debug}
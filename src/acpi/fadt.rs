@@ -0,0 +1,76 @@
+//! Fixed ACPI Description Table
+
+use super::find_table;
+use core::mem::size_of;
+use core::ptr;
+
+const SIGNATURE: &[u8; 4] = b"FACP";
+
+/// Fields following the header, up to and including the IA-PC boot flags
+///
+/// Later fields were added in later revisions and are not needed yet
+#[repr(C, packed)]
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+struct Fixed {
+    firmware_ctrl: u32,
+    dsdt: u32,
+    reserved: u8,
+    preferred_pm_profile: u8,
+    sci_int: u16,
+    smi_cmd: u32,
+    acpi_enable: u8,
+    acpi_disable: u8,
+    s4bios_req: u8,
+    pstate_cnt: u8,
+    pm1a_evt_blk: u32,
+    pm1b_evt_blk: u32,
+    pm1a_cnt_blk: u32,
+    pm1b_cnt_blk: u32,
+    pm2_cnt_blk: u32,
+    pm_tmr_blk: u32,
+    gpe0_blk: u32,
+    gpe1_blk: u32,
+    pm1_evt_len: u8,
+    pm1_cnt_len: u8,
+    pm2_cnt_len: u8,
+    pm_tmr_len: u8,
+    gpe0_blk_len: u8,
+    gpe1_blk_len: u8,
+    gpe1_base: u8,
+    cst_cnt: u8,
+    p_lvl2_lat: u16,
+    p_lvl3_lat: u16,
+    flush_size: u16,
+    flush_stride: u16,
+    duty_offset: u8,
+    duty_width: u8,
+    day_alrm: u8,
+    mon_alrm: u8,
+    century: u8,
+    iapc_boot_arch: u16,
+}
+
+/// The parts of the FADT the kernel uses
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    /// CMOS index of the RTC century register, if there is one
+    pub century: Option<u8>,
+    /// IA-PC boot architecture flags
+    pub boot_arch: u16,
+}
+
+/// Find the FADT
+pub fn get() -> Option<Fadt> {
+    let table = find_table(SIGNATURE)?;
+    let body = table.body();
+    if body.len() < size_of::<Fixed>() {
+        return None;
+    }
+    let fixed: Fixed = unsafe {ptr::read_unaligned(body.as_ptr() as *const Fixed)};
+    Some(Fadt {
+        century: if fixed.century == 0 { None } else { Some(fixed.century) },
+        // Only defined from revision 2, before which the field was reserved as zero
+        boot_arch: if table.revision >= 2 { fixed.iapc_boot_arch } else { 0 },
+    })
+}
@@ -0,0 +1,42 @@
+//! HPET Description Table
+
+use super::{find_table, GenericAddress, ADDRESS_SPACE_MEMORY};
+use core::mem::size_of;
+use core::ptr;
+
+const SIGNATURE: &[u8; 4] = b"HPET";
+
+/// Fixed fields following the header
+#[repr(C, packed)]
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+struct Fixed {
+    event_timer_block_id: u32,
+    base_address: GenericAddress,
+    hpet_number: u8,
+    minimum_tick: u16,
+    page_protection: u8,
+}
+
+/// Description of the first HPET block
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    /// Physical address of the registers
+    pub address: usize,
+    /// Minimum periodic tick, in main counter ticks, that will not lose interrupts
+    pub minimum_tick: u16,
+}
+
+/// Find the HPET described by ACPI, if there is one in memory space
+pub fn get() -> Option<Hpet> {
+    let table = find_table(SIGNATURE)?;
+    let body = table.body();
+    if body.len() < size_of::<Fixed>() {
+        return None;
+    }
+    let fixed: Fixed = unsafe {ptr::read_unaligned(body.as_ptr() as *const Fixed)};
+    if fixed.base_address.address_space != ADDRESS_SPACE_MEMORY {
+        return None;
+    }
+    Some(Hpet { address: fixed.base_address.address as usize, minimum_tick: fixed.minimum_tick })
+}
@@ -0,0 +1,147 @@
+//! Multiple APIC Description Table
+//!
+//! Describes the local APICs and I/O APICs, along with how ISA IRQs have been wired to GSIs when
+//! that differs from the identity mapping.
+
+use super::{find_table, SdtHeader};
+use core::ptr;
+
+const SIGNATURE: &[u8; 4] = b"APIC";
+
+/// Fixed fields following the header
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct Fixed {
+    local_apic_address: u32,
+    flags: u32,
+}
+
+/// System also has dual 8259s
+pub const FLAG_PCAT_COMPAT: u32 = 1;
+
+const TYPE_LOCAL_APIC: u8 = 0;
+const TYPE_IO_APIC: u8 = 1;
+const TYPE_SOURCE_OVERRIDE: u8 = 2;
+const TYPE_LOCAL_APIC_ADDRESS: u8 = 5;
+const TYPE_LOCAL_X2APIC: u8 = 9;
+
+/// Processor is usable
+const LOCAL_APIC_ENABLED: u32 = 1;
+
+/// How an interrupt line is electrically signalled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signal {
+    pub active_low: bool,
+    pub level: bool,
+}
+
+/// Conforms to the bus, which for ISA is edge triggered and active high
+pub const ISA_SIGNAL: Signal = Signal { active_low: false, level: false };
+
+impl Signal {
+    fn from_flags(flags: u16) -> Signal {
+        Signal {
+            active_low: flags & 0x3 == 0x3,
+            level: (flags >> 2) & 0x3 == 0x3,
+        }
+    }
+}
+
+/// Entry in the MADT
+#[derive(Debug, Clone, Copy)]
+pub enum Entry {
+    /// A processor, identified by its APIC ID
+    LocalApic { apic_id: u32, enabled: bool },
+    IoApic { id: u8, address: usize, gsi_base: u32 },
+    /// ISA IRQ that is not identity mapped, or is not signalled as ISA is
+    SourceOverride { irq: u8, gsi: u32, signal: Signal },
+    /// 64-bit replacement for the local APIC address
+    LocalApicAddress(usize),
+    /// Entry type not handled here
+    Other(u8),
+}
+
+pub struct Madt {
+    table: &'static SdtHeader,
+}
+
+unsafe fn read<T: Copy>(bytes: &[u8], offset: usize) -> T {
+    ptr::read_unaligned(bytes[offset..].as_ptr() as *const T)
+}
+
+impl Madt {
+    fn fixed(&self) -> Fixed {
+        unsafe {read(self.table.body(), 0)}
+    }
+    /// Physical address of the local APICs, taking any 64-bit override into account
+    pub fn local_apic_address(&self) -> usize {
+        self.entries().filter_map(|entry| if let Entry::LocalApicAddress(address) = entry { Some(address) } else { None })
+            .next()
+            .unwrap_or(self.fixed().local_apic_address as usize)
+    }
+    pub fn flags(&self) -> u32 {
+        self.fixed().flags
+    }
+    pub fn entries(&self) -> Entries {
+        Entries { bytes: &self.table.body()[8..] }
+    }
+    /// GSI, and signalling, that an ISA IRQ arrives on
+    pub fn isa_irq(&self, irq: u8) -> (u32, Signal) {
+        self.entries()
+            .filter_map(|entry| match entry {
+                Entry::SourceOverride { irq: source, gsi, signal } if source == irq => Some((gsi, signal)),
+                _ => None,
+            })
+            .next()
+            .unwrap_or((irq as u32, ISA_SIGNAL))
+    }
+}
+
+pub struct Entries {
+    bytes: &'static [u8],
+}
+
+impl Iterator for Entries {
+    type Item = Entry;
+    fn next(&mut self) -> Option<Entry> {
+        if self.bytes.len() < 2 {
+            return None;
+        }
+        let kind = self.bytes[0];
+        let len = self.bytes[1] as usize;
+        if len < 2 || len > self.bytes.len() {
+            // Malformed, stop rather than walk off into the weeds
+            self.bytes = &[];
+            return None;
+        }
+        let entry = &self.bytes[..len];
+        self.bytes = &self.bytes[len..];
+        Some(unsafe {match kind {
+            TYPE_LOCAL_APIC if len >= 8 => Entry::LocalApic {
+                apic_id: entry[3] as u32,
+                enabled: read::<u32>(entry, 4) & LOCAL_APIC_ENABLED != 0,
+            },
+            TYPE_IO_APIC if len >= 12 => Entry::IoApic {
+                id: entry[2],
+                address: read::<u32>(entry, 4) as usize,
+                gsi_base: read(entry, 8),
+            },
+            TYPE_SOURCE_OVERRIDE if len >= 10 => Entry::SourceOverride {
+                irq: entry[3],
+                gsi: read(entry, 4),
+                signal: Signal::from_flags(read(entry, 8)),
+            },
+            TYPE_LOCAL_APIC_ADDRESS if len >= 12 => Entry::LocalApicAddress(read::<u64>(entry, 4) as usize),
+            TYPE_LOCAL_X2APIC if len >= 16 => Entry::LocalApic {
+                apic_id: read(entry, 4),
+                enabled: read::<u32>(entry, 8) & LOCAL_APIC_ENABLED != 0,
+            },
+            other => Entry::Other(other),
+        }})
+    }
+}
+
+/// Find the MADT
+pub fn get() -> Option<Madt> {
+    find_table(SIGNATURE).map(|table| Madt { table: table })
+}
@@ -0,0 +1,57 @@
+//! PCI Express Memory Mapped Configuration table
+//!
+//! Lists the ECAM regions, each covering a range of buses of a PCI segment.
+
+use super::find_table;
+use core::mem::size_of;
+use core::ptr;
+
+const SIGNATURE: &[u8; 4] = b"MCFG";
+
+/// Reserved bytes between the header and the first allocation
+const RESERVED: usize = 8;
+
+#[repr(C, packed)]
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+struct RawAllocation {
+    base_address: u64,
+    segment: u16,
+    start_bus: u8,
+    end_bus: u8,
+    reserved: u32,
+}
+
+/// An ECAM region
+#[derive(Debug, Clone, Copy)]
+pub struct Allocation {
+    /// Physical address of the configuration space of bus 0, even when `start_bus` is not 0
+    pub address: usize,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl Allocation {
+    /// Physical address range of the configuration space of the buses covered
+    pub fn range(&self) -> (usize, usize) {
+        (self.address + ((self.start_bus as usize) << 20), self.address + ((self.end_bus as usize + 1) << 20))
+    }
+}
+
+/// Iterate the ECAM regions, if there is an MCFG
+pub fn allocations() -> impl Iterator<Item = Allocation> {
+    let body = find_table(SIGNATURE).map_or(&[][..], |table| table.body());
+    let body = if body.len() > RESERVED { &body[RESERVED..] } else { &[] };
+    body.chunks(size_of::<RawAllocation>())
+        .filter(|chunk| chunk.len() == size_of::<RawAllocation>())
+        .map(|chunk| {
+            let raw: RawAllocation = unsafe {ptr::read_unaligned(chunk.as_ptr() as *const RawAllocation)};
+            Allocation {
+                address: raw.base_address as usize,
+                segment: raw.segment,
+                start_bus: raw.start_bus,
+                end_bus: raw.end_bus,
+            }
+        })
+}
@@ -0,0 +1,197 @@
+//! Discovery of static ACPI tables
+//!
+//! There is no AML interpreter, only the static tables that describe hardware are used. Multiboot
+//! v1 does not pass along the RSDP, so it is found by searching the BIOS areas, which limits this
+//! to legacy BIOS boots. All tables are accessed through the kernel window, and so must reside in
+//! the first 4GB of physical memory, which in practice they always do.
+
+pub mod madt;
+pub mod hpet;
+pub mod fadt;
+pub mod mcfg;
+
+use state::STATE;
+use vspace::Translation;
+use core::mem::size_of;
+use core::ptr;
+use core::slice;
+use core::str;
+
+/// Physical address of the word holding the EBDA segment
+const EBDA_SEGMENT_PADDR: usize = 0x40E;
+/// Size of the EBDA region that may contain the RSDP
+const EBDA_SEARCH_SIZE: usize = 1024;
+/// BIOS read only area that may contain the RSDP
+const BIOS_AREA: (usize, usize) = (0xE0000, 0x100000);
+/// RSDP is always on a 16 byte boundary
+const RSDP_ALIGN: usize = 16;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+/// Root System Description Pointer
+#[repr(C, packed)]
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // Fields from here on only exist from revision 2
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// Size of the revision 0 portion of the RSDP covered by the first checksum
+const RSDP_V1_SIZE: usize = 20;
+
+/// Header common to all system description tables
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+impl SdtHeader {
+    pub fn signature(&self) -> &str {
+        str::from_utf8(&self.signature).unwrap_or("????")
+    }
+    /// Bytes of the whole table, including the header
+    pub fn bytes(&self) -> &[u8] {
+        unsafe {slice::from_raw_parts(self as *const SdtHeader as *const u8, self.length as usize)}
+    }
+    /// Bytes of the table following the header
+    pub fn body(&self) -> &[u8] {
+        &self.bytes()[size_of::<SdtHeader>()..]
+    }
+}
+
+/// Generic Address Structure
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+/// `GenericAddress` address space for system memory
+pub const ADDRESS_SPACE_MEMORY: u8 = 0;
+/// `GenericAddress` address space for system I/O
+pub const ADDRESS_SPACE_IO: u8 = 1;
+
+/// Location of the root table, either the RSDT with 32-bit entries or the XSDT with 64-bit
+#[derive(Debug, Clone, Copy)]
+enum Root {
+    Rsdt(usize),
+    Xsdt(usize),
+}
+
+static mut ROOT: Option<Root> = None;
+
+fn checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) == 0
+}
+
+/// Retrieve a physical range through the kernel window
+unsafe fn phys_bytes(paddr: usize, len: usize) -> Option<&'static [u8]> {
+    STATE.kernel_as.paddr_to_vaddr_range(paddr..paddr + len)
+        .map(|vaddr| slice::from_raw_parts(vaddr.start as *const u8, len))
+}
+
+unsafe fn find_rsdp_in(start: usize, end: usize) -> Option<Rsdp> {
+    let area = phys_bytes(start, end - start)?;
+    area.chunks(RSDP_ALIGN)
+        .filter(|chunk| chunk.len() == RSDP_ALIGN && chunk.starts_with(RSDP_SIGNATURE))
+        .map(|chunk| chunk.as_ptr() as usize - area.as_ptr() as usize + start)
+        .filter_map(|paddr| {
+            let v1 = phys_bytes(paddr, RSDP_V1_SIZE)?;
+            if !checksum(v1) {
+                return None;
+            }
+            let rsdp = ptr::read_unaligned(phys_bytes(paddr, size_of::<Rsdp>())?.as_ptr() as *const Rsdp);
+            if rsdp.revision >= 2 && !checksum(phys_bytes(paddr, rsdp.length as usize)?) {
+                return None;
+            }
+            Some(rsdp)
+        })
+        .next()
+}
+
+unsafe fn find_rsdp() -> Option<Rsdp> {
+    let ebda = phys_bytes(EBDA_SEGMENT_PADDR, 2)
+        .map(|seg| ((seg[0] as usize) | (seg[1] as usize) << 8) << 4)
+        .filter(|ebda| *ebda != 0);
+    ebda.and_then(|ebda| find_rsdp_in(ebda, ebda + EBDA_SEARCH_SIZE))
+        .or_else(|| find_rsdp_in(BIOS_AREA.0, BIOS_AREA.1))
+}
+
+/// Retrieve a table by physical address, validating its checksum
+unsafe fn table_at(paddr: usize) -> Option<&'static SdtHeader> {
+    let header = phys_bytes(paddr, size_of::<SdtHeader>())?;
+    let header = &*(header.as_ptr() as *const SdtHeader);
+    if (header.length as usize) < size_of::<SdtHeader>() {
+        return None;
+    }
+    let bytes = phys_bytes(paddr, header.length as usize)?;
+    if checksum(bytes) { Some(header) } else { None }
+}
+
+/// Iterate the physical addresses of every table in the root table
+fn table_addresses() -> impl Iterator<Item = usize> {
+    let (root, entry_size) = match unsafe {ROOT} {
+        Some(Root::Rsdt(paddr)) => (unsafe {table_at(paddr)}, 4),
+        Some(Root::Xsdt(paddr)) => (unsafe {table_at(paddr)}, 8),
+        None => (None, 4),
+    };
+    root.map(|root| root.body()).unwrap_or(&[]).chunks(entry_size)
+        .filter(move |entry| entry.len() == entry_size)
+        .map(|entry| entry.iter().rev().fold(0usize, |acc, b| acc << 8 | *b as usize))
+}
+
+/// Find a table by its signature
+///
+/// Returns the first table with a valid checksum, or nothing if ACPI was not found
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    table_addresses()
+        .filter_map(|paddr| unsafe {table_at(paddr)})
+        .find(|table| &table.signature == signature)
+}
+
+/// Find and record the root ACPI table
+///
+/// Requires the kernel address space. Returns whether ACPI tables were found, without them
+/// everything falls back to conventional PC locations.
+pub fn init() -> bool {
+    let rsdp = match unsafe {find_rsdp()} {
+        Some(rsdp) => rsdp,
+        None => {
+            print!(Info, "No ACPI RSDP found");
+            return false;
+        },
+    };
+    let root = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        Root::Xsdt(rsdp.xsdt_address as usize)
+    } else {
+        Root::Rsdt(rsdp.rsdt_address as usize)
+    };
+    unsafe {ROOT = Some(root)};
+    print!(Info, "Found ACPI revision {} root table {:?}", rsdp.revision, root);
+    for table in table_addresses().filter_map(|paddr| unsafe {table_at(paddr)}) {
+        print!(Debug, "ACPI table {} revision {} length {}", table.signature(), table.revision, {table.length});
+    }
+    true
+}
@@ -0,0 +1,163 @@
+//! GUID partition tables
+//!
+//! The primary header is at sector 1, with a backup in the last sector of the disk, which is
+//! used if the primary is damaged. Both the header and the partition entries are protected by
+//! CRC32s, which must be correct for a table to be used.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use super::{Disk, Error};
+use super::partition::{read_u32, read_u64, Kind, Partition};
+
+const SIGNATURE: &[u8] = b"EFI PART";
+const HEADER_SIZE_OFFSET: usize = 12;
+const HEADER_CRC_OFFSET: usize = 16;
+const MY_LBA_OFFSET: usize = 24;
+const ENTRIES_LBA_OFFSET: usize = 72;
+const ENTRY_COUNT_OFFSET: usize = 80;
+const ENTRY_SIZE_OFFSET: usize = 84;
+const ENTRIES_CRC_OFFSET: usize = 88;
+/// Smallest header, as of revision 1.0
+const MIN_HEADER_SIZE: usize = 92;
+/// Smallest entry, which must also be a multiple of this
+const MIN_ENTRY_SIZE: usize = 128;
+/// Limit on the size of the entry array, well above the usual 16K, so that a corrupt count does
+/// not lead to reading the whole disk
+const MAX_ENTRIES_SIZE: usize = 1024 * 1024;
+
+const ENTRY_TYPE_OFFSET: usize = 0;
+const ENTRY_GUID_OFFSET: usize = 16;
+const ENTRY_FIRST_OFFSET: usize = 32;
+const ENTRY_LAST_OFFSET: usize = 40;
+const ENTRY_NAME_OFFSET: usize = 56;
+/// Name is up to 36 UTF-16 code units
+const ENTRY_NAME_LEN: usize = 72;
+
+/// GUID in its on disk, mixed endian, layout
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub fn is_nil(&self) -> bool {
+        self.0.iter().all(|&byte| byte == 0)
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(f, "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9])?;
+        for byte in &b[10..] {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// CRC32 as used by GPT, which is the common reflected 0x04C11DB7 variant
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn guid(bytes: &[u8]) -> Guid {
+    let mut guid = [0; 16];
+    guid.copy_from_slice(&bytes[..16]);
+    Guid(guid)
+}
+
+/// Read and check the header at `lba`, returning it
+unsafe fn header(disk: &mut Disk, lba: u64) -> Result<Option<Vec<u8>>, Error> {
+    let sector = disk.read(lba, 1)?;
+    if &sector[..SIGNATURE.len()] != SIGNATURE {
+        return Ok(None);
+    }
+    let size = read_u32(&sector, HEADER_SIZE_OFFSET) as usize;
+    if size < MIN_HEADER_SIZE || size > sector.len() || read_u64(&sector, MY_LBA_OFFSET) != lba {
+        return Ok(None);
+    }
+    let mut header = sector[..size].to_vec();
+    let crc = read_u32(&header, HEADER_CRC_OFFSET);
+    // The CRC is calculated with its own field zeroed
+    for byte in header[HEADER_CRC_OFFSET..HEADER_CRC_OFFSET + 4].iter_mut() {
+        *byte = 0;
+    }
+    Ok(if crc32(&header) == crc { Some(header) } else { None })
+}
+
+/// Read and check the partition entries a header describes
+unsafe fn entries(disk: &mut Disk, header: &[u8]) -> Result<Option<Vec<Partition>>, Error> {
+    let count = read_u32(header, ENTRY_COUNT_OFFSET) as usize;
+    let entry_size = read_u32(header, ENTRY_SIZE_OFFSET) as usize;
+    if entry_size < MIN_ENTRY_SIZE || entry_size % MIN_ENTRY_SIZE != 0 {
+        return Ok(None);
+    }
+    let len = match count.checked_mul(entry_size) {
+        Some(len) if len <= MAX_ENTRIES_SIZE => len,
+        _ => return Ok(None),
+    };
+    let sector_size = disk.sector_size();
+    let mut array = match disk.read(read_u64(header, ENTRIES_LBA_OFFSET), (len + sector_size - 1) / sector_size) {
+        Ok(array) => array,
+        Err(Error::OutOfRange) => return Ok(None),
+        Err(error) => return Err(error),
+    };
+    array.truncate(len);
+    if crc32(&array) != read_u32(header, ENTRIES_CRC_OFFSET) {
+        return Ok(None);
+    }
+    let mut partitions = Vec::new();
+    for (index, entry) in array.chunks(entry_size).enumerate() {
+        let type_guid = guid(&entry[ENTRY_TYPE_OFFSET..]);
+        let first = read_u64(entry, ENTRY_FIRST_OFFSET);
+        let last = read_u64(entry, ENTRY_LAST_OFFSET);
+        if type_guid.is_nil() || last < first {
+            continue;
+        }
+        let units: Vec<u16> = entry[ENTRY_NAME_OFFSET..ENTRY_NAME_OFFSET + ENTRY_NAME_LEN].chunks(2)
+            .map(|pair| pair[0] as u16 | (pair[1] as u16) << 8)
+            .take_while(|&unit| unit != 0)
+            .collect();
+        partitions.push(Partition {
+            index: index + 1,
+            start: first,
+            sectors: last - first + 1,
+            kind: Kind::Gpt {
+                type_guid: type_guid,
+                guid: guid(&entry[ENTRY_GUID_OFFSET..]),
+                name: String::from_utf16_lossy(&units),
+            },
+        });
+    }
+    Ok(Some(partitions))
+}
+
+/// Read the partitions from the primary GPT, or the backup if the primary is damaged
+///
+/// A disk with neither valid has no partitions.
+pub unsafe fn scan(disk: &mut Disk) -> Result<Vec<Partition>, Error> {
+    let last = disk.sectors() - 1;
+    for &lba in [1, last].iter() {
+        if let Some(header) = header(disk, lba)? {
+            if let Some(partitions) = entries(disk, &header)? {
+                return Ok(partitions);
+            }
+        }
+        print!(Error, "{} GPT of {} is damaged", if lba == 1 { "Primary" } else { "Backup" }, disk.name());
+    }
+    Ok(Vec::new())
+}
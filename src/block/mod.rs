@@ -0,0 +1,233 @@
+//! Block devices
+//!
+//! Storage drivers implement `BlockDevice`, which transfers whole sectors synchronously, and
+//! register it. Each registered device becomes a `Disk`, which is given a name from the prefix
+//! the driver asked for, or one the driver made itself, has its partition table read, and queues
+//! requests to the device.
+//!
+//! Access to disks is sector granular through the `Disk`, with transfers always being a whole
+//! number of sectors in length.
+
+mod queue;
+mod partition;
+mod gpt;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+pub use self::queue::{Op, Request, RequestId, RequestQueue};
+pub use self::partition::{Kind, Partition};
+pub use self::gpt::Guid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Sectors beyond the end of the device
+    OutOfRange,
+    /// Buffer that is not a whole number of sectors
+    Misaligned,
+    /// Write to a read only device
+    ReadOnly,
+    /// Device does not support the operation
+    Unsupported,
+    /// Device reported an error
+    Io,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            Error::OutOfRange => "out of range",
+            Error::Misaligned => "not a whole number of sectors",
+            Error::ReadOnly => "read only",
+            Error::Unsupported => "unsupported",
+            Error::Io => "I/O error",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Storage that is read and written in sectors
+///
+/// Buffers are always a whole number of sectors, and the range is within the device, as this
+/// is checked before the device is called.
+pub trait BlockDevice {
+    /// Size of a sector in bytes
+    fn sector_size(&self) -> usize;
+    /// Number of sectors on the device
+    fn sectors(&self) -> u64;
+    fn read_only(&self) -> bool;
+    unsafe fn read(&mut self, sector: u64, buffer: &mut [u8]) -> Result<(), Error>;
+    unsafe fn write(&mut self, sector: u64, buffer: &[u8]) -> Result<(), Error>;
+    /// Ensure completed writes are persistent
+    unsafe fn flush(&mut self) -> Result<(), Error>;
+}
+
+/// Display a size in bytes in the largest binary unit that keeps it above 1
+struct Size(u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+        let mut size = self.0;
+        let mut unit = 0;
+        while size >= 1024 * 10 && unit + 1 < units.len() {
+            size /= 1024;
+            unit += 1;
+        }
+        write!(f, "{} {}", size, units[unit])
+    }
+}
+
+/// A registered block device
+pub struct Disk {
+    name: String,
+    device: Box<BlockDevice>,
+    queue: RequestQueue,
+    partitions: Vec<Partition>,
+}
+
+impl Disk {
+    pub fn new(name: String, device: Box<BlockDevice>) -> Disk {
+        Disk { name: name, device: device, queue: RequestQueue::new(), partitions: Vec::new() }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn sector_size(&self) -> usize {
+        self.device.sector_size()
+    }
+    pub fn sectors(&self) -> u64 {
+        self.device.sectors()
+    }
+    pub fn read_only(&self) -> bool {
+        self.device.read_only()
+    }
+    /// Size of the disk in bytes
+    pub fn size(&self) -> u64 {
+        self.sectors() * self.sector_size() as u64
+    }
+    pub fn partitions(&self) -> &[Partition] {
+        &self.partitions
+    }
+    /// Queue a request to be performed by `run`
+    pub fn submit(&mut self, request: Request) -> RequestId {
+        self.queue.submit(request)
+    }
+    /// Perform all queued requests in order
+    pub unsafe fn run(&mut self) {
+        self.queue.run(&mut *self.device)
+    }
+    /// Take a finished request, with its buffer and result
+    pub fn complete(&mut self, id: RequestId) -> Option<(Request, Result<(), Error>)> {
+        self.queue.complete(id)
+    }
+    unsafe fn perform(&mut self, request: Request) -> (Request, Result<(), Error>) {
+        let id = self.submit(request);
+        self.run();
+        self.complete(id).expect("Request lost from the queue")
+    }
+    /// Read `count` sectors starting at `sector`
+    pub unsafe fn read(&mut self, sector: u64, count: usize) -> Result<Vec<u8>, Error> {
+        let len = count.checked_mul(self.sector_size()).ok_or(Error::OutOfRange)?;
+        let (request, result) = self.perform(Request::read(sector, len));
+        result.map(|_| request.buffer)
+    }
+    /// Write whole sectors starting at `sector`
+    pub unsafe fn write(&mut self, sector: u64, data: &[u8]) -> Result<(), Error> {
+        self.perform(Request::write(sector, data.to_vec())).1
+    }
+    pub unsafe fn flush(&mut self) -> Result<(), Error> {
+        self.perform(Request::flush()).1
+    }
+    /// Read the partition table, replacing any partitions found before
+    pub unsafe fn scan_partitions(&mut self) -> Result<(), Error> {
+        self.partitions = partition::scan(self)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Disk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} sectors of {} bytes ({}){}", self.name, self.sectors(), self.sector_size(),
+            Size(self.size()), if self.read_only() { " read only" } else { "" })
+    }
+}
+
+static mut DISKS: Option<Vec<Disk>> = None;
+
+fn disks_mut() -> &'static mut Vec<Disk> {
+    unsafe {DISKS.get_or_insert_with(Vec::new)}
+}
+
+/// Letters numbering a disk, `a` to `z`, then `aa` and so on
+fn letters(mut n: usize) -> String {
+    let mut suffix = Vec::new();
+    loop {
+        suffix.push(b'a' + (n % 26) as u8);
+        if n < 26 {
+            break;
+        }
+        n = n / 26 - 1;
+    }
+    suffix.reverse();
+    String::from_utf8(suffix).unwrap()
+}
+
+/// First name with `prefix` that no disk has, such as `vda`, `vdb`, ... `vdz`, `vdaa`
+fn next_name(prefix: &str) -> String {
+    (0..).map(|n| format!("{}{}", prefix, letters(n)))
+        .find(|name| find(name).is_none())
+        .unwrap()
+}
+
+/// Add a block device, returning the name it was given
+///
+/// The name is `prefix` followed by the first letters that are free, so a name is only reused
+/// once the disk that had it is removed. The partition table is read and listed along with the
+/// device.
+pub fn register(prefix: &str, device: Box<BlockDevice>) -> String {
+    let name = next_name(prefix);
+    add(Disk::new(name.clone(), device));
+    name
+}
+
+/// Add a block device under a name the driver chose, failing if a disk already has it
+pub fn register_as(name: String, device: Box<BlockDevice>) -> bool {
+    if find(&name).is_some() {
+        print!(Error, "Disk {} already exists", name);
+        return false;
+    }
+    add(Disk::new(name, device));
+    true
+}
+
+fn add(mut disk: Disk) {
+    print!(Info, "Disk {}", disk);
+    // A name ending in a digit would run into the partition number, as in `nvme0n1p1`
+    let separator = if disk.name.ends_with(|c: char| c.is_ascii_digit()) { "p" } else { "" };
+    match unsafe {disk.scan_partitions()} {
+        Ok(()) => for partition in disk.partitions() {
+            print!(Info, "    {}{}{} {}", disk.name, separator, partition.index, partition);
+        },
+        Err(error) => print!(Error, "Failed to read partitions of {}: {}", disk.name, error),
+    }
+    disks_mut().push(disk);
+}
+
+/// Remove a block device by name, returning it
+pub fn unregister(name: &str) -> Option<Disk> {
+    let disks = disks_mut();
+    let index = disks.iter().position(|disk| disk.name == name)?;
+    Some(disks.remove(index))
+}
+
+/// Every registered disk
+pub fn disks() -> &'static mut [Disk] {
+    disks_mut()
+}
+
+pub fn find(name: &str) -> Option<&'static mut Disk> {
+    disks_mut().iter_mut().find(|disk| disk.name == name)
+}
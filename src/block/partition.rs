@@ -0,0 +1,106 @@
+//! Partition tables
+//!
+//! The MBR is read from the first sector. If it only holds a protective partition then the disk
+//! is GPT, and the partitions are taken from the GPT instead. Only the primary MBR partitions
+//! are listed, extended partitions are not walked for the logical ones within.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use super::{Disk, Error};
+use super::gpt::{self, Guid};
+
+const MBR_SIGNATURE_OFFSET: usize = 510;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const MBR_ENTRIES_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_ENTRIES: usize = 4;
+
+const MBR_TYPE_OFFSET: usize = 4;
+const MBR_START_OFFSET: usize = 8;
+const MBR_SECTORS_OFFSET: usize = 12;
+
+const MBR_TYPE_EMPTY: u8 = 0x00;
+/// Partition covering the disk to protect a GPT from tools that only understand MBR
+const MBR_TYPE_PROTECTIVE: u8 = 0xEE;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// MBR partition with its system ID
+    Mbr(u8),
+    Gpt { type_guid: Guid, guid: Guid, name: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    /// Number of the partition, from 1, as its position in the table
+    pub index: usize,
+    /// First sector
+    pub start: u64,
+    pub sectors: u64,
+    pub kind: Kind,
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "sectors {} to {}", self.start, self.start + self.sectors - 1)?;
+        match self.kind {
+            Kind::Mbr(id) => write!(f, " type {:#04x}", id),
+            Kind::Gpt { ref type_guid, ref name, .. } => write!(f, " type {} \"{}\"", type_guid, name),
+        }
+    }
+}
+
+pub fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut value = 0;
+    for i in (0..4).rev() {
+        value = value << 8 | bytes[offset + i] as u32;
+    }
+    value
+}
+
+pub fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    read_u32(bytes, offset) as u64 | (read_u32(bytes, offset + 4) as u64) << 32
+}
+
+/// Entries of an MBR as (index, system ID, start, sectors)
+fn mbr_entries(sector: &[u8]) -> Option<Vec<(usize, u8, u64, u64)>> {
+    if sector[MBR_SIGNATURE_OFFSET..MBR_SIGNATURE_OFFSET + 2] != MBR_SIGNATURE {
+        return None;
+    }
+    Some((0..MBR_ENTRIES).filter_map(|index| {
+        let entry = &sector[MBR_ENTRIES_OFFSET + index * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        let id = entry[MBR_TYPE_OFFSET];
+        let start = read_u32(entry, MBR_START_OFFSET) as u64;
+        let sectors = read_u32(entry, MBR_SECTORS_OFFSET) as u64;
+        if id == MBR_TYPE_EMPTY || sectors == 0 {
+            None
+        } else {
+            Some((index + 1, id, start, sectors))
+        }
+    }).collect())
+}
+
+/// Read the partitions of a disk
+///
+/// A disk with no partition table has no partitions. Partitions that extend past the end of
+/// the disk are dropped.
+pub unsafe fn scan(disk: &mut Disk) -> Result<Vec<Partition>, Error> {
+    if disk.sector_size() < MBR_SIGNATURE_OFFSET + 2 || disk.sectors() == 0 {
+        return Ok(Vec::new());
+    }
+    let mbr = disk.read(0, 1)?;
+    let entries = match mbr_entries(&mbr) {
+        Some(entries) => entries,
+        None => return Ok(Vec::new()),
+    };
+    let partitions = if entries.iter().any(|entry| entry.1 == MBR_TYPE_PROTECTIVE) {
+        gpt::scan(disk)?
+    } else {
+        entries.into_iter()
+            .map(|(index, id, start, sectors)| Partition { index: index, start: start, sectors: sectors, kind: Kind::Mbr(id) })
+            .collect()
+    };
+    let end = disk.sectors();
+    Ok(partitions.into_iter().filter(|partition| partition.start.checked_add(partition.sectors).map_or(false, |top| top <= end)).collect())
+}
@@ -0,0 +1,102 @@
+//! Requests to a block device
+//!
+//! Requests are queued and performed in the order submitted, each owning its buffer, which is
+//! handed back along with the result once the request is complete. Requests are checked
+//! against the device before it is asked to perform them.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use super::{BlockDevice, Error};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Read,
+    Write,
+    Flush,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub op: Op,
+    /// First sector, ignored for a flush
+    pub sector: u64,
+    /// Data to write, or space to read into
+    pub buffer: Vec<u8>,
+}
+
+impl Request {
+    /// Read `len` bytes, which must be a whole number of sectors, starting at `sector`
+    pub fn read(sector: u64, len: usize) -> Request {
+        Request { op: Op::Read, sector: sector, buffer: vec![0; len] }
+    }
+    pub fn write(sector: u64, data: Vec<u8>) -> Request {
+        Request { op: Op::Write, sector: sector, buffer: data }
+    }
+    pub fn flush() -> Request {
+        Request { op: Op::Flush, sector: 0, buffer: Vec::new() }
+    }
+    /// Check that the request fits the device
+    fn check(&self, device: &BlockDevice) -> Result<(), Error> {
+        if self.op == Op::Flush {
+            return Ok(());
+        }
+        if self.op == Op::Write && device.read_only() {
+            return Err(Error::ReadOnly);
+        }
+        let sector_size = device.sector_size();
+        if self.buffer.len() % sector_size != 0 {
+            return Err(Error::Misaligned);
+        }
+        let count = (self.buffer.len() / sector_size) as u64;
+        match self.sector.checked_add(count) {
+            Some(end) if end <= device.sectors() => Ok(()),
+            _ => Err(Error::OutOfRange),
+        }
+    }
+}
+
+/// Identifies a submitted request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RequestId(u64);
+
+pub struct RequestQueue {
+    next_id: u64,
+    pending: VecDeque<(RequestId, Request)>,
+    done: Vec<(RequestId, Request, Result<(), Error>)>,
+}
+
+impl RequestQueue {
+    pub fn new() -> RequestQueue {
+        RequestQueue { next_id: 0, pending: VecDeque::new(), done: Vec::new() }
+    }
+    pub fn submit(&mut self, request: Request) -> RequestId {
+        let id = RequestId(self.next_id);
+        self.next_id += 1;
+        self.pending.push_back((id, request));
+        id
+    }
+    /// Number of requests not yet performed
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+    /// Perform every pending request, in order, on `device`
+    pub unsafe fn run(&mut self, device: &mut BlockDevice) {
+        while let Some((id, mut request)) = self.pending.pop_front() {
+            let result = match request.check(device) {
+                Err(error) => Err(error),
+                Ok(()) => match request.op {
+                    Op::Read => device.read(request.sector, &mut request.buffer),
+                    Op::Write => device.write(request.sector, &request.buffer),
+                    Op::Flush => device.flush(),
+                },
+            };
+            self.done.push((id, request, result));
+        }
+    }
+    /// Take a performed request
+    pub fn complete(&mut self, id: RequestId) -> Option<(Request, Result<(), Error>)> {
+        let index = self.done.iter().position(|done| done.0 == id)?;
+        let (_, request, result) = self.done.remove(index);
+        Some((request, result))
+    }
+}
@@ -0,0 +1,128 @@
+//! Matching devices to drivers, apart from how either is found
+//!
+//! Each driver is offered every device it matches, until one takes it. Devices that can only be
+//! found by probing, such as legacy ISA ones, are offered to their driver exactly once.
+
+use alloc::vec::Vec;
+use core::fmt;
+use decls::{Driver, Match};
+use drivers::pci::Function;
+
+/// A device a driver can be bound to
+#[derive(Clone, Copy)]
+pub enum Device {
+    Pci(&'static Function),
+    /// Device in the ACPI namespace, by hardware ID
+    Acpi(&'static str),
+    /// Legacy device the driver has to probe for itself
+    Isa,
+}
+
+impl Device {
+    /// Whether two devices are the same one
+    ///
+    /// Legacy devices are not anything in particular until a driver has probed for one, so are
+    /// never the same as another
+    pub fn is(&self, other: &Device) -> bool {
+        match (*self, *other) {
+            (Device::Pci(a), Device::Pci(b)) => a.address == b.address,
+            (Device::Acpi(a), Device::Acpi(b)) => a == b,
+            _ => false,
+        }
+    }
+    pub fn matches(&self, m: &Match) -> bool {
+        match (*self, m) {
+            (Device::Pci(function), &Match::Pci { vendor, device, class }) =>
+                vendor.map_or(true, |vendor| vendor == function.vendor)
+                    && device.map_or(true, |device| device == function.device)
+                    && class.map_or(true, |class| class == (function.class, function.subclass)),
+            (Device::Acpi(hid), &Match::Acpi(id)) => hid == id,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Device::Pci(function) => write!(f, "PCI {}", function.address),
+            Device::Acpi(hid) => write!(f, "ACPI {}", hid),
+            Device::Isa => write!(f, "ISA"),
+        }
+    }
+}
+
+/// A device with the driver that took it
+pub struct Binding {
+    pub driver: &'static Driver,
+    pub device: Device,
+}
+
+/// Every device that has a driver
+pub struct Bindings {
+    bindings: Vec<Binding>,
+}
+
+impl Bindings {
+    pub fn new() -> Bindings {
+        Bindings { bindings: Vec::new() }
+    }
+    /// Whether a device already has a driver
+    ///
+    /// Legacy devices are identified only by their driver, as each driver probes for its own
+    pub fn bound(&self, driver: &'static Driver, device: &Device) -> bool {
+        self.bindings.iter().any(|binding| match *device {
+            Device::Isa => binding.driver as *const Driver == driver as *const Driver,
+            _ => binding.device.is(device),
+        })
+    }
+    fn try_bind(&mut self, driver: &'static Driver, device: Device) {
+        if self.bound(driver, &device) || !(driver.probe)(&device) {
+            return;
+        }
+        print!(Info, "Bound {} to {}", driver.name, device);
+        self.bindings.push(Binding { driver: driver, device: device });
+    }
+    /// Offer the PCI functions and ACPI devices to the drivers that match them
+    ///
+    /// Devices already bound are skipped, so this can be called again after more devices appear.
+    pub fn bind<I>(&mut self, drivers: I, functions: &'static [Function], acpi: &[&'static str])
+        where I: Iterator<Item = &'static Driver> {
+        for driver in drivers {
+            for m in driver.matches {
+                match *m {
+                    Match::Pci { .. } => {
+                        for function in functions {
+                            let device = Device::Pci(function);
+                            if device.matches(m) {
+                                self.try_bind(driver, device);
+                            }
+                        }
+                    },
+                    Match::Acpi(_) => {
+                        for &hid in acpi {
+                            let device = Device::Acpi(hid);
+                            if device.matches(m) {
+                                self.try_bind(driver, device);
+                            }
+                        }
+                    },
+                    Match::Isa => self.try_bind(driver, Device::Isa),
+                }
+            }
+        }
+    }
+    /// Remove the driver from a PCI or ACPI device
+    ///
+    /// Legacy devices cannot go away, so are never unbound
+    pub fn unbind(&mut self, device: &Device) {
+        if let Some(index) = self.bindings.iter().position(|binding| binding.device.is(device)) {
+            let binding = self.bindings.remove(index);
+            (binding.driver.remove)(&binding.device);
+            print!(Info, "Unbound {} from {}", binding.driver.name, binding.device);
+        }
+    }
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+}
@@ -0,0 +1,58 @@
+//! System buses and the devices found on them
+//!
+//! The drivers for the buses themselves are generic and live in `drivers`, this is where the
+//! instances for this system are found and kept.
+//!
+//! Device drivers declare themselves with `make_driver_decl!`, listing what they match. Once the
+//! buses have been enumerated `bind` offers every discovered device to the drivers, as `binding`
+//! describes.
+
+use alloc::vec::Vec;
+use acpi;
+
+pub mod binding;
+pub mod pci;
+
+pub use self::binding::{Binding, Device};
+use self::binding::Bindings;
+
+/// Hardware ID of the HPET in the ACPI namespace
+const HID_HPET: &str = "PNP0103";
+
+static mut BINDINGS: Option<Bindings> = None;
+
+fn bindings_mut() -> &'static mut Bindings {
+    unsafe {BINDINGS.get_or_insert_with(Bindings::new)}
+}
+
+/// Devices in the ACPI namespace
+///
+/// There is no AML interpreter, so these are only the devices that the static tables describe.
+/// No driver matches one yet, as the HPET is used by `time` before drivers are bound.
+fn acpi_devices() -> Vec<&'static str> {
+    let mut devices = Vec::new();
+    if acpi::hpet::get().is_some() {
+        devices.push(HID_HPET);
+    }
+    devices
+}
+
+/// Offer every discovered device to the drivers that match it
+///
+/// Requires the buses to have been enumerated. Devices already bound are skipped, so this can
+/// be called again after more devices appear.
+pub fn bind() {
+    bindings_mut().bind(decls_iter!(Driver), pci::registry().functions(), &acpi_devices());
+}
+
+/// Remove the driver from a PCI or ACPI device
+///
+/// Legacy devices cannot go away, so are never unbound
+pub fn unbind(device: &Device) {
+    bindings_mut().unbind(device)
+}
+
+/// Every device that has a driver
+pub fn bindings() -> &'static [Binding] {
+    bindings_mut().bindings()
+}
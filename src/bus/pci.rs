@@ -0,0 +1,105 @@
+//! The PCI segment of the system
+//!
+//! Configuration space is accessed through ECAM when ACPI describes it, falling back to the
+//! legacy ports otherwise. Only segment 0 is enumerated.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use drivers::io::{MemIO, PortIO};
+use drivers::pci::{Address, ConfigAccess, Config, Ecam, Function, Legacy, Registry, LEGACY_PORT};
+use acpi::mcfg;
+use state::STATE;
+use cpu::MemoryType;
+
+const SEGMENT: u16 = 0;
+
+static mut ACCESS: Option<Box<ConfigAccess>> = None;
+static mut REGISTRY: Option<Registry> = None;
+/// Memory BARs already mapped, by function and index, with their virtual address
+static mut BAR_MAPPINGS: Option<Vec<(Address, u8, usize)>> = None;
+
+fn ecam() -> Option<Ecam<MemIO<u32>>> {
+    let allocation = mcfg::allocations().find(|allocation| allocation.segment == SEGMENT)?;
+    let (start, end) = allocation.range();
+    let vaddr = unsafe {STATE.kernel_as.map_device(start..end, MemoryType::StrongUC)}?;
+    print!(Info, "Using PCIe ECAM at {:#x} for buses {} to {}", start, allocation.start_bus, allocation.end_bus);
+    Some(Ecam::new(unsafe {MemIO::new(vaddr)}, SEGMENT, allocation.start_bus, allocation.end_bus))
+}
+
+/// Enumerate every PCI function and list them
+///
+/// Requires the kernel address space and ACPI
+pub fn init() {
+    let mut access: Box<ConfigAccess> = match ecam() {
+        Some(ecam) => box ecam,
+        None => {
+            print!(Info, "Using legacy PCI configuration ports");
+            box Legacy::new(PortIO::<u32>::new(LEGACY_PORT))
+        },
+    };
+    let mut registry = Registry::new();
+    unsafe {registry.scan(&mut *access, SEGMENT)};
+    print!(Info, "Found {} PCI functions", registry.functions().len());
+    for function in registry.functions() {
+        print!(Info, "PCI {}", function);
+        for (index, bar) in function.bars.iter().enumerate() {
+            if let Some(bar) = *bar {
+                print!(Debug, "    BAR{} {}", index, bar);
+            }
+        }
+        if function.interrupt_pin != 0 {
+            print!(Debug, "    INT{} IRQ {}", (b'A' + function.interrupt_pin - 1) as char, function.interrupt_line);
+        }
+    }
+    unsafe {
+        ACCESS = Some(access);
+        REGISTRY = Some(registry);
+    }
+}
+
+/// Configuration space access for the segment
+///
+/// # Panics
+///
+/// If `init` has not yet been called
+pub fn access() -> &'static mut ConfigAccess {
+    unsafe {ACCESS.as_mut()}.map(|access| &mut **access).expect("PCI used before init")
+}
+
+/// Every function found on the segment
+///
+/// # Panics
+///
+/// If `init` has not yet been called
+pub fn registry() -> &'static Registry {
+    unsafe {REGISTRY.as_ref()}.expect("PCI used before init")
+}
+
+/// Configuration space of a function
+pub fn config(function: &Function) -> Config<'static> {
+    function.config(access())
+}
+
+/// Map a memory BAR of a function, returning its virtual address
+///
+/// Each BAR is only mapped once, with later calls returning the same mapping. BARs are mapped
+/// uncacheable, so this is not suitable for framebuffers.
+pub fn map_bar(function: &Function, index: u8) -> Option<usize> {
+    map_bar_as(function, index, MemoryType::StrongUC)
+}
+
+/// Map a memory BAR of a function with the given memory type, returning its virtual address
+///
+/// As with `map_bar`, a BAR that is already mapped keeps its first mapping, whatever type it
+/// was given.
+pub fn map_bar_as(function: &Function, index: u8, mt: MemoryType) -> Option<usize> {
+    let mappings = unsafe {BAR_MAPPINGS.get_or_insert_with(Vec::new)};
+    if let Some(&(_, _, vaddr)) = mappings.iter().find(|m| m.0 == function.address && m.1 == index) {
+        return Some(vaddr);
+    }
+    let (address, size) = function.bars.get(index as usize)?.and_then(|bar| bar.memory())?;
+    let start = address as usize;
+    let vaddr = unsafe {STATE.kernel_as.map_device(start..start.checked_add(size as usize)?, mt)}?;
+    mappings.push((function.address, index, vaddr));
+    Some(vaddr)
+}
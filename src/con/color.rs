@@ -0,0 +1,131 @@
+//! Colours of console output
+//!
+//! Each verbosity level is printed in its own colour. Colours are the 16 of the VGA text mode,
+//! which ANSI terminals also have, and are chosen per con with args such as `info=cyan`.
+
+use core::str::FromStr;
+
+use super::V;
+
+/// Colours in the order of the VGA palette
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Color {
+    Black = 0,
+    Blue = 1,
+    Green = 2,
+    Cyan = 3,
+    Red = 4,
+    Magenta = 5,
+    Brown = 6,
+    LightGray = 7,
+    DarkGray = 8,
+    LightBlue = 9,
+    LightGreen = 10,
+    LightCyan = 11,
+    LightRed = 12,
+    Pink = 13,
+    Yellow = 14,
+    White = 15,
+}
+
+impl Color {
+    /// VGA attribute for this colour on a black background
+    pub fn vga(self) -> u8 {
+        self as u8
+    }
+    /// Parameters of the ANSI SGR sequence that selects this colour
+    ///
+    /// Bright colours are selected as bold, which is the only way older terminals have of
+    /// showing them.
+    pub fn ansi(self) -> (u8, u8) {
+        let i = self as u8;
+        // VGA puts blue in the low bit where ANSI puts red
+        let code = 30 + ((i & 1) << 2 | (i & 2) | (i & 4) >> 2);
+        (if i & 8 != 0 { 1 } else { 0 }, code)
+    }
+}
+
+impl FromStr for Color {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Ok(match s {
+            "black" => Color::Black,
+            "blue" => Color::Blue,
+            "green" => Color::Green,
+            "cyan" => Color::Cyan,
+            "red" => Color::Red,
+            "magenta" => Color::Magenta,
+            "brown" => Color::Brown,
+            "lightgray" => Color::LightGray,
+            "darkgray" => Color::DarkGray,
+            "lightblue" => Color::LightBlue,
+            "lightgreen" => Color::LightGreen,
+            "lightcyan" => Color::LightCyan,
+            "lightred" => Color::LightRed,
+            "pink" => Color::Pink,
+            "yellow" => Color::Yellow,
+            "white" => Color::White,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// Colour of each verbosity level
+#[derive(Debug, Clone, Copy)]
+pub struct Colors {
+    pub panic: Color,
+    pub error: Color,
+    pub info: Color,
+    pub debug: Color,
+    pub trace: Color,
+}
+
+impl Colors {
+    pub fn get(&self, v: V) -> Color {
+        match v {
+            V::Panic => self.panic,
+            V::Error => self.error,
+            V::Info => self.info,
+            V::Debug => self.debug,
+            V::Trace => self.trace,
+        }
+    }
+    /// Handle a con arg of the form `LEVEL=COLOR`, such as `debug=darkgray`
+    ///
+    /// Fails if either the level or the colour is unknown, so this can be the last case when
+    /// matching the args of a con.
+    pub fn set(&mut self, level: &str, color: &str) -> Result<(), ()> {
+        let color = color.parse()?;
+        match level {
+            "panic" => self.panic = color,
+            "error" => self.error = color,
+            "info" => self.info = color,
+            "debug" => self.debug = color,
+            "trace" => self.trace = color,
+            _ => return Err(()),
+        }
+        Ok(())
+    }
+}
+
+/// When a con that can show colour should
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Whenever the con believes it can
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorMode {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(()),
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! Code page 437, the character set of the VGA text mode font
+//!
+//! The BIOS font has glyphs for ASCII, accented Latin letters, box drawing, a few Greek letters
+//! and mathematical symbols, and a set of dingbats in place of the control characters. Anything
+//! else has no glyph and is shown as `REPLACEMENT`.
+
+/// Shown for characters that have no glyph, a small square
+pub const REPLACEMENT: u8 = 0xFE;
+
+/// Characters shown by the glyphs that take the place of the control characters, from 0x01
+const LOW: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+/// Characters shown by the glyphs from 0x80
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Glyph that shows `c`, if there is one
+///
+/// Control characters have no glyph, as the glyphs in their place are for dingbats.
+pub fn from_char(c: char) -> Option<u8> {
+    match c {
+        ' ' ..= '~' => Some(c as u8),
+        '⌂' => Some(0x7F),
+        // Letters that are drawn the same as ones that have a glyph
+        'β' => Some(0xE1),
+        'μ' => Some(0xE6),
+        '\u{2126}' => Some(0xEA),
+        _ => LOW.iter().position(|&low| low == c).map(|i| i as u8 + 0x01)
+            .or_else(|| HIGH.iter().position(|&high| high == c).map(|i| i as u8 + 0x80)),
+    }
+}
+
+/// Glyph that best shows `c`, which is `REPLACEMENT` for anything without its own glyph
+pub fn encode(c: char) -> u8 {
+    from_char(c).unwrap_or(REPLACEMENT)
+}
@@ -0,0 +1,52 @@
+//! Console on the QEMU and Bochs debug port
+//!
+//! The quickest way to get a log out of QEMU, with `-debugcon stdio` or `-debugcon file:log`.
+//! There is no way to show colour, so each line starts with its level instead.
+
+use core::fmt;
+use drivers::io::PortIO;
+use drivers::qemu::{DebugCon, DEBUGCON_PORT};
+
+use super::{Con, EarlyCon, V};
+
+pub struct ConDebug {
+    port: Option<DebugCon<PortIO<u8>>>,
+}
+
+static mut EARLY_DEBUGCON: ConDebug = ConDebug { port: None };
+
+impl Con for ConDebug {
+    fn print(&mut self, s: &str) -> fmt::Result {
+        if let Some(ref mut port) = self.port {
+            unsafe {port.write(s.as_bytes())};
+        }
+        Ok(())
+    }
+    fn prepare(&mut self, v: V) -> fmt::Result {
+        fmt::Write::write_fmt(self as &mut EarlyCon, format_args!("{:?}: ", v))
+    }
+    fn end(&mut self) -> fmt::Result {
+        self.print("\n")
+    }
+}
+
+impl EarlyCon for ConDebug {
+    fn shutdown(&mut self) {
+        self.port = None;
+    }
+    fn is_physical(&self) -> bool {
+        false
+    }
+}
+
+impl ConDebug {
+    /// Takes no args, and fails on anything without a debug port, such as real hardware
+    pub fn early_init(args: &str) -> Result<&'static mut EarlyCon, ()> {
+        if !args.is_empty() {
+            return Err(());
+        }
+        let port = unsafe {DebugCon::new(PortIO::new(DEBUGCON_PORT))}.ok_or(())?;
+        unsafe {EARLY_DEBUGCON.port = Some(port)};
+        Ok(unsafe{&mut EARLY_DEBUGCON})
+    }
+}
@@ -0,0 +1,174 @@
+//! Console drawn on the primary framebuffer
+//!
+//! Once a display driver has left text mode there is no VGA text memory to print to, so this
+//! draws the same grid of cells in the font the BIOS left, keeping its history in a `Scrollback`
+//! just as the VGA console does. Nothing is drawn while there is no primary framebuffer, and a
+//! framebuffer of another size starts the history over.
+
+use core::fmt;
+use drivers::framebuffer::{self, Framebuffer, VGA_PALETTE};
+use drivers::vga::FONT_WINDOW;
+use state::STATE;
+use vspace::Translation;
+
+use super::{Con, V};
+use super::scrollback::{Cell, Scrollback, HISTORY_CELLS};
+use super::cp437;
+use super::color::Colors;
+use super::vga::{bios_font, VGA_COLORS};
+
+const GLYPH_WIDTH: usize = 8;
+const GLYPH_HEIGHT: usize = 16;
+/// What the screen is cleared to, the same as the VGA console
+const BLANK: Cell = Cell::new(b' ', 0xb);
+
+static mut HISTORY: Scrollback = Scrollback::new();
+
+pub struct ConFramebuffer {
+    font: &'static [u8],
+    /// Size in cells of the framebuffer the history is of, zero before there has been one
+    columns: u16,
+    rows: u16,
+    cursor_x: u16,
+    scroll_next: bool,
+    active_color: u8,
+    colors: Colors,
+    history: &'static mut Scrollback,
+}
+
+fn foreground(color: u8) -> framebuffer::Color {
+    VGA_PALETTE[(color & 0xF) as usize]
+}
+
+fn background(color: u8) -> framebuffer::Color {
+    VGA_PALETTE[(color >> 4) as usize]
+}
+
+impl ConFramebuffer {
+    /// The primary framebuffer, starting the history over if its size has changed
+    fn screen(&mut self) -> Option<&'static mut Framebuffer> {
+        let fb = framebuffer::primary()?;
+        let columns = fb.width() / GLYPH_WIDTH;
+        if columns == 0 || columns > HISTORY_CELLS {
+            return None;
+        }
+        // A screen too large for the history is only partly used, rather than having none
+        let rows = (fb.height() / GLYPH_HEIGHT).min(HISTORY_CELLS / columns);
+        if rows == 0 {
+            return None;
+        }
+        if (columns as u16, rows as u16) != (self.columns, self.rows) {
+            self.columns = columns as u16;
+            self.rows = rows as u16;
+            self.cursor_x = 0;
+            self.history.reset(columns as u16, rows as u16, BLANK);
+            fb.clear(background(BLANK.color));
+        }
+        Some(fb)
+    }
+    fn draw(&self, fb: &mut Framebuffer, x: u16, y: u16, cell: Cell) {
+        let glyph = &self.font[cell.ch as usize * GLYPH_HEIGHT..][..GLYPH_HEIGHT];
+        fb.draw_glyph(x as usize * GLYPH_WIDTH, y as usize * GLYPH_HEIGHT, glyph, foreground(cell.color), background(cell.color));
+    }
+    fn put_at_cursor(&mut self, fb: &mut Framebuffer, c: u8) {
+        let (x, y) = (self.cursor_x, self.rows - 1);
+        let cell = Cell::new(c, self.active_color);
+        self.history.put(x, cell);
+        if self.history.is_live() {
+            self.draw(fb, x, y, cell);
+        }
+        self.cursor_x += 1;
+        if self.cursor_x == self.columns {
+            self.next_line(fb);
+        }
+    }
+    fn next_line(&mut self, fb: &mut Framebuffer) {
+        self.cursor_x = 0;
+        self.history.new_line();
+        if !self.history.is_live() {
+            // The view stays on the lines it was showing
+            return;
+        }
+        let bottom = (self.rows as usize - 1) * GLYPH_HEIGHT;
+        fb.copy_rows(0, GLYPH_HEIGHT, bottom);
+        fb.fill_rect(0, bottom, self.columns as usize * GLYPH_WIDTH, GLYPH_HEIGHT, background(BLANK.color));
+    }
+    /// Show the current view of the history
+    fn redraw(&mut self, fb: &mut Framebuffer) {
+        for y in 0..self.rows {
+            for x in 0..self.columns {
+                let cell = self.history.cell(x, y);
+                self.draw(fb, x, y, cell);
+            }
+        }
+    }
+}
+
+impl Con for ConFramebuffer {
+    fn print(&mut self, s: &str) -> fmt::Result {
+        let fb = match self.screen() {
+            Some(fb) => fb,
+            None => return Ok(()),
+        };
+        if self.history.snap() {
+            self.redraw(fb);
+        }
+        if self.scroll_next {
+            self.next_line(fb);
+            self.scroll_next = false;
+        }
+        for c in s.chars() {
+            if c.is_control() {
+                // The glyphs in place of control characters are dingbats, so show them escaped
+                for e in c.escape_default() {
+                    self.put_at_cursor(fb, e as u8);
+                }
+            } else {
+                self.put_at_cursor(fb, cp437::encode(c));
+            }
+        }
+        Ok(())
+    }
+    fn prepare(&mut self, v: V) -> fmt::Result {
+        self.active_color = self.colors.get(v).vga();
+        Ok(())
+    }
+    fn end(&mut self) -> fmt::Result {
+        self.scroll_next = true;
+        Ok(())
+    }
+    fn page(&mut self, up: bool) {
+        if let Some(fb) = self.screen() {
+            // Half a screen at a time, as the VGA console does
+            let lines = self.rows as usize / 2;
+            let moved = if up { self.history.page_up(lines) } else { self.history.page_down(lines) };
+            if moved {
+                self.redraw(fb);
+            }
+        }
+    }
+}
+
+/// Start the console, which draws on whatever framebuffer a display driver makes primary
+///
+/// The font is read from the VGA, so this has to be before any display driver is bound and
+/// leaves text mode.
+pub fn init() {
+    let window = match unsafe {STATE.kernel_as.paddr_to_vaddr(FONT_WINDOW)} {
+        Some(window) => window,
+        None => return,
+    };
+    match bios_font(window as *mut u8) {
+        Some(font) => super::register(box ConFramebuffer {
+            font: font,
+            columns: 0,
+            rows: 0,
+            cursor_x: 0,
+            scroll_next: false,
+            active_color: 0,
+            colors: VGA_COLORS,
+            history: unsafe {&mut HISTORY},
+        }),
+        None => print!(Info, "No BIOS font to draw a framebuffer console with"),
+    }
+}
@@ -15,16 +15,29 @@
 // 2. Reinitialize any early consoles from the cmdline
 // 3. Attempt default init of early consoles
 
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::result::Result;
 use core::fmt;
 use core::mem;
 use util;
+use time;
+use input::{self, Event, KeyCode, Modifiers};
 
 mod vga;
+mod scrollback;
+mod cp437;
+mod color;
 mod serial;
+mod debugcon;
+pub mod fb;
+pub mod net;
+pub mod virtio;
 
-use self::vga::init_vga_80_25;
+use self::vga::{init_vga, init_vga_80_25};
+pub use self::vga::load_font;
 use self::serial::ConSerial;
+use self::debugcon::ConDebug;
 
 // Verbosity level
 #[derive(Debug, Copy, Clone)]
@@ -43,6 +56,14 @@ pub trait Con {
     fn print(&mut self, s: &str) -> fmt::Result;
     fn prepare(&mut self, v: V) -> fmt::Result;
     fn end(&mut self) -> fmt::Result;
+    /// Push out anything the con has buffered
+    fn flush(&mut self) -> fmt::Result {
+        Ok(())
+    }
+    /// Move the view of a con that keeps history, `up` being towards older lines
+    ///
+    /// The view returns to the newest lines by itself when anything is printed.
+    fn page(&mut self, _up: bool) {}
 }
 
 pub trait EarlyCon: Con {
@@ -61,18 +82,22 @@ struct EarlyConEntry {
     init: fn(args: &str) -> Result<&'static mut EarlyCon,()>,
 }
 
-static EARLY_CONS: [EarlyConEntry; 2] = [
+static EARLY_CONS: [EarlyConEntry; 4] = [
+    EarlyConEntry {name: "vga", init: init_vga},
     EarlyConEntry {name: "vga_80_25", init: init_vga_80_25},
     EarlyConEntry {name: "serial", init: ConSerial::early_init},
+    EarlyConEntry {name: "debugcon", init: ConDebug::early_init},
 ];
 
 pub struct State {
     // Only support one early con at a time
     early: Option<&'static mut EarlyCon>,
+    // Cons that need the heap, and so are only added once the system is up
+    cons: Option<Vec<Box<Con>>>,
     verbosity: V,
 }
 
-static mut CON_STATE: State = State {early: None, verbosity: V::Debug};
+static mut CON_STATE: State = State {early: None, cons: None, verbosity: V::Debug};
 
 impl fmt::Write for EarlyCon {
     fn write_str(&mut self, s: &str) -> fmt::Result {
@@ -80,6 +105,23 @@ impl fmt::Write for EarlyCon {
     }
 }
 
+impl fmt::Write for Con {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.print(s)
+    }
+}
+
+fn write_line<C: Con + fmt::Write + ?Sized>(con: &mut C, verbosity: V, args: fmt::Arguments) -> fmt::Result {
+    con.prepare(verbosity)?;
+    if let err@Err(_) = fmt::Write::write_fmt(con, args) {
+        // still run `end`, but return the error from write_fmt
+        let _ = con.end();
+        err
+    } else {
+        con.end()
+    }
+}
+
 impl State {
     // Early console initialize always succeeds as there will be no way to inform the user if it
     // went wrong so we might as well just keep going and hope we can get a real console eventually
@@ -106,29 +148,49 @@ impl State {
         true
     }
 
+    pub fn register(&mut self, con: Box<Con>) {
+        self.cons.get_or_insert_with(Vec::new).push(con);
+    }
+
+    pub fn flush(&mut self) -> fmt::Result {
+        if let Some(ref mut con) = self.early {
+            con.flush()?;
+        }
+        for con in self.cons.iter_mut().flat_map(|cons| cons.iter_mut()) {
+            con.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn page(&mut self, up: bool) {
+        if let Some(ref mut con) = self.early {
+            con.page(up);
+        }
+        for con in self.cons.iter_mut().flat_map(|cons| cons.iter_mut()) {
+            con.page(up);
+        }
+    }
+
     fn print_line(&mut self, verbosity: V, args: fmt::Arguments) -> fmt::Result {
-        match self.early {
-            Some(ref mut con) => {
-                con.prepare(verbosity)?;
-                if let err@Err(_) = fmt::Write::write_fmt(con, args) {
-                    // still run `end`, but return the error from write_fmt
-                    let _ = con.end();
-                    err
-                } else {
-                    con.end()
-                }
-            },
-            None => Ok(()),
+        if let Some(ref mut con) = self.early {
+            write_line(&mut **con, verbosity, args)?;
+        }
+        for con in self.cons.iter_mut().flat_map(|cons| cons.iter_mut()) {
+            write_line(&mut **con, verbosity, args)?;
         }
+        Ok(())
     }
 
     pub fn print(&mut self, verbosity: V, args: fmt::Arguments) -> fmt::Result {
-        // TODO utf8 handling
         if self.log_allowed(verbosity) {
-            // Generate actual message and print it
-            let seconds = 0 as u64;
-            let micros = 0 as u32;
-            self.print_line(verbosity, format_args!("[{:0>5}.{:0>5}] {}", seconds, micros, args))
+            // Generate actual message and print it, dated once the RTC has been read
+            let uptime = time::uptime();
+            let seconds = uptime.as_secs();
+            let micros = uptime.subsec_nanos() / 1000;
+            match time::wall_clock() {
+                Some(now) => self.print_line(verbosity, format_args!("[{} {:0>5}.{:0>6}] {}", now, seconds, micros, args)),
+                None => self.print_line(verbosity, format_args!("[{:0>5}.{:0>6}] {}", seconds, micros, args)),
+            }
         } else {
             Ok(())
         }
@@ -148,6 +210,37 @@ unsafe fn get() -> &'static mut State {
 
 pub fn early_init(early: &str) {
     unsafe{get().early_init(early);}
+    input::set_hook(page_key);
+}
+
+/// Pages asked for by keys and not yet done, positive being up
+static mut PENDING_PAGES: isize = 0;
+
+/// Shift+PgUp and Shift+PgDn page through the history of the cons
+///
+/// Called from the keyboard interrupt, which may have interrupted a print, so the paging is only
+/// queued here and done by `page_pending`.
+fn page_key(event: &Event) -> bool {
+    match *event {
+        Event::Key(key) if key.pressed && key.modifiers.intersects(Modifiers::SHIFT) => {
+            let up = match key.code {
+                KeyCode::PageUp => true,
+                KeyCode::PageDown => false,
+                _ => return false,
+            };
+            unsafe {PENDING_PAGES += if up { 1 } else { -1 }};
+            true
+        },
+        _ => false,
+    }
+}
+
+/// Do the paging keys asked for since the last call, from outside any interrupt handler
+pub fn page_pending() {
+    let pages = unsafe {mem::replace(&mut PENDING_PAGES, 0)};
+    for _ in 0..pages.abs() {
+        unsafe{get()}.page(pages > 0);
+    }
 }
 
 pub fn print(verbosity: V, message: &str) {
@@ -164,6 +257,16 @@ pub fn disable_physical_con() {
     unsafe{get().disable_physical()}
 }
 
+/// Add a con that prints alongside the early con
+pub fn register(con: Box<Con>) {
+    unsafe{get().register(con)}
+}
+
+/// Push out anything buffered by the cons
+pub fn flush() {
+    let _ = unsafe{get()}.flush();
+}
+
 #[macro_export]
 macro_rules! print {
     ($v:ident, $($arg:tt)*) => ($crate::con::print_fmt($crate::con::V::$v, format_args!($($arg)*)));
@@ -0,0 +1,141 @@
+//! Buffering of the netconsole, which is independent of the network stack it sends through
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem;
+use core::time::Duration;
+use con::V;
+use time;
+
+/// Most bytes sent in one datagram, which stays well within the MTU
+pub const PAYLOAD: usize = 1400;
+/// Most bytes buffered while sending is not possible
+pub const MAX_BUFFER: usize = 16 * 1024;
+/// Milliseconds output may sit in the buffer before being sent
+pub const FLUSH_INTERVAL_MS: u64 = 100;
+
+/// Where the datagrams of the console go
+pub trait Sink {
+    /// Send a datagram if that can be done without waiting, returning whether it was sent
+    fn try_send(&mut self, data: &[u8]) -> bool;
+}
+
+pub struct ConNet<S> {
+    sink: S,
+    buffer: Vec<u8>,
+    last_flush: Duration,
+    /// Bytes dropped that no note has been sent about yet
+    dropped: usize,
+    /// Offset in the buffer at which bytes started being dropped, where the note goes
+    dropped_at: usize,
+    /// Verbosity of the line being printed
+    verbosity: V,
+    /// Set while sending, as the network stack can itself print
+    sending: bool,
+}
+
+impl<S: Sink> ConNet<S> {
+    pub fn new(sink: S) -> ConNet<S> {
+        ConNet {
+            sink: sink,
+            buffer: Vec::with_capacity(MAX_BUFFER),
+            last_flush: time::uptime(),
+            dropped: 0,
+            dropped_at: 0,
+            verbosity: V::Info,
+            sending: false,
+        }
+    }
+    fn push(&mut self, bytes: &[u8]) {
+        let room = MAX_BUFFER - self.buffer.len();
+        let len = bytes.len().min(room);
+        self.buffer.extend_from_slice(&bytes[..len]);
+        if len < bytes.len() {
+            if self.dropped == 0 {
+                self.dropped_at = self.buffer.len();
+            }
+            self.dropped += bytes.len() - len;
+        }
+    }
+    /// Send as much of the buffer as possible, without waiting
+    pub fn send(&mut self) {
+        if self.sending {
+            return;
+        }
+        self.sending = true;
+        self.last_flush = time::uptime();
+        self.send_buffer();
+        self.sending = false;
+    }
+    fn send_buffer(&mut self) {
+        // Anything printed while sending is appended to a fresh buffer
+        let mut pending = mem::replace(&mut self.buffer, Vec::new());
+        let mut sent = 0;
+        loop {
+            // The note goes where the bytes were dropped, so that it marks the gap
+            let end = if self.dropped > 0 { self.dropped_at } else { pending.len() };
+            sent += self.send_bytes(&pending[sent..end]);
+            if sent < end || self.dropped == 0 {
+                break;
+            }
+            let note = format!("Error: netconsole dropped {} bytes\n", self.dropped);
+            if !self.sink.try_send(note.as_bytes()) {
+                break;
+            }
+            self.dropped = 0;
+        }
+        pending.drain(..sent);
+        if self.dropped > 0 {
+            self.dropped_at -= sent;
+        }
+        let printed = mem::replace(&mut self.buffer, pending);
+        self.push(&printed);
+    }
+    /// Send bytes in datagrams until the sink is busy, returning how many were sent
+    fn send_bytes(&mut self, bytes: &[u8]) -> usize {
+        let mut sent = 0;
+        while sent < bytes.len() {
+            let rest = &bytes[sent..];
+            let len = if rest.len() <= PAYLOAD {
+                rest.len()
+            } else {
+                // Keep lines whole where they fit
+                rest[..PAYLOAD].iter().rposition(|&byte| byte == b'\n').map_or(PAYLOAD, |newline| newline + 1)
+            };
+            if !self.sink.try_send(&rest[..len]) {
+                break;
+            }
+            sent += len;
+        }
+        sent
+    }
+    pub fn print(&mut self, s: &str) {
+        self.push(s.as_bytes());
+        if self.buffer.len() >= PAYLOAD {
+            self.send();
+        }
+    }
+    /// Start a line, which is prefixed with its level
+    pub fn prepare(&mut self, v: V) {
+        self.verbosity = v;
+        let _ = fmt::Write::write_fmt(self, format_args!("{:?}: ", v));
+    }
+    /// End a line, sending the buffer if it is due
+    pub fn end(&mut self) {
+        self.push(b"\n");
+        let urgent = match self.verbosity {
+            V::Panic | V::Error => true,
+            _ => false,
+        };
+        if urgent || self.buffer.len() >= PAYLOAD || time::uptime() >= self.last_flush + Duration::from_millis(FLUSH_INTERVAL_MS) {
+            self.send();
+        }
+    }
+}
+
+impl<S: Sink> fmt::Write for ConNet<S> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.print(s);
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+//! Console over UDP, for collecting the logs of many machines in one place
+//!
+//! Lines are batched into datagrams, which are sent once enough has built up, a while has
+//! passed since the last one, or a line is an error. Sending never waits, if the network stack
+//! is busy, or the device has no room, output stays buffered for the next attempt. Output that
+//! does not fit in the buffer is dropped and counted, so that printing never blocks, and a note
+//! of how much was lost is sent ahead of whatever follows it.
+
+use core::fmt;
+use net::{Ipv4Address, UdpSocket};
+use util;
+
+use super::{Con, V};
+
+mod buffer;
+
+pub use self::buffer::{ConNet, Sink};
+
+const DEFAULT_PORT: u16 = 6666;
+
+/// Endpoint from --netconsole
+static mut ENDPOINT: Option<(Ipv4Address, u16)> = None;
+
+/// Socket the netconsole sends from, and where to
+struct Endpoint {
+    socket: UdpSocket,
+    dst: Ipv4Address,
+    port: u16,
+}
+
+impl Sink for Endpoint {
+    fn try_send(&mut self, data: &[u8]) -> bool {
+        match self.socket.try_send_to(data, self.dst, self.port) {
+            Some(Ok(())) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<S: Sink> Con for ConNet<S> {
+    fn print(&mut self, s: &str) -> fmt::Result {
+        ConNet::print(self, s);
+        Ok(())
+    }
+    fn prepare(&mut self, v: V) -> fmt::Result {
+        ConNet::prepare(self, v);
+        Ok(())
+    }
+    fn end(&mut self) -> fmt::Result {
+        ConNet::end(self);
+        Ok(())
+    }
+    fn flush(&mut self) -> fmt::Result {
+        self.send();
+        Ok(())
+    }
+}
+
+/// Start the netconsole, if one was asked for, once the network is up
+pub fn init() {
+    let (dst, port) = match unsafe {ENDPOINT} {
+        Some(endpoint) => endpoint,
+        None => return,
+    };
+    match UdpSocket::bind(0) {
+        Ok(socket) => {
+            print!(Info, "Logging to {}:{} from port {}", dst, port, socket.port());
+            super::register(box ConNet::new(Endpoint { socket: socket, dst: dst, port: port }));
+        },
+        Err(error) => print!(Error, "Failed to start netconsole: {:?}", error),
+    }
+}
+
+fn set_endpoint(endpoint: &str) {
+    let (address, port) = util::split_first_str(endpoint, ":");
+    let port = if port.is_empty() { Ok(DEFAULT_PORT) } else { port.parse() };
+    match (address.parse(), port) {
+        (Ok(address), Ok(port)) => unsafe {ENDPOINT = Some((address, port))},
+        _ => print!(Error, "Invalid netconsole endpoint {}", endpoint),
+    }
+}
+
+/// Sends the console to a UDP endpoint, for example --netconsole=10.0.2.2:6666
+make_cmdline_decl!("netconsole", set_endpoint, NETCONSOLE);
@@ -0,0 +1,130 @@
+//! History of a text console
+//!
+//! Text consoles write every character to a `Scrollback` as well as to the screen, so that lines
+//! that have scrolled off the top can be paged back into view. The newest lines of the history
+//! are the ones on the screen, and the view is described as how many lines it is behind those.
+//! Any console drawing a grid of cells, be it VGA text memory or glyphs on a framebuffer, can
+//! keep its history this way.
+
+/// Cells of history kept, how many lines this is depends on the width of the console
+pub const HISTORY_CELLS: usize = 48 * 1024;
+
+/// Character and colour attribute at one position of a text console
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: u8,
+    pub color: u8,
+}
+
+impl Cell {
+    pub const fn new(ch: u8, color: u8) -> Self {
+        Cell { ch: ch, color: color }
+    }
+}
+
+pub struct Scrollback {
+    cells: [Cell; HISTORY_CELLS],
+    blank: Cell,
+    width: usize,
+    rows: usize,
+    /// Number of the line being written, counting every line since the last reset
+    newest: usize,
+    /// Lines the view is behind the newest lines
+    offset: usize,
+}
+
+impl Scrollback {
+    /// Empty history, which must be `reset` before use
+    ///
+    /// All the cells are zero so that a `static` scrollback takes no space in the image.
+    pub const fn new() -> Self {
+        Scrollback {
+            cells: [Cell { ch: 0, color: 0 }; HISTORY_CELLS],
+            blank: Cell { ch: 0, color: 0 },
+            width: 0,
+            rows: 0,
+            newest: 0,
+            offset: 0,
+        }
+    }
+    /// Forget all history and start again for a screen of the given size
+    ///
+    /// The screen starts out full of `blank` lines, with the bottom one being written.
+    pub fn reset(&mut self, width: u16, rows: u16, blank: Cell) {
+        assert!(width > 0 && rows > 0 && width as usize * rows as usize <= HISTORY_CELLS);
+        self.width = width as usize;
+        self.rows = rows as usize;
+        self.blank = blank;
+        self.newest = self.rows - 1;
+        self.offset = 0;
+        for cell in self.cells.iter_mut() {
+            *cell = blank;
+        }
+    }
+    /// Number of lines that fit in the history
+    pub fn capacity(&self) -> usize {
+        HISTORY_CELLS / self.width
+    }
+    fn line_start(&self, line: usize) -> usize {
+        (line % self.capacity()) * self.width
+    }
+    /// Write a cell of the line at the bottom of the screen
+    pub fn put(&mut self, x: u16, cell: Cell) {
+        if (x as usize) < self.width {
+            let start = self.line_start(self.newest);
+            self.cells[start + x as usize] = cell;
+        }
+    }
+    /// Start a new blank line, dropping the oldest if the history is full
+    ///
+    /// A view that is scrolled back stays on the lines it is showing.
+    pub fn new_line(&mut self) {
+        self.newest += 1;
+        let start = self.line_start(self.newest);
+        let blank = self.blank;
+        for cell in self.cells[start..start + self.width].iter_mut() {
+            *cell = blank;
+        }
+        if self.offset > 0 {
+            self.offset = (self.offset + 1).min(self.max_offset());
+        }
+    }
+    fn max_offset(&self) -> usize {
+        (self.newest + 1).min(self.capacity()).saturating_sub(self.rows)
+    }
+    /// Lines the view is behind the newest lines
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    /// Whether the view is of the newest lines, and so the screen can be written directly
+    pub fn is_live(&self) -> bool {
+        self.offset == 0
+    }
+    /// Move the view `lines` further back, returning whether it moved
+    pub fn page_up(&mut self, lines: usize) -> bool {
+        let offset = (self.offset + lines).min(self.max_offset());
+        let moved = offset != self.offset;
+        self.offset = offset;
+        moved
+    }
+    /// Move the view `lines` towards the newest lines, returning whether it moved
+    pub fn page_down(&mut self, lines: usize) -> bool {
+        let offset = self.offset.saturating_sub(lines);
+        let moved = offset != self.offset;
+        self.offset = offset;
+        moved
+    }
+    /// Return the view to the newest lines, returning whether it moved
+    pub fn snap(&mut self) -> bool {
+        let offset = self.offset;
+        self.page_down(offset)
+    }
+    /// Cell that the current view shows at the given position of the screen
+    pub fn cell(&self, x: u16, y: u16) -> Cell {
+        if x as usize >= self.width || y as usize >= self.rows {
+            return self.blank;
+        }
+        let line = self.newest + 1 + y as usize - self.rows - self.offset;
+        self.cells[self.line_start(line) + x as usize]
+    }
+}
@@ -1,24 +1,61 @@
 use core::fmt;
 use drivers;
 use drivers::Serial;
+use boot::cmdline::option_is_true;
+use util;
 
-use super::{Con, EarlyCon, V};
+use super::{write_line, Con, EarlyCon, V};
+use super::color::{Color, ColorMode, Colors};
 
 pub struct ConSerial {
     uart: Option<drivers::uart16550::Uart<drivers::io::PortIO<u8>>>,
+    /// I/O port of the UART
+    port: u16,
+    /// Spell out anything that is not ASCII, for terminals that do not understand UTF-8
+    ascii: bool,
+    /// Whether the other end understands ANSI escape sequences
+    ansi: bool,
+    color: ColorMode,
+    colors: Colors,
 }
 
-static mut EARLY_SERIAL: ConSerial = ConSerial { uart: None };
+const SERIAL_COLORS: Colors = Colors {
+    panic: Color::LightRed,
+    error: Color::Yellow,
+    info: Color::White,
+    debug: Color::LightGreen,
+    trace: Color::LightBlue,
+};
+
+static mut EARLY_SERIAL: ConSerial = ConSerial {
+    uart: None,
+    port: 0x3f8,
+    ascii: false,
+    ansi: true,
+    color: ColorMode::Auto,
+    colors: SERIAL_COLORS,
+};
 
 impl Con for ConSerial {
     fn print(&mut self, s: &str) -> fmt::Result {
+        let ascii = self.ascii;
         unsafe {
             match self.uart {
-                Some(ref mut uart) =>
+                // Control characters are passed through unescaped so that our colour control
+                // codes reach the terminal
+                Some(ref mut uart) if ascii =>
                     for c in s.chars() {
-                        // TODO: for now we assume a terminal that understands unicode. this is helpful
-                        // as it means our colour control codes also get passed through unescaped
-                        uart.write_byte(c as u8);
+                        if c.is_ascii() {
+                            uart.write_byte(c as u8);
+                        } else {
+                            for e in c.escape_unicode() {
+                                uart.write_byte(e as u8);
+                            }
+                        }
+                    },
+                Some(ref mut uart) =>
+                    for &b in s.as_bytes() {
+                        uart.write_byte(b);
                     },
                 None => (),
             }
@@ -26,19 +63,18 @@ impl Con for ConSerial {
         Ok(())
     }
     fn prepare(&mut self, v: V) -> fmt::Result {
-        //unimplemented!()
-        // TODO: option for disabling ansi terminal assumption
-        let mut wbf = |col| fmt::Write::write_fmt(self as &mut EarlyCon, format_args!("\x1B[1;{}m", col));
-        match v {
-            V::Panic => wbf(31),
-            V::Error => wbf(33),
-            V::Info => wbf(37),
-            V::Debug => wbf(32),
-            V::Trace => wbf(34),
+        if self.colored() {
+            let (bold, code) = self.colors.get(v).ansi();
+            fmt::Write::write_fmt(self as &mut EarlyCon, format_args!("\x1B[{};{}m", bold, code))
+        } else {
+            Ok(())
         }
     }
     fn end(&mut self) -> fmt::Result {
-        //unimplemented!()
+        if self.colored() {
+            // So that whatever the terminal shows next is not in our colour
+            self.print("\x1B[0m")?;
+        }
         unsafe {
             match self.uart {
                 Some(ref mut uart) => {
@@ -62,10 +98,47 @@ impl EarlyCon for ConSerial {
 }
 
 impl ConSerial {
-    pub fn early_init(_args: &str) ->Result<&'static mut EarlyCon, ()> {
-        unsafe {
-            EARLY_SERIAL.uart = Some(drivers::uart16550::Uart::new(drivers::io::PortIO::new(0x3f8)));
+    fn colored(&self) -> bool {
+        match self.color {
+            ColorMode::Auto => self.ansi,
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+    /// Handle one of the args, failing if it is not understood
+    fn set_option(&mut self, option: &str, value: &str) -> Result<(), ()> {
+        match option {
+            "port" => self.port = u16::from_str_radix(value.trim_left_matches("0x"), 16).map_err(|_| ())?,
+            "ascii" => self.ascii = option_is_true(value),
+            "ansi" => self.ansi = option_is_true(value),
+            "color" => self.color = value.parse()?,
+            level => self.colors.set(level, value)?,
+        }
+        Ok(())
+    }
+    /// Format of the args is a list of options
+    ///
+    /// * `port=` the I/O port of the UART in hex, which defaults to 3f8
+    /// * `ascii=on` for terminals that do not understand UTF-8
+    /// * `ansi=off` for terminals that do not understand escape sequences, such as a log file
+    /// * `color=auto|always|never` whether to colour each level, with `auto` doing so if `ansi`
+    /// * `LEVEL=COLOR` the colour of a level, such as `debug=darkgray`
+    ///
+    /// For example `--earlycon=serial,port=0x2f8,ansi=off`. Args that are not understood are
+    /// reported on the console once it is up, rather than leaving the machine without one.
+    pub fn early_init(args: &str) ->Result<&'static mut EarlyCon, ()> {
+        let con = unsafe {&mut EARLY_SERIAL};
+        let options = || args.split(',').filter(|arg| !arg.is_empty()).map(|arg| util::split_first_str(arg, "="));
+        for (option, value) in options() {
+            let _ = con.set_option(option, value);
+        }
+        con.uart = Some(unsafe {drivers::uart16550::Uart::new(drivers::io::PortIO::new(con.port))});
+        // Applying an option again changes nothing, so this only finds the ones to report
+        for (option, value) in options() {
+            if con.set_option(option, value).is_err() {
+                let _ = write_line(&mut *con as &mut EarlyCon, V::Error, format_args!("Ignoring serial option {}={}", option, value));
+            }
         }
-        Ok(unsafe{&mut EARLY_SERIAL})
+        Ok(con)
     }
 }
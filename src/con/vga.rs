@@ -2,63 +2,39 @@
 
 use core::fmt;
 use core::{ptr, intrinsics};
+use drivers::io::PortIO;
+use drivers::vga::{self, halve_font, TextMode, Vga, GLYPHS};
+use util;
 use x86::shared::io;
 
 use super::{Con, EarlyCon, V};
+use super::scrollback::{Cell, Scrollback};
+use super::cp437;
+use super::color::{Color, Colors};
 
 struct VGAText {
     base: *mut u8,
+    mode: TextMode,
+    /// Whether this is the early con, and so the VGA may be reprogrammed
+    active: bool,
     width: u16,
     height: u16,
     line_stride: u32,
     cursor_x: u16,
     scroll_next: bool,
     active_color: u8,
-    panic_color: u8,
-    error_color: u8,
-    info_color: u8,
-    debug_color: u8,
-    trace_color: u8,
+    colors: Colors,
+    /// Everything written, of which the screen shows a view
+    history: Scrollback,
 }
 
-#[derive(Debug, Clone, Copy)]
-#[repr(u8)]
-#[allow(dead_code)]
-enum BackgroundColor {
-    Black = 0,
-    Blue = 1,
-    Green = 2,
-    Cyan = 3,
-    Red = 4,
-    Magenta = 5,
-    Brown = 6,
-}
-
-#[derive(Debug, Clone, Copy)]
-#[repr(u8)]
-#[allow(dead_code)]
-enum ForegroundColor {
-    Black = 0,
-    Blue = 1,
-    Green = 2,
-    Cyan = 3,
-    Red = 4,
-    Magenta = 5,
-    Brown = 6,
-    LightGray = 7,
-    DarkGray = 8,
-    LightBlue = 9,
-    LightGreen = 10,
-    LightCyan = 11,
-    LightRed = 12,
-    Pink = 13,
-    Yellow = 14,
-    White = 15,
-}
-
-const fn make_color(fore: ForegroundColor, back: BackgroundColor) -> u8 {
-    ((back as u8) << 4) | (fore as u8)
-}
+pub const VGA_COLORS: Colors = Colors {
+    panic: Color::LightRed,
+    error: Color::Yellow,
+    info: Color::White,
+    debug: Color::Green,
+    trace: Color::LightBlue,
+};
 
 // TODO: define trait for text screens that defines common logic that can be shared to
 // framebuffer implementations that mimic text modes
@@ -73,7 +49,10 @@ impl VGAText {
     fn put_at_cursor(&mut self, c: u8, color: u8) {
         let x = self.cursor_x;
         let y = self.height - 1;
-        self.put_at(x, y, c, color);
+        self.history.put(x, Cell::new(c, color));
+        if self.history.is_live() {
+            self.put_at(x, y, c, color);
+        }
     }
     fn increment_cursor(&mut self)  {
         self.cursor_x = self.cursor_x + 1;
@@ -82,6 +61,9 @@ impl VGAText {
         }
     }
     fn reset(&mut self) {
+        let (width, height) = (self.width, self.height);
+        self.history.reset(width, height, Cell::new(' ' as u8, 0xb));
+        self.cursor_x = 0;
         for i in 0..self.height {
             self.blank_line(i);
         }
@@ -108,81 +90,182 @@ impl VGAText {
         }
     }
     fn scroll(&mut self) {
+        self.history.new_line();
+        if !self.history.is_live() {
+            // The view stays on the lines it was showing
+            return;
+        }
         let h = self.height;
         for i in 0..h - 1 {
             self.copy_line(i, i + 1);
         }
         self.blank_line(h - 1);
     }
+    /// Show the current view of the history
+    fn redraw(&mut self) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let cell = self.history.cell(x, y);
+                self.put_at(x, y, cell.ch, cell.color);
+            }
+        }
+    }
 }
 
 impl Con for VGAText {
     fn print(&mut self, s: &str) -> fmt::Result {
+        if self.history.snap() {
+            self.redraw();
+        }
         if self.scroll_next {
             self.next_line();
             self.scroll_next = false;
         }
         let color = self.active_color;
         for c in s.chars() {
-            // TODO: this is duplicated
-            // We want default escaping *except* for quotes as they are regular printable ascii characters
-            if c == '"' || c == '\'' {
-                self.put_at_cursor(c as u8, color);
-                self.increment_cursor();
-            } else {
+            if c.is_control() {
+                // The glyphs in place of control characters are dingbats, so show them escaped
                 for e in c.escape_default() {
                     self.put_at_cursor(e as u8, color);
                     self.increment_cursor();
                 }
+            } else {
+                self.put_at_cursor(cp437::encode(c), color);
+                self.increment_cursor();
             }
         }
         Ok(())
     }
     fn prepare(&mut self, v: V) -> fmt::Result {
-        self.active_color = match v {
-            V::Panic => self.panic_color,
-            V::Error => self.error_color,
-            V::Info => self.info_color,
-            V::Debug => self.debug_color,
-            V::Trace => self.trace_color,
-        };
+        self.active_color = self.colors.get(v).vga();
         Ok(())
     }
     fn end(&mut self) -> fmt::Result {
         self.scroll_next = true;
         Ok(())
     }
+    fn page(&mut self, up: bool) {
+        // Half a screen at a time, so there is always context from the previous view
+        let lines = self.height as usize / 2;
+        let moved = if up { self.history.page_up(lines) } else { self.history.page_down(lines) };
+        if self.active && moved {
+            self.redraw();
+        }
+    }
 }
 
 impl EarlyCon for VGAText {
     fn shutdown(&mut self) -> () {
-        // nothing to do?
+        self.active = false;
     }
     fn is_physical(&self) -> bool {
         true
     }
 }
 
-static mut EARLY_VGA_80_25: VGAText = VGAText {
+static mut EARLY_VGA: VGAText = VGAText {
     base: 0xb8000 as *mut u8,
+    mode: TextMode::Text80x25,
+    active: false,
     width: 80,
     height: 25,
     line_stride: 80 * 2,
     cursor_x: 0,
     scroll_next: false,
     active_color: 0,
-    // TODO: Should probably make default colors for this?
-    panic_color: make_color(ForegroundColor::LightRed, BackgroundColor::Black),
-    error_color: make_color(ForegroundColor::Yellow, BackgroundColor::Black),
-    info_color: make_color(ForegroundColor::White, BackgroundColor::Black),
-    debug_color: make_color(ForegroundColor::Green, BackgroundColor::Black),
-    trace_color: make_color(ForegroundColor::LightBlue, BackgroundColor::Black),
+    colors: VGA_COLORS,
+    history: Scrollback::new(),
 };
 
-pub fn init_vga_80_25(_args: &str) -> Result<&'static mut EarlyCon, ()> {
-    // TODO: validate that the base is within the memory limit
+/// The font the BIOS left, saved before it is first replaced so that 80x25 can be returned to
+static mut FONT_8X16: [u8; GLYPHS * 16] = [0; GLYPHS * 16];
+/// The BIOS font at half height, for the denser modes
+static mut FONT_8X8: [u8; GLYPHS * 8] = [0; GLYPHS * 8];
+static mut FONTS_SAVED: bool = false;
+
+fn vga() -> Vga<PortIO<u8>> {
+    unsafe {Vga::new(PortIO::new(vga::PORT_BASE), vga::FONT_WINDOW as *mut u8)}
+}
+
+/// Save the BIOS font, unless that has already been done
+unsafe fn save_fonts(vga: &mut Vga<PortIO<u8>>) -> Result<(), vga::Error> {
+    if !FONTS_SAVED {
+        // Without the BIOS font there is nothing to derive the others from
+        if vga.font_height() != 16 {
+            return Err(vga::Error::BadFont);
+        }
+        vga.read_font(&mut FONT_8X16, 16)?;
+        halve_font(&FONT_8X16, &mut FONT_8X8);
+        FONTS_SAVED = true;
+    }
+    Ok(())
+}
+
+/// The 8x16 font the BIOS left, 16 bytes a glyph, for drawing text elsewhere
+///
+/// `window` is where `vga::FONT_WINDOW` is mapped. Only works while the VGA is in the text
+/// mode the BIOS set up, if the VGA console has not already saved the font.
+pub fn bios_font(window: *mut u8) -> Option<&'static [u8]> {
+    unsafe {
+        save_fonts(&mut Vga::new(PortIO::new(vga::PORT_BASE), window)).ok()?;
+        Some(&FONT_8X16)
+    }
+}
+
+/// Program a text mode, using the BIOS font scaled to fit
+unsafe fn set_mode(mode: TextMode) -> Result<(), vga::Error> {
+    let mut vga = vga();
+    save_fonts(&mut vga)?;
+    let font: &[u8] = if mode.font_height() == 16 { &FONT_8X16 } else { &FONT_8X8 };
+    vga.set_text_mode(mode, font)?;
+    EARLY_VGA.mode = mode;
+    EARLY_VGA.width = mode.columns();
+    EARLY_VGA.height = mode.rows();
+    EARLY_VGA.line_stride = mode.columns() as u32 * 2;
+    Ok(())
+}
+
+/// Replace the glyphs of the VGA console with a font of 256 glyphs, one byte a scan line
+///
+/// The glyphs must be the height of the current mode, 16 for 80x25 and 8 otherwise. Only
+/// possible while the VGA console is the early con, as the font is reached through the
+/// physical window.
+pub fn load_font(font: &[u8], height: usize) -> Result<(), vga::Error> {
     unsafe {
-        EARLY_VGA_80_25.reset();
+        if !EARLY_VGA.active || height != EARLY_VGA.mode.font_height() {
+            return Err(vga::Error::BadFont);
+        }
+        vga().load_font(font, height)
+    }
+}
+
+/// Format of the args is a list of options
+///
+/// * `mode=COLUMNSxROWS` the text mode, of 80x25, 80x50 and 90x60
+/// * `LEVEL=COLOR` the colour of a level, such as `debug=darkgray`
+///
+/// For example `--earlycon=vga,mode=80x50`
+pub fn init_vga(args: &str) -> Result<&'static mut EarlyCon, ()> {
+    let mut mode = TextMode::Text80x25;
+    let mut colors = VGA_COLORS;
+    for arg in args.split(',').filter(|arg| !arg.is_empty()) {
+        match util::split_first_str(arg, "=") {
+            ("mode", value) => mode = value.parse()?,
+            (level, value) => colors.set(level, value)?,
+        }
     }
-    Ok(unsafe{&mut EARLY_VGA_80_25})
+    unsafe {
+        EARLY_VGA.colors = colors;
+        // Staying in the mode the BIOS left is better than having no console at all
+        if mode != EARLY_VGA.mode && set_mode(mode).is_err() {
+            let _ = set_mode(TextMode::Text80x25);
+        }
+        EARLY_VGA.active = true;
+        EARLY_VGA.reset();
+    }
+    Ok(unsafe{&mut EARLY_VGA})
+}
+
+pub fn init_vga_80_25(_args: &str) -> Result<&'static mut EarlyCon, ()> {
+    init_vga("")
 }
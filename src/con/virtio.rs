@@ -0,0 +1,93 @@
+//! Console over a virtio-console port
+//!
+//! Uses the port named `org.rlk.log` if there is one, so that the log can be kept apart from
+//! the console, and the console port otherwise. Each line is written in one go, which with the
+//! device taking whole buffers at a time is far faster than a UART. Should the host stop
+//! taking output, lines are dropped and counted after a short wait, rather than blocking.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+use drivers::virtio::console::{ConsolePort, PORT_LOG};
+use time;
+
+use super::{Con, V};
+
+/// Longest line buffered, beyond which it is written out in pieces
+const MAX_LINE: usize = 4096;
+/// Milliseconds to wait for the device to take a line before dropping it
+const WRITE_TIMEOUT_MS: u64 = 5;
+
+pub struct ConVirtio {
+    port: ConsolePort,
+    line: Vec<u8>,
+    /// Bytes dropped since the last line that was written whole
+    dropped: usize,
+}
+
+impl ConVirtio {
+    pub fn new(port: ConsolePort) -> ConVirtio {
+        ConVirtio { port: port, line: Vec::with_capacity(MAX_LINE), dropped: 0 }
+    }
+    /// Write out the buffered line, giving up if the device has no room for a while
+    fn write_line(&mut self) {
+        if self.dropped > 0 {
+            let note = format!("Error: virtio-console dropped {} bytes\n", self.dropped);
+            if self.port.write(note.as_bytes()) == note.len() {
+                self.dropped = 0;
+            }
+        }
+        let end = time::uptime() + Duration::from_millis(WRITE_TIMEOUT_MS);
+        let mut written = 0;
+        while written < self.line.len() {
+            written += self.port.write(&self.line[written..]);
+            if time::uptime() >= end {
+                break;
+            }
+        }
+        self.dropped += self.line.len() - written;
+        self.line.clear();
+    }
+}
+
+impl Con for ConVirtio {
+    fn print(&mut self, s: &str) -> fmt::Result {
+        for chunk in s.as_bytes().chunks(MAX_LINE) {
+            if self.line.len() + chunk.len() > MAX_LINE {
+                self.write_line();
+            }
+            self.line.extend_from_slice(chunk);
+        }
+        Ok(())
+    }
+    fn prepare(&mut self, v: V) -> fmt::Result {
+        fmt::Write::write_fmt(self, format_args!("{:?}: ", v))
+    }
+    fn end(&mut self) -> fmt::Result {
+        self.line.push(b'\n');
+        self.write_line();
+        Ok(())
+    }
+    fn flush(&mut self) -> fmt::Result {
+        if !self.line.is_empty() {
+            self.write_line();
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Write for ConVirtio {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.print(s)
+    }
+}
+
+/// Start logging to a virtio-console, once devices are bound
+pub fn init() {
+    let port = match ConsolePort::open(PORT_LOG).or_else(ConsolePort::console) {
+        Some(port) => port,
+        None => return,
+    };
+    print!(Info, "Logging to virtio-console");
+    super::register(box ConVirtio::new(port));
+}
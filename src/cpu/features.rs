@@ -28,6 +28,9 @@ make_flag!(PAT, get_feature_info, has_pat);
 make_flag!(Page1GB, get_extended_function_info, has_1gib_pages);
 make_flag!(PGE, get_feature_info, has_pge);
 make_flag!(NXE, get_extended_function_info, has_execute_disable);
+make_flag!(X2APIC, get_feature_info, has_x2apic);
+make_flag!(TSCDeadline, get_feature_info, has_tsc_deadline);
+make_flag!(InvariantTSC, get_extended_function_info, has_invariant_tsc);
 
 #[derive(Debug, Clone, Copy)]
 pub enum Missing {
@@ -82,6 +85,12 @@ impl Required {
     pub fn get_msr(&self) -> MSR {
         self.msr
     }
+    pub fn get_apic(&self) -> APIC {
+        self.apic
+    }
+    pub fn get_tsc(&self) -> TSC {
+        self.tsc
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -90,6 +99,9 @@ pub struct Features {
     page1gb: Option<Page1GB>,
     pge: Option<PGE>,
     nxe: Option<NXE>,
+    x2apic: Option<X2APIC>,
+    tsc_deadline: Option<TSCDeadline>,
+    invariant_tsc: Option<InvariantTSC>,
 }
 
 impl Features {
@@ -99,6 +111,9 @@ impl Features {
             page1gb: None,
             pge: None,
             nxe: None,
+            x2apic: None,
+            tsc_deadline: None,
+            invariant_tsc: None,
         }
     }
     pub fn check() -> Result<Self, Missing> {
@@ -108,6 +123,9 @@ impl Features {
             page1gb: Page1GB::check(),
             pge: PGE::check(),
             nxe: NXE::check(),
+            x2apic: X2APIC::check(),
+            tsc_deadline: TSCDeadline::check(),
+            invariant_tsc: InvariantTSC::check(),
         })
     }
     pub fn get_required(&self) -> Required {
@@ -122,4 +140,18 @@ impl Features {
     pub fn get_nxe(&self) -> Option<NXE> {
         self.nxe
     }
+    pub fn get_x2apic(&self) -> Option<X2APIC> {
+        self.x2apic
+    }
+    pub fn get_tsc_deadline(&self) -> Option<TSCDeadline> {
+        self.tsc_deadline
+    }
+    pub fn get_invariant_tsc(&self) -> Option<InvariantTSC> {
+        self.invariant_tsc
+    }
+}
+
+/// Whether CPUID reports running under a hypervisor, which `FeatureInfo` has no flag for
+pub fn hypervisor() -> bool {
+    cpuid1(1).ecx & (1 << 31) != 0
 }
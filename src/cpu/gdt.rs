@@ -0,0 +1,154 @@
+//! Global Descriptor Table and Task State Segment
+//!
+//! Every CPU has its own `Gdt`, as the TSS it contains holds the stacks that CPU switches to. In
+//! long mode segments are flat, so the GDT only exists to describe the privilege levels, and the
+//! TSS to provide the interrupt stack table. Exceptions that can happen when the current stack
+//! is unusable, such as a double fault from a kernel stack overflow, run on stacks of their own
+//! from the IST, each with a guard below it.
+//!
+//! The user data segment directly precedes user code, as `sysret` expects.
+
+use core::mem;
+use cpu::idt;
+use state::STATE;
+use vspace::Stack;
+
+pub const KERNEL_CODE: u16 = 0x08;
+pub const KERNEL_DATA: u16 = 0x10;
+/// User selectors include the requested privilege level of 3
+pub const USER_DATA: u16 = 0x18 | 3;
+pub const USER_CODE: u16 = 0x20 | 3;
+pub const TSS: u16 = 0x28;
+
+/// IST slots, numbered from 1 as used in the IDT
+pub const IST_DOUBLE_FAULT: u8 = 1;
+pub const IST_NMI: u8 = 2;
+pub const IST_MACHINE_CHECK: u8 = 3;
+
+/// Vectors given IST stacks, and the slot of each
+const IST_VECTORS: [(u8, u8); 3] = [
+    (idt::DOUBLE_FAULT, IST_DOUBLE_FAULT),
+    (idt::NMI, IST_NMI),
+    (idt::MACHINE_CHECK, IST_MACHINE_CHECK),
+];
+
+/// Null, four segments and the TSS, which takes two entries
+const ENTRIES: usize = 7;
+
+const PRESENT: u64 = 1 << 47;
+const USER: u64 = 3 << 45;
+/// Code or data, rather than a system descriptor
+const NOT_SYSTEM: u64 = 1 << 44;
+const EXECUTABLE: u64 = 1 << 43;
+/// Readable for code, writable for data
+const READ_WRITE: u64 = 1 << 41;
+const LONG_MODE: u64 = 1 << 53;
+/// Available 64-bit TSS
+const TYPE_TSS: u64 = 0x9 << 40;
+
+const CODE: u64 = PRESENT | NOT_SYSTEM | EXECUTABLE | READ_WRITE | LONG_MODE;
+const DATA: u64 = PRESENT | NOT_SYSTEM | READ_WRITE;
+
+/// Long mode TSS, which holds nothing but stack pointers
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct Tss {
+    reserved0: u32,
+    /// Stacks for entering each privilege level from a lower one
+    rsp: [u64; 3],
+    reserved1: u64,
+    ist: [u64; 7],
+    reserved2: u64,
+    reserved3: u16,
+    /// Offset of the I/O permission bitmap, which is beyond the end as there is none
+    iomap_base: u16,
+}
+
+/// Descriptor tables of a CPU
+pub struct Gdt {
+    entries: [u64; ENTRIES],
+    tss: Tss,
+}
+
+/// Operand of `lgdt`
+#[repr(C, packed)]
+struct Pointer {
+    limit: u16,
+    base: u64,
+}
+
+extern {
+    fn gdt_switch(pointer: *const Pointer, code: u16, data: u16);
+    fn tss_load(selector: u16);
+}
+
+impl Gdt {
+    pub const fn new() -> Gdt {
+        Gdt {
+            entries: [0, CODE, DATA, DATA | USER, CODE | USER, 0, 0],
+            tss: Tss {
+                reserved0: 0,
+                rsp: [0; 3],
+                reserved1: 0,
+                ist: [0; 7],
+                reserved2: 0,
+                reserved3: 0,
+                iomap_base: mem::size_of::<Tss>() as u16,
+            },
+        }
+    }
+    /// Fill in the TSS descriptor, which can only be done once the TSS has its final address
+    fn describe_tss(&mut self) {
+        let base = &self.tss as *const Tss as u64;
+        let limit = (mem::size_of::<Tss>() - 1) as u64;
+        let index = TSS as usize / 8;
+        self.entries[index] = PRESENT | TYPE_TSS | limit | (base & 0xFF_FFFF) << 16 | (base >> 24 & 0xFF) << 56;
+        self.entries[index + 1] = base >> 32;
+    }
+    /// Make these the tables of the current CPU
+    ///
+    /// # Safety
+    ///
+    /// Must not be in use by any other CPU.
+    pub unsafe fn load(&'static mut self) {
+        self.describe_tss();
+        let pointer = Pointer {limit: (mem::size_of_val(&self.entries) - 1) as u16, base: self.entries.as_ptr() as u64};
+        gdt_switch(&pointer, KERNEL_CODE, KERNEL_DATA);
+        tss_load(TSS);
+    }
+    /// Stack that exceptions using IST slot `ist` switch to
+    pub fn set_ist(&mut self, ist: u8, top: usize) {
+        assert!(ist >= 1 && ist <= 7, "No IST slot {}", ist);
+        self.tss.ist[ist as usize - 1] = top as u64;
+    }
+}
+
+static mut BSP: Gdt = Gdt::new();
+
+/// Load the tables of the boot CPU
+///
+/// The tables are part of the kernel image, so unlike those used by the boot code they stay
+/// mapped once the boot mappings are removed.
+pub fn init() {
+    unsafe {BSP.load()};
+}
+
+/// Give the exceptions that may happen on a broken stack stacks of their own
+///
+/// Needs the kernel address space, from which the stacks are allocated.
+pub fn init_stacks() {
+    for &(vector, ist) in IST_VECTORS.iter() {
+        let stack = match unsafe {Stack::new_kernel(&mut STATE.kernel_as)} {
+            Some(stack) => stack,
+            None => {
+                print!(Error, "No memory for the stack of vector {}, it stays on the current stack", vector);
+                continue;
+            },
+        };
+        unsafe {
+            BSP.set_ist(ist, stack.top());
+            idt::set_ist(vector, ist);
+        }
+    }
+    print!(Info, "Exception stacks allocated");
+}
@@ -0,0 +1,194 @@
+//! Interrupt Descriptor Table
+//!
+//! Each of the 32 exception vectors enters through a stub in `idt_entry.S`, which saves the full
+//! register state as a `Registers` before calling `exception_handler`. No exception is recoverable
+//! yet, so the handler prints everything known about the fault and panics. The remaining vectors
+//! are interrupts, which `irq` dispatches to whatever registered for them.
+//!
+//! Those exceptions that can arrive when the stack is broken are moved onto stacks of their own,
+//! from the TSS set up by `gdt`, once the kernel address space exists to allocate them from.
+
+use core::mem;
+use cpu::gdt;
+
+/// Number of vectors in the IDT
+pub const VECTORS: usize = 256;
+/// Vectors reserved by the processor for exceptions
+pub const EXCEPTIONS: usize = 32;
+
+pub const NMI: u8 = 2;
+pub const DOUBLE_FAULT: u8 = 8;
+pub const PAGE_FAULT: u8 = 14;
+pub const MACHINE_CHECK: u8 = 18;
+
+/// Present, ring 0, 64-bit interrupt gate
+const GATE_INTERRUPT: u8 = 0x8E;
+
+/// Spacing of the interrupt stubs
+const INTERRUPT_STUB_SIZE: usize = 16;
+
+/// Names of the exception vectors
+const NAMES: [&str; EXCEPTIONS] = [
+    "Divide error (#DE)",
+    "Debug (#DB)",
+    "Non-maskable interrupt (NMI)",
+    "Breakpoint (#BP)",
+    "Overflow (#OF)",
+    "Bound range exceeded (#BR)",
+    "Invalid opcode (#UD)",
+    "Device not available (#NM)",
+    "Double fault (#DF)",
+    "Coprocessor segment overrun",
+    "Invalid TSS (#TS)",
+    "Segment not present (#NP)",
+    "Stack segment fault (#SS)",
+    "General protection (#GP)",
+    "Page fault (#PF)",
+    "Reserved",
+    "x87 floating point (#MF)",
+    "Alignment check (#AC)",
+    "Machine check (#MC)",
+    "SIMD floating point (#XM)",
+    "Virtualization (#VE)",
+    "Control protection (#CP)",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Hypervisor injection (#HV)",
+    "VMM communication (#VC)",
+    "Security (#SX)",
+    "Reserved",
+];
+
+/// Gate descriptor
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Entry {
+    offset_low: u16,
+    selector: u16,
+    /// Interrupt stack table index, 0 for the current stack
+    ist: u8,
+    flags: u8,
+    offset_mid: u16,
+    offset_high: u32,
+    reserved: u32,
+}
+
+impl Entry {
+    const fn missing() -> Entry {
+        Entry {offset_low: 0, selector: 0, ist: 0, flags: 0, offset_mid: 0, offset_high: 0, reserved: 0}
+    }
+    fn new(handler: usize) -> Entry {
+        Entry {
+            offset_low: handler as u16,
+            selector: gdt::KERNEL_CODE,
+            ist: 0,
+            flags: GATE_INTERRUPT,
+            offset_mid: (handler >> 16) as u16,
+            offset_high: (handler >> 32) as u32,
+            reserved: 0,
+        }
+    }
+}
+
+/// Operand of `lidt`
+#[repr(C, packed)]
+struct Pointer {
+    limit: u16,
+    base: u64,
+}
+
+/// State at the time of an exception, as saved by the entry stub
+///
+/// Fields are in the order they are on the stack, with the last ones pushed by the processor.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Registers {
+    pub cr2: u64,
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    /// Zero for the exceptions that do not have one
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+extern {
+    /// Entry stubs of the exception vectors, in order
+    static exception_stubs: [usize; EXCEPTIONS];
+    /// First of the interrupt stubs, which follow on every `INTERRUPT_STUB_SIZE` bytes
+    static interrupt_stubs: u8;
+    fn idt_load(pointer: *const Pointer);
+}
+
+static mut IDT: [Entry; VECTORS] = [Entry::missing(); VECTORS];
+
+/// Describe the cause of a page fault from its error code
+fn page_fault_cause(error_code: u64) -> (&'static str, &'static str, &'static str) {
+    (
+        if error_code & (1 << 0) != 0 { "protection violation" } else { "not present" },
+        if error_code & (1 << 4) != 0 { "instruction fetch" } else if error_code & (1 << 1) != 0 { "write" } else { "read" },
+        if error_code & (1 << 2) != 0 { "user" } else { "kernel" },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn exception_handler(regs: &Registers) -> ! {
+    let name = NAMES[regs.vector as usize % EXCEPTIONS];
+    print!(Panic, "{} at {:#x}, vector {} error code {:#x} cr2 {:#x}", name, regs.rip, regs.vector, regs.error_code, regs.cr2);
+    if regs.vector == PAGE_FAULT as u64 {
+        let (kind, access, mode) = page_fault_cause(regs.error_code);
+        print!(Panic, "Page fault is {} on {} by {} of {:#x}", kind, access, mode, regs.cr2);
+    }
+    print!(Panic, "rax {:016x} rbx {:016x} rcx {:016x} rdx {:016x}", regs.rax, regs.rbx, regs.rcx, regs.rdx);
+    print!(Panic, "rsi {:016x} rdi {:016x} rbp {:016x} rsp {:016x}", regs.rsi, regs.rdi, regs.rbp, regs.rsp);
+    print!(Panic, "r8  {:016x} r9  {:016x} r10 {:016x} r11 {:016x}", regs.r8, regs.r9, regs.r10, regs.r11);
+    print!(Panic, "r12 {:016x} r13 {:016x} r14 {:016x} r15 {:016x}", regs.r12, regs.r13, regs.r14, regs.r15);
+    print!(Panic, "rip {:016x} rflags {:08x} cs {:04x} ss {:04x}", regs.rip, regs.rflags, regs.cs, regs.ss);
+    panic!("Unhandled exception {}", name);
+}
+
+/// Install the exception handlers and the interrupt entry points
+pub fn init() {
+    unsafe {
+        for (entry, &stub) in IDT.iter_mut().zip(exception_stubs.iter()) {
+            *entry = Entry::new(stub);
+        }
+        let first = &interrupt_stubs as *const u8 as usize;
+        for (i, entry) in IDT[EXCEPTIONS..].iter_mut().enumerate() {
+            *entry = Entry::new(first + i * INTERRUPT_STUB_SIZE);
+        }
+        let idt = Pointer {limit: (mem::size_of_val(&IDT) - 1) as u16, base: IDT.as_ptr() as u64};
+        idt_load(&idt);
+    }
+    print!(Info, "Exception handlers installed");
+}
+
+/// Have `vector` switch to the stack in IST slot `ist` of the TSS
+///
+/// # Safety
+///
+/// The slot must hold a usable stack in the TSS of every CPU.
+pub unsafe fn set_ist(vector: u8, ist: u8) {
+    IDT[vector as usize].ist = ist;
+}
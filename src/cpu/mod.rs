@@ -1,10 +1,12 @@
 pub mod features;
+pub mod gdt;
+pub mod idt;
 mod pat;
 
 pub use self::features::Features;
 use state::CPU_FEATURES;
 use x86::shared::control_regs::{cr4, cr4_write, CR4_ENABLE_GLOBAL_PAGES, cr3_write};
-use x86::bits64::paging::{PDPTEntry, PDPT_PWT, PDPT_PCD, PDPT_PAT};
+use x86::bits64::paging::{PDPTEntry, PDPT_PWT, PDPT_PCD, PDPT_PAT, PDEntry, PD_PWT, PD_PCD, PD_PAT};
 
 /// x86 Memory Types
 #[derive(Debug, Clone, Copy)]
@@ -49,6 +51,23 @@ impl From<MemoryType> for PDPTEntry {
     }
 }
 
+impl From<MemoryType> for PDEntry {
+    fn from(mt: MemoryType) -> PDEntry {
+        let mut entry = PDEntry::empty();
+        let index = pat::Entry::from(mt).index();
+        if index.pwt() {
+            entry.insert(PD_PWT);
+        }
+        if index.pcd() {
+            entry.insert(PD_PCD);
+        }
+        if index.pat() {
+            entry.insert(PD_PAT);
+        }
+        entry
+    }
+}
+
 /// Read MSR wrapper
 ///
 /// This wrapper allows for handling faults to deal with MSRs that may or may not exist
@@ -83,6 +102,8 @@ pub fn init() -> bool {
         Ok(features) => unsafe { CPU_FEATURES = features; },
     }
     print!(Info, "CPU has minimal supported features");
+    gdt::init();
+    idt::init();
     // TODO: printout feature list
     print!(Info, "Initializing CPU");
     pat::init();
@@ -26,7 +26,7 @@ impl Index {
         (self.0 % 2) == 1
     }
     pub fn pcd(&self) -> bool {
-        ((self.0 / 2) % 2) == 0
+        ((self.0 / 2) % 2) == 1
     }
     pub fn pat(&self) -> bool {
         self.0 / 4 == 1
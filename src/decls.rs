@@ -9,6 +9,7 @@
 //! be parameterized by a cmdline argument and just have it 'work' without introducing further logic
 
 use core;
+use bus::Device;
 
 // Random bytes curtesy of random.org
 pub const DECL_NONCE: u64 = 0x4ea4789985e1ad56;
@@ -26,9 +27,43 @@ pub struct CMDLine {
 pub struct SelfTest {
 }
 
+/// Devices that a driver can be bound to
+pub enum Match {
+    /// PCI function, with any field that is `None` matching anything
+    Pci { vendor: Option<u16>, device: Option<u16>, class: Option<(u8, u8)> },
+    /// Device in the ACPI namespace with this hardware ID
+    Acpi(&'static str),
+    /// Legacy device that can only be found by the driver probing for it
+    Isa,
+}
+
+impl Match {
+    /// Match a PCI vendor and device ID
+    pub const fn pci(vendor: u16, device: u16) -> Match {
+        Match::Pci { vendor: Some(vendor), device: Some(device), class: None }
+    }
+    /// Match a PCI class and subclass
+    pub const fn pci_class(class: u8, subclass: u8) -> Match {
+        Match::Pci { vendor: None, device: None, class: Some((class, subclass)) }
+    }
+}
+
+/// Declares a driver and the devices it can drive
+///
+/// The bus core calls `probe` with each discovered device that matches, and the driver returns
+/// whether it took the device. `remove` is called with a device that is going away, and must
+/// release everything the driver took for it.
+pub struct Driver {
+    pub name: &'static str,
+    pub matches: &'static [Match],
+    pub probe: fn(&Device) -> bool,
+    pub remove: fn(&Device),
+}
+
 pub enum Type {
     CMDLine(CMDLine),
     SelfTest(SelfTest),
+    Driver(Driver),
 }
 
 #[repr(align(64))]
@@ -87,3 +122,10 @@ macro_rules! make_cmdline_decl {
         $crate::decls::Type::CMDLine($crate::decls::CMDLine{option:$option, f: $function}), $name
     );}
 }
+
+#[macro_export]
+macro_rules! make_driver_decl {
+    ($driver:expr, $matches:expr, $probe:expr, $remove:expr, $name:ident) => {make_decl!(
+        $crate::decls::Type::Driver($crate::decls::Driver{name: $driver, matches: $matches, probe: $probe, remove: $remove}), $name
+    );}
+}
@@ -0,0 +1,127 @@
+//! AHCI host bus adapter
+//!
+//! The generic host control registers at the start of the ABAR, which cover the whole
+//! controller. Each port then has its own register block, see `port`.
+
+use drivers::io::{wait, Io};
+
+pub const CAP: u16 = 0x00;
+pub const GHC: u16 = 0x04;
+pub const IS: u16 = 0x08;
+pub const PI: u16 = 0x0C;
+pub const VS: u16 = 0x10;
+pub const CAP2: u16 = 0x24;
+pub const BOHC: u16 = 0x28;
+
+/// Supports 64-bit addressing
+pub const CAP_S64A: u32 = 1 << 31;
+/// Supports staggered spin-up, so ports have to be spun up by the driver
+pub const CAP_SSS: u32 = 1 << 27;
+
+/// BIOS/OS handoff supported
+pub const CAP2_BOH: u32 = 1 << 0;
+
+pub const GHC_HR: u32 = 1 << 0;
+pub const GHC_IE: u32 = 1 << 1;
+/// AHCI enable, without which the controller may be in legacy IDE mode
+pub const GHC_AE: u32 = 1 << 31;
+
+/// BIOS owned semaphore
+pub const BOHC_BOS: u32 = 1 << 0;
+/// OS owned semaphore
+pub const BOHC_OOS: u32 = 1 << 1;
+/// BIOS busy, cleaning up before handing over
+pub const BOHC_BB: u32 = 1 << 4;
+
+/// Most ports a controller can have
+pub const MAX_PORTS: u8 = 32;
+
+/// Polls of a register before the controller is given up on
+pub const POLL_LIMIT: usize = 1000000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Controller did not complete a reset
+    ResetTimeout,
+    /// BIOS did not give up the controller
+    Handoff,
+    /// No device is attached to the port
+    NoDevice,
+    /// Device is not an ATA disk, with its signature
+    NotAta(u32),
+    /// Port did not start or stop
+    PortTimeout,
+    /// Command did not complete
+    Timeout,
+    /// Device failed a command, with its status and error registers
+    Device { status: u8, error: u8 },
+    /// Memory for the port could not be allocated
+    NoMemory,
+    /// Memory for the port is above 4GiB, which the controller cannot address
+    Addressing,
+}
+
+pub struct Hba<T: Io<Item = u32>> {
+    io: T,
+    cap: u32,
+}
+
+impl<T, R> Hba<T> where T: Io<Item = u32, Range=R>, R: From<u16> {
+    pub unsafe fn new(mut io: T) -> Hba<T> {
+        let cap = io.read(R::from(CAP));
+        Hba { io: io, cap: cap }
+    }
+    unsafe fn read(&mut self, reg: u16) -> u32 {
+        self.io.read(R::from(reg))
+    }
+    unsafe fn write(&mut self, reg: u16, value: u32) {
+        self.io.write(R::from(reg), value)
+    }
+    pub fn cap(&self) -> u32 {
+        self.cap
+    }
+    /// Number of command slots each port has
+    pub fn slots(&self) -> u8 {
+        (self.cap >> 8 & 0x1F) as u8 + 1
+    }
+    pub fn supports_64bit(&self) -> bool {
+        self.cap & CAP_S64A != 0
+    }
+    /// AHCI version, as (major, minor)
+    pub unsafe fn version(&mut self) -> (u16, u16) {
+        let vs = self.read(VS);
+        ((vs >> 16) as u16, vs as u16)
+    }
+    /// Bitmap of the ports the controller has
+    pub unsafe fn ports_implemented(&mut self) -> u32 {
+        self.read(PI)
+    }
+    /// Take the controller from the BIOS, if it supports handing it over
+    pub unsafe fn take_ownership(&mut self) -> Result<(), Error> {
+        if self.read(CAP2) & CAP2_BOH == 0 {
+            return Ok(());
+        }
+        let bohc = self.read(BOHC);
+        self.write(BOHC, bohc | BOHC_OOS);
+        if !wait(POLL_LIMIT, || self.io.read(R::from(BOHC)), |bohc| bohc & (BOHC_BOS | BOHC_BB) == 0) {
+            return Err(Error::Handoff);
+        }
+        Ok(())
+    }
+    /// Reset the controller and enable AHCI mode, with interrupts disabled
+    ///
+    /// Resetting stops every port and returns them to their power on state.
+    pub unsafe fn reset(&mut self) -> Result<(), Error> {
+        // The reset bit is only defined when AHCI is enabled
+        self.write(GHC, GHC_AE);
+        self.write(GHC, GHC_AE | GHC_HR);
+        if !wait(POLL_LIMIT, || self.io.read(R::from(GHC)), |ghc| ghc & GHC_HR == 0) {
+            return Err(Error::ResetTimeout);
+        }
+        self.write(GHC, GHC_AE);
+        self.cap = self.read(CAP);
+        let is = self.read(IS);
+        self.write(IS, is);
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+//! AHCI SATA controllers
+//!
+//! The controller is reset and taken from the BIOS, then every implemented port with an ATA
+//! disk is started and registered as a block device. Commands are polled, so the controller's
+//! interrupt is left disabled. ATAPI devices and port multipliers are not supported.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use block;
+use bus::{self, Device};
+use cpu::MemoryType;
+use decls::Match;
+use drivers::io::MemIO;
+use drivers::pci::{Address, Command};
+use state::STATE;
+
+pub mod hba;
+pub mod port;
+
+pub use self::hba::{Error, Hba};
+pub use self::port::{Identity, Port};
+
+/// The ABAR, holding every register of the controller
+const ABAR: u8 = 5;
+const CLASS_STORAGE: u8 = 0x01;
+const SUBCLASS_SATA: u8 = 0x06;
+const PROG_IF_AHCI: u8 = 0x01;
+
+/// Disks registered from each controller, by the function it is
+static mut DISKS: Option<Vec<(Address, String)>> = None;
+
+fn probe(device: &Device) -> bool {
+    let function = match *device {
+        Device::Pci(function) => function,
+        _ => return false,
+    };
+    if function.prog_if != PROG_IF_AHCI {
+        return false;
+    }
+    // Registers must not be cached. UC- is weakened to WC by an MTRR write combining range, but
+    // firmware only sets those up over framebuffers, so the ABAR stays uncached
+    let abar = match bus::pci::map_bar_as(function, ABAR, MemoryType::UC) {
+        Some(abar) => abar,
+        None => {
+            print!(Error, "AHCI {} has no ABAR", function.address);
+            return false;
+        },
+    };
+    unsafe {bus::pci::config(function).enable(Command::MEMORY | Command::BUS_MASTER)};
+    let mut hba = unsafe {Hba::new(MemIO::<u32>::new(abar))};
+    let version = unsafe {hba.version()};
+    if let Err(error) = unsafe {hba.take_ownership().and_then(|()| hba.reset())} {
+        print!(Error, "Failed to reset AHCI {}: {:?}", function.address, error);
+        return false;
+    }
+    let implemented = unsafe {hba.ports_implemented()};
+    print!(Info, "AHCI {} version {}.{}, {} command slots, ports {:#x}", function.address, version.0, version.1,
+        hba.slots(), implemented);
+    let disks = unsafe {DISKS.get_or_insert_with(Vec::new)};
+    for index in (0..hba::MAX_PORTS).filter(|index| implemented & 1 << index != 0) {
+        match unsafe {Port::new(MemIO::<u32>::new(abar), index, &STATE.kernel_as)} {
+            Ok(port) => {
+                {
+                    let identity = port.identity();
+                    print!(Info, "AHCI {} port {}: {} serial {}", function.address, index, identity.model, identity.serial);
+                }
+                let name = block::register("sd", box port);
+                disks.push((function.address, name));
+            },
+            Err(Error::NoDevice) => (),
+            Err(error) => print!(Error, "Failed to start AHCI {} port {}: {:?}", function.address, index, error),
+        }
+    }
+    true
+}
+
+fn remove(device: &Device) {
+    if let Device::Pci(function) = *device {
+        let disks = unsafe {DISKS.get_or_insert_with(Vec::new)};
+        while let Some(index) = disks.iter().position(|disk| disk.0 == function.address) {
+            let (_, name) = disks.remove(index);
+            block::unregister(&name);
+        }
+    }
+}
+
+make_driver_decl!("ahci", &[Match::pci_class(CLASS_STORAGE, SUBCLASS_SATA)], probe, remove, AHCI_DRIVER);
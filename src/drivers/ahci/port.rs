@@ -0,0 +1,337 @@
+//! AHCI port with an ATA disk attached
+//!
+//! The port is given a command list, a received FIS area, and a single command table, so only
+//! command slot 0 is used and each command is waited on before the next is made. Like
+//! virtio-blk, data goes through a bounce buffer, which larger transfers are split to fit.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use block::{self, BlockDevice};
+use drivers::dma::Dma;
+use drivers::io::{wait, Io};
+use vspace::Translation;
+use super::hba::{self, Error};
+
+/// Offset of the first port's registers, each port having `PORT_SIZE` bytes of them
+const PORT_BASE: u16 = 0x100;
+const PORT_SIZE: u16 = 0x80;
+
+pub const CLB: u16 = 0x00;
+pub const CLBU: u16 = 0x04;
+pub const FB: u16 = 0x08;
+pub const FBU: u16 = 0x0C;
+pub const IS: u16 = 0x10;
+pub const IE: u16 = 0x14;
+pub const CMD: u16 = 0x18;
+pub const TFD: u16 = 0x20;
+pub const SIG: u16 = 0x24;
+pub const SSTS: u16 = 0x28;
+pub const SERR: u16 = 0x30;
+pub const CI: u16 = 0x38;
+
+pub const CMD_ST: u32 = 1 << 0;
+pub const CMD_SUD: u32 = 1 << 1;
+pub const CMD_POD: u32 = 1 << 2;
+pub const CMD_FRE: u32 = 1 << 4;
+pub const CMD_FR: u32 = 1 << 14;
+pub const CMD_CR: u32 = 1 << 15;
+
+/// Task file error
+pub const IS_TFES: u32 = 1 << 30;
+
+pub const TFD_ERR: u32 = 1 << 0;
+pub const TFD_DRQ: u32 = 1 << 3;
+pub const TFD_BSY: u32 = 1 << 7;
+
+/// Device detection field of the SATA status
+const SSTS_DET: u32 = 0xF;
+/// Device present and communication established
+const DET_PRESENT: u32 = 3;
+
+pub const SIG_ATA: u32 = 0x0000_0101;
+
+pub const ATA_READ_DMA: u8 = 0xC8;
+pub const ATA_READ_DMA_EXT: u8 = 0x25;
+pub const ATA_WRITE_DMA: u8 = 0xCA;
+pub const ATA_WRITE_DMA_EXT: u8 = 0x35;
+pub const ATA_FLUSH_CACHE: u8 = 0xE7;
+pub const ATA_FLUSH_CACHE_EXT: u8 = 0xEA;
+pub const ATA_IDENTIFY: u8 = 0xEC;
+
+const FIS_H2D: u8 = 0x27;
+/// The FIS holds a command, rather than a device control update
+const FIS_COMMAND: u8 = 1 << 7;
+const FIS_H2D_SIZE: usize = 20;
+/// LBA addressing, in the device register
+const DEVICE_LBA: u8 = 1 << 6;
+
+/// Command header flags, after the FIS length in dwords
+const HEADER_WRITE: u32 = 1 << 6;
+
+const COMMAND_LIST_SIZE: usize = 1024;
+const RECEIVED_FIS_SIZE: usize = 256;
+const PRDT_OFFSET: usize = 0x80;
+const COMMAND_TABLE_SIZE: usize = PRDT_OFFSET + 16;
+/// Largest transfer made in one command, which one PRDT entry can describe
+const BOUNCE_SIZE: usize = 64 * 1024;
+/// Highest sector that LBA28 commands can reach
+const LBA28_LIMIT: u64 = 1 << 28;
+
+/// What IDENTIFY DEVICE reports about a disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub model: String,
+    pub serial: String,
+    pub sectors: u64,
+    pub sector_size: usize,
+    pub lba48: bool,
+}
+
+impl Identity {
+    /// Decode the 256 words of IDENTIFY DEVICE data
+    pub fn parse(data: &[u8]) -> Identity {
+        let word = |index: usize| data[index * 2] as u16 | (data[index * 2 + 1] as u16) << 8;
+        // Strings are stored with the bytes of each word swapped
+        let string = |words: ::core::ops::Range<usize>| {
+            let mut bytes = Vec::new();
+            for index in words {
+                bytes.push((word(index) >> 8) as u8);
+                bytes.push(word(index) as u8);
+            }
+            String::from(String::from_utf8_lossy(&bytes).trim())
+        };
+        let lba48 = word(83) & 1 << 10 != 0;
+        let sectors = if lba48 {
+            (0..4).fold(0, |sectors, i| sectors | (word(100 + i) as u64) << (i * 16))
+        } else {
+            word(60) as u64 | (word(61) as u64) << 16
+        };
+        let size_info = word(106);
+        let sector_size = if size_info & 0xC000 == 0x4000 && size_info & 1 << 12 != 0 {
+            (word(117) as usize | (word(118) as usize) << 16) * 2
+        } else {
+            512
+        };
+        Identity { model: string(27..47), serial: string(10..20), sectors: sectors, sector_size: sector_size, lba48: lba48 }
+    }
+}
+
+pub struct Port<T> where T: Io<Item = u32>, T::Range: From<u16> {
+    io: T,
+    index: u8,
+    command_list: Dma,
+    received_fis: Dma,
+    table: Dma,
+    bounce: Dma,
+    identity: Identity,
+}
+
+impl<T, R> Port<T> where T: Io<Item = u32, Range=R>, R: From<u16> {
+    /// Start port `index` of the controller whose registers are `io`, and identify its disk
+    ///
+    /// The controller must have been reset. `translation` is passed to `Dma::new`.
+    pub unsafe fn new(mut io: T, index: u8, translation: &Translation) -> Result<Port<T>, Error> {
+        assert!(index < hba::MAX_PORTS, "AHCI port {} does not exist", index);
+        let cap = io.read(R::from(hba::CAP));
+        let alloc = |size, align| Dma::new(translation, size, align).ok_or(Error::NoMemory);
+        let mut port = Port {
+            io: io,
+            index: index,
+            command_list: alloc(COMMAND_LIST_SIZE, COMMAND_LIST_SIZE)?,
+            received_fis: alloc(RECEIVED_FIS_SIZE, RECEIVED_FIS_SIZE)?,
+            table: alloc(COMMAND_TABLE_SIZE, 128)?,
+            bounce: alloc(BOUNCE_SIZE, 4096)?,
+            identity: Identity { model: String::new(), serial: String::new(), sectors: 0, sector_size: 512, lba48: false },
+        };
+        let highest = [&port.command_list, &port.received_fis, &port.table, &port.bounce].iter()
+            .map(|dma| dma.paddr() + dma.len() as u64).max().unwrap();
+        if cap & hba::CAP_S64A == 0 && highest > 1 << 32 {
+            return Err(Error::Addressing);
+        }
+        port.stop()?;
+        let (command_list, received_fis) = (port.command_list.paddr(), port.received_fis.paddr());
+        port.write(CLB, command_list as u32);
+        port.write(CLBU, (command_list >> 32) as u32);
+        port.write(FB, received_fis as u32);
+        port.write(FBU, (received_fis >> 32) as u32);
+        port.write(IE, 0);
+        let cmd = port.read(CMD);
+        port.write(CMD, cmd | CMD_FRE);
+        if cap & hba::CAP_SSS != 0 {
+            let cmd = port.read(CMD);
+            port.write(CMD, cmd | CMD_SUD | CMD_POD);
+        }
+        if !wait(hba::POLL_LIMIT, || port.read(SSTS), |ssts| ssts & SSTS_DET == DET_PRESENT) {
+            port.stop()?;
+            return Err(Error::NoDevice);
+        }
+        port.write(SERR, 0xFFFF_FFFF);
+        port.write(IS, 0xFFFF_FFFF);
+        if !wait(hba::POLL_LIMIT, || port.read(TFD), |tfd| tfd & (TFD_BSY | TFD_DRQ) == 0) {
+            port.stop()?;
+            return Err(Error::PortTimeout);
+        }
+        let signature = port.read(SIG);
+        if signature != SIG_ATA {
+            port.stop()?;
+            return Err(Error::NotAta(signature));
+        }
+        port.start();
+        port.command(ATA_IDENTIFY, 0, 0, 512, false)?;
+        port.identity = Identity::parse(&port.bounce.as_slice()[..512]);
+        Ok(port)
+    }
+    unsafe fn read(&mut self, reg: u16) -> u32 {
+        self.io.read(R::from(PORT_BASE + self.index as u16 * PORT_SIZE + reg))
+    }
+    unsafe fn write(&mut self, reg: u16, value: u32) {
+        self.io.write(R::from(PORT_BASE + self.index as u16 * PORT_SIZE + reg), value)
+    }
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+    /// Stop processing commands and receiving FISes
+    unsafe fn stop(&mut self) -> Result<(), Error> {
+        let cmd = self.read(CMD);
+        self.write(CMD, cmd & !CMD_ST);
+        if !wait(hba::POLL_LIMIT, || self.read(CMD), |cmd| cmd & CMD_CR == 0) {
+            return Err(Error::PortTimeout);
+        }
+        let cmd = self.read(CMD);
+        self.write(CMD, cmd & !CMD_FRE);
+        if !wait(hba::POLL_LIMIT, || self.read(CMD), |cmd| cmd & CMD_FR == 0) {
+            return Err(Error::PortTimeout);
+        }
+        Ok(())
+    }
+    unsafe fn start(&mut self) {
+        let cmd = self.read(CMD);
+        self.write(CMD, cmd | CMD_ST);
+    }
+    /// Get the port going again after a failed command, which stops it
+    unsafe fn recover(&mut self) -> Result<(), Error> {
+        let cmd = self.read(CMD);
+        self.write(CMD, cmd & !CMD_ST);
+        if !wait(hba::POLL_LIMIT, || self.read(CMD), |cmd| cmd & CMD_CR == 0) {
+            return Err(Error::PortTimeout);
+        }
+        self.write(SERR, 0xFFFF_FFFF);
+        self.write(IS, 0xFFFF_FFFF);
+        self.start();
+        Ok(())
+    }
+    /// Run a command in slot 0, transferring `len` bytes of the bounce buffer
+    unsafe fn command(&mut self, command: u8, lba: u64, count: u16, len: usize, write: bool) -> Result<(), Error> {
+        // LBA28 commands take the top bits of the address in the device register
+        let device = if self.identity.lba48 { DEVICE_LBA } else { DEVICE_LBA | (lba >> 24) as u8 & 0xF };
+        {
+            let fis = &mut self.table.as_mut_slice()[..FIS_H2D_SIZE];
+            for byte in fis.iter_mut() {
+                *byte = 0;
+            }
+            fis[0] = FIS_H2D;
+            fis[1] = FIS_COMMAND;
+            fis[2] = command;
+            fis[4] = lba as u8;
+            fis[5] = (lba >> 8) as u8;
+            fis[6] = (lba >> 16) as u8;
+            fis[7] = device;
+            fis[8] = (lba >> 24) as u8;
+            fis[9] = (lba >> 32) as u8;
+            fis[10] = (lba >> 40) as u8;
+            fis[12] = count as u8;
+            fis[13] = (count >> 8) as u8;
+        }
+        let bounce = self.bounce.paddr();
+        put_u32(&mut self.table.as_mut_slice()[PRDT_OFFSET..], 0, bounce as u32);
+        put_u32(&mut self.table.as_mut_slice()[PRDT_OFFSET..], 4, (bounce >> 32) as u32);
+        put_u32(&mut self.table.as_mut_slice()[PRDT_OFFSET..], 12, (len as u32).saturating_sub(1));
+        let prdt_len = if len > 0 { 1 } else { 0 };
+        let flags = (FIS_H2D_SIZE / 4) as u32 | if write { HEADER_WRITE } else { 0 } | prdt_len << 16;
+        let table = self.table.paddr();
+        {
+            let header = self.command_list.as_mut_slice();
+            put_u32(header, 0, flags);
+            put_u32(header, 4, 0);
+            put_u32(header, 8, table as u32);
+            put_u32(header, 12, (table >> 32) as u32);
+        }
+        self.write(IS, 0xFFFF_FFFF);
+        self.write(CI, 1);
+        // Slot 0 stays issued if the command fails, so an error also ends the wait
+        let done = wait(hba::POLL_LIMIT, || self.read(CI) & 1 | self.read(IS) & IS_TFES, |state| state != 1);
+        let tfd = self.read(TFD);
+        if self.read(IS) & IS_TFES != 0 || tfd & TFD_ERR != 0 {
+            self.recover()?;
+            Err(Error::Device { status: tfd as u8, error: (tfd >> 8) as u8 })
+        } else if !done {
+            self.recover()?;
+            Err(Error::Timeout)
+        } else {
+            Ok(())
+        }
+    }
+    /// Transfer sectors through the bounce buffer, with `len` bytes of them
+    unsafe fn transfer(&mut self, sector: u64, len: usize, write: bool) -> Result<(), block::Error> {
+        let count = (len / self.identity.sector_size) as u16;
+        let end = sector + count as u64;
+        let command = match (self.identity.lba48, write) {
+            (true, false) => ATA_READ_DMA_EXT,
+            (true, true) => ATA_WRITE_DMA_EXT,
+            (false, _) if end > LBA28_LIMIT => return Err(block::Error::OutOfRange),
+            (false, false) => ATA_READ_DMA,
+            (false, true) => ATA_WRITE_DMA,
+        };
+        self.command(command, sector, count, len, write).map_err(|_| block::Error::Io)
+    }
+}
+
+fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    for i in 0..4 {
+        bytes[offset + i] = (value >> (i * 8)) as u8;
+    }
+}
+
+impl<T, R> BlockDevice for Port<T> where T: Io<Item = u32, Range=R>, R: From<u16> {
+    fn sector_size(&self) -> usize {
+        self.identity.sector_size
+    }
+    fn sectors(&self) -> u64 {
+        self.identity.sectors
+    }
+    fn read_only(&self) -> bool {
+        false
+    }
+    unsafe fn read(&mut self, sector: u64, buffer: &mut [u8]) -> Result<(), block::Error> {
+        let sector_size = self.identity.sector_size;
+        for (index, chunk) in buffer.chunks_mut(BOUNCE_SIZE).enumerate() {
+            let offset = (index * BOUNCE_SIZE / sector_size) as u64;
+            self.transfer(sector + offset, chunk.len(), false)?;
+            chunk.copy_from_slice(&self.bounce.as_slice()[..chunk.len()]);
+        }
+        Ok(())
+    }
+    unsafe fn write(&mut self, sector: u64, buffer: &[u8]) -> Result<(), block::Error> {
+        let sector_size = self.identity.sector_size;
+        for (index, chunk) in buffer.chunks(BOUNCE_SIZE).enumerate() {
+            let offset = (index * BOUNCE_SIZE / sector_size) as u64;
+            self.bounce.as_mut_slice()[..chunk.len()].copy_from_slice(chunk);
+            self.transfer(sector + offset, chunk.len(), true)?;
+        }
+        Ok(())
+    }
+    unsafe fn flush(&mut self) -> Result<(), block::Error> {
+        let command = if self.identity.lba48 { ATA_FLUSH_CACHE_EXT } else { ATA_FLUSH_CACHE };
+        self.command(command, 0, 0, 0, false).map_err(|_| block::Error::Io)
+    }
+}
+
+impl<T> Drop for Port<T> where T: Io<Item = u32>, T::Range: From<u16> {
+    fn drop(&mut self) {
+        // Stop the port before the memory it has been given is freed
+        let _ = unsafe {self.stop()};
+    }
+}
@@ -0,0 +1,314 @@
+//! Driver for the local APIC
+//!
+//! The local APIC is either accessed through a page of memory mapped registers (xAPIC), or in
+//! x2APIC mode through MSRs. Registers are described here by their xAPIC offset, and the `Msr`
+//! accessor translates these into the equivalent x2APIC MSR, so the driver itself only needs to
+//! know the mode for the few places where the register formats differ.
+
+use super::io::Io;
+use x86::shared::msr::{rdmsr, wrmsr, IA32_TSC_DEADLINE};
+
+/// Size of the xAPIC register window
+pub const MMIO_SIZE: usize = 0x1000;
+
+const ID: u16 = 0x20;
+const VERSION: u16 = 0x30;
+const TPR: u16 = 0x80;
+const EOI: u16 = 0xB0;
+const SVR: u16 = 0xF0;
+const ESR: u16 = 0x280;
+const ICR_LOW: u16 = 0x300;
+const ICR_HIGH: u16 = 0x310;
+const LVT_TIMER: u16 = 0x320;
+const LVT_LINT0: u16 = 0x350;
+const LVT_LINT1: u16 = 0x360;
+const LVT_ERROR: u16 = 0x370;
+const TIMER_INITIAL: u16 = 0x380;
+const TIMER_CURRENT: u16 = 0x390;
+const TIMER_DIVIDE: u16 = 0x3E0;
+
+/// First x2APIC MSR, registers follow at one MSR per 16 bytes of xAPIC offset
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+/// Software enable in the spurious vector register
+const SVR_ENABLE: u32 = 1 << 8;
+/// Mask bit common to all local vector table entries
+const LVT_MASKED: u32 = 1 << 16;
+
+/// Number of times to poll for an xAPIC IPI to be accepted before giving up
+const POLL_LIMIT: usize = 100000;
+
+bitfield!{
+    /// Local vector table entry for the timer
+    #[derive(Clone, Copy)]
+    pub struct LvtTimer(u32);
+    pub vector, set_vector: 7, 0;
+    pub pending, _: 12;
+    pub masked, set_masked: 16;
+    pub mode, set_mode: 18, 17;
+}
+
+bitfield!{
+    /// Low half of the interrupt command register
+    struct IcrLow(u32);
+    _, set_vector: 7, 0;
+    _, set_delivery_mode: 10, 8;
+    pending, _: 12;
+    _, set_assert: 14;
+    _, set_shorthand: 19, 18;
+}
+
+/// Counting modes of the local APIC timer
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    /// Count down once from the initial count
+    OneShot = 0,
+    /// Count down repeatedly, reloading from the initial count
+    Periodic = 1,
+    /// Fire when the TSC reaches the value in IA32_TSC_DEADLINE
+    TscDeadline = 2,
+}
+
+/// Divider applied to the bus clock before it drives the timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Divide {
+    By1,
+    By2,
+    By4,
+    By8,
+    By16,
+    By32,
+    By64,
+    By128,
+}
+
+impl Divide {
+    /// Encoding for the divide configuration register, whose bit 2 is reserved
+    fn bits(self) -> u32 {
+        match self {
+            Divide::By1 => 0b1011,
+            Divide::By2 => 0b0000,
+            Divide::By4 => 0b0001,
+            Divide::By8 => 0b0010,
+            Divide::By16 => 0b0011,
+            Divide::By32 => 0b1000,
+            Divide::By64 => 0b1001,
+            Divide::By128 => 0b1010,
+        }
+    }
+}
+
+/// How an IPI is delivered to its destination
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryMode {
+    Fixed = 0,
+    LowestPriority = 1,
+    SMI = 2,
+    NMI = 4,
+    Init = 5,
+    StartUp = 6,
+}
+
+/// Target of an IPI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    /// Single processor by physical APIC ID
+    Apic(u32),
+    /// The sending processor
+    Current,
+    /// Every processor, including the sender
+    All,
+    /// Every processor other than the sender
+    Others,
+}
+
+impl Destination {
+    fn shorthand(self) -> u32 {
+        match self {
+            Destination::Apic(_) => 0,
+            Destination::Current => 1,
+            Destination::All => 2,
+            Destination::Others => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Physical APIC ID does not fit in the xAPIC destination field
+    InvalidDestination(u32),
+    /// Previous IPI was never accepted
+    Timeout,
+}
+
+/// x2APIC register access through MSRs
+///
+/// The x2APIC replaces the pair of 32-bit ICR registers with a single 64-bit MSR. To keep the
+/// xAPIC register layout a write to the high half is held back and combined with the write of
+/// the low half, which is what triggers sending in both modes anyway.
+pub struct Msr {
+    icr_high: u32,
+}
+
+impl Msr {
+    /// # Safety
+    ///
+    /// The local APIC must be in x2APIC mode for any access to succeed
+    pub unsafe fn new() -> Msr {
+        Msr { icr_high: 0 }
+    }
+}
+
+impl Io for Msr {
+    type Item = u32;
+    type Range = u16;
+    unsafe fn read(&mut self, offset: u16) -> u32 {
+        rdmsr(X2APIC_MSR_BASE + (offset as u32 >> 4)) as u32
+    }
+    unsafe fn write(&mut self, offset: u16, value: u32) {
+        match offset {
+            ICR_HIGH => self.icr_high = value,
+            ICR_LOW => wrmsr(X2APIC_MSR_BASE + (ICR_LOW as u32 >> 4), (self.icr_high as u64) << 32 | value as u64),
+            _ => wrmsr(X2APIC_MSR_BASE + (offset as u32 >> 4), value as u64),
+        }
+    }
+}
+
+pub struct LocalApic<T: Io<Item = u32>> {
+    io: T,
+    x2apic: bool,
+}
+
+impl<T, R> LocalApic<T> where T: Io<Item = u32, Range=R>, R: From<u16> {
+    unsafe fn read(&mut self, reg: u16) -> u32 {
+        self.io.read(R::from(reg))
+    }
+    unsafe fn write(&mut self, reg: u16, value: u32) {
+        self.io.write(R::from(reg), value)
+    }
+    /// Physical APIC ID of this processor
+    pub unsafe fn id(&mut self) -> u32 {
+        let id = self.read(ID);
+        if self.x2apic { id } else { id >> 24 }
+    }
+    pub unsafe fn version(&mut self) -> u8 {
+        self.read(VERSION) as u8
+    }
+    /// Number of entries in the local vector table
+    pub unsafe fn max_lvt(&mut self) -> u8 {
+        (self.read(VERSION) >> 16) as u8 + 1
+    }
+    pub fn is_x2apic(&self) -> bool {
+        self.x2apic
+    }
+    /// Software enable the APIC, with spurious interrupts delivered on `spurious`
+    ///
+    /// All local interrupt sources are masked, except errors which are delivered on `error`,
+    /// and the task priority is lowered to accept every interrupt.
+    pub unsafe fn enable(&mut self, spurious: u8, error: u8) {
+        self.write(LVT_TIMER, LVT_MASKED);
+        self.write(LVT_LINT0, LVT_MASKED);
+        self.write(LVT_LINT1, LVT_MASKED);
+        self.write(LVT_ERROR, error as u32);
+        // The ESR must be written before it can be read, which also clears it
+        self.write(ESR, 0);
+        self.write(TPR, 0);
+        self.write(SVR, SVR_ENABLE | spurious as u32);
+    }
+    pub unsafe fn disable(&mut self) {
+        let svr = self.read(SVR);
+        self.write(SVR, svr & !SVR_ENABLE);
+    }
+    /// Read and clear the error status
+    pub unsafe fn error_status(&mut self) -> u32 {
+        self.write(ESR, 0);
+        self.read(ESR)
+    }
+    /// Signal end of interrupt for the highest priority interrupt in service
+    pub unsafe fn eoi(&mut self) {
+        self.write(EOI, 0)
+    }
+    /// Program the timer LVT, masked if there is no vector
+    unsafe fn set_timer(&mut self, vector: Option<u8>, mode: TimerMode) {
+        let mut lvt = LvtTimer(0);
+        lvt.set_vector(vector.unwrap_or(0) as u32);
+        lvt.set_masked(vector.is_none());
+        lvt.set_mode(mode as u32);
+        self.write(LVT_TIMER, lvt.0);
+    }
+    /// Start the timer counting down from `count` repeatedly, interrupting on `vector` each time
+    pub unsafe fn timer_periodic(&mut self, vector: u8, divide: Divide, count: u32) {
+        self.write(TIMER_DIVIDE, divide.bits());
+        self.set_timer(Some(vector), TimerMode::Periodic);
+        self.write(TIMER_INITIAL, count);
+    }
+    /// Start the timer counting down from `count` once, interrupting on `vector` at zero
+    ///
+    /// With no vector the interrupt is masked, which is how the timer frequency is measured by
+    /// reading `timer_current`
+    pub unsafe fn timer_one_shot(&mut self, vector: Option<u8>, divide: Divide, count: u32) {
+        self.write(TIMER_DIVIDE, divide.bits());
+        self.set_timer(vector, TimerMode::OneShot);
+        self.write(TIMER_INITIAL, count);
+    }
+    /// Put the timer in TSC deadline mode, interrupting on `vector`
+    ///
+    /// Nothing fires until a deadline is set with `set_tsc_deadline`. The caller is responsible
+    /// for checking the processor supports this mode.
+    pub unsafe fn timer_tsc_deadline(&mut self, vector: u8) {
+        self.set_timer(Some(vector), TimerMode::TscDeadline);
+    }
+    /// Arm the TSC deadline, with zero disarming it
+    pub unsafe fn set_tsc_deadline(&mut self, tsc: u64) {
+        wrmsr(IA32_TSC_DEADLINE, tsc)
+    }
+    /// Stop the timer and mask its interrupt
+    pub unsafe fn timer_stop(&mut self) {
+        self.write(LVT_TIMER, LVT_MASKED);
+        self.write(TIMER_INITIAL, 0);
+    }
+    pub unsafe fn timer_current(&mut self) -> u32 {
+        self.read(TIMER_CURRENT)
+    }
+    pub unsafe fn timer_lvt(&mut self) -> LvtTimer {
+        LvtTimer(self.read(LVT_TIMER))
+    }
+    unsafe fn wait_icr_idle(&mut self) -> Result<(), Error> {
+        // Delivery status does not exist in x2APIC mode and always reads as idle
+        for _ in 0..POLL_LIMIT {
+            if !IcrLow(self.read(ICR_LOW)).pending() {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+    /// Send an inter-processor interrupt
+    ///
+    /// `vector` is ignored by the NMI and INIT delivery modes, and is the start page of the
+    /// startup code for a startup IPI.
+    pub unsafe fn send_ipi(&mut self, dest: Destination, mode: DeliveryMode, vector: u8) -> Result<(), Error> {
+        let high = match dest {
+            Destination::Apic(id) if self.x2apic => id,
+            Destination::Apic(id) if id < 0xFF => id << 24,
+            Destination::Apic(id) => return Err(Error::InvalidDestination(id)),
+            _ => 0,
+        };
+        let mut low = IcrLow(0);
+        low.set_vector(vector as u32);
+        low.set_delivery_mode(mode as u32);
+        low.set_assert(true);
+        low.set_shorthand(dest.shorthand());
+        self.wait_icr_idle()?;
+        self.write(ICR_HIGH, high);
+        self.write(ICR_LOW, low.0);
+        self.wait_icr_idle()
+    }
+    /// Construct a driver for the local APIC
+    ///
+    /// `x2apic` must reflect the mode the APIC is in, and so how `io` is accessing it
+    pub fn new(io: T, x2apic: bool) -> LocalApic<T> {
+        LocalApic { io: io, x2apic: x2apic }
+    }
+}
@@ -0,0 +1,225 @@
+//! Bochs VBE display interface
+//!
+//! The extension to VGA that Bochs and QEMU provide for linear framebuffer modes. Registers are
+//! 16 bits and selected by index, either by writing the index to one port and accessing the
+//! value at the next, or, on the `bochs-display` and newer standard VGA devices, as an array in
+//! an MMIO BAR. There is no list of modes, instead any resolution up to a maximum can be set,
+//! provided it fits in video memory.
+//!
+//! Once a mode is enabled the VGA text console no longer shows, until the interface is disabled
+//! again.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+use drivers::framebuffer::Format;
+use drivers::io::MixedIo;
+
+/// Index port, with the data port after it
+pub const PORT_BASE: u16 = 0x1CE;
+const PORT_INDEX: usize = 0;
+const PORT_DATA: usize = 1;
+/// Offset of the registers within the MMIO BAR
+pub const MMIO_OFFSET: usize = 0x500;
+
+pub const INDEX_ID: u16 = 0x0;
+pub const INDEX_XRES: u16 = 0x1;
+pub const INDEX_YRES: u16 = 0x2;
+pub const INDEX_BPP: u16 = 0x3;
+pub const INDEX_ENABLE: u16 = 0x4;
+pub const INDEX_BANK: u16 = 0x5;
+pub const INDEX_VIRT_WIDTH: u16 = 0x6;
+pub const INDEX_VIRT_HEIGHT: u16 = 0x7;
+pub const INDEX_X_OFFSET: u16 = 0x8;
+pub const INDEX_Y_OFFSET: u16 = 0x9;
+/// Video memory in units of 64KiB
+pub const INDEX_VIDEO_MEMORY_64K: u16 = 0xA;
+
+/// Range of interface versions, of which any is enough for a linear framebuffer
+pub const ID_MIN: u16 = 0xB0C2;
+pub const ID_MAX: u16 = 0xB0C5;
+
+pub const ENABLE_ENABLED: u16 = 1 << 0;
+/// Make the resolution and depth registers read as their maximums
+pub const ENABLE_GETCAPS: u16 = 1 << 1;
+pub const ENABLE_LFB: u16 = 1 << 6;
+pub const ENABLE_NOCLEARMEM: u16 = 1 << 7;
+
+/// Resolutions offered by `modes`, as any that fit could be set
+const RESOLUTIONS: [(u16, u16); 11] = [
+    (640, 480), (800, 600), (1024, 768), (1280, 720), (1280, 1024),
+    (1600, 900), (1600, 1200), (1920, 1080), (1920, 1200), (2560, 1440), (2560, 1600),
+];
+const DEPTHS: [u8; 5] = [8, 15, 16, 24, 32];
+
+/// How the registers are reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Through `PORT_BASE`, with an index port and a data port
+    Ports,
+    /// In an MMIO window starting at `MMIO_OFFSET` of the BAR, with a register every 2 bytes
+    Mmio,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Mode is beyond what the device can do, or does not fit in its memory
+    Unsupported,
+    /// Device did not take the mode
+    Rejected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mode {
+    pub width: u16,
+    pub height: u16,
+    pub bpp: u8,
+}
+
+impl Mode {
+    pub fn new(width: u16, height: u16, bpp: u8) -> Mode {
+        Mode { width: width, height: height, bpp: bpp }
+    }
+    pub fn format(&self) -> Option<Format> {
+        Format::from_bpp(self.bpp)
+    }
+    /// Bytes per row, which with the virtual width set to the width has no padding
+    pub fn stride(&self) -> usize {
+        self.width as usize * self.format().map_or(0, |format| format.bytes_per_pixel())
+    }
+    pub fn size(&self) -> usize {
+        self.stride() * self.height as usize
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}x{}", self.width, self.height, self.bpp)
+    }
+}
+
+/// Parses `WIDTHxHEIGHT` or `WIDTHxHEIGHTxBPP`, with a depth of 32 when it is left out
+impl FromStr for Mode {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Mode, ()> {
+        let mut parts = s.split('x');
+        let width = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        let height = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        let bpp = match parts.next() {
+            Some(bpp) => bpp.parse().map_err(|_| ())?,
+            None => 32,
+        };
+        if parts.next().is_some() || Format::from_bpp(bpp).is_none() || width == 0 || height == 0 {
+            return Err(());
+        }
+        Ok(Mode::new(width, height, bpp))
+    }
+}
+
+pub struct Dispi<T> {
+    io: T,
+    access: Access,
+    id: u16,
+    /// Largest mode in each dimension, which need not all be possible at once
+    max: Mode,
+    /// Bytes of video memory
+    memory: usize,
+}
+
+impl<T: MixedIo> Dispi<T> {
+    /// Find the interface through `io` and read its capabilities, returning none if it is not there
+    ///
+    /// `memory` is the size of the framebuffer BAR, which is used when the device does not say
+    /// how much memory it has.
+    pub unsafe fn new(io: T, access: Access, memory: usize) -> Option<Dispi<T>> {
+        let mut dispi = Dispi { io: io, access: access, id: 0, max: Mode::new(0, 0, 0), memory: memory };
+        dispi.id = dispi.read(INDEX_ID);
+        if dispi.id < ID_MIN || dispi.id > ID_MAX {
+            return None;
+        }
+        let enable = dispi.read(INDEX_ENABLE);
+        dispi.write(INDEX_ENABLE, enable | ENABLE_GETCAPS);
+        dispi.max = Mode::new(dispi.read(INDEX_XRES), dispi.read(INDEX_YRES), dispi.read(INDEX_BPP) as u8);
+        dispi.write(INDEX_ENABLE, enable);
+        let reported = dispi.read(INDEX_VIDEO_MEMORY_64K) as usize * 64 * 1024;
+        if reported != 0 {
+            dispi.memory = dispi.memory.min(reported);
+        }
+        Some(dispi)
+    }
+    unsafe fn read(&mut self, index: u16) -> u16 {
+        match self.access {
+            Access::Ports => {
+                self.io.write16(PORT_INDEX, index);
+                self.io.read16(PORT_DATA)
+            },
+            Access::Mmio => self.io.read16(index as usize * 2),
+        }
+    }
+    unsafe fn write(&mut self, index: u16, value: u16) {
+        match self.access {
+            Access::Ports => {
+                self.io.write16(PORT_INDEX, index);
+                self.io.write16(PORT_DATA, value)
+            },
+            Access::Mmio => self.io.write16(index as usize * 2, value),
+        }
+    }
+    /// Interface version, from 0 to 5
+    pub fn version(&self) -> u16 {
+        self.id - 0xB0C0
+    }
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+    pub fn max(&self) -> Mode {
+        self.max
+    }
+    /// Whether a mode can be set, which needs its width to be a multiple of 8
+    pub fn supports(&self, mode: Mode) -> bool {
+        mode.format().is_some() && mode.width != 0 && mode.width % 8 == 0 && mode.height != 0 && mode.width <= self.max.width
+            && mode.height <= self.max.height && mode.bpp <= self.max.bpp && mode.size() <= self.memory
+    }
+    /// Common modes that the device supports, smallest first
+    pub fn modes(&self) -> Vec<Mode> {
+        RESOLUTIONS.iter()
+            .flat_map(|&(width, height)| DEPTHS.iter().map(move |&bpp| Mode::new(width, height, bpp)))
+            .filter(|&mode| self.supports(mode))
+            .collect()
+    }
+    /// The mode that is enabled, or none if the display is in VGA mode
+    pub unsafe fn current(&mut self) -> Option<Mode> {
+        if self.read(INDEX_ENABLE) & ENABLE_ENABLED == 0 {
+            return None;
+        }
+        Some(Mode::new(self.read(INDEX_XRES), self.read(INDEX_YRES), self.read(INDEX_BPP) as u8))
+    }
+    /// Enable a mode with a linear framebuffer, starting at the beginning of video memory
+    ///
+    /// The device clears the framebuffer as part of changing mode.
+    pub unsafe fn set_mode(&mut self, mode: Mode) -> Result<(), Error> {
+        if !self.supports(mode) {
+            return Err(Error::Unsupported);
+        }
+        // Resolution and depth can only be changed while disabled
+        self.write(INDEX_ENABLE, 0);
+        self.write(INDEX_BPP, mode.bpp as u16);
+        self.write(INDEX_XRES, mode.width);
+        self.write(INDEX_YRES, mode.height);
+        self.write(INDEX_BANK, 0);
+        self.write(INDEX_VIRT_WIDTH, mode.width);
+        self.write(INDEX_VIRT_HEIGHT, mode.height);
+        self.write(INDEX_X_OFFSET, 0);
+        self.write(INDEX_Y_OFFSET, 0);
+        self.write(INDEX_ENABLE, ENABLE_ENABLED | ENABLE_LFB);
+        if self.current() != Some(mode) || self.read(INDEX_VIRT_WIDTH) != mode.width {
+            self.disable();
+            return Err(Error::Rejected);
+        }
+        Ok(())
+    }
+    /// Return to VGA, where the text console shows again
+    pub unsafe fn disable(&mut self) {
+        self.write(INDEX_ENABLE, 0);
+    }
+}
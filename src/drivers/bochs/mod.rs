@@ -0,0 +1,141 @@
+//! Bochs and QEMU display adapters
+//!
+//! Both QEMU's standard VGA and its `bochs-display` are found as PCI functions, with the
+//! framebuffer in BAR 0 and, when there is one, the display interface registers in BAR 2. The
+//! standard VGA of older versions only has the registers at their legacy ports.
+//!
+//! The display is left in VGA text mode unless a mode is asked for with `--video`, such as
+//! `--video=1024x768x32`. Once a mode is set its framebuffer becomes the primary one, mapped
+//! write combining so that drawing is not slowed by every write going out on its own.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use bus::{self, Device};
+use cpu::MemoryType;
+use decls::Match;
+use drivers::framebuffer::{self, Framebuffer};
+use drivers::io::{MemIO, MixedIo, PortIO};
+use drivers::pci::{Address, Command};
+
+mod dispi;
+
+pub use self::dispi::{Access, Dispi, Error, Mode};
+
+const VENDOR_BOCHS: u16 = 0x1234;
+const DEVICE_DISPLAY: u16 = 0x1111;
+const FRAMEBUFFER_BAR: u8 = 0;
+const MMIO_BAR: u8 = 2;
+const SUBCLASS_VGA: u8 = 0x00;
+
+struct Display {
+    address: Address,
+    dispi: Dispi<Box<MixedIo>>,
+    /// Virtual and physical address of the framebuffer BAR
+    vaddr: usize,
+    paddr: u64,
+}
+
+static mut DISPLAY: Option<Display> = None;
+/// Mode from --video
+static mut MODE: Option<Mode> = None;
+
+fn probe(device: &Device) -> bool {
+    let function = match *device {
+        Device::Pci(function) => function,
+        _ => return false,
+    };
+    if unsafe {DISPLAY.is_some()} {
+        print!(Error, "Bochs display {} ignored, as only one display is supported", function.address);
+        return false;
+    }
+    let (paddr, size) = match function.bars[FRAMEBUFFER_BAR as usize].and_then(|bar| bar.memory()) {
+        Some(bar) => bar,
+        None => return false,
+    };
+    let (io, access): (Box<MixedIo>, Access) = match bus::pci::map_bar(function, MMIO_BAR) {
+        Some(mmio) => (box unsafe {MemIO::<u8>::new(mmio + dispi::MMIO_OFFSET)}, Access::Mmio),
+        None if function.subclass == SUBCLASS_VGA => (box PortIO::<u8>::new(dispi::PORT_BASE), Access::Ports),
+        None => {
+            print!(Error, "Bochs display {} has no registers", function.address);
+            return false;
+        },
+    };
+    unsafe {bus::pci::config(function).enable(Command::IO | Command::MEMORY)};
+    let dispi = match unsafe {Dispi::new(io, access, size as usize)} {
+        Some(dispi) => dispi,
+        None => {
+            print!(Error, "Bochs display {} has no display interface", function.address);
+            return false;
+        },
+    };
+    let vaddr = match bus::pci::map_bar_as(function, FRAMEBUFFER_BAR, MemoryType::WC) {
+        Some(vaddr) => vaddr,
+        None => {
+            print!(Error, "Failed to map the framebuffer of Bochs display {}", function.address);
+            return false;
+        },
+    };
+    print!(Info, "Bochs display {} version {} through {:?}, {} KiB, up to {}", function.address, dispi.version(), access,
+        dispi.memory() / 1024, dispi.max());
+    for mode in dispi.modes() {
+        print!(Debug, "    {}", mode);
+    }
+    unsafe {DISPLAY = Some(Display { address: function.address, dispi: dispi, vaddr: vaddr, paddr: paddr })};
+    if let Some(mode) = unsafe {MODE} {
+        if let Err(error) = set_mode(mode) {
+            print!(Error, "Failed to set video mode {}: {:?}", mode, error);
+        }
+    }
+    true
+}
+
+fn remove(device: &Device) {
+    if let Device::Pci(function) = *device {
+        if unsafe {DISPLAY.as_ref()}.map_or(false, |display| display.address == function.address) {
+            let _ = disable();
+            unsafe {DISPLAY = None};
+        }
+    }
+}
+
+make_driver_decl!("bochs_display", &[Match::pci(VENDOR_BOCHS, DEVICE_DISPLAY)], probe, remove, BOCHS_DISPLAY_DRIVER);
+
+/// Modes the display supports, or none if there is no display
+pub fn modes() -> Option<Vec<Mode>> {
+    unsafe {DISPLAY.as_ref()}.map(|display| display.dispi.modes())
+}
+
+/// Set a mode, making its framebuffer the primary one
+///
+/// Returns `Unsupported` if there is no display.
+pub fn set_mode(mode: Mode) -> Result<&'static mut Framebuffer, Error> {
+    let display = unsafe {DISPLAY.as_mut()}.ok_or(Error::Unsupported)?;
+    // A mode that could not be drawn to is refused before the hardware is touched
+    let format = mode.format().ok_or(Error::Unsupported)?;
+    // Nothing may draw to the framebuffer while the mode changes under it
+    framebuffer::set_primary(None);
+    unsafe {display.dispi.set_mode(mode)}?;
+    let framebuffer = unsafe {Framebuffer::new(display.vaddr as *mut u8, display.paddr, mode.width as usize,
+        mode.height as usize, mode.stride(), format)};
+    print!(Info, "Video mode {}", framebuffer);
+    framebuffer::set_primary(Some(framebuffer));
+    Ok(framebuffer::primary().unwrap())
+}
+
+/// Return the display to VGA text mode, dropping the primary framebuffer
+pub fn disable() -> Result<(), Error> {
+    let display = unsafe {DISPLAY.as_mut()}.ok_or(Error::Unsupported)?;
+    framebuffer::set_primary(None);
+    unsafe {display.dispi.disable()};
+    Ok(())
+}
+
+fn set_video(mode: &str) {
+    match mode.parse() {
+        Ok(mode) => unsafe {MODE = Some(mode)},
+        Err(()) => print!(Error, "Invalid video mode {}", mode),
+    }
+}
+
+/// Sets a mode once the display is found, for example --video=1024x768x32
+make_cmdline_decl!("video", set_video, VIDEO);
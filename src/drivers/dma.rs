@@ -0,0 +1,64 @@
+//! Memory shared with devices
+//!
+//! Devices see physical addresses, so memory handed to them must be physically contiguous and
+//! have a known physical address. Heap allocations are made from the kernel window, where a
+//! contiguous virtual range is also physically contiguous, so are suitable once translated.
+//! Memory is assumed to be coherent with devices, as it is on x86.
+
+use alloc::alloc::{alloc_zeroed, dealloc};
+use core::alloc::Layout;
+use core::slice;
+use vspace::Translation;
+
+/// A zeroed, physically contiguous, allocation
+pub struct Dma {
+    vaddr: *mut u8,
+    paddr: u64,
+    layout: Layout,
+}
+
+impl Dma {
+    /// Allocate `size` bytes aligned to `align`, which must be a power of 2
+    ///
+    /// The memory comes from the heap, so `translation` must be one that describes it, such as
+    /// the kernel address space. Fails if `size` is 0, or the memory could not be allocated or is
+    /// not described by `translation`
+    pub fn new(translation: &Translation, size: usize, align: usize) -> Option<Dma> {
+        if size == 0 {
+            return None;
+        }
+        let layout = Layout::from_size_align(size, align).ok()?;
+        let vaddr = unsafe {alloc_zeroed(layout)};
+        if vaddr.is_null() {
+            return None;
+        }
+        match translation.vaddr_to_paddr_range(vaddr as usize..vaddr as usize + size) {
+            Some(paddr) => Some(Dma { vaddr: vaddr, paddr: paddr.start as u64, layout: layout }),
+            None => {
+                unsafe {dealloc(vaddr, layout)};
+                None
+            },
+        }
+    }
+    pub fn vaddr(&self) -> *mut u8 {
+        self.vaddr
+    }
+    pub fn paddr(&self) -> u64 {
+        self.paddr
+    }
+    pub fn len(&self) -> usize {
+        self.layout.size()
+    }
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe {slice::from_raw_parts(self.vaddr, self.len())}
+    }
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe {slice::from_raw_parts_mut(self.vaddr, self.len())}
+    }
+}
+
+impl Drop for Dma {
+    fn drop(&mut self) {
+        unsafe {dealloc(self.vaddr, self.layout)}
+    }
+}
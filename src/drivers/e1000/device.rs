@@ -0,0 +1,384 @@
+//! Intel 8254x and 82574 gigabit Ethernet controllers
+//!
+//! Covers the 82540EM that QEMU provides as `e1000`, the similar 8254x parts, and the 82574L
+//! that it provides as `e1000e`. All of them accept the legacy descriptor formats used here,
+//! and differ only in how the EEPROM is read. Each descriptor of the receive and transmit rings
+//! has a buffer of its own that frames are copied through.
+//!
+//! Both rings are polled. Interrupts start masked, and once enabled only tell a handler that
+//! there is something to poll for, with `interrupt` acknowledging them.
+
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+use drivers::dma::Dma;
+use drivers::io::{wait, Io};
+use net::{device, MacAddress, NetDevice};
+use vspace::Translation;
+
+pub const CTRL: u16 = 0x0000;
+pub const STATUS: u16 = 0x0008;
+pub const EERD: u16 = 0x0014;
+pub const ICR: u16 = 0x00C0;
+pub const IMS: u16 = 0x00D0;
+pub const IMC: u16 = 0x00D8;
+pub const RCTL: u16 = 0x0100;
+pub const TCTL: u16 = 0x0400;
+pub const TIPG: u16 = 0x0410;
+pub const RDBAL: u16 = 0x2800;
+pub const RDBAH: u16 = 0x2804;
+pub const RDLEN: u16 = 0x2808;
+pub const RDH: u16 = 0x2810;
+pub const RDT: u16 = 0x2818;
+pub const TDBAL: u16 = 0x3800;
+pub const TDBAH: u16 = 0x3804;
+pub const TDLEN: u16 = 0x3808;
+pub const TDH: u16 = 0x3810;
+pub const TDT: u16 = 0x3818;
+/// Multicast table array, of `MTA_ENTRIES` dwords
+pub const MTA: u16 = 0x5200;
+pub const RAL0: u16 = 0x5400;
+pub const RAH0: u16 = 0x5404;
+
+const MTA_ENTRIES: u16 = 128;
+
+/// Link reset
+pub const CTRL_LRST: u32 = 1 << 3;
+/// Auto-speed detection
+pub const CTRL_ASDE: u32 = 1 << 5;
+/// Set link up
+pub const CTRL_SLU: u32 = 1 << 6;
+pub const CTRL_RST: u32 = 1 << 26;
+pub const CTRL_PHY_RST: u32 = 1 << 31;
+
+pub const STATUS_LU: u32 = 1 << 1;
+
+/// Transmit descriptor written back
+pub const ICR_TXDW: u32 = 1 << 0;
+/// Link status change
+pub const ICR_LSC: u32 = 1 << 2;
+/// Receive descriptors running low
+pub const ICR_RXDMT0: u32 = 1 << 4;
+/// Receiver overrun, frames were dropped for want of descriptors
+pub const ICR_RXO: u32 = 1 << 6;
+/// Receive timer, a frame has arrived
+pub const ICR_RXT0: u32 = 1 << 7;
+
+pub const RCTL_EN: u32 = 1 << 1;
+/// Unicast promiscuous
+pub const RCTL_UPE: u32 = 1 << 3;
+/// Multicast promiscuous
+pub const RCTL_MPE: u32 = 1 << 4;
+/// Accept broadcasts
+pub const RCTL_BAM: u32 = 1 << 15;
+/// Strip the FCS from received frames
+pub const RCTL_SECRC: u32 = 1 << 26;
+
+pub const TCTL_EN: u32 = 1 << 1;
+/// Pad short frames
+pub const TCTL_PSP: u32 = 1 << 3;
+/// Collision threshold and distance, as recommended for full duplex
+const TCTL_COLLISIONS: u32 = 0x0F << 4 | 0x40 << 12;
+/// Inter packet gap, as recommended for copper
+const TIPG_COPPER: u32 = 10 | 8 << 10 | 6 << 20;
+
+/// Receive address valid, in `RAH0`
+pub const RAH_AV: u32 = 1 << 31;
+
+pub const DESC_SIZE: usize = 16;
+/// Descriptor done, in both receive and transmit status
+pub const DESC_DD: u8 = 1 << 0;
+/// End of packet, in receive status
+pub const RX_EOP: u8 = 1 << 1;
+/// Transmit command bits: end of packet, insert FCS and report status
+pub const TX_EOP: u8 = 1 << 0;
+pub const TX_IFCS: u8 = 1 << 1;
+pub const TX_RS: u8 = 1 << 3;
+
+/// Ring lengths must be a multiple of 128 bytes, so of 8 descriptors
+pub const RX_DESCRIPTORS: usize = 32;
+pub const TX_DESCRIPTORS: usize = 16;
+/// Receive buffer size selected by `RCTL`, which is also used for transmit buffers
+pub const BUFFER_SIZE: usize = 2048;
+/// Largest frame, without the FCS
+const MAX_FRAME: usize = 1514;
+
+/// Polls of a register before the device is given up on
+pub const POLL_LIMIT: usize = 100000;
+
+/// Which EEPROM interface a device has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    /// 8254x, with the EERD address at bit 8 and done at bit 4
+    I8254x,
+    /// 82574, with the EERD address at bit 2 and done at bit 1
+    I82574,
+}
+
+impl Model {
+    fn eerd(&self, word: u8) -> (u32, u32) {
+        match *self {
+            Model::I8254x => (1 | (word as u32) << 8, 1 << 4),
+            Model::I82574 => (1 | (word as u32) << 2, 1 << 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Device did not come out of reset
+    ResetTimeout,
+    /// Neither the EEPROM nor the receive address registers hold a MAC address
+    NoMac,
+    /// Memory for the rings could not be allocated
+    NoMemory,
+}
+
+pub struct E1000<T> where T: Io<Item = u32>, T::Range: From<u16> {
+    io: T,
+    model: Model,
+    mac: MacAddress,
+    rx_ring: Dma,
+    rx_buffers: Dma,
+    /// Next receive descriptor the device will fill
+    rx_next: usize,
+    tx_ring: Dma,
+    tx_buffers: Dma,
+    /// Next transmit descriptor to fill, which is the tail given to the device
+    tx_tail: usize,
+    /// Oldest transmit descriptor not yet reclaimed
+    tx_clean: usize,
+}
+
+impl<T, R> E1000<T> where T: Io<Item = u32, Range=R>, R: From<u16> {
+    /// Reset the device whose registers are `io`, read its MAC address, and start both rings
+    ///
+    /// `translation` is passed to `Dma::new`.
+    pub unsafe fn new(io: T, model: Model, translation: &Translation) -> Result<E1000<T>, Error> {
+        let alloc = |size, align| Dma::new(translation, size, align).ok_or(Error::NoMemory);
+        let mut device = E1000 {
+            io: io,
+            model: model,
+            mac: MacAddress::default(),
+            rx_ring: alloc(RX_DESCRIPTORS * DESC_SIZE, 128)?,
+            rx_buffers: alloc(RX_DESCRIPTORS * BUFFER_SIZE, 16)?,
+            rx_next: 0,
+            tx_ring: alloc(TX_DESCRIPTORS * DESC_SIZE, 128)?,
+            tx_buffers: alloc(TX_DESCRIPTORS * BUFFER_SIZE, 16)?,
+            tx_tail: 0,
+            tx_clean: 0,
+        };
+        device.reset()?;
+        device.mac = device.read_mac().ok_or(Error::NoMac)?;
+        let mac = device.mac.0;
+        device.write(RAL0, mac[0] as u32 | (mac[1] as u32) << 8 | (mac[2] as u32) << 16 | (mac[3] as u32) << 24);
+        device.write(RAH0, mac[4] as u32 | (mac[5] as u32) << 8 | RAH_AV);
+        for entry in 0..MTA_ENTRIES {
+            device.write(MTA + entry * 4, 0);
+        }
+        let ctrl = device.read(CTRL);
+        device.write(CTRL, (ctrl | CTRL_SLU | CTRL_ASDE) & !(CTRL_LRST | CTRL_PHY_RST));
+        device.setup_rx();
+        device.setup_tx();
+        Ok(device)
+    }
+    unsafe fn read(&mut self, reg: u16) -> u32 {
+        self.io.read(R::from(reg))
+    }
+    unsafe fn write(&mut self, reg: u16, value: u32) {
+        self.io.write(R::from(reg), value)
+    }
+    /// Reset the whole device, leaving interrupts masked and both rings stopped
+    unsafe fn reset(&mut self) -> Result<(), Error> {
+        self.write(IMC, 0xFFFF_FFFF);
+        let ctrl = self.read(CTRL);
+        self.write(CTRL, ctrl | CTRL_RST);
+        if !wait(POLL_LIMIT, || self.read(CTRL), |ctrl| ctrl & CTRL_RST == 0) {
+            return Err(Error::ResetTimeout);
+        }
+        // Interrupts are masked again in case the reset unmasked them, and stale causes cleared
+        self.write(IMC, 0xFFFF_FFFF);
+        self.read(ICR);
+        Ok(())
+    }
+    /// Read a 16-bit word of the EEPROM, if there is one
+    pub unsafe fn read_eeprom(&mut self, word: u8) -> Option<u16> {
+        let (start, done) = self.model.eerd(word);
+        self.write(EERD, start);
+        for _ in 0..POLL_LIMIT {
+            let eerd = self.read(EERD);
+            if eerd & done != 0 {
+                return Some((eerd >> 16) as u16);
+            }
+        }
+        None
+    }
+    /// The address in the first 3 words of the EEPROM, or the one firmware left in the receive
+    /// address registers
+    unsafe fn read_mac(&mut self) -> Option<MacAddress> {
+        let mut mac = [0; 6];
+        let mut from_eeprom = true;
+        for word in 0..3 {
+            match self.read_eeprom(word as u8) {
+                Some(value) => {
+                    mac[word * 2] = value as u8;
+                    mac[word * 2 + 1] = (value >> 8) as u8;
+                },
+                None => {
+                    from_eeprom = false;
+                    break;
+                },
+            }
+        }
+        if from_eeprom {
+            return Some(MacAddress(mac));
+        }
+        let (low, high) = (self.read(RAL0), self.read(RAH0));
+        if high & RAH_AV == 0 {
+            return None;
+        }
+        for i in 0..4 {
+            mac[i] = (low >> (i * 8)) as u8;
+        }
+        mac[4] = high as u8;
+        mac[5] = (high >> 8) as u8;
+        Some(MacAddress(mac))
+    }
+    unsafe fn setup_rx(&mut self) {
+        for index in 0..RX_DESCRIPTORS {
+            let buffer = self.rx_buffers.paddr() + (index * BUFFER_SIZE) as u64;
+            let desc = self.rx_desc(index);
+            ptr::write_volatile(desc as *mut u64, buffer);
+            ptr::write_volatile(desc.offset(8) as *mut u64, 0);
+        }
+        let ring = self.rx_ring.paddr();
+        self.write(RDBAL, ring as u32);
+        self.write(RDBAH, (ring >> 32) as u32);
+        self.write(RDLEN, (RX_DESCRIPTORS * DESC_SIZE) as u32);
+        self.write(RDH, 0);
+        // Every descriptor but the one the tail points at belongs to the device
+        self.write(RDT, RX_DESCRIPTORS as u32 - 1);
+        self.write(RCTL, RCTL_EN | RCTL_BAM | RCTL_SECRC);
+    }
+    unsafe fn setup_tx(&mut self) {
+        let ring = self.tx_ring.paddr();
+        self.write(TDBAL, ring as u32);
+        self.write(TDBAH, (ring >> 32) as u32);
+        self.write(TDLEN, (TX_DESCRIPTORS * DESC_SIZE) as u32);
+        self.write(TDH, 0);
+        self.write(TDT, 0);
+        self.write(TIPG, TIPG_COPPER);
+        self.write(TCTL, TCTL_EN | TCTL_PSP | TCTL_COLLISIONS);
+    }
+    fn rx_desc(&self, index: usize) -> *mut u8 {
+        unsafe {self.rx_ring.vaddr().offset((index * DESC_SIZE) as isize)}
+    }
+    fn tx_desc(&self, index: usize) -> *mut u8 {
+        unsafe {self.tx_ring.vaddr().offset((index * DESC_SIZE) as isize)}
+    }
+    /// Unmask the interrupts for received frames, link changes and completed transmits
+    pub unsafe fn enable_interrupts(&mut self) {
+        self.write(IMS, ICR_RXT0 | ICR_RXO | ICR_RXDMT0 | ICR_LSC | ICR_TXDW);
+    }
+    pub unsafe fn disable_interrupts(&mut self) {
+        self.write(IMC, 0xFFFF_FFFF);
+    }
+    /// Acknowledge an interrupt, returning its causes
+    ///
+    /// Reading the causes clears them, and with them the interrupt. Frames are left for
+    /// `receive`, and reclaiming transmit descriptors for the next `transmit`.
+    pub unsafe fn interrupt(&mut self) -> u32 {
+        self.read(ICR)
+    }
+    /// Reclaim the transmit descriptors the device has finished with
+    unsafe fn complete_tx(&mut self) {
+        while self.tx_clean != self.tx_tail {
+            let status = ptr::read_volatile(self.tx_desc(self.tx_clean).offset(12));
+            if status & DESC_DD == 0 {
+                break;
+            }
+            self.tx_clean = (self.tx_clean + 1) % TX_DESCRIPTORS;
+        }
+    }
+}
+
+impl<T, R> NetDevice for E1000<T> where T: Io<Item = u32, Range=R>, R: From<u16> {
+    fn name(&self) -> &'static str {
+        match self.model {
+            Model::I8254x => "e1000",
+            Model::I82574 => "e1000e",
+        }
+    }
+    fn mac(&self) -> MacAddress {
+        self.mac
+    }
+    fn mtu(&self) -> usize {
+        1500
+    }
+    unsafe fn link_up(&mut self) -> bool {
+        self.read(STATUS) & STATUS_LU != 0
+    }
+    unsafe fn transmit(&mut self, frame: &[u8]) -> Result<(), device::Error> {
+        if frame.len() > MAX_FRAME {
+            return Err(device::Error::TooLarge);
+        }
+        self.complete_tx();
+        let index = self.tx_tail;
+        let next = (index + 1) % TX_DESCRIPTORS;
+        if next == self.tx_clean {
+            return Err(device::Error::Busy);
+        }
+        let offset = index * BUFFER_SIZE;
+        self.tx_buffers.as_mut_slice()[offset..offset + frame.len()].copy_from_slice(frame);
+        let buffer = self.tx_buffers.paddr() + offset as u64;
+        let desc = self.tx_desc(index);
+        ptr::write_volatile(desc as *mut u64, buffer);
+        ptr::write_volatile(desc.offset(8) as *mut u16, frame.len() as u16);
+        ptr::write_volatile(desc.offset(10), 0);
+        ptr::write_volatile(desc.offset(11), TX_EOP | TX_IFCS | TX_RS);
+        ptr::write_volatile(desc.offset(12) as *mut u32, 0);
+        fence(Ordering::SeqCst);
+        self.tx_tail = next;
+        self.write(TDT, next as u32);
+        Ok(())
+    }
+    unsafe fn receive(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let index = self.rx_next;
+            let desc = self.rx_desc(index);
+            let status = ptr::read_volatile(desc.offset(12));
+            if status & DESC_DD == 0 {
+                return None;
+            }
+            fence(Ordering::SeqCst);
+            let len = ptr::read_volatile(desc.offset(8) as *const u16) as usize;
+            let errors = ptr::read_volatile(desc.offset(13));
+            // Frames that span buffers are too large for the stack anyway, so are dropped
+            let frame = if status & RX_EOP != 0 && errors == 0 && len <= MAX_FRAME {
+                let offset = index * BUFFER_SIZE;
+                Some(self.rx_buffers.as_slice()[offset..offset + len].to_vec())
+            } else {
+                None
+            };
+            ptr::write_volatile(desc.offset(8) as *mut u64, 0);
+            fence(Ordering::SeqCst);
+            self.rx_next = (index + 1) % RX_DESCRIPTORS;
+            self.write(RDT, index as u32);
+            if frame.is_some() {
+                return frame;
+            }
+        }
+    }
+    unsafe fn set_promiscuous(&mut self, enable: bool) -> bool {
+        let rctl = self.read(RCTL);
+        self.write(RCTL, if enable { rctl | RCTL_UPE | RCTL_MPE } else { rctl & !(RCTL_UPE | RCTL_MPE) });
+        true
+    }
+}
+
+impl<T> Drop for E1000<T> where T: Io<Item = u32>, T::Range: From<u16> {
+    fn drop(&mut self) {
+        // Stop the device before the memory it has been given is freed
+        let _ = unsafe {self.reset()};
+    }
+}
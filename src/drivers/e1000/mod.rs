@@ -0,0 +1,93 @@
+//! Intel e1000 and e1000e network devices
+//!
+//! Each device found is registered as a network interface. Like virtio-net the device is
+//! polled, and its INTx interrupt, where the firmware routed one, is only acknowledged so that
+//! the level triggered line drops again.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use bus::{self, Device};
+use decls::Match;
+use drivers::io::MemIO;
+use drivers::pci::{Address, Command};
+use irq;
+use state::STATE;
+
+mod device;
+
+pub use self::device::{E1000, Error, Model};
+
+const VENDOR_INTEL: u16 = 0x8086;
+/// Devices known to work, with the EEPROM interface each has
+const DEVICES: &[(u16, Model)] = &[
+    // 82540EM, QEMU's e1000
+    (0x100E, Model::I8254x),
+    // 82545EM
+    (0x100F, Model::I8254x),
+    // 82574L, QEMU's e1000e
+    (0x10D3, Model::I82574),
+];
+/// Registers are in the first memory BAR
+const BAR: u8 = 0;
+
+/// Interfaces registered, by the function they are on, with the device that the interface owns
+///
+/// The device is only touched through the pointer from the interrupt handler, which never runs
+/// while the network stack is using it.
+static mut INTERFACES: Option<Vec<(Address, String, *mut E1000<MemIO<u32>>)>> = None;
+
+/// Handler of the INTx line, which every device shares
+fn interrupt() {
+    for &(_, _, device) in unsafe {INTERFACES.get_or_insert_with(Vec::new)}.iter() {
+        unsafe {(*device).interrupt()};
+    }
+}
+
+fn probe(device: &Device) -> bool {
+    let function = match *device {
+        Device::Pci(function) => function,
+        _ => return false,
+    };
+    let model = match DEVICES.iter().find(|known| known.0 == function.device) {
+        Some(&(_, model)) => model,
+        None => return false,
+    };
+    let registers = match bus::pci::map_bar(function, BAR) {
+        Some(registers) => registers,
+        None => {
+            print!(Error, "e1000 {} has no register BAR", function.address);
+            return false;
+        },
+    };
+    unsafe {bus::pci::config(function).enable(Command::MEMORY | Command::BUS_MASTER)};
+    match unsafe {E1000::new(MemIO::<u32>::new(registers), model, &STATE.kernel_as)} {
+        Ok(device) => {
+            let mut device = box device;
+            let pointer = &mut *device as *mut E1000<MemIO<u32>>;
+            let name = ::net::register(device);
+            unsafe {INTERFACES.get_or_insert_with(Vec::new)}.push((function.address, name, pointer));
+            if function.interrupt_pin != 0 && irq::enable_pci(function.interrupt_line, interrupt) {
+                unsafe {(*pointer).enable_interrupts()};
+            }
+            true
+        },
+        Err(error) => {
+            print!(Error, "Failed to set up e1000 {}: {:?}", function.address, error);
+            false
+        },
+    }
+}
+
+fn remove(device: &Device) {
+    if let Device::Pci(function) = *device {
+        let interfaces = unsafe {INTERFACES.get_or_insert_with(Vec::new)};
+        if let Some(index) = interfaces.iter().position(|interface| interface.0 == function.address) {
+            let (_, name, device) = interfaces.remove(index);
+            unsafe {(*device).disable_interrupts()};
+            ::net::unregister(&name);
+        }
+    }
+}
+
+make_driver_decl!("e1000", &[Match::pci(VENDOR_INTEL, 0x100E), Match::pci(VENDOR_INTEL, 0x100F),
+    Match::pci(VENDOR_INTEL, 0x10D3)], probe, remove, E1000_DRIVER);
@@ -0,0 +1,259 @@
+//! Linear framebuffers
+//!
+//! A `Framebuffer` is a mapped block of pixels, as set up by a display driver, that consoles and
+//! graphics code draw into without knowing which device it came from. Drawing is clipped to the
+//! framebuffer, and colours are packed into whatever format the mode has.
+//!
+//! The display driver that set the mode makes its framebuffer the primary one, which is then
+//! what everything else draws to.
+
+use core::fmt;
+use core::mem;
+use core::ptr;
+
+/// Layout of a pixel in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Index into the palette
+    Indexed8,
+    /// 5 bits each of red, green and blue, with red highest
+    Rgb555,
+    /// 5 bits of red and blue, with 6 of green
+    Rgb565,
+    /// 3 bytes of blue, green and red
+    Rgb888,
+    /// 4 bytes of blue, green, red and an unused byte
+    Xrgb8888,
+}
+
+impl Format {
+    /// Format of a depth in bits per pixel, as display devices describe modes
+    pub fn from_bpp(bpp: u8) -> Option<Format> {
+        match bpp {
+            8 => Some(Format::Indexed8),
+            15 => Some(Format::Rgb555),
+            16 => Some(Format::Rgb565),
+            24 => Some(Format::Rgb888),
+            32 => Some(Format::Xrgb8888),
+            _ => None,
+        }
+    }
+    pub fn bpp(&self) -> u8 {
+        match *self {
+            Format::Indexed8 => 8,
+            Format::Rgb555 => 15,
+            Format::Rgb565 => 16,
+            Format::Rgb888 => 24,
+            Format::Xrgb8888 => 32,
+        }
+    }
+    pub fn bytes_per_pixel(&self) -> usize {
+        match *self {
+            Format::Indexed8 => 1,
+            Format::Rgb555 | Format::Rgb565 => 2,
+            Format::Rgb888 => 3,
+            Format::Xrgb8888 => 4,
+        }
+    }
+    /// Pack a colour into a pixel, in the low bytes of the result
+    ///
+    /// Indexed formats take the colour as being in the default VGA palette, and so use the
+    /// nearest of its 16 text colours.
+    pub fn pack(&self, color: Color) -> u32 {
+        let (r, g, b) = (color.r as u32, color.g as u32, color.b as u32);
+        match *self {
+            Format::Indexed8 => color.nearest_vga() as u32,
+            Format::Rgb555 => (r >> 3) << 10 | (g >> 3) << 5 | b >> 3,
+            Format::Rgb565 => (r >> 3) << 11 | (g >> 2) << 5 | b >> 3,
+            Format::Rgb888 | Format::Xrgb8888 => r << 16 | g << 8 | b,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// The 16 colours of the default VGA palette, in the order of their indices
+pub const VGA_PALETTE: [Color; 16] = [
+    Color::new(0x00, 0x00, 0x00), Color::new(0x00, 0x00, 0xAA), Color::new(0x00, 0xAA, 0x00), Color::new(0x00, 0xAA, 0xAA),
+    Color::new(0xAA, 0x00, 0x00), Color::new(0xAA, 0x00, 0xAA), Color::new(0xAA, 0x55, 0x00), Color::new(0xAA, 0xAA, 0xAA),
+    Color::new(0x55, 0x55, 0x55), Color::new(0x55, 0x55, 0xFF), Color::new(0x55, 0xFF, 0x55), Color::new(0x55, 0xFF, 0xFF),
+    Color::new(0xFF, 0x55, 0x55), Color::new(0xFF, 0x55, 0xFF), Color::new(0xFF, 0xFF, 0x55), Color::new(0xFF, 0xFF, 0xFF),
+];
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8) -> Color {
+        Color { r: r, g: g, b: b }
+    }
+    /// Index of the closest colour in `VGA_PALETTE`
+    pub fn nearest_vga(&self) -> u8 {
+        let distance = |other: &Color| {
+            let d = |a: u8, b: u8| (a as i32 - b as i32) * (a as i32 - b as i32);
+            d(self.r, other.r) + d(self.g, other.g) + d(self.b, other.b)
+        };
+        let mut best = 0;
+        for (index, color) in VGA_PALETTE.iter().enumerate() {
+            if distance(color) < distance(&VGA_PALETTE[best]) {
+                best = index;
+            }
+        }
+        best as u8
+    }
+}
+
+pub struct Framebuffer {
+    base: *mut u8,
+    paddr: u64,
+    width: usize,
+    height: usize,
+    /// Bytes from the start of one row to the next
+    stride: usize,
+    format: Format,
+}
+
+impl Framebuffer {
+    /// Describe the pixels mapped at `base`
+    ///
+    /// # Safety
+    ///
+    /// `base` must be the virtual address of at least `stride * height` bytes that stay mapped
+    /// for as long as the framebuffer exists, and `stride` must fit a row of `width` pixels
+    pub unsafe fn new(base: *mut u8, paddr: u64, width: usize, height: usize, stride: usize, format: Format) -> Framebuffer {
+        Framebuffer { base: base, paddr: paddr, width: width, height: height, stride: stride, format: format }
+    }
+    pub fn base(&self) -> *mut u8 {
+        self.base
+    }
+    pub fn paddr(&self) -> u64 {
+        self.paddr
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+    pub fn format(&self) -> Format {
+        self.format
+    }
+    /// Size of the visible pixels in bytes
+    pub fn size(&self) -> usize {
+        self.stride * self.height
+    }
+    /// Write a packed pixel, which must be within the framebuffer
+    unsafe fn write_pixel(&mut self, x: usize, y: usize, pixel: u32) {
+        let at = self.base.offset((y * self.stride + x * self.format.bytes_per_pixel()) as isize);
+        match self.format.bytes_per_pixel() {
+            1 => ptr::write_volatile(at, pixel as u8),
+            2 => ptr::write_volatile(at as *mut u16, pixel as u16),
+            3 => {
+                ptr::write_volatile(at, pixel as u8);
+                ptr::write_volatile(at.offset(1), (pixel >> 8) as u8);
+                ptr::write_volatile(at.offset(2), (pixel >> 16) as u8);
+            },
+            _ => ptr::write_volatile(at as *mut u32, pixel),
+        }
+    }
+    /// The packed pixel at a position, or none if it is outside the framebuffer
+    ///
+    /// Reading back is slow on a write combined mapping, so is better avoided when drawing.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u32> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let bytes = self.format.bytes_per_pixel();
+        let at = unsafe {self.base.offset((y * self.stride + x * bytes) as isize)};
+        let mut pixel = 0;
+        for i in 0..bytes {
+            pixel |= (unsafe {ptr::read_volatile(at.offset(i as isize))} as u32) << (i * 8);
+        }
+        Some(pixel)
+    }
+    pub fn put_pixel(&mut self, x: usize, y: usize, color: Color) {
+        if x < self.width && y < self.height {
+            let pixel = self.format.pack(color);
+            unsafe {self.write_pixel(x, y, pixel)};
+        }
+    }
+    /// Fill a rectangle, clipped to the framebuffer
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        let pixel = self.format.pack(color);
+        let right = x.saturating_add(width).min(self.width);
+        let bottom = y.saturating_add(height).min(self.height);
+        for row in y..bottom {
+            for column in x..right {
+                unsafe {self.write_pixel(column, row, pixel)};
+            }
+        }
+    }
+    /// Draw a glyph 8 pixels wide, one byte a row with the leftmost pixel in the high bit
+    ///
+    /// Set bits are drawn in `fg` and clear ones in `bg`, so the glyph replaces whatever was
+    /// there. Clipped to the framebuffer.
+    pub fn draw_glyph(&mut self, x: usize, y: usize, glyph: &[u8], fg: Color, bg: Color) {
+        let (fg, bg) = (self.format.pack(fg), self.format.pack(bg));
+        for (row, &bits) in glyph.iter().enumerate() {
+            if y + row >= self.height {
+                break;
+            }
+            for column in 0..8 {
+                if x + column < self.width {
+                    let pixel = if bits & (0x80 >> column) != 0 { fg } else { bg };
+                    unsafe {self.write_pixel(x + column, y + row, pixel)};
+                }
+            }
+        }
+    }
+    pub fn clear(&mut self, color: Color) {
+        let (width, height) = (self.width, self.height);
+        self.fill_rect(0, 0, width, height, color);
+    }
+    /// Copy `count` rows starting at `src` to start at `dest`, as for scrolling
+    ///
+    /// The rows may overlap, and any that would fall outside the framebuffer are not copied.
+    pub fn copy_rows(&mut self, dest: usize, src: usize, count: usize) {
+        let count = count.min(self.height.saturating_sub(dest)).min(self.height.saturating_sub(src));
+        let row = self.width * self.format.bytes_per_pixel();
+        unsafe {
+            if dest < src {
+                for i in 0..count {
+                    ptr::copy(self.base.offset(((src + i) * self.stride) as isize),
+                        self.base.offset(((dest + i) * self.stride) as isize), row);
+                }
+            } else {
+                for i in (0..count).rev() {
+                    ptr::copy(self.base.offset(((src + i) * self.stride) as isize),
+                        self.base.offset(((dest + i) * self.stride) as isize), row);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Framebuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}x{} at {:#x}", self.width, self.height, self.format.bpp(), self.paddr)
+    }
+}
+
+static mut PRIMARY: Option<Framebuffer> = None;
+
+/// Make `framebuffer` the one that consoles and graphics draw to, returning the one it replaces
+///
+/// Display drivers call this whenever they set a mode, and with none when the mode they set
+/// goes away, as anything still drawing to it would then be writing to unmapped memory.
+pub fn set_primary(framebuffer: Option<Framebuffer>) -> Option<Framebuffer> {
+    unsafe {mem::replace(&mut PRIMARY, framebuffer)}
+}
+
+/// The framebuffer of the current display mode, if it has one
+pub fn primary() -> Option<&'static mut Framebuffer> {
+    unsafe {PRIMARY.as_mut()}
+}
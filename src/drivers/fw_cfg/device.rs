@@ -0,0 +1,213 @@
+//! QEMU firmware configuration interface
+//!
+//! Items are chosen by writing a 16 bit key to the selector, and their contents then read a byte
+//! at a time from the data register. Named files are found through a directory item listing
+//! their names, sizes and keys. All multi byte values in the directory are big endian.
+//!
+//! Newer versions also have a DMA interface, where the address of a request describing the
+//! transfer is written to the DMA register, which is much faster for large files. It is used
+//! whenever the device has it and DMA has been enabled.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+use drivers::dma::Dma;
+use drivers::io::MixedIo;
+use vspace::Translation;
+
+/// Base of the selector and data ports on x86
+pub const PORT_BASE: u16 = 0x510;
+
+const SELECTOR: usize = 0;
+const DATA: usize = 1;
+/// High half of the DMA request address, written as big endian
+const DMA_HIGH: usize = 4;
+/// Low half of the DMA request address, whose write starts the request
+const DMA_LOW: usize = 8;
+
+const KEY_SIGNATURE: u16 = 0x0000;
+const KEY_ID: u16 = 0x0001;
+const KEY_FILE_DIR: u16 = 0x0019;
+
+const SIGNATURE: &[u8] = b"QEMU";
+/// Device has the DMA interface
+pub const ID_DMA: u32 = 1 << 1;
+
+const DMA_ERROR: u32 = 1 << 0;
+const DMA_READ: u32 = 1 << 1;
+const DMA_SELECT: u32 = 1 << 3;
+/// Size of the DMA request, which is control, length and address
+const DMA_REQUEST_SIZE: usize = 16;
+/// Largest transfer made in one DMA request
+pub const DMA_CHUNK: usize = 4096;
+
+const FILE_ENTRY_SIZE: usize = 64;
+const FILE_NAME_OFFSET: usize = 8;
+const FILE_NAME_LEN: usize = 56;
+
+/// Number of polls of a DMA request before giving up
+const POLL_LIMIT: usize = 1000000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// No file with the name
+    NotFound,
+    /// Device reported an error with a DMA request
+    Dma,
+    /// DMA request did not complete
+    Timeout,
+}
+
+/// An entry of the file directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct File {
+    pub name: String,
+    pub size: u32,
+    /// Key that selects the file
+    pub key: u16,
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
+
+fn put_be(bytes: &mut [u8], value: u64) {
+    let len = bytes.len();
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (value >> ((len - 1 - i) * 8)) as u8;
+    }
+}
+
+/// Whether the device is at `io`, found by its signature without reading anything else
+///
+/// This needs no heap, so can be used early on to tell whether the kernel is running on QEMU.
+pub unsafe fn detect<T: MixedIo>(io: &mut T) -> bool {
+    io.write16(SELECTOR, KEY_SIGNATURE);
+    SIGNATURE.iter().all(|&byte| io.read8(DATA) == byte)
+}
+
+pub struct FwCfg<T: MixedIo> {
+    io: T,
+    id: u32,
+    files: Vec<File>,
+    /// DMA request and the buffer transfers are made through
+    dma: Option<(Dma, Dma)>,
+}
+
+impl<T: MixedIo> FwCfg<T> {
+    /// Detect the device by its signature and read its file directory
+    pub unsafe fn new(io: T) -> Option<FwCfg<T>> {
+        let mut fw_cfg = FwCfg { io: io, id: 0, files: Vec::new(), dma: None };
+        if !detect(&mut fw_cfg.io) {
+            return None;
+        }
+        let id = fw_cfg.read_port(KEY_ID, 4);
+        // The ID is the one little endian item
+        fw_cfg.id = id[0] as u32 | (id[1] as u32) << 8 | (id[2] as u32) << 16 | (id[3] as u32) << 24;
+        fw_cfg.files = fw_cfg.read_directory();
+        Some(fw_cfg)
+    }
+    /// Feature bits of the interface, as `ID_*`
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    /// Use the DMA interface, if the device has it
+    ///
+    /// `translation` is passed to `Dma::new`. Returns whether DMA will be used.
+    pub fn enable_dma(&mut self, translation: &Translation) -> bool {
+        if self.id & ID_DMA == 0 {
+            return false;
+        }
+        let request = Dma::new(translation, DMA_REQUEST_SIZE, DMA_REQUEST_SIZE);
+        let buffer = Dma::new(translation, DMA_CHUNK, DMA_CHUNK);
+        if let (Some(request), Some(buffer)) = (request, buffer) {
+            self.dma = Some((request, buffer));
+        }
+        self.dma.is_some()
+    }
+    pub fn files(&self) -> &[File] {
+        &self.files
+    }
+    pub fn find(&self, name: &str) -> Option<&File> {
+        self.files.iter().find(|file| file.name == name)
+    }
+    unsafe fn read_port(&mut self, key: u16, len: usize) -> Vec<u8> {
+        self.io.write16(SELECTOR, key);
+        (0..len).map(|_| self.io.read8(DATA)).collect()
+    }
+    unsafe fn read_directory(&mut self) -> Vec<File> {
+        self.io.write16(SELECTOR, KEY_FILE_DIR);
+        let count = be32(&(0..4).map(|_| self.io.read8(DATA)).collect::<Vec<u8>>());
+        let mut files = Vec::new();
+        let mut entry = [0; FILE_ENTRY_SIZE];
+        for _ in 0..count {
+            for byte in entry.iter_mut() {
+                *byte = self.io.read8(DATA);
+            }
+            let name = &entry[FILE_NAME_OFFSET..FILE_NAME_OFFSET + FILE_NAME_LEN];
+            let len = name.iter().position(|&byte| byte == 0).unwrap_or(FILE_NAME_LEN);
+            files.push(File {
+                name: String::from_utf8_lossy(&name[..len]).into_owned(),
+                size: be32(&entry[0..4]),
+                key: (entry[4] as u16) << 8 | entry[5] as u16,
+            });
+        }
+        files
+    }
+    /// Read `len` bytes of an item with DMA, in chunks that continue from the previous one
+    unsafe fn read_dma(&mut self, key: u16, len: usize) -> Result<Vec<u8>, Error> {
+        let (ref mut request, ref buffer) = *self.dma.as_mut().expect("DMA read without DMA");
+        let mut data = Vec::with_capacity(len);
+        let mut control = (key as u32) << 16 | DMA_SELECT | DMA_READ;
+        while data.len() < len {
+            let chunk = (len - data.len()).min(DMA_CHUNK);
+            {
+                let fields = request.as_mut_slice();
+                put_be(&mut fields[0..4], control as u64);
+                put_be(&mut fields[4..8], chunk as u64);
+                put_be(&mut fields[8..16], buffer.paddr());
+            }
+            // The request must be visible before the device is told of it
+            fence(Ordering::SeqCst);
+            let paddr = request.paddr();
+            self.io.write32(DMA_HIGH, ((paddr >> 32) as u32).to_be());
+            self.io.write32(DMA_LOW, (paddr as u32).to_be());
+            let mut polls = 0;
+            loop {
+                let status = u32::from_be(ptr::read_volatile(request.vaddr() as *const u32));
+                if status & DMA_ERROR != 0 {
+                    return Err(Error::Dma);
+                }
+                if status == 0 {
+                    break;
+                }
+                polls += 1;
+                if polls == POLL_LIMIT {
+                    return Err(Error::Timeout);
+                }
+            }
+            fence(Ordering::SeqCst);
+            data.extend_from_slice(&buffer.as_slice()[..chunk]);
+            // Later chunks carry on from where the last ended
+            control = DMA_READ;
+        }
+        Ok(data)
+    }
+    /// Read the first `len` bytes of the item selected by `key`
+    pub unsafe fn read(&mut self, key: u16, len: usize) -> Result<Vec<u8>, Error> {
+        if self.dma.is_some() {
+            self.read_dma(key, len)
+        } else {
+            Ok(self.read_port(key, len))
+        }
+    }
+    /// Read the whole of a named file
+    pub unsafe fn read_file(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        let (key, size) = match self.find(name) {
+            Some(file) => (file.key, file.size as usize),
+            None => return Err(Error::NotFound),
+        };
+        self.read(key, size)
+    }
+}
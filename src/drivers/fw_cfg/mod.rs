@@ -0,0 +1,55 @@
+//! QEMU firmware configuration
+//!
+//! Lets the host hand files to the kernel, such as those given with
+//! `-fw_cfg name=opt/rlk/config,file=config`. The device is probed for at its fixed ports, but
+//! only under a hypervisor as real hardware may have anything there, and once found its files can
+//! be read by name from anywhere in the kernel.
+
+use alloc::vec::Vec;
+use bus::Device;
+use cpu::features;
+use decls::Match;
+use drivers::io::PortIO;
+use state::STATE;
+
+mod device;
+
+pub use self::device::{detect, Error, File, FwCfg, PORT_BASE};
+
+static mut FW_CFG: Option<FwCfg<PortIO<u8>>> = None;
+
+fn probe(_device: &Device) -> bool {
+    if !features::hypervisor() {
+        return false;
+    }
+    let mut fw_cfg = match unsafe {FwCfg::new(PortIO::new(PORT_BASE))} {
+        Some(fw_cfg) => fw_cfg,
+        None => return false,
+    };
+    let dma = fw_cfg.enable_dma(unsafe {&STATE.kernel_as});
+    print!(Info, "fw_cfg with {} files{}", fw_cfg.files().len(), if dma { " using DMA" } else { "" });
+    for file in fw_cfg.files() {
+        print!(Debug, "    {} {} bytes", file.name, file.size);
+    }
+    unsafe {FW_CFG = Some(fw_cfg)};
+    true
+}
+
+fn remove(_device: &Device) {
+    unsafe {FW_CFG = None};
+}
+
+make_driver_decl!("fw_cfg", &[Match::Isa], probe, remove, FW_CFG_DRIVER);
+
+/// Files the host provided, or none if there is no fw_cfg
+pub fn files() -> &'static [File] {
+    unsafe {FW_CFG.as_ref()}.map_or(&[][..], |fw_cfg| fw_cfg.files())
+}
+
+/// Read a whole file by name, such as `opt/rlk/config`
+pub fn read(name: &str) -> Result<Vec<u8>, Error> {
+    match unsafe {FW_CFG.as_mut()} {
+        Some(fw_cfg) => unsafe {fw_cfg.read_file(name)},
+        None => Err(Error::NotFound),
+    }
+}
@@ -0,0 +1,196 @@
+//! Driver for the High Precision Event Timer
+//!
+//! An HPET block is a single free running main counter with a number of comparators, called
+//! timers, that interrupt when the counter reaches them. All registers are 64-bit and memory
+//! mapped. Timers are routed either through legacy replacement, where timer 0 takes over ISA IRQ
+//! 0 from the PIT and timer 1 takes IRQ 8 from the RTC, or individually to an I/O APIC input.
+
+use super::io::Io;
+
+/// Conventional physical address of the first HPET block
+pub const DEFAULT_PADDR: usize = 0xFED00000;
+/// Size of the register window
+pub const MMIO_SIZE: usize = 0x400;
+
+const CAPABILITIES: usize = 0x000;
+const CONFIG: usize = 0x010;
+const INTERRUPT_STATUS: usize = 0x020;
+const MAIN_COUNTER: usize = 0x0F0;
+const TIMER_BASE: usize = 0x100;
+const TIMER_STRIDE: usize = 0x20;
+const TIMER_CONFIG: usize = 0x00;
+const TIMER_COMPARATOR: usize = 0x08;
+
+/// Longest counter period allowed by the specification, in femtoseconds
+const MAX_PERIOD_FS: u64 = 100_000_000;
+const FS_PER_SECOND: u64 = 1_000_000_000_000_000;
+
+/// Main counter runs
+const CONFIG_ENABLE: u64 = 1 << 0;
+/// Timers 0 and 1 are routed as legacy replacement for the PIT and RTC
+const CONFIG_LEGACY: u64 = 1 << 1;
+
+bitfield!{
+    /// General capabilities and ID register
+    #[derive(Clone, Copy)]
+    pub struct Capabilities(u64);
+    pub revision, _: 7, 0;
+    pub last_timer, _: 12, 8;
+    pub counter_64, _: 13;
+    pub legacy_capable, _: 15;
+    pub vendor, _: 31, 16;
+    /// Main counter period in femtoseconds
+    pub period, _: 63, 32;
+}
+
+bitfield!{
+    /// Timer configuration and capability register
+    #[derive(Clone, Copy)]
+    pub struct TimerConfig(u64);
+    pub level_trigger, set_level_trigger: 1;
+    pub enabled, set_enabled: 2;
+    pub periodic, set_periodic: 3;
+    pub periodic_capable, _: 4;
+    pub comparator_64, _: 5;
+    /// Next comparator write sets the periodic accumulator
+    pub _, set_value: 6;
+    pub force_32, set_force_32: 8;
+    pub route, set_route: 13, 9;
+    pub fsb, set_fsb: 14;
+    pub fsb_capable, _: 15;
+    /// Bitmask of the I/O APIC inputs this timer can be routed to
+    pub route_capable, _: 63, 32;
+}
+
+pub struct Hpet<T: Io<Item = u64>> {
+    io: T,
+    capabilities: Capabilities,
+}
+
+impl<T, R> Hpet<T> where T: Io<Item = u64, Range=R>, R: From<u16> {
+    unsafe fn read(&mut self, reg: usize) -> u64 {
+        self.io.read(R::from(reg as u16))
+    }
+    unsafe fn write(&mut self, reg: usize, value: u64) {
+        self.io.write(R::from(reg as u16), value)
+    }
+    fn timer_reg(&self, timer: u8, reg: usize) -> usize {
+        assert!(timer < self.timers(), "HPET has no timer {}", timer);
+        TIMER_BASE + TIMER_STRIDE * timer as usize + reg
+    }
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+    /// Number of timers in this block
+    pub fn timers(&self) -> u8 {
+        self.capabilities.last_timer() as u8 + 1
+    }
+    /// Main counter frequency in Hz
+    pub fn frequency(&self) -> u64 {
+        FS_PER_SECOND / self.capabilities.period()
+    }
+    /// Mask of the bits the main counter has before it wraps
+    pub fn counter_mask(&self) -> u64 {
+        if self.capabilities.counter_64() { !0 } else { 0xFFFF_FFFF }
+    }
+    pub unsafe fn counter(&mut self) -> u64 {
+        self.read(MAIN_COUNTER) & self.counter_mask()
+    }
+    /// Start the main counter
+    ///
+    /// `legacy` selects legacy replacement routing, which must be supported
+    pub unsafe fn enable(&mut self, legacy: bool) {
+        assert!(!legacy || self.capabilities.legacy_capable(), "HPET cannot do legacy replacement");
+        let mut config = self.read(CONFIG) | CONFIG_ENABLE;
+        if legacy {
+            config |= CONFIG_LEGACY;
+        } else {
+            config &= !CONFIG_LEGACY;
+        }
+        self.write(CONFIG, config);
+    }
+    /// Halt the main counter
+    pub unsafe fn disable(&mut self) {
+        let config = self.read(CONFIG);
+        self.write(CONFIG, config & !CONFIG_ENABLE);
+    }
+    pub unsafe fn timer_config(&mut self, timer: u8) -> TimerConfig {
+        let reg = self.timer_reg(timer, TIMER_CONFIG);
+        TimerConfig(self.read(reg))
+    }
+    unsafe fn set_timer_config(&mut self, timer: u8, config: TimerConfig) {
+        let reg = self.timer_reg(timer, TIMER_CONFIG);
+        self.write(reg, config.0);
+    }
+    unsafe fn set_comparator(&mut self, timer: u8, value: u64) {
+        let reg = self.timer_reg(timer, TIMER_COMPARATOR);
+        self.write(reg, value);
+    }
+    /// Route a timer to an I/O APIC input, when not using legacy replacement
+    pub unsafe fn route(&mut self, timer: u8, input: u8) {
+        let mut config = self.timer_config(timer);
+        assert!(input < 32 && config.route_capable() & (1 << input) != 0, "HPET timer {} cannot route to input {}", timer, input);
+        config.set_route(input as u64);
+        self.set_timer_config(timer, config);
+    }
+    /// Interrupt every `ticks` counts of the main counter
+    ///
+    /// The main counter is left running, as it may be in use as a clock source. If setting the
+    /// timer up takes so long that the counter may have passed the first comparison before it
+    /// was written, the timer is set again with the first comparison further away.
+    pub unsafe fn periodic(&mut self, timer: u8, ticks: u64) {
+        let mut config = self.timer_config(timer);
+        assert!(config.periodic_capable(), "HPET timer {} is not periodic capable", timer);
+        config.set_level_trigger(false);
+        config.set_enabled(true);
+        config.set_periodic(true);
+        config.set_value(true);
+        let mask = self.counter_mask();
+        let mut margin = ticks;
+        loop {
+            self.set_timer_config(timer, config);
+            let now = self.counter();
+            self.set_comparator(timer, now.wrapping_add(margin) & mask);
+            // With the accumulator being set the second write is the period
+            self.set_comparator(timer, ticks);
+            if self.counter().wrapping_sub(now) & mask < margin {
+                break;
+            }
+            margin = margin.saturating_mul(2);
+        }
+    }
+    /// Interrupt once, `ticks` counts of the main counter from now
+    pub unsafe fn one_shot(&mut self, timer: u8, ticks: u64) {
+        let mut config = self.timer_config(timer);
+        config.set_level_trigger(false);
+        config.set_enabled(true);
+        config.set_periodic(false);
+        self.set_timer_config(timer, config);
+        let target = self.counter().wrapping_add(ticks) & self.counter_mask();
+        self.set_comparator(timer, target);
+    }
+    /// Stop a timer from interrupting
+    pub unsafe fn stop(&mut self, timer: u8) {
+        let mut config = self.timer_config(timer);
+        config.set_enabled(false);
+        config.set_periodic(false);
+        self.set_timer_config(timer, config);
+    }
+    /// Acknowledge a level triggered interrupt from a timer
+    pub unsafe fn ack(&mut self, timer: u8) {
+        self.write(INTERRUPT_STATUS, 1 << timer);
+    }
+    /// Construct a driver, if the registers look like an HPET
+    ///
+    /// Reading somewhere that has no device typically returns all 1s or all 0s, neither of
+    /// which is a valid counter period
+    pub unsafe fn new(io: T) -> Option<Hpet<T>> {
+        let mut hpet = Hpet { io: io, capabilities: Capabilities(0) };
+        hpet.capabilities = Capabilities(hpet.read(CAPABILITIES));
+        let period = hpet.capabilities.period();
+        if period == 0 || period > MAX_PERIOD_FS {
+            return None;
+        }
+        Some(hpet)
+    }
+}
@@ -0,0 +1,245 @@
+//! Driver for the 8042 PS/2 controller
+//!
+//! The controller is accessed through two ports, data at 0x60 and status/command at 0x64, so it
+//! is described by a single `Io` window based at 0x60.
+
+use super::io::Io;
+
+/// Conventional base of the controller window
+pub const PORT_BASE: u16 = 0x60;
+
+const DATA: u8 = 0;
+const STATUS_COMMAND: u8 = 4;
+
+/// Number of status polls to perform before giving up on the controller
+///
+/// There is no timer to bound waits by, so this is just generously sized to not fail on a
+/// slow controller.
+const POLL_LIMIT: usize = 100000;
+
+bitflags! {
+    /// Status Register
+    struct Status: u8 {
+        /// Parity Error
+        const PARITY = 0b10000000;
+        /// Timeout Error
+        const TIMEOUT = 0b1000000;
+        /// Data in the output buffer came from the second port
+        const AUX_DATA = 0b100000;
+        /// Last write was a command and not data
+        const COMMAND = 0b1000;
+        /// System flag, set after passing POST
+        const SYSTEM = 0b100;
+        /// Input Buffer Full, must be clear before writing
+        const INPUT_FULL = 0b10;
+        /// Output Buffer Full, must be set before reading
+        const OUTPUT_FULL = 0b1;
+    }
+}
+
+bitflags! {
+    /// Controller Configuration Byte
+    struct Config: u8 {
+        /// Translate the first port to scancode set 1
+        const PORT1_TRANSLATE = 0b1000000;
+        /// Second port clock disabled
+        const PORT2_CLOCK_DISABLE = 0b100000;
+        /// First port clock disabled
+        const PORT1_CLOCK_DISABLE = 0b10000;
+        /// System flag, set after passing POST
+        const SYSTEM = 0b100;
+        /// Second port interrupt enabled
+        const PORT2_INT = 0b10;
+        /// First port interrupt enabled
+        const PORT1_INT = 0b1;
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)]
+enum Command {
+    ReadConfig = 0x20,
+    WriteConfig = 0x60,
+    DisablePort2 = 0xA7,
+    EnablePort2 = 0xA8,
+    TestPort2 = 0xA9,
+    SelfTest = 0xAA,
+    TestPort1 = 0xAB,
+    DisablePort1 = 0xAD,
+    EnablePort1 = 0xAE,
+    PulseReset = 0xFE,
+}
+
+/// Response to a controller self test that passed
+const SELF_TEST_PASS: u8 = 0x55;
+/// Response to a port test that passed
+const PORT_TEST_PASS: u8 = 0x00;
+
+/// Device acknowledged a command
+pub const DEVICE_ACK: u8 = 0xFA;
+/// Device requested a command be resent
+pub const DEVICE_RESEND: u8 = 0xFE;
+
+/// Number of times to resend a byte to a device before giving up
+pub const DEVICE_RETRIES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Controller did not respond in time
+    Timeout,
+    /// Controller self test returned something other than the pass value
+    SelfTestFailed(u8),
+    /// Port test returned an error code
+    PortTestFailed(u8),
+    /// Device responded to a command with something other than an ACK
+    NoAck(u8),
+}
+
+/// Result of initializing the controller
+#[derive(Debug, Clone, Copy)]
+pub struct Ports {
+    /// Controller has a second port
+    pub dual: bool,
+}
+
+pub struct Controller<T: Io<Item = u8>> {
+    io: T,
+}
+
+impl<T, R> Controller<T> where T: Io<Item = u8, Range=R>, R: From<u8> {
+    unsafe fn read_status(&mut self) -> Status {
+        Status::from_bits_truncate(self.io.read(R::from(STATUS_COMMAND)))
+    }
+    unsafe fn wait_input_empty(&mut self) -> Result<(), Error> {
+        for _ in 0..POLL_LIMIT {
+            if !self.read_status().contains(Status::INPUT_FULL) {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+    unsafe fn wait_output_full(&mut self) -> Result<(), Error> {
+        for _ in 0..POLL_LIMIT {
+            if self.read_status().contains(Status::OUTPUT_FULL) {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+    unsafe fn command(&mut self, command: Command) -> Result<(), Error> {
+        self.wait_input_empty()?;
+        self.io.write(R::from(STATUS_COMMAND), command as u8);
+        Ok(())
+    }
+    unsafe fn command_response(&mut self, command: Command) -> Result<u8, Error> {
+        self.command(command)?;
+        self.read_data_wait()
+    }
+    unsafe fn read_config(&mut self) -> Result<Config, Error> {
+        self.command_response(Command::ReadConfig).map(Config::from_bits_truncate)
+    }
+    unsafe fn write_config(&mut self, config: Config) -> Result<(), Error> {
+        self.command(Command::WriteConfig)?;
+        self.write_data(config.bits())
+    }
+    /// Send a byte to the device on the first port without waiting for a response
+    pub unsafe fn write_data(&mut self, data: u8) -> Result<(), Error> {
+        self.wait_input_empty()?;
+        self.io.write(R::from(DATA), data);
+        Ok(())
+    }
+    /// Discard anything sitting in the output buffer
+    unsafe fn flush(&mut self) {
+        for _ in 0..POLL_LIMIT {
+            if !self.read_status().contains(Status::OUTPUT_FULL) {
+                return;
+            }
+            self.io.read(R::from(DATA));
+        }
+    }
+    /// Wait for, and retrieve, the next byte from the controller
+    pub unsafe fn read_data_wait(&mut self) -> Result<u8, Error> {
+        self.wait_output_full()?;
+        Ok(self.io.read(R::from(DATA)))
+    }
+    /// Retrieve a pending byte from the first port, if there is one
+    ///
+    /// Any byte from the second port is discarded
+    pub unsafe fn read_port1(&mut self) -> Option<u8> {
+        let status = self.read_status();
+        if !status.contains(Status::OUTPUT_FULL) {
+            return None;
+        }
+        let data = self.io.read(R::from(DATA));
+        if status.contains(Status::AUX_DATA) {
+            None
+        } else {
+            Some(data)
+        }
+    }
+    /// Send a byte to the device on the first port and wait for it to be acknowledged
+    pub unsafe fn send_port1(&mut self, byte: u8) -> Result<(), Error> {
+        for _ in 0..DEVICE_RETRIES {
+            self.write_data(byte)?;
+            match self.read_data_wait()? {
+                DEVICE_ACK => return Ok(()),
+                DEVICE_RESEND => continue,
+                other => return Err(Error::NoAck(other)),
+            }
+        }
+        Err(Error::NoAck(DEVICE_RESEND))
+    }
+    /// Enable or disable interrupts from the first port
+    pub unsafe fn set_port1_interrupt(&mut self, enable: bool) -> Result<(), Error> {
+        let mut config = self.read_config()?;
+        config.set(Config::PORT1_INT, enable);
+        self.write_config(config)
+    }
+    /// Bring the controller to a known state and test it
+    ///
+    /// Fails if the first port does not pass its interface test, as there is nothing else we
+    /// would use the controller for. Leaves both ports disabled, with interrupts and translation
+    /// off. Use `enable_port1` once ready to receive data from a device.
+    pub unsafe fn init(&mut self) -> Result<Ports, Error> {
+        self.command(Command::DisablePort1)?;
+        self.command(Command::DisablePort2)?;
+        self.flush();
+        let mut config = self.read_config()?;
+        config.remove(Config::PORT1_INT | Config::PORT2_INT | Config::PORT1_TRANSLATE);
+        // If the second port clock did not get disabled then there is no second port
+        let dual = config.contains(Config::PORT2_CLOCK_DISABLE);
+        self.write_config(config)?;
+        match self.command_response(Command::SelfTest)? {
+            SELF_TEST_PASS => (),
+            other => return Err(Error::SelfTestFailed(other)),
+        }
+        // Self test can reset the controller, so write the configuration again
+        self.write_config(config)?;
+        let dual = dual && {
+            // Confirm by seeing if enabling the second port clears its clock disable
+            self.command(Command::EnablePort2)?;
+            let enabled = !self.read_config()?.contains(Config::PORT2_CLOCK_DISABLE);
+            self.command(Command::DisablePort2)?;
+            enabled
+        };
+        match self.command_response(Command::TestPort1)? {
+            PORT_TEST_PASS => (),
+            other => return Err(Error::PortTestFailed(other)),
+        }
+        Ok(Ports {dual: dual})
+    }
+    pub unsafe fn enable_port1(&mut self) -> Result<(), Error> {
+        self.command(Command::EnablePort1)
+    }
+    /// Reset the machine by pulsing the CPU reset line
+    ///
+    /// Only returns if the reset did not happen
+    pub unsafe fn pulse_reset(&mut self) {
+        // Failing to wait just means we try anyway
+        let _ = self.command(Command::PulseReset);
+    }
+    pub fn new(io: T) -> Controller<T> {
+        Controller { io: io }
+    }
+}
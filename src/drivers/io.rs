@@ -1,6 +1,8 @@
 //! Define generic IO traits and implementations
 
+use alloc::boxed::Box;
 use core::marker::PhantomData;
+use core::ptr;
 use x86::shared::io;
 
 pub trait Io {
@@ -35,3 +37,171 @@ impl Io for PortIO<u8> {
         io::outb(self.base + offset, value)
     }
 }
+
+impl Io for PortIO<u32> {
+    type Item = u32;
+    type Range = u16;
+    unsafe fn read(&mut self, offset: u16) -> u32 {
+        io::inl(self.base + offset)
+    }
+    unsafe fn write(&mut self, offset: u16, value: u32) {
+        io::outl(self.base + offset, value)
+    }
+}
+
+/// Memory mapped IO
+///
+/// Accesses are volatile and of exactly the size of `T`, with offsets given in bytes from
+/// the base virtual address. The region must be mapped with a suitable memory type, as
+/// nothing here prevents the accesses from being cached or combined.
+pub struct MemIO<T> {
+    base: usize,
+    data: PhantomData<T>,
+}
+
+impl<T> MemIO<T> {
+    /// Construct an accessor for the mapped region starting at `base`
+    ///
+    /// # Safety
+    ///
+    /// `base` must be the virtual address of device memory that remains mapped for as
+    /// long as this accessor exists
+    pub unsafe fn new(base: usize) -> MemIO<T> {
+        MemIO {base: base, data: PhantomData}
+    }
+    pub fn base(&self) -> usize {
+        self.base
+    }
+}
+
+impl<T: Copy> Io for MemIO<T> {
+    type Item = T;
+    unsafe fn read(&mut self, offset: usize) -> T {
+        ptr::read_volatile((self.base + offset) as *const T)
+    }
+    unsafe fn write(&mut self, offset: usize, value: T) {
+        ptr::write_volatile((self.base + offset) as *mut T, value)
+    }
+}
+
+/// Registers of differing widths in one window
+///
+/// Some register blocks mix register sizes, which a single `Io` cannot describe. Offsets are
+/// in bytes and must be aligned to the size of the access.
+pub trait MixedIo {
+    unsafe fn read8(&mut self, offset: usize) -> u8;
+    unsafe fn read16(&mut self, offset: usize) -> u16;
+    unsafe fn read32(&mut self, offset: usize) -> u32;
+    unsafe fn write8(&mut self, offset: usize, value: u8);
+    unsafe fn write16(&mut self, offset: usize, value: u16);
+    unsafe fn write32(&mut self, offset: usize, value: u32);
+}
+
+/// Accesses of any width relative to the base of a byte window
+impl MixedIo for MemIO<u8> {
+    unsafe fn read8(&mut self, offset: usize) -> u8 {
+        ptr::read_volatile((self.base + offset) as *const u8)
+    }
+    unsafe fn read16(&mut self, offset: usize) -> u16 {
+        ptr::read_volatile((self.base + offset) as *const u16)
+    }
+    unsafe fn read32(&mut self, offset: usize) -> u32 {
+        ptr::read_volatile((self.base + offset) as *const u32)
+    }
+    unsafe fn write8(&mut self, offset: usize, value: u8) {
+        ptr::write_volatile((self.base + offset) as *mut u8, value)
+    }
+    unsafe fn write16(&mut self, offset: usize, value: u16) {
+        ptr::write_volatile((self.base + offset) as *mut u16, value)
+    }
+    unsafe fn write32(&mut self, offset: usize, value: u32) {
+        ptr::write_volatile((self.base + offset) as *mut u32, value)
+    }
+}
+
+/// Accesses of any width relative to the base port
+impl MixedIo for PortIO<u8> {
+    unsafe fn read8(&mut self, offset: usize) -> u8 {
+        io::inb(self.base + offset as u16)
+    }
+    unsafe fn read16(&mut self, offset: usize) -> u16 {
+        io::inw(self.base + offset as u16)
+    }
+    unsafe fn read32(&mut self, offset: usize) -> u32 {
+        io::inl(self.base + offset as u16)
+    }
+    unsafe fn write8(&mut self, offset: usize, value: u8) {
+        io::outb(self.base + offset as u16, value)
+    }
+    unsafe fn write16(&mut self, offset: usize, value: u16) {
+        io::outw(self.base + offset as u16, value)
+    }
+    unsafe fn write32(&mut self, offset: usize, value: u32) {
+        io::outl(self.base + offset as u16, value)
+    }
+}
+
+impl<T: MixedIo + ?Sized> MixedIo for &mut T {
+    unsafe fn read8(&mut self, offset: usize) -> u8 {
+        (**self).read8(offset)
+    }
+    unsafe fn read16(&mut self, offset: usize) -> u16 {
+        (**self).read16(offset)
+    }
+    unsafe fn read32(&mut self, offset: usize) -> u32 {
+        (**self).read32(offset)
+    }
+    unsafe fn write8(&mut self, offset: usize, value: u8) {
+        (**self).write8(offset, value)
+    }
+    unsafe fn write16(&mut self, offset: usize, value: u16) {
+        (**self).write16(offset, value)
+    }
+    unsafe fn write32(&mut self, offset: usize, value: u32) {
+        (**self).write32(offset, value)
+    }
+}
+
+/// Allows a driver to hold whichever window its device turned out to have
+impl<T: MixedIo + ?Sized> MixedIo for Box<T> {
+    unsafe fn read8(&mut self, offset: usize) -> u8 {
+        (**self).read8(offset)
+    }
+    unsafe fn read16(&mut self, offset: usize) -> u16 {
+        (**self).read16(offset)
+    }
+    unsafe fn read32(&mut self, offset: usize) -> u32 {
+        (**self).read32(offset)
+    }
+    unsafe fn write8(&mut self, offset: usize, value: u8) {
+        (**self).write8(offset, value)
+    }
+    unsafe fn write16(&mut self, offset: usize, value: u16) {
+        (**self).write16(offset, value)
+    }
+    unsafe fn write32(&mut self, offset: usize, value: u32) {
+        (**self).write32(offset, value)
+    }
+}
+
+/// Forward IO through a mutable reference
+///
+/// Allows a driver to be constructed around a borrowed accessor, leaving the accessor
+/// usable by its owner once the driver has gone away
+impl<T: Io + ?Sized> Io for &mut T {
+    type Item = T::Item;
+    type Range = T::Range;
+    unsafe fn read(&mut self, offset: T::Range) -> T::Item {
+        (**self).read(offset)
+    }
+    unsafe fn write(&mut self, offset: T::Range, value: T::Item) {
+        (**self).write(offset, value)
+    }
+}
+
+/// Call `read` until `done` holds of what it returns, giving up after `limit` reads
+///
+/// For waiting on a register, where the device may never get to the state it is waited for.
+pub fn wait<F, D, V>(limit: usize, mut read: F, done: D) -> bool where F: FnMut() -> V, D: Fn(V) -> bool {
+    (0..limit).any(|_| done(read()))
+}
@@ -0,0 +1,138 @@
+//! Driver for the I/O APIC
+//!
+//! The I/O APIC routes external interrupt lines, known as global system interrupts (GSIs), to
+//! local APICs. It has only two memory mapped registers, a select register and a window, through
+//! which the indirect registers, including the redirection table, are accessed.
+
+use super::io::Io;
+use super::apic::DeliveryMode;
+
+/// Conventional physical address of the first I/O APIC
+pub const DEFAULT_PADDR: usize = 0xFEC00000;
+
+/// Size of the register window
+pub const MMIO_SIZE: usize = 0x20;
+
+const IOREGSEL: u8 = 0x00;
+const IOWIN: u8 = 0x10;
+
+const ID: u32 = 0x00;
+const VERSION: u32 = 0x01;
+/// First redirection table register, each entry is a pair of registers
+const REDIRECTION_BASE: u32 = 0x10;
+
+bitfield!{
+    /// Redirection table entry
+    #[derive(Clone, Copy)]
+    pub struct Redirection(u64);
+    pub vector, set_vector: 7, 0;
+    pub delivery_mode, set_delivery_mode: 10, 8;
+    pub logical, set_logical: 11;
+    pub pending, _: 12;
+    pub active_low, set_active_low: 13;
+    pub remote_irr, _: 14;
+    pub level_trigger, set_level_trigger: 15;
+    pub masked, set_masked: 16;
+    pub destination, set_destination: 63, 56;
+}
+
+impl Redirection {
+    /// Fixed delivery of `vector` to a single local APIC, by physical ID
+    ///
+    /// The entry starts out masked
+    pub fn fixed(vector: u8, apic_id: u8) -> Redirection {
+        let mut entry = Redirection(0);
+        entry.set_vector(vector as u64);
+        entry.set_delivery_mode(DeliveryMode::Fixed as u64);
+        entry.set_destination(apic_id as u64);
+        entry.set_masked(true);
+        entry
+    }
+    /// Set the electrical characteristics, ISA interrupts being edge triggered and active high
+    pub fn trigger(mut self, level: bool, active_low: bool) -> Redirection {
+        self.set_level_trigger(level);
+        self.set_active_low(active_low);
+        self
+    }
+}
+
+pub struct IoApic<T: Io<Item = u32>> {
+    io: T,
+    gsi_base: u32,
+    entries: u32,
+}
+
+impl<T, R> IoApic<T> where T: Io<Item = u32, Range=R>, R: From<u8> {
+    unsafe fn read(&mut self, reg: u32) -> u32 {
+        self.io.write(R::from(IOREGSEL), reg);
+        self.io.read(R::from(IOWIN))
+    }
+    unsafe fn write(&mut self, reg: u32, value: u32) {
+        self.io.write(R::from(IOREGSEL), reg);
+        self.io.write(R::from(IOWIN), value)
+    }
+    pub unsafe fn id(&mut self) -> u8 {
+        ((self.read(ID) >> 24) & 0xF) as u8
+    }
+    pub unsafe fn version(&mut self) -> u8 {
+        self.read(VERSION) as u8
+    }
+    /// First GSI handled by this I/O APIC
+    pub fn gsi_base(&self) -> u32 {
+        self.gsi_base
+    }
+    /// Number of entries in the redirection table
+    pub fn entries(&self) -> u32 {
+        self.entries
+    }
+    /// Whether the GSI is routed by this I/O APIC
+    pub fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.entries
+    }
+    fn register_for(&self, gsi: u32) -> u32 {
+        assert!(self.handles(gsi), "GSI {} is not routed by this I/O APIC", gsi);
+        REDIRECTION_BASE + (gsi - self.gsi_base) * 2
+    }
+    pub unsafe fn redirection(&mut self, gsi: u32) -> Redirection {
+        let reg = self.register_for(gsi);
+        let low = self.read(reg) as u64;
+        let high = self.read(reg + 1) as u64;
+        Redirection(high << 32 | low)
+    }
+    /// Program a redirection table entry
+    ///
+    /// The entry is masked whilst the destination is being changed, so that an interrupt is
+    /// never delivered to a half written entry
+    pub unsafe fn set_redirection(&mut self, gsi: u32, entry: Redirection) {
+        let reg = self.register_for(gsi);
+        let mut masked = entry;
+        masked.set_masked(true);
+        self.write(reg, masked.0 as u32);
+        self.write(reg + 1, (entry.0 >> 32) as u32);
+        self.write(reg, entry.0 as u32);
+    }
+    unsafe fn set_masked(&mut self, gsi: u32, masked: bool) {
+        let reg = self.register_for(gsi);
+        let mut entry = Redirection(self.read(reg) as u64);
+        entry.set_masked(masked);
+        self.write(reg, entry.0 as u32);
+    }
+    pub unsafe fn mask(&mut self, gsi: u32) {
+        self.set_masked(gsi, true)
+    }
+    pub unsafe fn unmask(&mut self, gsi: u32) {
+        self.set_masked(gsi, false)
+    }
+    /// Mask every entry in the redirection table
+    pub unsafe fn mask_all(&mut self) {
+        for gsi in self.gsi_base..self.gsi_base + self.entries {
+            self.mask(gsi);
+        }
+    }
+    /// Construct a driver for an I/O APIC whose first entry is `gsi_base`
+    pub unsafe fn new(io: T, gsi_base: u32) -> IoApic<T> {
+        let mut ioapic = IoApic { io: io, gsi_base: gsi_base, entries: 0 };
+        ioapic.entries = ((ioapic.read(VERSION) >> 16) & 0xFF) + 1;
+        ioapic
+    }
+}
@@ -2,8 +2,25 @@
 
 pub mod uart16550;
 pub mod io;
+pub mod i8042;
+pub mod ps2kbd;
+pub mod pic8259;
+pub mod apic;
+pub mod ioapic;
+pub mod pit;
+pub mod hpet;
+pub mod rtc;
+pub mod vga;
+pub mod pci;
+pub mod dma;
+pub mod framebuffer;
+pub mod bochs;
+pub mod e1000;
+pub mod fw_cfg;
+pub mod qemu;
+pub mod ahci;
+pub mod nvme;
+pub mod virtio;
+mod serial;
 
-pub trait Serial {
-    // TODO: should have errors or timeouts?
-    unsafe fn write_byte(&mut self, byte: u8);
-}
+pub use self::serial::Serial;
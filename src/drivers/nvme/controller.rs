@@ -0,0 +1,394 @@
+//! NVMe controller
+//!
+//! The controller is reset and enabled with an admin queue pair, identified, and then given a
+//! single I/O queue pair that every namespace shares. Only one command is outstanding at a time,
+//! and data moves through a bounce buffer described by a fixed PRP list, like AHCI.
+//!
+//! Completions are always found by reading the completion queues. When the I/O queue is given
+//! an MSI-X vector its interrupt handler calls `interrupt`, which takes completions off the
+//! queues the same way, so a command can complete through either path.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use drivers::dma::Dma;
+use drivers::io::{wait, Io};
+use vspace::Translation;
+use super::queue::{Command, Completion, Queue};
+
+pub const CAP: u16 = 0x00;
+pub const CAP_HIGH: u16 = 0x04;
+pub const VS: u16 = 0x08;
+pub const INTMS: u16 = 0x0C;
+pub const INTMC: u16 = 0x10;
+pub const CC: u16 = 0x14;
+pub const CSTS: u16 = 0x1C;
+pub const AQA: u16 = 0x24;
+pub const ASQ: u16 = 0x28;
+pub const ASQ_HIGH: u16 = 0x2C;
+pub const ACQ: u16 = 0x30;
+pub const ACQ_HIGH: u16 = 0x34;
+/// Start of the doorbells, spaced by the stride in `CAP`
+pub const DOORBELLS: u16 = 0x1000;
+
+/// NVM command set supported, in the high half of `CAP`
+const CAP_CSS_NVM: u32 = 1 << 5;
+
+pub const CC_EN: u32 = 1 << 0;
+/// Normal shutdown notification
+pub const CC_SHN_NORMAL: u32 = 1 << 14;
+const CC_IOSQES_SHIFT: u32 = 16;
+const CC_IOCQES_SHIFT: u32 = 20;
+
+pub const CSTS_RDY: u32 = 1 << 0;
+/// Controller fatal status
+pub const CSTS_CFS: u32 = 1 << 1;
+const CSTS_SHST_MASK: u32 = 0b11 << 2;
+pub const CSTS_SHST_COMPLETE: u32 = 0b10 << 2;
+
+pub const ADMIN_DELETE_SQ: u8 = 0x00;
+pub const ADMIN_CREATE_SQ: u8 = 0x01;
+pub const ADMIN_DELETE_CQ: u8 = 0x04;
+pub const ADMIN_CREATE_CQ: u8 = 0x05;
+pub const ADMIN_IDENTIFY: u8 = 0x06;
+pub const ADMIN_SET_FEATURES: u8 = 0x09;
+
+pub const IO_FLUSH: u8 = 0x00;
+pub const IO_WRITE: u8 = 0x01;
+pub const IO_READ: u8 = 0x02;
+
+pub const IDENTIFY_NAMESPACE: u32 = 0;
+pub const IDENTIFY_CONTROLLER: u32 = 1;
+pub const IDENTIFY_ACTIVE_NAMESPACES: u32 = 2;
+
+pub const FEATURE_NUMBER_OF_QUEUES: u32 = 0x07;
+
+/// Queue is physically contiguous, in the create commands
+const QUEUE_CONTIGUOUS: u32 = 1 << 0;
+/// Completion queue raises interrupts
+const QUEUE_INTERRUPTS: u32 = 1 << 1;
+
+/// Sizes of queue entries as powers of 2
+const SQ_ENTRY_SHIFT: u32 = 6;
+const CQ_ENTRY_SHIFT: u32 = 4;
+
+pub const ADMIN_DEPTH: u16 = 32;
+pub const IO_DEPTH: u16 = 64;
+pub const IO_QUEUE: u16 = 1;
+
+/// Memory page size, which is the smallest every controller supports
+pub const PAGE_SIZE: usize = 4096;
+/// Largest transfer made in one command
+const BOUNCE_SIZE: usize = 128 * 1024;
+/// Size of Identify data
+const IDENTIFY_SIZE: usize = 4096;
+
+/// Polls before the controller or a command is given up on
+pub const POLL_LIMIT: usize = 1000000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Controller did not become ready, or did not disable
+    Timeout,
+    /// Controller reported a fatal error
+    Fatal,
+    /// Controller lacks the NVM command set or 4KiB pages
+    Unsupported,
+    /// Memory for the queues could not be allocated
+    NoMemory,
+    /// Command did not complete
+    CommandTimeout,
+    /// Command failed, with its status code and type
+    Status(u16),
+}
+
+/// What Identify Controller reports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub vendor: u16,
+    pub serial: String,
+    pub model: String,
+    pub firmware: String,
+    /// Largest transfer as a power of 2 of the minimum page size, 0 for no limit
+    pub mdts: u8,
+    /// Highest namespace ID
+    pub namespaces: u32,
+}
+
+impl Identity {
+    pub fn parse(data: &[u8]) -> Identity {
+        let string = |bytes: &[u8]| String::from(String::from_utf8_lossy(bytes).trim());
+        Identity {
+            vendor: data[0] as u16 | (data[1] as u16) << 8,
+            serial: string(&data[4..24]),
+            model: string(&data[24..64]),
+            firmware: string(&data[64..72]),
+            mdts: data[77],
+            namespaces: (0..4).fold(0, |nn, i| nn | (data[516 + i] as u32) << (i * 8)),
+        }
+    }
+}
+
+pub struct Controller<T> where T: Io<Item = u32>, T::Range: From<u16> {
+    io: T,
+    /// Bytes between doorbells
+    doorbell_stride: u16,
+    admin: Queue,
+    io_queue: Option<Queue>,
+    /// MSI-X vector of the I/O completion queue, if it interrupts
+    vector: Option<u16>,
+    bounce: Dma,
+    prp_list: Dma,
+    max_transfer: usize,
+    identity: Identity,
+}
+
+impl<T, R> Controller<T> where T: Io<Item = u32, Range=R>, R: From<u16> {
+    /// Reset and enable the controller whose registers are `io`, identify it, and create the I/O
+    /// queue pair
+    ///
+    /// The I/O completion queue interrupts on MSI-X `vector` if one is given, which must already
+    /// be enabled along with the rest of MSI-X, otherwise it is only polled. Pin interrupts are
+    /// masked. `translation` is passed to `Dma::new`.
+    pub unsafe fn new(mut io: T, translation: &Translation, vector: Option<u16>) -> Result<Controller<T>, Error> {
+        let cap = io.read(R::from(CAP)) as u64 | (io.read(R::from(CAP_HIGH)) as u64) << 32;
+        let max_entries = (cap & 0xFFFF) as u16 + 1;
+        let stride = 4 << ((cap >> 32) & 0xF);
+        let mps_min = (cap >> 48) & 0xF;
+        if (cap >> 32) as u32 & CAP_CSS_NVM == 0 || mps_min != 0 {
+            return Err(Error::Unsupported);
+        }
+        let alloc = |size, align| Dma::new(translation, size, align).ok_or(Error::NoMemory);
+        let mut controller = Controller {
+            io: io,
+            doorbell_stride: stride as u16,
+            admin: Queue::new(0, ADMIN_DEPTH.min(max_entries), translation).ok_or(Error::NoMemory)?,
+            io_queue: None,
+            vector: vector,
+            bounce: alloc(BOUNCE_SIZE, PAGE_SIZE)?,
+            prp_list: alloc(PAGE_SIZE, PAGE_SIZE)?,
+            max_transfer: BOUNCE_SIZE,
+            identity: Identity::parse(&[0; IDENTIFY_SIZE]),
+        };
+        // Every page of the bounce buffer after the first, for transfers of more than 2 pages
+        let bounce = controller.bounce.paddr();
+        for page in 1..BOUNCE_SIZE / PAGE_SIZE {
+            let entry = bounce + (page * PAGE_SIZE) as u64;
+            let list = controller.prp_list.as_mut_slice();
+            for i in 0..8 {
+                list[(page - 1) * 8 + i] = (entry >> (i * 8)) as u8;
+            }
+        }
+        controller.disable()?;
+        let depth = controller.admin.depth() as u32 - 1;
+        controller.write(AQA, depth << 16 | depth);
+        let (asq, acq) = (controller.admin.submission_paddr(), controller.admin.completion_paddr());
+        controller.write(ASQ, asq as u32);
+        controller.write(ASQ_HIGH, (asq >> 32) as u32);
+        controller.write(ACQ, acq as u32);
+        controller.write(ACQ_HIGH, (acq >> 32) as u32);
+        if vector.is_none() {
+            controller.write(INTMS, 0xFFFF_FFFF);
+        }
+        controller.write(CC, CQ_ENTRY_SHIFT << CC_IOCQES_SHIFT | SQ_ENTRY_SHIFT << CC_IOSQES_SHIFT | CC_EN);
+        if !wait(POLL_LIMIT, || controller.read(CSTS), |csts| csts & (CSTS_RDY | CSTS_CFS) != 0) {
+            return Err(Error::Timeout);
+        }
+        if controller.read(CSTS) & CSTS_CFS != 0 {
+            return Err(Error::Fatal);
+        }
+        controller.identify(IDENTIFY_CONTROLLER, 0)?;
+        controller.identity = Identity::parse(&controller.bounce.as_slice()[..IDENTIFY_SIZE]);
+        // Limits beyond the bounce buffer do not matter, and may not fit in a usize
+        let mdts = controller.identity.mdts as usize;
+        if mdts != 0 && PAGE_SIZE << mdts.min(16) < BOUNCE_SIZE {
+            controller.max_transfer = PAGE_SIZE << mdts;
+        }
+        controller.create_io_queue(translation, max_entries)?;
+        Ok(controller)
+    }
+    unsafe fn read(&mut self, reg: u16) -> u32 {
+        self.io.read(R::from(reg))
+    }
+    unsafe fn write(&mut self, reg: u16, value: u32) {
+        self.io.write(R::from(reg), value)
+    }
+    /// Clear the enable bit and wait for the controller to stop
+    unsafe fn disable(&mut self) -> Result<(), Error> {
+        let cc = self.read(CC);
+        self.write(CC, cc & !CC_EN);
+        if !wait(POLL_LIMIT, || self.read(CSTS), |csts| csts & CSTS_RDY == 0) {
+            return Err(Error::Timeout);
+        }
+        Ok(())
+    }
+    unsafe fn create_io_queue(&mut self, translation: &Translation, max_entries: u16) -> Result<(), Error> {
+        let requested = (IO_QUEUE - 1) as u32;
+        self.admin_command(Command::new(ADMIN_SET_FEATURES)
+            .dword(10, FEATURE_NUMBER_OF_QUEUES)
+            .dword(11, requested << 16 | requested))?;
+        let queue = Queue::new(IO_QUEUE, IO_DEPTH.min(max_entries), translation).ok_or(Error::NoMemory)?;
+        let size = (queue.depth() as u32 - 1) << 16 | IO_QUEUE as u32;
+        let interrupts = match self.vector {
+            Some(vector) => (vector as u32) << 16 | QUEUE_INTERRUPTS,
+            None => 0,
+        };
+        self.admin_command(Command::new(ADMIN_CREATE_CQ)
+            .prp(queue.completion_paddr(), 0)
+            .dword(10, size)
+            .dword(11, interrupts | QUEUE_CONTIGUOUS))?;
+        self.admin_command(Command::new(ADMIN_CREATE_SQ)
+            .prp(queue.submission_paddr(), 0)
+            .dword(10, size)
+            .dword(11, (IO_QUEUE as u32) << 16 | QUEUE_CONTIGUOUS))?;
+        self.io_queue = Some(queue);
+        Ok(())
+    }
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+    /// Largest transfer a single command makes
+    pub fn max_transfer(&self) -> usize {
+        self.max_transfer
+    }
+    pub unsafe fn version(&mut self) -> (u16, u8) {
+        let vs = self.read(VS);
+        ((vs >> 16) as u16, (vs >> 8) as u8)
+    }
+    /// Identify data left in the bounce buffer by the last `identify`
+    pub fn identify_data(&self) -> &[u8] {
+        &self.bounce.as_slice()[..IDENTIFY_SIZE]
+    }
+    /// Run an Identify command, leaving its data in the bounce buffer
+    pub unsafe fn identify(&mut self, cns: u32, nsid: u32) -> Result<(), Error> {
+        let bounce = self.bounce.paddr();
+        self.admin_command(Command::new(ADMIN_IDENTIFY).namespace(nsid).prp(bounce, 0).dword(10, cns))?;
+        Ok(())
+    }
+    /// IDs of the active namespaces
+    pub unsafe fn active_namespaces(&mut self) -> Result<Vec<u32>, Error> {
+        self.identify(IDENTIFY_ACTIVE_NAMESPACES, 0)?;
+        Ok(self.identify_data().chunks(4)
+            .map(|id| (0..4).fold(0, |nsid, i| nsid | (id[i] as u32) << (i * 8)))
+            .take_while(|&nsid| nsid != 0)
+            .collect())
+    }
+    fn doorbell(&self, queue: u16, completion: bool) -> u16 {
+        DOORBELLS + (2 * queue + completion as u16) * self.doorbell_stride
+    }
+    fn queue(&mut self, admin: bool) -> &mut Queue {
+        if admin {
+            &mut self.admin
+        } else {
+            self.io_queue.as_mut().expect("NVMe I/O queue used before it was created")
+        }
+    }
+    /// Take completions off a queue and tell the controller how far it has been read
+    unsafe fn reap(&mut self, admin: bool) -> bool {
+        let (id, head) = {
+            let queue = self.queue(admin);
+            (queue.id(), queue.reap())
+        };
+        match head {
+            Some(head) => {
+                let doorbell = self.doorbell(id, true);
+                self.write(doorbell, head as u32);
+                true
+            },
+            None => false,
+        }
+    }
+    /// Take completions off every queue, from an MSI-X interrupt handler
+    ///
+    /// Returns whether there were any, which the waiting command will pick up.
+    pub unsafe fn interrupt(&mut self) -> bool {
+        let admin = self.reap(true);
+        let io = self.io_queue.is_some() && self.reap(false);
+        admin || io
+    }
+    unsafe fn command(&mut self, admin: bool, command: Command) -> Result<Completion, Error> {
+        let submitted = {
+            let queue = self.queue(admin);
+            queue.submit(command).map(|(cid, tail)| (queue.id(), cid, tail))
+        };
+        // Commands are waited on one at a time, so the queue only fills if the controller stops
+        let (id, cid, tail) = submitted.ok_or(Error::CommandTimeout)?;
+        let doorbell = self.doorbell(id, false);
+        self.write(doorbell, tail as u32);
+        for _ in 0..POLL_LIMIT {
+            if let Some(completion) = self.queue(admin).take(cid) {
+                return if completion.success() { Ok(completion) } else { Err(Error::Status(completion.status)) };
+            }
+            self.reap(admin);
+        }
+        Err(Error::CommandTimeout)
+    }
+    pub unsafe fn admin_command(&mut self, command: Command) -> Result<Completion, Error> {
+        self.command(true, command)
+    }
+    pub unsafe fn io_command(&mut self, command: Command) -> Result<Completion, Error> {
+        self.command(false, command)
+    }
+    /// PRP entries for the first `len` bytes of the bounce buffer
+    fn prp(&self, len: usize) -> (u64, u64) {
+        let bounce = self.bounce.paddr();
+        if len <= PAGE_SIZE {
+            (bounce, 0)
+        } else if len <= 2 * PAGE_SIZE {
+            (bounce, bounce + PAGE_SIZE as u64)
+        } else {
+            (bounce, self.prp_list.paddr())
+        }
+    }
+    /// Read or write `len` bytes of the bounce buffer, as `count` blocks from `lba`
+    unsafe fn transfer(&mut self, nsid: u32, lba: u64, count: u16, len: usize, write: bool) -> Result<(), Error> {
+        assert!(len <= self.max_transfer && count > 0, "NVMe transfer of {} bytes", len);
+        let (prp1, prp2) = self.prp(len);
+        self.io_command(Command::new(if write { IO_WRITE } else { IO_READ })
+            .namespace(nsid)
+            .prp(prp1, prp2)
+            .dword(10, lba as u32)
+            .dword(11, (lba >> 32) as u32)
+            .dword(12, count as u32 - 1))?;
+        Ok(())
+    }
+    /// Read blocks of `block_size` bytes from a namespace into `buffer`
+    pub unsafe fn read_blocks(&mut self, nsid: u32, lba: u64, block_size: usize, buffer: &mut [u8]) -> Result<(), Error> {
+        let chunk_size = self.max_transfer;
+        for (index, chunk) in buffer.chunks_mut(chunk_size).enumerate() {
+            let offset = (index * chunk_size / block_size) as u64;
+            self.transfer(nsid, lba + offset, (chunk.len() / block_size) as u16, chunk.len(), false)?;
+            chunk.copy_from_slice(&self.bounce.as_slice()[..chunk.len()]);
+        }
+        Ok(())
+    }
+    /// Write blocks of `block_size` bytes to a namespace from `buffer`
+    pub unsafe fn write_blocks(&mut self, nsid: u32, lba: u64, block_size: usize, buffer: &[u8]) -> Result<(), Error> {
+        let chunk_size = self.max_transfer;
+        for (index, chunk) in buffer.chunks(chunk_size).enumerate() {
+            let offset = (index * chunk_size / block_size) as u64;
+            self.bounce.as_mut_slice()[..chunk.len()].copy_from_slice(chunk);
+            self.transfer(nsid, lba + offset, (chunk.len() / block_size) as u16, chunk.len(), true)?;
+        }
+        Ok(())
+    }
+    pub unsafe fn flush(&mut self, nsid: u32) -> Result<(), Error> {
+        self.io_command(Command::new(IO_FLUSH).namespace(nsid))?;
+        Ok(())
+    }
+    /// Tell the controller it is about to lose power, then disable it
+    pub unsafe fn shutdown(&mut self) -> Result<(), Error> {
+        let cc = self.read(CC);
+        self.write(CC, cc | CC_SHN_NORMAL);
+        if !wait(POLL_LIMIT, || self.read(CSTS), |csts| csts & CSTS_SHST_MASK == CSTS_SHST_COMPLETE) {
+            return Err(Error::Timeout);
+        }
+        self.disable()
+    }
+}
+
+impl<T> Drop for Controller<T> where T: Io<Item = u32>, T::Range: From<u16> {
+    fn drop(&mut self) {
+        // Stop the controller before the queues it has been given are freed
+        let _ = unsafe {self.shutdown()};
+    }
+}
@@ -0,0 +1,163 @@
+//! NVMe controllers
+//!
+//! Each controller gets an admin and a single I/O queue pair, and every active namespace is
+//! registered as a block device. Namespaces are named by the controller and their ID, so `nvme0n1`
+//! is namespace 1 of the first controller. Completions are polled. With MSI-X, both completion
+//! queues also interrupt, and the handler takes off any completions that nobody was waiting for.
+
+use alloc::rc::{Rc, Weak};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use block;
+use bus::{self, Device};
+use decls::Match;
+use drivers::io::MemIO;
+use drivers::pci::{msix, Address, Command, Function, MsiX};
+use drivers::pci::capability::MSIX;
+use irq;
+use state::STATE;
+
+pub mod controller;
+pub mod namespace;
+pub mod queue;
+
+pub use self::controller::{Controller, Error};
+pub use self::namespace::Namespace;
+
+/// BAR0 and BAR1 together hold the controller registers
+const BAR: u8 = 0;
+const CLASS_STORAGE: u8 = 0x01;
+const SUBCLASS_NVM: u8 = 0x08;
+const PROG_IF_NVME: u8 = 0x02;
+
+/// MSI-X table entries of the admin and I/O completion queues
+const ADMIN_ENTRY: u16 = 0;
+const IO_ENTRY: u16 = 1;
+
+/// Namespaces registered from each controller, by the function it is
+static mut DISKS: Option<Vec<(Address, String)>> = None;
+/// Controllers using MSI-X, which the namespaces keep alive
+static mut CONTROLLERS: Option<Vec<(Address, Weak<RefCell<Controller<MemIO<u32>>>>)>> = None;
+
+/// Lowest controller number that no registered namespace is using
+fn controller_number() -> usize {
+    let disks = unsafe {DISKS.get_or_insert_with(Vec::new)};
+    (0..).find(|n| {
+        let prefix = format!("nvme{}n", n);
+        !disks.iter().any(|disk| disk.1.starts_with(&prefix))
+    }).unwrap()
+}
+
+/// Handler of `irq::NVME_VECTOR`, which every controller shares
+fn interrupt() {
+    for &(_, ref controller) in unsafe {CONTROLLERS.get_or_insert_with(Vec::new)}.iter() {
+        // A controller in the middle of a command is already reading its queues
+        if let Some(controller) = controller.upgrade() {
+            if let Ok(mut controller) = controller.try_borrow_mut() {
+                unsafe {controller.interrupt()};
+            }
+        }
+    }
+}
+
+/// Program the MSI-X table and enable MSI-X, with every entry still masked
+///
+/// Returns the table along with the entry for the I/O queue.
+fn setup_msix(function: &Function) -> Option<(msix::Table<MemIO<u32>>, u16)> {
+    let capability = function.capability(MSIX)?;
+    let mut config = bus::pci::config(function);
+    let msix = unsafe {MsiX::read(&mut config, capability)};
+    let table = bus::pci::map_bar(function, msix.table_bar)?;
+    let mut table = msix::Table::new(unsafe {MemIO::<u32>::new(table + msix.table_offset as usize)}, msix.size);
+    // The admin queue always interrupts on the first entry, so the I/O queue shares it if alone
+    let io_entry = if msix.size > IO_ENTRY { IO_ENTRY } else { ADMIN_ENTRY };
+    let message = msix::message(unsafe {irq::local_apic().id()} as u8, irq::NVME_VECTOR);
+    unsafe {
+        table.mask_all();
+        table.set(ADMIN_ENTRY, message);
+        table.set(io_entry, message);
+        msix.enable(&mut config);
+    }
+    Some((table, io_entry))
+}
+
+fn probe(device: &Device) -> bool {
+    let function = match *device {
+        Device::Pci(function) => function,
+        _ => return false,
+    };
+    if function.prog_if != PROG_IF_NVME {
+        return false;
+    }
+    let registers = match bus::pci::map_bar(function, BAR) {
+        Some(registers) => registers,
+        None => {
+            print!(Error, "NVMe {} has no register BAR", function.address);
+            return false;
+        },
+    };
+    unsafe {bus::pci::config(function).enable(Command::MEMORY | Command::BUS_MASTER)};
+    let msix = setup_msix(function);
+    let vector = msix.as_ref().map(|&(_, io_entry)| io_entry);
+    let controller = match unsafe {Controller::new(MemIO::<u32>::new(registers), &STATE.kernel_as, vector)} {
+        Ok(controller) => controller,
+        Err(error) => {
+            print!(Error, "Failed to start NVMe {}: {:?}", function.address, error);
+            return false;
+        },
+    };
+    let controller = Rc::new(RefCell::new(controller));
+    if let Some((mut table, io_entry)) = msix {
+        unsafe {CONTROLLERS.get_or_insert_with(Vec::new)}.push((function.address, Rc::downgrade(&controller)));
+        irq::register(irq::NVME_VECTOR, interrupt);
+        unsafe {
+            table.unmask(ADMIN_ENTRY);
+            table.unmask(io_entry);
+        }
+    }
+    let namespaces = {
+        let mut controller = controller.borrow_mut();
+        let version = unsafe {controller.version()};
+        {
+            let identity = controller.identity();
+            print!(Info, "NVMe {} version {}.{}: {} serial {} firmware {}", function.address, version.0, version.1,
+                identity.model, identity.serial, identity.firmware);
+        }
+        unsafe {controller.active_namespaces()}
+    };
+    let namespaces = match namespaces {
+        Ok(namespaces) => namespaces,
+        Err(error) => {
+            print!(Error, "Failed to list namespaces of NVMe {}: {:?}", function.address, error);
+            return false;
+        },
+    };
+    let number = controller_number();
+    let disks = unsafe {DISKS.get_or_insert_with(Vec::new)};
+    for id in namespaces {
+        match unsafe {Namespace::new(controller.clone(), id)} {
+            Ok(namespace) => {
+                let name = format!("nvme{}n{}", number, id);
+                if block::register_as(name.clone(), box namespace) {
+                    disks.push((function.address, name));
+                }
+            },
+            Err(error) => print!(Error, "Failed to use NVMe {} namespace {}: {:?}", function.address, id, error),
+        }
+    }
+    true
+}
+
+fn remove(device: &Device) {
+    if let Device::Pci(function) = *device {
+        let disks = unsafe {DISKS.get_or_insert_with(Vec::new)};
+        while let Some(index) = disks.iter().position(|disk| disk.0 == function.address) {
+            let (_, name) = disks.remove(index);
+            block::unregister(&name);
+        }
+        unsafe {CONTROLLERS.get_or_insert_with(Vec::new)}.retain(|controller| controller.0 != function.address);
+    }
+}
+
+make_driver_decl!("nvme", &[Match::pci_class(CLASS_STORAGE, SUBCLASS_NVM)], probe, remove, NVME_DRIVER);
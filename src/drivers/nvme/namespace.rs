@@ -0,0 +1,88 @@
+//! NVMe namespaces as block devices
+//!
+//! Every namespace of a controller goes through the same I/O queue, so each holds a reference
+//! to the controller rather than owning it. The controller is shut down once the last of its
+//! namespaces is dropped.
+
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use block::{self, BlockDevice};
+use drivers::io::Io;
+use super::controller::{self, Controller, Error};
+
+/// What Identify Namespace reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Identity {
+    /// Size in logical blocks
+    pub blocks: u64,
+    pub block_size: usize,
+    /// Metadata bytes kept with each block, which are not supported
+    pub metadata: u16,
+}
+
+impl Identity {
+    pub fn parse(data: &[u8]) -> Identity {
+        let u64_at = |offset: usize| (0..8).fold(0, |value, i| value | (data[offset + i] as u64) << (i * 8));
+        let format = (data[26] & 0xF) as usize;
+        let entry = 128 + format * 4;
+        Identity {
+            blocks: u64_at(0),
+            block_size: 1usize.checked_shl(data[entry + 2] as u32).unwrap_or(0),
+            metadata: data[entry] as u16 | (data[entry + 1] as u16) << 8,
+        }
+    }
+}
+
+pub struct Namespace<T> where T: Io<Item = u32>, T::Range: From<u16> {
+    controller: Rc<RefCell<Controller<T>>>,
+    id: u32,
+    identity: Identity,
+}
+
+impl<T, R> Namespace<T> where T: Io<Item = u32, Range=R>, R: From<u16> {
+    /// Identify namespace `id` of a controller
+    ///
+    /// Fails with `Error::Unsupported` for formats with metadata, or blocks that do not fit the
+    /// controller's largest transfer.
+    pub unsafe fn new(controller: Rc<RefCell<Controller<T>>>, id: u32) -> Result<Namespace<T>, Error> {
+        let identity = {
+            let mut controller = controller.borrow_mut();
+            controller.identify(controller::IDENTIFY_NAMESPACE, id)?;
+            let identity = Identity::parse(controller.identify_data());
+            if identity.metadata != 0 || identity.block_size < 512 || identity.block_size > controller.max_transfer() {
+                return Err(Error::Unsupported);
+            }
+            identity
+        };
+        Ok(Namespace { controller: controller, id: id, identity: identity })
+    }
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+}
+
+impl<T, R> BlockDevice for Namespace<T> where T: Io<Item = u32, Range=R>, R: From<u16> {
+    fn sector_size(&self) -> usize {
+        self.identity.block_size
+    }
+    fn sectors(&self) -> u64 {
+        self.identity.blocks
+    }
+    fn read_only(&self) -> bool {
+        false
+    }
+    unsafe fn read(&mut self, sector: u64, buffer: &mut [u8]) -> Result<(), block::Error> {
+        self.controller.borrow_mut().read_blocks(self.id, sector, self.identity.block_size, buffer)
+            .map_err(|_| block::Error::Io)
+    }
+    unsafe fn write(&mut self, sector: u64, buffer: &[u8]) -> Result<(), block::Error> {
+        self.controller.borrow_mut().write_blocks(self.id, sector, self.identity.block_size, buffer)
+            .map_err(|_| block::Error::Io)
+    }
+    unsafe fn flush(&mut self) -> Result<(), block::Error> {
+        self.controller.borrow_mut().flush(self.id).map_err(|_| block::Error::Io)
+    }
+}
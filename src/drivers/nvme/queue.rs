@@ -0,0 +1,174 @@
+//! Submission and completion queue pairs
+//!
+//! Commands are 64 byte entries written at the tail of a submission queue, and the controller
+//! posts a 16 byte completion for each to the completion queue paired with it. Every completion
+//! carries a phase bit, which the controller inverts each time it wraps around the queue, so new
+//! completions are found from memory alone. Completions record how far the controller has
+//! consumed the submission queue, which is what stops the driver overwriting commands it has
+//! not yet fetched.
+
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+use drivers::dma::Dma;
+use vspace::Translation;
+
+pub const COMMAND_SIZE: usize = 64;
+pub const COMPLETION_SIZE: usize = 16;
+/// Queues are page aligned, in the controller's 4KiB pages
+const QUEUE_ALIGN: usize = 4096;
+
+/// A submission queue entry, as its 16 dwords
+///
+/// The command identifier in dword 0 is filled in when the command is submitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Command {
+    pub dwords: [u32; 16],
+}
+
+impl Command {
+    pub fn new(opcode: u8) -> Command {
+        let mut dwords = [0; 16];
+        dwords[0] = opcode as u32;
+        Command { dwords: dwords }
+    }
+    pub fn opcode(&self) -> u8 {
+        self.dwords[0] as u8
+    }
+    pub fn namespace(mut self, nsid: u32) -> Command {
+        self.dwords[1] = nsid;
+        self
+    }
+    /// Set the two physical region page entries describing the data
+    pub fn prp(mut self, prp1: u64, prp2: u64) -> Command {
+        self.dwords[6] = prp1 as u32;
+        self.dwords[7] = (prp1 >> 32) as u32;
+        self.dwords[8] = prp2 as u32;
+        self.dwords[9] = (prp2 >> 32) as u32;
+        self
+    }
+    /// Set one of the command specific dwords, 10 to 15
+    pub fn dword(mut self, index: usize, value: u32) -> Command {
+        assert!(index >= 10 && index < 16, "Command dword {} is not command specific", index);
+        self.dwords[index] = value;
+        self
+    }
+}
+
+/// A completion queue entry, without its phase bit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Completion {
+    /// Command specific result from dword 0
+    pub result: u32,
+    pub sq_head: u16,
+    pub sq_id: u16,
+    pub id: u16,
+    /// Status code in the low byte and status code type above it, 0 for success
+    pub status: u16,
+}
+
+impl Completion {
+    pub fn success(&self) -> bool {
+        self.status == 0
+    }
+}
+
+pub struct Queue {
+    id: u16,
+    depth: u16,
+    submissions: Dma,
+    completions: Dma,
+    tail: u16,
+    head: u16,
+    /// Submission queue head, as last reported by the controller
+    sq_head: u16,
+    phase: bool,
+    next_id: u16,
+    /// Completions taken from the queue that nobody has asked for yet
+    done: Vec<Completion>,
+}
+
+impl Queue {
+    /// Allocate a pair with `depth` entries in each queue
+    pub fn new(id: u16, depth: u16, translation: &Translation) -> Option<Queue> {
+        assert!(depth >= 2, "NVMe queues need at least 2 entries");
+        Some(Queue {
+            id: id,
+            depth: depth,
+            submissions: Dma::new(translation, depth as usize * COMMAND_SIZE, QUEUE_ALIGN)?,
+            completions: Dma::new(translation, depth as usize * COMPLETION_SIZE, QUEUE_ALIGN)?,
+            tail: 0,
+            head: 0,
+            sq_head: 0,
+            phase: true,
+            next_id: 0,
+            done: Vec::new(),
+        })
+    }
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+    pub fn depth(&self) -> u16 {
+        self.depth
+    }
+    pub fn submission_paddr(&self) -> u64 {
+        self.submissions.paddr()
+    }
+    pub fn completion_paddr(&self) -> u64 {
+        self.completions.paddr()
+    }
+    /// Whether the submission queue has no room for another command
+    pub fn full(&self) -> bool {
+        (self.tail + 1) % self.depth == self.sq_head
+    }
+    /// Write a command at the tail, returning its identifier and the new tail for the doorbell
+    pub unsafe fn submit(&mut self, mut command: Command) -> Option<(u16, u16)> {
+        if self.full() {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        command.dwords[0] = command.dwords[0] & 0xFFFF | (id as u32) << 16;
+        let entry = self.submissions.vaddr().offset(self.tail as isize * COMMAND_SIZE as isize) as *mut u32;
+        for (index, &dword) in command.dwords.iter().enumerate() {
+            ptr::write_volatile(entry.offset(index as isize), dword);
+        }
+        fence(Ordering::SeqCst);
+        self.tail = (self.tail + 1) % self.depth;
+        Some((id, self.tail))
+    }
+    /// Take every new completion, returning the new head for the doorbell if there were any
+    pub unsafe fn reap(&mut self) -> Option<u16> {
+        let mut found = false;
+        loop {
+            let entry = self.completions.vaddr().offset(self.head as isize * COMPLETION_SIZE as isize) as *const u32;
+            let dword3 = ptr::read_volatile(entry.offset(3));
+            if (dword3 & 1 << 16 != 0) != self.phase {
+                break;
+            }
+            fence(Ordering::SeqCst);
+            let dword2 = ptr::read_volatile(entry.offset(2));
+            let completion = Completion {
+                result: ptr::read_volatile(entry),
+                sq_head: dword2 as u16,
+                sq_id: (dword2 >> 16) as u16,
+                id: dword3 as u16,
+                status: (dword3 >> 17) as u16 & 0x7FF,
+            };
+            self.sq_head = completion.sq_head % self.depth;
+            self.done.push(completion);
+            found = true;
+            self.head += 1;
+            if self.head == self.depth {
+                self.head = 0;
+                self.phase = !self.phase;
+            }
+        }
+        if found { Some(self.head) } else { None }
+    }
+    /// Remove the completion of command `id`, if it has been reaped
+    pub fn take(&mut self, id: u16) -> Option<Completion> {
+        let index = self.done.iter().position(|completion| completion.id == id)?;
+        Some(self.done.remove(index))
+    }
+}
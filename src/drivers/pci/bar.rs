@@ -0,0 +1,115 @@
+//! Base address registers
+//!
+//! A BAR is sized by writing all 1s to it and reading back which bits stuck, as the device
+//! hardwires the bits below its size to 0. Decoding should be disabled while this is done, so
+//! the transient address is never responded to.
+
+use super::{Config, BAR0};
+use core::fmt;
+
+const BAR_IO: u32 = 1 << 0;
+const BAR_TYPE_MASK: u32 = 0b110;
+const BAR_TYPE_64: u32 = 0b100;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+const BAR_IO_MASK: u32 = !0x3;
+const BAR_MEMORY_MASK: u32 = !0xF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+        /// Takes two BAR slots, and can be placed above 4GB
+        wide: bool,
+    },
+    Io {
+        port: u32,
+        size: u32,
+    },
+}
+
+impl Bar {
+    /// Number of BAR slots taken
+    pub fn slots(&self) -> u8 {
+        match *self {
+            Bar::Memory { wide: true, .. } => 2,
+            _ => 1,
+        }
+    }
+    pub fn memory(&self) -> Option<(u64, u64)> {
+        match *self {
+            Bar::Memory { address, size, .. } => Some((address, size)),
+            Bar::Io { .. } => None,
+        }
+    }
+}
+
+/// Display a size in the largest unit it is a whole number of
+struct Size(u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let units = [(1 << 30, "G"), (1 << 20, "M"), (1 << 10, "K")];
+        match units.iter().find(|&&(unit, _)| self.0 >= unit && self.0 % unit == 0) {
+            Some(&(unit, suffix)) => write!(f, "{}{}", self.0 / unit, suffix),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bar::Memory { address, size, prefetchable, wide } => {
+                write!(f, "mem {:#x} [{}]", address, Size(size))?;
+                if wide {
+                    write!(f, " 64-bit")?;
+                }
+                if prefetchable {
+                    write!(f, " prefetchable")?;
+                }
+                Ok(())
+            },
+            Bar::Io { port, size } => write!(f, "io {:#x} [{}]", port, Size(size as u64)),
+        }
+    }
+}
+
+/// Write all 1s and read back what stuck, restoring the original value
+unsafe fn size_mask(config: &mut Config, offset: u16) -> (u32, u32) {
+    let original = config.read_u32(offset);
+    config.write_u32(offset, !0);
+    let mask = config.read_u32(offset);
+    config.write_u32(offset, original);
+    (original, mask)
+}
+
+/// Decode and size BAR `index`
+///
+/// Returns nothing for an unimplemented BAR. Decoding must already be disabled.
+pub unsafe fn probe(config: &mut Config, index: u8) -> Option<Bar> {
+    let offset = BAR0 + index as u16 * 4;
+    let (original, mask) = size_mask(config, offset);
+    if original & BAR_IO != 0 {
+        let mask = mask & BAR_IO_MASK;
+        if mask == 0 {
+            return None;
+        }
+        // Devices may only implement the low 16 bits of an I/O BAR
+        let mask = if mask & 0xFFFF_0000 == 0 { mask | 0xFFFF_0000 } else { mask };
+        return Some(Bar::Io { port: original & BAR_IO_MASK, size: (!mask).wrapping_add(1) });
+    }
+    let wide = original & BAR_TYPE_MASK == BAR_TYPE_64;
+    let (original_high, mask_high) = if wide { size_mask(config, offset + 4) } else { (0, !0) };
+    let mask = (mask_high as u64) << 32 | (mask & BAR_MEMORY_MASK) as u64;
+    if mask & BAR_MEMORY_MASK as u64 == 0 && (!wide || mask_high == 0) {
+        return None;
+    }
+    Some(Bar::Memory {
+        address: (original_high as u64) << 32 | (original & BAR_MEMORY_MASK) as u64,
+        size: (!mask).wrapping_add(1),
+        prefetchable: original & BAR_PREFETCHABLE != 0,
+        wide: wide,
+    })
+}
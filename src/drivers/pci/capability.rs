@@ -0,0 +1,42 @@
+//! Capability lists
+//!
+//! Capabilities form a linked list through configuration space, starting from the capabilities
+//! pointer, each entry being an ID byte followed by the offset of the next.
+
+use super::{Config, STATUS, CAPABILITIES_PTR};
+use alloc::vec::Vec;
+
+pub const POWER_MANAGEMENT: u8 = 0x01;
+pub const MSI: u8 = 0x05;
+pub const VENDOR_SPECIFIC: u8 = 0x09;
+pub const PCI_EXPRESS: u8 = 0x10;
+pub const MSIX: u8 = 0x11;
+
+/// Status bit indicating that the capabilities pointer is valid
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+/// Most entries that fit in the legacy configuration space after the header, used to stop on a
+/// malformed list that loops
+const MAX_CAPABILITIES: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability {
+    pub id: u8,
+    /// Offset in configuration space of the ID byte
+    pub offset: u8,
+}
+
+/// Walk the capability list of a function
+pub unsafe fn list(config: &mut Config) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    if config.read_u16(STATUS) & STATUS_CAPABILITIES == 0 {
+        return capabilities;
+    }
+    let mut next = config.read_u8(CAPABILITIES_PTR) & !0x3;
+    while next != 0 && capabilities.len() < MAX_CAPABILITIES {
+        let header = config.read_u16(next as u16);
+        capabilities.push(Capability { id: header as u8, offset: next });
+        next = (header >> 8) as u8 & !0x3;
+    }
+    capabilities
+}
@@ -0,0 +1,162 @@
+//! Configuration space access mechanisms
+
+use super::{Address, Command, COMMAND};
+use super::super::io::Io;
+
+/// Base of the legacy configuration address and data ports
+pub const LEGACY_PORT: u16 = 0xCF8;
+
+const CONFIG_ADDRESS: u8 = 0;
+const CONFIG_DATA: u8 = 4;
+/// Configuration space access enable in the address register
+const ADDRESS_ENABLE: u32 = 1 << 31;
+
+/// Size of the legacy configuration space of a function
+pub const LEGACY_SIZE: u16 = 0x100;
+/// Size of the extended configuration space of a function
+pub const EXTENDED_SIZE: u16 = 0x1000;
+
+/// Means of reading and writing the configuration space of any function
+///
+/// Accesses are always whole dwords. Reads of functions, or offsets, that cannot be reached
+/// return all 1s, as with a function that is not present, and writes to them are dropped.
+pub trait ConfigAccess {
+    /// Read the dword at `offset`, which must be 4 byte aligned
+    unsafe fn read(&mut self, address: Address, offset: u16) -> u32;
+    /// Write the dword at `offset`, which must be 4 byte aligned
+    unsafe fn write(&mut self, address: Address, offset: u16, value: u32);
+}
+
+/// Configuration mechanism #1, through the address and data ports
+///
+/// Only reaches segment 0 and the first 256 bytes of each function.
+pub struct Legacy<T: Io<Item = u32>> {
+    io: T,
+}
+
+impl<T, R> Legacy<T> where T: Io<Item = u32, Range=R>, R: From<u8> {
+    pub fn new(io: T) -> Legacy<T> {
+        Legacy { io: io }
+    }
+    fn reachable(address: Address, offset: u16) -> bool {
+        address.segment == 0 && offset < LEGACY_SIZE
+    }
+    unsafe fn select(&mut self, address: Address, offset: u16) {
+        let value = ADDRESS_ENABLE | (address.bus as u32) << 16 | (address.device as u32) << 11
+            | (address.function as u32) << 8 | offset as u32 & 0xFC;
+        self.io.write(R::from(CONFIG_ADDRESS), value);
+    }
+}
+
+impl<T, R> ConfigAccess for Legacy<T> where T: Io<Item = u32, Range=R>, R: From<u8> {
+    unsafe fn read(&mut self, address: Address, offset: u16) -> u32 {
+        if !Self::reachable(address, offset) {
+            return !0;
+        }
+        self.select(address, offset);
+        self.io.read(R::from(CONFIG_DATA))
+    }
+    unsafe fn write(&mut self, address: Address, offset: u16, value: u32) {
+        if !Self::reachable(address, offset) {
+            return;
+        }
+        self.select(address, offset);
+        self.io.write(R::from(CONFIG_DATA), value)
+    }
+}
+
+/// PCI Express enhanced configuration access mechanism
+///
+/// The configuration space of every function of a range of buses of one segment is memory
+/// mapped, each function getting 4K.
+pub struct Ecam<T: Io<Item = u32>> {
+    io: T,
+    segment: u16,
+    start_bus: u8,
+    end_bus: u8,
+}
+
+impl<T> Ecam<T> where T: Io<Item = u32, Range=usize> {
+    /// Construct an accessor for buses `start_bus` to `end_bus` inclusive of `segment`
+    ///
+    /// The window `io` starts at the configuration space of `start_bus`
+    pub fn new(io: T, segment: u16, start_bus: u8, end_bus: u8) -> Ecam<T> {
+        Ecam { io: io, segment: segment, start_bus: start_bus, end_bus: end_bus }
+    }
+    fn offset(&self, address: Address, offset: u16) -> Option<usize> {
+        if address.segment != self.segment || address.bus < self.start_bus || address.bus > self.end_bus
+                || offset >= EXTENDED_SIZE {
+            return None;
+        }
+        Some(((address.bus - self.start_bus) as usize) << 20 | (address.device as usize) << 15
+            | (address.function as usize) << 12 | (offset & !3) as usize)
+    }
+}
+
+impl<T> ConfigAccess for Ecam<T> where T: Io<Item = u32, Range=usize> {
+    unsafe fn read(&mut self, address: Address, offset: u16) -> u32 {
+        match self.offset(address, offset) {
+            Some(offset) => self.io.read(offset),
+            None => !0,
+        }
+    }
+    unsafe fn write(&mut self, address: Address, offset: u16, value: u32) {
+        if let Some(offset) = self.offset(address, offset) {
+            self.io.write(offset, value)
+        }
+    }
+}
+
+/// Configuration space of a single function
+pub struct Config<'a> {
+    access: &'a mut ConfigAccess,
+    address: Address,
+}
+
+impl<'a> Config<'a> {
+    pub fn new(access: &'a mut ConfigAccess, address: Address) -> Config<'a> {
+        Config { access: access, address: address }
+    }
+    pub fn address(&self) -> Address {
+        self.address
+    }
+    pub unsafe fn read_u32(&mut self, offset: u16) -> u32 {
+        self.access.read(self.address, offset & !3)
+    }
+    pub unsafe fn read_u16(&mut self, offset: u16) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+    pub unsafe fn read_u8(&mut self, offset: u16) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+    pub unsafe fn write_u32(&mut self, offset: u16, value: u32) {
+        self.access.write(self.address, offset & !3, value)
+    }
+    /// Write part of a dword
+    ///
+    /// The rest of the dword is read and written back, so this must not be used next to bits
+    /// that are cleared by writing 1s to them.
+    pub unsafe fn write_u16(&mut self, offset: u16, value: u16) {
+        let shift = (offset & 2) * 8;
+        let dword = self.read_u32(offset) & !(0xFFFF << shift) | (value as u32) << shift;
+        self.write_u32(offset, dword)
+    }
+    /// Write part of a dword, with the same caveat as `write_u16`
+    pub unsafe fn write_u8(&mut self, offset: u16, value: u8) {
+        let shift = (offset & 3) * 8;
+        let dword = self.read_u32(offset) & !(0xFF << shift) | (value as u32) << shift;
+        self.write_u32(offset, dword)
+    }
+    pub unsafe fn command(&mut self) -> Command {
+        Command::from_bits_truncate(self.read_u16(COMMAND))
+    }
+    pub unsafe fn set_command(&mut self, command: Command) {
+        // The status register shares the dword, and writing 0s leaves its bits alone
+        self.write_u32(COMMAND, command.bits() as u32)
+    }
+    /// Turn on the given command bits, such as to enable decoding or bus mastering
+    pub unsafe fn enable(&mut self, flags: Command) {
+        let command = self.command() | flags;
+        self.set_command(command)
+    }
+}
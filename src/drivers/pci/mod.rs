@@ -0,0 +1,283 @@
+//! PCI configuration space access and enumeration
+//!
+//! Configuration space is reached through a `ConfigAccess`, either the legacy I/O port mechanism
+//! or the memory mapped PCI Express ECAM. Enumeration starts from the host bridge and follows
+//! PCI-to-PCI bridges down to their secondary buses, using the bus numbers the firmware assigned.
+//! Every function found is decoded once, including sizing its BARs and walking its capability
+//! list, and kept in a `Registry` for drivers to match against.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+mod config;
+mod bar;
+pub mod capability;
+pub mod msix;
+
+pub use self::config::{ConfigAccess, Config, Legacy, Ecam, LEGACY_PORT, LEGACY_SIZE, EXTENDED_SIZE};
+pub use self::bar::Bar;
+pub use self::capability::Capability;
+pub use self::msix::MsiX;
+
+pub const VENDOR_ID: u16 = 0x00;
+pub const DEVICE_ID: u16 = 0x02;
+pub const COMMAND: u16 = 0x04;
+pub const STATUS: u16 = 0x06;
+pub const REVISION: u16 = 0x08;
+pub const PROG_IF: u16 = 0x09;
+pub const SUBCLASS: u16 = 0x0A;
+pub const CLASS: u16 = 0x0B;
+pub const HEADER_TYPE: u16 = 0x0E;
+pub const BAR0: u16 = 0x10;
+pub const SECONDARY_BUS: u16 = 0x19;
+pub const SUBSYSTEM_VENDOR_ID: u16 = 0x2C;
+pub const SUBSYSTEM_ID: u16 = 0x2E;
+pub const CAPABILITIES_PTR: u16 = 0x34;
+pub const INTERRUPT_LINE: u16 = 0x3C;
+pub const INTERRUPT_PIN: u16 = 0x3D;
+
+/// Vendor ID read back for a function that is not present
+const VENDOR_NONE: u16 = 0xFFFF;
+
+const HEADER_TYPE_MASK: u8 = 0x7F;
+const HEADER_MULTI_FUNCTION: u8 = 0x80;
+
+pub const HEADER_GENERAL: u8 = 0;
+pub const HEADER_BRIDGE: u8 = 1;
+pub const HEADER_CARDBUS: u8 = 2;
+
+pub const DEVICES_PER_BUS: u8 = 32;
+pub const FUNCTIONS_PER_DEVICE: u8 = 8;
+
+bitflags! {
+    /// Command register
+    pub struct Command: u16 {
+        /// Respond to I/O space accesses
+        const IO = 1 << 0;
+        /// Respond to memory space accesses
+        const MEMORY = 1 << 1;
+        /// Allow the function to perform DMA
+        const BUS_MASTER = 1 << 2;
+        const SPECIAL_CYCLES = 1 << 3;
+        const MEMORY_WRITE_INVALIDATE = 1 << 4;
+        const VGA_PALETTE_SNOOP = 1 << 5;
+        const PARITY_ERROR = 1 << 6;
+        const SERR = 1 << 8;
+        const FAST_BACK_TO_BACK = 1 << 9;
+        /// Stop the function asserting legacy INTx interrupts
+        const INTERRUPT_DISABLE = 1 << 10;
+    }
+}
+
+/// Location of a function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Address {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl Address {
+    pub fn new(segment: u16, bus: u8, device: u8, function: u8) -> Address {
+        assert!(device < DEVICES_PER_BUS && function < FUNCTIONS_PER_DEVICE, "Invalid PCI address");
+        Address { segment: segment, bus: bus, device: device, function: function }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}:{:02x}:{:02x}.{}", self.segment, self.bus, self.device, self.function)
+    }
+}
+
+/// Describe a class code
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x00, 0x01) => "VGA compatible device",
+        (0x00, _) => "Unclassified device",
+        (0x01, 0x00) => "SCSI storage controller",
+        (0x01, 0x01) => "IDE interface",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "Non-volatile memory controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "Display controller",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x07, 0x00) => "Serial controller",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x09, _) => "Input device controller",
+        (0x0C, 0x03) => "USB controller",
+        (0x0C, 0x05) => "SMBus",
+        (0x0C, _) => "Serial bus controller",
+        _ => "Unknown device",
+    }
+}
+
+/// A function found by enumeration
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub address: Address,
+    pub vendor: u16,
+    pub device: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    /// Header layout, without the multi-function flag
+    pub header_type: u8,
+    /// Only meaningful for general devices, 0 otherwise
+    pub subsystem_vendor: u16,
+    pub subsystem: u16,
+    /// INTx pin used, 1 for INTA# through 4 for INTD#, or 0 for none
+    pub interrupt_pin: u8,
+    /// Legacy IRQ the firmware routed the pin to
+    pub interrupt_line: u8,
+    /// Decoded BARs, indexed by slot, with the upper slot of a 64-bit BAR left empty
+    pub bars: [Option<Bar>; 6],
+    pub capabilities: Vec<Capability>,
+    /// Bus behind a PCI-to-PCI bridge
+    pub secondary_bus: Option<u8>,
+}
+
+impl Function {
+    /// Decode the function at `address`, if it is present
+    ///
+    /// Decoding is briefly disabled to size the BARs.
+    pub unsafe fn probe(access: &mut ConfigAccess, address: Address) -> Option<Function> {
+        let mut config = Config::new(access, address);
+        let vendor = config.read_u16(VENDOR_ID);
+        if vendor == VENDOR_NONE {
+            return None;
+        }
+        let header_type = config.read_u8(HEADER_TYPE) & HEADER_TYPE_MASK;
+        let bar_count = match header_type {
+            HEADER_GENERAL => 6,
+            HEADER_BRIDGE => 2,
+            _ => 0,
+        };
+        let mut bars = [None; 6];
+        let command = config.command();
+        config.set_command(command - (Command::IO | Command::MEMORY));
+        let mut index = 0;
+        while index < bar_count {
+            let bar = bar::probe(&mut config, index);
+            bars[index as usize] = bar;
+            index += bar.map_or(1, |bar| bar.slots());
+        }
+        config.set_command(command);
+        let general = header_type == HEADER_GENERAL;
+        Some(Function {
+            address: address,
+            vendor: vendor,
+            device: config.read_u16(DEVICE_ID),
+            class: config.read_u8(CLASS),
+            subclass: config.read_u8(SUBCLASS),
+            prog_if: config.read_u8(PROG_IF),
+            revision: config.read_u8(REVISION),
+            header_type: header_type,
+            subsystem_vendor: if general { config.read_u16(SUBSYSTEM_VENDOR_ID) } else { 0 },
+            subsystem: if general { config.read_u16(SUBSYSTEM_ID) } else { 0 },
+            interrupt_pin: config.read_u8(INTERRUPT_PIN),
+            interrupt_line: config.read_u8(INTERRUPT_LINE),
+            bars: bars,
+            capabilities: capability::list(&mut config),
+            secondary_bus: if header_type == HEADER_BRIDGE { Some(config.read_u8(SECONDARY_BUS)) } else { None },
+        })
+    }
+    /// Access the configuration space of this function
+    pub fn config<'a>(&self, access: &'a mut ConfigAccess) -> Config<'a> {
+        Config::new(access, self.address)
+    }
+    /// First capability with the given ID
+    pub fn capability(&self, id: u8) -> Option<Capability> {
+        self.capabilities.iter().find(|capability| capability.id == id).cloned()
+    }
+    pub fn class_name(&self) -> &'static str {
+        class_name(self.class, self.subclass)
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:04x}:{:04x} [{:02x}{:02x}{:02x}] {}", self.address, self.vendor, self.device,
+            self.class, self.subclass, self.prog_if, self.class_name())
+    }
+}
+
+/// Every function found by enumeration
+pub struct Registry {
+    functions: Vec<Function>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry { functions: Vec::new() }
+    }
+    /// Enumerate every function of a segment reachable from its host bridges
+    ///
+    /// A multi-function host bridge at device 0 of bus 0 is a set of host bridges, with function
+    /// `n` being the bridge to bus `n`.
+    pub unsafe fn scan(&mut self, access: &mut ConfigAccess, segment: u16) {
+        let mut visited = [false; 256];
+        let host = Address::new(segment, 0, 0, 0);
+        let present = Config::new(access, host).read_u16(VENDOR_ID) != VENDOR_NONE;
+        if present && Config::new(access, host).read_u8(HEADER_TYPE) & HEADER_MULTI_FUNCTION != 0 {
+            for function in 0..FUNCTIONS_PER_DEVICE {
+                if Config::new(access, Address::new(segment, 0, 0, function)).read_u16(VENDOR_ID) != VENDOR_NONE {
+                    self.scan_bus(access, segment, function, &mut visited);
+                }
+            }
+        } else {
+            self.scan_bus(access, segment, 0, &mut visited);
+        }
+        self.functions.sort_by_key(|function| function.address);
+    }
+    unsafe fn scan_bus(&mut self, access: &mut ConfigAccess, segment: u16, bus: u8, visited: &mut [bool; 256]) {
+        // Stop on a misconfigured bridge pointing back up the tree
+        if visited[bus as usize] {
+            return;
+        }
+        visited[bus as usize] = true;
+        for device in 0..DEVICES_PER_BUS {
+            self.scan_device(access, Address::new(segment, bus, device, 0), visited);
+        }
+    }
+    unsafe fn scan_device(&mut self, access: &mut ConfigAccess, address: Address, visited: &mut [bool; 256]) {
+        if Config::new(access, address).read_u16(VENDOR_ID) == VENDOR_NONE {
+            return;
+        }
+        let header_type = Config::new(access, address).read_u8(HEADER_TYPE);
+        // Functions other than 0 of a single function device may alias function 0
+        let functions = if header_type & HEADER_MULTI_FUNCTION != 0 { FUNCTIONS_PER_DEVICE } else { 1 };
+        for function in 0..functions {
+            let address = Address { function: function, ..address };
+            if let Some(found) = Function::probe(access, address) {
+                let secondary = found.secondary_bus;
+                self.functions.push(found);
+                if let Some(bus) = secondary {
+                    self.scan_bus(access, address.segment, bus, visited);
+                }
+            }
+        }
+    }
+    pub fn functions(&self) -> &[Function] {
+        &self.functions
+    }
+    pub fn find(&self, address: Address) -> Option<&Function> {
+        self.functions.iter().find(|function| function.address == address)
+    }
+    /// Iterate the functions accepted by `filter`
+    pub fn matching<'r, F>(&'r self, filter: F) -> impl Iterator<Item = &'r Function> + 'r
+            where F: Fn(&Function) -> bool + 'r {
+        self.functions.iter().filter(move |function| filter(function))
+    }
+}
@@ -0,0 +1,122 @@
+//! MSI-X
+//!
+//! The capability says how many vectors a function has, and which BARs hold the vector table
+//! and the pending bit array. Each table entry is a message, the address and data the function
+//! writes to raise the interrupt, along with a mask bit. Enabling MSI-X stops the function using
+//! INTx.
+
+use drivers::io::Io;
+use super::{Capability, Config};
+
+/// Offset of the message control register within the capability
+const CONTROL: u16 = 0x02;
+/// Offset of the table BIR and offset within the capability
+const TABLE: u16 = 0x04;
+/// Offset of the pending bit array BIR and offset within the capability
+const PBA: u16 = 0x08;
+
+const CONTROL_SIZE_MASK: u16 = 0x7FF;
+const CONTROL_FUNCTION_MASK: u16 = 1 << 14;
+const CONTROL_ENABLE: u16 = 1 << 15;
+const BIR_MASK: u32 = 0x7;
+
+/// Size of a table entry
+pub const ENTRY_SIZE: u16 = 16;
+const ENTRY_ADDRESS: u16 = 0x0;
+const ENTRY_ADDRESS_HIGH: u16 = 0x4;
+const ENTRY_DATA: u16 = 0x8;
+const ENTRY_CONTROL: u16 = 0xC;
+const ENTRY_MASKED: u32 = 1 << 0;
+
+/// Base of the address range that messages to local APICs are written to
+const MESSAGE_ADDRESS: u64 = 0xFEE0_0000;
+
+/// Where the MSI-X structures of a function are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiX {
+    pub capability: Capability,
+    /// Number of vectors
+    pub size: u16,
+    pub table_bar: u8,
+    pub table_offset: u32,
+    pub pba_bar: u8,
+    pub pba_offset: u32,
+}
+
+impl MsiX {
+    /// Decode an MSI-X capability
+    pub unsafe fn read(config: &mut Config, capability: Capability) -> MsiX {
+        let base = capability.offset as u16;
+        let table = config.read_u32(base + TABLE);
+        let pba = config.read_u32(base + PBA);
+        MsiX {
+            capability: capability,
+            size: (config.read_u16(base + CONTROL) & CONTROL_SIZE_MASK) + 1,
+            table_bar: (table & BIR_MASK) as u8,
+            table_offset: table & !BIR_MASK,
+            pba_bar: (pba & BIR_MASK) as u8,
+            pba_offset: pba & !BIR_MASK,
+        }
+    }
+    /// Enable MSI-X, with every vector still subject to its own mask bit
+    pub unsafe fn enable(&self, config: &mut Config) {
+        let offset = self.capability.offset as u16 + CONTROL;
+        let control = config.read_u16(offset);
+        config.write_u16(offset, (control | CONTROL_ENABLE) & !CONTROL_FUNCTION_MASK);
+    }
+    /// Disable MSI-X, returning the function to INTx
+    pub unsafe fn disable(&self, config: &mut Config) {
+        let offset = self.capability.offset as u16 + CONTROL;
+        let control = config.read_u16(offset);
+        config.write_u16(offset, control & !CONTROL_ENABLE);
+    }
+}
+
+/// Message that delivers `vector` to the local APIC `apic_id`, edge triggered with fixed delivery
+pub fn message(apic_id: u8, vector: u8) -> (u64, u32) {
+    (MESSAGE_ADDRESS | (apic_id as u64) << 12, vector as u32)
+}
+
+/// The vector table, reached through the BAR it is in
+pub struct Table<T: Io<Item = u32>> {
+    io: T,
+    size: u16,
+}
+
+impl<T, R> Table<T> where T: Io<Item = u32, Range=R>, R: From<u16> {
+    /// Table of `size` entries, with `io` starting at the first
+    pub fn new(io: T, size: u16) -> Table<T> {
+        Table { io: io, size: size }
+    }
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+    unsafe fn write(&mut self, entry: u16, reg: u16, value: u32) {
+        assert!(entry < self.size, "MSI-X entry {} out of range", entry);
+        self.io.write(R::from(entry * ENTRY_SIZE + reg), value)
+    }
+    unsafe fn read(&mut self, entry: u16, reg: u16) -> u32 {
+        assert!(entry < self.size, "MSI-X entry {} out of range", entry);
+        self.io.read(R::from(entry * ENTRY_SIZE + reg))
+    }
+    /// Program the message of an entry, leaving it masked
+    pub unsafe fn set(&mut self, entry: u16, message: (u64, u32)) {
+        self.mask(entry);
+        self.write(entry, ENTRY_ADDRESS, message.0 as u32);
+        self.write(entry, ENTRY_ADDRESS_HIGH, (message.0 >> 32) as u32);
+        self.write(entry, ENTRY_DATA, message.1);
+    }
+    pub unsafe fn mask(&mut self, entry: u16) {
+        let control = self.read(entry, ENTRY_CONTROL);
+        self.write(entry, ENTRY_CONTROL, control | ENTRY_MASKED);
+    }
+    pub unsafe fn unmask(&mut self, entry: u16) {
+        let control = self.read(entry, ENTRY_CONTROL);
+        self.write(entry, ENTRY_CONTROL, control & !ENTRY_MASKED);
+    }
+    pub unsafe fn mask_all(&mut self) {
+        for entry in 0..self.size {
+            self.mask(entry);
+        }
+    }
+}
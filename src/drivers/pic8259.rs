@@ -0,0 +1,220 @@
+//! Driver for the legacy 8259 programmable interrupt controllers
+//!
+//! PC compatibles have two 8259s, with the slave cascaded into IRQ2 of the master. At power on
+//! the master delivers IRQ0-7 on vectors 8-15, which overlap CPU exceptions, so the pair must be
+//! remapped before interrupts are enabled, even if they are then only going to be disabled in
+//! favour of the APIC.
+
+use super::io::{Io, PortIO};
+
+/// Conventional base of the master command and data ports
+pub const MASTER_BASE: u16 = 0x20;
+/// Conventional base of the slave command and data ports
+pub const SLAVE_BASE: u16 = 0xA0;
+
+/// Vector the master is remapped to, with the slave following it
+pub const VECTOR_BASE: u8 = 0x20;
+
+/// Number of IRQ lines on each chip
+const LINES: u8 = 8;
+/// Line on the master that the slave is cascaded through
+const CASCADE_LINE: u8 = 2;
+/// Line that spurious interrupts are reported on
+const SPURIOUS_LINE: u8 = 7;
+
+const COMMAND: u8 = 0;
+const DATA: u8 = 1;
+
+/// ICW1: initialization, ICW4 will be provided
+const ICW1_INIT: u8 = 0x11;
+/// ICW4: 8086 mode
+const ICW4_8086: u8 = 0x01;
+/// OCW2: non specific end of interrupt
+const OCW2_EOI: u8 = 0x20;
+/// OCW3: read the interrupt request register on the next command port read
+const OCW3_READ_IRR: u8 = 0x0A;
+/// OCW3: read the in service register on the next command port read
+const OCW3_READ_ISR: u8 = 0x0B;
+
+/// A single 8259
+pub struct Pic<T: Io<Item = u8>> {
+    io: T,
+    offset: u8,
+}
+
+impl<T, R> Pic<T> where T: Io<Item = u8, Range=R>, R: From<u8> {
+    unsafe fn write_command(&mut self, value: u8) {
+        self.io.write(R::from(COMMAND), value)
+    }
+    unsafe fn read_command(&mut self) -> u8 {
+        self.io.read(R::from(COMMAND))
+    }
+    unsafe fn write_data(&mut self, value: u8) {
+        self.io.write(R::from(DATA), value)
+    }
+    unsafe fn read_data(&mut self) -> u8 {
+        self.io.read(R::from(DATA))
+    }
+    /// Run the initialization sequence
+    ///
+    /// `cascade` is ICW3, which is a bitmask of slave lines for a master and the cascade
+    /// identity for a slave
+    unsafe fn init(&mut self, offset: u8, cascade: u8, mask: u8) {
+        self.write_command(ICW1_INIT);
+        self.write_data(offset);
+        self.write_data(cascade);
+        self.write_data(ICW4_8086);
+        self.write_data(mask);
+        self.offset = offset;
+    }
+    fn handles(&self, vector: u8) -> bool {
+        vector >= self.offset && vector - self.offset < LINES
+    }
+    pub unsafe fn mask(&mut self) -> u8 {
+        self.read_data()
+    }
+    pub unsafe fn set_mask(&mut self, mask: u8) {
+        self.write_data(mask)
+    }
+    pub unsafe fn irr(&mut self) -> u8 {
+        self.write_command(OCW3_READ_IRR);
+        self.read_command()
+    }
+    pub unsafe fn isr(&mut self) -> u8 {
+        self.write_command(OCW3_READ_ISR);
+        self.read_command()
+    }
+    pub unsafe fn eoi(&mut self) {
+        self.write_command(OCW2_EOI)
+    }
+    pub fn new(io: T) -> Pic<T> {
+        Pic { io: io, offset: 0 }
+    }
+}
+
+/// The master and slave pair
+pub struct ChainedPics<T: Io<Item = u8>> {
+    master: Pic<T>,
+    slave: Pic<T>,
+}
+
+impl<T, R> ChainedPics<T> where T: Io<Item = u8, Range=R>, R: From<u8> {
+    /// Remap the pair so that IRQ0 arrives at `base` and IRQ8 at `base + 8`
+    ///
+    /// All lines other than the cascade are left masked. The 16 vectors must lie between the
+    /// exceptions and the end of the IDT, so `base` can be no higher than 240.
+    pub unsafe fn remap(&mut self, base: u8) {
+        assert!(base >= 32 && base % LINES == 0, "PIC vector base {} overlaps exceptions or is unaligned", base);
+        assert!(base <= u8::max_value() - (LINES * 2 - 1), "PIC vector base {} leaves no room for the slave", base);
+        self.master.init(base, 1 << CASCADE_LINE, !(1 << CASCADE_LINE));
+        self.slave.init(base + LINES, CASCADE_LINE, 0xff);
+    }
+    /// Mask every line, leaving the pair in a state where it delivers nothing
+    ///
+    /// The pair should have been remapped first, as a spurious interrupt can still be raised
+    /// even when every line is masked
+    pub unsafe fn disable(&mut self) {
+        self.master.set_mask(0xff);
+        self.slave.set_mask(0xff);
+    }
+    fn pic_for(&mut self, irq: u8) -> (&mut Pic<T>, u8) {
+        assert!(irq < LINES * 2, "Invalid legacy IRQ {}", irq);
+        if irq < LINES {
+            (&mut self.master, irq)
+        } else {
+            (&mut self.slave, irq - LINES)
+        }
+    }
+    pub unsafe fn mask_irq(&mut self, irq: u8) {
+        let (pic, line) = self.pic_for(irq);
+        let mask = pic.mask();
+        pic.set_mask(mask | (1 << line));
+    }
+    pub unsafe fn unmask_irq(&mut self, irq: u8) {
+        let (pic, line) = self.pic_for(irq);
+        let mask = pic.mask();
+        pic.set_mask(mask & !(1 << line));
+    }
+    /// Combined mask with the slave in the high byte
+    pub unsafe fn masks(&mut self) -> u16 {
+        (self.slave.mask() as u16) << 8 | self.master.mask() as u16
+    }
+    /// Combined in service register with the slave in the high byte
+    pub unsafe fn in_service(&mut self) -> u16 {
+        (self.slave.isr() as u16) << 8 | self.master.isr() as u16
+    }
+    /// Combined interrupt request register with the slave in the high byte
+    pub unsafe fn requested(&mut self) -> u16 {
+        (self.slave.irr() as u16) << 8 | self.master.irr() as u16
+    }
+    /// Whether the vector belongs to one of the pair
+    pub fn handles(&self, vector: u8) -> bool {
+        self.master.handles(vector) || self.slave.handles(vector)
+    }
+    /// Convert a vector into an IRQ number, if the vector belongs to the pair
+    pub fn irq_for(&self, vector: u8) -> Option<u8> {
+        if self.handles(vector) {
+            Some(vector - self.master.offset)
+        } else {
+            None
+        }
+    }
+    /// Check whether an interrupt on this IRQ is spurious
+    ///
+    /// A spurious interrupt is reported on the lowest priority line of a chip, without the line
+    /// being marked in service. Spurious interrupts must not be acknowledged with an EOI, except
+    /// that a spurious interrupt from the slave was still a real interrupt to the master, and so
+    /// the master is acknowledged here.
+    pub unsafe fn is_spurious(&mut self, irq: u8) -> bool {
+        let in_service = {
+            let (pic, line) = self.pic_for(irq);
+            line != SPURIOUS_LINE || pic.isr() & (1 << SPURIOUS_LINE) != 0
+        };
+        if in_service {
+            return false;
+        }
+        if irq >= LINES {
+            self.master.eoi();
+        }
+        true
+    }
+    /// Signal end of interrupt for an IRQ
+    pub unsafe fn eoi(&mut self, irq: u8) {
+        if irq >= LINES {
+            self.slave.eoi();
+        }
+        self.master.eoi();
+    }
+    pub fn new(master: T, slave: T) -> ChainedPics<T> {
+        ChainedPics { master: Pic::new(master), slave: Pic::new(slave) }
+    }
+}
+
+static mut PICS: Option<ChainedPics<PortIO<u8>>> = None;
+
+/// Remap the legacy PICs away from the exception vectors and mask all lines
+pub fn init() {
+    let mut pics = ChainedPics::new(PortIO::new(MASTER_BASE), PortIO::new(SLAVE_BASE));
+    unsafe {
+        pics.remap(VECTOR_BASE);
+        PICS = Some(pics);
+    }
+    print!(Info, "Remapped legacy PICs to vectors {:#x}-{:#x}", VECTOR_BASE, VECTOR_BASE + LINES * 2 - 1);
+}
+
+/// Retrieve the legacy PICs
+///
+/// # Panics
+///
+/// If `init` has not yet been called
+pub fn get() -> &'static mut ChainedPics<PortIO<u8>> {
+    unsafe {PICS.as_mut()}.expect("Legacy PICs used before init")
+}
+
+/// Stop the legacy PICs from delivering any interrupts
+///
+/// Used once interrupts are routed through the APIC instead
+pub fn disable() {
+    unsafe {get().disable()};
+    print!(Info, "Disabled legacy PICs");
+}
@@ -0,0 +1,193 @@
+//! Driver for the 8254 programmable interval timer
+//!
+//! Channel 0 is wired to ISA IRQ 0 and is used as a periodic or one-shot event source. Channel 2
+//! has its gate controlled through port 0x61 and its output readable there, which allows a known
+//! interval to be timed by polling, without interrupts, for calibrating other counters. Once
+//! nothing needs calibrating, channel 2 can instead be left free running and read as a clock.
+
+use super::io::Io;
+use core::cmp;
+
+/// Conventional base of the counter and command ports
+pub const PORT_BASE: u16 = 0x40;
+/// Port controlling the gate of channel 2, shared with the PC speaker
+pub const GATE_PORT: u16 = 0x61;
+
+/// Input clock frequency in Hz
+pub const FREQUENCY: u64 = 1193182;
+/// Largest count, written to the reload register as 0
+pub const MAX_COUNT: u32 = 0x10000;
+
+const CHANNEL0: u8 = 0;
+const CHANNEL2: u8 = 2;
+const COMMAND: u8 = 3;
+
+/// Channel 2 gate input
+const GATE_CH2: u8 = 0b1;
+/// Connect channel 2 output to the speaker
+const GATE_SPEAKER: u8 = 0b10;
+/// Current level of the channel 2 output
+const GATE_OUT2: u8 = 0b100000;
+
+/// Latch the current count for reading
+const ACCESS_LATCH: u8 = 0;
+/// Read and write the low then high byte
+const ACCESS_LOHI: u8 = 3;
+
+/// Number of polls of the channel 2 output before assuming it is never going to fire
+///
+/// Generous enough to cover a full count on even a slow emulated port
+const POLL_LIMIT: usize = 10000000;
+
+bitfield!{
+    struct Command(u8);
+    _, set_channel: 7, 6;
+    _, set_access: 5, 4;
+    _, set_mode: 3, 1;
+}
+
+/// Counter operating modes
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Output goes high once the count reaches zero, and stays there
+    InterruptOnTerminalCount = 0,
+    /// Output pulses low every time the count reaches zero, and then reloads
+    RateGenerator = 2,
+    /// Rate generator with a 50% duty cycle
+    SquareWave = 3,
+}
+
+/// Convert a count in to the value for the reload register
+///
+/// # Panics
+///
+/// If the count is zero or larger than `MAX_COUNT`
+fn reload(count: u32) -> u16 {
+    assert!(count != 0 && count <= MAX_COUNT, "Invalid PIT count {}", count);
+    // A reload of 0 is treated as MAX_COUNT by the hardware
+    count as u16
+}
+
+/// Number of input clocks closest to the provided number of nanoseconds
+pub fn count_for_nanos(nanos: u64) -> u32 {
+    // Anything beyond a second is well past MAX_COUNT, and would overflow below
+    let nanos = cmp::min(nanos, 1_000_000_000);
+    let count = (nanos * FREQUENCY + 500_000_000) / 1_000_000_000;
+    if count == 0 {
+        1
+    } else if count > MAX_COUNT as u64 {
+        MAX_COUNT
+    } else {
+        count as u32
+    }
+}
+
+pub struct Pit<T: Io<Item = u8>> {
+    io: T,
+    gate: T,
+}
+
+impl<T, R> Pit<T> where T: Io<Item = u8, Range=R>, R: From<u8> {
+    unsafe fn command(&mut self, channel: u8, access: u8, mode: Mode) {
+        let mut command = Command(0);
+        command.set_channel(channel);
+        command.set_access(access);
+        command.set_mode(mode as u8);
+        self.io.write(R::from(COMMAND), command.0);
+    }
+    unsafe fn write_reload(&mut self, channel: u8, count: u32) {
+        let value = reload(count);
+        self.io.write(R::from(channel), value as u8);
+        self.io.write(R::from(channel), (value >> 8) as u8);
+    }
+    unsafe fn start(&mut self, channel: u8, mode: Mode, count: u32) {
+        self.command(channel, ACCESS_LOHI, mode);
+        self.write_reload(channel, count);
+    }
+    /// Interrupt every `count` input clocks on channel 0
+    pub unsafe fn periodic(&mut self, count: u32) {
+        self.start(CHANNEL0, Mode::RateGenerator, count);
+    }
+    /// Interrupt once after `count` input clocks on channel 0
+    pub unsafe fn one_shot(&mut self, count: u32) {
+        self.start(CHANNEL0, Mode::InterruptOnTerminalCount, count);
+    }
+    /// Stop channel 0 from producing any further interrupts
+    ///
+    /// Selecting a mode stops the counter until a new count is written
+    pub unsafe fn stop(&mut self) {
+        self.command(CHANNEL0, ACCESS_LOHI, Mode::InterruptOnTerminalCount);
+    }
+    /// Current count of a channel
+    pub unsafe fn read_count(&mut self, channel: u8) -> u16 {
+        // The mode bits are ignored for a latch command
+        self.command(channel, ACCESS_LATCH, Mode::InterruptOnTerminalCount);
+        let low = self.io.read(R::from(channel)) as u16;
+        let high = self.io.read(R::from(channel)) as u16;
+        high << 8 | low
+    }
+    unsafe fn set_gate(&mut self, high: bool) {
+        let mut gate = self.gate.read(R::from(0)) & !GATE_SPEAKER;
+        if high {
+            gate |= GATE_CH2;
+        } else {
+            gate &= !GATE_CH2;
+        }
+        self.gate.write(R::from(0), gate);
+    }
+    /// Load `count` into channel 2 and then open its gate to start counting
+    ///
+    /// The speaker is disconnected so that nothing is heard
+    pub unsafe fn start_gated(&mut self, count: u32) {
+        self.set_gate(false);
+        self.start(CHANNEL2, Mode::InterruptOnTerminalCount, count);
+        self.set_gate(true);
+    }
+    /// Whether the count started by `start_gated` has completed
+    pub unsafe fn gated_done(&mut self) -> bool {
+        self.gate.read(R::from(0)) & GATE_OUT2 != 0
+    }
+    pub unsafe fn stop_gated(&mut self) {
+        self.set_gate(false);
+    }
+    /// Leave channel 2 counting continuously through `MAX_COUNT`, for `free_running_count`
+    ///
+    /// The speaker is disconnected so that nothing is heard
+    pub unsafe fn start_free_running(&mut self) {
+        self.set_gate(false);
+        self.start(CHANNEL2, Mode::RateGenerator, MAX_COUNT);
+        self.set_gate(true);
+    }
+    /// Count of channel 2 since `start_free_running`, wrapping every `MAX_COUNT` input clocks
+    pub unsafe fn free_running_count(&mut self) -> u16 {
+        // The channel counts down from MAX_COUNT, which reads as 0
+        0u16.wrapping_sub(self.read_count(CHANNEL2))
+    }
+    /// Measure the frequency of another counter
+    ///
+    /// Times `count` input clocks using channel 2 and returns how many times per second `read`
+    /// increments, or nothing if channel 2 never completed. Interrupts should be disabled, as
+    /// anything that delays the final read makes the result too high.
+    pub unsafe fn calibrate<F: FnMut() -> u64>(&mut self, count: u32, mut read: F) -> Option<u64> {
+        self.start_gated(count);
+        let start = read();
+        let mut done = false;
+        for _ in 0..POLL_LIMIT {
+            if self.gated_done() {
+                done = true;
+                break;
+            }
+        }
+        let end = read();
+        self.stop_gated();
+        if !done {
+            return None;
+        }
+        Some((end - start) * FREQUENCY / count as u64)
+    }
+    /// Construct a driver from the counter window and the gate port
+    pub fn new(io: T, gate: T) -> Pit<T> {
+        Pit { io: io, gate: gate }
+    }
+}
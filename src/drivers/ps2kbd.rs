@@ -0,0 +1,513 @@
+//! Driver for a keyboard attached to the first port of an 8042 controller
+//!
+//! Scancodes are decoded into `KeyCode`s and passed through a `KeyState` to produce input
+//! events. The controller has translation disabled and set 2 is requested from the keyboard, but
+//! set 1 is decoded as well for keyboards that will not switch.
+
+use super::i8042::{self, Controller, Error};
+use super::io::{Io, PortIO};
+use bus::Device;
+use decls::Match;
+use input::{self, Event, KeyCode, KeyEvent, KeyState, Modifiers};
+use irq;
+
+const CMD_SET_LEDS: u8 = 0xED;
+const CMD_SCANCODE_SET: u8 = 0xF0;
+const CMD_ENABLE_SCANNING: u8 = 0xF4;
+const CMD_RESET: u8 = 0xFF;
+
+/// Sent by the keyboard after a successful reset
+const SELF_TEST_PASS: u8 = 0xAA;
+
+/// Number of scancode bytes that can be held while waiting for a command response
+const BACKLOG_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    Set1,
+    Set2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecodeState {
+    Start,
+    /// Seen an 0xE0 prefix
+    Extended,
+    /// Seen an 0xF0 break prefix. Only in set 2
+    Release,
+    /// Seen 0xE0 0xF0. Only in set 2
+    ExtendedRelease,
+    /// In the pause sequence, with this many bytes of it left
+    Pause(u8),
+}
+
+/// Converts a stream of scancode bytes into key transitions
+pub struct Decoder {
+    set: ScancodeSet,
+    state: DecodeState,
+}
+
+fn set1(code: u8) -> Option<KeyCode> {
+    Some(match code {
+        0x01 => KeyCode::Escape,
+        0x02 => KeyCode::Key1,
+        0x03 => KeyCode::Key2,
+        0x04 => KeyCode::Key3,
+        0x05 => KeyCode::Key4,
+        0x06 => KeyCode::Key5,
+        0x07 => KeyCode::Key6,
+        0x08 => KeyCode::Key7,
+        0x09 => KeyCode::Key8,
+        0x0A => KeyCode::Key9,
+        0x0B => KeyCode::Key0,
+        0x0C => KeyCode::Minus,
+        0x0D => KeyCode::Equals,
+        0x0E => KeyCode::Backspace,
+        0x0F => KeyCode::Tab,
+        0x10 => KeyCode::Q,
+        0x11 => KeyCode::W,
+        0x12 => KeyCode::E,
+        0x13 => KeyCode::R,
+        0x14 => KeyCode::T,
+        0x15 => KeyCode::Y,
+        0x16 => KeyCode::U,
+        0x17 => KeyCode::I,
+        0x18 => KeyCode::O,
+        0x19 => KeyCode::P,
+        0x1A => KeyCode::LeftBracket,
+        0x1B => KeyCode::RightBracket,
+        0x1C => KeyCode::Enter,
+        0x1D => KeyCode::LeftCtrl,
+        0x1E => KeyCode::A,
+        0x1F => KeyCode::S,
+        0x20 => KeyCode::D,
+        0x21 => KeyCode::F,
+        0x22 => KeyCode::G,
+        0x23 => KeyCode::H,
+        0x24 => KeyCode::J,
+        0x25 => KeyCode::K,
+        0x26 => KeyCode::L,
+        0x27 => KeyCode::Semicolon,
+        0x28 => KeyCode::Quote,
+        0x29 => KeyCode::Backtick,
+        0x2A => KeyCode::LeftShift,
+        0x2B => KeyCode::Backslash,
+        0x2C => KeyCode::Z,
+        0x2D => KeyCode::X,
+        0x2E => KeyCode::C,
+        0x2F => KeyCode::V,
+        0x30 => KeyCode::B,
+        0x31 => KeyCode::N,
+        0x32 => KeyCode::M,
+        0x33 => KeyCode::Comma,
+        0x34 => KeyCode::Period,
+        0x35 => KeyCode::Slash,
+        0x36 => KeyCode::RightShift,
+        0x37 => KeyCode::KpMultiply,
+        0x38 => KeyCode::LeftAlt,
+        0x39 => KeyCode::Space,
+        0x3A => KeyCode::CapsLock,
+        0x3B => KeyCode::F1,
+        0x3C => KeyCode::F2,
+        0x3D => KeyCode::F3,
+        0x3E => KeyCode::F4,
+        0x3F => KeyCode::F5,
+        0x40 => KeyCode::F6,
+        0x41 => KeyCode::F7,
+        0x42 => KeyCode::F8,
+        0x43 => KeyCode::F9,
+        0x44 => KeyCode::F10,
+        0x45 => KeyCode::NumLock,
+        0x46 => KeyCode::ScrollLock,
+        0x47 => KeyCode::Kp7,
+        0x48 => KeyCode::Kp8,
+        0x49 => KeyCode::Kp9,
+        0x4A => KeyCode::KpMinus,
+        0x4B => KeyCode::Kp4,
+        0x4C => KeyCode::Kp5,
+        0x4D => KeyCode::Kp6,
+        0x4E => KeyCode::KpPlus,
+        0x4F => KeyCode::Kp1,
+        0x50 => KeyCode::Kp2,
+        0x51 => KeyCode::Kp3,
+        0x52 => KeyCode::Kp0,
+        0x53 => KeyCode::KpPeriod,
+        0x56 => KeyCode::NonUsBackslash,
+        0x57 => KeyCode::F11,
+        0x58 => KeyCode::F12,
+        _ => return None,
+    })
+}
+
+fn set1_extended(code: u8) -> Option<KeyCode> {
+    Some(match code {
+        0x1C => KeyCode::KpEnter,
+        0x1D => KeyCode::RightCtrl,
+        0x35 => KeyCode::KpDivide,
+        0x37 => KeyCode::PrintScreen,
+        0x38 => KeyCode::RightAlt,
+        0x47 => KeyCode::Home,
+        0x48 => KeyCode::Up,
+        0x49 => KeyCode::PageUp,
+        0x4B => KeyCode::Left,
+        0x4D => KeyCode::Right,
+        0x4F => KeyCode::End,
+        0x50 => KeyCode::Down,
+        0x51 => KeyCode::PageDown,
+        0x52 => KeyCode::Insert,
+        0x53 => KeyCode::Delete,
+        0x5B => KeyCode::LeftGui,
+        0x5C => KeyCode::RightGui,
+        0x5D => KeyCode::Menu,
+        _ => return None,
+    })
+}
+
+fn set2(code: u8) -> Option<KeyCode> {
+    Some(match code {
+        0x01 => KeyCode::F9,
+        0x03 => KeyCode::F5,
+        0x04 => KeyCode::F3,
+        0x05 => KeyCode::F1,
+        0x06 => KeyCode::F2,
+        0x07 => KeyCode::F12,
+        0x09 => KeyCode::F10,
+        0x0A => KeyCode::F8,
+        0x0B => KeyCode::F6,
+        0x0C => KeyCode::F4,
+        0x0D => KeyCode::Tab,
+        0x0E => KeyCode::Backtick,
+        0x11 => KeyCode::LeftAlt,
+        0x12 => KeyCode::LeftShift,
+        0x14 => KeyCode::LeftCtrl,
+        0x15 => KeyCode::Q,
+        0x16 => KeyCode::Key1,
+        0x1A => KeyCode::Z,
+        0x1B => KeyCode::S,
+        0x1C => KeyCode::A,
+        0x1D => KeyCode::W,
+        0x1E => KeyCode::Key2,
+        0x21 => KeyCode::C,
+        0x22 => KeyCode::X,
+        0x23 => KeyCode::D,
+        0x24 => KeyCode::E,
+        0x25 => KeyCode::Key4,
+        0x26 => KeyCode::Key3,
+        0x29 => KeyCode::Space,
+        0x2A => KeyCode::V,
+        0x2B => KeyCode::F,
+        0x2C => KeyCode::T,
+        0x2D => KeyCode::R,
+        0x2E => KeyCode::Key5,
+        0x31 => KeyCode::N,
+        0x32 => KeyCode::B,
+        0x33 => KeyCode::H,
+        0x34 => KeyCode::G,
+        0x35 => KeyCode::Y,
+        0x36 => KeyCode::Key6,
+        0x3A => KeyCode::M,
+        0x3B => KeyCode::J,
+        0x3C => KeyCode::U,
+        0x3D => KeyCode::Key7,
+        0x3E => KeyCode::Key8,
+        0x41 => KeyCode::Comma,
+        0x42 => KeyCode::K,
+        0x43 => KeyCode::I,
+        0x44 => KeyCode::O,
+        0x45 => KeyCode::Key0,
+        0x46 => KeyCode::Key9,
+        0x49 => KeyCode::Period,
+        0x4A => KeyCode::Slash,
+        0x4B => KeyCode::L,
+        0x4C => KeyCode::Semicolon,
+        0x4D => KeyCode::P,
+        0x4E => KeyCode::Minus,
+        0x52 => KeyCode::Quote,
+        0x54 => KeyCode::LeftBracket,
+        0x55 => KeyCode::Equals,
+        0x58 => KeyCode::CapsLock,
+        0x59 => KeyCode::RightShift,
+        0x5A => KeyCode::Enter,
+        0x5B => KeyCode::RightBracket,
+        0x5D => KeyCode::Backslash,
+        0x61 => KeyCode::NonUsBackslash,
+        0x66 => KeyCode::Backspace,
+        0x69 => KeyCode::Kp1,
+        0x6B => KeyCode::Kp4,
+        0x6C => KeyCode::Kp7,
+        0x70 => KeyCode::Kp0,
+        0x71 => KeyCode::KpPeriod,
+        0x72 => KeyCode::Kp2,
+        0x73 => KeyCode::Kp5,
+        0x74 => KeyCode::Kp6,
+        0x75 => KeyCode::Kp8,
+        0x76 => KeyCode::Escape,
+        0x77 => KeyCode::NumLock,
+        0x78 => KeyCode::F11,
+        0x79 => KeyCode::KpPlus,
+        0x7A => KeyCode::Kp3,
+        0x7B => KeyCode::KpMinus,
+        0x7C => KeyCode::KpMultiply,
+        0x7D => KeyCode::Kp9,
+        0x7E => KeyCode::ScrollLock,
+        0x83 => KeyCode::F7,
+        _ => return None,
+    })
+}
+
+fn set2_extended(code: u8) -> Option<KeyCode> {
+    Some(match code {
+        0x11 => KeyCode::RightAlt,
+        0x14 => KeyCode::RightCtrl,
+        0x1F => KeyCode::LeftGui,
+        0x27 => KeyCode::RightGui,
+        0x2F => KeyCode::Menu,
+        0x4A => KeyCode::KpDivide,
+        0x5A => KeyCode::KpEnter,
+        0x69 => KeyCode::End,
+        0x6B => KeyCode::Left,
+        0x6C => KeyCode::Home,
+        0x70 => KeyCode::Insert,
+        0x71 => KeyCode::Delete,
+        0x72 => KeyCode::Down,
+        0x74 => KeyCode::Right,
+        0x75 => KeyCode::Up,
+        0x7A => KeyCode::PageDown,
+        0x7C => KeyCode::PrintScreen,
+        0x7D => KeyCode::PageUp,
+        _ => return None,
+    })
+}
+
+impl Decoder {
+    pub fn new(set: ScancodeSet) -> Self {
+        Decoder {set: set, state: DecodeState::Start}
+    }
+    pub fn set(&self) -> ScancodeSet {
+        self.set
+    }
+    fn feed_set1(&mut self, byte: u8) -> Option<(KeyCode, bool)> {
+        let pressed = byte & 0x80 == 0;
+        let code = byte & 0x7f;
+        match self.state {
+            DecodeState::Start => match byte {
+                0xE0 => { self.state = DecodeState::Extended; None },
+                // Pause is E1 1D 45 E1 9D C5, a press and release sent together
+                0xE1 => { self.state = DecodeState::Pause(5); None },
+                // Keyboard responses that are not keys
+                0x00 | 0xFF | 0xFA | 0xFE | 0xEE => None,
+                _ => set1(code).map(|k| (k, pressed)),
+            },
+            DecodeState::Extended => {
+                self.state = DecodeState::Start;
+                match code {
+                    // Fake shifts surrounding print screen and the navigation keys
+                    0x2A | 0x36 => None,
+                    _ => set1_extended(code).map(|k| (k, pressed)),
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+    fn feed_set2(&mut self, byte: u8) -> Option<(KeyCode, bool)> {
+        match self.state {
+            DecodeState::Start => match byte {
+                0xE0 => { self.state = DecodeState::Extended; None },
+                0xF0 => { self.state = DecodeState::Release; None },
+                // Pause is E1 14 77 E1 F0 14 F0 77, a press and release sent together
+                0xE1 => { self.state = DecodeState::Pause(7); None },
+                // Keyboard responses that are not keys
+                0x00 | 0xFF | 0xFA | 0xFE | 0xEE | 0xAA => None,
+                _ => set2(byte).map(|k| (k, true)),
+            },
+            DecodeState::Release => {
+                self.state = DecodeState::Start;
+                set2(byte).map(|k| (k, false))
+            },
+            DecodeState::Extended => match byte {
+                0xF0 => { self.state = DecodeState::ExtendedRelease; None },
+                _ => {
+                    self.state = DecodeState::Start;
+                    // Fake shifts surrounding print screen and the navigation keys are not in
+                    // the extended table, so fall out as None
+                    set2_extended(byte).map(|k| (k, true))
+                },
+            },
+            DecodeState::ExtendedRelease => {
+                self.state = DecodeState::Start;
+                set2_extended(byte).map(|k| (k, false))
+            },
+            _ => unreachable!(),
+        }
+    }
+    /// Process a single byte from the keyboard
+    ///
+    /// Returns a key and whether it was pressed or released, if this byte completed a scancode
+    pub fn feed(&mut self, byte: u8) -> Option<(KeyCode, bool)> {
+        if let DecodeState::Pause(remaining) = self.state {
+            let remaining = remaining - 1;
+            // Bytes of the pause sequence that make up its release
+            let release = match self.set {
+                ScancodeSet::Set1 => 3,
+                ScancodeSet::Set2 => 5,
+            };
+            if remaining == 0 {
+                self.state = DecodeState::Start;
+                return Some((KeyCode::Pause, false));
+            }
+            self.state = DecodeState::Pause(remaining);
+            return if remaining == release { Some((KeyCode::Pause, true)) } else { None };
+        }
+        match self.set {
+            ScancodeSet::Set1 => self.feed_set1(byte),
+            ScancodeSet::Set2 => self.feed_set2(byte),
+        }
+    }
+}
+
+pub struct Keyboard<T: Io<Item = u8>> {
+    controller: Controller<T>,
+    decoder: Decoder,
+    state: KeyState,
+    /// Scancode bytes that arrived while waiting for a command response
+    backlog: [u8; BACKLOG_SIZE],
+    backlog_len: usize,
+}
+
+impl<T, R> Keyboard<T> where T: Io<Item = u8, Range=R>, R: From<u8> {
+    /// Attempt to switch the keyboard to set 2, reporting the set that is in use
+    unsafe fn select_set(controller: &mut Controller<T>) -> Result<ScancodeSet, Error> {
+        let set2 = controller.send_port1(CMD_SCANCODE_SET).and_then(|_| controller.send_port1(2));
+        if set2.is_ok() {
+            return Ok(ScancodeSet::Set2);
+        }
+        // Keyboard would not switch, ask it what it is using
+        controller.send_port1(CMD_SCANCODE_SET)?;
+        controller.send_port1(0)?;
+        match controller.read_data_wait()? {
+            1 => Ok(ScancodeSet::Set1),
+            2 => Ok(ScancodeSet::Set2),
+            other => Err(Error::NoAck(other)),
+        }
+    }
+    /// Send a byte to the keyboard while it is scanning and wait for it to be acknowledged
+    ///
+    /// Keys may have been pressed before the keyboard saw the command, so anything that arrives
+    /// ahead of the response is kept for `poll` to decode later.
+    unsafe fn send_scanning(&mut self, byte: u8) -> Result<(), Error> {
+        for _ in 0..i8042::DEVICE_RETRIES {
+            self.controller.write_data(byte)?;
+            loop {
+                match self.controller.read_data_wait()? {
+                    i8042::DEVICE_ACK => return Ok(()),
+                    i8042::DEVICE_RESEND => break,
+                    other => if self.backlog_len < BACKLOG_SIZE {
+                        self.backlog[self.backlog_len] = other;
+                        self.backlog_len += 1;
+                    },
+                }
+            }
+        }
+        Err(Error::NoAck(i8042::DEVICE_RESEND))
+    }
+    unsafe fn update_leds(&mut self) -> Result<(), Error> {
+        let m = self.state.modifiers();
+        let leds = (m.contains(Modifiers::SCROLL_LOCK) as u8)
+            | ((m.contains(Modifiers::NUM_LOCK) as u8) << 1)
+            | ((m.contains(Modifiers::CAPS_LOCK) as u8) << 2);
+        self.send_scanning(CMD_SET_LEDS)?;
+        self.send_scanning(leds)
+    }
+    unsafe fn next_byte(&mut self) -> Option<u8> {
+        if self.backlog_len > 0 {
+            let byte = self.backlog[0];
+            self.backlog_len -= 1;
+            for i in 0..self.backlog_len {
+                self.backlog[i] = self.backlog[i + 1];
+            }
+            Some(byte)
+        } else {
+            self.controller.read_port1()
+        }
+    }
+    /// Process any pending bytes from the keyboard until a key event is produced
+    pub unsafe fn poll(&mut self) -> Option<KeyEvent> {
+        while let Some(byte) = self.next_byte() {
+            if let Some((code, pressed)) = self.decoder.feed(byte) {
+                let locks = self.state.modifiers() & Modifiers::LOCKS;
+                let event = self.state.process(code, pressed);
+                if event.modifiers & Modifiers::LOCKS != locks {
+                    if let Err(e) = self.update_leds() {
+                        print!(Error, "Failed to update keyboard LEDs: {:?}", e);
+                    }
+                }
+                return Some(event);
+            }
+        }
+        None
+    }
+    /// Move any pending keys into the input queue
+    pub unsafe fn service(&mut self) {
+        while let Some(event) = self.poll() {
+            input::push(Event::Key(event));
+        }
+    }
+    pub fn scancode_set(&self) -> ScancodeSet {
+        self.decoder.set()
+    }
+    /// Reset and configure a keyboard on the first port of an initialized controller
+    pub unsafe fn new(mut controller: Controller<T>) -> Result<Keyboard<T>, Error> {
+        controller.enable_port1()?;
+        controller.send_port1(CMD_RESET)?;
+        match controller.read_data_wait()? {
+            SELF_TEST_PASS => (),
+            other => return Err(Error::SelfTestFailed(other)),
+        }
+        let set = Self::select_set(&mut controller)?;
+        controller.send_port1(CMD_ENABLE_SCANNING)?;
+        let mut kb = Keyboard {
+            controller: controller,
+            decoder: Decoder::new(set),
+            state: KeyState::new(input::layout()),
+            backlog: [0; BACKLOG_SIZE],
+            backlog_len: 0,
+        };
+        kb.update_leds()?;
+        Ok(kb)
+    }
+}
+
+static mut KEYBOARD: Option<Keyboard<PortIO<u8>>> = None;
+
+/// Probe for a keyboard on the legacy controller, and have its IRQ service it
+fn probe(_device: &Device) -> bool {
+    let mut controller = Controller::new(PortIO::new(i8042::PORT_BASE));
+    let result = unsafe {
+        controller.init().and_then(|_| Keyboard::new(controller))
+    };
+    match result {
+        Ok(kb) => {
+            print!(Info, "PS/2 keyboard using scancode {:?} with layout {}", kb.scancode_set(), input::layout().name);
+            unsafe {KEYBOARD = Some(kb)};
+            irq::enable_isa(irq::ISA_KEYBOARD, service)
+        },
+        Err(e) => {
+            print!(Info, "No PS/2 keyboard found: {:?}", e);
+            false
+        },
+    }
+}
+
+fn remove(_device: &Device) {
+    irq::disable_isa(irq::ISA_KEYBOARD);
+    unsafe {KEYBOARD = None};
+}
+
+/// Move any pending keys into the input queue
+fn service() {
+    if let Some(kb) = unsafe {KEYBOARD.as_mut()} {
+        unsafe {kb.service()};
+    }
+}
+
+make_driver_decl!("ps2kbd", &[Match::Isa], probe, remove, PS2KBD_DRIVER);
@@ -0,0 +1,84 @@
+//! QEMU debug devices
+//!
+//! The debug console, started with `-debugcon`, is a port where every byte written appears on
+//! the host straight away, with none of the setup or pacing of a UART. It is also in Bochs, and
+//! is found by reading back its own port number. The isa-debug-exit device ends QEMU with a
+//! status made from the value written to it, so a test run can report how it went.
+//!
+//! Neither exists on real hardware, where they must not be touched, so both are only used once
+//! detected.
+
+use cpu::features;
+use drivers::fw_cfg;
+use drivers::io::{Io, PortIO};
+
+/// Port of the debug console
+pub const DEBUGCON_PORT: u16 = 0xE9;
+/// Port of isa-debug-exit, as QEMU places it by default
+pub const DEBUG_EXIT_PORT: u16 = 0x501;
+
+/// Reading the debug console returns this
+const DEBUGCON_PRESENT: u8 = 0xE9;
+
+/// Code `exit` is given when the kernel panics
+pub const EXIT_PANIC: u8 = 1;
+
+/// Status QEMU exits with when the kernel exits with `code`
+///
+/// There is no way to exit with 0, as the status is always odd.
+pub fn exit_status(code: u8) -> i32 {
+    (code as i32) << 1 | 1
+}
+
+pub struct DebugCon<T> {
+    io: T,
+}
+
+impl<T, R> DebugCon<T> where T: Io<Item=u8, Range=R>, R: From<u16> {
+    /// Find the debug console, which is only there if its port reads back as `DEBUGCON_PRESENT`
+    pub unsafe fn new(mut io: T) -> Option<DebugCon<T>> {
+        if io.read(R::from(0)) == DEBUGCON_PRESENT {
+            Some(DebugCon { io: io })
+        } else {
+            None
+        }
+    }
+    pub unsafe fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.io.write(R::from(0), byte);
+        }
+    }
+}
+
+pub struct DebugExit<T> {
+    io: T,
+}
+
+impl<T, R> DebugExit<T> where T: Io<Item=u8, Range=R>, R: From<u16> {
+    /// There is no way of detecting the device itself, only that this is QEMU, which is up to
+    /// the caller
+    pub unsafe fn new(io: T) -> DebugExit<T> {
+        DebugExit { io: io }
+    }
+    /// Ask QEMU to exit with `exit_status(code)`, which returns if the device is not there
+    pub unsafe fn exit(&mut self, code: u8) {
+        self.io.write(R::from(0), code);
+    }
+}
+
+/// Whether the kernel is running on QEMU, judged by the presence of fw_cfg
+///
+/// Real hardware may have anything at the fw_cfg ports, so they are only probed under a
+/// hypervisor.
+pub fn is_qemu() -> bool {
+    features::hypervisor() && unsafe {fw_cfg::detect(&mut PortIO::<u8>::new(fw_cfg::PORT_BASE))}
+}
+
+/// Terminate QEMU with `exit_status(code)`
+///
+/// Does nothing, and returns, on real hardware or if QEMU was started without isa-debug-exit.
+pub fn exit(code: u8) {
+    if is_qemu() {
+        unsafe {DebugExit::new(PortIO::<u8>::new(DEBUG_EXIT_PORT)).exit(code)};
+    }
+}
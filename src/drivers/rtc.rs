@@ -0,0 +1,236 @@
+//! Driver for the MC146818 compatible CMOS real time clock
+//!
+//! Registers are accessed by writing an index to the first port and then using the second as
+//! data. The top bit of the index port masks NMIs, and as the port cannot be read back there is
+//! no keeping whatever it was set to, so every index is written with NMIs unmasked.
+//!
+//! The clock fields may be stored as BCD or binary and the hours in 12 or 24 hour form, as
+//! selected by status register B, which the firmware is free to have set either way. While the
+//! clock is updating, roughly once a second, the fields are not valid to read.
+
+use super::io::Io;
+use core::fmt;
+
+/// Conventional base of the index and data ports
+pub const PORT_BASE: u16 = 0x70;
+
+const INDEX: u8 = 0;
+const DATA: u8 = 1;
+
+/// Set in the index to mask NMIs
+const INDEX_NMI_DISABLE: u8 = 1 << 7;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0A;
+const STATUS_B: u8 = 0x0B;
+
+/// Update in progress
+const STATUS_A_UIP: u8 = 1 << 7;
+/// Hours are in 24 hour form
+const STATUS_B_24H: u8 = 1 << 1;
+/// Fields are binary instead of BCD
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// PM flag in the hours field in 12 hour form
+const HOURS_PM: u8 = 1 << 7;
+
+/// Number of polls of the update in progress flag before giving up
+///
+/// An update lasts under 2ms, this is far longer than that on any real port
+const POLL_LIMIT: usize = 1000000;
+/// Number of times to read the clock looking for two consecutive matching readings
+const READ_ATTEMPTS: usize = 10;
+
+const SECONDS_PER_DAY: u64 = 86400;
+/// Days from 0000-03-01 to 1970-01-01 in the proleptic Gregorian calendar
+const DAYS_TO_EPOCH: u64 = 719468;
+const DAYS_PER_ERA: u64 = 146097;
+
+/// A calendar date and time, in whatever time zone the clock was set to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+impl DateTime {
+    /// Whether every field is in range, with the year no earlier than 1970
+    pub fn is_valid(&self) -> bool {
+        self.year >= 1970 && self.month >= 1 && self.month <= 12 && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month) && self.hour < 24
+            && self.minute < 60 && self.second < 60
+    }
+    /// Seconds since 1970-01-01 00:00:00
+    ///
+    /// Must be `is_valid`
+    pub fn to_unix(&self) -> u64 {
+        // Count years from March so that the leap day is the last day of the year
+        let (year, month) = if self.month <= 2 {
+            (self.year as u64 - 1, self.month as u64 + 9)
+        } else {
+            (self.year as u64, self.month as u64 - 3)
+        };
+        let era = year / 400;
+        let year_of_era = year % 400;
+        let day_of_year = (153 * month + 2) / 5 + self.day as u64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * DAYS_PER_ERA + day_of_era - DAYS_TO_EPOCH;
+        days * SECONDS_PER_DAY + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+    }
+    /// Convert seconds since 1970-01-01 00:00:00
+    pub fn from_unix(seconds: u64) -> DateTime {
+        let days = seconds / SECONDS_PER_DAY + DAYS_TO_EPOCH;
+        let time = seconds % SECONDS_PER_DAY;
+        let era = days / DAYS_PER_ERA;
+        let day_of_era = days % DAYS_PER_ERA;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}", self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0xF)
+}
+
+/// Clock fields exactly as read, before any decoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Raw {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+pub struct Rtc<T: Io<Item = u8>> {
+    io: T,
+    century: Option<u8>,
+}
+
+impl<T, R> Rtc<T> where T: Io<Item = u8, Range=R>, R: From<u8> {
+    unsafe fn read(&mut self, reg: u8) -> u8 {
+        self.io.write(R::from(INDEX), reg & !INDEX_NMI_DISABLE);
+        self.io.read(R::from(DATA))
+    }
+    unsafe fn update_in_progress(&mut self) -> bool {
+        self.read(STATUS_A) & STATUS_A_UIP != 0
+    }
+    /// Wait for any update to finish, returning false if it never does
+    unsafe fn wait_update(&mut self) -> bool {
+        for _ in 0..POLL_LIMIT {
+            if !self.update_in_progress() {
+                return true;
+            }
+        }
+        false
+    }
+    unsafe fn read_raw(&mut self) -> Raw {
+        Raw {
+            second: self.read(SECONDS),
+            minute: self.read(MINUTES),
+            hour: self.read(HOURS),
+            day: self.read(DAY),
+            month: self.read(MONTH),
+            year: self.read(YEAR),
+            century: match self.century {
+                Some(reg) => self.read(reg),
+                None => 0,
+            },
+        }
+    }
+    fn decode(&self, raw: Raw, status_b: u8) -> DateTime {
+        let binary = status_b & STATUS_B_BINARY != 0;
+        let field = |value: u8| if binary { value } else { from_bcd(value) };
+        let pm = raw.hour & HOURS_PM != 0;
+        let mut hour = field(raw.hour & !HOURS_PM);
+        if status_b & STATUS_B_24H == 0 {
+            // 12 hour form counts 12, 1, ... 11
+            hour %= 12;
+            if pm {
+                hour += 12;
+            }
+        }
+        let year = field(raw.year) as u16;
+        let year = match self.century {
+            Some(_) => field(raw.century) as u16 * 100 + year,
+            // Without a century assume the clock is within 1970 to 2069
+            None if year < 70 => 2000 + year,
+            None => 1900 + year,
+        };
+        DateTime {
+            year: year,
+            month: field(raw.month),
+            day: field(raw.day),
+            hour: hour,
+            minute: field(raw.minute),
+            second: field(raw.second),
+        }
+    }
+    /// Read the current date and time
+    ///
+    /// The clock is read until two consecutive readings, each taken outside of an update, agree,
+    /// so that an update starting part way through cannot produce a mixed reading. Returns
+    /// nothing if no consistent and valid reading could be made.
+    pub unsafe fn now(&mut self) -> Option<DateTime> {
+        if !self.wait_update() {
+            return None;
+        }
+        let mut last = self.read_raw();
+        for _ in 0..READ_ATTEMPTS {
+            if !self.wait_update() {
+                return None;
+            }
+            let raw = self.read_raw();
+            if raw == last {
+                let status_b = self.read(STATUS_B);
+                let now = self.decode(raw, status_b);
+                return if now.is_valid() { Some(now) } else { None };
+            }
+            last = raw;
+        }
+        None
+    }
+    /// Construct a driver
+    ///
+    /// `century` is the CMOS index of the century register, if the platform has one
+    pub fn new(io: T, century: Option<u8>) -> Rtc<T> {
+        Rtc { io: io, century: century }
+    }
+}
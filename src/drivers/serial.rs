@@ -0,0 +1,10 @@
+//! Generic serial device interface
+
+pub trait Serial {
+    // TODO: should have errors or timeouts?
+    unsafe fn write_byte(&mut self, byte: u8);
+    /// Retrieve a pending byte, if there is one
+    ///
+    /// This does not block and returns `None` if nothing has been received
+    unsafe fn read_byte(&mut self) -> Option<u8>;
+}
@@ -63,7 +63,7 @@ bitfield!{
     #[allow(dead_code)]
     stops, set_stops: 2, 2;
     #[allow(dead_code)]
-    word_len, set_word_len: 2, 0;
+    word_len, set_word_len: 1, 0;
 }
 
 bitflags! {
@@ -143,7 +143,6 @@ pub struct Uart<T: Io<Item = u8>> {
 impl<T, R> Uart<T> where T: Io<Item = u8, Range=R>, R: From<u8> {
     // Due to register overlapping we don't define a register map and just have direct logical
     // accessor functions
-    #[allow(dead_code)]
     unsafe fn read_data(&mut self) -> u8 {
         self.io.read(R::from(0))
     }
@@ -198,6 +197,7 @@ impl<T, R> Uart<T> where T: Io<Item = u8, Range=R>, R: From<u8> {
         lcr.set_stops(two_stops as u8);
         lcr.set_word_len(bits.into());
         lcr.set_parity(parity.into());
+        self.write_lcr(lcr);
     }
     unsafe fn write_latch(&mut self, latch: u16) {
         self.set_dlab(true);
@@ -237,4 +237,11 @@ impl<T, R> Serial for Uart<T> where T: Io<Item = u8, Range=R>, R: From<u8> {
         while !self.read_lsr().contains(LSR::ETHR) {}
         self.write_data(byte);
     }
+    unsafe fn read_byte(&mut self) -> Option<u8> {
+        if self.read_lsr().contains(LSR::DR) {
+            Some(self.read_data())
+        } else {
+            None
+        }
+    }
 }
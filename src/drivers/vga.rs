@@ -0,0 +1,338 @@
+//! VGA register programming for text modes
+//!
+//! A mode is the whole of the miscellaneous output, sequencer, CRTC, graphics controller and
+//! attribute controller registers, which are written in one go. The CRTC is assumed to be at
+//! its colour address, which every mode here selects.
+//!
+//! Glyphs live in plane 2, 32 bytes apart whatever their height, and are only reachable by
+//! briefly switching the sequencer and graphics controller to planar access to that plane.
+//! The window at 0xA0000 this needs is given as a pointer, as it is in the physical window
+//! early on.
+
+use core::ptr;
+use core::str::FromStr;
+use drivers::io::Io;
+
+/// Base of the VGA ports, which all registers are offsets from
+pub const PORT_BASE: u16 = 0x3C0;
+/// Physical address of the window the glyphs are reached through
+pub const FONT_WINDOW: usize = 0xA0000;
+
+const ATTR_INDEX: u16 = 0x00;
+const ATTR_READ: u16 = 0x01;
+const MISC_WRITE: u16 = 0x02;
+const SEQ_INDEX: u16 = 0x04;
+const SEQ_DATA: u16 = 0x05;
+const MISC_READ: u16 = 0x0C;
+const GC_INDEX: u16 = 0x0E;
+const GC_DATA: u16 = 0x0F;
+const CRTC_INDEX: u16 = 0x14;
+const CRTC_DATA: u16 = 0x15;
+/// Input status 1, whose read resets the attribute controller to expect an index
+const STATUS: u16 = 0x1A;
+
+pub const SEQ_RESET: u8 = 0x00;
+pub const SEQ_MAP_MASK: u8 = 0x02;
+pub const SEQ_MEMORY_MODE: u8 = 0x04;
+pub const GC_READ_MAP: u8 = 0x04;
+pub const GC_MODE: u8 = 0x05;
+pub const GC_MISC: u8 = 0x06;
+pub const CRTC_END_HORIZONTAL_BLANK: u8 = 0x03;
+pub const CRTC_MAX_SCAN_LINE: u8 = 0x09;
+pub const CRTC_VERTICAL_SYNC_END: u8 = 0x11;
+/// Set in the attribute index to let the attribute controller drive the display
+const ATTR_PALETTE_SOURCE: u8 = 0x20;
+/// Protects CRTC registers 0 to 7 in `CRTC_VERTICAL_SYNC_END`
+const CRTC_PROTECT: u8 = 0x80;
+
+pub const SEQ_COUNT: usize = 5;
+pub const CRTC_COUNT: usize = 25;
+pub const GC_COUNT: usize = 9;
+pub const ATTR_COUNT: usize = 21;
+
+pub const GLYPHS: usize = 256;
+/// Bytes from one glyph to the next in plane 2
+const GLYPH_STRIDE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Font is not 256 glyphs of a height the mode can show
+    BadFont,
+}
+
+/// Every register that makes up a mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub misc: u8,
+    pub seq: [u8; SEQ_COUNT],
+    pub crtc: [u8; CRTC_COUNT],
+    pub gc: [u8; GC_COUNT],
+    pub attr: [u8; ATTR_COUNT],
+}
+
+/// Graphics controller of every text mode, with odd/even access to the text at 0xB8000
+const TEXT_GC: [u8; GC_COUNT] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF];
+/// The 16 text colours of the palette, followed by the mode control registers
+const TEXT_ATTR_9DOT: [u8; ATTR_COUNT] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F,
+    0x0C, 0x00, 0x0F, 0x08, 0x00,
+];
+/// As for 9 dot characters, but without the horizontal panning that they need
+const TEXT_ATTR_8DOT: [u8; ATTR_COUNT] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F,
+    0x0C, 0x00, 0x0F, 0x00, 0x00,
+];
+
+/// 720x400 with 9x16 characters, as the BIOS leaves it
+pub const TEXT_80X25: Registers = Registers {
+    misc: 0x67,
+    seq: [0x03, 0x00, 0x03, 0x00, 0x02],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F, 0x00, 0x4F, 0x0D, 0x0E, 0x00, 0x00, 0x00, 0x00,
+        0x9C, 0x0E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+    ],
+    gc: TEXT_GC,
+    attr: TEXT_ATTR_9DOT,
+};
+
+/// The same 720x400 timing with 9x8 characters
+pub const TEXT_80X50: Registers = Registers {
+    misc: 0x67,
+    seq: [0x03, 0x00, 0x03, 0x00, 0x02],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F, 0x00, 0x47, 0x06, 0x07, 0x00, 0x00, 0x00, 0x00,
+        0x9C, 0x0E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+    ],
+    gc: TEXT_GC,
+    attr: TEXT_ATTR_9DOT,
+};
+
+/// 720x480 with 8x8 characters, from the 28MHz clock and 480 line timing
+pub const TEXT_90X60: Registers = Registers {
+    misc: 0xE7,
+    seq: [0x03, 0x01, 0x03, 0x00, 0x02],
+    crtc: [
+        0x6B, 0x59, 0x5A, 0x82, 0x60, 0x8D, 0x0B, 0x3E, 0x00, 0x47, 0x06, 0x07, 0x00, 0x00, 0x00, 0x00,
+        0xEA, 0x0C, 0xDF, 0x2D, 0x1F, 0xE7, 0x04, 0xA3, 0xFF,
+    ],
+    gc: TEXT_GC,
+    attr: TEXT_ATTR_8DOT,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextMode {
+    Text80x25,
+    Text80x50,
+    Text90x60,
+}
+
+impl TextMode {
+    pub fn columns(&self) -> u16 {
+        match *self {
+            TextMode::Text80x25 | TextMode::Text80x50 => 80,
+            TextMode::Text90x60 => 90,
+        }
+    }
+    pub fn rows(&self) -> u16 {
+        match *self {
+            TextMode::Text80x25 => 25,
+            TextMode::Text80x50 => 50,
+            TextMode::Text90x60 => 60,
+        }
+    }
+    /// Height in scan lines of the glyphs the mode needs
+    pub fn font_height(&self) -> usize {
+        match *self {
+            TextMode::Text80x25 => 16,
+            TextMode::Text80x50 | TextMode::Text90x60 => 8,
+        }
+    }
+    pub fn registers(&self) -> &'static Registers {
+        match *self {
+            TextMode::Text80x25 => &TEXT_80X25,
+            TextMode::Text80x50 => &TEXT_80X50,
+            TextMode::Text90x60 => &TEXT_90X60,
+        }
+    }
+}
+
+/// Parses `COLUMNSxROWS`, such as `80x50`
+impl FromStr for TextMode {
+    type Err = ();
+    fn from_str(s: &str) -> Result<TextMode, ()> {
+        match s {
+            "80x25" => Ok(TextMode::Text80x25),
+            "80x50" => Ok(TextMode::Text80x50),
+            "90x60" => Ok(TextMode::Text90x60),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Make a font half the height of `font`, by merging each pair of rows
+///
+/// Merging rather than dropping rows keeps strokes that are only one row thick, so an 8x16
+/// font gives a legible 8x8 one. `half` must be half the length of `font`.
+pub fn halve_font(font: &[u8], half: &mut [u8]) {
+    for (row, pair) in half.iter_mut().zip(font.chunks(2)) {
+        *row = pair.iter().fold(0, |row, &line| row | line);
+    }
+}
+
+pub struct Vga<T> {
+    io: T,
+    /// Virtual address of `FONT_WINDOW`
+    window: *mut u8,
+}
+
+impl<T, R> Vga<T> where T: Io<Item = u8, Range=R>, R: From<u16> {
+    /// Program the VGA through `io`, with `window` mapping `FONT_WINDOW`
+    ///
+    /// # Safety
+    ///
+    /// `window` must map 64KiB of the VGA memory window, for as long as the driver exists
+    pub unsafe fn new(io: T, window: *mut u8) -> Vga<T> {
+        Vga { io: io, window: window }
+    }
+    unsafe fn read_indexed(&mut self, (index_port, data_port): (u16, u16), index: u8) -> u8 {
+        self.io.write(R::from(index_port), index);
+        self.io.read(R::from(data_port))
+    }
+    unsafe fn write_indexed(&mut self, (index_port, data_port): (u16, u16), index: u8, value: u8) {
+        self.io.write(R::from(index_port), index);
+        self.io.write(R::from(data_port), value);
+    }
+    unsafe fn read_seq(&mut self, index: u8) -> u8 {
+        self.read_indexed((SEQ_INDEX, SEQ_DATA), index)
+    }
+    unsafe fn write_seq(&mut self, index: u8, value: u8) {
+        self.write_indexed((SEQ_INDEX, SEQ_DATA), index, value)
+    }
+    unsafe fn read_gc(&mut self, index: u8) -> u8 {
+        self.read_indexed((GC_INDEX, GC_DATA), index)
+    }
+    unsafe fn write_gc(&mut self, index: u8, value: u8) {
+        self.write_indexed((GC_INDEX, GC_DATA), index, value)
+    }
+    unsafe fn read_crtc(&mut self, index: u8) -> u8 {
+        self.read_indexed((CRTC_INDEX, CRTC_DATA), index)
+    }
+    unsafe fn write_crtc(&mut self, index: u8, value: u8) {
+        self.write_indexed((CRTC_INDEX, CRTC_DATA), index, value)
+    }
+    /// Read every register of the current mode
+    pub unsafe fn registers(&mut self) -> Registers {
+        let mut regs = Registers { misc: 0, seq: [0; SEQ_COUNT], crtc: [0; CRTC_COUNT], gc: [0; GC_COUNT], attr: [0; ATTR_COUNT] };
+        regs.misc = self.io.read(R::from(MISC_READ));
+        for i in 0..SEQ_COUNT {
+            regs.seq[i] = self.read_seq(i as u8);
+        }
+        for i in 0..CRTC_COUNT {
+            regs.crtc[i] = self.read_crtc(i as u8);
+        }
+        for i in 0..GC_COUNT {
+            regs.gc[i] = self.read_gc(i as u8);
+        }
+        for i in 0..ATTR_COUNT {
+            self.io.read(R::from(STATUS));
+            self.io.write(R::from(ATTR_INDEX), i as u8 | ATTR_PALETTE_SOURCE);
+            regs.attr[i] = self.io.read(R::from(ATTR_READ));
+        }
+        self.io.read(R::from(STATUS));
+        regs
+    }
+    /// Write every register of a mode
+    ///
+    /// The sequencer is held in reset and the display blanked while the timing changes.
+    pub unsafe fn set_registers(&mut self, regs: &Registers) {
+        self.write_seq(SEQ_RESET, 0x01);
+        self.io.write(R::from(MISC_WRITE), regs.misc);
+        for i in 1..SEQ_COUNT {
+            self.write_seq(i as u8, regs.seq[i]);
+        }
+        self.write_seq(SEQ_RESET, regs.seq[0]);
+        // Registers 0 to 7 are protected until this is cleared, and must stay unprotected
+        let sync_end = self.read_crtc(CRTC_VERTICAL_SYNC_END);
+        self.write_crtc(CRTC_VERTICAL_SYNC_END, sync_end & !CRTC_PROTECT);
+        for i in 0..CRTC_COUNT {
+            let value = match i as u8 {
+                CRTC_END_HORIZONTAL_BLANK => regs.crtc[i] | 0x80,
+                CRTC_VERTICAL_SYNC_END => regs.crtc[i] & !CRTC_PROTECT,
+                _ => regs.crtc[i],
+            };
+            self.write_crtc(i as u8, value);
+        }
+        for i in 0..GC_COUNT {
+            self.write_gc(i as u8, regs.gc[i]);
+        }
+        // Writing indices without the palette source blanks the display until the end
+        for i in 0..ATTR_COUNT {
+            self.io.read(R::from(STATUS));
+            self.io.write(R::from(ATTR_INDEX), i as u8);
+            self.io.write(R::from(ATTR_INDEX), regs.attr[i]);
+        }
+        self.io.read(R::from(STATUS));
+        self.io.write(R::from(ATTR_INDEX), ATTR_PALETTE_SOURCE);
+    }
+    /// Height in scan lines of the glyphs the current mode shows
+    pub unsafe fn font_height(&mut self) -> usize {
+        (self.read_crtc(CRTC_MAX_SCAN_LINE) & 0x1F) as usize + 1
+    }
+    /// Run `f` with plane 2 mapped linearly at the window, restoring text access after
+    unsafe fn with_font_plane<F: FnOnce(*mut u8)>(&mut self, f: F) {
+        let (map_mask, memory_mode) = (self.read_seq(SEQ_MAP_MASK), self.read_seq(SEQ_MEMORY_MODE));
+        let (read_map, mode, misc) = (self.read_gc(GC_READ_MAP), self.read_gc(GC_MODE), self.read_gc(GC_MISC));
+        self.write_seq(SEQ_MAP_MASK, 1 << 2);
+        // Sequential rather than odd/even addressing
+        self.write_seq(SEQ_MEMORY_MODE, 0x07);
+        self.write_gc(GC_READ_MAP, 2);
+        self.write_gc(GC_MODE, 0x00);
+        // 64KiB at 0xA0000, without chaining odd and even
+        self.write_gc(GC_MISC, 0x04);
+        f(self.window);
+        self.write_seq(SEQ_MAP_MASK, map_mask);
+        self.write_seq(SEQ_MEMORY_MODE, memory_mode);
+        self.write_gc(GC_READ_MAP, read_map);
+        self.write_gc(GC_MODE, mode);
+        self.write_gc(GC_MISC, misc);
+    }
+    /// Load 256 glyphs of `height` scan lines, one byte a line, into the first font slot
+    pub unsafe fn load_font(&mut self, font: &[u8], height: usize) -> Result<(), Error> {
+        if height == 0 || height > GLYPH_STRIDE || font.len() != GLYPHS * height {
+            return Err(Error::BadFont);
+        }
+        self.with_font_plane(|window| {
+            for (glyph, lines) in font.chunks(height).enumerate() {
+                let base = window.offset((glyph * GLYPH_STRIDE) as isize);
+                for offset in 0..GLYPH_STRIDE {
+                    let line = lines.get(offset).cloned().unwrap_or(0);
+                    ptr::write_volatile(base.offset(offset as isize), line);
+                }
+            }
+        });
+        Ok(())
+    }
+    /// Read the 256 glyphs of the first font slot, at `height` scan lines each
+    pub unsafe fn read_font(&mut self, font: &mut [u8], height: usize) -> Result<(), Error> {
+        if height == 0 || height > GLYPH_STRIDE || font.len() != GLYPHS * height {
+            return Err(Error::BadFont);
+        }
+        self.with_font_plane(|window| {
+            for (glyph, lines) in font.chunks_mut(height).enumerate() {
+                let base = window.offset((glyph * GLYPH_STRIDE) as isize);
+                for (offset, line) in lines.iter_mut().enumerate() {
+                    *line = ptr::read_volatile(base.offset(offset as isize));
+                }
+            }
+        });
+        Ok(())
+    }
+    /// Switch to a text mode, with a font of the height it needs
+    pub unsafe fn set_text_mode(&mut self, mode: TextMode, font: &[u8]) -> Result<(), Error> {
+        if font.len() != GLYPHS * mode.font_height() {
+            return Err(Error::BadFont);
+        }
+        self.set_registers(mode.registers());
+        self.load_font(font, mode.font_height())
+    }
+}
@@ -0,0 +1,154 @@
+//! Virtio block device
+//!
+//! Requests are a chain of a header giving the operation and sector, the data, and a status
+//! byte the device writes on completion. Only a single request queue is used, with each request
+//! waited on before the next is made. Data goes through a bounce buffer, so that callers can
+//! use any memory, and larger transfers are split to fit it.
+
+use block::{self, BlockDevice};
+use core::ptr;
+use drivers::dma::Dma;
+use drivers::io::MixedIo;
+use drivers::virtio::{Buffer, Error, Transport, Virtqueue};
+use vspace::Translation;
+
+/// Device is read only
+pub const F_RO: u64 = 1 << 5;
+/// Device reports its optimal block size
+pub const F_BLK_SIZE: u64 = 1 << 6;
+/// Device has a write cache that can be flushed
+pub const F_FLUSH: u64 = 1 << 9;
+
+/// Capacity, in 512 byte sectors, in the device configuration
+const CONFIG_CAPACITY: usize = 0;
+const CONFIG_BLK_SIZE: usize = 20;
+
+const TYPE_IN: u32 = 0;
+const TYPE_OUT: u32 = 1;
+const TYPE_FLUSH: u32 = 4;
+
+const STATUS_OK: u8 = 0;
+const STATUS_UNSUPPORTED: u8 = 2;
+
+/// Requests always address 512 byte sectors, whatever the block size of the device
+pub const SECTOR_SIZE: usize = 512;
+
+const HEADER_SIZE: usize = 16;
+/// Largest transfer made in one request
+const BOUNCE_SIZE: usize = 64 * 1024;
+const QUEUE_SIZE: u16 = 16;
+
+pub struct Blk<T: MixedIo> {
+    transport: Transport<T>,
+    queue: Virtqueue,
+    header: Dma,
+    status: Dma,
+    bounce: Dma,
+    capacity: u64,
+    block_size: u32,
+}
+
+impl<T: MixedIo> Blk<T> {
+    /// Set up the device and its request queue
+    ///
+    /// `translation` is passed to `Dma::new`.
+    pub unsafe fn new(mut transport: Transport<T>, translation: &Translation) -> Result<Blk<T>, Error> {
+        let (capacity, block_size, queue, header, status, bounce) =
+            transport.initialize(F_RO | F_BLK_SIZE | F_FLUSH, |transport| Self::setup(transport, translation))?;
+        Ok(Blk {
+            transport: transport,
+            queue: queue,
+            header: header,
+            status: status,
+            bounce: bounce,
+            capacity: capacity,
+            block_size: block_size,
+        })
+    }
+    unsafe fn setup(transport: &mut Transport<T>, translation: &Translation) -> Result<(u64, u32, Virtqueue, Dma, Dma, Dma), Error> {
+        let features = transport.features();
+        let (capacity, block_size) = transport.read_config(|config| {
+            let capacity = config.read32(CONFIG_CAPACITY) as u64 | (config.read32(CONFIG_CAPACITY + 4) as u64) << 32;
+            let block_size = if features & F_BLK_SIZE != 0 { config.read32(CONFIG_BLK_SIZE) } else { SECTOR_SIZE as u32 };
+            (capacity, block_size)
+        })?;
+        // Requests are waited on by polling
+        let queue = transport.setup_polled_queue(translation, 0, QUEUE_SIZE)?;
+        let header = Dma::new(translation, HEADER_SIZE, HEADER_SIZE).ok_or(Error::NoMemory)?;
+        let status = Dma::new(translation, 1, 1).ok_or(Error::NoMemory)?;
+        let bounce = Dma::new(translation, BOUNCE_SIZE, SECTOR_SIZE).ok_or(Error::NoMemory)?;
+        Ok((capacity, block_size, queue, header, status, bounce))
+    }
+    /// Optimal block size the device reported, which can be larger than a sector
+    pub fn block_size(&self) -> u32 {
+        self.block_size
+    }
+    /// Make a single request, with `len` bytes of data in the bounce buffer
+    unsafe fn request(&mut self, kind: u32, sector: u64, len: usize) -> Result<(), block::Error> {
+        {
+            let header = self.header.as_mut_slice();
+            header[0..4].copy_from_slice(&[kind as u8, (kind >> 8) as u8, (kind >> 16) as u8, (kind >> 24) as u8]);
+            for i in 0..8 {
+                header[8 + i] = (sector >> (i * 8)) as u8;
+            }
+        }
+        ptr::write_volatile(self.status.vaddr(), 0xFF);
+        let header = Buffer::readable(self.header.paddr(), HEADER_SIZE as u32);
+        let status = Buffer::writable(self.status.paddr(), 1);
+        let chain = match kind {
+            TYPE_FLUSH => self.queue.add(&[header, status]),
+            TYPE_IN => self.queue.add(&[header, Buffer::writable(self.bounce.paddr(), len as u32), status]),
+            _ => self.queue.add(&[header, Buffer::readable(self.bounce.paddr(), len as u32), status]),
+        };
+        chain.expect("Block queue full with only one request");
+        self.transport.notify(&self.queue);
+        self.queue.wait_used();
+        match ptr::read_volatile(self.status.vaddr()) {
+            STATUS_OK => Ok(()),
+            STATUS_UNSUPPORTED => Err(block::Error::Unsupported),
+            _ => Err(block::Error::Io),
+        }
+    }
+}
+
+impl<T: MixedIo> BlockDevice for Blk<T> {
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+    fn sectors(&self) -> u64 {
+        self.capacity
+    }
+    fn read_only(&self) -> bool {
+        self.transport.features() & F_RO != 0
+    }
+    unsafe fn read(&mut self, sector: u64, buffer: &mut [u8]) -> Result<(), block::Error> {
+        for (index, chunk) in buffer.chunks_mut(BOUNCE_SIZE).enumerate() {
+            let offset = (index * BOUNCE_SIZE / SECTOR_SIZE) as u64;
+            self.request(TYPE_IN, sector + offset, chunk.len())?;
+            chunk.copy_from_slice(&self.bounce.as_slice()[..chunk.len()]);
+        }
+        Ok(())
+    }
+    unsafe fn write(&mut self, sector: u64, buffer: &[u8]) -> Result<(), block::Error> {
+        for (index, chunk) in buffer.chunks(BOUNCE_SIZE).enumerate() {
+            let offset = (index * BOUNCE_SIZE / SECTOR_SIZE) as u64;
+            self.bounce.as_mut_slice()[..chunk.len()].copy_from_slice(chunk);
+            self.request(TYPE_OUT, sector + offset, chunk.len())?;
+        }
+        Ok(())
+    }
+    unsafe fn flush(&mut self) -> Result<(), block::Error> {
+        if self.transport.features() & F_FLUSH == 0 {
+            // Without a write cache writes are already persistent
+            return Ok(());
+        }
+        self.request(TYPE_FLUSH, 0, 0)
+    }
+}
+
+impl<T: MixedIo> Drop for Blk<T> {
+    fn drop(&mut self) {
+        // Stop the device before the memory it has been given is freed
+        let _ = unsafe {self.transport.reset()};
+    }
+}
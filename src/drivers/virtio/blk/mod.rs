@@ -0,0 +1,42 @@
+//! Virtio block devices
+//!
+//! Each device found is registered as a disk, named from `vd`.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use block;
+use bus::Device;
+use decls::Match;
+use drivers::pci::Address;
+use super::{device_id, VENDOR, TYPE_BLOCK, TRANSITIONAL_BLOCK};
+
+mod device;
+
+pub use self::device::{Blk, F_RO, F_BLK_SIZE, F_FLUSH, SECTOR_SIZE};
+
+/// Disks registered, by the function they are on
+static mut DISKS: Option<Vec<(Address, String)>> = None;
+
+fn probe(device: &Device) -> bool {
+    match super::probe("virtio-blk", device, |transport, translation| unsafe {Blk::new(transport, translation)}) {
+        Some((address, blk)) => {
+            let name = block::register("vd", box blk);
+            unsafe {DISKS.get_or_insert_with(Vec::new)}.push((address, name));
+            true
+        },
+        None => false,
+    }
+}
+
+fn remove(device: &Device) {
+    if let Device::Pci(function) = *device {
+        let disks = unsafe {DISKS.get_or_insert_with(Vec::new)};
+        if let Some(index) = disks.iter().position(|disk| disk.0 == function.address) {
+            let (_, name) = disks.remove(index);
+            block::unregister(&name);
+        }
+    }
+}
+
+make_driver_decl!("virtio-blk", &[Match::pci(VENDOR, device_id(TYPE_BLOCK)), Match::pci(VENDOR, TRANSITIONAL_BLOCK)],
+    probe, remove, VIRTIO_BLK_DRIVER);
@@ -0,0 +1,361 @@
+//! Virtio console device
+//!
+//! With the multiport feature the device has a number of ports, each a separate byte stream
+//! with its own pair of queues, which the host announces over a pair of control queues and
+//! may give names to. Without it there is only port 0.
+//!
+//! Like the network device, receive queues are kept full of buffers and everything is polled.
+//! Received data is copied out into a per port queue until it is read, and writes are copied
+//! into transmit buffers, so neither waits on the host.
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use drivers::io::MixedIo;
+use drivers::virtio::{Error, Pool, Transport};
+use vspace::Translation;
+
+/// Device reports the size of the console
+pub const F_SIZE: u64 = 1 << 0;
+/// Device has more than one port, and control queues
+pub const F_MULTIPORT: u64 = 1 << 1;
+
+const CONFIG_MAX_NR_PORTS: usize = 4;
+
+/// Most ports set up, beyond which ports the device offers are ignored
+pub const MAX_PORTS: u32 = 8;
+
+const CONTROL_RX_QUEUE: u16 = 2;
+const CONTROL_TX_QUEUE: u16 = 3;
+
+pub const EVENT_DEVICE_READY: u16 = 0;
+pub const EVENT_DEVICE_ADD: u16 = 1;
+pub const EVENT_DEVICE_REMOVE: u16 = 2;
+pub const EVENT_PORT_READY: u16 = 3;
+pub const EVENT_CONSOLE_PORT: u16 = 4;
+pub const EVENT_RESIZE: u16 = 5;
+pub const EVENT_PORT_OPEN: u16 = 6;
+pub const EVENT_PORT_NAME: u16 = 7;
+
+/// Size of `virtio_console_control`, which a name follows
+pub const CONTROL_SIZE: usize = 8;
+/// Control buffers hold a message and a name
+const CONTROL_BUFFER_SIZE: usize = 256;
+const CONTROL_QUEUE_SIZE: u16 = 8;
+
+const BUFFER_SIZE: usize = 1024;
+const RX_QUEUE_SIZE: u16 = 8;
+const TX_QUEUE_SIZE: u16 = 32;
+/// Most received bytes kept for a port that is not being read, beyond which more are dropped
+const MAX_RECEIVED: usize = 64 * 1024;
+
+/// Polls for a control buffer to come back before a message is given up on
+const CONTROL_POLL_LIMIT: usize = 1000000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortError {
+    /// Device has not announced the port
+    NoPort,
+}
+
+pub struct Port {
+    id: u32,
+    name: Option<String>,
+    /// Announced by the device, which ports without multiport always are
+    present: bool,
+    console: bool,
+    host_open: bool,
+    guest_open: bool,
+    rx: Pool,
+    tx: Pool,
+    /// Transmit buffers not with the device
+    tx_free: Vec<usize>,
+    received: VecDeque<u8>,
+    /// Received bytes dropped as nothing read them
+    dropped: usize,
+}
+
+impl Port {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    /// Name the host gave the port, such as `org.rlk.shell`
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|name| name.as_str())
+    }
+    /// Whether the host marked this as the console port
+    pub fn is_console(&self) -> bool {
+        self.console
+    }
+    /// Whether something on the host end has the port open
+    pub fn host_open(&self) -> bool {
+        self.host_open
+    }
+    pub fn guest_open(&self) -> bool {
+        self.guest_open
+    }
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+    unsafe fn complete_tx(&mut self) {
+        while let Some((slot, _)) = self.tx.pop() {
+            self.tx_free.push(slot);
+        }
+    }
+}
+
+/// Receive and transmit queue of a port
+fn port_queues(id: u32) -> (u16, u16) {
+    match id {
+        0 => (0, 1),
+        id => (2 * id as u16 + 2, 2 * id as u16 + 3),
+    }
+}
+
+pub struct Console<T: MixedIo> {
+    transport: Transport<T>,
+    /// Control receive and transmit queues, with multiport
+    control: Option<(Pool, Pool)>,
+    control_free: Vec<usize>,
+    /// Every port the queues were set up for, by ID
+    ports: Vec<Port>,
+}
+
+impl<T: MixedIo> Console<T> {
+    /// Set up the device and the queues of every port it may have, and start it
+    ///
+    /// With multiport the device is then told the driver is ready, and announces its ports,
+    /// which are only usable once `poll` has seen them.
+    ///
+    /// `translation` is passed to `Dma::new`.
+    pub unsafe fn new(mut transport: Transport<T>, translation: &Translation) -> Result<Console<T>, Error> {
+        let (control, ports) = transport.initialize(F_MULTIPORT, |transport| Self::setup(transport, translation))?;
+        let multiport = control.is_some();
+        let control_free = control.as_ref().map_or(Vec::new(), |control| (0..control.1.len()).collect());
+        let mut console = Console { transport: transport, control: control, control_free: control_free, ports: ports };
+        // The device must not be notified of buffers before it is running
+        if let Some((ref mut rx, _)) = console.control {
+            rx.fill();
+            console.transport.notify(rx.queue());
+        }
+        for port in console.ports.iter_mut() {
+            port.rx.fill();
+            console.transport.notify(port.rx.queue());
+        }
+        if multiport {
+            console.send_control(0xFFFF_FFFF, EVENT_DEVICE_READY, 1);
+            console.poll();
+        } else {
+            console.ports[0].present = true;
+        }
+        Ok(console)
+    }
+    unsafe fn setup(transport: &mut Transport<T>, translation: &Translation) -> Result<(Option<(Pool, Pool)>, Vec<Port>), Error> {
+        let (control, count) = if transport.features() & F_MULTIPORT != 0 {
+            let rx = transport.setup_polled_queue(translation, CONTROL_RX_QUEUE, CONTROL_QUEUE_SIZE)?;
+            let tx = transport.setup_polled_queue(translation, CONTROL_TX_QUEUE, CONTROL_QUEUE_SIZE)?;
+            let control = (Pool::new(rx, translation, CONTROL_BUFFER_SIZE)?, Pool::new(tx, translation, CONTROL_BUFFER_SIZE)?);
+            let max = transport.read_config(|config| config.read32(CONFIG_MAX_NR_PORTS))?;
+            (Some(control), max.min(MAX_PORTS).max(1))
+        } else {
+            (None, 1)
+        };
+        let mut ports = Vec::with_capacity(count as usize);
+        for id in 0..count {
+            let (rx, tx) = port_queues(id);
+            let rx = transport.setup_polled_queue(translation, rx, RX_QUEUE_SIZE)?;
+            let tx = transport.setup_polled_queue(translation, tx, TX_QUEUE_SIZE)?;
+            let tx = Pool::new(tx, translation, BUFFER_SIZE)?;
+            let tx_free = (0..tx.len()).collect();
+            ports.push(Port {
+                id: id,
+                name: None,
+                present: false,
+                console: false,
+                host_open: false,
+                guest_open: false,
+                rx: Pool::new(rx, translation, BUFFER_SIZE)?,
+                tx: tx,
+                tx_free: tx_free,
+                received: VecDeque::new(),
+                dropped: 0,
+            });
+        }
+        Ok((control, ports))
+    }
+    pub fn multiport(&self) -> bool {
+        self.control.is_some()
+    }
+    /// Ports the device has announced
+    pub fn ports<'a>(&'a self) -> impl Iterator<Item = &'a Port> + 'a {
+        self.ports.iter().filter(|port| port.present)
+    }
+    pub fn port(&self, id: u32) -> Option<&Port> {
+        self.ports.get(id as usize).filter(|port| port.present)
+    }
+    /// Port with the name the host gave it
+    pub fn find(&self, name: &str) -> Option<u32> {
+        self.ports().find(|port| port.name() == Some(name)).map(|port| port.id)
+    }
+    /// Port the host marked as the console, or port 0
+    pub fn console_port(&self) -> Option<u32> {
+        self.ports().find(|port| port.console).or_else(|| self.port(0)).map(|port| port.id)
+    }
+    fn present_port(&mut self, id: u32) -> Result<&mut Port, PortError> {
+        self.ports.get_mut(id as usize).filter(|port| port.present).ok_or(PortError::NoPort)
+    }
+    /// Send a control message, waiting for a buffer if they are all with the device
+    unsafe fn send_control(&mut self, id: u32, event: u16, value: u16) {
+        let mut polls = 0;
+        let slot = loop {
+            let tx = &mut self.control.as_mut().expect("Control message without multiport").1;
+            while let Some((slot, _)) = tx.pop() {
+                self.control_free.push(slot);
+            }
+            if let Some(slot) = self.control_free.pop() {
+                break slot;
+            }
+            polls += 1;
+            if polls == CONTROL_POLL_LIMIT {
+                print!(Error, "virtio-console did not return a control buffer, dropping event {}", event);
+                return;
+            }
+        };
+        let tx = &mut self.control.as_mut().unwrap().1;
+        {
+            let buffer = tx.buffer(slot);
+            for i in 0..4 {
+                buffer[i] = (id >> (i * 8)) as u8;
+            }
+            buffer[4] = event as u8;
+            buffer[5] = (event >> 8) as u8;
+            buffer[6] = value as u8;
+            buffer[7] = (value >> 8) as u8;
+        }
+        tx.add(slot, CONTROL_SIZE, false);
+        self.transport.notify(tx.queue());
+    }
+    /// Take the next message the device sent on the control queue, handing its buffer back
+    unsafe fn next_control(&mut self) -> Option<(u32, u16, u16, Vec<u8>)> {
+        let rx = &mut self.control.as_mut()?.0;
+        let (slot, len) = rx.pop()?;
+        let message = rx.buffer(slot)[..len.min(CONTROL_BUFFER_SIZE)].to_vec();
+        rx.add(slot, CONTROL_BUFFER_SIZE, true);
+        self.transport.notify(rx.queue());
+        if message.len() < CONTROL_SIZE {
+            return Some((0xFFFF_FFFF, 0xFFFF, 0, Vec::new()));
+        }
+        let id = message[0] as u32 | (message[1] as u32) << 8 | (message[2] as u32) << 16 | (message[3] as u32) << 24;
+        let event = message[4] as u16 | (message[5] as u16) << 8;
+        let value = message[6] as u16 | (message[7] as u16) << 8;
+        Some((id, event, value, message[CONTROL_SIZE..].to_vec()))
+    }
+    unsafe fn handle_control(&mut self, id: u32, event: u16, value: u16, data: Vec<u8>) {
+        if id as usize >= self.ports.len() {
+            if event == EVENT_DEVICE_ADD {
+                // Tell the device the port cannot be used
+                self.send_control(id, EVENT_PORT_READY, 0);
+            }
+            return;
+        }
+        {
+            let port = &mut self.ports[id as usize];
+            match event {
+                EVENT_DEVICE_ADD => port.present = true,
+                EVENT_DEVICE_REMOVE => {
+                    port.present = false;
+                    port.name = None;
+                    port.console = false;
+                    port.host_open = false;
+                    port.guest_open = false;
+                    port.received.clear();
+                },
+                EVENT_CONSOLE_PORT => port.console = true,
+                EVENT_PORT_OPEN => port.host_open = value != 0,
+                EVENT_PORT_NAME => port.name = Some(String::from_utf8_lossy(&data).into_owned()),
+                _ => (),
+            }
+        }
+        if event == EVENT_DEVICE_ADD {
+            self.send_control(id, EVENT_PORT_READY, 1);
+        } else if event == EVENT_CONSOLE_PORT {
+            // A console port is always open, from the guest end
+            self.open(id).ok();
+        }
+    }
+    /// Handle what the device has sent, and take back the buffers it has finished with
+    pub unsafe fn poll(&mut self) {
+        while let Some((id, event, value, data)) = self.next_control() {
+            self.handle_control(id, event, value, data);
+        }
+        for port in self.ports.iter_mut() {
+            while let Some((slot, len)) = port.rx.pop() {
+                if port.present {
+                    let room = MAX_RECEIVED - port.received.len();
+                    let len = len.min(BUFFER_SIZE);
+                    port.received.extend(port.rx.buffer(slot)[..len.min(room)].iter());
+                    port.dropped += len - len.min(room);
+                }
+                port.rx.add(slot, BUFFER_SIZE, true);
+                self.transport.notify(port.rx.queue());
+            }
+            port.complete_tx();
+        }
+    }
+    /// Tell the host the port is open at this end
+    pub unsafe fn open(&mut self, id: u32) -> Result<(), PortError> {
+        self.present_port(id)?.guest_open = true;
+        if self.multiport() {
+            self.send_control(id, EVENT_PORT_OPEN, 1);
+        }
+        Ok(())
+    }
+    pub unsafe fn close(&mut self, id: u32) -> Result<(), PortError> {
+        self.present_port(id)?.guest_open = false;
+        if self.multiport() {
+            self.send_control(id, EVENT_PORT_OPEN, 0);
+        }
+        Ok(())
+    }
+    /// Queue as much of `data` as there are transmit buffers for, returning how much that was
+    pub unsafe fn write(&mut self, id: u32, data: &[u8]) -> Result<usize, PortError> {
+        let written = {
+            let port = self.present_port(id)?;
+            port.complete_tx();
+            let mut written = 0;
+            while written < data.len() {
+                let slot = match port.tx_free.pop() {
+                    Some(slot) => slot,
+                    None => break,
+                };
+                let len = (data.len() - written).min(BUFFER_SIZE);
+                port.tx.buffer(slot)[..len].copy_from_slice(&data[written..written + len]);
+                port.tx.add(slot, len, false);
+                written += len;
+            }
+            written
+        };
+        if written > 0 {
+            let port = &self.ports[id as usize];
+            self.transport.notify(port.tx.queue());
+        }
+        Ok(written)
+    }
+    /// Copy out received data, returning how much there was
+    pub unsafe fn read(&mut self, id: u32, buffer: &mut [u8]) -> Result<usize, PortError> {
+        self.poll();
+        let port = self.present_port(id)?;
+        let len = buffer.len().min(port.received.len());
+        for (byte, received) in buffer.iter_mut().zip(port.received.drain(..len)) {
+            *byte = received;
+        }
+        Ok(len)
+    }
+}
+
+impl<T: MixedIo> Drop for Console<T> {
+    fn drop(&mut self) {
+        // The device must stop using the queues before they are freed
+        let _ = unsafe {self.transport.reset()};
+    }
+}
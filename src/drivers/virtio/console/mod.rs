@@ -0,0 +1,106 @@
+//! Virtio console devices
+//!
+//! Devices are kept here, rather than registered anywhere, and their ports opened by name
+//! through `ConsolePort`.
+
+use alloc::vec::Vec;
+use bus::Device;
+use core::time::Duration;
+use decls::Match;
+use drivers::io::MemIO;
+use drivers::pci::Address;
+use super::{device_id, VENDOR, TYPE_CONSOLE, TRANSITIONAL_CONSOLE};
+use time;
+
+mod device;
+
+pub use self::device::{Console, Port, PortError, F_SIZE, F_MULTIPORT, MAX_PORTS};
+
+/// Names of the ports used for each kind of traffic, given with `virtserialport,name=`
+pub const PORT_LOG: &str = "org.rlk.log";
+pub const PORT_SHELL: &str = "org.rlk.shell";
+pub const PORT_DEBUG: &str = "org.rlk.debug";
+
+/// Milliseconds a new console device is given to announce its ports
+const PORTS_TIMEOUT_MS: u64 = 10;
+
+static mut CONSOLES: Option<Vec<(Address, Console<MemIO<u8>>)>> = None;
+
+fn consoles() -> &'static mut Vec<(Address, Console<MemIO<u8>>)> {
+    unsafe {CONSOLES.get_or_insert_with(Vec::new)}
+}
+
+fn probe(device: &Device) -> bool {
+    let probed = super::probe("virtio-console", device, |transport, translation| unsafe {Console::new(transport, translation)});
+    let (address, mut console) = match probed {
+        Some(console) => console,
+        None => return false,
+    };
+    // Ports are announced, and then named, in response to the driver
+    let end = time::uptime() + Duration::from_millis(PORTS_TIMEOUT_MS);
+    while time::uptime() < end {
+        unsafe {console.poll()};
+    }
+    for port in console.ports() {
+        print!(Info, "virtio-console {} port {} {}{}", address, port.id(), port.name().unwrap_or("unnamed"),
+            if port.is_console() { " (console)" } else { "" });
+    }
+    consoles().push((address, console));
+    true
+}
+
+fn remove(device: &Device) {
+    if let Device::Pci(function) = *device {
+        consoles().retain(|console| console.0 != function.address);
+    }
+}
+
+make_driver_decl!("virtio-console", &[Match::pci(VENDOR, device_id(TYPE_CONSOLE)), Match::pci(VENDOR, TRANSITIONAL_CONSOLE)],
+    probe, remove, VIRTIO_CONSOLE_DRIVER);
+
+/// A port of a console device
+///
+/// Ports are looked up on every use, so one whose device has gone away reads and writes
+/// nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsolePort {
+    device: Address,
+    id: u32,
+}
+
+impl ConsolePort {
+    /// Open the first port with the given name, such as `PORT_SHELL`
+    pub fn open(name: &str) -> Option<ConsolePort> {
+        let port = consoles().iter().filter_map(|console| console.1.find(name).map(|id| ConsolePort { device: console.0, id: id }))
+            .next()?;
+        port.with(|console, id| unsafe {console.open(id)}.ok())?;
+        Some(port)
+    }
+    /// Open the port the host marked as the console of the first device
+    pub fn console() -> Option<ConsolePort> {
+        let port = consoles().iter().filter_map(|console| console.1.console_port().map(|id| ConsolePort { device: console.0, id: id }))
+            .next()?;
+        port.with(|console, id| unsafe {console.open(id)}.ok())?;
+        Some(port)
+    }
+    fn with<F, R>(&self, f: F) -> Option<R> where F: FnOnce(&mut Console<MemIO<u8>>, u32) -> Option<R> {
+        let console = consoles().iter_mut().find(|console| console.0 == self.device)?;
+        f(&mut console.1, self.id)
+    }
+    /// Queue as much of `data` as the device has room for, returning how much that was
+    pub fn write(&self, data: &[u8]) -> usize {
+        self.with(|console, id| unsafe {console.write(id, data)}.ok()).unwrap_or(0)
+    }
+    /// Copy out what has been received, returning how much that was
+    pub fn read(&self, buffer: &mut [u8]) -> usize {
+        self.with(|console, id| unsafe {console.read(id, buffer)}.ok()).unwrap_or(0)
+    }
+    /// Handle what the device has sent, such as the host opening or closing the port
+    pub fn poll(&self) {
+        self.with(|console, _| Some(unsafe {console.poll()}));
+    }
+    /// Whether something on the host end has the port open
+    pub fn host_open(&self) -> bool {
+        self.with(|console, id| console.port(id).map(|port| port.host_open())).unwrap_or(false)
+    }
+}
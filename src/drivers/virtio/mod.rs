@@ -0,0 +1,100 @@
+//! Virtio devices over PCI
+//!
+//! Only the modern (1.0) interface is supported, where the device structures are found through
+//! vendor capabilities rather than in an I/O BAR. The transport and queues are generic, the
+//! device drivers are built on top of them.
+
+use bus::{self, Device};
+use drivers::io::MemIO;
+use drivers::pci::{Address, Command, Function};
+use state::STATE;
+use vspace::Translation;
+
+mod pci;
+mod queue;
+mod transport;
+mod pool;
+pub mod blk;
+pub mod console;
+pub mod net;
+pub mod rng;
+
+pub use self::pci::{layout, Layout, Region};
+pub use self::queue::{Buffer, Virtqueue, MAX_SIZE};
+pub use self::transport::*;
+pub use self::pool::Pool;
+
+/// PCI vendor ID of virtio devices
+pub const VENDOR: u16 = 0x1AF4;
+/// PCI device ID of a modern device is this plus the device type
+pub const MODERN_DEVICE_BASE: u16 = 0x1040;
+
+pub const TYPE_NET: u16 = 1;
+pub const TYPE_BLOCK: u16 = 2;
+pub const TYPE_CONSOLE: u16 = 3;
+pub const TYPE_ENTROPY: u16 = 4;
+
+/// PCI device IDs of transitional devices, which have both interfaces
+const TRANSITIONAL_NET: u16 = 0x1000;
+const TRANSITIONAL_BLOCK: u16 = 0x1001;
+const TRANSITIONAL_CONSOLE: u16 = 0x1003;
+const TRANSITIONAL_ENTROPY: u16 = 0x1005;
+
+/// PCI device ID of the modern interface of a device type
+pub const fn device_id(device_type: u16) -> u16 {
+    MODERN_DEVICE_BASE + device_type
+}
+
+fn map_region(function: &Function, region: Region) -> Option<MemIO<u8>> {
+    let (_, size) = function.bars[region.bar as usize]?.memory()?;
+    if region.offset as u64 + region.length as u64 > size {
+        return None;
+    }
+    let vaddr = bus::pci::map_bar(function, region.bar)?;
+    Some(unsafe {MemIO::new(vaddr + region.offset as usize)})
+}
+
+/// Find and map the structures of a function, and enable it to access memory
+///
+/// The device is not reset, that happens when features are negotiated.
+pub fn open(function: &Function) -> Option<Transport<MemIO<u8>>> {
+    let mut config = bus::pci::config(function);
+    let layout = unsafe {layout(function, &mut config)}?;
+    let device = match layout.device {
+        Some(region) => Some(map_region(function, region)?),
+        None => None,
+    };
+    let transport = Transport::new(
+        map_region(function, layout.common)?,
+        map_region(function, layout.notify)?,
+        layout.notify_multiplier,
+        map_region(function, layout.isr)?,
+        device);
+    unsafe {config.enable(Command::MEMORY | Command::BUS_MASTER)};
+    Some(transport)
+}
+
+/// Open a device found on the bus and set up its driver with `new`
+///
+/// The common part of probing every device type, which reports why a device could not be used.
+/// Returns the driver along with the function it is on, for the caller to register.
+pub fn probe<D, F>(name: &str, device: &Device, new: F) -> Option<(Address, D)> where F: FnOnce(Transport<MemIO<u8>>, &Translation) -> Result<D, Error> {
+    let function = match *device {
+        Device::Pci(function) => function,
+        _ => return None,
+    };
+    let transport = match open(function) {
+        Some(transport) => transport,
+        None => {
+            print!(Error, "{} {} has no modern interface", name, function.address);
+            return None;
+        },
+    };
+    match new(transport, unsafe {&STATE.kernel_as}) {
+        Ok(driver) => Some((function.address, driver)),
+        Err(error) => {
+            print!(Error, "Failed to set up {} {}: {:?}", name, function.address, error);
+            None
+        },
+    }
+}
@@ -0,0 +1,140 @@
+//! Virtio network device
+//!
+//! Frames are preceded by a `virtio_net_hdr`, which is left zeroed as no offloads are
+//! negotiated. The receive queue is kept full of buffers, each being handed back to the device
+//! as soon as its frame has been copied out. Transmitted frames are copied into a buffer of the
+//! transmit queue, and buffers are reclaimed from the device when more are needed.
+//!
+//! Both queues are polled.
+
+use alloc::vec::Vec;
+use drivers::io::MixedIo;
+use drivers::virtio::{Error, Pool, Transport};
+use net::{device, MacAddress, NetDevice};
+use vspace::Translation;
+
+/// Device reports its MAC address
+pub const F_MAC: u64 = 1 << 5;
+/// Device reports whether the link is up
+pub const F_STATUS: u64 = 1 << 16;
+
+const CONFIG_MAC: usize = 0;
+const CONFIG_STATUS: usize = 6;
+const STATUS_LINK_UP: u16 = 1;
+
+/// Size of `virtio_net_hdr`, which with version 1 always includes `num_buffers`
+pub const HEADER_SIZE: usize = 12;
+/// Largest frame, without the FCS
+const MAX_FRAME: usize = 1514;
+/// Each buffer holds a header and a whole frame, rounded up to keep them aligned
+const BUFFER_SIZE: usize = 1536;
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+const RX_QUEUE_SIZE: u16 = 32;
+const TX_QUEUE_SIZE: u16 = 16;
+
+/// Address used when the device does not provide one, which is locally administered
+const DEFAULT_MAC: MacAddress = MacAddress([0x02, 0, 0, 0, 0, 1]);
+
+pub struct Net<T: MixedIo> {
+    transport: Transport<T>,
+    rx: Pool,
+    tx: Pool,
+    /// Transmit buffers not with the device
+    tx_free: Vec<usize>,
+    mac: MacAddress,
+}
+
+impl<T: MixedIo> Net<T> {
+    /// Set up the device, fill its receive queue, and start it
+    ///
+    /// `translation` is passed to `Dma::new`.
+    pub unsafe fn new(mut transport: Transport<T>, translation: &Translation) -> Result<Net<T>, Error> {
+        let (mac, mut rx, tx) = transport.initialize(F_MAC | F_STATUS, |transport| Self::setup(transport, translation))?;
+        // The device must not be notified of buffers before it is running
+        rx.fill();
+        transport.notify(rx.queue());
+        let tx_free = (0..tx.len()).collect();
+        Ok(Net { transport: transport, rx: rx, tx: tx, tx_free: tx_free, mac: mac })
+    }
+    unsafe fn setup(transport: &mut Transport<T>, translation: &Translation) -> Result<(MacAddress, Pool, Pool), Error> {
+        let mac = if transport.features() & F_MAC != 0 {
+            transport.read_config(|config| {
+                let mut mac = [0; 6];
+                for (i, byte) in mac.iter_mut().enumerate() {
+                    *byte = config.read8(CONFIG_MAC + i);
+                }
+                MacAddress(mac)
+            })?
+        } else {
+            DEFAULT_MAC
+        };
+        let rx = transport.setup_polled_queue(translation, RX_QUEUE, RX_QUEUE_SIZE)?;
+        let tx = transport.setup_polled_queue(translation, TX_QUEUE, TX_QUEUE_SIZE)?;
+        Ok((mac, Pool::new(rx, translation, BUFFER_SIZE)?, Pool::new(tx, translation, BUFFER_SIZE)?))
+    }
+    /// Reclaim the transmit buffers the device has finished sending
+    unsafe fn complete_tx(&mut self) {
+        while let Some((slot, _)) = self.tx.pop() {
+            self.tx_free.push(slot);
+        }
+    }
+}
+
+impl<T: MixedIo> NetDevice for Net<T> {
+    fn name(&self) -> &'static str {
+        "virtio-net"
+    }
+    fn mac(&self) -> MacAddress {
+        self.mac
+    }
+    fn mtu(&self) -> usize {
+        1500
+    }
+    unsafe fn link_up(&mut self) -> bool {
+        if self.transport.features() & F_STATUS == 0 {
+            // Without status the link is assumed to always be up
+            return true;
+        }
+        self.transport.read_config(|config| config.read16(CONFIG_STATUS) & STATUS_LINK_UP != 0).unwrap_or(false)
+    }
+    unsafe fn transmit(&mut self, frame: &[u8]) -> Result<(), device::Error> {
+        if frame.len() > MAX_FRAME {
+            return Err(device::Error::TooLarge);
+        }
+        self.complete_tx();
+        let slot = self.tx_free.pop().ok_or(device::Error::Busy)?;
+        {
+            let buffer = self.tx.buffer(slot);
+            for byte in buffer[..HEADER_SIZE].iter_mut() {
+                *byte = 0;
+            }
+            buffer[HEADER_SIZE..HEADER_SIZE + frame.len()].copy_from_slice(frame);
+        }
+        self.tx.add(slot, HEADER_SIZE + frame.len(), false);
+        self.transport.notify(self.tx.queue());
+        Ok(())
+    }
+    unsafe fn receive(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let (slot, len) = self.rx.pop()?;
+            let frame = if len > HEADER_SIZE && len <= BUFFER_SIZE {
+                Some(self.rx.buffer(slot)[HEADER_SIZE..len].to_vec())
+            } else {
+                None
+            };
+            self.rx.add(slot, BUFFER_SIZE, true);
+            self.transport.notify(self.rx.queue());
+            if frame.is_some() {
+                return frame;
+            }
+        }
+    }
+}
+
+impl<T: MixedIo> Drop for Net<T> {
+    fn drop(&mut self) {
+        // Stop the device before the memory it has been given is freed
+        let _ = unsafe {self.transport.reset()};
+    }
+}
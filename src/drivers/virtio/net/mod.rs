@@ -0,0 +1,41 @@
+//! Virtio network devices
+//!
+//! Each device found is registered as a network interface.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use bus::Device;
+use decls::Match;
+use drivers::pci::Address;
+use super::{device_id, VENDOR, TYPE_NET, TRANSITIONAL_NET};
+
+mod device;
+
+pub use self::device::{Net, F_MAC, F_STATUS, HEADER_SIZE};
+
+/// Interfaces registered, by the function they are on
+static mut INTERFACES: Option<Vec<(Address, String)>> = None;
+
+fn probe(device: &Device) -> bool {
+    match super::probe("virtio-net", device, |transport, translation| unsafe {Net::new(transport, translation)}) {
+        Some((address, net)) => {
+            let name = ::net::register(box net);
+            unsafe {INTERFACES.get_or_insert_with(Vec::new)}.push((address, name));
+            true
+        },
+        None => false,
+    }
+}
+
+fn remove(device: &Device) {
+    if let Device::Pci(function) = *device {
+        let interfaces = unsafe {INTERFACES.get_or_insert_with(Vec::new)};
+        if let Some(index) = interfaces.iter().position(|interface| interface.0 == function.address) {
+            let (_, name) = interfaces.remove(index);
+            ::net::unregister(&name);
+        }
+    }
+}
+
+make_driver_decl!("virtio-net", &[Match::pci(VENDOR, device_id(TYPE_NET)), Match::pci(VENDOR, TRANSITIONAL_NET)],
+    probe, remove, VIRTIO_NET_DRIVER);
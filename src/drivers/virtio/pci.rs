@@ -0,0 +1,85 @@
+//! Locating the virtio structures of a PCI function
+//!
+//! Each structure is described by a vendor specific capability giving the BAR it is in, and
+//! where in the BAR. A device may offer the same structure more than once, in which case the
+//! first is the preferred one.
+
+use super::super::pci::{capability, Config, Function};
+
+/// Common configuration
+pub const CAP_COMMON: u8 = 1;
+/// Queue notifications
+pub const CAP_NOTIFY: u8 = 2;
+/// Interrupt status
+pub const CAP_ISR: u8 = 3;
+/// Device specific configuration
+pub const CAP_DEVICE: u8 = 4;
+
+const CAP_CFG_TYPE: u16 = 3;
+const CAP_BAR: u16 = 4;
+const CAP_OFFSET: u16 = 8;
+const CAP_LENGTH: u16 = 12;
+/// Only in the notification capability
+const CAP_NOTIFY_MULTIPLIER: u16 = 16;
+
+/// Highest BAR index, beyond which capabilities are reserved
+const MAX_BAR: u8 = 5;
+
+/// Where a structure is within the BARs of the function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub bar: u8,
+    pub offset: u32,
+    pub length: u32,
+}
+
+/// The structures of a modern virtio device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub common: Region,
+    pub notify: Region,
+    pub notify_multiplier: u32,
+    pub isr: Region,
+    /// Not every device type has a device specific configuration
+    pub device: Option<Region>,
+}
+
+/// Find the virtio structures from the capabilities of a function
+///
+/// Returns nothing if any required structure is missing, as is the case for legacy only
+/// devices.
+pub unsafe fn layout(function: &Function, config: &mut Config) -> Option<Layout> {
+    let mut common = None;
+    let mut notify = None;
+    let mut isr = None;
+    let mut device = None;
+    let vendor = function.capabilities.iter().filter(|cap| cap.id == capability::VENDOR_SPECIFIC);
+    for cap in vendor {
+        let base = cap.offset as u16;
+        let bar = config.read_u8(base + CAP_BAR);
+        if bar > MAX_BAR {
+            continue;
+        }
+        let region = Region {
+            bar: bar,
+            offset: config.read_u32(base + CAP_OFFSET),
+            length: config.read_u32(base + CAP_LENGTH),
+        };
+        match config.read_u8(base + CAP_CFG_TYPE) {
+            CAP_COMMON if common.is_none() => common = Some(region),
+            CAP_NOTIFY if notify.is_none() =>
+                notify = Some((region, config.read_u32(base + CAP_NOTIFY_MULTIPLIER))),
+            CAP_ISR if isr.is_none() => isr = Some(region),
+            CAP_DEVICE if device.is_none() => device = Some(region),
+            _ => (),
+        }
+    }
+    let (notify, notify_multiplier) = notify?;
+    Some(Layout {
+        common: common?,
+        notify: notify,
+        notify_multiplier: notify_multiplier,
+        isr: isr?,
+        device: device,
+    })
+}
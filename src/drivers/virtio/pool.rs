@@ -0,0 +1,67 @@
+//! Queues that each own a pool of equally sized buffers
+//!
+//! Devices that stream data, rather than making requests, keep a buffer per descriptor, which
+//! is given to the device and taken back as the device returns it. Each buffer is a single
+//! descriptor.
+
+use alloc::vec::Vec;
+use drivers::dma::Dma;
+use vspace::Translation;
+use super::{Buffer, Error, Virtqueue};
+
+/// Alignment of each buffer, which keeps them cache line aligned
+const BUFFER_ALIGN: usize = 64;
+
+pub struct Pool {
+    queue: Virtqueue,
+    buffers: Dma,
+    buffer_size: usize,
+    /// Buffer given to the device under each chain ID
+    slots: Vec<Option<usize>>,
+}
+
+impl Pool {
+    /// A buffer of `buffer_size` bytes, which must be a multiple of 64, for every descriptor
+    pub fn new(queue: Virtqueue, translation: &Translation, buffer_size: usize) -> Result<Pool, Error> {
+        assert_eq!(buffer_size % BUFFER_ALIGN, 0, "Pool buffers would be misaligned");
+        let count = queue.size() as usize;
+        let buffers = Dma::new(translation, count * buffer_size, BUFFER_ALIGN).ok_or(Error::NoMemory)?;
+        Ok(Pool { queue: queue, buffers: buffers, buffer_size: buffer_size, slots: vec![None; count] })
+    }
+    pub fn queue(&self) -> &Virtqueue {
+        &self.queue
+    }
+    /// Number of buffers, which is also the number of descriptors
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+    pub fn buffer(&mut self, slot: usize) -> &mut [u8] {
+        let size = self.buffer_size;
+        &mut self.buffers.as_mut_slice()[slot * size..][..size]
+    }
+    /// Give a buffer to the device, with `len` bytes of it being used
+    ///
+    /// The device still needs notifying.
+    pub unsafe fn add(&mut self, slot: usize, len: usize, writable: bool) {
+        let paddr = self.buffers.paddr() + (slot * self.buffer_size) as u64;
+        let buffer = if writable { Buffer::writable(paddr, len as u32) } else { Buffer::readable(paddr, len as u32) };
+        let head = self.queue.add(&[buffer]).expect("More buffers than descriptors");
+        self.slots[head as usize] = Some(slot);
+    }
+    /// Give every buffer to the device, for it to write into
+    pub unsafe fn fill(&mut self) {
+        for slot in 0..self.len() {
+            let size = self.buffer_size;
+            self.add(slot, size, true);
+        }
+    }
+    /// Take back a buffer the device is done with, along with the length it wrote
+    pub unsafe fn pop(&mut self) -> Option<(usize, usize)> {
+        let (head, len) = self.queue.pop_used()?;
+        let slot = self.slots[head as usize].take().expect("Device returned a chain it was not given");
+        Some((slot, len as usize))
+    }
+}
@@ -0,0 +1,264 @@
+//! Split virtqueues
+//!
+//! A queue is three areas of memory shared with the device. The descriptor table holds buffers,
+//! chained together through their `next` fields. The driver offers the head of each chain in
+//! the available ring, and the device hands chains back in the used ring, along with how much
+//! it wrote. Free descriptors are kept in a list threaded through the table itself.
+
+use super::super::dma::Dma;
+use core::mem::size_of;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+use vspace::Translation;
+
+/// Largest queue size allowed
+pub const MAX_SIZE: u16 = 32768;
+
+/// Buffer continues in the `next` descriptor
+const DESC_F_NEXT: u16 = 1;
+/// Buffer is written by the device, rather than read
+const DESC_F_WRITE: u16 = 2;
+
+/// Driver does not want interrupts for used buffers
+const AVAIL_F_NO_INTERRUPT: u16 = 1;
+/// Device does not want notifications for available buffers
+const USED_F_NO_NOTIFY: u16 = 1;
+
+const TABLE_ALIGN: usize = 16;
+const AVAIL_ALIGN: usize = 2;
+const USED_ALIGN: usize = 4;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// A buffer given to the device, by physical address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Buffer {
+    pub paddr: u64,
+    pub len: u32,
+    /// Whether the device writes to this buffer, rather than reading it
+    pub writable: bool,
+}
+
+impl Buffer {
+    pub fn readable(paddr: u64, len: u32) -> Buffer {
+        Buffer { paddr: paddr, len: len, writable: false }
+    }
+    pub fn writable(paddr: u64, len: u32) -> Buffer {
+        Buffer { paddr: paddr, len: len, writable: true }
+    }
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    memory: Dma,
+    avail_offset: usize,
+    used_offset: usize,
+    /// Head of the free descriptor list
+    free_head: u16,
+    free: u16,
+    /// Next free slot in the available ring
+    avail_idx: u16,
+    /// Next entry of the used ring to be taken
+    last_used: u16,
+    notify_offset: u32,
+}
+
+impl Virtqueue {
+    /// Allocate queue `index` with `size` descriptors, which must be a power of 2
+    pub fn new(translation: &Translation, index: u16, size: u16) -> Option<Virtqueue> {
+        if !size.is_power_of_two() || size > MAX_SIZE {
+            return None;
+        }
+        let entries = size as usize;
+        let avail_offset = align_up(size_of::<Descriptor>() * entries, AVAIL_ALIGN);
+        let used_offset = align_up(avail_offset + 6 + 2 * entries, USED_ALIGN);
+        let total = used_offset + 6 + size_of::<UsedElem>() * entries;
+        let memory = Dma::new(translation, total, TABLE_ALIGN)?;
+        let mut queue = Virtqueue {
+            index: index,
+            size: size,
+            memory: memory,
+            avail_offset: avail_offset,
+            used_offset: used_offset,
+            free_head: 0,
+            free: size,
+            avail_idx: 0,
+            last_used: 0,
+            notify_offset: 0,
+        };
+        for i in 0..size {
+            let next = if i + 1 < size { i + 1 } else { 0 };
+            unsafe {queue.write_desc(i, Descriptor { addr: 0, len: 0, flags: 0, next: next })};
+        }
+        Some(queue)
+    }
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+    /// Number of descriptors not in use
+    pub fn free(&self) -> u16 {
+        self.free
+    }
+    pub fn desc_paddr(&self) -> u64 {
+        self.memory.paddr()
+    }
+    pub fn avail_paddr(&self) -> u64 {
+        self.memory.paddr() + self.avail_offset as u64
+    }
+    pub fn used_paddr(&self) -> u64 {
+        self.memory.paddr() + self.used_offset as u64
+    }
+    /// Offset in the notification region to write to, set up by the transport
+    pub fn notify_offset(&self) -> u32 {
+        self.notify_offset
+    }
+    pub fn set_notify_offset(&mut self, offset: u32) {
+        self.notify_offset = offset;
+    }
+    fn at<T>(&self, offset: usize) -> *mut T {
+        unsafe {self.memory.vaddr().offset(offset as isize) as *mut T}
+    }
+    fn desc(&self, index: u16) -> *mut Descriptor {
+        self.at(size_of::<Descriptor>() * index as usize)
+    }
+    unsafe fn read_desc(&self, index: u16) -> Descriptor {
+        ptr::read_volatile(self.desc(index))
+    }
+    unsafe fn write_desc(&mut self, index: u16, desc: Descriptor) {
+        ptr::write_volatile(self.desc(index), desc)
+    }
+    fn avail_flags(&self) -> *mut u16 {
+        self.at(self.avail_offset)
+    }
+    fn avail_idx(&self) -> *mut u16 {
+        self.at(self.avail_offset + 2)
+    }
+    fn avail_ring(&self, slot: u16) -> *mut u16 {
+        self.at(self.avail_offset + 4 + 2 * (slot % self.size) as usize)
+    }
+    fn used_flags(&self) -> *mut u16 {
+        self.at(self.used_offset)
+    }
+    fn used_idx(&self) -> *mut u16 {
+        self.at(self.used_offset + 2)
+    }
+    fn used_ring(&self, slot: u16) -> *mut UsedElem {
+        self.at(self.used_offset + 4 + size_of::<UsedElem>() * (slot % self.size) as usize)
+    }
+    /// Offer a chain of buffers to the device
+    ///
+    /// Buffers the device reads must come before those it writes. Returns the ID of the chain,
+    /// which is returned by `pop_used` once the device is done with it, or nothing if there
+    /// are not enough free descriptors. The device still needs notifying.
+    pub unsafe fn add(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        assert!(!buffers.is_empty(), "Empty virtqueue chain");
+        assert!(buffers.windows(2).all(|pair| !pair[0].writable || pair[1].writable),
+            "Device readable buffer after a writable one");
+        if buffers.len() > self.free as usize {
+            return None;
+        }
+        let head = self.free_head;
+        let mut index = head;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let next = self.read_desc(index).next;
+            let last = i + 1 == buffers.len();
+            let mut flags = if buffer.writable { DESC_F_WRITE } else { 0 };
+            if !last {
+                flags |= DESC_F_NEXT;
+            }
+            self.write_desc(index, Descriptor { addr: buffer.paddr, len: buffer.len, flags: flags, next: next });
+            if last {
+                self.free_head = next;
+            } else {
+                index = next;
+            }
+        }
+        self.free -= buffers.len() as u16;
+        ptr::write_volatile(self.avail_ring(self.avail_idx), head);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        // The ring entry must be visible before the index that publishes it
+        fence(Ordering::SeqCst);
+        ptr::write_volatile(self.avail_idx(), self.avail_idx);
+        fence(Ordering::SeqCst);
+        Some(head)
+    }
+    /// Whether the device wants to be notified of new buffers
+    pub fn should_notify(&self) -> bool {
+        let flags = unsafe {ptr::read_volatile(self.used_flags())};
+        flags & USED_F_NO_NOTIFY == 0
+    }
+    /// Whether the device has returned any chains not yet taken
+    pub fn has_used(&self) -> bool {
+        let used_idx = unsafe {ptr::read_volatile(self.used_idx())};
+        used_idx != self.last_used
+    }
+    /// Take the next chain the device has finished with
+    ///
+    /// Returns the chain ID from `add` and how many bytes the device wrote. The descriptors
+    /// of the chain are freed.
+    pub unsafe fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+        // Entries must not be read before the index says they are there
+        fence(Ordering::SeqCst);
+        let elem = ptr::read_volatile(self.used_ring(self.last_used));
+        self.last_used = self.last_used.wrapping_add(1);
+        let head = elem.id as u16;
+        assert!(head < self.size, "Device returned invalid descriptor {}", head);
+        let mut index = head;
+        let mut count = 1;
+        loop {
+            let desc = self.read_desc(index);
+            if desc.flags & DESC_F_NEXT == 0 {
+                break;
+            }
+            index = desc.next;
+            count += 1;
+        }
+        // Put the whole chain back on the front of the free list
+        let mut tail = self.read_desc(index);
+        tail.next = self.free_head;
+        self.write_desc(index, tail);
+        self.free_head = head;
+        self.free += count;
+        Some((head, elem.len))
+    }
+    /// Busy wait for the device to return a chain
+    pub unsafe fn wait_used(&mut self) -> (u16, u32) {
+        loop {
+            if let Some(used) = self.pop_used() {
+                return used;
+            }
+        }
+    }
+    /// Ask the device not to interrupt when it returns chains, for polled completion
+    pub fn disable_interrupts(&mut self) {
+        unsafe {ptr::write_volatile(self.avail_flags(), AVAIL_F_NO_INTERRUPT)};
+    }
+    pub fn enable_interrupts(&mut self) {
+        unsafe {ptr::write_volatile(self.avail_flags(), 0)};
+    }
+}
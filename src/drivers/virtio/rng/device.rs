@@ -0,0 +1,68 @@
+//! Virtio entropy device
+//!
+//! The device fills whatever buffers it is given with random bytes, as it has them. Its one
+//! queue is kept full, and bytes are copied out as the buffers come back.
+
+use alloc::vec::Vec;
+use drivers::io::MixedIo;
+use drivers::virtio::{Error, Pool, Transport};
+use random::EntropySource;
+use vspace::Translation;
+
+const QUEUE: u16 = 0;
+const QUEUE_SIZE: u16 = 4;
+const BUFFER_SIZE: usize = 64;
+
+pub struct Rng<T: MixedIo> {
+    transport: Transport<T>,
+    queue: Pool,
+    /// Bytes from a returned buffer that have not been read yet
+    pending: Vec<u8>,
+}
+
+impl<T: MixedIo> Rng<T> {
+    /// Set up the device, and ask it for as many bytes as there are buffers for
+    ///
+    /// `translation` is passed to `Dma::new`.
+    pub unsafe fn new(mut transport: Transport<T>, translation: &Translation) -> Result<Rng<T>, Error> {
+        let mut queue = transport.initialize(0, |transport| {
+            let queue = transport.setup_polled_queue(translation, QUEUE, QUEUE_SIZE)?;
+            Pool::new(queue, translation, BUFFER_SIZE)
+        })?;
+        queue.fill();
+        transport.notify(queue.queue());
+        Ok(Rng { transport: transport, queue: queue, pending: Vec::with_capacity(BUFFER_SIZE) })
+    }
+}
+
+impl<T: MixedIo> EntropySource for Rng<T> {
+    fn name(&self) -> &'static str {
+        "virtio-rng"
+    }
+    unsafe fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let mut read = 0;
+        loop {
+            let len = (buffer.len() - read).min(self.pending.len());
+            buffer[read..read + len].copy_from_slice(&self.pending[..len]);
+            self.pending.drain(..len);
+            read += len;
+            if read == buffer.len() {
+                return read;
+            }
+            let (slot, len) = match self.queue.pop() {
+                Some(used) => used,
+                None => return read,
+            };
+            self.pending.extend_from_slice(&self.queue.buffer(slot)[..len.min(BUFFER_SIZE)]);
+            self.queue.add(slot, BUFFER_SIZE, true);
+            self.transport.notify(self.queue.queue());
+        }
+    }
+}
+
+impl<T: MixedIo> Drop for Rng<T> {
+    fn drop(&mut self) {
+        // The device must stop using the queue before it is freed
+        let _ = unsafe {self.transport.reset()};
+    }
+}
@@ -0,0 +1,41 @@
+//! Virtio entropy devices
+//!
+//! Each device found is registered as an entropy source.
+
+use alloc::vec::Vec;
+use bus::Device;
+use decls::Match;
+use drivers::pci::Address;
+use random;
+use super::{device_id, VENDOR, TYPE_ENTROPY, TRANSITIONAL_ENTROPY};
+
+mod device;
+
+pub use self::device::Rng;
+
+/// Sources registered, by the function they are on
+static mut SOURCES: Option<Vec<(Address, usize)>> = None;
+
+fn probe(device: &Device) -> bool {
+    match super::probe("virtio-rng", device, |transport, translation| unsafe {Rng::new(transport, translation)}) {
+        Some((address, rng)) => {
+            let id = random::register(box rng);
+            unsafe {SOURCES.get_or_insert_with(Vec::new)}.push((address, id));
+            true
+        },
+        None => false,
+    }
+}
+
+fn remove(device: &Device) {
+    if let Device::Pci(function) = *device {
+        let sources = unsafe {SOURCES.get_or_insert_with(Vec::new)};
+        if let Some(index) = sources.iter().position(|source| source.0 == function.address) {
+            let (_, id) = sources.remove(index);
+            random::unregister(id);
+        }
+    }
+}
+
+make_driver_decl!("virtio-rng", &[Match::pci(VENDOR, device_id(TYPE_ENTROPY)), Match::pci(VENDOR, TRANSITIONAL_ENTROPY)],
+    probe, remove, VIRTIO_RNG_DRIVER);
@@ -0,0 +1,265 @@
+//! Modern virtio-pci transport
+//!
+//! The device is driven through the common configuration structure, which handles feature
+//! negotiation, device status and queue setup. Queues are kicked through the notification
+//! region, interrupts are acknowledged through the ISR byte, and the device specific
+//! configuration has its own region.
+
+use super::super::io::MixedIo;
+use super::Virtqueue;
+use vspace::Translation;
+
+/// Device acknowledged as a virtio device
+pub const STATUS_ACKNOWLEDGE: u8 = 1;
+/// Driver for the device found
+pub const STATUS_DRIVER: u8 = 2;
+/// Driver is set up and the device is live
+pub const STATUS_DRIVER_OK: u8 = 4;
+/// Feature negotiation complete
+pub const STATUS_FEATURES_OK: u8 = 8;
+/// Device has hit an error it cannot recover from without a reset
+pub const STATUS_NEEDS_RESET: u8 = 64;
+/// Driver has given up on the device
+pub const STATUS_FAILED: u8 = 128;
+
+/// Descriptors may refer to tables of further descriptors
+pub const F_INDIRECT_DESC: u64 = 1 << 28;
+/// Interrupt and notification suppression through ring event indices
+pub const F_EVENT_IDX: u64 = 1 << 29;
+/// Device conforms to virtio 1.0 or later, as opposed to the legacy interface
+pub const F_VERSION_1: u64 = 1 << 32;
+
+/// Queue interrupt pending, in the ISR
+pub const ISR_QUEUE: u8 = 1;
+/// Device configuration changed, in the ISR
+pub const ISR_CONFIG: u8 = 2;
+
+/// No MSI-X vector, so interrupts are through INTx and the ISR
+const NO_VECTOR: u16 = 0xFFFF;
+
+const DEVICE_FEATURE_SELECT: usize = 0x00;
+const DEVICE_FEATURE: usize = 0x04;
+const DRIVER_FEATURE_SELECT: usize = 0x08;
+const DRIVER_FEATURE: usize = 0x0C;
+const MSIX_CONFIG: usize = 0x10;
+const NUM_QUEUES: usize = 0x12;
+const DEVICE_STATUS: usize = 0x14;
+const CONFIG_GENERATION: usize = 0x15;
+const QUEUE_SELECT: usize = 0x16;
+const QUEUE_SIZE: usize = 0x18;
+const QUEUE_MSIX_VECTOR: usize = 0x1A;
+const QUEUE_ENABLE: usize = 0x1C;
+const QUEUE_NOTIFY_OFF: usize = 0x1E;
+const QUEUE_DESC: usize = 0x20;
+const QUEUE_DRIVER: usize = 0x28;
+const QUEUE_DEVICE: usize = 0x30;
+
+/// Number of polls of the status for a reset to complete
+const RESET_POLL_LIMIT: usize = 1000000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Device did not complete a reset
+    ResetTimeout,
+    /// Device only offers the legacy interface
+    Legacy,
+    /// Device did not accept the features the driver selected
+    FeaturesRejected,
+    /// Queue does not exist, or is too small for what the driver asked
+    NoQueue(u16),
+    /// Device has no device specific configuration
+    NoDeviceConfig,
+    /// Queue memory could not be allocated
+    NoMemory,
+}
+
+pub struct Transport<T: MixedIo> {
+    common: T,
+    notify: T,
+    notify_multiplier: u32,
+    isr: T,
+    device: Option<T>,
+    features: u64,
+}
+
+impl<T: MixedIo> Transport<T> {
+    /// Construct from the mapped regions of the device
+    ///
+    /// `notify_multiplier` is from the notification capability, and is how far apart the
+    /// notification addresses of each queue are
+    pub fn new(common: T, notify: T, notify_multiplier: u32, isr: T, device: Option<T>) -> Transport<T> {
+        Transport {
+            common: common,
+            notify: notify,
+            notify_multiplier: notify_multiplier,
+            isr: isr,
+            device: device,
+            features: 0,
+        }
+    }
+    pub unsafe fn status(&mut self) -> u8 {
+        self.common.read8(DEVICE_STATUS)
+    }
+    unsafe fn add_status(&mut self, status: u8) {
+        let current = self.status();
+        self.common.write8(DEVICE_STATUS, current | status);
+    }
+    pub unsafe fn reset(&mut self) -> Result<(), Error> {
+        self.common.write8(DEVICE_STATUS, 0);
+        // The device may take time to finish resetting, and reads 0 once it has
+        for _ in 0..RESET_POLL_LIMIT {
+            if self.status() == 0 {
+                return Ok(());
+            }
+        }
+        Err(Error::ResetTimeout)
+    }
+    /// Reset the device and negotiate features
+    ///
+    /// Accepts those of `supported` that the device offers, with `F_VERSION_1` always
+    /// required. Returns the accepted features. On success the queues can then be set up and
+    /// `driver_ok` called, on failure the device has been marked as failed.
+    pub unsafe fn negotiate(&mut self, supported: u64) -> Result<u64, Error> {
+        let result = self.try_negotiate(supported);
+        if result.is_err() {
+            self.fail();
+        }
+        result
+    }
+    unsafe fn try_negotiate(&mut self, supported: u64) -> Result<u64, Error> {
+        self.reset()?;
+        self.add_status(STATUS_ACKNOWLEDGE);
+        self.add_status(STATUS_DRIVER);
+        let offered = self.device_features();
+        if offered & F_VERSION_1 == 0 {
+            return Err(Error::Legacy);
+        }
+        let features = offered & (supported | F_VERSION_1);
+        self.common.write32(DRIVER_FEATURE_SELECT, 0);
+        self.common.write32(DRIVER_FEATURE, features as u32);
+        self.common.write32(DRIVER_FEATURE_SELECT, 1);
+        self.common.write32(DRIVER_FEATURE, (features >> 32) as u32);
+        self.add_status(STATUS_FEATURES_OK);
+        if self.status() & STATUS_FEATURES_OK == 0 {
+            return Err(Error::FeaturesRejected);
+        }
+        self.features = features;
+        // Configuration changes are only reported through the ISR
+        self.common.write16(MSIX_CONFIG, NO_VECTOR);
+        Ok(features)
+    }
+    pub unsafe fn device_features(&mut self) -> u64 {
+        self.common.write32(DEVICE_FEATURE_SELECT, 0);
+        let low = self.common.read32(DEVICE_FEATURE) as u64;
+        self.common.write32(DEVICE_FEATURE_SELECT, 1);
+        let high = self.common.read32(DEVICE_FEATURE) as u64;
+        high << 32 | low
+    }
+    /// Features accepted by `negotiate`
+    pub fn features(&self) -> u64 {
+        self.features
+    }
+    pub unsafe fn num_queues(&mut self) -> u16 {
+        self.common.read16(NUM_QUEUES)
+    }
+    /// Largest size the device supports for a queue, 0 if it does not exist
+    pub unsafe fn max_queue_size(&mut self, index: u16) -> u16 {
+        self.common.write16(QUEUE_SELECT, index);
+        self.common.read16(QUEUE_SIZE)
+    }
+    /// Give a queue to the device and enable it
+    pub unsafe fn enable_queue(&mut self, queue: &mut Virtqueue) -> Result<(), Error> {
+        let index = queue.index();
+        let max = self.max_queue_size(index);
+        if max == 0 || queue.size() > max {
+            return Err(Error::NoQueue(index));
+        }
+        self.common.write16(QUEUE_SIZE, queue.size());
+        self.common.write16(QUEUE_MSIX_VECTOR, NO_VECTOR);
+        self.write64(QUEUE_DESC, queue.desc_paddr());
+        self.write64(QUEUE_DRIVER, queue.avail_paddr());
+        self.write64(QUEUE_DEVICE, queue.used_paddr());
+        let notify_off = self.common.read16(QUEUE_NOTIFY_OFF) as u32;
+        queue.set_notify_offset(notify_off * self.notify_multiplier);
+        self.common.write16(QUEUE_ENABLE, 1);
+        Ok(())
+    }
+    /// Allocate a queue of up to `size` descriptors and give it to the device
+    ///
+    /// The size is reduced to what the device supports, and must be a power of 2.
+    pub unsafe fn setup_queue(&mut self, translation: &Translation, index: u16, size: u16) -> Result<Virtqueue, Error> {
+        let max = self.max_queue_size(index);
+        if max == 0 {
+            return Err(Error::NoQueue(index));
+        }
+        // A device maximum that is not a power of 2 is rounded down to one
+        let max = 1 << (15 - max.leading_zeros());
+        let mut queue = Virtqueue::new(translation, index, size.min(max)).ok_or(Error::NoMemory)?;
+        self.enable_queue(&mut queue)?;
+        Ok(queue)
+    }
+    /// `setup_queue` for a queue that is polled, so never asks the device for interrupts
+    pub unsafe fn setup_polled_queue(&mut self, translation: &Translation, index: u16, size: u16) -> Result<Virtqueue, Error> {
+        let mut queue = self.setup_queue(translation, index, size)?;
+        queue.disable_interrupts();
+        Ok(queue)
+    }
+    /// Negotiate features, set up the driver with `setup`, then start the device
+    ///
+    /// If `setup` fails the device is marked as failed, so drivers do not each have to.
+    pub unsafe fn initialize<F, R>(&mut self, supported: u64, setup: F) -> Result<R, Error> where F: FnOnce(&mut Transport<T>) -> Result<R, Error> {
+        self.negotiate(supported)?;
+        match setup(self) {
+            Ok(result) => {
+                self.driver_ok();
+                Ok(result)
+            },
+            Err(error) => {
+                self.fail();
+                Err(error)
+            },
+        }
+    }
+    unsafe fn write64(&mut self, offset: usize, value: u64) {
+        self.common.write32(offset, value as u32);
+        self.common.write32(offset + 4, (value >> 32) as u32);
+    }
+    /// Tell the device that the driver is ready and the device can start operating
+    pub unsafe fn driver_ok(&mut self) {
+        self.add_status(STATUS_DRIVER_OK);
+    }
+    /// Tell the device the driver has given up on it
+    pub unsafe fn fail(&mut self) {
+        self.add_status(STATUS_FAILED);
+    }
+    /// Tell the device there are new buffers in a queue, if it wants to know
+    pub unsafe fn notify(&mut self, queue: &Virtqueue) {
+        if queue.should_notify() {
+            self.notify.write16(queue.notify_offset() as usize, queue.index());
+        }
+    }
+    /// Read and acknowledge the pending interrupt causes, as `ISR_*` bits
+    pub unsafe fn isr(&mut self) -> u8 {
+        self.isr.read8(0)
+    }
+    /// Read the device specific configuration
+    ///
+    /// Retries until the configuration generation is the same before and after, so that
+    /// multiple reads see a consistent view of fields the device may change
+    pub unsafe fn read_config<F, R>(&mut self, mut read: F) -> Result<R, Error> where F: FnMut(&mut T) -> R {
+        loop {
+            let before = self.common.read8(CONFIG_GENERATION);
+            let result = match self.device {
+                Some(ref mut device) => read(device),
+                None => return Err(Error::NoDeviceConfig),
+            };
+            if self.common.read8(CONFIG_GENERATION) == before {
+                return Ok(result);
+            }
+        }
+    }
+    /// Access the device specific configuration directly, such as to write it
+    pub unsafe fn device_config(&mut self) -> Result<&mut T, Error> {
+        self.device.as_mut().ok_or(Error::NoDeviceConfig)
+    }
+}
@@ -0,0 +1,74 @@
+//! Physical key identifiers
+
+/// Identifies a physical key
+///
+/// Keys are named after what they produce on a US QWERTY layout, but identify a position on the
+/// keyboard and not a character. Converting to characters is the job of a `Layout`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCode {
+    Escape,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    PrintScreen,
+    ScrollLock,
+    Pause,
+    Backtick,
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+    Minus,
+    Equals,
+    Backspace,
+    Tab,
+    Q, W, E, R, T, Y, U, I, O, P,
+    LeftBracket,
+    RightBracket,
+    Backslash,
+    CapsLock,
+    A, S, D, F, G, H, J, K, L,
+    Semicolon,
+    Quote,
+    Enter,
+    LeftShift,
+    /// Additional key between left shift and Z found on ISO keyboards
+    NonUsBackslash,
+    Z, X, C, V, B, N, M,
+    Comma,
+    Period,
+    Slash,
+    RightShift,
+    LeftCtrl,
+    LeftGui,
+    LeftAlt,
+    Space,
+    RightAlt,
+    RightGui,
+    Menu,
+    RightCtrl,
+    Insert,
+    Home,
+    PageUp,
+    Delete,
+    End,
+    PageDown,
+    Up,
+    Left,
+    Down,
+    Right,
+    NumLock,
+    KpDivide,
+    KpMultiply,
+    KpMinus,
+    KpPlus,
+    KpEnter,
+    KpPeriod,
+    Kp0, Kp1, Kp2, Kp3, Kp4, Kp5, Kp6, Kp7, Kp8, Kp9,
+}
+
+impl KeyCode {
+    /// Whether this key is on the numeric keypad and changes meaning with num lock
+    pub fn is_keypad_numeric(&self) -> bool {
+        match *self {
+            KeyCode::KpPeriod | KeyCode::Kp0 | KeyCode::Kp1 | KeyCode::Kp2 | KeyCode::Kp3 | KeyCode::Kp4
+                | KeyCode::Kp5 | KeyCode::Kp6 | KeyCode::Kp7 | KeyCode::Kp8 | KeyCode::Kp9 => true,
+            _ => false,
+        }
+    }
+}
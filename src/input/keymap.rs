@@ -0,0 +1,232 @@
+//! Keyboard layouts
+//!
+//! A layout converts a physical `KeyCode`, at a particular shift level, into the character it
+//! produces. Dead keys are not supported, keys that would be dead just produce their character.
+
+use super::KeyCode;
+
+/// Shift level a key is looked up at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Base,
+    Shift,
+    AltGr,
+    ShiftAltGr,
+}
+
+pub struct Layout {
+    pub name: &'static str,
+    map: fn(KeyCode, Level) -> Option<char>,
+}
+
+impl Layout {
+    pub fn lookup(&self, code: KeyCode, level: Level) -> Option<char> {
+        (self.map)(code, level)
+    }
+}
+
+pub static LAYOUTS: [Layout; 4] = [
+    Layout {name: "us", map: us},
+    Layout {name: "uk", map: uk},
+    Layout {name: "de", map: de},
+    Layout {name: "dvorak", map: dvorak},
+];
+
+/// Find a layout by its name
+pub fn find(name: &str) -> Option<&'static Layout> {
+    LAYOUTS.iter().find(|x| x.name == name)
+}
+
+/// Layout used if no other has been requested
+pub fn default() -> &'static Layout {
+    &LAYOUTS[0]
+}
+
+/// Choose between the unshifted and shifted character of a key
+fn pick(level: Level, base: char, shift: char) -> Option<char> {
+    match level {
+        Level::Base => Some(base),
+        Level::Shift => Some(shift),
+        _ => None,
+    }
+}
+
+/// Keys that produce the same thing on every layout
+fn common(code: KeyCode) -> Option<char> {
+    match code {
+        KeyCode::Enter | KeyCode::KpEnter => Some('\n'),
+        KeyCode::Tab => Some('\t'),
+        KeyCode::Backspace => Some('\x08'),
+        KeyCode::Escape => Some('\x1b'),
+        KeyCode::Space => Some(' '),
+        KeyCode::KpDivide => Some('/'),
+        KeyCode::KpMultiply => Some('*'),
+        KeyCode::KpMinus => Some('-'),
+        KeyCode::KpPlus => Some('+'),
+        KeyCode::KpPeriod => Some('.'),
+        KeyCode::Kp0 => Some('0'),
+        KeyCode::Kp1 => Some('1'),
+        KeyCode::Kp2 => Some('2'),
+        KeyCode::Kp3 => Some('3'),
+        KeyCode::Kp4 => Some('4'),
+        KeyCode::Kp5 => Some('5'),
+        KeyCode::Kp6 => Some('6'),
+        KeyCode::Kp7 => Some('7'),
+        KeyCode::Kp8 => Some('8'),
+        KeyCode::Kp9 => Some('9'),
+        _ => None,
+    }
+}
+
+fn us(code: KeyCode, level: Level) -> Option<char> {
+    if let Some(c) = common(code) {
+        return Some(c);
+    }
+    let (base, shift) = match code {
+        KeyCode::Backtick => ('`', '~'),
+        KeyCode::Key1 => ('1', '!'),
+        KeyCode::Key2 => ('2', '@'),
+        KeyCode::Key3 => ('3', '#'),
+        KeyCode::Key4 => ('4', '$'),
+        KeyCode::Key5 => ('5', '%'),
+        KeyCode::Key6 => ('6', '^'),
+        KeyCode::Key7 => ('7', '&'),
+        KeyCode::Key8 => ('8', '*'),
+        KeyCode::Key9 => ('9', '('),
+        KeyCode::Key0 => ('0', ')'),
+        KeyCode::Minus => ('-', '_'),
+        KeyCode::Equals => ('=', '+'),
+        KeyCode::Q => ('q', 'Q'),
+        KeyCode::W => ('w', 'W'),
+        KeyCode::E => ('e', 'E'),
+        KeyCode::R => ('r', 'R'),
+        KeyCode::T => ('t', 'T'),
+        KeyCode::Y => ('y', 'Y'),
+        KeyCode::U => ('u', 'U'),
+        KeyCode::I => ('i', 'I'),
+        KeyCode::O => ('o', 'O'),
+        KeyCode::P => ('p', 'P'),
+        KeyCode::LeftBracket => ('[', '{'),
+        KeyCode::RightBracket => (']', '}'),
+        KeyCode::Backslash => ('\\', '|'),
+        KeyCode::A => ('a', 'A'),
+        KeyCode::S => ('s', 'S'),
+        KeyCode::D => ('d', 'D'),
+        KeyCode::F => ('f', 'F'),
+        KeyCode::G => ('g', 'G'),
+        KeyCode::H => ('h', 'H'),
+        KeyCode::J => ('j', 'J'),
+        KeyCode::K => ('k', 'K'),
+        KeyCode::L => ('l', 'L'),
+        KeyCode::Semicolon => (';', ':'),
+        KeyCode::Quote => ('\'', '"'),
+        KeyCode::NonUsBackslash => ('\\', '|'),
+        KeyCode::Z => ('z', 'Z'),
+        KeyCode::X => ('x', 'X'),
+        KeyCode::C => ('c', 'C'),
+        KeyCode::V => ('v', 'V'),
+        KeyCode::B => ('b', 'B'),
+        KeyCode::N => ('n', 'N'),
+        KeyCode::M => ('m', 'M'),
+        KeyCode::Comma => (',', '<'),
+        KeyCode::Period => ('.', '>'),
+        KeyCode::Slash => ('/', '?'),
+        _ => return None,
+    };
+    pick(level, base, shift)
+}
+
+fn uk(code: KeyCode, level: Level) -> Option<char> {
+    match (code, level) {
+        (KeyCode::Key2, Level::Shift) => Some('"'),
+        (KeyCode::Key3, Level::Shift) => Some('£'),
+        (KeyCode::Key4, Level::AltGr) => Some('€'),
+        (KeyCode::Backtick, _) => pick(level, '`', '¬'),
+        (KeyCode::Quote, _) => pick(level, '\'', '@'),
+        // ISO keyboards have the hash key where US keyboards have backslash
+        (KeyCode::Backslash, _) => pick(level, '#', '~'),
+        (KeyCode::NonUsBackslash, _) => pick(level, '\\', '|'),
+        _ => us(code, level),
+    }
+}
+
+fn de(code: KeyCode, level: Level) -> Option<char> {
+    match (code, level) {
+        (KeyCode::Q, Level::AltGr) => Some('@'),
+        (KeyCode::E, Level::AltGr) => Some('€'),
+        (KeyCode::M, Level::AltGr) => Some('µ'),
+        (KeyCode::Key2, Level::AltGr) => Some('²'),
+        (KeyCode::Key3, Level::AltGr) => Some('³'),
+        (KeyCode::Key7, Level::AltGr) => Some('{'),
+        (KeyCode::Key8, Level::AltGr) => Some('['),
+        (KeyCode::Key9, Level::AltGr) => Some(']'),
+        (KeyCode::Key0, Level::AltGr) => Some('}'),
+        (KeyCode::Minus, Level::AltGr) => Some('\\'),
+        (KeyCode::RightBracket, Level::AltGr) => Some('~'),
+        (KeyCode::NonUsBackslash, Level::AltGr) => Some('|'),
+        (KeyCode::Y, _) => pick(level, 'z', 'Z'),
+        (KeyCode::Z, _) => pick(level, 'y', 'Y'),
+        (KeyCode::Key2, _) => pick(level, '2', '"'),
+        (KeyCode::Key3, _) => pick(level, '3', '§'),
+        (KeyCode::Key6, _) => pick(level, '6', '&'),
+        (KeyCode::Key7, _) => pick(level, '7', '/'),
+        (KeyCode::Key8, _) => pick(level, '8', '('),
+        (KeyCode::Key9, _) => pick(level, '9', ')'),
+        (KeyCode::Key0, _) => pick(level, '0', '='),
+        (KeyCode::Minus, _) => pick(level, 'ß', '?'),
+        (KeyCode::Equals, _) => pick(level, '´', '`'),
+        (KeyCode::Backtick, _) => pick(level, '^', '°'),
+        (KeyCode::LeftBracket, _) => pick(level, 'ü', 'Ü'),
+        (KeyCode::RightBracket, _) => pick(level, '+', '*'),
+        (KeyCode::Semicolon, _) => pick(level, 'ö', 'Ö'),
+        (KeyCode::Quote, _) => pick(level, 'ä', 'Ä'),
+        (KeyCode::Backslash, _) => pick(level, '#', '\''),
+        (KeyCode::NonUsBackslash, _) => pick(level, '<', '>'),
+        (KeyCode::Comma, _) => pick(level, ',', ';'),
+        (KeyCode::Period, _) => pick(level, '.', ':'),
+        (KeyCode::Slash, _) => pick(level, '-', '_'),
+        _ => us(code, level),
+    }
+}
+
+fn dvorak(code: KeyCode, level: Level) -> Option<char> {
+    let (base, shift) = match code {
+        KeyCode::Minus => ('[', '{'),
+        KeyCode::Equals => (']', '}'),
+        KeyCode::Q => ('\'', '"'),
+        KeyCode::W => (',', '<'),
+        KeyCode::E => ('.', '>'),
+        KeyCode::R => ('p', 'P'),
+        KeyCode::T => ('y', 'Y'),
+        KeyCode::Y => ('f', 'F'),
+        KeyCode::U => ('g', 'G'),
+        KeyCode::I => ('c', 'C'),
+        KeyCode::O => ('r', 'R'),
+        KeyCode::P => ('l', 'L'),
+        KeyCode::LeftBracket => ('/', '?'),
+        KeyCode::RightBracket => ('=', '+'),
+        KeyCode::A => ('a', 'A'),
+        KeyCode::S => ('o', 'O'),
+        KeyCode::D => ('e', 'E'),
+        KeyCode::F => ('u', 'U'),
+        KeyCode::G => ('i', 'I'),
+        KeyCode::H => ('d', 'D'),
+        KeyCode::J => ('h', 'H'),
+        KeyCode::K => ('t', 'T'),
+        KeyCode::L => ('n', 'N'),
+        KeyCode::Semicolon => ('s', 'S'),
+        KeyCode::Quote => ('-', '_'),
+        KeyCode::Z => (';', ':'),
+        KeyCode::X => ('q', 'Q'),
+        KeyCode::C => ('j', 'J'),
+        KeyCode::V => ('k', 'K'),
+        KeyCode::B => ('x', 'X'),
+        KeyCode::N => ('b', 'B'),
+        KeyCode::M => ('m', 'M'),
+        KeyCode::Comma => ('w', 'W'),
+        KeyCode::Period => ('v', 'V'),
+        KeyCode::Slash => ('z', 'Z'),
+        _ => return us(code, level),
+    };
+    pick(level, base, shift)
+}
@@ -0,0 +1,182 @@
+//! Input events
+//!
+//! Input drivers translate whatever their hardware produces into `Event`s and place them in a
+//! global queue, from which a consumer, such as a console or monitor, can retrieve them without
+//! needing to know where they came from.
+//!
+//! Keyboards report transitions of physical keys as a `KeyCode`. Tracking of modifier and lock
+//! keys and conversion to characters through the active `Layout` is common to all keyboards and
+//! done by `KeyState`.
+//!
+//! Keys meant for the kernel itself are taken out before they reach the queue by a hook.
+
+mod keycode;
+mod queue;
+pub mod keymap;
+
+pub use self::keycode::KeyCode;
+pub use self::keymap::{Layout, Level};
+pub use self::queue::Queue;
+
+bitflags! {
+    /// State of modifier and lock keys
+    pub struct Modifiers: u16 {
+        const LEFT_SHIFT = 0b1;
+        const RIGHT_SHIFT = 0b10;
+        const LEFT_CTRL = 0b100;
+        const RIGHT_CTRL = 0b1000;
+        const LEFT_ALT = 0b10000;
+        /// Right alt is treated as AltGr
+        const RIGHT_ALT = 0b100000;
+        const LEFT_GUI = 0b1000000;
+        const RIGHT_GUI = 0b10000000;
+        const CAPS_LOCK = 0b100000000;
+        const NUM_LOCK = 0b1000000000;
+        const SCROLL_LOCK = 0b10000000000;
+        /// Meta flag for either shift key
+        const SHIFT = Self::LEFT_SHIFT.bits | Self::RIGHT_SHIFT.bits;
+        /// Meta flag for either ctrl key
+        const CTRL = Self::LEFT_CTRL.bits | Self::RIGHT_CTRL.bits;
+        /// Meta flag for all the lock keys
+        const LOCKS = Self::CAPS_LOCK.bits | Self::NUM_LOCK.bits | Self::SCROLL_LOCK.bits;
+    }
+}
+
+/// Key press or release
+#[derive(Debug, Clone, Copy)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub pressed: bool,
+    /// Modifiers in effect after this event was processed
+    pub modifiers: Modifiers,
+    /// Character this key produces, only present for presses
+    pub ch: Option<char>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    Key(KeyEvent),
+}
+
+/// Tracks modifier state of a keyboard and converts key transitions into `KeyEvent`s
+///
+/// Each keyboard should have its own `KeyState` so that holding shift on one keyboard does not
+/// affect another.
+pub struct KeyState {
+    modifiers: Modifiers,
+    layout: &'static Layout,
+}
+
+impl KeyState {
+    pub fn new(layout: &'static Layout) -> Self {
+        KeyState {modifiers: Modifiers::empty(), layout: layout}
+    }
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+    fn modifier_for(code: KeyCode) -> Option<Modifiers> {
+        match code {
+            KeyCode::LeftShift => Some(Modifiers::LEFT_SHIFT),
+            KeyCode::RightShift => Some(Modifiers::RIGHT_SHIFT),
+            KeyCode::LeftCtrl => Some(Modifiers::LEFT_CTRL),
+            KeyCode::RightCtrl => Some(Modifiers::RIGHT_CTRL),
+            KeyCode::LeftAlt => Some(Modifiers::LEFT_ALT),
+            KeyCode::RightAlt => Some(Modifiers::RIGHT_ALT),
+            KeyCode::LeftGui => Some(Modifiers::LEFT_GUI),
+            KeyCode::RightGui => Some(Modifiers::RIGHT_GUI),
+            _ => None,
+        }
+    }
+    fn lock_for(code: KeyCode) -> Option<Modifiers> {
+        match code {
+            KeyCode::CapsLock => Some(Modifiers::CAPS_LOCK),
+            KeyCode::NumLock => Some(Modifiers::NUM_LOCK),
+            KeyCode::ScrollLock => Some(Modifiers::SCROLL_LOCK),
+            _ => None,
+        }
+    }
+    fn translate(&self, code: KeyCode) -> Option<char> {
+        if code.is_keypad_numeric() && !self.modifiers.contains(Modifiers::NUM_LOCK) {
+            // Keypad is acting as navigation keys
+            return None;
+        }
+        let shift = self.modifiers.intersects(Modifiers::SHIFT);
+        let altgr = self.modifiers.contains(Modifiers::RIGHT_ALT);
+        let level = |shift| match (shift, altgr) {
+            (false, false) => Level::Base,
+            (true, false) => Level::Shift,
+            (false, true) => Level::AltGr,
+            (true, true) => Level::ShiftAltGr,
+        };
+        // Caps lock only inverts shift for keys that produce letters
+        let is_letter = self.layout.lookup(code, Level::Base).map_or(false, |c| c.is_alphabetic());
+        let caps = is_letter && self.modifiers.contains(Modifiers::CAPS_LOCK);
+        let c = self.layout.lookup(code, level(shift != caps))?;
+        if self.modifiers.intersects(Modifiers::CTRL) && c.is_ascii_alphabetic() {
+            // Produce the matching control character
+            Some(((c.to_ascii_lowercase() as u8) - b'a' + 1) as char)
+        } else {
+            Some(c)
+        }
+    }
+    /// Record a key transition and produce the resulting event
+    pub fn process(&mut self, code: KeyCode, pressed: bool) -> KeyEvent {
+        if let Some(m) = Self::modifier_for(code) {
+            self.modifiers.set(m, pressed);
+        }
+        if let Some(l) = Self::lock_for(code) {
+            if pressed {
+                self.modifiers.toggle(l);
+            }
+        }
+        KeyEvent {
+            code: code,
+            pressed: pressed,
+            modifiers: self.modifiers,
+            ch: if pressed { self.translate(code) } else { None },
+        }
+    }
+}
+
+static mut EVENTS: Queue = Queue::new();
+
+static mut LAYOUT: Option<&'static Layout> = None;
+
+static mut HOOK: Option<fn(&Event) -> bool> = None;
+
+/// Add an event to the global input queue
+pub fn push(event: Event) {
+    if unsafe {HOOK}.map_or(false, |hook| hook(&event)) {
+        return;
+    }
+    unsafe {EVENTS.push(event);}
+}
+
+/// Show every event to `hook` before it is queued, with events it returns `true` for being
+/// consumed
+///
+/// This is for keys that the kernel itself responds to, such as paging the console, which should
+/// work regardless of whether anything is reading the queue.
+pub fn set_hook(hook: fn(&Event) -> bool) {
+    unsafe {HOOK = Some(hook)};
+}
+
+/// Take the oldest event from the global input queue
+pub fn pop() -> Option<Event> {
+    unsafe {EVENTS.pop()}
+}
+
+/// Layout that keyboards should use
+pub fn layout() -> &'static Layout {
+    unsafe {LAYOUT}.unwrap_or(keymap::default())
+}
+
+fn set_layout(name: &str) {
+    match keymap::find(name) {
+        Some(layout) => unsafe {LAYOUT = Some(layout)},
+        None => print!(Error, "Unknown keymap {}, keeping {}", name, layout().name),
+    }
+}
+
+/// Selects the keyboard layout, for example --keymap=de
+make_cmdline_decl!("keymap", set_layout, KEYMAP);
@@ -0,0 +1,54 @@
+//! Fixed size queue of input events
+//!
+//! Events are produced from driver context, potentially from interrupt handlers, so the queue
+//! is statically sized and never allocates.
+
+use super::Event;
+
+const QUEUE_SIZE: usize = 64;
+
+pub struct Queue {
+    events: [Option<Event>; QUEUE_SIZE],
+    head: usize,
+    len: usize,
+    dropped: usize,
+}
+
+impl Queue {
+    pub const fn new() -> Self {
+        Queue {events: [None; QUEUE_SIZE], head: 0, len: 0, dropped: 0}
+    }
+    /// Add an event to the back of the queue
+    ///
+    /// If the queue is full the event is discarded, as the oldest events are the ones the
+    /// consumer is expecting to see next, and this returns `false`
+    pub fn push(&mut self, event: Event) -> bool {
+        if self.len == QUEUE_SIZE {
+            self.dropped += 1;
+            return false;
+        }
+        self.events[(self.head + self.len) % QUEUE_SIZE] = Some(event);
+        self.len += 1;
+        true
+    }
+    /// Remove the event at the front of the queue
+    pub fn pop(&mut self) -> Option<Event> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.head].take();
+        self.head = (self.head + 1) % QUEUE_SIZE;
+        self.len -= 1;
+        event
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Number of events that have been discarded due to the queue being full
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
@@ -0,0 +1,236 @@
+//! Interrupt controller setup, routing and dispatch
+//!
+//! Interrupts are delivered through the local APIC of the boot processor, with device interrupts
+//! routed to it by the I/O APIC. The legacy 8259s are left remapped but fully masked. ISA IRQs
+//! keep the vectors the 8259s were remapped to, so whoever handles a vector does not need to
+//! care which controller delivered it.
+//!
+//! Drivers `register` a handler for each vector they use, which is called with the interrupt
+//! acknowledged afterwards. Interrupts are only enabled while in `wait`, so handlers are never
+//! run in the middle of other kernel code and need no locking against it.
+//!
+//! The I/O APIC, and how ISA IRQs are wired to it, is described by the ACPI MADT. Without it a
+//! single I/O APIC at its conventional address is assumed, with ISA IRQs identity mapped to GSIs.
+//! Only the I/O APIC that handles the ISA IRQs is used.
+
+use drivers::io::{Io, MemIO};
+use drivers::apic::{self, LocalApic, Msr};
+use drivers::ioapic::{self, IoApic, Redirection};
+use drivers::pic8259;
+use acpi::madt::{self, Entry, Signal, ISA_SIGNAL};
+use x86::shared::msr::{rdmsr, wrmsr, IA32_APIC_BASE};
+use state::{STATE, CPU_FEATURES};
+use cpu::MemoryType;
+use cpu::idt;
+
+/// Vector for NVMe completion interrupts
+pub const NVME_VECTOR: u8 = 0x40;
+/// Vector for the local APIC timer
+pub const TIMER_VECTOR: u8 = 0xF0;
+/// Vector for local APIC errors
+pub const ERROR_VECTOR: u8 = 0xFE;
+/// Vector for spurious interrupts from the local APIC
+///
+/// Older processors hardwire the low 4 bits of this to 1s
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// ISA IRQ of the system timer
+pub const ISA_TIMER: u8 = 0;
+/// ISA IRQ of the PS/2 keyboard
+pub const ISA_KEYBOARD: u8 = 1;
+/// ISA IRQ of the first serial port
+pub const ISA_COM1: u8 = 4;
+
+/// APIC global enable
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+/// x2APIC mode enable, only valid once globally enabled
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+/// Physical address of the xAPIC registers
+const APIC_BASE_ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// Access to the local APIC in whichever mode it was enabled in
+pub enum Registers {
+    XApic(MemIO<u32>),
+    X2Apic(Msr),
+}
+
+impl Io for Registers {
+    type Item = u32;
+    type Range = u16;
+    unsafe fn read(&mut self, offset: u16) -> u32 {
+        match *self {
+            Registers::XApic(ref mut io) => io.read(offset as usize),
+            Registers::X2Apic(ref mut io) => io.read(offset),
+        }
+    }
+    unsafe fn write(&mut self, offset: u16, value: u32) {
+        match *self {
+            Registers::XApic(ref mut io) => io.write(offset as usize, value),
+            Registers::X2Apic(ref mut io) => io.write(offset, value),
+        }
+    }
+}
+
+static mut LOCAL_APIC: Option<LocalApic<Registers>> = None;
+static mut IO_APIC: Option<IoApic<MemIO<u32>>> = None;
+static mut HANDLERS: [Option<fn()>; idt::VECTORS] = [None; idt::VECTORS];
+
+extern {
+    fn interrupts_wait();
+}
+
+unsafe fn init_local_apic() -> LocalApic<Registers> {
+    let base = rdmsr(IA32_APIC_BASE) | APIC_BASE_ENABLE;
+    // Must be globally enabled in xAPIC mode before switching to x2APIC
+    wrmsr(IA32_APIC_BASE, base);
+    let mut lapic = if CPU_FEATURES.get_x2apic().is_some() {
+        wrmsr(IA32_APIC_BASE, base | APIC_BASE_X2APIC);
+        LocalApic::new(Registers::X2Apic(Msr::new()), true)
+    } else {
+        let paddr = (base & APIC_BASE_ADDR_MASK) as usize;
+        let vaddr = STATE.kernel_as.map_device(paddr..paddr + apic::MMIO_SIZE, MemoryType::StrongUC)
+            .expect("Failed to map local APIC");
+        LocalApic::new(Registers::XApic(MemIO::new(vaddr)), false)
+    };
+    lapic.enable(SPURIOUS_VECTOR, ERROR_VECTOR);
+    print!(Info, "Enabled local APIC {} version {:#x} in {} mode", lapic.id(), lapic.version(),
+        if lapic.is_x2apic() { "x2APIC" } else { "xAPIC" });
+    lapic
+}
+
+unsafe fn init_io_apic() -> IoApic<MemIO<u32>> {
+    let (paddr, gsi_base) = madt::get()
+        .and_then(|madt| madt.entries()
+            .filter_map(|entry| match entry {
+                Entry::IoApic { address, gsi_base: 0, .. } => Some((address, 0)),
+                _ => None,
+            })
+            .next())
+        .unwrap_or((ioapic::DEFAULT_PADDR, 0));
+    let vaddr = STATE.kernel_as.map_device(paddr..paddr + ioapic::MMIO_SIZE, MemoryType::StrongUC)
+        .expect("Failed to map I/O APIC");
+    let mut io_apic = IoApic::new(MemIO::new(vaddr), gsi_base);
+    io_apic.mask_all();
+    print!(Info, "Found I/O APIC {} version {:#x} with {} entries", io_apic.id(), io_apic.version(), io_apic.entries());
+    io_apic
+}
+
+/// Enable the APICs and stop the legacy PICs from delivering interrupts
+///
+/// Requires the kernel address space, as the APIC registers need mapping
+pub fn init() {
+    unsafe {
+        LOCAL_APIC = Some(init_local_apic());
+        IO_APIC = Some(init_io_apic());
+    }
+    pic8259::disable();
+}
+
+/// Retrieve the local APIC of the current processor
+///
+/// # Panics
+///
+/// If `init` has not yet been called
+pub fn local_apic() -> &'static mut LocalApic<Registers> {
+    unsafe {LOCAL_APIC.as_mut()}.expect("Local APIC used before init")
+}
+
+/// Retrieve the I/O APIC
+///
+/// # Panics
+///
+/// If `init` has not yet been called
+pub fn io_apic() -> &'static mut IoApic<MemIO<u32>> {
+    unsafe {IO_APIC.as_mut()}.expect("I/O APIC used before init")
+}
+
+/// Vector that an ISA IRQ is delivered on
+pub fn isa_vector(irq: u8) -> u8 {
+    pic8259::VECTOR_BASE + irq
+}
+
+/// GSI, and signalling, that an ISA IRQ arrives on
+pub fn isa_gsi(irq: u8) -> (u32, Signal) {
+    madt::get().map_or((irq as u32, ISA_SIGNAL), |madt| madt.isa_irq(irq))
+}
+
+/// Route an ISA IRQ to this processor and unmask it, with `handler` called when it fires
+///
+/// Fails if this processor has an x2APIC ID above 255, which cannot be put in the 8 bit
+/// destination of an I/O APIC entry without interrupt remapping.
+pub fn enable_isa(irq: u8, handler: fn()) -> bool {
+    let (gsi, signal) = isa_gsi(irq);
+    route(irq, gsi, signal, handler)
+}
+
+/// Route the INTx interrupt of a PCI function, by the IRQ the firmware left in its interrupt line
+///
+/// Without the ACPI _PRT the only routing known is the one the firmware set up for the 8259s,
+/// which puts the interrupt on that ISA IRQ. PCI interrupts are level triggered and shared, so
+/// the handler is called for every function on the line and has to ask its devices whether
+/// they raised it. A line already in use has its handler replaced.
+pub fn enable_pci(line: u8, handler: fn()) -> bool {
+    // Lines above the ISA IRQs mean the pin was not routed at all
+    if line >= 16 {
+        print!(Error, "PCI interrupt line {} was not routed", line);
+        return false;
+    }
+    let (gsi, signal) = isa_gsi(line);
+    route(line, gsi, Signal { level: true, ..signal }, handler)
+}
+
+fn route(irq: u8, gsi: u32, signal: Signal, handler: fn()) -> bool {
+    let id = unsafe {local_apic().id()};
+    if id > 0xFF {
+        print!(Error, "Cannot route ISA IRQ {} to local APIC {}, which the I/O APIC cannot address", irq, id);
+        return false;
+    }
+    register(isa_vector(irq), handler);
+    unsafe {
+        let entry = Redirection::fixed(isa_vector(irq), id as u8).trigger(signal.level, signal.active_low);
+        let io_apic = io_apic();
+        io_apic.set_redirection(gsi, entry);
+        io_apic.unmask(gsi);
+    }
+    print!(Debug, "Routed ISA IRQ {} through GSI {} to vector {:#x}", irq, gsi, isa_vector(irq));
+    true
+}
+
+/// Mask an ISA IRQ at the I/O APIC
+pub fn disable_isa(irq: u8) {
+    let (gsi, _) = isa_gsi(irq);
+    unsafe {io_apic().mask(gsi)};
+}
+
+/// Signal end of interrupt to the local APIC
+pub fn eoi() {
+    unsafe {local_apic().eoi()};
+}
+
+/// Have `handler` called whenever `vector` is raised
+///
+/// Replaces any handler already registered for the vector.
+pub fn register(vector: u8, handler: fn()) {
+    assert!(vector as usize >= idt::EXCEPTIONS, "Vector {} is an exception", vector);
+    unsafe {HANDLERS[vector as usize] = Some(handler)};
+}
+
+/// Called from the interrupt entry stubs
+#[no_mangle]
+pub extern "C" fn interrupt_handler(vector: u64) {
+    let vector = vector as u8;
+    // Spurious interrupts are not put in service, so must not be acknowledged
+    if vector == SPURIOUS_VECTOR {
+        return;
+    }
+    match unsafe {HANDLERS[vector as usize]} {
+        Some(handler) => handler(),
+        None => print!(Error, "Interrupt on vector {:#x} with no handler", vector),
+    }
+    eoi();
+}
+
+/// Run the handlers of any pending interrupts, or wait for the next interrupt
+pub fn wait() {
+    unsafe {interrupts_wait()};
+}
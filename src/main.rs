@@ -30,6 +30,7 @@ extern crate x86;
 extern crate bitflags;
 #[macro_use]
 extern crate bitfield;
+#[macro_use]
 extern crate alloc;
 extern crate raw_cpuid;
 #[macro_use]
@@ -49,6 +50,14 @@ pub mod heap;
 pub mod state;
 pub mod ip_collections;
 pub mod cpu;
+pub mod input;
+pub mod irq;
+pub mod acpi;
+pub mod time;
+pub mod bus;
+pub mod block;
+pub mod net;
+pub mod random;
 
 /// Allocator has to be defined in the root of the crate so we extern it here and actually declare in heap
 #[global_allocator]
@@ -56,12 +65,24 @@ pub static mut ALLOCATOR: heap::AllocProxy = heap::AllocProxy::new();
 
 fn boot_continued(_no_arg: ()) -> ! {
     // TODO: switch to non early cons
+    bus::pci::init();
+    con::fb::init();
+    bus::bind();
+    con::virtio::init();
+    print!(Info, "Found {} disks", block::disks().len());
+    net::init();
+    con::net::init();
     print!(Panic, "Panic");
     print!(Error, "Error");
     print!(Info, "Info");
     print!(Debug, "Debug");
     print!(Trace, "Trace");
-    panic!("End of boot");
+    print!(Info, "End of boot");
+    // Nothing left to do but handle input
+    loop {
+        irq::wait();
+        con::page_pending();
+    }
 }
 
 #[no_mangle]
@@ -74,8 +95,13 @@ pub extern "C" fn boot_system(arg1: usize, arg2: usize) -> ! {
     if !cpu::init() {
         panic!("Failed to init cpu");
     }
+    drivers::pic8259::init();
     print!(Info, "Switching to full kernel address space");
     unsafe {vspace::make_kernel_address_space(&mut boot::state::STATE)};
+    cpu::gdt::init_stacks();
+    acpi::init();
+    irq::init();
+    time::init();
     unsafe {
         print!(Info, "Switching to proper kernel stack");
         let mut stack = vspace::Stack::new_kernel(&mut state::STATE.kernel_as).unwrap();
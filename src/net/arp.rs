@@ -0,0 +1,96 @@
+//! Address resolution
+//!
+//! Mappings are learnt from any ARP packet addressed to us, as well as from replies to our own
+//! requests, and are forgotten after a while so that a host changing its address is noticed.
+
+use alloc::vec::Vec;
+use core::time::Duration;
+use super::ethernet::{MacAddress, TYPE_IPV4};
+use super::ipv4::Ipv4Address;
+
+pub const PACKET_SIZE: usize = 28;
+const HTYPE_ETHERNET: u16 = 1;
+
+pub const OP_REQUEST: u16 = 1;
+pub const OP_REPLY: u16 = 2;
+
+/// How long a learnt mapping is used for, in seconds
+const ENTRY_LIFETIME: u64 = 60;
+/// Most mappings kept, beyond which the oldest is replaced
+const CACHE_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub op: u16,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Address,
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Address,
+}
+
+impl Packet {
+    /// Parse an ARP packet, which must be for Ethernet and IPv4
+    pub fn parse(bytes: &[u8]) -> Option<Packet> {
+        if bytes.len() < PACKET_SIZE {
+            return None;
+        }
+        let htype = (bytes[0] as u16) << 8 | bytes[1] as u16;
+        let ptype = (bytes[2] as u16) << 8 | bytes[3] as u16;
+        if htype != HTYPE_ETHERNET || ptype != TYPE_IPV4 || bytes[4] != 6 || bytes[5] != 4 {
+            return None;
+        }
+        Some(Packet {
+            op: (bytes[6] as u16) << 8 | bytes[7] as u16,
+            sender_mac: MacAddress::from_slice(&bytes[8..]),
+            sender_ip: Ipv4Address::from_slice(&bytes[14..]),
+            target_mac: MacAddress::from_slice(&bytes[18..]),
+            target_ip: Ipv4Address::from_slice(&bytes[24..]),
+        })
+    }
+    pub fn request(sender_mac: MacAddress, sender_ip: Ipv4Address, target_ip: Ipv4Address) -> Packet {
+        Packet { op: OP_REQUEST, sender_mac: sender_mac, sender_ip: sender_ip, target_mac: MacAddress::default(), target_ip: target_ip }
+    }
+    /// Reply to this request, from `mac`
+    pub fn reply(&self, mac: MacAddress) -> Packet {
+        Packet { op: OP_REPLY, sender_mac: mac, sender_ip: self.target_ip, target_mac: self.sender_mac, target_ip: self.sender_ip }
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PACKET_SIZE);
+        bytes.extend_from_slice(&[(HTYPE_ETHERNET >> 8) as u8, HTYPE_ETHERNET as u8, (TYPE_IPV4 >> 8) as u8, TYPE_IPV4 as u8,
+            6, 4, (self.op >> 8) as u8, self.op as u8]);
+        bytes.extend_from_slice(&self.sender_mac.0);
+        bytes.extend_from_slice(&self.sender_ip.0);
+        bytes.extend_from_slice(&self.target_mac.0);
+        bytes.extend_from_slice(&self.target_ip.0);
+        bytes
+    }
+}
+
+/// Learnt IPv4 to MAC mappings
+pub struct Cache {
+    entries: Vec<(Ipv4Address, MacAddress, Duration)>,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache { entries: Vec::new() }
+    }
+    /// Record a mapping, learnt at `now`
+    pub fn insert(&mut self, ip: Ipv4Address, mac: MacAddress, now: Duration) {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.0 == ip) {
+            entry.1 = mac;
+            entry.2 = now;
+            return;
+        }
+        if self.entries.len() == CACHE_SIZE {
+            let oldest = (0..self.entries.len()).min_by_key(|&index| self.entries[index].2).unwrap();
+            self.entries.remove(oldest);
+        }
+        self.entries.push((ip, mac, now));
+    }
+    pub fn lookup(&self, ip: Ipv4Address, now: Duration) -> Option<MacAddress> {
+        self.entries.iter()
+            .find(|entry| entry.0 == ip && now < entry.2 + Duration::from_secs(ENTRY_LIFETIME))
+            .map(|entry| entry.1)
+    }
+}
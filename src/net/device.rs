@@ -0,0 +1,34 @@
+//! Network devices
+
+use alloc::vec::Vec;
+use super::ethernet::MacAddress;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Device has no room for more frames until some have been sent
+    Busy,
+    /// Frame is larger than the device can send
+    TooLarge,
+    /// Device has no link
+    NoLink,
+}
+
+/// Something that sends and receives Ethernet frames
+///
+/// Frames are complete, from the destination address up to but excluding the FCS. Reception is
+/// polled. Devices normally only receive frames addressed to them, broadcasts, and multicasts,
+/// but may be able to receive everything on the wire, which is what packet capture wants.
+pub trait NetDevice {
+    fn name(&self) -> &'static str;
+    fn mac(&self) -> MacAddress;
+    /// Largest payload a frame can carry
+    fn mtu(&self) -> usize;
+    unsafe fn link_up(&mut self) -> bool;
+    unsafe fn transmit(&mut self, frame: &[u8]) -> Result<(), Error>;
+    /// Take the next received frame, if any
+    unsafe fn receive(&mut self) -> Option<Vec<u8>>;
+    /// Receive every frame regardless of its destination, returning whether the device can
+    unsafe fn set_promiscuous(&mut self, _enable: bool) -> bool {
+        false
+    }
+}
@@ -0,0 +1,193 @@
+//! DHCP client
+//!
+//! Follows the usual discover, offer, request and acknowledge exchange to obtain a lease. The
+//! client only tracks protocol state, sending messages and retrying them is up to the caller.
+//! Leases are not renewed, they are assumed to outlive the kernel.
+
+use alloc::vec::Vec;
+use super::ethernet::MacAddress;
+use super::ipv4::Ipv4Address;
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+/// Ask the server to broadcast replies, as unicast cannot be received before configuration
+const FLAG_BROADCAST: u16 = 0x8000;
+const MAGIC: [u8; 4] = [99, 130, 83, 99];
+/// Size of the fixed part of a message, up to the magic cookie
+const FIXED_SIZE: usize = 236;
+
+const XID_OFFSET: usize = 4;
+const FLAGS_OFFSET: usize = 10;
+const YIADDR_OFFSET: usize = 16;
+const CHADDR_OFFSET: usize = 28;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PARAMETERS: u8 = 55;
+const OPTION_END: u8 = 255;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lease {
+    pub address: Ipv4Address,
+    pub netmask: Ipv4Address,
+    pub gateway: Option<Ipv4Address>,
+    pub dns: Option<Ipv4Address>,
+    pub server: Ipv4Address,
+    /// Length of the lease in seconds
+    pub lease_time: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Selecting,
+    Requesting { server: Ipv4Address, offered: Ipv4Address },
+    Bound(Lease),
+}
+
+/// A reply from a server
+struct Reply {
+    kind: u8,
+    yiaddr: Ipv4Address,
+    server: Option<Ipv4Address>,
+    netmask: Option<Ipv4Address>,
+    gateway: Option<Ipv4Address>,
+    dns: Option<Ipv4Address>,
+    lease_time: u32,
+}
+
+pub struct Client {
+    mac: MacAddress,
+    xid: u32,
+    state: State,
+}
+
+impl Client {
+    /// Start obtaining a lease, with `xid` identifying this client's messages
+    pub fn new(mac: MacAddress, xid: u32) -> Client {
+        Client { mac: mac, xid: xid, state: State::Selecting }
+    }
+    pub fn lease(&self) -> Option<Lease> {
+        match self.state {
+            State::Bound(lease) => Some(lease),
+            _ => None,
+        }
+    }
+    /// Message to broadcast in the current state, nothing once bound
+    pub fn message(&self) -> Option<Vec<u8>> {
+        let mut message = vec![0; FIXED_SIZE];
+        message[0] = OP_REQUEST;
+        message[1] = HTYPE_ETHERNET;
+        message[2] = 6;
+        message[XID_OFFSET..XID_OFFSET + 4].copy_from_slice(&[(self.xid >> 24) as u8, (self.xid >> 16) as u8, (self.xid >> 8) as u8, self.xid as u8]);
+        message[FLAGS_OFFSET] = (FLAG_BROADCAST >> 8) as u8;
+        message[CHADDR_OFFSET..CHADDR_OFFSET + 6].copy_from_slice(&self.mac.0);
+        message.extend_from_slice(&MAGIC);
+        match self.state {
+            State::Selecting => message.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, DHCPDISCOVER]),
+            State::Requesting { server, offered } => {
+                message.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, DHCPREQUEST, OPTION_REQUESTED_IP, 4]);
+                message.extend_from_slice(&offered.0);
+                message.extend_from_slice(&[OPTION_SERVER_ID, 4]);
+                message.extend_from_slice(&server.0);
+            },
+            State::Bound(_) => return None,
+        }
+        message.extend_from_slice(&[OPTION_PARAMETERS, 4, OPTION_SUBNET_MASK, OPTION_ROUTER, OPTION_DNS, OPTION_LEASE_TIME, OPTION_END]);
+        Some(message)
+    }
+    fn parse(&self, bytes: &[u8]) -> Option<Reply> {
+        if bytes.len() < FIXED_SIZE + MAGIC.len() || bytes[0] != OP_REPLY || bytes[FIXED_SIZE..FIXED_SIZE + 4] != MAGIC {
+            return None;
+        }
+        let xid = (bytes[XID_OFFSET] as u32) << 24 | (bytes[XID_OFFSET + 1] as u32) << 16
+            | (bytes[XID_OFFSET + 2] as u32) << 8 | bytes[XID_OFFSET + 3] as u32;
+        if xid != self.xid || MacAddress::from_slice(&bytes[CHADDR_OFFSET..]) != self.mac {
+            return None;
+        }
+        let mut reply = Reply {
+            kind: 0,
+            yiaddr: Ipv4Address::from_slice(&bytes[YIADDR_OFFSET..]),
+            server: None,
+            netmask: None,
+            gateway: None,
+            dns: None,
+            lease_time: 0,
+        };
+        let mut options = &bytes[FIXED_SIZE + 4..];
+        while let Some(&code) = options.first() {
+            if code == OPTION_END {
+                break;
+            }
+            if code == OPTION_PAD {
+                options = &options[1..];
+                continue;
+            }
+            let len = *options.get(1)? as usize;
+            let data = options.get(2..2 + len)?;
+            let address = if len >= 4 { Some(Ipv4Address::from_slice(data)) } else { None };
+            match code {
+                OPTION_MESSAGE_TYPE if len == 1 => reply.kind = data[0],
+                OPTION_SERVER_ID => reply.server = address,
+                OPTION_SUBNET_MASK => reply.netmask = address,
+                OPTION_ROUTER => reply.gateway = address,
+                OPTION_DNS => reply.dns = address,
+                OPTION_LEASE_TIME if len == 4 => reply.lease_time = address.unwrap().to_u32(),
+                _ => (),
+            }
+            options = &options[2 + len..];
+        }
+        Some(reply)
+    }
+    /// Handle a message received on the client port
+    ///
+    /// Returns whether the state changed, in which case any new `message` should be sent
+    /// straight away.
+    pub fn receive(&mut self, bytes: &[u8]) -> bool {
+        let reply = match self.parse(bytes) {
+            Some(reply) => reply,
+            None => return false,
+        };
+        match (self.state, reply.kind) {
+            (State::Selecting, DHCPOFFER) => match reply.server {
+                Some(server) => {
+                    self.state = State::Requesting { server: server, offered: reply.yiaddr };
+                    true
+                },
+                None => false,
+            },
+            (State::Requesting { server, offered }, DHCPACK) if reply.yiaddr == offered => {
+                self.state = State::Bound(Lease {
+                    address: offered,
+                    // Without a mask assume the natural class C sized network
+                    netmask: reply.netmask.unwrap_or(Ipv4Address([255, 255, 255, 0])),
+                    gateway: reply.gateway,
+                    dns: reply.dns,
+                    server: server,
+                    lease_time: reply.lease_time,
+                });
+                true
+            },
+            (State::Requesting { .. }, DHCPNAK) => {
+                self.state = State::Selecting;
+                true
+            },
+            _ => false,
+        }
+    }
+}
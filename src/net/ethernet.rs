@@ -0,0 +1,81 @@
+//! Ethernet II frames
+
+use alloc::vec::Vec;
+use core::fmt;
+
+pub const HEADER_SIZE: usize = 14;
+/// Largest payload of a frame without jumbo frames
+pub const MTU: usize = 1500;
+/// Shortest frame, without the FCS, that the payload is padded up to
+const MIN_FRAME: usize = 60;
+
+pub const TYPE_IPV4: u16 = 0x0800;
+pub const TYPE_ARP: u16 = 0x0806;
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xFF; 6]);
+    pub fn is_broadcast(&self) -> bool {
+        *self == MacAddress::BROADCAST
+    }
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 1 != 0
+    }
+    pub fn from_slice(bytes: &[u8]) -> MacAddress {
+        let mut mac = [0; 6];
+        mac.copy_from_slice(&bytes[..6]);
+        MacAddress(mac)
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let m = &self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", m[0], m[1], m[2], m[3], m[4], m[5])
+    }
+}
+
+impl fmt::Debug for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// A received frame, borrowing its payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub dst: MacAddress,
+    pub src: MacAddress,
+    pub ethertype: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Option<Frame<'a>> {
+        if bytes.len() < HEADER_SIZE {
+            return None;
+        }
+        Some(Frame {
+            dst: MacAddress::from_slice(&bytes[0..]),
+            src: MacAddress::from_slice(&bytes[6..]),
+            ethertype: (bytes[12] as u16) << 8 | bytes[13] as u16,
+            payload: &bytes[HEADER_SIZE..],
+        })
+    }
+}
+
+/// Build a frame, padded to the minimum length
+pub fn build(dst: MacAddress, src: MacAddress, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(MIN_FRAME.max(HEADER_SIZE + payload.len()));
+    frame.extend_from_slice(&dst.0);
+    frame.extend_from_slice(&src.0);
+    frame.push((ethertype >> 8) as u8);
+    frame.push(ethertype as u8);
+    frame.extend_from_slice(payload);
+    while frame.len() < MIN_FRAME {
+        frame.push(0);
+    }
+    frame
+}
@@ -0,0 +1,50 @@
+//! ICMP echo
+//!
+//! Only echo requests and replies are handled, other messages are ignored.
+
+use alloc::vec::Vec;
+use super::ipv4::checksum;
+
+pub const TYPE_ECHO_REPLY: u8 = 0;
+pub const TYPE_ECHO_REQUEST: u8 = 8;
+const HEADER_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Echo<'a> {
+    /// `TYPE_ECHO_REQUEST` or `TYPE_ECHO_REPLY`
+    pub kind: u8,
+    pub id: u16,
+    pub seq: u16,
+    pub data: &'a [u8],
+}
+
+impl<'a> Echo<'a> {
+    /// Parse an echo request or reply, checking the checksum
+    pub fn parse(bytes: &'a [u8]) -> Option<Echo<'a>> {
+        if bytes.len() < HEADER_SIZE || checksum(bytes, 0) != 0 || bytes[1] != 0 {
+            return None;
+        }
+        match bytes[0] {
+            TYPE_ECHO_REPLY | TYPE_ECHO_REQUEST => Some(Echo {
+                kind: bytes[0],
+                id: (bytes[4] as u16) << 8 | bytes[5] as u16,
+                seq: (bytes[6] as u16) << 8 | bytes[7] as u16,
+                data: &bytes[HEADER_SIZE..],
+            }),
+            _ => None,
+        }
+    }
+    /// The reply to this request
+    pub fn reply(&self) -> Echo<'a> {
+        Echo { kind: TYPE_ECHO_REPLY, ..*self }
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.data.len());
+        bytes.extend_from_slice(&[self.kind, 0, 0, 0, (self.id >> 8) as u8, self.id as u8, (self.seq >> 8) as u8, self.seq as u8]);
+        bytes.extend_from_slice(self.data);
+        let sum = checksum(&bytes, 0);
+        bytes[2] = (sum >> 8) as u8;
+        bytes[3] = sum as u8;
+        bytes
+    }
+}
@@ -0,0 +1,471 @@
+//! A network interface and the protocols above it
+//!
+//! Everything happens when the interface is polled, which receives and handles every waiting
+//! frame and retries anything that has timed out. Time is passed in, rather than read, so that
+//! the caller decides what clock to use.
+//!
+//! Packets to a host that has not been resolved yet wait for the ARP reply, for a while.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+use super::arp::{self, Cache};
+use super::device::{self, NetDevice};
+use super::dhcp::{self, Client, Lease};
+use super::ethernet::{self, Frame, MacAddress, TYPE_ARP, TYPE_IPV4};
+use super::icmp::{self, Echo};
+use super::ipv4::{self, Ipv4Address, Packet, PROTOCOL_ICMP, PROTOCOL_UDP};
+use super::udp::Datagram;
+
+/// Most packets waiting on address resolution, beyond which the oldest is dropped
+const MAX_WAITING: usize = 16;
+/// Milliseconds between ARP requests for a host
+const ARP_RETRY_MS: u64 = 1000;
+/// Milliseconds a packet waits on address resolution before being dropped
+const ARP_TIMEOUT_MS: u64 = 3000;
+/// Milliseconds between retries of a DHCP message
+const DHCP_RETRY_MS: u64 = 2000;
+/// Most datagrams queued on a socket, beyond which new ones are dropped
+const SOCKET_QUEUE: usize = 32;
+/// Most echo replies remembered
+const MAX_ECHO_REPLIES: usize = 16;
+/// Ports handed out when binding to port 0
+const EPHEMERAL_PORTS: (u16, u16) = (49152, 65535);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Interface has no address
+    NotConfigured,
+    /// Destination is not on the local network and there is no gateway
+    NoRoute,
+    /// Port is already bound
+    AddressInUse,
+    /// Port is not bound
+    NotBound,
+    /// Packet would not fit in a frame
+    TooLarge,
+    Device(device::Error),
+}
+
+impl From<device::Error> for Error {
+    fn from(error: device::Error) -> Error {
+        Error::Device(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub address: Ipv4Address,
+    pub netmask: Ipv4Address,
+    pub gateway: Option<Ipv4Address>,
+    pub dns: Option<Ipv4Address>,
+}
+
+impl Config {
+    pub fn prefix_len(&self) -> u32 {
+        self.netmask.to_u32().count_ones()
+    }
+    /// Whether `ip` is on the local network
+    pub fn on_link(&self, ip: Ipv4Address) -> bool {
+        let mask = self.netmask.to_u32();
+        ip.to_u32() & mask == self.address.to_u32() & mask
+    }
+    /// Broadcast address of the local network
+    pub fn broadcast(&self) -> Ipv4Address {
+        Ipv4Address::from_u32(self.address.to_u32() | !self.netmask.to_u32())
+    }
+}
+
+impl From<Lease> for Config {
+    fn from(lease: Lease) -> Config {
+        Config { address: lease.address, netmask: lease.netmask, gateway: lease.gateway, dns: lease.dns }
+    }
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len())?;
+        if let Some(gateway) = self.gateway {
+            write!(f, " gateway {}", gateway)?;
+        }
+        if let Some(dns) = self.dns {
+            write!(f, " dns {}", dns)?;
+        }
+        Ok(())
+    }
+}
+
+/// A datagram received on a socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Received {
+    pub src: Ipv4Address,
+    pub src_port: u16,
+    pub data: Vec<u8>,
+}
+
+struct Socket {
+    port: u16,
+    queue: VecDeque<Received>,
+}
+
+/// An IP packet waiting for its next hop to be resolved
+struct Waiting {
+    next_hop: Ipv4Address,
+    packet: Vec<u8>,
+    since: Duration,
+}
+
+/// Counts of traffic through an interface
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub received: u64,
+    pub sent: u64,
+    /// Frames received that were malformed or not for us
+    pub dropped: u64,
+}
+
+pub struct Interface {
+    name: String,
+    device: Box<NetDevice>,
+    mac: MacAddress,
+    config: Option<Config>,
+    arp: Cache,
+    /// When each host was last asked for with ARP
+    arp_requests: Vec<(Ipv4Address, Duration)>,
+    waiting: VecDeque<Waiting>,
+    sockets: Vec<Socket>,
+    /// Echo replies received, as (source, id, sequence)
+    echo_replies: VecDeque<(Ipv4Address, u16, u16)>,
+    /// DHCP client, along with when it last sent
+    dhcp: Option<(Client, Duration)>,
+    next_id: u16,
+    next_port: u16,
+    stats: Stats,
+}
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+impl Interface {
+    pub fn new(name: String, device: Box<NetDevice>) -> Interface {
+        let mac = device.mac();
+        Interface {
+            name: name,
+            device: device,
+            mac: mac,
+            config: None,
+            arp: Cache::new(),
+            arp_requests: Vec::new(),
+            waiting: VecDeque::new(),
+            sockets: Vec::new(),
+            echo_replies: VecDeque::new(),
+            dhcp: None,
+            next_id: 0,
+            next_port: EPHEMERAL_PORTS.0,
+            stats: Stats::default(),
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn mac(&self) -> MacAddress {
+        self.mac
+    }
+    pub fn device(&mut self) -> &mut NetDevice {
+        &mut *self.device
+    }
+    pub fn config(&self) -> Option<Config> {
+        self.config
+    }
+    pub fn set_config(&mut self, config: Option<Config>) {
+        self.config = config;
+    }
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+    /// Obtain a configuration with DHCP, `xid` should differ between boots
+    pub unsafe fn start_dhcp(&mut self, now: Duration, xid: u32) {
+        self.dhcp = Some((Client::new(self.mac, xid), now));
+        self.send_dhcp(now);
+    }
+    /// Whether DHCP is still in progress
+    pub fn dhcp_pending(&self) -> bool {
+        self.dhcp.is_some()
+    }
+    unsafe fn send_dhcp(&mut self, now: Duration) {
+        let message = match self.dhcp {
+            Some((ref client, _)) => client.message(),
+            None => None,
+        };
+        if let Some(message) = message {
+            let datagram = Datagram { src_port: dhcp::CLIENT_PORT, dst_port: dhcp::SERVER_PORT, payload: &message };
+            let payload = datagram.to_bytes(Ipv4Address::UNSPECIFIED, Ipv4Address::BROADCAST);
+            let packet = ipv4::build(Ipv4Address::UNSPECIFIED, Ipv4Address::BROADCAST, PROTOCOL_UDP, self.next_id(), &payload);
+            let _ = self.transmit(MacAddress::BROADCAST, TYPE_IPV4, &packet);
+        }
+        if let Some((_, ref mut sent)) = self.dhcp {
+            *sent = now;
+        }
+    }
+    fn next_id(&mut self) -> u16 {
+        self.next_id = self.next_id.wrapping_add(1);
+        self.next_id
+    }
+    unsafe fn transmit(&mut self, dst: MacAddress, ethertype: u16, payload: &[u8]) -> Result<(), Error> {
+        let frame = ethernet::build(dst, self.mac, ethertype, payload);
+        self.device.transmit(&frame)?;
+        self.stats.sent += 1;
+        Ok(())
+    }
+    unsafe fn request_arp(&mut self, ip: Ipv4Address, now: Duration) {
+        let recent = self.arp_requests.iter().any(|&(requested, at)| requested == ip && now < at + ms(ARP_RETRY_MS));
+        if recent {
+            return;
+        }
+        let src = match self.config {
+            Some(config) => config.address,
+            None => return,
+        };
+        self.arp_requests.retain(|&(requested, _)| requested != ip);
+        self.arp_requests.push((ip, now));
+        let request = arp::Packet::request(self.mac, src, ip).to_bytes();
+        let _ = self.transmit(MacAddress::BROADCAST, TYPE_ARP, &request);
+    }
+    /// Send an IP packet from the configured address
+    unsafe fn send_ipv4(&mut self, now: Duration, dst: Ipv4Address, protocol: u8, payload: &[u8]) -> Result<(), Error> {
+        let config = self.config.ok_or(Error::NotConfigured)?;
+        if ipv4::HEADER_SIZE + payload.len() > self.device.mtu() {
+            return Err(Error::TooLarge);
+        }
+        let id = self.next_id();
+        let packet = ipv4::build(config.address, dst, protocol, id, payload);
+        if dst.is_broadcast() || dst == config.broadcast() {
+            return self.transmit(MacAddress::BROADCAST, TYPE_IPV4, &packet);
+        }
+        let next_hop = if config.on_link(dst) { dst } else { config.gateway.ok_or(Error::NoRoute)? };
+        match self.arp.lookup(next_hop, now) {
+            Some(mac) => self.transmit(mac, TYPE_IPV4, &packet),
+            None => {
+                if self.waiting.len() == MAX_WAITING {
+                    self.waiting.pop_front();
+                }
+                self.waiting.push_back(Waiting { next_hop: next_hop, packet: packet, since: now });
+                self.request_arp(next_hop, now);
+                Ok(())
+            },
+        }
+    }
+    /// Send every waiting packet whose next hop is now known
+    unsafe fn flush_waiting(&mut self, now: Duration) {
+        let mut index = 0;
+        while index < self.waiting.len() {
+            let mac = self.arp.lookup(self.waiting[index].next_hop, now);
+            match mac {
+                Some(mac) => {
+                    let waiting = self.waiting.remove(index).unwrap();
+                    let _ = self.transmit(mac, TYPE_IPV4, &waiting.packet);
+                },
+                None => index += 1,
+            }
+        }
+    }
+    /// Send a UDP datagram from `src_port`, which need not be bound
+    pub unsafe fn send_udp(&mut self, now: Duration, src_port: u16, dst: Ipv4Address, dst_port: u16, data: &[u8]) -> Result<(), Error> {
+        let src = self.config.ok_or(Error::NotConfigured)?.address;
+        let payload = Datagram { src_port: src_port, dst_port: dst_port, payload: data }.to_bytes(src, dst);
+        self.send_ipv4(now, dst, PROTOCOL_UDP, &payload)
+    }
+    /// Receive datagrams sent to `port`, or an unused ephemeral port if it is 0
+    ///
+    /// Returns the port bound.
+    pub fn bind_udp(&mut self, port: u16) -> Result<u16, Error> {
+        let port = if port != 0 {
+            if self.sockets.iter().any(|socket| socket.port == port) {
+                return Err(Error::AddressInUse);
+            }
+            port
+        } else {
+            let count = (EPHEMERAL_PORTS.1 - EPHEMERAL_PORTS.0) as usize + 1;
+            let mut found = None;
+            for _ in 0..count {
+                let candidate = self.next_port;
+                self.next_port = if candidate == EPHEMERAL_PORTS.1 { EPHEMERAL_PORTS.0 } else { candidate + 1 };
+                if !self.sockets.iter().any(|socket| socket.port == candidate) {
+                    found = Some(candidate);
+                    break;
+                }
+            }
+            found.ok_or(Error::AddressInUse)?
+        };
+        self.sockets.push(Socket { port: port, queue: VecDeque::new() });
+        Ok(port)
+    }
+    pub fn unbind_udp(&mut self, port: u16) {
+        self.sockets.retain(|socket| socket.port != port);
+    }
+    /// Take the next datagram received on a bound port
+    pub fn recv_udp(&mut self, port: u16) -> Result<Option<Received>, Error> {
+        let socket = self.sockets.iter_mut().find(|socket| socket.port == port).ok_or(Error::NotBound)?;
+        Ok(socket.queue.pop_front())
+    }
+    /// Send an echo request
+    pub unsafe fn send_ping(&mut self, now: Duration, dst: Ipv4Address, id: u16, seq: u16, data: &[u8]) -> Result<(), Error> {
+        let echo = Echo { kind: icmp::TYPE_ECHO_REQUEST, id: id, seq: seq, data: data }.to_bytes();
+        self.send_ipv4(now, dst, PROTOCOL_ICMP, &echo)
+    }
+    /// Take the reply to an echo request, if it has been received
+    pub fn take_echo_reply(&mut self, src: Ipv4Address, id: u16, seq: u16) -> bool {
+        match self.echo_replies.iter().position(|&reply| reply == (src, id, seq)) {
+            Some(index) => {
+                self.echo_replies.remove(index);
+                true
+            },
+            None => false,
+        }
+    }
+    /// Handle every received frame and retry anything that has timed out
+    pub unsafe fn poll(&mut self, now: Duration) {
+        while let Some(frame) = self.device.receive() {
+            self.stats.received += 1;
+            if !self.handle_frame(now, &frame) {
+                self.stats.dropped += 1;
+            }
+        }
+        let retry = match self.dhcp {
+            Some((_, sent)) => now >= sent + ms(DHCP_RETRY_MS),
+            None => false,
+        };
+        if retry {
+            self.send_dhcp(now);
+        }
+        self.waiting.retain(|waiting| now < waiting.since + ms(ARP_TIMEOUT_MS));
+        let unresolved: Vec<Ipv4Address> = self.waiting.iter().map(|waiting| waiting.next_hop).collect();
+        for ip in unresolved {
+            self.request_arp(ip, now);
+        }
+    }
+    /// Returns whether the frame was used
+    unsafe fn handle_frame(&mut self, now: Duration, bytes: &[u8]) -> bool {
+        let frame = match Frame::parse(bytes) {
+            Some(frame) => frame,
+            None => return false,
+        };
+        if frame.dst != self.mac && !frame.dst.is_broadcast() {
+            return false;
+        }
+        match frame.ethertype {
+            TYPE_ARP => self.handle_arp(now, frame.payload),
+            TYPE_IPV4 => self.handle_ipv4(now, frame.payload),
+            _ => false,
+        }
+    }
+    unsafe fn handle_arp(&mut self, now: Duration, bytes: &[u8]) -> bool {
+        let (packet, config) = match (arp::Packet::parse(bytes), self.config) {
+            (Some(packet), Some(config)) => (packet, config),
+            _ => return false,
+        };
+        if packet.target_ip != config.address {
+            return false;
+        }
+        self.arp.insert(packet.sender_ip, packet.sender_mac, now);
+        if packet.op == arp::OP_REQUEST {
+            let reply = packet.reply(self.mac).to_bytes();
+            let _ = self.transmit(packet.sender_mac, TYPE_ARP, &reply);
+        }
+        self.flush_waiting(now);
+        true
+    }
+    unsafe fn handle_ipv4(&mut self, now: Duration, bytes: &[u8]) -> bool {
+        let packet = match Packet::parse(bytes) {
+            Some(packet) => packet,
+            None => return false,
+        };
+        let for_us = match self.config {
+            Some(config) => packet.dst == config.address || packet.dst.is_broadcast() || packet.dst == config.broadcast(),
+            // Before configuration anything could be the reply to DHCP
+            None => true,
+        };
+        if !for_us {
+            return false;
+        }
+        match packet.protocol {
+            PROTOCOL_ICMP => self.handle_icmp(now, &packet),
+            PROTOCOL_UDP => self.handle_udp(now, &packet),
+            _ => false,
+        }
+    }
+    unsafe fn handle_icmp(&mut self, now: Duration, packet: &Packet) -> bool {
+        let echo = match Echo::parse(packet.payload) {
+            Some(echo) => echo,
+            None => return false,
+        };
+        match echo.kind {
+            icmp::TYPE_ECHO_REQUEST if packet.dst.is_broadcast() => false,
+            icmp::TYPE_ECHO_REQUEST => {
+                let reply = echo.reply().to_bytes();
+                self.send_ipv4(now, packet.src, PROTOCOL_ICMP, &reply).is_ok()
+            },
+            _ => {
+                if self.echo_replies.len() == MAX_ECHO_REPLIES {
+                    self.echo_replies.pop_front();
+                }
+                self.echo_replies.push_back((packet.src, echo.id, echo.seq));
+                true
+            },
+        }
+    }
+    unsafe fn handle_udp(&mut self, now: Duration, packet: &Packet) -> bool {
+        let datagram = match Datagram::parse(packet.payload, packet.src, packet.dst) {
+            Some(datagram) => datagram,
+            None => return false,
+        };
+        if datagram.dst_port == dhcp::CLIENT_PORT && self.dhcp.is_some() {
+            self.handle_dhcp(now, datagram.payload);
+            return true;
+        }
+        if self.config.is_none() {
+            return false;
+        }
+        match self.sockets.iter_mut().find(|socket| socket.port == datagram.dst_port) {
+            Some(socket) => {
+                if socket.queue.len() < SOCKET_QUEUE {
+                    socket.queue.push_back(Received { src: packet.src, src_port: datagram.src_port, data: datagram.payload.to_vec() });
+                }
+                true
+            },
+            None => false,
+        }
+    }
+    unsafe fn handle_dhcp(&mut self, now: Duration, bytes: &[u8]) {
+        let (changed, lease) = match self.dhcp {
+            Some((ref mut client, _)) => (client.receive(bytes), client.lease()),
+            None => return,
+        };
+        if !changed {
+            return;
+        }
+        match lease {
+            Some(lease) => {
+                let config = Config::from(lease);
+                print!(Info, "{}: DHCP lease {} from {}", self.name, config, lease.server);
+                self.config = Some(config);
+                self.dhcp = None;
+            },
+            None => self.send_dhcp(now),
+        }
+    }
+}
+
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} {}", self.name, self.device.name(), self.mac)?;
+        match self.config {
+            Some(config) => write!(f, " {}", config),
+            None => write!(f, " unconfigured"),
+        }
+    }
+}
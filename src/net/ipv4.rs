@@ -0,0 +1,146 @@
+//! IPv4 packets
+//!
+//! Fragments are not reassembled, and are dropped, as nothing the stack sends or expects to
+//! receive is large enough to need them.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+pub const HEADER_SIZE: usize = 20;
+const VERSION_IHL: u8 = 0x45;
+const DEFAULT_TTL: u8 = 64;
+/// Don't fragment
+const FLAG_DF: u16 = 0x4000;
+const FLAG_MF: u16 = 0x2000;
+const FRAGMENT_OFFSET: u16 = 0x1FFF;
+
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_UDP: u8 = 17;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+impl Ipv4Address {
+    pub const UNSPECIFIED: Ipv4Address = Ipv4Address([0; 4]);
+    pub const BROADCAST: Ipv4Address = Ipv4Address([255; 4]);
+    pub fn from_slice(bytes: &[u8]) -> Ipv4Address {
+        Ipv4Address([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+    pub fn from_u32(value: u32) -> Ipv4Address {
+        Ipv4Address([(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8])
+    }
+    pub fn to_u32(&self) -> u32 {
+        (self.0[0] as u32) << 24 | (self.0[1] as u32) << 16 | (self.0[2] as u32) << 8 | self.0[3] as u32
+    }
+    pub fn is_unspecified(&self) -> bool {
+        *self == Ipv4Address::UNSPECIFIED
+    }
+    pub fn is_broadcast(&self) -> bool {
+        *self == Ipv4Address::BROADCAST
+    }
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0xF0 == 0xE0
+    }
+}
+
+impl fmt::Display for Ipv4Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+impl fmt::Debug for Ipv4Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl FromStr for Ipv4Address {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Ipv4Address, ()> {
+        let mut address = [0; 4];
+        let mut parts = s.split('.');
+        for byte in address.iter_mut() {
+            *byte = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        }
+        if parts.next().is_some() {
+            return Err(());
+        }
+        Ok(Ipv4Address(address))
+    }
+}
+
+/// Internet checksum of `data`, continuing from the partial `sum`
+pub fn checksum(data: &[u8], sum: u32) -> u16 {
+    let mut sum = sum;
+    for pair in data.chunks(2) {
+        let word = if pair.len() == 2 { (pair[0] as u32) << 8 | pair[1] as u32 } else { (pair[0] as u32) << 8 };
+        sum += word;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Partial checksum of the pseudo header used by transport protocols
+pub fn pseudo_header_sum(src: Ipv4Address, dst: Ipv4Address, protocol: u8, len: usize) -> u32 {
+    let src = src.to_u32();
+    let dst = dst.to_u32();
+    (src >> 16) + (src & 0xFFFF) + (dst >> 16) + (dst & 0xFFFF) + protocol as u32 + len as u32
+}
+
+/// A received packet, borrowing its payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet<'a> {
+    pub src: Ipv4Address,
+    pub dst: Ipv4Address,
+    pub protocol: u8,
+    pub ttl: u8,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    /// Parse and check a packet, dropping fragments and anything malformed
+    pub fn parse(bytes: &'a [u8]) -> Option<Packet<'a>> {
+        if bytes.len() < HEADER_SIZE || bytes[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = (bytes[0] & 0xF) as usize * 4;
+        let total_len = ((bytes[2] as usize) << 8) | bytes[3] as usize;
+        if header_len < HEADER_SIZE || total_len < header_len || total_len > bytes.len() {
+            return None;
+        }
+        if checksum(&bytes[..header_len], 0) != 0 {
+            return None;
+        }
+        let flags = (bytes[6] as u16) << 8 | bytes[7] as u16;
+        if flags & FLAG_MF != 0 || flags & FRAGMENT_OFFSET != 0 {
+            return None;
+        }
+        Some(Packet {
+            src: Ipv4Address::from_slice(&bytes[12..]),
+            dst: Ipv4Address::from_slice(&bytes[16..]),
+            protocol: bytes[9],
+            ttl: bytes[8],
+            // Ethernet padding is beyond the total length
+            payload: &bytes[header_len..total_len],
+        })
+    }
+}
+
+/// Build a packet with no options
+pub fn build(src: Ipv4Address, dst: Ipv4Address, protocol: u8, id: u16, payload: &[u8]) -> Vec<u8> {
+    let total = HEADER_SIZE + payload.len();
+    let mut packet = Vec::with_capacity(total);
+    packet.extend_from_slice(&[VERSION_IHL, 0, (total >> 8) as u8, total as u8, (id >> 8) as u8, id as u8,
+        (FLAG_DF >> 8) as u8, FLAG_DF as u8, DEFAULT_TTL, protocol, 0, 0]);
+    packet.extend_from_slice(&src.0);
+    packet.extend_from_slice(&dst.0);
+    let sum = checksum(&packet, 0);
+    packet[10] = (sum >> 8) as u8;
+    packet[11] = sum as u8;
+    packet.extend_from_slice(payload);
+    packet
+}
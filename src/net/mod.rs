@@ -0,0 +1,243 @@
+//! Networking
+//!
+//! Network drivers implement `NetDevice` and register it, which makes it an `Interface` named
+//! `eth0`, `eth1` and so on, and starts DHCP on it. The stack is polled rather than driven by
+//! interrupts, anything waiting on the network polls every interface until it is done or times
+//! out.
+//!
+//! Only IPv4 is supported, with ICMP echo and UDP above it. Sockets and `ping` use the first
+//! interface that has an address.
+
+pub mod arp;
+pub mod dhcp;
+pub mod ethernet;
+pub mod icmp;
+pub mod interface;
+pub mod ipv4;
+pub mod udp;
+pub mod device;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+use time;
+
+pub use self::device::NetDevice;
+pub use self::ethernet::MacAddress;
+pub use self::interface::{Config, Error, Interface, Received, Stats};
+pub use self::ipv4::Ipv4Address;
+
+/// Milliseconds boot waits for DHCP to configure the interfaces
+const DHCP_TIMEOUT_MS: u64 = 5000;
+/// Milliseconds to wait for each reply to the boot time ping
+const PING_TIMEOUT_MS: u64 = 1000;
+/// Number of pings made at boot
+const PING_COUNT: u16 = 4;
+
+static mut INTERFACES: Option<Vec<Interface>> = None;
+/// Address to ping at boot, from --ping
+static mut PING: Option<Ipv4Address> = None;
+static mut NEXT_PING_ID: u16 = 0;
+/// Whether the stack is in use, so that anything printed from within it does not re-enter it
+static mut ACTIVE: bool = false;
+
+fn interfaces_mut() -> &'static mut Vec<Interface> {
+    unsafe {INTERFACES.get_or_insert_with(Vec::new)}
+}
+
+/// Add a network device, returning the name of its interface
+pub fn register(device: Box<NetDevice>) -> String {
+    enter(|| {
+        let interfaces = interfaces_mut();
+        let name = format!("eth{}", interfaces.len());
+        let mut interface = Interface::new(name.clone(), device);
+        print!(Info, "Interface {}", interface);
+        let now = time::uptime();
+        // Only has to differ from other clients on the network, which the MAC mostly ensures
+        let mac = interface.mac().0;
+        let xid = (mac[2] as u32) << 24 | (mac[3] as u32) << 16 | (mac[4] as u32) << 8 | mac[5] as u32;
+        unsafe {interface.start_dhcp(now, xid ^ now.subsec_nanos())};
+        interfaces.push(interface);
+        name
+    })
+}
+
+/// Remove an interface by name, returning it
+pub fn unregister(name: &str) -> Option<Interface> {
+    enter(|| {
+        let interfaces = interfaces_mut();
+        let index = interfaces.iter().position(|interface| interface.name() == name)?;
+        Some(interfaces.remove(index))
+    })
+}
+
+/// Every registered interface
+pub fn interfaces() -> &'static mut [Interface] {
+    interfaces_mut()
+}
+
+/// Run `f` with the stack marked as in use
+///
+/// # Panics
+///
+/// If the stack is already in use, such as when an interrupt handler uses the network
+fn enter<R, F: FnOnce() -> R>(f: F) -> R {
+    assert!(!unsafe {ACTIVE}, "Network stack re-entered");
+    unsafe {ACTIVE = true};
+    let result = f();
+    unsafe {ACTIVE = false};
+    result
+}
+
+/// Whether the stack is in use, in which case it must not be entered
+pub fn active() -> bool {
+    unsafe {ACTIVE}
+}
+
+fn poll_interfaces() {
+    let now = time::uptime();
+    for interface in interfaces_mut().iter_mut() {
+        unsafe {interface.poll(now)};
+    }
+}
+
+/// Handle everything received on every interface
+pub fn poll() {
+    enter(poll_interfaces)
+}
+
+/// Poll until `done` returns something, or `timeout` passes
+fn poll_until<R, F: FnMut() -> Option<R>>(timeout: Duration, mut done: F) -> Option<R> {
+    let end = time::uptime() + timeout;
+    loop {
+        poll();
+        if let Some(result) = done() {
+            return Some(result);
+        }
+        if time::uptime() >= end {
+            return None;
+        }
+    }
+}
+
+/// First interface with an address
+fn default_interface() -> Result<&'static mut Interface, Error> {
+    interfaces_mut().iter_mut().find(|interface| interface.config().is_some()).ok_or(Error::NotConfigured)
+}
+
+/// Send an echo request to `dst` and wait for the reply, returning the round trip time
+pub fn ping(dst: Ipv4Address, seq: u16, timeout: Duration) -> Result<Option<Duration>, Error> {
+    let id = unsafe {
+        NEXT_PING_ID = NEXT_PING_ID.wrapping_add(1);
+        NEXT_PING_ID
+    };
+    let start = time::uptime();
+    enter(|| unsafe {default_interface()?.send_ping(start, dst, id, seq, b"rlk ping")})?;
+    Ok(poll_until(timeout, || {
+        let replied = enter(|| default_interface().ok().map_or(false, |interface| interface.take_echo_reply(dst, id, seq)));
+        if replied { Some(time::uptime() - start) } else { None }
+    }))
+}
+
+/// A UDP port on the default interface, unbound when dropped
+pub struct UdpSocket {
+    port: u16,
+}
+
+impl UdpSocket {
+    /// Bind to `port`, or to an unused one if it is 0
+    pub fn bind(port: u16) -> Result<UdpSocket, Error> {
+        let port = enter(|| default_interface()?.bind_udp(port))?;
+        Ok(UdpSocket { port: port })
+    }
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+    pub fn send_to(&self, data: &[u8], dst: Ipv4Address, port: u16) -> Result<(), Error> {
+        enter(|| unsafe {default_interface()?.send_udp(time::uptime(), self.port, dst, port, data)})
+    }
+    /// Send without waiting for the stack, returning nothing if it is in use
+    ///
+    /// Anything received is handled first, so that address resolution can finish without
+    /// anyone else polling.
+    pub fn try_send_to(&self, data: &[u8], dst: Ipv4Address, port: u16) -> Option<Result<(), Error>> {
+        if active() {
+            return None;
+        }
+        Some(enter(|| {
+            poll_interfaces();
+            unsafe {default_interface()?.send_udp(time::uptime(), self.port, dst, port, data)}
+        }))
+    }
+    /// Take a received datagram, if there is one, without waiting
+    pub fn recv_from(&self) -> Result<Option<Received>, Error> {
+        enter(|| {
+            poll_interfaces();
+            default_interface()?.recv_udp(self.port)
+        })
+    }
+    /// Wait up to `timeout` for a datagram
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<Received>, Error> {
+        let port = self.port;
+        let result = poll_until(timeout, || match enter(|| default_interface().and_then(|interface| interface.recv_udp(port))) {
+            Ok(None) => None,
+            Ok(Some(received)) => Some(Ok(received)),
+            Err(error) => Some(Err(error)),
+        });
+        match result {
+            Some(Ok(received)) => Ok(Some(received)),
+            Some(Err(error)) => Err(error),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        let port = self.port;
+        enter(|| if let Ok(interface) = default_interface() {
+            interface.unbind_udp(port);
+        })
+    }
+}
+
+/// Wait for DHCP to configure the interfaces, then run the --ping check if asked for
+pub fn init() {
+    if interfaces_mut().is_empty() {
+        return;
+    }
+    let configured = poll_until(Duration::from_millis(DHCP_TIMEOUT_MS), || {
+        if interfaces_mut().iter().all(|interface| !interface.dhcp_pending()) { Some(()) } else { None }
+    });
+    for interface in interfaces_mut().iter() {
+        if interface.dhcp_pending() {
+            print!(Error, "{}: no reply to DHCP", interface.name());
+        }
+    }
+    if configured.is_none() && default_interface().is_err() {
+        return;
+    }
+    if let Some(dst) = unsafe {PING} {
+        for seq in 0..PING_COUNT {
+            match ping(dst, seq, Duration::from_millis(PING_TIMEOUT_MS)) {
+                Ok(Some(rtt)) => print!(Info, "Reply from {} seq {} in {} us", dst, seq, time::as_nanos(rtt) / 1000),
+                Ok(None) => print!(Error, "No reply from {} seq {}", dst, seq),
+                Err(error) => {
+                    print!(Error, "Failed to ping {}: {:?}", dst, error);
+                    break;
+                },
+            }
+        }
+    }
+}
+
+fn set_ping(address: &str) {
+    match address.parse() {
+        Ok(address) => unsafe {PING = Some(address)},
+        Err(_) => print!(Error, "Invalid address to ping {}", address),
+    }
+}
+
+/// Pings an address once the network is up, for example --ping=10.0.2.2
+make_cmdline_decl!("ping", set_ping, PING_DECL);
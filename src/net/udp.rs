@@ -0,0 +1,52 @@
+//! UDP datagrams
+
+use alloc::vec::Vec;
+use super::ipv4::{checksum, pseudo_header_sum, Ipv4Address, PROTOCOL_UDP};
+
+pub const HEADER_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Datagram<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Datagram<'a> {
+    /// Parse a datagram carried between `src` and `dst`, checking the checksum if it has one
+    pub fn parse(bytes: &'a [u8], src: Ipv4Address, dst: Ipv4Address) -> Option<Datagram<'a>> {
+        if bytes.len() < HEADER_SIZE {
+            return None;
+        }
+        let len = (bytes[4] as usize) << 8 | bytes[5] as usize;
+        if len < HEADER_SIZE || len > bytes.len() {
+            return None;
+        }
+        let bytes = &bytes[..len];
+        let sum = (bytes[6] as u16) << 8 | bytes[7] as u16;
+        if sum != 0 && checksum(bytes, pseudo_header_sum(src, dst, PROTOCOL_UDP, len)) != 0 {
+            return None;
+        }
+        Some(Datagram {
+            src_port: (bytes[0] as u16) << 8 | bytes[1] as u16,
+            dst_port: (bytes[2] as u16) << 8 | bytes[3] as u16,
+            payload: &bytes[HEADER_SIZE..],
+        })
+    }
+    /// Build the datagram to be carried between `src` and `dst`
+    pub fn to_bytes(&self, src: Ipv4Address, dst: Ipv4Address) -> Vec<u8> {
+        let len = HEADER_SIZE + self.payload.len();
+        let mut bytes = Vec::with_capacity(len);
+        bytes.extend_from_slice(&[(self.src_port >> 8) as u8, self.src_port as u8, (self.dst_port >> 8) as u8, self.dst_port as u8,
+            (len >> 8) as u8, len as u8, 0, 0]);
+        bytes.extend_from_slice(self.payload);
+        let sum = match checksum(&bytes, pseudo_header_sum(src, dst, PROTOCOL_UDP, len)) {
+            // A checksum of 0 means there is none, so is sent as its equivalent
+            0 => 0xFFFF,
+            sum => sum,
+        };
+        bytes[6] = (sum >> 8) as u8;
+        bytes[7] = sum as u8;
+        bytes
+    }
+}
@@ -1,13 +1,13 @@
 use core::panic::PanicInfo;
 
-use drivers::io::{Io, PortIO};
+use con;
+use drivers::io::PortIO;
+use drivers::i8042;
+use drivers::qemu;
+use time;
 
 pub unsafe fn reboot() -> ! {
-    let mut kb = PortIO::new(0x64);
-    // drain keyboard buffer
-    while (kb.read(0) & 0x2) != 0 {}
-    // toggle reset pin
-    kb.write(0, 0xFE);
+    i8042::Controller::new(PortIO::new(i8042::PORT_BASE)).pulse_reset();
     print!(Error, "Reboot by 8042 seems to have failed");
     loop {}
 }
@@ -22,6 +22,13 @@ pub extern fn panic(info: &PanicInfo) -> ! {
     } else {
         print!(Panic, "Panic at {:?} with {:?}", info.location(), info.message());
     }
+    if let Some(now) = time::wall_clock() {
+        print!(Panic, "Panicked on {}", now);
+    }
+    // Buffered cons, such as the netconsole, would otherwise lose the end of the log
+    con::flush();
+    // Under QEMU let whoever started it know how it went
+    qemu::exit(qemu::EXIT_PANIC);
     // No power management yet for power off, so try and trigger a reset instead
     unsafe {reboot()}
 }
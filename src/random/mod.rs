@@ -0,0 +1,107 @@
+//! Kernel random numbers
+//!
+//! Drivers for hardware random number generators implement `EntropySource` and register it.
+//! Sources are read when registered, and again whenever enough output has been generated since
+//! they were last read. The uptime is mixed in on every request as well, uncredited. Output is
+//! available before the pool is seeded, but is only unpredictable once `seeded` is true.
+
+pub mod pool;
+pub mod source;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::time::Duration;
+use time;
+use self::pool::Pool;
+
+pub use self::source::EntropySource;
+
+/// Bytes read from a source when it is registered
+const SEED_BYTES: usize = 64;
+/// Milliseconds to wait for a new source to provide its first bytes
+const SEED_TIMEOUT_MS: u64 = 100;
+/// Bytes read from the sources when reseeding
+const RESEED_BYTES: usize = 32;
+/// Output generated before the sources are read again
+pub const RESEED_INTERVAL: u64 = 1024 * 1024;
+
+static mut POOL: Option<Pool> = None;
+static mut SOURCES: Option<Vec<(usize, Box<EntropySource>)>> = None;
+static mut NEXT_SOURCE: usize = 0;
+
+fn pool() -> &'static mut Pool {
+    unsafe {POOL.get_or_insert_with(Pool::new)}
+}
+
+fn sources() -> &'static mut Vec<(usize, Box<EntropySource>)> {
+    unsafe {SOURCES.get_or_insert_with(Vec::new)}
+}
+
+/// Read up to `len` bytes from a source into the pool
+fn feed(source: &mut EntropySource, len: usize) -> usize {
+    let mut buffer = [0; SEED_BYTES];
+    let len = unsafe {source.read(&mut buffer[..len.min(SEED_BYTES)])};
+    pool().add(&buffer[..len], len * source.bits_per_byte());
+    len
+}
+
+fn mix_uptime() {
+    let now = time::uptime();
+    let nanos = now.as_secs() ^ (now.subsec_nanos() as u64) << 32;
+    let mut bytes = [0; 8];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (nanos >> (i * 8)) as u8;
+    }
+    pool().add(&bytes, 0);
+}
+
+/// Add a source, and seed the pool from it, returning an ID to unregister it with
+pub fn register(mut source: Box<EntropySource>) -> usize {
+    let end = time::uptime() + Duration::from_millis(SEED_TIMEOUT_MS);
+    let mut seeded = 0;
+    while seeded < SEED_BYTES && time::uptime() < end {
+        seeded += feed(&mut *source, SEED_BYTES - seeded);
+    }
+    if seeded < SEED_BYTES {
+        print!(Error, "{} provided {} of {} seed bytes", source.name(), seeded, SEED_BYTES);
+    }
+    print!(Info, "Entropy source {}, pool has {} bits", source.name(), pool().bits());
+    let id = unsafe {NEXT_SOURCE};
+    unsafe {NEXT_SOURCE += 1};
+    sources().push((id, source));
+    id
+}
+
+pub fn unregister(id: usize) -> Option<Box<EntropySource>> {
+    let sources = sources();
+    let index = sources.iter().position(|source| source.0 == id)?;
+    Some(sources.remove(index).1)
+}
+
+/// Mix data into the pool, such as timings, crediting it with `bits` of entropy
+pub fn add_entropy(data: &[u8], bits: usize) {
+    pool().add(data, bits);
+}
+
+/// Whether the pool has had enough entropy for its output to be unpredictable
+pub fn seeded() -> bool {
+    pool().seeded()
+}
+
+/// Fill `buffer` with random bytes
+pub fn fill(buffer: &mut [u8]) {
+    if !pool().seeded() || pool().generated() >= RESEED_INTERVAL {
+        for source in sources().iter_mut() {
+            feed(&mut *source.1, RESEED_BYTES);
+        }
+        pool().reseeded();
+    }
+    mix_uptime();
+    pool().fill(buffer);
+}
+
+pub fn u64() -> u64 {
+    let mut bytes = [0; 8];
+    fill(&mut bytes);
+    bytes.iter().rev().fold(0, |value, &byte| value << 8 | byte as u64)
+}
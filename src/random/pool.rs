@@ -0,0 +1,122 @@
+//! Entropy pool and the generator built on it
+//!
+//! Input is gathered into a 32 byte block, which is absorbed into the generator key once full,
+//! or before output is generated, by hashing the key and block together with ChaCha20. Output
+//! is the ChaCha20 keystream, and the key is replaced from the keystream after every request,
+//! so that earlier output cannot be recovered from the state.
+
+/// Bits of entropy needed before the output is considered unpredictable
+pub const SEED_BITS: usize = 256;
+
+const BLOCK_SIZE: usize = 64;
+const INPUT_SIZE: usize = 32;
+/// Nonce used when absorbing input, which keeps it apart from the output keystream
+const ABSORB_NONCE: [u32; 3] = [0x6162_736F, 0x7262_0000, 0];
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// The ChaCha20 block function, as in RFC 7539
+pub fn chacha20_block(key: &[u32; 8], counter: u32, nonce: &[u32; 3]) -> [u32; 16] {
+    let mut state = [0x6170_7865, 0x3320_646E, 0x7962_2D32, 0x6B20_6574,
+        key[0], key[1], key[2], key[3], key[4], key[5], key[6], key[7],
+        counter, nonce[0], nonce[1], nonce[2]];
+    let initial = state;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    for (word, initial) in state.iter_mut().zip(initial.iter()) {
+        *word = word.wrapping_add(*initial);
+    }
+    state
+}
+
+pub struct Pool {
+    key: [u32; 8],
+    input: [u8; INPUT_SIZE],
+    input_len: usize,
+    /// Entropy credited to everything added, up to `SEED_BITS`
+    bits: usize,
+    /// Bytes generated since the sources were last read, which absorbing input does not reset
+    generated: u64,
+    /// Number of times the key has been replaced, which keeps output streams apart
+    generation: u64,
+}
+
+impl Pool {
+    pub fn new() -> Pool {
+        Pool { key: [0; 8], input: [0; INPUT_SIZE], input_len: 0, bits: 0, generated: 0, generation: 0 }
+    }
+    /// Mix in `data`, crediting it with `bits` of entropy
+    pub fn add(&mut self, data: &[u8], bits: usize) {
+        for &byte in data {
+            self.input[self.input_len] ^= byte;
+            self.input_len += 1;
+            if self.input_len == INPUT_SIZE {
+                self.absorb();
+            }
+        }
+        self.bits = (self.bits + bits).min(SEED_BITS);
+    }
+    /// Entropy credited so far, up to `SEED_BITS`
+    pub fn bits(&self) -> usize {
+        self.bits
+    }
+    pub fn seeded(&self) -> bool {
+        self.bits >= SEED_BITS
+    }
+    /// Bytes generated since `reseeded` was last called
+    pub fn generated(&self) -> u64 {
+        self.generated
+    }
+    /// Note that the sources have been read again, restarting the count of `generated`
+    pub fn reseeded(&mut self) {
+        self.generated = 0;
+    }
+    fn absorb(&mut self) {
+        let mut key = self.key;
+        for (i, word) in key.iter_mut().enumerate() {
+            let input = &self.input[i * 4..];
+            *word ^= input[0] as u32 | (input[1] as u32) << 8 | (input[2] as u32) << 16 | (input[3] as u32) << 24;
+        }
+        let block = chacha20_block(&key, 0, &ABSORB_NONCE);
+        self.key.copy_from_slice(&block[..8]);
+        self.input = [0; INPUT_SIZE];
+        self.input_len = 0;
+    }
+    /// Fill `output` from the generator
+    pub fn fill(&mut self, output: &mut [u8]) {
+        if self.input_len > 0 {
+            self.absorb();
+        }
+        self.generation += 1;
+        let nonce = [self.generation as u32, (self.generation >> 32) as u32, 0];
+        // Block 0 becomes the next key, the rest is output
+        let mut counter = 1;
+        for chunk in output.chunks_mut(BLOCK_SIZE) {
+            let block = chacha20_block(&self.key, counter, &nonce);
+            for (i, byte) in chunk.iter_mut().enumerate() {
+                *byte = (block[i / 4] >> (i % 4 * 8)) as u8;
+            }
+            counter += 1;
+        }
+        let next = chacha20_block(&self.key, 0, &nonce);
+        self.key.copy_from_slice(&next[..8]);
+        self.generated += output.len() as u64;
+    }
+}
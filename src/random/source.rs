@@ -0,0 +1,12 @@
+//! Devices that provide entropy
+
+/// A hardware source of random bytes
+pub trait EntropySource {
+    fn name(&self) -> &'static str;
+    /// Fill as much of `buffer` as the source has bytes for now, returning how many that was
+    unsafe fn read(&mut self, buffer: &mut [u8]) -> usize;
+    /// Bits of entropy credited for each byte read
+    fn bits_per_byte(&self) -> usize {
+        8
+    }
+}
@@ -0,0 +1,257 @@
+//! Clock sources and clock events for the timers the kernel has drivers for
+
+use super::{ClockSource, ClockEvent, as_nanos, duration_to_ticks, ticks_to_duration};
+use drivers::io::{MemIO, PortIO};
+use drivers::pit::{self, Pit};
+use drivers::hpet::{self, Hpet};
+use acpi;
+use irq;
+use state::{STATE, CPU_FEATURES};
+use cpu::MemoryType;
+use core::time::Duration;
+use x86::shared::time::rdtsc;
+
+/// Counts of the PIT used to calibrate the TSC, which is roughly 10ms
+const CALIBRATE_PIT_COUNT: u32 = 11932;
+/// Milliseconds over which to calibrate the TSC against the HPET
+const CALIBRATE_HPET_MS: u64 = 10;
+
+/// Timer of the HPET used for clock events, which legacy replacement routes to ISA IRQ 0
+const HPET_EVENT_TIMER: u8 = 0;
+
+/// Located and mapped HPET
+#[derive(Debug, Clone, Copy)]
+pub struct HpetBlock {
+    vaddr: usize,
+}
+
+impl HpetBlock {
+    unsafe fn driver(&self) -> Hpet<MemIO<u64>> {
+        Hpet::new(MemIO::new(self.vaddr)).unwrap()
+    }
+}
+
+/// Find and map the HPET, and start its main counter
+///
+/// Uses the location from ACPI if there is one, otherwise tries the conventional address
+pub fn probe_hpet() -> Option<HpetBlock> {
+    let paddr = acpi::hpet::get().map_or(hpet::DEFAULT_PADDR, |hpet| hpet.address);
+    let range = paddr..paddr + hpet::MMIO_SIZE;
+    let vaddr = unsafe {STATE.kernel_as.map_device(range.clone(), MemoryType::StrongUC)}?;
+    unsafe {
+        let mut hpet = match Hpet::new(MemIO::new(vaddr)) {
+            Some(hpet) => hpet,
+            None => {
+                print!(Info, "No HPET found at {:#x}", paddr);
+                STATE.kernel_as.unmap_device(vaddr, range);
+                return None;
+            },
+        };
+        for timer in 0..hpet.timers() {
+            hpet.stop(timer);
+        }
+        hpet.enable(false);
+        print!(Info, "Found HPET at {:#x} with {} timers at {} Hz", paddr, hpet.timers(), hpet.frequency());
+    }
+    Some(HpetBlock { vaddr: vaddr })
+}
+
+fn pit() -> Pit<PortIO<u8>> {
+    Pit::new(PortIO::new(pit::PORT_BASE), PortIO::new(pit::GATE_PORT))
+}
+
+/// Time stamp counter
+pub struct Tsc {
+    frequency: u64,
+    invariant: bool,
+}
+
+impl Tsc {
+    /// Determine the TSC frequency against the HPET, if given, or the PIT
+    pub fn calibrate(hpet: Option<HpetBlock>) -> Option<Tsc> {
+        let _tsc = unsafe {CPU_FEATURES}.get_required().get_tsc();
+        let frequency = match hpet {
+            Some(block) => unsafe {
+                let mut hpet = block.driver();
+                let ticks = hpet.frequency() * CALIBRATE_HPET_MS / 1000;
+                let start_tsc = rdtsc();
+                let start = hpet.counter();
+                while hpet.counter().wrapping_sub(start) & hpet.counter_mask() < ticks {}
+                let tsc = rdtsc() - start_tsc;
+                Some(tsc * 1000 / CALIBRATE_HPET_MS)
+            },
+            None => unsafe {pit().calibrate(CALIBRATE_PIT_COUNT, || rdtsc())},
+        }?;
+        let invariant = unsafe {CPU_FEATURES}.get_invariant_tsc().is_some();
+        print!(Info, "Calibrated {}TSC at {} Hz", if invariant { "invariant " } else { "" }, frequency);
+        Some(Tsc { frequency: frequency, invariant: invariant })
+    }
+}
+
+impl ClockSource for Tsc {
+    fn name(&self) -> &'static str {
+        "tsc"
+    }
+    fn rating(&self) -> u32 {
+        // A TSC that varies with power states is only good as a last resort
+        if self.invariant { 300 } else { 100 }
+    }
+    fn frequency(&self) -> u64 {
+        self.frequency
+    }
+    fn mask(&self) -> u64 {
+        !0
+    }
+    unsafe fn read(&mut self) -> u64 {
+        rdtsc()
+    }
+}
+
+/// Main counter of the HPET
+pub struct HpetSource {
+    hpet: Hpet<MemIO<u64>>,
+}
+
+impl HpetSource {
+    pub fn new(block: HpetBlock) -> HpetSource {
+        HpetSource { hpet: unsafe {block.driver()} }
+    }
+}
+
+impl ClockSource for HpetSource {
+    fn name(&self) -> &'static str {
+        "hpet"
+    }
+    fn rating(&self) -> u32 {
+        250
+    }
+    fn frequency(&self) -> u64 {
+        self.hpet.frequency()
+    }
+    fn mask(&self) -> u64 {
+        self.hpet.counter_mask()
+    }
+    unsafe fn read(&mut self) -> u64 {
+        self.hpet.counter()
+    }
+}
+
+/// HPET timer 0, through legacy replacement
+pub struct HpetEvent {
+    hpet: Hpet<MemIO<u64>>,
+}
+
+impl HpetEvent {
+    /// Use the HPET for events, if it can replace the PIT
+    ///
+    /// Switches the HPET to legacy replacement, after which the PIT no longer interrupts
+    pub fn new(block: HpetBlock) -> Option<HpetEvent> {
+        let mut hpet = unsafe {block.driver()};
+        let periodic = unsafe {hpet.timer_config(HPET_EVENT_TIMER)}.periodic_capable();
+        if !hpet.capabilities().legacy_capable() || !periodic {
+            return None;
+        }
+        unsafe {hpet.enable(true)};
+        Some(HpetEvent { hpet: hpet })
+    }
+    fn ticks(&self, delay: Duration) -> u64 {
+        let ticks = duration_to_ticks(delay, self.hpet.frequency());
+        if ticks == 0 { 1 } else { ticks & self.hpet.counter_mask() }
+    }
+}
+
+impl ClockEvent for HpetEvent {
+    fn name(&self) -> &'static str {
+        "hpet"
+    }
+    fn rating(&self) -> u32 {
+        150
+    }
+    fn irq(&self) -> u8 {
+        irq::ISA_TIMER
+    }
+    fn max_delay(&self) -> Duration {
+        // Keep well inside a wrap of a 32-bit counter
+        ticks_to_duration(self.hpet.counter_mask() / 2, self.hpet.frequency())
+    }
+    unsafe fn periodic(&mut self, period: Duration) {
+        let ticks = self.ticks(period);
+        self.hpet.periodic(HPET_EVENT_TIMER, ticks);
+    }
+    unsafe fn one_shot(&mut self, delay: Duration) {
+        let ticks = self.ticks(delay);
+        self.hpet.one_shot(HPET_EVENT_TIMER, ticks);
+    }
+    unsafe fn stop(&mut self) {
+        self.hpet.stop(HPET_EVENT_TIMER);
+    }
+}
+
+/// PIT channel 2, left free running
+///
+/// At 16 bits this wraps every 55ms, so it is only good for when there is nothing else.
+pub struct PitSource {
+    pit: Pit<PortIO<u8>>,
+}
+
+impl PitSource {
+    /// Takes over channel 2, so nothing can be calibrated against the PIT afterwards
+    pub fn new() -> PitSource {
+        let mut pit = pit();
+        unsafe {pit.start_free_running()};
+        PitSource { pit: pit }
+    }
+}
+
+impl ClockSource for PitSource {
+    fn name(&self) -> &'static str {
+        "pit"
+    }
+    fn rating(&self) -> u32 {
+        50
+    }
+    fn frequency(&self) -> u64 {
+        pit::FREQUENCY
+    }
+    fn mask(&self) -> u64 {
+        0xFFFF
+    }
+    unsafe fn read(&mut self) -> u64 {
+        self.pit.free_running_count() as u64
+    }
+}
+
+/// PIT channel 0
+pub struct PitEvent {
+    pit: Pit<PortIO<u8>>,
+}
+
+impl PitEvent {
+    pub fn new() -> PitEvent {
+        PitEvent { pit: pit() }
+    }
+}
+
+impl ClockEvent for PitEvent {
+    fn name(&self) -> &'static str {
+        "pit"
+    }
+    fn rating(&self) -> u32 {
+        100
+    }
+    fn irq(&self) -> u8 {
+        irq::ISA_TIMER
+    }
+    fn max_delay(&self) -> Duration {
+        ticks_to_duration(pit::MAX_COUNT as u64, pit::FREQUENCY)
+    }
+    unsafe fn periodic(&mut self, period: Duration) {
+        self.pit.periodic(pit::count_for_nanos(as_nanos(period)));
+    }
+    unsafe fn one_shot(&mut self, delay: Duration) {
+        self.pit.one_shot(pit::count_for_nanos(as_nanos(delay)));
+    }
+    unsafe fn stop(&mut self) {
+        self.pit.stop();
+    }
+}
@@ -0,0 +1,184 @@
+//! Kernel time keeping
+//!
+//! Time is built from two kinds of device. A `ClockSource` is a free running counter that is
+//! read to tell how much time has passed, and a `ClockEvent` is something that can be asked to
+//! interrupt after a delay, or periodically. Each available device is given a rating and the
+//! highest rated of each kind is used, so the kernel runs with whatever the platform provides,
+//! from an invariant TSC with an HPET down to just the PIT.
+//!
+//! Calendar time comes from reading the CMOS RTC once at boot, and is then advanced by the
+//! monotonic clock, as the RTC only has a resolution of seconds.
+
+mod clocks;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::time::Duration;
+use drivers::io::PortIO;
+use drivers::rtc::{self, Rtc};
+use acpi;
+use irq;
+
+pub use self::clocks::{Tsc, HpetSource, HpetEvent, PitSource, PitEvent};
+pub use drivers::rtc::DateTime;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Free running counter
+pub trait ClockSource {
+    fn name(&self) -> &'static str;
+    /// Relative preference, higher is better
+    fn rating(&self) -> u32;
+    /// Counts per second
+    fn frequency(&self) -> u64;
+    /// Bits the counter has before wrapping
+    fn mask(&self) -> u64;
+    unsafe fn read(&mut self) -> u64;
+}
+
+/// Programmable source of timer interrupts
+pub trait ClockEvent {
+    fn name(&self) -> &'static str;
+    /// Relative preference, higher is better
+    fn rating(&self) -> u32;
+    /// ISA IRQ the events are delivered on
+    fn irq(&self) -> u8;
+    /// Longest delay or period that can be programmed, anything longer is shortened to this
+    fn max_delay(&self) -> Duration;
+    unsafe fn periodic(&mut self, period: Duration);
+    unsafe fn one_shot(&mut self, delay: Duration);
+    unsafe fn stop(&mut self);
+}
+
+/// Convert a `Duration` into nanoseconds, saturating
+pub fn as_nanos(duration: Duration) -> u64 {
+    duration.as_secs().saturating_mul(NANOS_PER_SEC).saturating_add(duration.subsec_nanos() as u64)
+}
+
+/// Convert a number of counts at `frequency` into a `Duration`
+pub fn ticks_to_duration(ticks: u64, frequency: u64) -> Duration {
+    let secs = ticks / frequency;
+    let nanos = (ticks % frequency) * NANOS_PER_SEC / frequency;
+    Duration::new(secs, nanos as u32)
+}
+
+/// Convert a `Duration` into a number of counts at `frequency`, saturating
+pub fn duration_to_ticks(duration: Duration, frequency: u64) -> u64 {
+    duration.as_secs().saturating_mul(frequency)
+        .saturating_add(duration.subsec_nanos() as u64 * frequency / NANOS_PER_SEC)
+}
+
+/// Monotonic time accumulated from the clock source
+///
+/// Counts are accumulated so that a source narrower than 64 bits can wrap, as long as it is
+/// read at least once per wrap.
+struct Monotonic {
+    source: Box<ClockSource>,
+    last: u64,
+    ticks: u64,
+}
+
+impl Monotonic {
+    fn now(&mut self) -> Duration {
+        let now = unsafe {self.source.read()};
+        self.ticks += now.wrapping_sub(self.last) & self.source.mask();
+        self.last = now;
+        ticks_to_duration(self.ticks, self.source.frequency())
+    }
+}
+
+static mut MONOTONIC: Option<Monotonic> = None;
+static mut EVENT: Option<Box<ClockEvent>> = None;
+/// Seconds since the epoch as read from the RTC, and the uptime it was read at
+static mut BOOT_TIME: Option<(u64, Duration)> = None;
+
+fn best<T: ?Sized, F: Fn(&T) -> u32>(candidates: Vec<Box<T>>, rating: F) -> Option<Box<T>> {
+    let mut best: Option<Box<T>> = None;
+    for candidate in candidates {
+        let better = best.as_ref().map_or(true, |b| rating(&*candidate) > rating(&**b));
+        if better {
+            best = Some(candidate);
+        }
+    }
+    best
+}
+
+/// Nothing is driven by clock events yet, so they only need acknowledging
+fn event_interrupt() {}
+
+/// Probe the available timers and select the best clock source and clock event
+///
+/// Requires the kernel address space and the interrupt controllers. The chosen clock event is
+/// left stopped, with its IRQ routed.
+pub fn init() {
+    let hpet = clocks::probe_hpet();
+    let mut sources: Vec<Box<ClockSource>> = Vec::new();
+    let mut events: Vec<Box<ClockEvent>> = Vec::new();
+    if let Some(hpet) = hpet {
+        sources.push(box HpetSource::new(hpet));
+        if let Some(event) = HpetEvent::new(hpet) {
+            events.push(box event);
+        }
+    }
+    match Tsc::calibrate(hpet) {
+        Some(tsc) => sources.push(box tsc),
+        None => print!(Error, "Failed to calibrate TSC"),
+    }
+    // The PIT is always there, so there is always a source and an event to fall back on
+    sources.push(box PitSource::new());
+    events.push(box PitEvent::new());
+    let mut source = best(sources, |s| s.rating()).unwrap();
+    let mut event = best(events, |e| e.rating()).unwrap();
+    print!(Info, "Using clock source {} at {} Hz", source.name(), source.frequency());
+    print!(Info, "Using clock event {} with maximum delay {:?}", event.name(), event.max_delay());
+    unsafe {
+        event.stop();
+        irq::enable_isa(event.irq(), event_interrupt);
+        let last = source.read();
+        MONOTONIC = Some(Monotonic { source: source, last: last, ticks: 0 });
+        EVENT = Some(event);
+    }
+    init_wall_clock();
+}
+
+fn init_wall_clock() {
+    let century = acpi::fadt::get().and_then(|fadt| fadt.century);
+    let mut rtc = Rtc::new(PortIO::new(rtc::PORT_BASE), century);
+    match unsafe {rtc.now()} {
+        Some(now) => {
+            print!(Info, "RTC reads {}", now);
+            unsafe {BOOT_TIME = Some((now.to_unix(), uptime()))};
+        },
+        None => print!(Error, "Failed to read the RTC"),
+    }
+}
+
+/// Time since the clock source was selected
+///
+/// Is zero until `init` has been called
+pub fn uptime() -> Duration {
+    unsafe {MONOTONIC.as_mut()}.map_or(Duration::new(0, 0), |m| m.now())
+}
+
+/// Current calendar date and time
+///
+/// Is nothing until `init` has been called, or if the RTC could not be read
+pub fn wall_clock() -> Option<DateTime> {
+    unsafe {BOOT_TIME}.map(|(seconds, at)| DateTime::from_unix(seconds + (uptime() - at).as_secs()))
+}
+
+/// Busy wait for at least the given duration
+///
+/// # Panics
+///
+/// If called before `init`, as the wait would never end
+pub fn spin(duration: Duration) {
+    assert!(unsafe {MONOTONIC.is_some()}, "Spin before time init");
+    let end = uptime() + duration;
+    while uptime() < end {}
+}
+
+/// Retrieve the clock event device, once selected
+pub fn clock_event() -> Option<&'static mut ClockEvent> {
+    unsafe {EVENT.as_mut()}.map(|event| &mut **event)
+}
@@ -3,8 +3,11 @@
 use vspace::*;
 use vspace::paging::*;
 use cpu::features::Page1GB;
+use cpu::MemoryType;
 use state::CPU_FEATURES;
+use alloc::alloc::alloc_zeroed;
 use alloc::boxed::Box;
+use core::alloc::Layout;
 use cpu;
 use heap;
 use con;
@@ -12,6 +15,8 @@ use core::ptr::Unique;
 use boot::state::BootState;
 use state::STATE;
 use util::units::MB;
+use util::range_contains;
+use x86::shared::tlb;
 
 pub struct KernelVSpace {
     root: Unique<AS>,
@@ -45,30 +50,126 @@ unsafe impl Allocation for KernelVSpace {
         if base % PAGE_SIZE_2M != 0 || size % PAGE_SIZE_2M != 0 {
             return None;
         }
-        for mb in (base..base+size).step_by(PAGE_SIZE_2M) {
-            // allocate a page and turn it into a raw
-            
-//            let mapping = PageMappingBuilder(mb, 
+        let layout = Layout::from_size_align(PAGE_SIZE_2M, PAGE_SIZE_2M).ok()?;
+        for offset in (0..size).step_by(PAGE_SIZE_2M) {
+            // Frames come from the heap, which is in the kernel window and so physically contiguous
+            let frame = unsafe {alloc_zeroed(layout)};
+            let paddr = if frame.is_null() { None } else { KernelWindow.vaddr_to_paddr(frame as usize) };
+            let page = unsafe {Page::<Page2M>::new_unchecked(base + offset)};
+            match paddr.and_then(|paddr| PageMappingBuilder::new_frame(page, paddr)) {
+                Some(builder) => unsafe {
+                    let mapping = builder.kernel().no_execute().write().finish();
+                    self.root.as_mut().ensure_mapping_entry(&KernelWindow, mapping.clone());
+                    self.root.as_mut().raw_map_page(&KernelWindow, mapping);
+                },
+                None => {
+                    // The heap cannot free, so the frames already mapped are lost
+                    unsafe {self.unmap_frames(base, offset)};
+                    return None;
+                },
+            }
         }
-        unimplemented!()
+        Some(base as *mut u8)
+    }
+}
+
+/// Translation for the linear portions of the kernel window
+///
+/// This covers the default range and the kernel image, which are fixed offsets from physical
+/// memory. Mappings in the dynamic range are not described. Having this separate from the
+/// `KernelVSpace` allows it to be used whilst the vspace itself is being modified.
+struct KernelWindow;
+
+unsafe impl Translation for KernelWindow {
+    fn range_valid(&self, range: Range<usize>) -> bool {
+        range_contains(&KERNEL_BASE_DEFAULT_RANGE, &range) || range_contains(&KERNEL_IMAGE_RANGE, &range)
+    }
+    fn vaddr_to_paddr_range(&self, range: Range<usize>) -> Option<Range<usize>> {
+        if range_contains(&KERNEL_BASE_DEFAULT_RANGE, &range) {
+            Some(range.start - (KERNEL_BASE_DEFAULT_RANGE.start - KERNEL_PHYS_BASE)..range.end - (KERNEL_BASE_DEFAULT_RANGE.start - KERNEL_PHYS_BASE))
+        } else if range_contains(&KERNEL_IMAGE_RANGE, &range) {
+            Some(range.start - (KERNEL_IMAGE_RANGE.start - KERNEL_PHYS_BASE)..range.end - (KERNEL_IMAGE_RANGE.start - KERNEL_PHYS_BASE))
+        } else {
+            None
+        }
+    }
+    fn paddr_to_vaddr_range(&self, range:Range<usize>) -> Option<Range<usize>> {
+        self.vaddr_to_paddr_range(KERNEL_BASE_DEFAULT_RANGE)
+            .and_then(|x| if range_contains(&x, &range) { Some(range.start - KERNEL_PHYS_BASE + KERNEL_BASE_DEFAULT_RANGE.start..range.end - KERNEL_PHYS_BASE + KERNEL_BASE_DEFAULT_RANGE.start) } else { None })
     }
 }
 
 unsafe impl Translation for KernelVSpace {
     fn range_valid(&self, range: Range<usize>) -> bool {
-        unimplemented!()
+        KernelWindow.range_valid(range)
     }
     fn vaddr_to_paddr_range(&self, range: Range<usize>) -> Option<Range<usize>> {
-        unimplemented!()
+        KernelWindow.vaddr_to_paddr_range(range)
     }
     fn paddr_to_vaddr_range(&self, range:Range<usize>) -> Option<Range<usize>> {
-        unimplemented!()
+        KernelWindow.paddr_to_vaddr_range(range)
     }
 }
 
 unsafe impl VSpace for KernelVSpace {}
 
 impl KernelVSpace {
+    /// Map a range of device memory into the dynamic range
+    ///
+    /// The range is expanded out to 2MB frames, which are mapped kernel only, writable and non
+    /// executable with the requested memory type. Returns the virtual address of `paddr.start`.
+    ///
+    /// # Safety
+    ///
+    /// The physical range must not be RAM in use by the heap, as it will be mapped with a
+    /// conflicting memory type
+    pub unsafe fn map_device(&mut self, paddr: Range<usize>, mt: MemoryType) -> Option<usize> {
+        let base = paddr.start & !(PAGE_SIZE_2M - 1);
+        let top = paddr.end.checked_add(PAGE_SIZE_2M - 1)? & !(PAGE_SIZE_2M - 1);
+        let vaddr = self.reserve(top - base, PAGE_SIZE_2M)?;
+        for offset in (0..top - base).step_by(PAGE_SIZE_2M) {
+            let page = Page::<Page2M>::new_unchecked(vaddr + offset);
+            let mapping = match PageMappingBuilder::new_frame(page, base + offset) {
+                Some(builder) => builder.kernel().no_execute().write().memory_type(mt).finish(),
+                None => {
+                    self.unmap_frames(vaddr, offset);
+                    self.release(vaddr, top - base);
+                    return None;
+                },
+            };
+            self.root.as_mut().ensure_mapping_entry(&KernelWindow, mapping.clone());
+            self.root.as_mut().raw_map_page(&KernelWindow, mapping);
+        }
+        Some(vaddr + (paddr.start - base))
+    }
+    /// Remove a mapping made by `map_device`
+    ///
+    /// `vaddr` is what `map_device` returned for `paddr`. The virtual range is only reused if
+    /// nothing has been reserved after it.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may use the mapping afterwards
+    pub unsafe fn unmap_device(&mut self, vaddr: usize, paddr: Range<usize>) {
+        let base = paddr.start & !(PAGE_SIZE_2M - 1);
+        let top = (paddr.end + PAGE_SIZE_2M - 1) & !(PAGE_SIZE_2M - 1);
+        let vbase = vaddr - (paddr.start - base);
+        self.unmap_frames(vbase, top - base);
+        self.release(vbase, top - base);
+    }
+    /// Unmap, and flush from the TLB, the 2MB frames mapped in `vaddr..vaddr + size`
+    unsafe fn unmap_frames(&mut self, vaddr: usize, size: usize) {
+        for offset in (0..size).step_by(PAGE_SIZE_2M) {
+            self.root.as_mut().raw_unmap_page(&KernelWindow, Page::<Page2M>::new_unchecked(vaddr + offset));
+            tlb::flush(vaddr + offset);
+        }
+    }
+    /// Give back a reservation, which is only possible if it was the most recent
+    fn release(&mut self, vaddr: usize, size: usize) {
+        if self.dynamic_free.start == vaddr + size {
+            self.dynamic_free.start = vaddr;
+        }
+    }
     unsafe fn map_kernel_window<'a, T: Translation + ?Sized>(&mut self, translation: &'a T) {
         // currently assume 1gb pages
         let page1gb: Page1GB = unsafe{CPU_FEATURES}.get_page1gb().expect("Require 1GB page support");
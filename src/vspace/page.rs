@@ -76,6 +76,12 @@ impl Page<Page1G> {
     }
 }
 
+impl Page<Page2M> {
+    pub unsafe fn new_unchecked(vaddr: usize) -> Page<Page2M> {
+        Page { inner: vaddr as *mut u8, phantom: PhantomData}
+    }
+}
+
 /// Raw unit of memory referenced by physical address
 struct Frame<S: PageLevel> {
     paddr: usize,
@@ -63,6 +63,22 @@ impl From<Access> for PDPTEntry {
     }
 }
 
+impl From<Access> for PDEntry {
+    fn from(access: Access) -> PDEntry {
+        let mut entry = PDEntry::empty();
+        if access.write {
+            entry.insert(PD_RW);
+        }
+        if access.user {
+            entry.insert(PD_US);
+        }
+        if access.nxe.is_some() {
+            entry.insert(PD_XD);
+        }
+        entry
+    }
+}
+
 impl Access {
     /// Default access permissions for a kernel paging structure
     ///
@@ -107,6 +123,16 @@ impl From<PageMapping<Page1G>> for PDPTEntry {
     }
 }
 
+impl From<PageMapping<Page2M>> for PDEntry {
+    fn from(mapping: PageMapping<Page2M>) -> PDEntry {
+        let mut entry = PDEntry::new(PAddr::from_u64(mapping.paddr as u64), PDEntry::from(mapping.access) | PDEntry::from(mapping.mt) | PD_PS);
+        if mapping.pge.is_some() {
+            entry.insert(PD_G);
+        }
+        entry
+    }
+}
+
 pub struct PageMappingBuilder<S: PageLevel> {
     internal: PageMapping<S>,
 }
@@ -127,6 +153,29 @@ impl<S: PageLevel> PageMappingBuilder<S> {
                 }
             )
     }
+    /// Map a page to an explicit physical address
+    ///
+    /// Used for memory, such as devices, that does not exist in any translation. Fails if
+    /// `paddr` is not aligned to the page size
+    pub fn new_frame(page: Page<S>, paddr: usize) -> Option<Self> {
+        if paddr % S::bytes() != 0 {
+            return None;
+        }
+        Some(PageMappingBuilder {
+            internal: PageMapping {
+                vaddr: page.range().start,
+                paddr: paddr,
+                access: Access {write: false, user: false, nxe: None},
+                pge: None,
+                mt: MemoryType::WB,
+                marker: PhantomData,
+            },
+        })
+    }
+    pub fn memory_type(mut self, mt: MemoryType) -> Self {
+        self.internal.mt = mt;
+        self
+    }
     pub fn user(mut self) -> Self {
         self.internal.access.user = true;
         self.internal.pge = None;
@@ -181,6 +230,32 @@ impl PDPTWrap {
     }
 }
 
+#[repr(C, align(4096))]
+struct PDWrap(PD);
+assert_eq_size!(pd_page_size; PDWrap, [u8; 4096]);
+
+impl Default for PDWrap {
+    fn default() -> PDWrap {
+        PDWrap{0: [PDEntry::empty(); 512]}
+    }
+}
+
+impl PDWrap {
+    fn make_entry<'a, T: Translation + ?Sized>(&self, translation: &'a T, access: Access) -> PDPTEntry {
+        PDPTEntry::new(PAddr::from_u64(translation.vaddr_to_paddr(self as *const PDWrap as usize).unwrap() as u64), PDPTEntry::from(access) | PDPT_P)
+    }
+    unsafe fn from_entry<'a, T: Translation + ?Sized>(entry: PDPTEntry, translation: &'a T) -> &'static mut PDWrap {
+        if !entry.is_present() {
+            panic!("No PD entry in PDPT");
+        }
+        if entry.contains(PDPT_PS) {
+            panic!("PDPT entry is a 1GB frame and not a PD");
+        }
+        let vaddr = translation.paddr_to_vaddr(entry.get_address().as_u64() as usize).unwrap();
+        mem::transmute(vaddr as *mut PDWrap)
+    }
+}
+
 #[repr(C, align(4096))]
 pub struct AS(PML4);
 assert_eq_size!(as_page_size; AS, [u8; 4096]);
@@ -209,6 +284,15 @@ pub unsafe trait ASMappingOps<S: PageLevel> {
     /// i.e. if trying to ensure an entry for a 4K frame but there is already a 2M frame
     /// covering the region, preventing the necessary page table from being created.
     unsafe fn ensure_mapping_entry<'a, T: Translation + ?Sized>(&mut self, translation: &'a T, mapping: PageMapping<S>);
+    /// Removes the mapping of a page without performing consistency updates
+    ///
+    /// As with `raw_map_page` any TLB updates are left to the caller. The paging structures
+    /// that held the mapping are kept.
+    ///
+    /// # Panics
+    ///
+    /// If the page is not mapped
+    unsafe fn raw_unmap_page<'a, T: Translation + ?Sized>(&mut self, translation: &'a T, page: Page<S>);
 }
 
 unsafe impl ASMappingOps<Page1G> for AS {
@@ -230,6 +314,57 @@ unsafe impl ASMappingOps<Page1G> for AS {
             Box::into_raw(pdpt);
         }
     }
+    unsafe fn raw_unmap_page<'a, T: Translation + ?Sized>(&mut self, translation: &'a T, page: Page<Page1G>) {
+        let vaddr = VAddr::from_usize(page.range().start);
+        let pdpt = PDPTWrap::from_entry(self.0[pml4_index(vaddr)], translation);
+        let pdptent = &mut pdpt.0[pdpt_index(vaddr)];
+        if !pdptent.is_present() {
+            panic!("No mapping present in PDPT");
+        }
+        *pdptent = PDPTEntry::empty();
+    }
+}
+
+unsafe impl ASMappingOps<Page2M> for AS {
+    unsafe fn raw_map_page<'a, T: Translation + ?Sized>(&mut self, translation: &'a T, mapping: PageMapping<Page2M>) {
+        let vaddr = VAddr::from_usize(mapping.vaddr);
+        let pdpt = PDPTWrap::from_entry(self.0[pml4_index(vaddr)], translation);
+        let pd = PDWrap::from_entry(pdpt.0[pdpt_index(vaddr)], translation);
+        let pdent = &mut pd.0[pd_index(vaddr)];
+        if pdent.is_present() {
+            panic!("Mapping already present in PD");
+        }
+        *pdent = PDEntry::from(mapping) | PD_P;
+    }
+    unsafe fn ensure_mapping_entry<'a, T: Translation + ?Sized>(&mut self, translation: &'a T, mapping: PageMapping<Page2M>) {
+        let vaddr = VAddr::from_usize(mapping.vaddr);
+        let pml4ent = &mut self.0[pml4_index(vaddr)];
+        if !pml4ent.is_present() {
+            let pdpt = box PDPTWrap::default();
+            *pml4ent = pdpt.make_entry(translation, Access::default_kernel_paging());
+            Box::into_raw(pdpt);
+        }
+        let pdpt = PDPTWrap::from_entry(*pml4ent, translation);
+        let pdptent = &mut pdpt.0[pdpt_index(vaddr)];
+        if pdptent.contains(PDPT_PS) {
+            panic!("Cannot create PD where a 1GB frame is mapped");
+        }
+        if !pdptent.is_present() {
+            let pd = box PDWrap::default();
+            *pdptent = pd.make_entry(translation, Access::default_kernel_paging());
+            Box::into_raw(pd);
+        }
+    }
+    unsafe fn raw_unmap_page<'a, T: Translation + ?Sized>(&mut self, translation: &'a T, page: Page<Page2M>) {
+        let vaddr = VAddr::from_usize(page.range().start);
+        let pdpt = PDPTWrap::from_entry(self.0[pml4_index(vaddr)], translation);
+        let pd = PDWrap::from_entry(pdpt.0[pdpt_index(vaddr)], translation);
+        let pdent = &mut pd.0[pd_index(vaddr)];
+        if !pdent.is_present() {
+            panic!("No mapping present in PD");
+        }
+        *pdent = PDEntry::empty();
+    }
 }
 
 impl Default for AS {
@@ -9,6 +9,10 @@ pub struct Stack {
 }
 
 impl Stack {
+    /// Address just beyond the usable stack, which is what the stack pointer starts at
+    pub fn top(&self) -> usize {
+        self.base + self.top_offset
+    }
     /// Execute the given function on this stack
     ///
     /// # Safety
@@ -26,8 +30,9 @@ impl Stack {
     /// Stacks are not free'd when they are dropped. For this reason the function is unsafe
     /// as it is the callers responsibility to ensure that memory is cleaned up.
     pub unsafe fn new_kernel<V: VSpace>(vspace: &mut V) -> Option<Stack> {
+        // The lower half of the reservation is left unmapped as a guard
         vspace.reserve(2 * PAGE_SIZE_2M, PAGE_SIZE_2M)
-            .and_then(|base| vspace.fill(base + PAGE_SIZE_2M, PAGE_SIZE_2M))
-            .map(|base| Stack {base: base as usize, guard_size: PAGE_SIZE_2M, top_offset: 2 * PAGE_SIZE_2M})
+            .and_then(|base| vspace.fill(base + PAGE_SIZE_2M, PAGE_SIZE_2M).map(|_| base))
+            .map(|base| Stack {base: base, guard_size: PAGE_SIZE_2M, top_offset: 2 * PAGE_SIZE_2M})
     }
 }